@@ -30,6 +30,7 @@ extern crate tikv;
 extern crate time;
 
 mod channel;
+mod raftkv;
 mod writebatch;
 mod serialization;
 mod coprocessor;
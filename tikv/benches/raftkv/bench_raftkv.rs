@@ -0,0 +1,124 @@
+// Copyright 2018 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::Arc;
+
+use test::Bencher;
+
+use kvproto::kvrpcpb::Context;
+use kvproto::metapb::Region;
+use kvproto::raft_cmdpb::{RaftCmdRequest, RaftCmdResponse, Response};
+use protobuf::RepeatedField;
+use rocksdb::DB;
+use tempdir::TempDir;
+
+use tikv::raftstore::store::{Callback, Msg as StoreMsg, ReadResponse, RegionSnapshot,
+                             SignificantMsg, WriteResponse};
+use tikv::raftstore::Result as RaftStoreResult;
+use tikv::server::transport::RaftStoreRouter;
+use tikv::storage::{make_key, Engine, Modify, RaftKv, ALL_CFS, CF_DEFAULT};
+use tikv::util::rocksdb::new_engine;
+
+/// A router that answers commands on the spot, so the benchmark measures
+/// the routing overhead of `RaftKv` without a raftstore behind it.
+#[derive(Clone)]
+struct SyncBenchRouter {
+    db: Arc<DB>,
+    region: Region,
+}
+
+impl SyncBenchRouter {
+    fn new(region: Region, db: Arc<DB>) -> SyncBenchRouter {
+        SyncBenchRouter {
+            db: db,
+            region: region,
+        }
+    }
+
+    fn invoke(&self, cmd: RaftCmdRequest, cb: Callback) {
+        let mut response = RaftCmdResponse::new();
+        let mut resps = Vec::with_capacity(cmd.get_requests().len());
+        for req in cmd.get_requests() {
+            let mut resp = Response::new();
+            resp.set_cmd_type(req.get_cmd_type());
+            resps.push(resp);
+        }
+        response.set_responses(RepeatedField::from_vec(resps));
+        match cb {
+            Callback::Read(cb) => cb(ReadResponse {
+                response: response,
+                snapshot: Some(RegionSnapshot::from_raw(
+                    Arc::clone(&self.db),
+                    self.region.clone(),
+                )),
+                apply_index: 0,
+            }),
+            Callback::Write(cb) => cb(WriteResponse { response: response }),
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl RaftStoreRouter for SyncBenchRouter {
+    fn send(&self, _: StoreMsg) -> RaftStoreResult<()> {
+        Ok(())
+    }
+
+    fn try_send(&self, msg: StoreMsg) -> RaftStoreResult<()> {
+        if let StoreMsg::RaftCmd {
+            request, callback, ..
+        } = msg
+        {
+            self.invoke(request, callback)
+        }
+        Ok(())
+    }
+
+    fn significant_send(&self, _: SignificantMsg) -> RaftStoreResult<()> {
+        Ok(())
+    }
+}
+
+fn new_raftkv() -> (TempDir, RaftKv<SyncBenchRouter>) {
+    let dir = TempDir::new("_bench_raftkv").unwrap();
+    let db = new_engine(dir.path().to_str().unwrap(), ALL_CFS, None).unwrap();
+    let mut region = Region::new();
+    region.set_id(1);
+    (dir, RaftKv::new(SyncBenchRouter::new(region, Arc::new(db))))
+}
+
+#[bench]
+fn bench_raftkv_async_snapshot(b: &mut Bencher) {
+    let (_dir, engine) = new_raftkv();
+    let ctx = Context::new();
+    b.iter(|| {
+        engine
+            .async_snapshot(&ctx, Box::new(move |_| {}))
+            .unwrap();
+    });
+}
+
+#[bench]
+fn bench_raftkv_async_write(b: &mut Bencher) {
+    let (_dir, engine) = new_raftkv();
+    let ctx = Context::new();
+    b.iter(|| {
+        engine
+            .async_write(
+                &ctx,
+                vec![Modify::Put(CF_DEFAULT, make_key(b"fooo"), b"bar".to_vec())],
+                Box::new(move |_| {}),
+            )
+            .unwrap();
+    });
+}
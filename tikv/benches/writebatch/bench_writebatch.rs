@@ -117,3 +117,20 @@ fn bench_writebatch_with_capacity(b: &mut Bencher) {
         fill_writebatch(&wb, 4096);
     });
 }
+
+#[bench]
+fn bench_writebatch_reallocate_1m(b: &mut Bencher) {
+    b.iter(|| {
+        let wb = WriteBatch::with_capacity(4096);
+        fill_writebatch(&wb, 1024 * 1024);
+    });
+}
+
+#[bench]
+fn bench_writebatch_reuse_1m(b: &mut Bencher) {
+    let wb = WriteBatch::with_capacity(1024 * 1024);
+    b.iter(|| {
+        wb.clear();
+        fill_writebatch(&wb, 1024 * 1024);
+    });
+}
@@ -56,16 +56,22 @@ mod imp {
         info!("{}", String::from_utf8_lossy(&buf));
     }
 
-    pub fn handle_signal(engines: Engines) {
+    pub fn handle_signal<F: FnMut()>(engines: Engines, mut on_reload: F) {
         use signal::trap::Trap;
         use nix::sys::signal::{SIGUSR1, SIGUSR2, SIGHUP, SIGINT, SIGTERM};
         let trap = Trap::trap(&[SIGTERM, SIGINT, SIGHUP, SIGUSR1, SIGUSR2]);
         for sig in trap {
             match sig {
-                SIGTERM | SIGINT | SIGHUP => {
+                SIGTERM | SIGINT => {
                     info!("receive signal {}, stopping server...", sig as c_int);
                     break;
                 }
+                SIGHUP => {
+                    // Use SIGHUP to reload the reloadable parts of the
+                    // configuration, currently only the store labels.
+                    info!("receive signal {}, reloading config...", sig as c_int);
+                    on_reload();
+                }
                 SIGUSR1 => {
                     // Use SIGUSR1 to log metrics.
                     let mut buffer = vec![];
@@ -122,7 +128,7 @@ mod imp {
 mod imp {
     use tikv::raftstore::store::Engines;
 
-    pub fn handle_signal(_: Engines) {}
+    pub fn handle_signal<F: FnMut()>(_: Engines, _: F) {}
 }
 
 pub use self::imp::handle_signal;
@@ -83,7 +83,7 @@ fn new_debug_executor(
                     .unwrap()
             });
             let kv_db_opts = cfg.rocksdb.build_opt();
-            let kv_cfs_opts = cfg.rocksdb.build_cf_opts();
+            let kv_cfs_opts = cfg.rocksdb.build_cf_opts(None, cfg.storage.enable_raw_ttl);
             let kv_db = rocksdb_util::new_engine_opt(kv_path, kv_db_opts, kv_cfs_opts).unwrap();
 
             let raft_path = raft_db
@@ -369,6 +369,16 @@ trait DebugExecutor {
 
     fn print_bad_regions(&self);
 
+    fn dump_orphaned_values(&self, clean: bool);
+
+    fn export_region_meta(&self, path: &str);
+
+    fn import_region_meta(&self, path: &str);
+
+    fn verify_region_meta(&self, path: &str);
+
+    fn modify_tikv_config(&self, module: &str, name: &str, value: &str);
+
     fn set_region_tombstone_after_remove_peer(
         &self,
         mgr: Arc<SecurityManager>,
@@ -517,6 +527,26 @@ impl DebugExecutor for DebugClient {
     fn print_bad_regions(&self) {
         unimplemented!("only avaliable for local mode");
     }
+
+    fn dump_orphaned_values(&self, _: bool) {
+        unimplemented!("only avaliable for local mode");
+    }
+
+    fn export_region_meta(&self, _: &str) {
+        unimplemented!("only avaliable for local mode");
+    }
+
+    fn import_region_meta(&self, _: &str) {
+        unimplemented!("only avaliable for local mode");
+    }
+
+    fn verify_region_meta(&self, _: &str) {
+        unimplemented!("only avaliable for local mode");
+    }
+
+    fn modify_tikv_config(&self, _: &str, _: &str, _: &str) {
+        unimplemented!("only avaliable for local mode");
+    }
 }
 
 impl DebugExecutor for Debugger {
@@ -592,6 +622,46 @@ impl DebugExecutor for Debugger {
         }
         println!("all regions are healthy")
     }
+
+    fn dump_orphaned_values(&self, clean: bool) {
+        let (scanned, orphans) = self.orphaned_values(clean)
+            .unwrap_or_else(|e| perror_and_exit("Debugger::orphaned_values", e));
+        println!("scanned values: {}", scanned);
+        println!("orphaned values: {}", orphans);
+        if clean {
+            println!("cleaned orphaned values: {}", orphans);
+        }
+    }
+
+    fn export_region_meta(&self, path: &str) {
+        let count = self.export_region_meta(path)
+            .unwrap_or_else(|e| perror_and_exit("Debugger::export_region_meta", e));
+        println!("exported meta of {} regions to {}", count, path);
+    }
+
+    fn import_region_meta(&self, path: &str) {
+        let count = self.import_region_meta(path)
+            .unwrap_or_else(|e| perror_and_exit("Debugger::import_region_meta", e));
+        println!("imported meta of {} regions from {}", count, path);
+    }
+
+    fn verify_region_meta(&self, path: &str) {
+        let mismatches = self.verify_region_meta(path)
+            .unwrap_or_else(|e| perror_and_exit("Debugger::verify_region_meta", e));
+        if !mismatches.is_empty() {
+            for (region_id, error) in mismatches {
+                println!("{}: {}", region_id, error);
+            }
+            return;
+        }
+        println!("all region meta matches")
+    }
+
+    fn modify_tikv_config(&self, module: &str, name: &str, value: &str) {
+        self.modify_tikv_config(module, name, value)
+            .unwrap_or_else(|e| perror_and_exit("Debugger::modify_tikv_config", e));
+        println!("success!");
+    }
 }
 
 fn main() {
@@ -913,6 +983,78 @@ fn main() {
         )
         .subcommand(
             SubCommand::with_name("bad-regions").about("get all regions with corrupt raft"),
+        )
+        .subcommand(
+            SubCommand::with_name("orphaned-values")
+                .about("scan for default CF values whose write CF records are gone")
+                .arg(
+                    Arg::with_name("clean")
+                        .long("clean")
+                        .takes_value(false)
+                        .help("delete the orphaned values"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("region-meta")
+                .about("export, import or verify region meta of the store")
+                .subcommand(
+                    SubCommand::with_name("export")
+                        .about("export all region meta to a portable file")
+                        .arg(
+                            Arg::with_name("file")
+                                .required(true)
+                                .short("f")
+                                .takes_value(true)
+                                .help("the file to export to"),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("import")
+                        .about("import region meta from an exported file")
+                        .arg(
+                            Arg::with_name("file")
+                                .required(true)
+                                .short("f")
+                                .takes_value(true)
+                                .help("the file to import from"),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("verify")
+                        .about("verify region meta of the store against an exported file")
+                        .arg(
+                            Arg::with_name("file")
+                                .required(true)
+                                .short("f")
+                                .takes_value(true)
+                                .help("the file to verify against"),
+                        ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("modify-tikv-config")
+                .about("modify tikv config, only rate-bytes-per-sec is supported now")
+                .arg(
+                    Arg::with_name("module")
+                        .required(true)
+                        .short("m")
+                        .takes_value(true)
+                        .help("the module of the config, kvdb or raftdb"),
+                )
+                .arg(
+                    Arg::with_name("config_name")
+                        .required(true)
+                        .short("n")
+                        .takes_value(true)
+                        .help("the config name, eg. rate-bytes-per-sec"),
+                )
+                .arg(
+                    Arg::with_name("config_value")
+                        .required(true)
+                        .short("v")
+                        .takes_value(true)
+                        .help("the config value, eg. 128MB"),
+                ),
         );
     let matches = app.clone().get_matches();
 
@@ -1012,6 +1154,23 @@ fn main() {
         debug_executor.set_region_tombstone_after_remove_peer(mgr, &cfg, regions);
     } else if matches.subcommand_matches("bad-regions").is_some() {
         debug_executor.print_bad_regions();
+    } else if let Some(matches) = matches.subcommand_matches("orphaned-values") {
+        debug_executor.dump_orphaned_values(matches.is_present("clean"));
+    } else if let Some(matches) = matches.subcommand_matches("region-meta") {
+        if let Some(matches) = matches.subcommand_matches("export") {
+            debug_executor.export_region_meta(matches.value_of("file").unwrap());
+        } else if let Some(matches) = matches.subcommand_matches("import") {
+            debug_executor.import_region_meta(matches.value_of("file").unwrap());
+        } else if let Some(matches) = matches.subcommand_matches("verify") {
+            debug_executor.verify_region_meta(matches.value_of("file").unwrap());
+        } else {
+            let _ = app.print_help();
+        }
+    } else if let Some(matches) = matches.subcommand_matches("modify-tikv-config") {
+        let module = matches.value_of("module").unwrap();
+        let config_name = matches.value_of("config_name").unwrap();
+        let config_value = matches.value_of("config_value").unwrap();
+        debug_executor.modify_tikv_config(module, config_name, config_value);
     } else {
         let _ = app.print_help();
     }
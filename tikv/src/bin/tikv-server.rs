@@ -70,9 +70,10 @@ use tikv::server::transport::ServerRaftStoreRouter;
 use tikv::server::resolve;
 use tikv::raftstore::store::{self, new_compaction_listener, Engines, SnapManagerBuilder};
 use tikv::raftstore::coprocessor::CoprocessorHost;
-use tikv::pd::{PdClient, RpcClient};
+use tikv::pd::{PdClient, RegionCache, RpcClient};
 use tikv::util::time::Monitor;
 use tikv::util::rocksdb::metrics_flusher::{MetricsFlusher, DEFAULT_FLUSHER_INTERVAL};
+use tikv::util::rocksdb::RegionBoundaries;
 use tikv::import::{ImportSSTService, SSTImporter};
 
 const RESERVED_OPEN_FDS: u64 = 1000;
@@ -112,7 +113,11 @@ fn init_log(config: &TiKvConfig) {
 }
 
 fn initial_metric(cfg: &MetricConfig, node_id: Option<u64>) {
-    if cfg.interval.as_secs() == 0 || cfg.address.is_empty() {
+    util::monitor_threads("tikv")
+        .unwrap_or_else(|e| fatal!("failed to start monitor thread: {:?}", e));
+
+    if cfg.disable_push || cfg.interval.as_secs() == 0 || cfg.address.is_empty() {
+        // pull only mode, metrics are left for scraping.
         return;
     }
 
@@ -121,12 +126,19 @@ fn initial_metric(cfg: &MetricConfig, node_id: Option<u64>) {
         push_job.push_str(&format!("_{}", id));
     }
 
-    info!("start prometheus client");
+    // The gateway client turns credentials embedded in the push URL into
+    // an `Authorization` header.
+    let mut address = cfg.address.clone();
+    if !cfg.basic_auth_user.is_empty() {
+        address = format!(
+            "{}:{}@{}",
+            cfg.basic_auth_user, cfg.basic_auth_password, cfg.address
+        );
+    }
 
-    util::monitor_threads("tikv")
-        .unwrap_or_else(|e| fatal!("failed to start monitor thread: {:?}", e));
+    info!("start prometheus client");
 
-    util::run_prometheus(cfg.interval.0, &cfg.address, &push_job);
+    util::run_prometheus(cfg.interval.0, &address, &push_job, &cfg.prefix, &cfg.labels);
 }
 
 fn check_system_config(config: &TiKvConfig) {
@@ -146,7 +158,15 @@ fn check_system_config(config: &TiKvConfig) {
     }
 }
 
-fn run_raft_server(pd_client: RpcClient, cfg: &TiKvConfig, security_mgr: Arc<SecurityManager>) {
+fn run_raft_server(
+    pd_client: RpcClient,
+    cfg: &TiKvConfig,
+    cfg_path: Option<&Path>,
+    security_mgr: Arc<SecurityManager>,
+) {
+    // Install slow-log thresholds before any component starts timing.
+    cfg.slow_log.apply();
+
     let store_path = Path::new(&cfg.storage.data_dir);
     let lock_path = store_path.join(Path::new("LOCK"));
     let db_path = store_path.join(Path::new(DEFAULT_ROCKSDB_SUB_DIR));
@@ -166,15 +186,20 @@ fn run_raft_server(pd_client: RpcClient, cfg: &TiKvConfig, security_mgr: Arc<Sec
     // Initialize raftstore channels.
     let mut event_loop = store::create_event_loop(&cfg.raft_store)
         .unwrap_or_else(|e| fatal!("failed to create event loop: {:?}", e));
-    let store_sendch = SendCh::new(event_loop.channel(), "raftstore");
+    let mut store_sendch = SendCh::new(event_loop.channel(), "raftstore");
+    store_sendch.set_capacity(cfg.raft_store.notify_capacity);
     let (significant_msg_sender, significant_msg_receiver) = mpsc::channel();
     let raft_router = ServerRaftStoreRouter::new(store_sendch.clone(), significant_msg_sender);
     let compaction_listener = new_compaction_listener(store_sendch.clone());
 
     // Create kv engine, storage.
+    // Shared with the raftstore so compactions can cut output files at
+    // region boundaries.
+    let region_boundaries = RegionBoundaries::default();
     let mut kv_db_opts = cfg.rocksdb.build_opt();
     kv_db_opts.add_event_listener(compaction_listener);
-    let kv_cfs_opts = cfg.rocksdb.build_cf_opts();
+    let kv_cfs_opts = cfg.rocksdb
+        .build_cf_opts(Some(&region_boundaries), cfg.storage.enable_raw_ttl);
     let kv_engine = Arc::new(
         rocksdb_util::new_engine_opt(db_path.to_str().unwrap(), kv_db_opts, kv_cfs_opts)
             .unwrap_or_else(|s| fatal!("failed to create kv engine: {:?}", s)),
@@ -197,19 +222,35 @@ fn run_raft_server(pd_client: RpcClient, cfg: &TiKvConfig, security_mgr: Arc<Sec
     // Create pd client and pd work, snapshot manager, server.
     let pd_client = Arc::new(pd_client);
     let pd_worker = FutureWorker::new("pd worker");
+    // Report read flow of storage commands to PD as well, so hot region
+    // scheduling can see read hotspots.
+    storage.set_read_flow_scheduler(pd_worker.scheduler());
+    // Reject commands whose keys fall outside the region their context
+    // claims before they reach the scheduler.
+    storage.set_region_validator(Arc::new(RegionCache::new(Arc::clone(&pd_client))));
     let (mut worker, resolver) = resolve::new_resolver(Arc::clone(&pd_client))
         .unwrap_or_else(|e| fatal!("failed to start address resolver: {:?}", e));
 
     let snap_mgr = SnapManagerBuilder::default()
         .max_write_bytes_per_sec(cfg.server.snap_max_write_bytes_per_sec.0)
         .max_total_size(cfg.server.snap_max_total_size.0)
+        .max_concurrent_gen(cfg.server.snap_max_concurrent_generations)
+        .max_concurrent_send(cfg.server.snap_max_concurrent_sends)
+        .max_concurrent_apply(cfg.server.snap_max_concurrent_applies)
         .build(
             snap_path.as_path().to_str().unwrap().to_owned(),
             Some(store_sendch),
         );
 
     let importer = Arc::new(SSTImporter::new(import_path).unwrap());
-    let import_service = ImportSSTService::new(cfg.import.clone(), storage.clone(), importer);
+    let import_service = ImportSSTService::new(
+        cfg.import.clone(),
+        storage.clone(),
+        importer,
+        raft_router.clone(),
+        Arc::clone(&pd_client),
+        Arc::clone(&security_mgr),
+    );
 
     let server_cfg = Arc::new(cfg.server.clone());
     // Create server
@@ -241,6 +282,7 @@ fn run_raft_server(pd_client: RpcClient, cfg: &TiKvConfig, security_mgr: Arc<Sec
         significant_msg_receiver,
         pd_worker,
         coprocessor_host,
+        region_boundaries,
     ).unwrap_or_else(|e| fatal!("failed to start node: {:?}", e));
     initial_metric(&cfg.metric, Some(node.id()));
 
@@ -264,7 +306,41 @@ fn run_raft_server(pd_client: RpcClient, cfg: &TiKvConfig, security_mgr: Arc<Sec
     server
         .start(server_cfg, security_mgr)
         .unwrap_or_else(|e| fatal!("failed to start server: {:?}", e));
-    signal_handler::handle_signal(engines);
+    // On SIGHUP, reread the config file and apply the parts that can change
+    // at runtime. Only the store labels for now, so topology relabeling
+    // doesn't need a rolling restart.
+    signal_handler::handle_signal(engines, || {
+        let path = match cfg_path {
+            Some(path) => path,
+            None => {
+                warn!("no config file was given on startup, nothing to reload");
+                return;
+            }
+        };
+        let new_cfg: TiKvConfig = match File::open(path)
+            .map_err::<Box<Error>, _>(|e| Box::new(e))
+            .and_then(|mut f| {
+                let mut s = String::new();
+                f.read_to_string(&mut s)?;
+                let c = toml::from_str(&s)?;
+                Ok(c)
+            }) {
+            Ok(c) => c,
+            Err(e) => {
+                error!("invalid configuration file {:?}: {}", path, e);
+                return;
+            }
+        };
+        let mut server_cfg = cfg.server.clone();
+        server_cfg.labels = new_cfg.server.labels;
+        if let Err(e) = server_cfg.validate() {
+            error!("invalid labels in {:?}: {:?}", path, e);
+            return;
+        }
+        if let Err(e) = node.refresh_store_labels(&server_cfg.labels) {
+            error!("failed to update store labels: {:?}", e);
+        }
+    });
 
     // Stop.
     server
@@ -324,6 +400,10 @@ fn overwrite_config_with_cmd_args(config: &mut TiKvConfig, matches: &ArgMatches)
         config.server.labels = labels;
     }
 
+    if matches.is_present("force-new-cluster") {
+        config.server.force_new_cluster = true;
+    }
+
     if let Some(capacity_str) = matches.value_of("capacity") {
         let capacity = capacity_str.parse().unwrap_or_else(|e| {
             fatal!("invalid capacity: {}", e);
@@ -457,6 +537,14 @@ fn main() {
                 .long("print-sample-config")
                 .help("Print a sample config to stdout"),
         )
+        .arg(
+            Arg::with_name("force-new-cluster")
+                .long("force-new-cluster")
+                .help(
+                    "Skip the startup check of the store id against PD, \
+                     to re-register an existing store into a new cluster",
+                ),
+        )
         .get_matches();
 
     if matches.is_present("print-sample-config") {
@@ -505,6 +593,12 @@ fn main() {
     // Before any startup, check system configuration.
     check_system_config(&config);
 
+    // Histograms capture their buckets when they are first used, so this
+    // must happen before any component touches a metric.
+    if !config.metric.latency_buckets.is_empty() {
+        util::metrics::set_latency_buckets(config.metric.latency_buckets.clone());
+    }
+
     configure_grpc_poll_strategy();
 
     let security_mgr = Arc::new(
@@ -523,5 +617,10 @@ fn main() {
     info!("connect to PD cluster {}", cluster_id);
 
     let _m = Monitor::default();
-    run_raft_server(pd_client, &config, security_mgr);
+    run_raft_server(
+        pd_client,
+        &config,
+        matches.value_of("config").map(Path::new),
+        security_mgr,
+    );
 }
@@ -26,13 +26,17 @@ use pd::Config as PdConfig;
 use raftstore::coprocessor::Config as CopConfig;
 use raftstore::store::Config as RaftstoreConfig;
 use raftstore::store::keys::region_raft_prefix_len;
-use storage::{Config as StorageConfig, CF_DEFAULT, CF_LOCK, CF_RAFT, CF_WRITE,
+use storage::{CfName, Config as StorageConfig, CF_APPLY, CF_DEFAULT, CF_LOCK, CF_RAFT, CF_WRITE,
               DEFAULT_ROCKSDB_SUB_DIR};
+use util::collections::HashMap;
 use util::config::{self, compression_type_level_serde, ReadableDuration, ReadableSize, GB, KB, MB};
 use util::properties::{MvccPropertiesCollectorFactory, SizePropertiesCollectorFactory};
-use util::rocksdb::{db_exist, CFOptions, EventListener, FixedPrefixSliceTransform,
-                    FixedSuffixSliceTransform, NoopSliceTransform};
+use util::rocksdb::raw_ttl::TtlCompactionFilter;
+use util::rocksdb::{db_exist, CFOptions, CompactionGuardFactory, EventListener,
+                    FixedPrefixSliceTransform, FixedSuffixSliceTransform, NoopSliceTransform,
+                    RegionBoundaries};
 use util::security::SecurityConfig;
+use util::slow_log::{self, Subsystem};
 
 const LOCKCF_MIN_MEM: usize = 256 * MB as usize;
 const LOCKCF_MAX_MEM: usize = GB as usize;
@@ -93,7 +97,7 @@ macro_rules! cf_config {
     }
 }
 
-macro_rules! build_cf_opt {
+macro_rules! build_block_base_opts {
     ($opt:ident) => {{
         let mut block_base_opts = BlockBasedOptions::new();
         block_base_opts.set_block_size($opt.block_size.0 as usize);
@@ -108,8 +112,23 @@ macro_rules! build_cf_opt {
             block_base_opts.set_whole_key_filtering($opt.whole_key_filtering);
         }
         block_base_opts.set_read_amp_bytes_per_bit($opt.read_amp_bytes_per_bit);
+        block_base_opts
+    }};
+}
+
+macro_rules! build_cf_opt {
+    ($opt:ident) => {{
+        let block_base_opts = build_block_base_opts!($opt);
+        build_cf_opt!($opt, block_base_opts)
+    }};
+    ($opt:ident, $cache:expr, $cf:expr) => {{
+        let mut block_base_opts = build_block_base_opts!($opt);
+        $cache.apply(&mut block_base_opts, $cf);
+        build_cf_opt!($opt, block_base_opts)
+    }};
+    ($opt:ident, $block_base_opts:expr) => {{
         let mut cf_opts = ColumnFamilyOptions::new();
-        cf_opts.set_block_based_table_factory(&block_base_opts);
+        cf_opts.set_block_based_table_factory(&$block_base_opts);
         cf_opts.set_num_levels($opt.num_levels);
         assert!($opt.compression_per_level.len() >= $opt.num_levels as usize);
         let compression_per_level = $opt.compression_per_level[..$opt.num_levels as usize].to_vec();
@@ -131,6 +150,94 @@ macro_rules! build_cf_opt {
     }};
 }
 
+#[derive(Clone, Serialize, Deserialize, PartialEq, Debug)]
+#[serde(default)]
+#[serde(rename_all = "kebab-case")]
+pub struct SecondaryCacheConfig {
+    pub path: String,
+    pub capacity: ReadableSize,
+    pub optimized_for_nvm: bool,
+}
+
+impl Default for SecondaryCacheConfig {
+    fn default() -> SecondaryCacheConfig {
+        SecondaryCacheConfig {
+            path: "".to_owned(),
+            capacity: ReadableSize::gb(0),
+            optimized_for_nvm: true,
+        }
+    }
+}
+
+impl SecondaryCacheConfig {
+    fn enabled(&self) -> bool {
+        !self.path.is_empty()
+    }
+
+    fn apply(&self, block_base_opts: &mut BlockBasedOptions, cf: &str) {
+        if !self.enabled() {
+            return;
+        }
+        // A persistent cache directory can not be shared, every cf keeps its
+        // own cache files under the configured path.
+        let path = format!("{}/{}", self.path, cf);
+        block_base_opts.set_persistent_cache(&path, self.capacity.0, self.optimized_for_nvm);
+    }
+
+    fn validate(&self) -> Result<(), Box<Error>> {
+        if self.enabled() && self.capacity.0 == 0 {
+            return Err("secondary-cache.capacity must be set when a path is configured".into());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize, PartialEq, Debug)]
+#[serde(default)]
+#[serde(rename_all = "kebab-case")]
+pub struct CompactionGuardConfig {
+    pub enable: bool,
+    pub min_output_file_size: ReadableSize,
+    pub max_output_file_size: ReadableSize,
+}
+
+impl Default for CompactionGuardConfig {
+    fn default() -> CompactionGuardConfig {
+        CompactionGuardConfig {
+            enable: false,
+            min_output_file_size: ReadableSize::mb(8),
+            max_output_file_size: ReadableSize::mb(128),
+        }
+    }
+}
+
+impl CompactionGuardConfig {
+    fn apply(&self, cf_opts: &mut ColumnFamilyOptions, cf: CfName, boundaries: &RegionBoundaries) {
+        if !self.enable {
+            return;
+        }
+        // Let output files stretch to the next region boundary instead of
+        // being cut at the cf target file size.
+        cf_opts.set_target_file_size_base(self.max_output_file_size.0);
+        let f = Box::new(CompactionGuardFactory::new(
+            cf,
+            boundaries.clone(),
+            self.min_output_file_size.0,
+        ));
+        cf_opts.set_sst_partitioner_factory("tikv.compaction-guard", f);
+    }
+
+    fn validate(&self) -> Result<(), Box<Error>> {
+        if self.enable && self.min_output_file_size.0 > self.max_output_file_size.0 {
+            return Err(
+                "compaction-guard.min-output-file-size must not exceed max-output-file-size"
+                    .into(),
+            );
+        }
+        Ok(())
+    }
+}
+
 cf_config!(DefaultCfConfig);
 
 impl Default for DefaultCfConfig {
@@ -173,8 +280,8 @@ impl Default for DefaultCfConfig {
 }
 
 impl DefaultCfConfig {
-    pub fn build_opt(&self) -> ColumnFamilyOptions {
-        let mut cf_opts = build_cf_opt!(self);
+    pub fn build_opt(&self, cache: &SecondaryCacheConfig) -> ColumnFamilyOptions {
+        let mut cf_opts = build_cf_opt!(self, cache, CF_DEFAULT);
         let f = Box::new(SizePropertiesCollectorFactory::default());
         cf_opts.add_table_properties_collector_factory("tikv.size-properties-collector", f);
         cf_opts
@@ -223,8 +330,8 @@ impl Default for WriteCfConfig {
 }
 
 impl WriteCfConfig {
-    pub fn build_opt(&self) -> ColumnFamilyOptions {
-        let mut cf_opts = build_cf_opt!(self);
+    pub fn build_opt(&self, cache: &SecondaryCacheConfig) -> ColumnFamilyOptions {
+        let mut cf_opts = build_cf_opt!(self, cache, CF_WRITE);
         // Prefix extractor(trim the timestamp at tail) for write cf.
         let e = Box::new(FixedSuffixSliceTransform::new(8));
         cf_opts
@@ -331,6 +438,53 @@ impl RaftCfConfig {
     }
 }
 
+cf_config!(ApplyCfConfig);
+
+impl Default for ApplyCfConfig {
+    fn default() -> ApplyCfConfig {
+        ApplyCfConfig {
+            block_size: ReadableSize::kb(16),
+            block_cache_size: ReadableSize::mb(32),
+            disable_block_cache: false,
+            cache_index_and_filter_blocks: true,
+            pin_l0_filter_and_index_blocks: true,
+            use_bloom_filter: true,
+            whole_key_filtering: true,
+            bloom_filter_bits_per_key: 10,
+            block_based_bloom_filter: false,
+            read_amp_bytes_per_bit: 0,
+            compression_per_level: [DBCompressionType::No; 7],
+            // Apply states are tiny but rewritten on every apply, so keep the
+            // cf small and compact it aggressively to limit write amplification.
+            write_buffer_size: ReadableSize::mb(32),
+            max_write_buffer_number: 5,
+            min_write_buffer_number_to_merge: 1,
+            max_bytes_for_level_base: ReadableSize::mb(32),
+            target_file_size_base: ReadableSize::mb(8),
+            level0_file_num_compaction_trigger: 1,
+            level0_slowdown_writes_trigger: 20,
+            level0_stop_writes_trigger: 36,
+            max_compaction_bytes: ReadableSize::gb(2),
+            compaction_pri: CompactionPriority::ByCompensatedSize,
+            dynamic_level_bytes: false,
+            num_levels: 7,
+            max_bytes_for_level_multiplier: 10,
+        }
+    }
+}
+
+impl ApplyCfConfig {
+    pub fn build_opt(&self) -> ColumnFamilyOptions {
+        let mut cf_opts = build_cf_opt!(self);
+        let f = Box::new(NoopSliceTransform);
+        cf_opts
+            .set_prefix_extractor("NoopSliceTransform", f)
+            .unwrap();
+        cf_opts.set_memtable_prefix_bloom_size_ratio(0.1);
+        cf_opts
+    }
+}
+
 #[derive(Clone, Serialize, Deserialize, PartialEq, Debug)]
 #[serde(default)]
 #[serde(rename_all = "kebab-case")]
@@ -351,16 +505,21 @@ pub struct DbConfig {
     pub info_log_roll_time: ReadableDuration,
     pub info_log_dir: String,
     pub rate_bytes_per_sec: ReadableSize,
+    pub rate_limiter_auto_tuned: bool,
     pub bytes_per_sync: ReadableSize,
     pub wal_bytes_per_sync: ReadableSize,
     pub max_sub_compactions: u32,
     pub writable_file_max_buffer_size: ReadableSize,
     pub use_direct_io_for_flush_and_compaction: bool,
     pub enable_pipelined_write: bool,
+    pub enable_atomic_flush: bool,
     pub defaultcf: DefaultCfConfig,
     pub writecf: WriteCfConfig,
     pub lockcf: LockCfConfig,
     pub raftcf: RaftCfConfig,
+    pub applycf: ApplyCfConfig,
+    pub secondary_cache: SecondaryCacheConfig,
+    pub compaction_guard: CompactionGuardConfig,
 }
 
 impl Default for DbConfig {
@@ -382,16 +541,21 @@ impl Default for DbConfig {
             info_log_roll_time: ReadableDuration::secs(0),
             info_log_dir: "".to_owned(),
             rate_bytes_per_sec: ReadableSize::kb(0),
+            rate_limiter_auto_tuned: false,
             bytes_per_sync: ReadableSize::mb(0),
             wal_bytes_per_sync: ReadableSize::kb(0),
             max_sub_compactions: 1,
             writable_file_max_buffer_size: ReadableSize::mb(1),
             use_direct_io_for_flush_and_compaction: false,
             enable_pipelined_write: true,
+            enable_atomic_flush: false,
             defaultcf: DefaultCfConfig::default(),
             writecf: WriteCfConfig::default(),
             lockcf: LockCfConfig::default(),
             raftcf: RaftCfConfig::default(),
+            applycf: ApplyCfConfig::default(),
+            secondary_cache: SecondaryCacheConfig::default(),
+            compaction_guard: CompactionGuardConfig::default(),
         }
     }
 }
@@ -425,7 +589,14 @@ impl DbConfig {
                 })
         }
         if self.rate_bytes_per_sec.0 > 0 {
-            opts.set_ratelimiter(self.rate_bytes_per_sec.0 as i64);
+            if self.rate_limiter_auto_tuned {
+                // The limiter adjusts itself between rate-bytes-per-sec / 20
+                // and rate-bytes-per-sec according to how congested it is, so
+                // the configured value only acts as an upper bound.
+                opts.set_ratelimiter_with_auto_tuned(self.rate_bytes_per_sec.0 as i64);
+            } else {
+                opts.set_ratelimiter(self.rate_bytes_per_sec.0 as i64);
+            }
         }
         opts.set_bytes_per_sync(self.bytes_per_sync.0 as u64);
         opts.set_wal_bytes_per_sync(self.wal_bytes_per_sync.0 as u64);
@@ -435,21 +606,44 @@ impl DbConfig {
             self.use_direct_io_for_flush_and_compaction,
         );
         opts.enable_pipelined_write(self.enable_pipelined_write);
+        opts.enable_atomic_flush(self.enable_atomic_flush);
         opts.add_event_listener(EventListener::new("kv"));
         opts
     }
 
-    pub fn build_cf_opts(&self) -> Vec<CFOptions> {
+    pub fn build_cf_opts(
+        &self,
+        region_boundaries: Option<&RegionBoundaries>,
+        raw_ttl: bool,
+    ) -> Vec<CFOptions> {
+        let mut default_opt = self.defaultcf.build_opt(&self.secondary_cache);
+        let mut write_opt = self.writecf.build_opt(&self.secondary_cache);
+        if raw_ttl {
+            // Raw data lives in the default cf, the other cfs never carry
+            // the expiry suffix.
+            default_opt
+                .set_compaction_filter("raw-ttl", box TtlCompactionFilter::default())
+                .unwrap();
+        }
+        if let Some(boundaries) = region_boundaries {
+            // Only the data cfs, their keys are what region boundaries split.
+            self.compaction_guard
+                .apply(&mut default_opt, CF_DEFAULT, boundaries);
+            self.compaction_guard
+                .apply(&mut write_opt, CF_WRITE, boundaries);
+        }
         vec![
-            CFOptions::new(CF_DEFAULT, self.defaultcf.build_opt()),
+            CFOptions::new(CF_DEFAULT, default_opt),
             CFOptions::new(CF_LOCK, self.lockcf.build_opt()),
-            CFOptions::new(CF_WRITE, self.writecf.build_opt()),
+            CFOptions::new(CF_WRITE, write_opt),
             CFOptions::new(CF_RAFT, self.raftcf.build_opt()),
+            CFOptions::new(CF_APPLY, self.applycf.build_opt()),
         ]
     }
 
     fn validate(&mut self) -> Result<(), Box<Error>> {
-        Ok(())
+        self.secondary_cache.validate()?;
+        self.compaction_guard.validate()
     }
 }
 
@@ -619,6 +813,18 @@ pub struct MetricConfig {
     pub interval: ReadableDuration,
     pub address: String,
     pub job: String,
+    /// A common prefix prepended to every metric name before it is pushed.
+    pub prefix: String,
+    /// Overrides the default buckets of latency histograms, in seconds.
+    pub latency_buckets: Vec<f64>,
+    /// Extra grouping labels (e.g. cluster id) attached to pushed metrics.
+    #[serde(with = "config::order_map_serde")] pub labels: HashMap<String, String>,
+    /// Credentials for push gateways behind HTTP basic authentication.
+    pub basic_auth_user: String,
+    pub basic_auth_password: String,
+    /// Disables pushing even if `address` is set; metrics stay available
+    /// for pull based collection.
+    pub disable_push: bool,
 }
 
 impl Default for MetricConfig {
@@ -627,6 +833,12 @@ impl Default for MetricConfig {
             interval: ReadableDuration::secs(15),
             address: "".to_owned(),
             job: "tikv".to_owned(),
+            prefix: "".to_owned(),
+            latency_buckets: Vec::new(),
+            labels: HashMap::default(),
+            basic_auth_user: "".to_owned(),
+            basic_auth_password: "".to_owned(),
+            disable_push: false,
         }
     }
 }
@@ -643,6 +855,37 @@ pub enum LogLevel {
     Off,
 }
 
+#[derive(Clone, Serialize, Deserialize, PartialEq, Debug)]
+#[serde(default)]
+#[serde(rename_all = "kebab-case")]
+pub struct SlowLogConfig {
+    pub raftstore: ReadableDuration,
+    pub apply: ReadableDuration,
+    pub coprocessor: ReadableDuration,
+    pub scheduler: ReadableDuration,
+}
+
+impl Default for SlowLogConfig {
+    fn default() -> SlowLogConfig {
+        SlowLogConfig {
+            raftstore: ReadableDuration::secs(1),
+            apply: ReadableDuration::secs(1),
+            coprocessor: ReadableDuration::secs(1),
+            scheduler: ReadableDuration::secs(1),
+        }
+    }
+}
+
+impl SlowLogConfig {
+    /// Installs the thresholds into the process wide slow-log facility.
+    pub fn apply(&self) {
+        slow_log::set_threshold(Subsystem::Raftstore, self.raftstore.0);
+        slow_log::set_threshold(Subsystem::Apply, self.apply.0);
+        slow_log::set_threshold(Subsystem::Coprocessor, self.coprocessor.0);
+        slow_log::set_threshold(Subsystem::Scheduler, self.scheduler.0);
+    }
+}
+
 #[derive(Clone, Serialize, Deserialize, PartialEq, Debug)]
 #[serde(default)]
 #[serde(rename_all = "kebab-case")]
@@ -654,6 +897,7 @@ pub struct TiKvConfig {
     pub storage: StorageConfig,
     pub pd: PdConfig,
     pub metric: MetricConfig,
+    pub slow_log: SlowLogConfig,
     #[serde(rename = "raftstore")] pub raft_store: RaftstoreConfig,
     pub coprocessor: CopConfig,
     pub rocksdb: DbConfig,
@@ -670,6 +914,7 @@ impl Default for TiKvConfig {
             readpool: ReadPoolConfig::default(),
             server: ServerConfig::default(),
             metric: MetricConfig::default(),
+            slow_log: SlowLogConfig::default(),
             raft_store: RaftstoreConfig::default(),
             coprocessor: CopConfig::default(),
             pd: PdConfig::default(),
@@ -706,6 +951,14 @@ impl TiKvConfig {
             return Err("default rocksdb not exist, buf raftdb exist".into());
         }
 
+        if self.raft_store.disable_kv_wal && !self.rocksdb.enable_atomic_flush {
+            return Err(
+                "raftstore.disable-kv-wal requires rocksdb.enable-atomic-flush, \
+                 otherwise kv cfs may recover to inconsistent apply indexes"
+                    .into(),
+            );
+        }
+
         self.rocksdb.validate()?;
         self.server.validate()?;
         self.raft_store.validate()?;
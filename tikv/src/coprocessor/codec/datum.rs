@@ -915,6 +915,38 @@ pub fn encode_value(values: &[Datum]) -> Result<Vec<u8>> {
     encode(values, false)
 }
 
+/// Encodes a composite sort key so that comparing two encoded keys
+/// byte by byte orders them like comparing the datums column by column,
+/// with descending columns reversed. Descending columns are encoded in
+/// the comparable format and then bitwise complemented.
+///
+/// The caller must produce each column from the same expression for
+/// every row: mixed types in one column compare by their encoding flag
+/// bytes instead of going through type coercion like `Datum::cmp` does.
+#[allow(needless_range_loop)]
+pub fn encode_sort_key(values: &[Datum], descs: &[bool]) -> Result<Vec<u8>> {
+    let mut buf = Vec::with_capacity(approximate_size(values, true));
+    for i in 0..values.len() {
+        // Columns are encoded one by one, so the check in `encode`
+        // never sees a datum following `Datum::Min`.
+        if let Datum::Min = values[i] {
+            if i + 1 != values.len() {
+                return Err(invalid_type!(
+                    "MinValue should be the last datum.".to_owned()
+                ));
+            }
+        }
+        let start = buf.len();
+        buf.encode(&values[i..i + 1], true)?;
+        if descs.get(i).cloned().unwrap_or(false) {
+            for b in &mut buf[start..] {
+                *b = !*b;
+            }
+        }
+    }
+    Ok(buf)
+}
+
 pub fn encode_to(buf: &mut Vec<u8>, values: &[Datum], comparable: bool) -> Result<()> {
     buf.reserve(approximate_size(values, comparable));
     buf.encode(values, comparable)?;
@@ -1066,6 +1098,34 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_encode_sort_key() {
+        let rows = vec![
+            vec![Datum::Null, Datum::I64(3)],
+            vec![Datum::Bytes(b"aaa".to_vec()), Datum::I64(2)],
+            vec![Datum::Bytes(b"aaa".to_vec()), Datum::I64(1)],
+            vec![Datum::Bytes(b"abc".to_vec()), Datum::I64(-1)],
+        ];
+        let tests = vec![
+            (vec![false, false], vec![0, 2, 1, 3]),
+            (vec![true, false], vec![3, 2, 1, 0]),
+            (vec![false, true], vec![0, 1, 2, 3]),
+            (vec![true, true], vec![3, 1, 2, 0]),
+        ];
+        for (descs, exp) in tests {
+            let mut keys: Vec<(Vec<u8>, usize)> = rows.iter()
+                .enumerate()
+                .map(|(i, vs)| (encode_sort_key(vs, &descs).unwrap(), i))
+                .collect();
+            keys.sort();
+            let order: Vec<usize> = keys.into_iter().map(|(_, i)| i).collect();
+            assert_eq!(order, exp, "unexpected order for descs {:?}", descs);
+        }
+
+        // `Datum::Min` may only be encoded as the last datum of a key.
+        assert!(encode_sort_key(&[Datum::Min, Datum::I64(1)], &[false, false]).is_err());
+    }
+
     #[test]
     fn test_datum_cmp() {
         let tests = vec![
@@ -43,17 +43,19 @@ impl DAGContext {
         snap: Box<Snapshot>,
         req_ctx: Arc<ReqContext>,
         batch_row_limit: usize,
+        scan_seek_bound: usize,
     ) -> Result<DAGContext> {
         let eval_ctx = Arc::new(box_try!(EvalContext::new(
             req.get_time_zone_offset(),
             req.get_flags()
         )));
-        let store = SnapshotStore::new(
+        let mut store = SnapshotStore::new(
             snap,
             req.get_start_ts(),
             req_ctx.isolation_level,
             req_ctx.fill_cache,
         );
+        store.set_scan_seek_bound(scan_seek_bound);
 
         let dag_executor = build_exec(req.take_executors().into_vec(), store, ranges, eval_ctx)?;
         Ok(DAGContext {
@@ -112,6 +114,17 @@ impl DAGContext {
         }
     }
 
+    /// Returns how many rows were actually scanned for each key range
+    /// of the request, keyed by range index, so the SQL optimizer can
+    /// correct its row count estimations. `SelectResponse` carries no
+    /// field for them in the tipb version this build uses, returning
+    /// them to the SQL layer needs a protocol extension.
+    pub fn collect_scan_counts(&mut self) -> Vec<(usize, i64)> {
+        let mut counts = Vec::new();
+        self.exec.collect_scan_counts(&mut counts);
+        counts
+    }
+
     pub fn collect_metrics_into(&mut self, metrics: &mut ExecutorMetrics) {
         self.exec.collect_metrics_into(metrics);
     }
@@ -201,6 +201,10 @@ impl Executor for HashAggExecutor {
         self.count = 0;
     }
 
+    fn collect_scan_counts(&mut self, counts: &mut Vec<(usize, i64)>) {
+        self.src.collect_scan_counts(counts);
+    }
+
     fn collect_metrics_into(&mut self, metrics: &mut ExecutorMetrics) {
         self.src.collect_metrics_into(metrics);
         if self.first_collect {
@@ -253,6 +257,10 @@ impl Executor for StreamAggExecutor {
         self.count = 0;
     }
 
+    fn collect_scan_counts(&mut self, counts: &mut Vec<(usize, i64)>) {
+        self.src.collect_scan_counts(counts);
+    }
+
     fn collect_metrics_into(&mut self, metrics: &mut ExecutorMetrics) {
         self.src.collect_metrics_into(metrics);
         if self.first_collect {
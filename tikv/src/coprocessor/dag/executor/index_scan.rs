@@ -32,10 +32,11 @@ pub struct IndexScanExecutor {
     desc: bool,
     col_ids: Vec<i64>,
     pk_col: Option<ColumnInfo>,
-    key_ranges: IntoIter<KeyRange>,
+    key_ranges: IntoIter<(usize, KeyRange)>,
     scanner: Option<Scanner>,
     unique: bool,
     count: i64,
+    scan_counts: Vec<(usize, i64)>,
     metrics: ExecutorMetrics,
     first_collect: bool,
 }
@@ -43,13 +44,16 @@ pub struct IndexScanExecutor {
 impl IndexScanExecutor {
     pub fn new(
         mut meta: IndexScan,
-        mut key_ranges: Vec<KeyRange>,
+        key_ranges: Vec<KeyRange>,
         store: SnapshotStore,
         unique: bool,
     ) -> Result<IndexScanExecutor> {
         box_try!(table::check_table_ranges(&key_ranges));
         let mut pk_col = None;
         let desc = meta.get_desc();
+        // Ranges keep their index in the request so scanned row counts
+        // can be keyed by it even for descending scans.
+        let mut key_ranges: Vec<_> = key_ranges.into_iter().enumerate().collect();
         if desc {
             key_ranges.reverse();
         }
@@ -68,6 +72,7 @@ impl IndexScanExecutor {
             scanner: None,
             unique: unique,
             count: 0,
+            scan_counts: Vec::new(),
             metrics: Default::default(),
             first_collect: true,
         })
@@ -85,10 +90,11 @@ impl IndexScanExecutor {
             desc: false,
             col_ids: col_ids,
             pk_col: None,
-            key_ranges: key_ranges.into_iter(),
+            key_ranges: key_ranges.into_iter().enumerate().collect::<Vec<_>>().into_iter(),
             scanner: None,
             unique: false,
             count: 0,
+            scan_counts: Vec::new(),
             metrics: ExecutorMetrics::default(),
             first_collect: true,
         })
@@ -157,12 +163,15 @@ impl Executor for IndexScanExecutor {
         loop {
             if let Some(row) = self.get_row_from_range_scanner()? {
                 self.count += 1;
+                self.scan_counts.last_mut().unwrap().1 += 1;
                 return Ok(Some(row));
             }
-            if let Some(range) = self.key_ranges.next() {
+            if let Some((idx, range)) = self.key_ranges.next() {
+                self.scan_counts.push((idx, 0));
                 if self.is_point(&range) {
                     if let Some(row) = self.get_row_from_point(range)? {
                         self.count += 1;
+                        self.scan_counts.last_mut().unwrap().1 += 1;
                         return Ok(Some(row));
                     }
                     continue;
@@ -185,6 +194,10 @@ impl Executor for IndexScanExecutor {
         self.count = 0;
     }
 
+    fn collect_scan_counts(&mut self, counts: &mut Vec<(usize, i64)>) {
+        counts.append(&mut self.scan_counts);
+    }
+
     fn collect_metrics_into(&mut self, metrics: &mut ExecutorMetrics) {
         metrics.merge(&mut self.metrics);
         if let Some(scanner) = self.scanner.take() {
@@ -55,6 +55,10 @@ impl<'a> Executor for LimitExecutor<'a> {
         // We do not know whether `limit` has consumed all of it's source, so just ignore it.
     }
 
+    fn collect_scan_counts(&mut self, counts: &mut Vec<(usize, i64)>) {
+        self.src.collect_scan_counts(counts);
+    }
+
     fn collect_metrics_into(&mut self, metrics: &mut ExecutorMetrics) {
         self.src.collect_metrics_into(metrics);
         if self.first_collect {
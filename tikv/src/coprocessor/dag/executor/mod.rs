@@ -135,6 +135,9 @@ impl Row {
 pub trait Executor {
     fn next(&mut self) -> Result<Option<Row>>;
     fn collect_output_counts(&mut self, counts: &mut Vec<i64>);
+    // Collects how many rows were scanned for each key range of the
+    // request, keyed by range index.
+    fn collect_scan_counts(&mut self, counts: &mut Vec<(usize, i64)>);
     fn collect_metrics_into(&mut self, metrics: &mut ExecutorMetrics);
 }
 
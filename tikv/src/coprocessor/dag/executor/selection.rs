@@ -82,6 +82,10 @@ impl Executor for SelectionExecutor {
         self.count = 0;
     }
 
+    fn collect_scan_counts(&mut self, counts: &mut Vec<(usize, i64)>) {
+        self.src.collect_scan_counts(counts);
+    }
+
     fn collect_metrics_into(&mut self, metrics: &mut ExecutorMetrics) {
         self.src.collect_metrics_into(metrics);
         if self.first_collect {
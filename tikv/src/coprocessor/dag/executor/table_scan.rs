@@ -29,9 +29,10 @@ pub struct TableScanExecutor {
     store: SnapshotStore,
     desc: bool,
     col_ids: HashSet<i64>,
-    key_ranges: IntoIter<KeyRange>,
+    key_ranges: IntoIter<(usize, KeyRange)>,
     scanner: Option<Scanner>,
     count: i64,
+    scan_counts: Vec<(usize, i64)>,
     metrics: ExecutorMetrics,
     first_collect: bool,
 }
@@ -39,7 +40,7 @@ pub struct TableScanExecutor {
 impl TableScanExecutor {
     pub fn new(
         meta: &TableScan,
-        mut key_ranges: Vec<KeyRange>,
+        key_ranges: Vec<KeyRange>,
         store: SnapshotStore,
     ) -> Result<TableScanExecutor> {
         box_try!(table::check_table_ranges(&key_ranges));
@@ -50,6 +51,9 @@ impl TableScanExecutor {
             .collect();
 
         let desc = meta.get_desc();
+        // Ranges keep their index in the request so scanned row counts
+        // can be keyed by it even for descending scans.
+        let mut key_ranges: Vec<_> = key_ranges.into_iter().enumerate().collect();
         if desc {
             key_ranges.reverse();
         }
@@ -61,6 +65,7 @@ impl TableScanExecutor {
             key_ranges: key_ranges.into_iter(),
             scanner: None,
             count: 0,
+            scan_counts: Vec::new(),
             metrics: Default::default(),
             first_collect: true,
         })
@@ -108,14 +113,17 @@ impl Executor for TableScanExecutor {
         loop {
             if let Some(row) = self.get_row_from_range_scanner()? {
                 self.count += 1;
+                self.scan_counts.last_mut().unwrap().1 += 1;
                 return Ok(Some(row));
             }
 
-            if let Some(range) = self.key_ranges.next() {
+            if let Some((idx, range)) = self.key_ranges.next() {
+                self.scan_counts.push((idx, 0));
                 if is_point(&range) {
                     self.metrics.scan_counter.inc_point();
                     if let Some(row) = self.get_row_from_point(range)? {
                         self.count += 1;
+                        self.scan_counts.last_mut().unwrap().1 += 1;
                         return Ok(Some(row));
                     }
                     continue;
@@ -138,6 +146,10 @@ impl Executor for TableScanExecutor {
         self.count = 0;
     }
 
+    fn collect_scan_counts(&mut self, counts: &mut Vec<(usize, i64)>) {
+        counts.append(&mut self.scan_counts);
+    }
+
     fn collect_metrics_into(&mut self, metrics: &mut ExecutorMetrics) {
         metrics.merge(&mut self.metrics);
         if let Some(scanner) = self.scanner.take() {
@@ -267,6 +279,9 @@ mod test {
             }
         }
         assert!(table_scanner.next().unwrap().is_none());
+        let mut scan_counts = Vec::with_capacity(4);
+        table_scanner.collect_scan_counts(&mut scan_counts);
+        assert_eq!(scan_counts, vec![(0, 0), (1, 5), (2, 1), (3, 4)]);
     }
 
     #[test]
@@ -303,5 +318,10 @@ mod test {
             }
         }
         assert!(table_scanner.next().unwrap().is_none());
+        // Counts stay keyed by the range index of the request even
+        // though the ranges are walked in reverse.
+        let mut scan_counts = Vec::with_capacity(4);
+        table_scanner.collect_scan_counts(&mut scan_counts);
+        assert_eq!(scan_counts, vec![(3, 4), (2, 1), (1, 5), (0, 0)]);
     }
 }
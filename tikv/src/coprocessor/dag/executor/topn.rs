@@ -27,7 +27,7 @@ use super::topn_heap::{SortRow, TopNHeap};
 use super::{inflate_with_col_for_dag, Executor, ExecutorMetrics, ExprColumnRefVisitor, Row};
 
 struct OrderBy {
-    items: Arc<Vec<ByItem>>,
+    descs: Vec<bool>,
     exprs: Vec<Expression>,
 }
 
@@ -40,7 +40,7 @@ impl OrderBy {
                 .collect()
         );
         Ok(OrderBy {
-            items: Arc::new(order_by),
+            descs: order_by.iter().map(ByItem::get_desc).collect(),
             exprs: exprs,
         })
     }
@@ -105,15 +105,9 @@ impl TopNExecutor {
                 row.handle,
             )?;
             let ob_values = self.order_by.eval(&self.ctx, &cols)?;
-            heap.try_add_row(
-                row.handle,
-                row.data,
-                ob_values,
-                Arc::clone(&self.order_by.items),
-                Arc::clone(&self.ctx),
-            )?;
+            heap.try_add_row(row.handle, row.data, ob_values, &self.order_by.descs)?;
         }
-        self.iter = Some(heap.into_sorted_vec()?.into_iter());
+        self.iter = Some(heap.into_sorted_vec().into_iter());
         Ok(())
     }
 }
@@ -142,6 +136,10 @@ impl Executor for TopNExecutor {
         self.count = 0;
     }
 
+    fn collect_scan_counts(&mut self, counts: &mut Vec<(usize, i64)>) {
+        self.src.collect_scan_counts(counts);
+    }
+
     fn collect_metrics_into(&mut self, metrics: &mut ExecutorMetrics) {
         self.src.collect_metrics_into(metrics);
         if self.first_collect {
@@ -184,12 +182,7 @@ pub mod test {
 
     #[test]
     pub fn test_topn_heap() {
-        let mut order_cols = Vec::new();
-        order_cols.push(new_order_by(0, true));
-        order_cols.push(new_order_by(1, false));
-        let order_cols = Arc::new(order_cols);
-        let ctx = Arc::new(EvalContext::default());
-
+        let descs = vec![true, false];
         let mut topn_heap = TopNHeap::new(5).unwrap();
 
         let test_data = vec![
@@ -281,16 +274,10 @@ pub mod test {
             let ob_values: Vec<Datum> = vec![name, count];
             let row_data = RowColsDict::new(HashMap::default(), data.into_bytes());
             topn_heap
-                .try_add_row(
-                    i64::from(handle),
-                    row_data,
-                    ob_values,
-                    Arc::clone(&order_cols),
-                    Arc::clone(&ctx),
-                )
+                .try_add_row(i64::from(handle), row_data, ob_values, &descs)
                 .unwrap();
         }
-        let result = topn_heap.into_sorted_vec().unwrap();
+        let result = topn_heap.into_sorted_vec();
         assert_eq!(result.len(), exp.len());
         for (row, (handle, _, name, count)) in result.iter().zip(exp) {
             let exp_key: Vec<Datum> = vec![name, count];
@@ -300,53 +287,31 @@ pub mod test {
     }
 
     #[test]
-    fn test_topn_heap_with_cmp_error() {
-        let mut order_cols = Vec::new();
-        order_cols.push(new_order_by(0, false));
-        order_cols.push(new_order_by(1, true));
-        let order_cols = Arc::new(order_cols);
-        let ctx = Arc::new(EvalContext::default());
+    fn test_topn_heap_with_encode_error() {
+        let descs = vec![false, true];
         let mut topn_heap = TopNHeap::new(5).unwrap();
 
         let ob_values1: Vec<Datum> = vec![Datum::Bytes(b"aaa".to_vec()), Datum::I64(2)];
         let row_data = RowColsDict::new(HashMap::default(), b"name:1".to_vec());
         topn_heap
-            .try_add_row(
-                0 as i64,
-                row_data,
-                ob_values1,
-                Arc::clone(&order_cols),
-                Arc::clone(&ctx),
-            )
+            .try_add_row(0 as i64, row_data, ob_values1, &descs)
             .unwrap();
 
         let ob_values2: Vec<Datum> = vec![Datum::Bytes(b"aaa".to_vec()), Datum::I64(3)];
         let row_data2 = RowColsDict::new(HashMap::default(), b"name:2".to_vec());
         topn_heap
-            .try_add_row(
-                0 as i64,
-                row_data2,
-                ob_values2,
-                Arc::clone(&order_cols),
-                Arc::clone(&ctx),
-            )
+            .try_add_row(0 as i64, row_data2, ob_values2, &descs)
             .unwrap();
 
-        let bad_key1: Vec<Datum> = vec![Datum::I64(2), Datum::Bytes(b"aaa".to_vec())];
+        let bad_key: Vec<Datum> = vec![Datum::Min, Datum::I64(2)];
         let row_data3 = RowColsDict::new(HashMap::default(), b"name:3".to_vec());
 
         assert!(
             topn_heap
-                .try_add_row(
-                    0 as i64,
-                    row_data3,
-                    bad_key1,
-                    Arc::clone(&order_cols),
-                    Arc::clone(&ctx)
-                )
+                .try_add_row(0 as i64, row_data3, bad_key, &descs)
                 .is_err()
         );
-        assert!(topn_heap.into_sorted_vec().is_err());
+        assert_eq!(topn_heap.into_sorted_vec().len(), 2);
     }
 
     // the first column should be i64 since it will be used as row handle
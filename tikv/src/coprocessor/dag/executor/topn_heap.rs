@@ -13,14 +13,10 @@
 
 use std::usize;
 use std::collections::BinaryHeap;
-use std::sync::Arc;
 use std::cmp::{self, Ordering};
-use std::cell::RefCell;
-use tipb::expression::ByItem;
 
 use coprocessor::codec::table::RowColsDict;
-use coprocessor::codec::datum::Datum;
-use coprocessor::dag::expr::EvalContext;
+use coprocessor::codec::datum::{self, Datum};
 use coprocessor::Result;
 
 const HEAP_MAX_CAPACITY: usize = 1024;
@@ -29,71 +25,23 @@ pub struct SortRow {
     pub handle: i64,
     pub data: RowColsDict,
     pub key: Vec<Datum>,
-    order_cols: Arc<Vec<ByItem>>,
-    ctx: Arc<EvalContext>,
-    err: Arc<RefCell<Option<String>>>,
+    sort_key: Vec<u8>,
 }
 
 impl SortRow {
-    fn new(
-        handle: i64,
-        data: RowColsDict,
-        key: Vec<Datum>,
-        order_cols: Arc<Vec<ByItem>>,
-        ctx: Arc<EvalContext>,
-        err: Arc<RefCell<Option<String>>>,
-    ) -> SortRow {
+    fn new(handle: i64, data: RowColsDict, key: Vec<Datum>, sort_key: Vec<u8>) -> SortRow {
         SortRow {
             handle: handle,
             data: data,
             key: key,
-            order_cols: order_cols,
-            ctx: ctx,
-            err: err,
-        }
-    }
-
-    fn cmp_and_check(&self, right: &SortRow) -> Result<Ordering> {
-        // check err
-        self.check_err()?;
-        let values = self.key.iter().zip(right.key.iter());
-        for (col, (v1, v2)) in self.order_cols.as_ref().iter().zip(values) {
-            match v1.cmp(self.ctx.as_ref(), v2) {
-                Ok(Ordering::Equal) => {
-                    continue;
-                }
-                Ok(order) => {
-                    if col.get_desc() {
-                        return Ok(order.reverse());
-                    }
-                    return Ok(order);
-                }
-                Err(err) => {
-                    self.set_err(format!("cmp failed with:{:?}", err));
-                    self.check_err()?;
-                }
-            }
-        }
-        Ok(Ordering::Equal)
-    }
-
-    #[inline]
-    fn check_err(&self) -> Result<()> {
-        if let Some(ref err_msg) = *self.err.as_ref().borrow() {
-            return Err(box_err!(err_msg.to_owned()));
+            sort_key: sort_key,
         }
-        Ok(())
-    }
-
-    fn set_err(&self, err_msg: String) {
-        *self.err.borrow_mut() = Some(err_msg);
     }
 }
 
 pub struct TopNHeap {
     pub rows: BinaryHeap<SortRow>,
     limit: usize,
-    err: Arc<RefCell<Option<String>>>,
 }
 
 impl TopNHeap {
@@ -105,60 +53,45 @@ impl TopNHeap {
         Ok(TopNHeap {
             rows: BinaryHeap::with_capacity(cap),
             limit: limit,
-            err: Arc::new(RefCell::new(None)),
         })
     }
 
-    #[inline]
-    pub fn check_err(&self) -> Result<()> {
-        if let Some(ref err_msg) = *self.err.as_ref().borrow() {
-            return Err(box_err!(err_msg.to_owned()));
-        }
-        Ok(())
-    }
-
     pub fn try_add_row(
         &mut self,
         handle: i64,
         data: RowColsDict,
         values: Vec<Datum>,
-        order_cols: Arc<Vec<ByItem>>,
-        ctx: Arc<EvalContext>,
+        descs: &[bool],
     ) -> Result<()> {
         if self.limit == 0 {
             return Ok(());
         }
-        let row = SortRow::new(handle, data, values, order_cols, ctx, Arc::clone(&self.err));
+        // Rows are compared by their encoded sort keys, so each
+        // comparison is a single memcmp instead of a datum by datum
+        // walk over the order by columns.
+        let sort_key = datum::encode_sort_key(&values, descs)?;
+        let row = SortRow::new(handle, data, values, sort_key);
         // push into heap when heap is not full
         if self.rows.len() < self.limit {
             self.rows.push(row);
         } else {
             // swap top value with row when heap is full and current row is less than top data
             let mut top_data = self.rows.peek_mut().unwrap();
-            let order = row.cmp_and_check(&top_data)?;
-            if Ordering::Less == order {
+            if row.sort_key < top_data.sort_key {
                 *top_data = row;
             }
         }
-        self.check_err()
+        Ok(())
     }
 
-    pub fn into_sorted_vec(self) -> Result<Vec<SortRow>> {
-        let sorted_data = self.rows.into_sorted_vec();
-        // check is needed here since err may caused by any call of cmp
-        if let Some(ref err_msg) = *self.err.as_ref().borrow() {
-            return Err(box_err!(err_msg.to_owned()));
-        }
-        Ok(sorted_data)
+    pub fn into_sorted_vec(self) -> Vec<SortRow> {
+        self.rows.into_sorted_vec()
     }
 }
 
 impl Ord for SortRow {
     fn cmp(&self, right: &SortRow) -> Ordering {
-        if let Ok(order) = self.cmp_and_check(right) {
-            return order;
-        }
-        Ordering::Equal
+        self.sort_key.cmp(&right.sort_key)
     }
 }
 
@@ -178,35 +111,15 @@ impl PartialOrd for SortRow {
 
 #[cfg(test)]
 mod tests {
-    use std::sync::Arc;
-
-    use tipb::expression::{ByItem, Expr, ExprType};
-
     use util::collections::HashMap;
-    use util::codec::number::*;
     use coprocessor::codec::Datum;
     use coprocessor::codec::table::RowColsDict;
-    use coprocessor::dag::expr::EvalContext;
 
     use super::*;
 
-    fn new_order_by(col_id: i64, desc: bool) -> ByItem {
-        let mut item = ByItem::new();
-        let mut expr = Expr::new();
-        expr.set_tp(ExprType::ColumnRef);
-        expr.mut_val().encode_i64(col_id).unwrap();
-        item.set_expr(expr);
-        item.set_desc(desc);
-        item
-    }
-
     #[test]
     fn test_topn_heap() {
-        let mut order_cols = Vec::new();
-        order_cols.push(new_order_by(0, true));
-        order_cols.push(new_order_by(1, false));
-        let order_cols = Arc::new(order_cols);
-        let ctx = Arc::new(EvalContext::default());
+        let descs = vec![true, false];
         let mut topn_heap = TopNHeap::new(5).unwrap();
         let test_data = vec![
             (1, String::from("data1"), Datum::Null, Datum::I64(1)),
@@ -297,16 +210,10 @@ mod tests {
             let cur_key: Vec<Datum> = vec![name, count];
             let row_data = RowColsDict::new(HashMap::default(), data.into_bytes());
             topn_heap
-                .try_add_row(
-                    i64::from(handle),
-                    row_data,
-                    cur_key,
-                    Arc::clone(&order_cols),
-                    Arc::clone(&ctx),
-                )
+                .try_add_row(i64::from(handle), row_data, cur_key, &descs)
                 .unwrap();
         }
-        let result = topn_heap.into_sorted_vec().unwrap();
+        let result = topn_heap.into_sorted_vec();
         assert_eq!(result.len(), exp.len());
         for (row, (handle, _, name, count)) in result.iter().zip(exp) {
             let exp_keys: Vec<Datum> = vec![name, count];
@@ -316,63 +223,32 @@ mod tests {
     }
 
     #[test]
-    fn test_topn_heap_with_cmp_error() {
-        let mut order_cols = Vec::new();
-        order_cols.push(new_order_by(0, true));
-        order_cols.push(new_order_by(1, false));
-        let order_cols = Arc::new(order_cols);
-        let ctx = Arc::new(EvalContext::default());
+    fn test_topn_heap_with_encode_error() {
+        let descs = vec![true, false];
         let mut topn_heap = TopNHeap::new(5).unwrap();
 
         let std_key: Vec<Datum> = vec![Datum::Bytes(b"aaa".to_vec()), Datum::I64(2)];
         let row_data = RowColsDict::new(HashMap::default(), b"name:1".to_vec());
         topn_heap
-            .try_add_row(
-                0 as i64,
-                row_data,
-                std_key,
-                Arc::clone(&order_cols),
-                Arc::clone(&ctx),
-            )
+            .try_add_row(0 as i64, row_data, std_key, &descs)
             .unwrap();
 
-        let std_key2: Vec<Datum> = vec![Datum::Bytes(b"aaa".to_vec()), Datum::I64(3)];
+        // `Datum::Min` can not be encoded unless it is the last key column,
+        // the row must be rejected instead of being ordered arbitrarily.
+        let bad_key: Vec<Datum> = vec![Datum::Min, Datum::I64(2)];
         let row_data2 = RowColsDict::new(HashMap::default(), b"name:2".to_vec());
-        topn_heap
-            .try_add_row(
-                0 as i64,
-                row_data2,
-                std_key2,
-                Arc::clone(&order_cols),
-                Arc::clone(&ctx),
-            )
-            .unwrap();
-
-        let bad_key1: Vec<Datum> = vec![Datum::I64(2), Datum::Bytes(b"aaa".to_vec())];
-        let row_data3 = RowColsDict::new(HashMap::default(), b"name:3".to_vec());
-
         assert!(
             topn_heap
-                .try_add_row(
-                    0 as i64,
-                    row_data3,
-                    bad_key1,
-                    Arc::clone(&order_cols),
-                    Arc::clone(&ctx)
-                )
+                .try_add_row(0 as i64, row_data2, bad_key, &descs)
                 .is_err()
         );
 
-        assert!(topn_heap.into_sorted_vec().is_err());
+        assert_eq!(topn_heap.into_sorted_vec().len(), 1);
     }
 
     #[test]
     fn test_topn_heap_with_few_data() {
-        let mut order_cols = Vec::new();
-        order_cols.push(new_order_by(0, true));
-        order_cols.push(new_order_by(1, false));
-        let order_cols = Arc::new(order_cols);
-        let ctx = Arc::new(EvalContext::default());
+        let descs = vec![true, false];
         let mut topn_heap = TopNHeap::new(10).unwrap();
         let test_data = vec![
             (
@@ -444,17 +320,11 @@ mod tests {
             let cur_key: Vec<Datum> = vec![name, count];
             let row_data = RowColsDict::new(HashMap::default(), data.into_bytes());
             topn_heap
-                .try_add_row(
-                    i64::from(handle),
-                    row_data,
-                    cur_key,
-                    Arc::clone(&order_cols),
-                    Arc::clone(&ctx),
-                )
+                .try_add_row(i64::from(handle), row_data, cur_key, &descs)
                 .unwrap();
         }
 
-        let result = topn_heap.into_sorted_vec().unwrap();
+        let result = topn_heap.into_sorted_vec();
         assert_eq!(result.len(), exp.len());
         for (row, (handle, _, name, count)) in result.iter().zip(exp) {
             let exp_keys: Vec<Datum> = vec![name, count];
@@ -476,17 +346,10 @@ mod tests {
         let mut topn_heap = TopNHeap::new(0).unwrap();
         let cur_key: Vec<Datum> = vec![Datum::I64(1), Datum::I64(2)];
         let row_data = RowColsDict::new(HashMap::default(), b"ssss".to_vec());
-        let ctx = Arc::new(EvalContext::default());
         topn_heap
-            .try_add_row(
-                i64::from(1),
-                row_data,
-                cur_key,
-                Arc::new(Vec::default()),
-                ctx,
-            )
+            .try_add_row(i64::from(1), row_data, cur_key, &[false, false])
             .unwrap();
 
-        assert!(topn_heap.into_sorted_vec().unwrap().is_empty());
+        assert!(topn_heap.into_sorted_vec().is_empty());
     }
 }
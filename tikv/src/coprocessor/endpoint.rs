@@ -11,11 +11,14 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::mem;
 use std::usize;
 use std::time::Duration;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::fmt::{self, Debug, Display, Formatter};
 
+use byteorder::{ByteOrder, LittleEndian};
+use crc::crc32;
 use tipb::select::{self, DAGRequest};
 use tipb::analyze::{AnalyzeReq, AnalyzeType};
 use tipb::executor::ExecType;
@@ -25,19 +28,23 @@ use kvproto::coprocessor::{KeyRange, Request, Response};
 use kvproto::errorpb::{self, ServerIsBusy};
 use kvproto::kvrpcpb::{CommandPri, ExecDetails, HandleTime, IsolationLevel};
 
+use util::error_code::ErrorCodeExt;
+use util::admission;
+use util::slow_log::{self, Subsystem};
 use util::time::{duration_to_sec, Instant};
 use util::worker::{FutureScheduler, Runnable, Scheduler};
 use util::collections::HashMap;
 use util::threadpool::{Context, ContextFactory, ThreadPool, ThreadPoolBuilder};
 use server::{Config, OnResponse};
-use storage::{self, engine, Engine, Snapshot};
+use storage::{self, engine, AccessChecker, Engine, Snapshot};
 use storage::engine::Error as EngineError;
+use storage::engine::PerfStatistics;
 use pd::PdTask;
 
 use super::codec::mysql;
 use super::codec::datum::Datum;
 use super::dag::DAGContext;
-use super::statistics::analyze::AnalyzeContext;
+use super::statistics::analyze::{AnalyzeCaps, AnalyzeContext};
 use super::metrics::*;
 use super::local_metrics::{BasicLocalMetrics, ExecLocalMetrics};
 use super::dag::executor::ExecutorMetrics;
@@ -49,9 +56,6 @@ pub const REQ_TYPE_ANALYZE: i64 = 104;
 // If a request has been handled for more than 60 seconds, the client should
 // be timeout already, so it can be safely aborted.
 pub const DEFAULT_REQUEST_MAX_HANDLE_SECS: u64 = 60;
-// If handle time is larger than the lower bound, the query is considered as slow query.
-const SLOW_QUERY_LOWER_BOUND: f64 = 1.0; // 1 second.
-
 const DEFAULT_ERROR_CODE: i32 = 1;
 
 pub const SINGLE_GROUP: &[u8] = b"SingleGroup";
@@ -70,7 +74,13 @@ pub struct Host {
     high_priority_pool: ThreadPool<CopContext>,
     max_running_task_count: usize,
     batch_row_limit: usize,
+    scan_seek_bound: usize,
     request_max_handle_secs: u64,
+    analyze_caps: AnalyzeCaps,
+    perf_context: bool,
+    running: RunningRegistry,
+    // authorization hook shared with the storage entry points, if set.
+    access_checker: Option<Arc<AccessChecker>>,
 }
 
 struct CopContextFactory {
@@ -118,6 +128,7 @@ impl Host {
             last_req_id: 0,
             max_running_task_count: cfg.end_point_max_tasks,
             batch_row_limit: cfg.end_point_batch_row_limit,
+            scan_seek_bound: cfg.end_point_scan_seek_bound,
             pool: ThreadPoolBuilder::new(
                 thd_name!("endpoint-normal-pool"),
                 CopContextFactory { sender: r.clone() },
@@ -137,7 +148,46 @@ impl Host {
                 .stack_size(cfg.end_point_stack_size.0 as usize)
                 .build(),
             request_max_handle_secs: cfg.end_point_request_max_handle_duration.as_secs(),
+            perf_context: cfg.end_point_perf_context,
+            analyze_caps: AnalyzeCaps {
+                max_bucket_size: cfg.end_point_analyze_max_bucket_size,
+                max_sample_size: cfg.end_point_analyze_max_sample_size,
+                max_sketch_size: cfg.end_point_analyze_max_sketch_size,
+                max_cm_sketch_depth: cfg.end_point_analyze_max_cmsketch_depth,
+                max_cm_sketch_width: cfg.end_point_analyze_max_cmsketch_width,
+            },
+            running: RunningRegistry::new(cfg.end_point_request_max_handle_duration.0),
+            access_checker: None,
+        }
+    }
+
+    /// Installs the authorization hook consulted before requests run,
+    /// usually the one installed on `Storage`. Must be called before
+    /// the host is started.
+    pub fn set_access_checker(&mut self, checker: Arc<AccessChecker>) {
+        self.access_checker = Some(checker);
+    }
+
+    // Consults the authorization hook, if any, with the request context
+    // and the span of its key ranges.
+    fn check_access(&self, req: &RequestTask) -> Result<()> {
+        let checker = match self.access_checker {
+            Some(ref checker) => checker,
+            None => return Ok(()),
+        };
+        let mut start: &[u8] = b"";
+        let mut end: &[u8] = b"";
+        for range in req.req.get_ranges() {
+            if start.is_empty() || range.get_start() < start {
+                start = range.get_start();
+            }
+            if range.get_end() > end {
+                end = range.get_end();
+            }
         }
+        checker
+            .check_access(req.req.get_context(), start, end, false)
+            .map_err(|msg| box_err!("access denied: {}", msg).into())
     }
 
     fn running_task_count(&self) -> usize {
@@ -145,12 +195,37 @@ impl Host {
             + self.high_priority_pool.get_task_count()
     }
 
-    fn handle_snapshot_result(&mut self, id: u64, snapshot: engine::Result<Box<Snapshot>>) {
+    fn handle_snapshot_result(
+        &mut self,
+        id: u64,
+        snapshot: engine::Result<Box<Snapshot>>,
+        may_serve_on_replica: bool,
+    ) {
         let reqs = self.reqs.remove(&id).unwrap();
         let mut local_metrics = BasicLocalMetrics::default();
         let snap = match snapshot {
             Ok(s) => s,
             Err(e) => {
+                // A low priority scan addressed at a follower is retried
+                // on that follower through raft read-index instead of
+                // being bounced to the leader, so backup and checksum
+                // style loads stay off the leader.
+                if may_serve_on_replica && is_not_leader(&e)
+                    && reqs.iter().all(|r| r.priority() == CommandPri::Low)
+                {
+                    let sched = self.sched.clone();
+                    let res = self.engine.async_snapshot_min_index(
+                        reqs[0].req.get_context(),
+                        0,
+                        box move |(_, res)| {
+                            sched.schedule(Task::ReplicaSnapRes(id, res)).unwrap()
+                        },
+                    );
+                    if res.is_ok() {
+                        self.reqs.insert(id, reqs);
+                        return;
+                    }
+                }
                 notify_batch_failed(e, reqs, &mut local_metrics, self.request_max_handle_secs);
                 return;
             }
@@ -167,7 +242,15 @@ impl Host {
         }
 
         let batch_row_limit = self.batch_row_limit;
+        let scan_seek_bound = self.scan_seek_bound;
+        let reqs = coalesce_duplicates(reqs);
         for req in reqs {
+            let req = match self.running.intercept(req) {
+                Some(req) => req,
+                // An identical request is already running, its response
+                // will answer this one too.
+                None => continue,
+            };
             let pri = req.priority();
             let pri_str = get_req_pri_str(pri);
             let type_str = req.ctx.get_scan_tag();
@@ -182,6 +265,8 @@ impl Host {
                 .with_label_values(&[type_str, pri_str])
                 .inc();
             let request_max_handle_secs = self.request_max_handle_secs;
+            let analyze_caps = self.analyze_caps.clone();
+            let perf_context = self.perf_context;
             pool.execute(move |ctx: &mut CopContext| {
                 // decrease pending task
                 COPR_PENDING_REQS
@@ -191,8 +276,11 @@ impl Host {
                 let stats = end_point.handle_request(
                     req,
                     batch_row_limit,
+                    scan_seek_bound,
                     &mut ctx.basic_local_metrics,
                     request_max_handle_secs,
+                    analyze_caps,
+                    perf_context,
                 );
                 ctx.exec_local_metrics.collect(type_str, region_id, stats);
             });
@@ -203,6 +291,7 @@ impl Host {
 pub enum Task {
     Request(RequestTask),
     SnapRes(u64, engine::Result<Box<Snapshot>>),
+    ReplicaSnapRes(u64, engine::Result<Box<Snapshot>>),
     BatchSnapRes(Vec<(u64, engine::Result<Box<Snapshot>>)>),
     RetryRequests(Vec<u64>),
 }
@@ -212,6 +301,7 @@ impl Display for Task {
         match *self {
             Task::Request(ref req) => write!(f, "{}", req),
             Task::SnapRes(req_id, _) => write!(f, "snapres [{}]", req_id),
+            Task::ReplicaSnapRes(req_id, _) => write!(f, "replica snapres [{}]", req_id),
             Task::BatchSnapRes(_) => write!(f, "batch snapres"),
             Task::RetryRequests(ref retry) => write!(f, "retry on task ids: {:?}", retry),
         }
@@ -257,6 +347,8 @@ pub struct RequestTask {
     wait_time: Option<f64>,
     timer: Instant,
     metrics: ExecutorMetrics,
+    // perf context delta of the handled request, if collection is on.
+    perf_statistics: Option<PerfStatistics>,
     on_resp: OnResponse,
     cop_req: Option<Result<CopRequest>>,
     ctx: Arc<ReqContext>,
@@ -320,6 +412,7 @@ impl RequestTask {
             wait_time: None,
             timer: timer,
             metrics: Default::default(),
+            perf_statistics: None,
             on_resp: on_resp,
             cop_req: Some(cop_req),
             ctx: Arc::new(req_ctx),
@@ -340,6 +433,7 @@ impl RequestTask {
             .wait_time
             .with_label_values(&[self.ctx.get_scan_tag()])
             .observe(wait_time);
+        admission::report_read_wait_ms((wait_time * 1000.0) as usize);
         self.wait_time = Some(wait_time);
     }
 
@@ -368,10 +462,11 @@ impl RequestTask {
         handle.set_wait_ms((wait_time * 1000.0) as i64);
 
         let mut exec_details = ExecDetails::new();
-        if handle_time > SLOW_QUERY_LOWER_BOUND {
+        let slow_threshold = duration_to_sec(slow_log::threshold(Subsystem::Coprocessor));
+        if handle_time > slow_threshold {
             info!(
                 "[region {}] handle {:?} [{}] takes {:?} [keys: {}, hit: {}, \
-                 ranges: {} ({:?})]",
+                 ranges: {} ({:?}), perf: {:?}]",
                 self.req.get_context().get_region_id(),
                 self.start_ts,
                 type_str,
@@ -379,7 +474,20 @@ impl RequestTask {
                 self.metrics.cf_stats.total_op_count(),
                 self.metrics.cf_stats.total_processed(),
                 self.req.get_ranges().len(),
-                self.req.get_ranges().get(0)
+                self.req.get_ranges().get(0),
+                self.perf_statistics
+            );
+            slow_log::record(
+                Subsystem::Coprocessor,
+                Duration::from_millis((handle_time * 1000.0) as u64),
+                format!(
+                    "[region {}] handle {:?} [{}] [keys: {}, ranges: {}]",
+                    self.req.get_context().get_region_id(),
+                    self.start_ts,
+                    type_str,
+                    self.metrics.cf_stats.total_op_count(),
+                    self.req.get_ranges().len()
+                ),
             );
             exec_details.set_scan_detail(self.metrics.cf_stats.scan_detail());
             exec_details.set_handle_time(handle);
@@ -404,6 +512,42 @@ impl RequestTask {
     pub fn priority(&self) -> CommandPri {
         self.req.get_context().get_priority()
     }
+
+    /// The bytes of everything that determines the response except the data
+    /// snapshot. Two requests with equal keys that are handled on the same
+    /// snapshot (which fixes the region epoch and the applied index they
+    /// observe) produce identical responses. Every variable length field is
+    /// length-prefixed so distinct requests cannot frame to the same bytes.
+    fn dedup_key(&self) -> Vec<u8> {
+        let mut key = Vec::with_capacity(self.req.get_data().len() + 64);
+        let mut buf = [0; 8];
+        LittleEndian::write_i64(&mut buf, self.req.get_tp());
+        key.extend_from_slice(&buf);
+        LittleEndian::write_u64(&mut buf, self.req.get_data().len() as u64);
+        key.extend_from_slice(&buf);
+        key.extend_from_slice(self.req.get_data());
+        for range in self.req.get_ranges() {
+            LittleEndian::write_u64(&mut buf, range.get_start().len() as u64);
+            key.extend_from_slice(&buf);
+            key.extend_from_slice(range.get_start());
+            LittleEndian::write_u64(&mut buf, range.get_end().len() as u64);
+            key.extend_from_slice(&buf);
+            key.extend_from_slice(range.get_end());
+        }
+        let ctx = self.req.get_context();
+        key.push(ctx.get_isolation_level() as u8);
+        key.push(ctx.get_not_fill_cache() as u8);
+        key.push(ctx.get_priority() as u8);
+        key
+    }
+}
+
+/// A digest of a dedup key, used as a cheap pre-filter when looking for
+/// identical requests. Equal requests always collide on the digest, but a
+/// digest match alone proves nothing: CRC32 is not collision resistant, a
+/// match must be confirmed against the full key bytes.
+fn dedup_digest(key: &[u8]) -> u32 {
+    crc32::checksum_ieee(key)
 }
 
 impl Display for RequestTask {
@@ -436,6 +580,19 @@ impl Runnable<Task> for Host {
                         on_error(e, req, &mut local_metrics, self.request_max_handle_secs);
                         continue;
                     }
+                    // Under sustained overload new low priority scans
+                    // are shed right away, they are the first to miss
+                    // their deadline anyway.
+                    if req.priority() == CommandPri::Low && admission::is_overloaded() {
+                        admission::on_shed("coprocessor");
+                        let e = Error::Full(self.max_running_task_count);
+                        on_error(e, req, &mut local_metrics, self.request_max_handle_secs);
+                        continue;
+                    }
+                    if let Err(e) = self.check_access(&req) {
+                        on_error(e, req, &mut local_metrics, self.request_max_handle_secs);
+                        continue;
+                    }
                     let key = {
                         let ctx = req.req.get_context();
                         (
@@ -448,10 +605,13 @@ impl Runnable<Task> for Host {
                     group.push(req);
                 }
                 Task::SnapRes(q_id, snap_res) => {
-                    self.handle_snapshot_result(q_id, snap_res);
+                    self.handle_snapshot_result(q_id, snap_res, true);
+                }
+                Task::ReplicaSnapRes(q_id, snap_res) => {
+                    self.handle_snapshot_result(q_id, snap_res, false);
                 }
                 Task::BatchSnapRes(batch) => for (q_id, snap_res) in batch {
-                    self.handle_snapshot_result(q_id, snap_res);
+                    self.handle_snapshot_result(q_id, snap_res, true);
                 },
                 Task::RetryRequests(retry) => for id in retry {
                     let reqs = self.reqs.remove(&id).unwrap();
@@ -574,7 +734,7 @@ fn err_resp(e: Error, metrics: &mut BasicLocalMetrics, request_max_handle_secs:
             "full"
         }
         Error::Other(_) => {
-            resp.set_other_error(format!("{}", e));
+            resp.set_other_error(format!("[{}] {}", e.error_code(), e));
             "other"
         }
     };
@@ -609,6 +769,174 @@ fn notify_batch_failed<E: Into<Error> + Debug>(
     }
 }
 
+/// Whether the error is a NotLeader raftstore error.
+fn is_not_leader(e: &EngineError) -> bool {
+    match *e {
+        EngineError::Request(ref header) => header.has_not_leader(),
+        _ => false,
+    }
+}
+
+/// Merges requests that would produce the same response so that only one of
+/// them is executed. All requests here are about to be handled on the same
+/// snapshot, so coalescing is safe whenever the digests match.
+fn coalesce_duplicates(reqs: Vec<RequestTask>) -> Vec<RequestTask> {
+    if reqs.len() <= 1 {
+        return reqs;
+    }
+    let mut out: Vec<RequestTask> = Vec::with_capacity(reqs.len());
+    let mut keys: Vec<Vec<u8>> = Vec::with_capacity(reqs.len());
+    // digest -> positions in `out` with that digest. The digest is only a
+    // pre-filter, a match is confirmed against the full key bytes before
+    // two requests are merged.
+    let mut index: HashMap<u32, Vec<usize>> = HashMap::default();
+    for req in reqs {
+        let key = req.dedup_key();
+        let candidates = index.entry(dedup_digest(&key)).or_insert_with(Vec::new);
+        let found = candidates.iter().find(|&&pos| keys[pos] == key).cloned();
+        if let Some(pos) = found {
+            COPR_COALESCED_REQS
+                .with_label_values(&[req.ctx.get_scan_tag()])
+                .inc();
+            let primary = &mut out[pos];
+            let prev = mem::replace(&mut primary.on_resp, box |_| {});
+            let dup = req.on_resp;
+            primary.on_resp = box move |resp: Response| {
+                dup(resp.clone());
+                prev(resp);
+            };
+        } else {
+            candidates.push(out.len());
+            keys.push(key);
+            out.push(req);
+        }
+    }
+    out
+}
+
+/// A request that is currently being handled, together with the
+/// callbacks of retried duplicates waiting for its response.
+struct RunningEntry {
+    id: u64,
+    // The determining bytes of the request. A token match alone is not
+    // proof of identity, only requests with the very same key may be
+    // parked behind this entry.
+    key: Vec<u8>,
+    since: Instant,
+    waiters: Vec<OnResponse>,
+}
+
+struct RegistryInner {
+    // token -> entries whose keys digest to it
+    entries: HashMap<u32, Vec<RunningEntry>>,
+    next_id: u64,
+}
+
+/// Tracks the requests that are currently being handled so a retry of
+/// an expensive request (sent after a client timeout) can wait for the
+/// running execution instead of launching a second one.
+///
+/// `kvproto`'s coprocessor `Request` carries no explicit idempotency
+/// token yet, so the request bytes double as the token: a retried
+/// request reproduces the original request bytes exactly. The digest of
+/// the bytes keys the lookup, the bytes themselves decide identity.
+#[derive(Clone)]
+struct RunningRegistry {
+    inner: Arc<Mutex<RegistryInner>>,
+    ttl: Duration,
+}
+
+impl RunningRegistry {
+    fn new(ttl: Duration) -> RunningRegistry {
+        RunningRegistry {
+            inner: Arc::new(Mutex::new(RegistryInner {
+                entries: HashMap::default(),
+                next_id: 0,
+            })),
+            ttl: ttl,
+        }
+    }
+
+    /// Registers the request as the running one for its token, wrapping
+    /// its callback so duplicates are notified when it responds. When an
+    /// identical request is already running the callback is parked
+    /// behind it instead and `None` is returned.
+    fn intercept(&self, mut req: RequestTask) -> Option<RequestTask> {
+        let key = req.dedup_key();
+        let token = dedup_digest(&key);
+        let ttl = self.ttl;
+        let id = {
+            let mut inner = self.inner.lock().unwrap();
+            inner.next_id += 1;
+            let id = inner.next_id;
+            let entries = inner.entries.entry(token).or_insert_with(Vec::new);
+            // The token is only a pre-filter, the running request must
+            // carry the very same key bytes before the retry is parked
+            // behind it.
+            let pos = entries.iter().position(|e| e.key == key);
+            match pos {
+                Some(pos) => {
+                    let entry = &mut entries[pos];
+                    if entry.since.elapsed() < ttl {
+                        COPR_IDEMPOTENT_RETRY
+                            .with_label_values(&[req.ctx.get_scan_tag()])
+                            .inc();
+                        entry.waiters.push(req.on_resp);
+                        return None;
+                    }
+                    // The entry has outlived the request deadline, its
+                    // owner will never respond. Take it over so its
+                    // waiters still get an answer from the new execution.
+                    entry.id = id;
+                    entry.since = Instant::now_coarse();
+                }
+                None => {
+                    entries.push(RunningEntry {
+                        id: id,
+                        key: key,
+                        since: Instant::now_coarse(),
+                        waiters: Vec::new(),
+                    });
+                }
+            }
+            id
+        };
+        let registry = self.clone();
+        let on_resp = mem::replace(&mut req.on_resp, box |_| {});
+        req.on_resp = box move |resp: Response| {
+            registry.finish(token, id, &resp);
+            on_resp(resp);
+        };
+        Some(req)
+    }
+
+    /// Removes the finished request from the registry and answers all
+    /// duplicates parked behind it.
+    fn finish(&self, token: u32, id: u64, resp: &Response) {
+        let waiters = {
+            let mut inner = self.inner.lock().unwrap();
+            let (waiters, emptied) = {
+                let entries = match inner.entries.get_mut(&token) {
+                    Some(entries) => entries,
+                    None => return,
+                };
+                let pos = match entries.iter().position(|e| e.id == id) {
+                    Some(pos) => pos,
+                    None => return,
+                };
+                (entries.swap_remove(pos).waiters, entries.is_empty())
+            };
+            if emptied {
+                inner.entries.remove(&token);
+            }
+            waiters
+        };
+        for waiter in waiters {
+            waiter(resp.clone());
+        }
+    }
+}
+
 fn respond(
     mut resp: Response,
     mut t: RequestTask,
@@ -636,8 +964,11 @@ impl TiDbEndPoint {
         self,
         mut t: RequestTask,
         batch_row_limit: usize,
+        scan_seek_bound: usize,
         metrics: &mut BasicLocalMetrics,
         request_max_handle_secs: u64,
+        analyze_caps: AnalyzeCaps,
+        perf_context: bool,
     ) -> ExecutorMetrics {
         t.stop_record_waiting(metrics);
 
@@ -645,11 +976,23 @@ impl TiDbEndPoint {
             return on_error(e, t, metrics, request_max_handle_secs);
         }
 
+        let perf = if perf_context {
+            Some(PerfStatistics::start())
+        } else {
+            None
+        };
         let resp = match t.cop_req.take().unwrap() {
-            Ok(CopRequest::DAG(dag)) => self.handle_dag(dag, &mut t, batch_row_limit),
-            Ok(CopRequest::Analyze(analyze)) => self.handle_analyze(analyze, &mut t),
+            Ok(CopRequest::DAG(dag)) => {
+                self.handle_dag(dag, &mut t, batch_row_limit, scan_seek_bound)
+            }
+            Ok(CopRequest::Analyze(analyze)) => self.handle_analyze(analyze, &mut t, analyze_caps),
             Err(err) => Err(err),
         };
+        if let Some(perf) = perf {
+            let delta = perf.delta();
+            delta.report_to(&COPR_ROCKSDB_PERF_COUNTER, t.ctx.get_scan_tag());
+            t.perf_statistics = Some(delta);
+        }
         match resp {
             Ok(r) => respond(r, t, metrics),
             Err(e) => on_error(e, t, metrics, request_max_handle_secs),
@@ -661,17 +1004,30 @@ impl TiDbEndPoint {
         dag: DAGRequest,
         t: &mut RequestTask,
         batch_row_limit: usize,
+        scan_seek_bound: usize,
     ) -> Result<Response> {
         let ranges = t.req.take_ranges().into_vec();
-        let mut ctx = DAGContext::new(dag, ranges, self.snap, Arc::clone(&t.ctx), batch_row_limit)?;
+        let mut ctx = DAGContext::new(
+            dag,
+            ranges,
+            self.snap,
+            Arc::clone(&t.ctx),
+            batch_row_limit,
+            scan_seek_bound,
+        )?;
         let res = ctx.handle_request();
         ctx.collect_metrics_into(&mut t.metrics);
         res
     }
 
-    pub fn handle_analyze(self, analyze: AnalyzeReq, t: &mut RequestTask) -> Result<Response> {
+    pub fn handle_analyze(
+        self,
+        analyze: AnalyzeReq,
+        t: &mut RequestTask,
+        caps: AnalyzeCaps,
+    ) -> Result<Response> {
         let ranges = t.req.take_ranges().into_vec();
-        let ctx = AnalyzeContext::new(analyze, ranges, self.snap, t.ctx.as_ref());
+        let ctx = AnalyzeContext::new(analyze, ranges, self.snap, t.ctx.as_ref(), caps);
         ctx.handle_request(&mut t.metrics)
     }
 }
@@ -745,12 +1101,13 @@ mod tests {
     use std::time::Duration;
     use std::ops::Sub;
 
-    use kvproto::coprocessor::Request;
+    use kvproto::coprocessor::{Request, Response};
     use tipb::select::DAGRequest;
     use tipb::expression::Expr;
     use tipb::executor::Executor;
 
     use util::worker::{Builder as WorkerBuilder, FutureWorker};
+    use util::slow_log::{self, Subsystem};
     use util::time::Instant;
 
     #[test]
@@ -827,9 +1184,8 @@ mod tests {
         task.ctx = Arc::new(ctx);
         let mut metrics = BasicLocalMetrics::default();
         task.stop_record_waiting(&mut metrics);
-        task.timer = task.timer.sub(Duration::from_secs(
-            (super::SLOW_QUERY_LOWER_BOUND * 2.0) as u64,
-        ));
+        task.timer = task.timer
+            .sub(slow_log::threshold(Subsystem::Coprocessor) * 2);
         worker.schedule(Task::Request(task)).unwrap();
         let resp = rx.recv_timeout(Duration::from_secs(3)).unwrap();
 
@@ -884,6 +1240,83 @@ mod tests {
         panic!("suppose to get ServerIsBusy error.");
     }
 
+    #[test]
+    fn test_coalesce_duplicates() {
+        let (tx, rx) = mpsc::channel();
+        let gen_task = |data: Vec<u8>| {
+            let tx = tx.clone();
+            let mut req = Request::new();
+            req.set_tp(REQ_TYPE_DAG);
+            req.set_data(data);
+            RequestTask::new(
+                req,
+                box move |msg| {
+                    tx.send(msg).unwrap();
+                },
+                1000,
+                super::DEFAULT_REQUEST_MAX_HANDLE_SECS,
+            )
+        };
+        let reqs = vec![gen_task(vec![]), gen_task(vec![]), gen_task(vec![1])];
+        let mut reqs = coalesce_duplicates(reqs);
+        assert_eq!(reqs.len(), 2);
+        // Responding to the merged task notifies both original requests.
+        let task = reqs.remove(0);
+        (task.on_resp)(Response::new());
+        rx.recv_timeout(Duration::from_secs(1)).unwrap();
+        rx.recv_timeout(Duration::from_secs(1)).unwrap();
+        assert!(rx.recv_timeout(Duration::from_millis(100)).is_err());
+    }
+
+    #[test]
+    fn test_running_registry() {
+        let (tx, rx) = mpsc::channel();
+        let gen_task = |data: Vec<u8>| {
+            let tx = tx.clone();
+            let mut req = Request::new();
+            req.set_tp(REQ_TYPE_DAG);
+            req.set_data(data);
+            RequestTask::new(
+                req,
+                box move |msg| {
+                    tx.send(msg).unwrap();
+                },
+                1000,
+                super::DEFAULT_REQUEST_MAX_HANDLE_SECS,
+            )
+        };
+
+        let registry = RunningRegistry::new(Duration::from_secs(60));
+        let primary = registry.intercept(gen_task(vec![])).unwrap();
+        // A retried duplicate is parked behind the running request.
+        assert!(registry.intercept(gen_task(vec![])).is_none());
+        // A different request runs on its own.
+        let other = registry.intercept(gen_task(vec![1])).unwrap();
+
+        // Responding to the primary also answers the parked retry.
+        (primary.on_resp)(Response::new());
+        rx.recv_timeout(Duration::from_secs(1)).unwrap();
+        rx.recv_timeout(Duration::from_secs(1)).unwrap();
+        assert!(rx.recv_timeout(Duration::from_millis(100)).is_err());
+
+        // Once the primary responded its token is free again.
+        let again = registry.intercept(gen_task(vec![])).unwrap();
+        (again.on_resp)(Response::new());
+        (other.on_resp)(Response::new());
+        rx.recv_timeout(Duration::from_secs(1)).unwrap();
+        rx.recv_timeout(Duration::from_secs(1)).unwrap();
+
+        // An entry that outlived the deadline is replaced instead of
+        // parking retries forever.
+        let registry = RunningRegistry::new(Duration::from_secs(0));
+        let stale = registry.intercept(gen_task(vec![])).unwrap();
+        let fresh = registry.intercept(gen_task(vec![])).unwrap();
+        (fresh.on_resp)(Response::new());
+        (stale.on_resp)(Response::new());
+        rx.recv_timeout(Duration::from_secs(1)).unwrap();
+        rx.recv_timeout(Duration::from_secs(1)).unwrap();
+    }
+
     #[test]
     fn test_stack_guard() {
         let mut expr = Expr::new();
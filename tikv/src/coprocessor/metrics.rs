@@ -13,13 +13,15 @@
 
 use prometheus::*;
 
+use util::metrics::latency_buckets;
+
 lazy_static! {
     pub static ref COPR_REQ_HISTOGRAM_VEC: HistogramVec =
         register_histogram_vec!(
             "tikv_coprocessor_request_duration_seconds",
             "Bucketed histogram of coprocessor request duration",
             &["req"],
-            exponential_buckets(0.0005, 2.0, 20).unwrap()
+            latency_buckets()
         ).unwrap();
 
     pub static ref OUTDATED_REQ_WAIT_TIME: HistogramVec =
@@ -27,7 +29,7 @@ lazy_static! {
             "tikv_coprocessor_outdated_request_wait_seconds",
             "Bucketed histogram of outdated coprocessor request wait duration",
             &["req"],
-            exponential_buckets(0.0005, 2.0, 20).unwrap()
+            latency_buckets()
         ).unwrap();
 
     pub static ref COPR_REQ_HANDLE_TIME: HistogramVec =
@@ -35,7 +37,7 @@ lazy_static! {
             "tikv_coprocessor_request_handle_seconds",
             "Bucketed histogram of coprocessor handle request duration",
             &["req"],
-            exponential_buckets(0.0005, 2.0, 20).unwrap()
+            latency_buckets()
         ).unwrap();
 
     pub static ref COPR_REQ_WAIT_TIME: HistogramVec =
@@ -43,7 +45,7 @@ lazy_static! {
             "tikv_coprocessor_request_wait_seconds",
             "Bucketed histogram of coprocessor request wait duration",
             &["req"],
-            exponential_buckets(0.0005, 2.0, 20).unwrap()
+            latency_buckets()
         ).unwrap();
 
     pub static ref COPR_REQ_ERROR: CounterVec =
@@ -53,6 +55,20 @@ lazy_static! {
             &["reason"]
         ).unwrap();
 
+    pub static ref COPR_COALESCED_REQS: CounterVec =
+        register_counter_vec!(
+            "tikv_coprocessor_coalesced_request",
+            "Total number of push down requests coalesced onto an identical one.",
+            &["req"]
+        ).unwrap();
+
+    pub static ref COPR_IDEMPOTENT_RETRY: CounterVec =
+        register_counter_vec!(
+            "tikv_coprocessor_idempotent_retry_total",
+            "Total number of retried requests answered by an already running execution.",
+            &["req"]
+        ).unwrap();
+
     pub static ref COPR_PENDING_REQS: GaugeVec =
         register_gauge_vec!(
             "tikv_coprocessor_pending_request",
@@ -75,6 +91,13 @@ lazy_static! {
              &["req", "cf", "tag"]
          ).unwrap();
 
+    pub static ref COPR_ROCKSDB_PERF_COUNTER: CounterVec =
+        register_counter_vec!(
+            "tikv_coprocessor_rocksdb_perf",
+            "Total RocksDB perf context counters of coprocessor requests",
+            &["req", "metric"]
+        ).unwrap();
+
     pub static ref COPR_EXECUTOR_COUNT: CounterVec =
         register_counter_vec!(
             "tikv_coprocessor_executor_count",
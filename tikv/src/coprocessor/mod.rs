@@ -25,6 +25,7 @@ use kvproto::kvrpcpb::LockInfo;
 use kvproto::errorpb;
 
 use storage::{engine, mvcc, txn};
+use util::error_code::{self, ErrorCode, ErrorCodeExt};
 use util::time::Instant;
 
 quick_error! {
@@ -85,5 +86,17 @@ impl From<txn::Error> for Error {
     }
 }
 
+impl ErrorCodeExt for Error {
+    fn error_code(&self) -> ErrorCode {
+        match *self {
+            Error::Region(_) => error_code::coprocessor::REGION,
+            Error::Locked(_) => error_code::coprocessor::LOCKED,
+            Error::Outdated(..) => error_code::coprocessor::OUTDATED,
+            Error::Full(_) => error_code::coprocessor::FULL,
+            Error::Other(_) => error_code::coprocessor::UNKNOWN,
+        }
+    }
+}
+
 pub use self::endpoint::{Host as EndPointHost, RequestTask, Task as EndPointTask,
                          DEFAULT_REQUEST_MAX_HANDLE_SECS, REQ_TYPE_DAG, SINGLE_GROUP};
@@ -11,6 +11,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::cmp;
 use std::mem;
 
 use rand::{thread_rng, Rng, ThreadRng};
@@ -29,11 +30,24 @@ use super::fmsketch::FMSketch;
 use super::cmsketch::CMSketch;
 use super::histogram::Histogram;
 
+/// Server-side caps on the sampling parameters carried by `AnalyzeReq`.
+/// The sizes are picked by the client, capping them here keeps a mistuned
+/// ANALYZE from collecting unbounded samples or sketches.
+#[derive(Clone)]
+pub struct AnalyzeCaps {
+    pub max_bucket_size: usize,
+    pub max_sample_size: usize,
+    pub max_sketch_size: usize,
+    pub max_cm_sketch_depth: usize,
+    pub max_cm_sketch_width: usize,
+}
+
 // `AnalyzeContext` is used to handle `AnalyzeReq`
 pub struct AnalyzeContext {
     req: AnalyzeReq,
     snap: Option<SnapshotStore>,
     ranges: Vec<KeyRange>,
+    caps: AnalyzeCaps,
 }
 
 impl AnalyzeContext {
@@ -42,6 +56,7 @@ impl AnalyzeContext {
         ranges: Vec<KeyRange>,
         snap: Box<Snapshot>,
         req_ctx: &ReqContext,
+        caps: AnalyzeCaps,
     ) -> AnalyzeContext {
         let snap = SnapshotStore::new(
             snap,
@@ -53,6 +68,7 @@ impl AnalyzeContext {
             req: req,
             snap: Some(snap),
             ranges: ranges,
+            caps: caps,
         }
     }
 
@@ -65,7 +81,7 @@ impl AnalyzeContext {
                     mem::replace(&mut self.ranges, Vec::new()),
                     self.snap.take().unwrap(),
                 )?;
-                let res = AnalyzeContext::handle_index(req, &mut scanner);
+                let res = AnalyzeContext::handle_index(req, &mut scanner, &self.caps);
                 scanner.collect_metrics_into(stats);
                 res
             }
@@ -74,7 +90,7 @@ impl AnalyzeContext {
                 let col_req = self.req.take_col_req();
                 let snap = self.snap.take().unwrap();
                 let ranges = mem::replace(&mut self.ranges, Vec::new());
-                let mut builder = SampleBuilder::new(col_req, snap, ranges)?;
+                let mut builder = SampleBuilder::new(col_req, snap, ranges, &self.caps)?;
                 let res = AnalyzeContext::handle_column(&mut builder);
                 builder.data.collect_metrics_into(stats);
                 res
@@ -116,11 +132,18 @@ impl AnalyzeContext {
 
     // handle_index is used to handle `AnalyzeIndexReq`,
     // it would build a histogram and count-min sketch of index values.
-    fn handle_index(req: AnalyzeIndexReq, scanner: &mut IndexScanExecutor) -> Result<Vec<u8>> {
-        let mut hist = Histogram::new(req.get_bucket_size() as usize);
+    fn handle_index(
+        req: AnalyzeIndexReq,
+        scanner: &mut IndexScanExecutor,
+        caps: &AnalyzeCaps,
+    ) -> Result<Vec<u8>> {
+        let mut hist = Histogram::new(cmp::min(
+            req.get_bucket_size() as usize,
+            caps.max_bucket_size,
+        ));
         let mut cms = CMSketch::new(
-            req.get_cmsketch_depth() as usize,
-            req.get_cmsketch_width() as usize,
+            cmp::min(req.get_cmsketch_depth() as usize, caps.max_cm_sketch_depth),
+            cmp::min(req.get_cmsketch_width() as usize, caps.max_cm_sketch_width),
         );
         while let Some(row) = scanner.next()? {
             let bytes = row.data.get_column_values();
@@ -160,6 +183,7 @@ impl SampleBuilder {
         mut req: AnalyzeColumnsReq,
         snap: SnapshotStore,
         ranges: Vec<KeyRange>,
+        caps: &AnalyzeCaps,
     ) -> Result<SampleBuilder> {
         let cols_info = req.take_columns_info();
         if cols_info.is_empty() {
@@ -178,11 +202,11 @@ impl SampleBuilder {
             data: table_scanner,
             cols: meta.take_columns().to_vec(),
             col_len: col_len,
-            max_bucket_size: req.get_bucket_size() as usize,
-            max_fm_sketch_size: req.get_sketch_size() as usize,
-            max_sample_size: req.get_sample_size() as usize,
-            cm_sketch_depth: req.get_cmsketch_depth() as usize,
-            cm_sketch_width: req.get_cmsketch_width() as usize,
+            max_bucket_size: cmp::min(req.get_bucket_size() as usize, caps.max_bucket_size),
+            max_fm_sketch_size: cmp::min(req.get_sketch_size() as usize, caps.max_sketch_size),
+            max_sample_size: cmp::min(req.get_sample_size() as usize, caps.max_sample_size),
+            cm_sketch_depth: cmp::min(req.get_cmsketch_depth() as usize, caps.max_cm_sketch_depth),
+            cm_sketch_width: cmp::min(req.get_cmsketch_width() as usize, caps.max_cm_sketch_width),
         })
     }
 
@@ -14,12 +14,19 @@
 use std::error::Error;
 use std::result::Result;
 
+use util::config::{ReadableDuration, ReadableSize};
+
 #[derive(Clone, Serialize, Deserialize, PartialEq, Debug)]
 #[serde(default)]
 #[serde(rename_all = "kebab-case")]
 pub struct Config {
     pub num_threads: usize,
     pub stream_channel_window: usize,
+    // SST files from aborted loads are garbage collected once they are
+    // older than this.
+    pub sst_ttl: ReadableDuration,
+    // Upload traffic beyond this rate is throttled, 0 means no limit.
+    pub speed_limit: ReadableSize,
 }
 
 impl Default for Config {
@@ -27,6 +34,8 @@ impl Default for Config {
         Config {
             num_threads: 8,
             stream_channel_window: 128,
+            sst_ttl: ReadableDuration::hours(12),
+            speed_limit: ReadableSize(0),
         }
     }
 }
@@ -39,6 +48,9 @@ impl Config {
         if self.stream_channel_window == 0 {
             return Err("import.stream_channel_window can not be 0".into());
         }
+        if self.sst_ttl.as_secs() == 0 {
+            return Err("import.sst_ttl can not be 0".into());
+        }
         Ok(())
     }
 }
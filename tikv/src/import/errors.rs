@@ -19,6 +19,9 @@ use futures::sync::oneshot::Canceled;
 use grpc::Error as GrpcError;
 use uuid::ParseError;
 
+use pd::Error as PdError;
+use raftstore::Error as RaftStoreError;
+use storage::Error as StorageError;
 use util::codec::Error as CodecError;
 
 quick_error! {
@@ -67,6 +70,27 @@ quick_error! {
         TokenNotFound(token: usize) {
             display("Token {} not found", token)
         }
+        Pd(err: PdError) {
+            from()
+            cause(err)
+            description(err.description())
+        }
+        RaftStore(err: RaftStoreError) {
+            from()
+            cause(err)
+            description(err.description())
+        }
+        SplitRegion(msg: String) {
+            display("Split region: {}", msg)
+        }
+        Storage(err: StorageError) {
+            from()
+            cause(err)
+            description(err.description())
+        }
+        Restore(msg: String) {
+            display("Restore: {}", msg)
+        }
     }
 }
 
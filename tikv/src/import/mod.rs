@@ -14,6 +14,8 @@
 mod config;
 mod errors;
 mod metrics;
+mod prepare;
+mod restore;
 #[macro_use]
 mod service;
 mod sst_service;
@@ -23,5 +25,6 @@ pub mod test_helpers;
 
 pub use self::config::Config;
 pub use self::errors::{Error, Result};
+pub use self::restore::{BackupMeta, RestoreJob};
 pub use self::sst_service::ImportSSTService;
 pub use self::sst_importer::SSTImporter;
@@ -0,0 +1,119 @@
+// Copyright 2018 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use futures::Future;
+use futures::sync::oneshot;
+use kvproto::importpb::SSTMeta;
+
+use pd::{PdClient, RegionInfo};
+use raftstore::store::{Callback, Msg as StoreMsg, WriteResponse};
+use server::transport::RaftStoreRouter;
+use storage::Key;
+
+use super::{Error, Result};
+
+// How long to wait for PD to observe a requested split before giving up.
+// PD learns about it from the next region heartbeat of the new leader.
+const SPLIT_WAIT_MAX_RETRY_TIMES: u64 = 20;
+const SPLIT_WAIT_INTERVAL_MILLIS: u64 = 50;
+
+/// Prepares the keyspace for an uploaded SST: splits the target regions at
+/// the SST range boundaries and asks PD to scatter the new regions across
+/// the cluster, so a single region doesn't absorb the whole dataset and
+/// then split under load during ingestion.
+pub struct PrepareJob<C, R> {
+    pd_client: Arc<C>,
+    router: R,
+}
+
+impl<C: PdClient, R: RaftStoreRouter> PrepareJob<C, R> {
+    pub fn new(pd_client: Arc<C>, router: R) -> PrepareJob<C, R> {
+        PrepareJob {
+            pd_client: pd_client,
+            router: router,
+        }
+    }
+
+    pub fn prepare(&self, meta: &SSTMeta) -> Result<()> {
+        let range = meta.get_range();
+        for key in &[range.get_start(), range.get_end()] {
+            if key.is_empty() {
+                continue;
+            }
+            // The SST range carries raw keys, region boundaries are encoded.
+            let split_key = Key::from_raw(key).encoded().clone();
+            self.split_and_scatter(split_key)?;
+        }
+        Ok(())
+    }
+
+    fn split_and_scatter(&self, split_key: Vec<u8>) -> Result<()> {
+        let region = self.pd_client.get_region_info(&split_key)?;
+        if region.get_start_key() == split_key.as_slice() {
+            // The key is already a region boundary.
+            return Ok(());
+        }
+
+        let (tx, rx) = oneshot::channel();
+        let msg = StoreMsg::SplitRegion {
+            region_id: region.get_id(),
+            region_epoch: region.get_region_epoch().clone(),
+            split_key: split_key.clone(),
+            callback: Callback::Write(box move |resp: WriteResponse| {
+                let _ = tx.send(resp);
+            }),
+        };
+        self.router.try_send(msg)?;
+
+        let mut resp = rx.wait()?.response;
+        if resp.get_header().has_error() {
+            return Err(Error::SplitRegion(format!(
+                "{:?}",
+                resp.get_header().get_error()
+            )));
+        }
+
+        // Scattering is best effort, an unbalanced ingest is still correct.
+        let mut split = resp.mut_admin_response().take_split();
+        for region in vec![split.take_left(), split.take_right()] {
+            let region_id = region.get_id();
+            if let Err(e) = self.pd_client.scatter_region(RegionInfo::new(region, None)) {
+                warn!("scatter region {}: {:?}", region_id, e);
+            }
+        }
+
+        self.wait_split(&split_key)
+    }
+
+    // Clients route ingest requests through PD, so don't return before it
+    // has seen the new boundary, otherwise they would still target the
+    // parent region.
+    fn wait_split(&self, split_key: &[u8]) -> Result<()> {
+        for _ in 0..SPLIT_WAIT_MAX_RETRY_TIMES {
+            match self.pd_client.get_region(split_key) {
+                Ok(ref region) if region.get_start_key() == split_key => return Ok(()),
+                Ok(_) => {}
+                Err(e) => warn!("wait split at {:?}: {:?}", split_key, e),
+            }
+            thread::sleep(Duration::from_millis(SPLIT_WAIT_INTERVAL_MILLIS));
+        }
+        Err(Error::SplitRegion(format!(
+            "timeout waiting for PD to observe the split at {:?}",
+            split_key
+        )))
+    }
+}
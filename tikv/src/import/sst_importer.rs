@@ -17,6 +17,7 @@ use std::io::Write;
 use std::path::{Path, PathBuf};
 use std::sync::Mutex;
 use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, SystemTime};
 
 use crc::crc32::{self, Hasher32};
 use uuid::Uuid;
@@ -92,12 +93,12 @@ impl SSTImporter {
         }
     }
 
-    pub fn finish(&self, token: Token) -> Result<()> {
+    pub fn finish(&self, token: Token) -> Result<SSTMeta> {
         match self.remove(token) {
             Some(mut f) => match f.finish() {
                 Ok(_) => {
                     info!("finish {:?}", f);
-                    Ok(())
+                    Ok(f.meta.clone())
                 }
                 Err(e) => {
                     error!("finish {:?}: {:?}", f, e);
@@ -108,6 +109,43 @@ impl SSTImporter {
         }
     }
 
+    /// Lists the active upload sessions and the disk space each occupies.
+    pub fn list(&self) -> Vec<(Token, SSTMeta, u64)> {
+        let files = self.files.lock().unwrap();
+        files
+            .iter()
+            .map(|(token, f)| (*token, f.meta.clone(), f.size()))
+            .collect()
+    }
+
+    /// Cancels an upload session, removing whatever it has written so far.
+    /// Returns false if the session doesn't exist.
+    pub fn cancel(&self, token: Token) -> bool {
+        match self.remove(token) {
+            Some(f) => {
+                info!("cancel {:?}", f);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Removes SST files older than `ttl` from the import directory. They
+    /// are left behind by aborted loads and would otherwise sit on disk
+    /// until manual cleanup. Files of active sessions are kept regardless
+    /// of age.
+    pub fn purge_expired(&self, ttl: Duration) -> Result<usize> {
+        let active: Vec<PathBuf> = {
+            let files = self.files.lock().unwrap();
+            files.values().map(|f| f.path.temp.clone()).collect()
+        };
+        let purged = self.dir.purge_expired(ttl, &active)?;
+        if purged > 0 {
+            info!("purged {} expired SST files", purged);
+        }
+        Ok(purged)
+    }
+
     pub fn delete(&self, meta: &SSTMeta) -> Result<()> {
         match self.dir.delete(meta) {
             Ok(path) => {
@@ -172,6 +210,38 @@ impl ImportDir {
         ImportFile::create(meta.clone(), path)
     }
 
+    fn purge_expired(&self, ttl: Duration, active: &[PathBuf]) -> Result<usize> {
+        let mut purged = 0;
+        let now = SystemTime::now();
+        for dir in &[&self.root_dir, &self.temp_dir, &self.clone_dir] {
+            for entry in fs::read_dir(dir)? {
+                let entry = entry?;
+                let path = entry.path();
+                if !path.to_string_lossy().ends_with(SST_SUFFIX) || active.contains(&path) {
+                    continue;
+                }
+                let age = match entry.metadata().and_then(|m| m.modified()) {
+                    Ok(modified) => now.duration_since(modified).unwrap_or_default(),
+                    Err(e) => {
+                        warn!("stat {:?}: {:?}", path, e);
+                        continue;
+                    }
+                };
+                if age < ttl {
+                    continue;
+                }
+                match fs::remove_file(&path) {
+                    Ok(_) => {
+                        info!("purge expired {:?}", path);
+                        purged += 1;
+                    }
+                    Err(e) => warn!("purge expired {:?}: {:?}", path, e),
+                }
+            }
+        }
+        Ok(purged)
+    }
+
     fn delete(&self, meta: &SSTMeta) -> Result<ImportPath> {
         let path = self.join(meta)?;
         if path.save.exists() {
@@ -234,6 +304,13 @@ impl ImportFile {
         Ok(())
     }
 
+    fn size(&self) -> u64 {
+        self.file
+            .as_ref()
+            .and_then(|f| f.metadata().ok())
+            .map_or(0, |m| m.len())
+    }
+
     fn finish(&mut self) -> Result<()> {
         self.validate()?;
         self.file.take().unwrap().sync_all()?;
@@ -386,6 +463,41 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_list_cancel_and_purge() {
+        let temp_dir = TempDir::new("test_list_cancel_and_purge").unwrap();
+        let importer = SSTImporter::new(temp_dir.path()).unwrap();
+
+        let mut meta = SSTMeta::new();
+        meta.set_uuid(Uuid::new_v4().as_bytes().to_vec());
+
+        let token = importer.token();
+        importer.create(token, &meta).unwrap();
+        importer.append(token, b"test_data").unwrap();
+
+        let sessions = importer.list();
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].0, token);
+        assert_eq!(sessions[0].2, b"test_data".len() as u64);
+
+        // An orphaned file from an aborted load.
+        let orphan = temp_dir.path().join("orphan.sst");
+        File::create(&orphan).unwrap();
+
+        // Everything is too young to be purged with a long TTL.
+        let purged = importer.purge_expired(Duration::from_secs(3600)).unwrap();
+        assert_eq!(purged, 0);
+        // A zero TTL purges the orphan, but not the active session.
+        let purged = importer.purge_expired(Duration::from_secs(0)).unwrap();
+        assert_eq!(purged, 1);
+        assert!(!orphan.exists());
+        assert_eq!(importer.list().len(), 1);
+
+        assert!(importer.cancel(token));
+        assert!(!importer.cancel(token));
+        assert!(importer.list().is_empty());
+    }
+
     #[test]
     fn test_sst_meta_to_path() {
         let mut meta = SSTMeta::new();
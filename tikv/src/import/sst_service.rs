@@ -13,44 +13,83 @@
 
 use std::sync::Arc;
 
-use grpc::{ClientStreamingSink, RequestStream, RpcContext, UnarySink};
+use grpc::{ClientStreamingSink, RequestStream, RpcContext, RpcStatus, RpcStatusCode, UnarySink};
 use futures::{Future, Stream};
 use futures::sync::mpsc;
 use futures_cpupool::{Builder, CpuPool};
 use kvproto::importpb::*;
 use kvproto::importpb_grpc::*;
 
+use pd::PdClient;
+use server::transport::RaftStoreRouter;
 use storage::Storage;
+use util::io_limiter::{IOLimiter, IOPriority};
+use util::security::SecurityManager;
 use util::time::Instant;
 
 use super::service::*;
 use super::metrics::*;
+use super::prepare::PrepareJob;
 use super::{Config, Error, SSTImporter};
 
-#[derive(Clone)]
-pub struct ImportSSTService {
+pub struct ImportSSTService<C, R> {
     cfg: Config,
     threads: CpuPool,
     storage: Storage,
     importer: Arc<SSTImporter>,
+    router: R,
+    pd_client: Arc<C>,
+    limiter: Option<Arc<IOLimiter>>,
+    security_mgr: Arc<SecurityManager>,
 }
 
-impl ImportSSTService {
-    pub fn new(cfg: Config, storage: Storage, importer: Arc<SSTImporter>) -> ImportSSTService {
+impl<C, R: Clone> Clone for ImportSSTService<C, R> {
+    fn clone(&self) -> ImportSSTService<C, R> {
+        ImportSSTService {
+            cfg: self.cfg.clone(),
+            threads: self.threads.clone(),
+            storage: self.storage.clone(),
+            importer: Arc::clone(&self.importer),
+            router: self.router.clone(),
+            pd_client: Arc::clone(&self.pd_client),
+            limiter: self.limiter.clone(),
+            security_mgr: Arc::clone(&self.security_mgr),
+        }
+    }
+}
+
+impl<C: PdClient, R: RaftStoreRouter + 'static> ImportSSTService<C, R> {
+    pub fn new(
+        cfg: Config,
+        storage: Storage,
+        importer: Arc<SSTImporter>,
+        router: R,
+        pd_client: Arc<C>,
+        security_mgr: Arc<SecurityManager>,
+    ) -> ImportSSTService<C, R> {
         let threads = Builder::new()
             .name_prefix("sst-importer")
             .pool_size(cfg.num_threads)
             .create();
+        let limiter = if cfg.speed_limit.0 > 0 {
+            Some(Arc::new(IOLimiter::new(cfg.speed_limit.0)))
+        } else {
+            None
+        };
         ImportSSTService {
             cfg: cfg,
             threads: threads,
             storage: storage,
             importer: importer,
+            router: router,
+            pd_client: pd_client,
+            limiter: limiter,
+            security_mgr: security_mgr,
         }
     }
 }
 
-impl ImportSst for ImportSSTService {
+impl<C: PdClient, R: RaftStoreRouter + 'static> ImportSst for ImportSSTService<C, R> {
     fn upload(
         &self,
         ctx: RpcContext,
@@ -58,20 +97,46 @@ impl ImportSst for ImportSSTService {
         sink: ClientStreamingSink<UploadResponse>,
     ) {
         let label = "upload";
+        // Loading data is as sensitive as writing it through the KV
+        // service, so the same token check applies.
+        if !self.security_mgr.check_auth(ctx.request_headers().iter()) {
+            let status = RpcStatus::new(
+                RpcStatusCode::Unauthenticated,
+                Some("invalid auth token".to_owned()),
+            );
+            ctx.spawn(sink.fail(status).map_err(|_| ()));
+            return;
+        }
         let timer = Instant::now_coarse();
 
+        // Garbage collect files left behind by earlier aborted loads, they
+        // would otherwise sit in the import directory until manual cleanup.
+        let import_gc = Arc::clone(&self.importer);
+        let sst_ttl = self.cfg.sst_ttl.0;
+        self.threads
+            .spawn_fn(move || -> Result<(), ()> {
+                if let Err(e) = import_gc.purge_expired(sst_ttl) {
+                    warn!("purge expired SST files: {:?}", e);
+                }
+                Ok(())
+            })
+            .forget();
+
         let token = self.importer.token();
         let thread1 = self.threads.clone();
         let thread2 = self.threads.clone();
         let import1 = Arc::clone(&self.importer);
         let import2 = Arc::clone(&self.importer);
         let bounded_stream = mpsc::spawn(stream, &self.threads, self.cfg.stream_channel_window);
+        let prepare = PrepareJob::new(Arc::clone(&self.pd_client), self.router.clone());
+        let limiter = self.limiter.clone();
 
         ctx.spawn(
             bounded_stream
                 .map_err(Error::from)
                 .for_each(move |chunk| {
                     let import1 = Arc::clone(&import1);
+                    let limiter = limiter.clone();
                     thread1.spawn_fn(move || {
                         let start = Instant::now_coarse();
                         if chunk.has_meta() {
@@ -79,6 +144,11 @@ impl ImportSst for ImportSSTService {
                         }
                         if !chunk.get_data().is_empty() {
                             let data = chunk.get_data();
+                            // Uploads run next to foreground traffic, pace
+                            // them before the data hits the disk.
+                            if let Some(ref limiter) = limiter {
+                                limiter.throttle(IOPriority::Low, data.len() as i64);
+                            }
                             import1.append(token, data)?;
                             IMPORT_UPLOAD_CHUNK_BYTES.observe(data.len() as f64);
                         }
@@ -88,7 +158,13 @@ impl ImportSst for ImportSSTService {
                 })
                 .then(move |res| {
                     thread2.spawn_fn(move || match res {
-                        Ok(_) => import2.finish(token),
+                        Ok(_) => {
+                            let meta = import2.finish(token)?;
+                            // Pre-split and scatter the target keyspace, so
+                            // the following ingest doesn't dump the whole
+                            // dataset into one region.
+                            prepare.prepare(&meta)
+                        }
                         Err(e) => {
                             if let Some(f) = import2.remove(token) {
                                 error!("remove {:?}: {:?}", f, e);
@@ -66,6 +66,8 @@ extern crate quick_error;
 extern crate rand;
 extern crate regex;
 extern crate rocksdb;
+extern crate rusoto_core;
+extern crate rusoto_s3;
 extern crate serde;
 #[macro_use]
 extern crate serde_derive;
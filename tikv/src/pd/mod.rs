@@ -14,12 +14,14 @@
 mod metrics;
 mod client;
 mod util;
+mod region_cache;
 
 pub mod errors;
 pub mod pd;
 mod config;
 pub use self::errors::{Error, Result};
 pub use self::client::RpcClient;
+pub use self::region_cache::RegionCache;
 pub use self::util::validate_endpoints;
 pub use self::pd::{Runner as PdRunner, Task as PdTask};
 pub use self::util::RECONNECT_INTERVAL_SEC;
@@ -34,7 +36,7 @@ use futures::Future;
 pub type Key = Vec<u8>;
 pub type PdFuture<T> = Box<Future<Item = T, Error = Error> + Send>;
 
-#[derive(Default)]
+#[derive(Clone, Default)]
 pub struct RegionStat {
     pub down_peers: Vec<pdpb::PeerStats>,
     pub pending_peers: Vec<metapb::Peer>,
@@ -67,7 +69,7 @@ impl RegionStat {
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct RegionInfo {
     pub region: metapb::Region,
     pub leader: Option<metapb::Peer>,
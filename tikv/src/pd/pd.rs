@@ -11,8 +11,10 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::sync::Arc;
+use std::mem;
+use std::sync::{Arc, Mutex};
 use std::fmt::{self, Display, Formatter};
+use std::time::{Duration, Instant};
 
 use futures::Future;
 use tokio_core::reactor::Handle;
@@ -26,11 +28,12 @@ use rocksdb::DB;
 use fs2;
 
 use util::worker::FutureRunnable as Runnable;
+use util::worker::FutureScheduler;
 use util::escape;
 use util::transport::SendCh;
 use util::rocksdb::*;
 use pd::{PdClient, RegionStat};
-use raftstore::store::Msg;
+use raftstore::store::{HotRegion, Msg};
 use raftstore::store::util::{get_region_approximate_size, is_epoch_stale};
 use raftstore::store::store::StoreInfo;
 use raftstore::store::Callback;
@@ -61,6 +64,7 @@ pub enum Task {
     StoreHeartbeat {
         stats: pdpb::StoreStats,
         store_info: StoreInfo,
+        hot_regions: Vec<HotRegion>,
     },
     ReportSplit {
         left: metapb::Region,
@@ -76,6 +80,94 @@ pub enum Task {
     DestroyPeer {
         region_id: u64,
     },
+    // The PD client reconnected. The new PD leader may know nothing
+    // about our in-flight operators and has not seen the heartbeats
+    // that were buffered on the dead stream.
+    Reconnected,
+}
+
+// A repeated operator won't be dispatched to raftstore again within this
+// period, so PD resending an operator on every heartbeat doesn't produce
+// duplicate conf changes while the first proposal is still in progress.
+const RETRY_DISPATCH_OPERATOR_INTERVAL_SECS: u64 = 10;
+
+/// A PD scheduling operator that has been dispatched to raftstore.
+#[derive(Debug, PartialEq)]
+enum Operator {
+    ChangePeer(ConfChangeType, metapb::Peer),
+    TransferLeader(metapb::Peer),
+}
+
+struct DispatchedOperator {
+    epoch: metapb::RegionEpoch,
+    peer: metapb::Peer,
+    operator: Operator,
+    dispatch_time: Instant,
+}
+
+/// The last region heartbeat sent for a region. PD does not acknowledge
+/// heartbeats (its stream only responds when it schedules an operator),
+/// so the last one is kept unconditionally and replayed once after a
+/// reconnect, in case it was still in flight on the dead stream.
+struct LastHeartbeat {
+    region: metapb::Region,
+    peer: metapb::Peer,
+    region_stat: RegionStat,
+}
+
+/// Checks whether a PD operator should be dispatched to raftstore, and
+/// records it if so. Out-of-date operators (epoch older than the last
+/// dispatched one) and recently repeated operators are filtered out.
+fn check_dispatch_operator(
+    dispatched: &mut HashMap<u64, DispatchedOperator>,
+    region_id: u64,
+    epoch: &metapb::RegionEpoch,
+    peer: &metapb::Peer,
+    operator: Operator,
+) -> bool {
+    if let Some(last) = dispatched.get_mut(&region_id) {
+        if is_epoch_stale(epoch, &last.epoch) {
+            info!(
+                "[region {}] operator {:?} [epoch: {:?}] is out of date, last \
+                 dispatched epoch {:?}, skip",
+                region_id, operator, epoch, last.epoch
+            );
+            PD_HEARTBEAT_COUNTER_VEC
+                .with_label_values(&["operator stale"])
+                .inc();
+            return false;
+        }
+        if *epoch == last.epoch && operator == last.operator
+            && last.dispatch_time.elapsed()
+                < Duration::from_secs(RETRY_DISPATCH_OPERATOR_INTERVAL_SECS)
+        {
+            info!(
+                "[region {}] operator {:?} is dispatched already, skip",
+                region_id, operator
+            );
+            PD_HEARTBEAT_COUNTER_VEC
+                .with_label_values(&["operator repeated"])
+                .inc();
+            return false;
+        }
+        *last = DispatchedOperator {
+            epoch: epoch.clone(),
+            peer: peer.clone(),
+            operator: operator,
+            dispatch_time: Instant::now(),
+        };
+        return true;
+    }
+    dispatched.insert(
+        region_id,
+        DispatchedOperator {
+            epoch: epoch.clone(),
+            peer: peer.clone(),
+            operator: operator,
+            dispatch_time: Instant::now(),
+        },
+    );
+    true
 }
 
 pub struct StoreStat {
@@ -154,6 +246,7 @@ impl Display for Task {
                 write!(f, "get the read statistics {:?}", read_stats)
             }
             Task::DestroyPeer { ref region_id } => write!(f, "destroy peer {}", region_id),
+            Task::Reconnected => write!(f, "replay operators and heartbeats after reconnect"),
         }
     }
 }
@@ -166,10 +259,24 @@ pub struct Runner<T: PdClient> {
     region_peers: HashMap<u64, PeerStat>,
     store_stat: StoreStat,
     is_hb_receiver_scheduled: bool,
+    dispatched_operators: Arc<Mutex<HashMap<u64, DispatchedOperator>>>,
+    last_heartbeats: HashMap<u64, LastHeartbeat>,
 }
 
 impl<T: PdClient> Runner<T> {
-    pub fn new(store_id: u64, pd_client: Arc<T>, ch: SendCh<Msg>, db: Arc<DB>) -> Runner<T> {
+    pub fn new(
+        store_id: u64,
+        pd_client: Arc<T>,
+        ch: SendCh<Msg>,
+        db: Arc<DB>,
+        scheduler: FutureScheduler<Task>,
+    ) -> Runner<T> {
+        let reconnect_scheduler = scheduler.clone();
+        pd_client.handle_reconnect(move || {
+            if let Err(e) = reconnect_scheduler.schedule(Task::Reconnected) {
+                error!("notify pd worker about reconnect failed: {:?}", e);
+            }
+        });
         Runner {
             store_id: store_id,
             pd_client: pd_client,
@@ -178,6 +285,8 @@ impl<T: PdClient> Runner<T> {
             is_hb_receiver_scheduled: false,
             region_peers: HashMap::default(),
             store_stat: StoreStat::default(),
+            dispatched_operators: Arc::new(Mutex::new(HashMap::default())),
+            last_heartbeats: HashMap::default(),
         }
     }
 
@@ -221,7 +330,7 @@ impl<T: PdClient> Runner<T> {
     }
 
     fn handle_heartbeat(
-        &self,
+        &mut self,
         handle: &Handle,
         region: metapb::Region,
         peer: metapb::Peer,
@@ -240,6 +349,27 @@ impl<T: PdClient> Runner<T> {
             .region_keys_read
             .observe(region_stat.read_keys as f64);
 
+        // Remember the heartbeat so it can be replayed if the stream
+        // dies with it in flight. A fresh heartbeat for the region
+        // supersedes an older one.
+        self.last_heartbeats.insert(
+            region.get_id(),
+            LastHeartbeat {
+                region: region.clone(),
+                peer: peer.clone(),
+                region_stat: region_stat.clone(),
+            },
+        );
+        self.send_heartbeat(handle, region, peer, region_stat);
+    }
+
+    fn send_heartbeat(
+        &self,
+        handle: &Handle,
+        region: metapb::Region,
+        peer: metapb::Peer,
+        region_stat: RegionStat,
+    ) {
         // Now we use put region protocol for heartbeat.
         let f = self.pd_client
             .region_heartbeat(region.clone(), peer.clone(), region_stat)
@@ -253,12 +383,78 @@ impl<T: PdClient> Runner<T> {
         handle.spawn(f);
     }
 
+    /// Pushes the cached operators back to raftstore after a reconnect.
+    /// A conf change the old PD leader scheduled may not be known to the
+    /// new one, so a lost proposal would otherwise never be retried.
+    /// Raftstore ignores requests that carry a stale epoch, replaying a
+    /// completed operator is harmless.
+    fn handle_reconnected(&mut self, handle: &Handle) {
+        let redispatch = {
+            let mut dispatched = self.dispatched_operators.lock().unwrap();
+            let mut redispatch = Vec::with_capacity(dispatched.len());
+            for (&region_id, op) in dispatched.iter_mut() {
+                if op.dispatch_time.elapsed()
+                    > Duration::from_secs(RETRY_DISPATCH_OPERATOR_INTERVAL_SECS)
+                {
+                    // Old enough that either it completed or raftstore
+                    // dropped it long ago, PD will reschedule if it still
+                    // wants it.
+                    continue;
+                }
+                op.dispatch_time = Instant::now();
+                let req = match op.operator {
+                    Operator::ChangePeer(change_type, ref peer) => {
+                        new_change_peer_request(change_type, peer.clone())
+                    }
+                    Operator::TransferLeader(ref peer) => {
+                        new_transfer_leader_request(peer.clone())
+                    }
+                };
+                redispatch.push((region_id, op.epoch.clone(), op.peer.clone(), req));
+            }
+            redispatch
+        };
+        for (region_id, epoch, peer, req) in redispatch {
+            info!(
+                "[region {}] replay operator {:?} after PD reconnect",
+                region_id, req
+            );
+            PD_HEARTBEAT_COUNTER_VEC
+                .with_label_values(&["operator replay"])
+                .inc();
+            send_admin_request(&self.ch, region_id, epoch, peer, req, Callback::None);
+        }
+
+        // The heartbeat that was in flight on the dead stream may be
+        // gone, replay the last one of every region exactly once. The
+        // map is drained: the regular heartbeats repopulate it, and a
+        // region's next one supersedes the replay anyway.
+        let replay = mem::replace(&mut self.last_heartbeats, HashMap::default());
+        for (_, hb) in replay {
+            PD_HEARTBEAT_COUNTER_VEC
+                .with_label_values(&["replay"])
+                .inc();
+            self.send_heartbeat(handle, hb.region, hb.peer, hb.region_stat);
+        }
+    }
+
     fn handle_store_heartbeat(
         &mut self,
         handle: &Handle,
         mut stats: pdpb::StoreStats,
         store_info: StoreInfo,
+        hot_regions: Vec<HotRegion>,
     ) {
+        // pdpb::StoreStats has no field for hot region digests yet,
+        // carrying them to the PD leader needs a protocol extension in
+        // kvproto. Until then the hints are logged so operators can
+        // transfer leaders of the hottest regions by hand.
+        for hot in &hot_regions {
+            info!(
+                "[region {}] hot region, {} qps, {} bytes/s",
+                hot.region_id, hot.qps, hot.byte_rate
+            );
+        }
         let disk_stats = match fs2::statvfs(store_info.engine.path()) {
             Err(e) => {
                 error!(
@@ -407,6 +603,7 @@ impl<T: PdClient> Runner<T> {
     fn schedule_heartbeat_receiver(&mut self, handle: &Handle) {
         let ch = self.ch.clone();
         let store_id = self.store_id;
+        let dispatched = Arc::clone(&self.dispatched_operators);
         let f = self.pd_client
             .handle_region_heartbeat_response(self.store_id, move |mut resp| {
                 let region_id = resp.get_region_id();
@@ -419,6 +616,19 @@ impl<T: PdClient> Runner<T> {
                         .inc();
 
                     let mut change_peer = resp.take_change_peer();
+                    let operator = Operator::ChangePeer(
+                        change_peer.get_change_type(),
+                        change_peer.get_peer().clone(),
+                    );
+                    if !check_dispatch_operator(
+                        &mut dispatched.lock().unwrap(),
+                        region_id,
+                        &epoch,
+                        &peer,
+                        operator,
+                    ) {
+                        return;
+                    }
                     info!(
                         "[region {}] try to change peer {:?} {:?}",
                         region_id,
@@ -436,6 +646,16 @@ impl<T: PdClient> Runner<T> {
                         .inc();
 
                     let mut transfer_leader = resp.take_transfer_leader();
+                    let operator = Operator::TransferLeader(transfer_leader.get_peer().clone());
+                    if !check_dispatch_operator(
+                        &mut dispatched.lock().unwrap(),
+                        region_id,
+                        &epoch,
+                        &peer,
+                        operator,
+                    ) {
+                        return;
+                    }
                     info!(
                         "[region {}] try to transfer leader from {:?} to {:?}",
                         region_id,
@@ -472,6 +692,11 @@ impl<T: PdClient> Runner<T> {
     }
 
     fn handle_destroy_peer(&mut self, region_id: u64) {
+        self.dispatched_operators
+            .lock()
+            .unwrap()
+            .remove(&region_id);
+        self.last_heartbeats.remove(&region_id);
         match self.region_peers.remove(&region_id) {
             None => return,
             Some(_) => info!("[region {}] remove peer statistic record in pd", region_id),
@@ -542,13 +767,16 @@ impl<T: PdClient> Runnable<Task> for Runner<T> {
                     ),
                 )
             }
-            Task::StoreHeartbeat { stats, store_info } => {
-                self.handle_store_heartbeat(handle, stats, store_info)
-            }
+            Task::StoreHeartbeat {
+                stats,
+                store_info,
+                hot_regions,
+            } => self.handle_store_heartbeat(handle, stats, store_info, hot_regions),
             Task::ReportSplit { left, right } => self.handle_report_split(handle, left, right),
             Task::ValidatePeer { region, peer } => self.handle_validate_peer(handle, region, peer),
             Task::ReadStats { read_stats } => self.handle_read_stats(read_stats),
             Task::DestroyPeer { region_id } => self.handle_destroy_peer(region_id),
+            Task::Reconnected => self.handle_reconnected(handle),
         };
     }
 }
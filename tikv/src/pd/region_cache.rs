@@ -0,0 +1,409 @@
+// Copyright 2018 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::BTreeMap;
+use std::collections::Bound::{Included, Unbounded};
+use std::sync::{Arc, Mutex};
+
+use futures::Future;
+use kvproto::errorpb;
+use kvproto::kvrpcpb::Context;
+use kvproto::metapb;
+
+use storage::RegionValidator;
+use util::collections::HashMap;
+use util::escape;
+
+use super::{Key, PdClient, RegionInfo, Result};
+
+/// A cache of region routing information for internal components that need
+/// to locate regions and their leaders without each implementing its own
+/// `get_region` loop.
+///
+/// Entries are filled from PD on a miss. The cache never refreshes itself,
+/// callers must invalidate an entry when a request against it fails with
+/// `NotLeader` or `StaleEpoch`, via `update_leader` and `invalidate`.
+pub struct RegionCache<C> {
+    pd_client: Arc<C>,
+    inner: Mutex<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    // region id -> cached info.
+    regions: HashMap<u64, RegionInfo>,
+    // region start key -> region id, ordered so that locating the region of
+    // a key is a single range query. The empty start key of the first region
+    // sorts before everything else, so it needs no special handling.
+    start_keys: BTreeMap<Key, u64>,
+}
+
+impl Inner {
+    fn find(&self, key: &[u8]) -> Option<&RegionInfo> {
+        let region_id = match self.start_keys
+            .range::<[u8], _>((Unbounded, Included(key)))
+            .next_back()
+        {
+            Some((_, &region_id)) => region_id,
+            None => return None,
+        };
+        let info = &self.regions[&region_id];
+        let end_key = info.region.get_end_key();
+        if end_key.is_empty() || key < end_key {
+            Some(info)
+        } else {
+            None
+        }
+    }
+
+    fn insert(&mut self, info: RegionInfo) {
+        let region_id = info.region.get_id();
+        let start_key = info.region.get_start_key().to_vec();
+        let end_key = info.region.get_end_key().to_vec();
+
+        // Remove stale regions overlapping the new one (e.g. the parent of a
+        // split), otherwise two entries would claim the same range.
+        let mut stale = Vec::new();
+        if let Some(overlap) = self.find(&start_key) {
+            stale.push(overlap.region.get_id());
+        }
+        for (key, &id) in self.start_keys.range(start_key.clone()..) {
+            if !end_key.is_empty() && key.as_slice() >= end_key.as_slice() {
+                break;
+            }
+            stale.push(id);
+        }
+        for id in stale {
+            self.remove(id);
+        }
+
+        self.start_keys.insert(start_key, region_id);
+        self.regions.insert(region_id, info);
+    }
+
+    fn remove(&mut self, region_id: u64) {
+        if let Some(info) = self.regions.remove(&region_id) {
+            let start_key = info.region.get_start_key();
+            if self.start_keys.get(start_key) == Some(&region_id) {
+                self.start_keys.remove(start_key);
+            }
+        }
+    }
+}
+
+impl<C: PdClient> RegionCache<C> {
+    pub fn new(pd_client: Arc<C>) -> RegionCache<C> {
+        RegionCache {
+            pd_client: pd_client,
+            inner: Mutex::new(Inner::default()),
+        }
+    }
+
+    /// Get the region containing `key`, asking PD on a cache miss.
+    pub fn get_region_info(&self, key: &[u8]) -> Result<RegionInfo> {
+        {
+            let inner = self.inner.lock().unwrap();
+            if let Some(info) = inner.find(key) {
+                return Ok(info.clone());
+            }
+        }
+        let info = self.pd_client.get_region_info(key)?;
+        let mut inner = self.inner.lock().unwrap();
+        inner.insert(info.clone());
+        Ok(info)
+    }
+
+    /// Get a region by its id, asking PD on a cache miss. Returns `None` if
+    /// the region doesn't exist any more.
+    pub fn get_region_by_id(&self, region_id: u64) -> Result<Option<RegionInfo>> {
+        {
+            let inner = self.inner.lock().unwrap();
+            if let Some(info) = inner.regions.get(&region_id) {
+                return Ok(Some(info.clone()));
+            }
+        }
+        match self.pd_client.get_region_by_id(region_id).wait()? {
+            Some(region) => {
+                let info = RegionInfo::new(region, None);
+                let mut inner = self.inner.lock().unwrap();
+                inner.insert(info.clone());
+                Ok(Some(info))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Update the cached leader of a region, e.g. from the hint carried by a
+    /// `NotLeader` error.
+    pub fn update_leader(&self, region_id: u64, leader: metapb::Peer) {
+        let mut inner = self.inner.lock().unwrap();
+        if let Some(info) = inner.regions.get_mut(&region_id) {
+            info.leader = Some(leader);
+        }
+    }
+
+    /// Drop a region from the cache. Call this when a request against the
+    /// cached info fails with `StaleEpoch`, or with `NotLeader` without a
+    /// new leader hint.
+    pub fn invalidate(&self, region_id: u64) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.remove(region_id);
+    }
+}
+
+fn key_not_in_region(key: &[u8], region: &metapb::Region) -> errorpb::Error {
+    let mut err = errorpb::Error::new();
+    err.set_message(format!(
+        "key {:?} is not in region {}",
+        escape(key),
+        region.get_id()
+    ));
+    err.mut_key_not_in_region().set_key(key.to_vec());
+    err.mut_key_not_in_region().set_region_id(region.get_id());
+    err.mut_key_not_in_region()
+        .set_start_key(region.get_start_key().to_vec());
+    err.mut_key_not_in_region()
+        .set_end_key(region.get_end_key().to_vec());
+    err
+}
+
+impl<C: PdClient> RegionValidator for RegionCache<C> {
+    fn validate(
+        &self,
+        ctx: &Context,
+        start_key: &[u8],
+        end_key: &[u8],
+    ) -> ::std::result::Result<(), errorpb::Error> {
+        let info = match self.get_region_by_id(ctx.get_region_id()) {
+            Ok(Some(info)) => info,
+            // An unknown region or an unreachable PD can not be judged
+            // here; raftstore remains the authority.
+            Ok(None) | Err(_) => return Ok(()),
+        };
+        let region = &info.region;
+        let cached = region.get_region_epoch();
+        let claimed = ctx.get_region_epoch();
+        // A client with a newer view than the cache may legitimately
+        // address keys outside the cached range (e.g. after a merge),
+        // so only claims at or below the cached epoch are checked.
+        if claimed.get_version() > cached.get_version()
+            || claimed.get_conf_ver() > cached.get_conf_ver()
+        {
+            return Ok(());
+        }
+        let region_start = region.get_start_key();
+        let region_end = region.get_end_key();
+        if !start_key.is_empty()
+            && (start_key < region_start || (!region_end.is_empty() && start_key >= region_end))
+        {
+            return Err(key_not_in_region(start_key, region));
+        }
+        // The end key is an exclusive bound: one equal to the region end
+        // still addresses only keys inside the region, clients routinely
+        // clip their ranges to region boundaries this way.
+        if !end_key.is_empty()
+            && (end_key < region_start || (!region_end.is_empty() && end_key > region_end))
+        {
+            return Err(key_not_in_region(end_key, region));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use futures::future;
+    use kvproto::kvrpcpb::Context;
+    use kvproto::pdpb;
+
+    use pd::{PdFuture, RegionStat};
+    use storage::RegionValidator;
+
+    use super::*;
+
+    struct MockPdClient {
+        regions: Vec<metapb::Region>,
+        get_region_count: AtomicUsize,
+    }
+
+    impl MockPdClient {
+        fn new(regions: Vec<metapb::Region>) -> MockPdClient {
+            MockPdClient {
+                regions: regions,
+                get_region_count: AtomicUsize::new(0),
+            }
+        }
+    }
+
+    impl PdClient for MockPdClient {
+        fn get_cluster_id(&self) -> Result<u64> {
+            unimplemented!()
+        }
+        fn bootstrap_cluster(&self, _: metapb::Store, _: metapb::Region) -> Result<()> {
+            unimplemented!()
+        }
+        fn is_cluster_bootstrapped(&self) -> Result<bool> {
+            unimplemented!()
+        }
+        fn alloc_id(&self) -> Result<u64> {
+            unimplemented!()
+        }
+        fn put_store(&self, _: metapb::Store) -> Result<()> {
+            unimplemented!()
+        }
+        fn get_store(&self, _: u64) -> Result<metapb::Store> {
+            unimplemented!()
+        }
+        fn get_cluster_config(&self) -> Result<metapb::Cluster> {
+            unimplemented!()
+        }
+        fn get_region(&self, key: &[u8]) -> Result<metapb::Region> {
+            self.get_region_count.fetch_add(1, Ordering::SeqCst);
+            for region in &self.regions {
+                let end_key = region.get_end_key();
+                if key >= region.get_start_key() && (end_key.is_empty() || key < end_key) {
+                    return Ok(region.clone());
+                }
+            }
+            panic!("no region for key {:?}", key);
+        }
+        fn get_region_by_id(&self, region_id: u64) -> PdFuture<Option<metapb::Region>> {
+            self.get_region_count.fetch_add(1, Ordering::SeqCst);
+            let region = self.regions
+                .iter()
+                .find(|r| r.get_id() == region_id)
+                .cloned();
+            Box::new(future::ok(region))
+        }
+        fn region_heartbeat(
+            &self,
+            _: metapb::Region,
+            _: metapb::Peer,
+            _: RegionStat,
+        ) -> PdFuture<()> {
+            unimplemented!()
+        }
+        fn handle_region_heartbeat_response<F>(&self, _: u64, _: F) -> PdFuture<()>
+        where
+            F: Fn(pdpb::RegionHeartbeatResponse) + Send + 'static,
+        {
+            unimplemented!()
+        }
+        fn ask_split(&self, _: metapb::Region) -> PdFuture<pdpb::AskSplitResponse> {
+            unimplemented!()
+        }
+        fn store_heartbeat(&self, _: pdpb::StoreStats) -> PdFuture<()> {
+            unimplemented!()
+        }
+        fn report_split(&self, _: metapb::Region, _: metapb::Region) -> PdFuture<()> {
+            unimplemented!()
+        }
+    }
+
+    fn new_region(id: u64, start_key: &[u8], end_key: &[u8]) -> metapb::Region {
+        let mut region = metapb::Region::new();
+        region.set_id(id);
+        region.set_start_key(start_key.to_vec());
+        region.set_end_key(end_key.to_vec());
+        region
+    }
+
+    #[test]
+    fn test_region_cache() {
+        let client = Arc::new(MockPdClient::new(vec![
+            new_region(1, b"", b"b"),
+            new_region(2, b"b", b"d"),
+            new_region(3, b"d", b""),
+        ]));
+        let cache = RegionCache::new(Arc::clone(&client));
+
+        assert_eq!(cache.get_region_info(b"a").unwrap().region.get_id(), 1);
+        assert_eq!(cache.get_region_info(b"c").unwrap().region.get_id(), 2);
+        assert_eq!(cache.get_region_info(b"e").unwrap().region.get_id(), 3);
+        assert_eq!(client.get_region_count.load(Ordering::SeqCst), 3);
+
+        // Hits don't go to PD, by key or by id.
+        assert_eq!(cache.get_region_info(b"a1").unwrap().region.get_id(), 1);
+        assert_eq!(cache.get_region_by_id(2).unwrap().unwrap().region.get_id(), 2);
+        assert_eq!(client.get_region_count.load(Ordering::SeqCst), 3);
+
+        // An invalidated region is fetched again.
+        cache.invalidate(2);
+        assert_eq!(cache.get_region_info(b"c").unwrap().region.get_id(), 2);
+        assert_eq!(client.get_region_count.load(Ordering::SeqCst), 4);
+
+        // Leader updates are visible to following lookups.
+        let mut leader = metapb::Peer::new();
+        leader.set_id(4);
+        cache.update_leader(2, leader.clone());
+        assert_eq!(cache.get_region_info(b"c").unwrap().leader, Some(leader));
+        assert_eq!(client.get_region_count.load(Ordering::SeqCst), 4);
+
+        assert_eq!(cache.get_region_by_id(4).unwrap(), None);
+    }
+
+    #[test]
+    fn test_region_validator() {
+        let cache = RegionCache::new(Arc::new(MockPdClient::new(vec![
+            new_region(1, b"a", b"c"),
+        ])));
+        let mut ctx = Context::new();
+        ctx.set_region_id(1);
+
+        // keys inside the region pass; an empty end key is unchecked.
+        assert!(cache.validate(&ctx, b"a", b"b").is_ok());
+        assert!(cache.validate(&ctx, b"b", b"").is_ok());
+        // the end bound is exclusive, a range clipped to the region
+        // boundary is valid.
+        assert!(cache.validate(&ctx, b"a", b"c").is_ok());
+
+        // keys outside are rejected with KeyNotInRegion.
+        let err = cache.validate(&ctx, b"c", b"").unwrap_err();
+        assert!(err.has_key_not_in_region());
+        assert_eq!(err.get_key_not_in_region().get_region_id(), 1);
+        assert_eq!(err.get_key_not_in_region().get_end_key(), b"c");
+        let err = cache.validate(&ctx, b"a", b"d").unwrap_err();
+        assert_eq!(err.get_key_not_in_region().get_key(), b"d");
+
+        // a claim newer than the cached epoch can not be judged here,
+        // neither can an unknown region.
+        ctx.mut_region_epoch().set_version(1);
+        assert!(cache.validate(&ctx, b"d", b"").is_ok());
+        ctx.mut_region_epoch().set_version(0);
+        ctx.set_region_id(9);
+        assert!(cache.validate(&ctx, b"z", b"").is_ok());
+    }
+
+    #[test]
+    fn test_region_cache_overlap() {
+        let client = Arc::new(MockPdClient::new(vec![new_region(1, b"", b"")]));
+        let cache = RegionCache::new(Arc::clone(&client));
+        assert_eq!(cache.get_region_info(b"a").unwrap().region.get_id(), 1);
+
+        // A split replaces the stale parent region in the cache.
+        let cache = RegionCache::new(Arc::new(MockPdClient::new(vec![
+            new_region(4, b"", b"b"),
+            new_region(5, b"b", b""),
+        ])));
+        {
+            let mut inner = cache.inner.lock().unwrap();
+            inner.insert(RegionInfo::new(new_region(1, b"", b""), None));
+            inner.insert(RegionInfo::new(new_region(4, b"", b"b"), None));
+        }
+        assert_eq!(cache.get_region_info(b"a").unwrap().region.get_id(), 4);
+        assert_eq!(cache.get_region_info(b"c").unwrap().region.get_id(), 5);
+        assert!(cache.get_region_by_id(1).unwrap().is_none());
+    }
+}
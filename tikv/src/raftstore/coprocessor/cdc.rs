@@ -0,0 +1,562 @@
+// Copyright 2018 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Change data capture.
+//!
+//! `CdcObserver` watches the apply pipeline and turns committed
+//! transactions into `CdcEvent`s: a put to the write CF is a commit, the
+//! lock CF carries the prewrites before it. The observer only captures,
+//! delivery to subscribers happens behind the registered `CdcSink`.
+
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use kvproto::raft_cmdpb::{CmdType, Request};
+use rocksdb::{SeekKey, DB};
+
+use raftstore::store::engine::{IterOption, Iterable, Peekable};
+use raftstore::store::keys;
+use storage::mvcc::{Lock, LockType, Write, WriteType};
+use storage::types::{truncate_ts, Key};
+use storage::{CF_DEFAULT, CF_LOCK, CF_WRITE};
+use util::collections::HashMap;
+
+use super::{Coprocessor, ObserverContext, QueryObserver};
+
+pub const CDC_OBSERVER_PRIORITY: u32 = 300;
+
+// Entries are removed at commit or rollback, the cap only protects
+// against leaks from locks that never resolve.
+const OLD_VALUE_CACHE_CAP: usize = 100_000;
+
+/// A change captured from one committed write.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CdcEvent {
+    /// The encoded user key, without a version.
+    pub key: Vec<u8>,
+    pub start_ts: u64,
+    pub commit_ts: u64,
+    /// `WriteType::Put` or `WriteType::Delete`.
+    pub write_type: WriteType,
+    pub value: Option<Vec<u8>>,
+    /// The value this write replaced. Only filled when old value capture
+    /// is enabled, downstream sinks need it to build update statements.
+    pub old_value: Option<Vec<u8>>,
+}
+
+/// Where captured events go. The observer calls `emit` on the apply
+/// thread, implementations must hand the events off quickly.
+pub trait CdcSink: Send + Sync {
+    fn emit(&self, region_id: u64, events: Vec<CdcEvent>);
+}
+
+pub struct CdcObserver {
+    db: Arc<DB>,
+    sink: Arc<CdcSink>,
+    capture_old_value: bool,
+    // Old values read at prewrite time, keyed by the locked key. Reading
+    // at prewrite hits the block cache while the key is hot and saves a
+    // second seek at commit time.
+    old_values: Mutex<HashMap<Vec<u8>, Option<Vec<u8>>>>,
+}
+
+impl CdcObserver {
+    pub fn new(db: Arc<DB>, sink: Arc<CdcSink>, capture_old_value: bool) -> CdcObserver {
+        CdcObserver {
+            db: db,
+            sink: sink,
+            capture_old_value: capture_old_value,
+            old_values: Mutex::new(HashMap::default()),
+        }
+    }
+
+    fn on_prewrite(&self, key: &[u8], value: &[u8]) {
+        if !self.capture_old_value {
+            return;
+        }
+        let lock = match Lock::parse(value) {
+            Ok(lock) => lock,
+            Err(e) => {
+                warn!("cdc: parse lock at {:?}: {:?}", key, e);
+                return;
+            }
+        };
+        match lock.lock_type {
+            LockType::Put | LockType::Delete => {}
+            // Lock records don't change data, there is no old value. A
+            // pessimistic lock is not a prewrite yet, the transaction
+            // locks again as part of its real prewrite.
+            LockType::Lock | LockType::Pessimistic => return,
+        }
+        let old_value = self.read_old_value(key, lock.ts);
+        let mut cache = self.old_values.lock().unwrap();
+        if cache.len() >= OLD_VALUE_CACHE_CAP {
+            warn!(
+                "cdc: old value cache is full, dropping {} entries",
+                cache.len()
+            );
+            cache.clear();
+        }
+        cache.insert(key.to_vec(), old_value);
+    }
+
+    fn on_commit(&self, key: &[u8], value: &[u8], events: &mut Vec<CdcEvent>) {
+        let write = match Write::parse(value) {
+            Ok(write) => write,
+            Err(e) => {
+                warn!("cdc: parse write at {:?}: {:?}", key, e);
+                return;
+            }
+        };
+        match write.write_type {
+            WriteType::Put | WriteType::Delete => {}
+            // Lock and Rollback records don't change data. Their cached
+            // old values are dropped when the lock delete is observed.
+            WriteType::Lock | WriteType::Rollback => return,
+        }
+        let commit_ts = match Key::from_encoded(key.to_vec()).decode_ts() {
+            Ok(ts) => ts,
+            Err(e) => {
+                warn!("cdc: decode ts of {:?}: {:?}", key, e);
+                return;
+            }
+        };
+        let user_key = truncate_ts(key).to_vec();
+        let old_value = if self.capture_old_value {
+            match self.old_values.lock().unwrap().remove(&user_key) {
+                Some(old_value) => old_value,
+                // The prewrite predates the observer or the cache was
+                // dropped, fall back to a read.
+                None => self.read_old_value(&user_key, commit_ts - 1),
+            }
+        } else {
+            None
+        };
+        let value = match write.write_type {
+            WriteType::Put => match write.short_value {
+                Some(ref v) => Some(v.clone()),
+                None => self.load_default(&user_key, write.start_ts),
+            },
+            _ => None,
+        };
+        events.push(CdcEvent {
+            key: user_key,
+            start_ts: write.start_ts,
+            commit_ts: commit_ts,
+            write_type: write.write_type,
+            value: value,
+            old_value: old_value,
+        });
+    }
+
+    /// Reads the committed value of `key` (an encoded user key) at `ts`.
+    fn read_old_value(&self, key: &[u8], ts: u64) -> Option<Vec<u8>> {
+        let prefix = keys::data_key(key);
+        let seek_key = Key::from_encoded(prefix.clone()).append_ts(ts);
+        let mut iter = match self.db
+            .new_iterator_cf(CF_WRITE, IterOption::new(None, None, true))
+        {
+            Ok(iter) => iter,
+            Err(e) => {
+                warn!("cdc: write cf iterator: {:?}", e);
+                return None;
+            }
+        };
+        let mut ok = iter.seek(SeekKey::Key(seek_key.encoded()));
+        while ok {
+            let (wkey, wvalue) = (iter.key().to_vec(), iter.value().to_vec());
+            if truncate_ts(&wkey) != prefix.as_slice() {
+                return None;
+            }
+            let write = match Write::parse(&wvalue) {
+                Ok(write) => write,
+                Err(_) => return None,
+            };
+            match write.write_type {
+                WriteType::Put => {
+                    return match write.short_value {
+                        Some(v) => Some(v),
+                        None => self.load_default(key, write.start_ts),
+                    }
+                }
+                WriteType::Delete => return None,
+                WriteType::Lock | WriteType::Rollback => ok = iter.next(),
+            }
+        }
+        None
+    }
+
+    fn load_default(&self, key: &[u8], start_ts: u64) -> Option<Vec<u8>> {
+        let versioned = Key::from_encoded(key.to_vec()).append_ts(start_ts);
+        let data_key = keys::data_key(versioned.encoded());
+        match self.db.get_value_cf(CF_DEFAULT, &data_key) {
+            Ok(value) => value.map(|v| v.to_vec()),
+            Err(e) => {
+                warn!("cdc: load default value of {:?}: {:?}", key, e);
+                None
+            }
+        }
+    }
+}
+
+impl Coprocessor for CdcObserver {}
+
+impl QueryObserver for CdcObserver {
+    fn pre_apply_query(&self, ctx: &mut ObserverContext, reqs: &[Request]) {
+        // A failed apply aborts the store, so observing right before the
+        // write batch goes down is as good as observing after it.
+        let mut events = Vec::new();
+        for req in reqs {
+            match req.get_cmd_type() {
+                CmdType::Put => {
+                    let put = req.get_put();
+                    match put.get_cf() {
+                        CF_LOCK => self.on_prewrite(put.get_key(), put.get_value()),
+                        CF_WRITE => self.on_commit(put.get_key(), put.get_value(), &mut events),
+                        _ => {}
+                    }
+                }
+                CmdType::Delete => {
+                    // A lock disappears on commit (its write record came
+                    // earlier in this batch) or on rollback, the cached
+                    // old value is stale either way.
+                    if req.get_delete().get_cf() == CF_LOCK {
+                        let mut cache = self.old_values.lock().unwrap();
+                        cache.remove(req.get_delete().get_key());
+                    }
+                }
+                _ => {}
+            }
+        }
+        if !events.is_empty() {
+            self.sink.emit(ctx.region().get_id(), events);
+        }
+    }
+}
+
+/// A resume token. Everything of the region committed at or below
+/// `resolved_ts` has been acknowledged, a new subscription starting from
+/// it sees every later change without a full re-scan.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ResumeToken {
+    pub region_id: u64,
+    pub resolved_ts: u64,
+}
+
+/// What `CdcEndpoint::poll` found.
+#[derive(Debug, PartialEq)]
+pub enum Poll {
+    Events(Vec<CdcEvent>),
+    /// The subscription fell behind its window and was cancelled, the
+    /// consumer resumes from the returned checkpoint.
+    Cancelled(ResumeToken),
+}
+
+struct Subscription {
+    region_id: u64,
+    // Pending plus unacknowledged events never exceed this.
+    window: usize,
+    // Captured but not yet polled events.
+    buffer: VecDeque<CdcEvent>,
+    // Commit ts of polled but not yet acknowledged events.
+    in_flight: VecDeque<u64>,
+    // The highest acknowledged commit ts, events at or below it are
+    // filtered on resume.
+    resolved_ts: u64,
+    cancelled: bool,
+}
+
+/// Fans captured events out to per region subscriptions. Each
+/// subscription has a bounded in-flight window: when a consumer stops
+/// polling or acknowledging, its subscription is cancelled instead of
+/// buffering without bound inside the store.
+#[derive(Default)]
+pub struct CdcEndpoint {
+    subs: Mutex<HashMap<usize, Subscription>>,
+    next_id: AtomicUsize,
+}
+
+impl CdcEndpoint {
+    pub fn new() -> CdcEndpoint {
+        CdcEndpoint::default()
+    }
+
+    /// Subscribes to the changes of a region committed after
+    /// `resolved_ts`. Returns the subscription id.
+    pub fn subscribe(&self, region_id: u64, resolved_ts: u64, window: usize) -> usize {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        let sub = Subscription {
+            region_id: region_id,
+            window: window,
+            buffer: VecDeque::new(),
+            in_flight: VecDeque::new(),
+            resolved_ts: resolved_ts,
+            cancelled: false,
+        };
+        self.subs.lock().unwrap().insert(id, sub);
+        id
+    }
+
+    /// Re-subscribes from a checkpoint taken before a disconnect.
+    pub fn resume(&self, token: ResumeToken, window: usize) -> usize {
+        self.subscribe(token.region_id, token.resolved_ts, window)
+    }
+
+    pub fn unsubscribe(&self, id: usize) {
+        self.subs.lock().unwrap().remove(&id);
+    }
+
+    /// Takes up to `max` pending events. The events count against the
+    /// in-flight window until they are acknowledged. Returns `None` for
+    /// an unknown subscription.
+    pub fn poll(&self, id: usize, max: usize) -> Option<Poll> {
+        let mut subs = self.subs.lock().unwrap();
+        let token = {
+            let sub = subs.get_mut(&id)?;
+            if !sub.cancelled {
+                let n = ::std::cmp::min(max, sub.buffer.len());
+                let mut events = Vec::with_capacity(n);
+                for _ in 0..n {
+                    let event = sub.buffer.pop_front().unwrap();
+                    sub.in_flight.push_back(event.commit_ts);
+                    events.push(event);
+                }
+                return Some(Poll::Events(events));
+            }
+            ResumeToken {
+                region_id: sub.region_id,
+                resolved_ts: sub.resolved_ts,
+            }
+        };
+        subs.remove(&id);
+        Some(Poll::Cancelled(token))
+    }
+
+    /// Acknowledges the oldest `count` in-flight events, freeing their
+    /// share of the window and advancing the checkpoint.
+    pub fn ack(&self, id: usize, count: usize) {
+        let mut subs = self.subs.lock().unwrap();
+        if let Some(sub) = subs.get_mut(&id) {
+            for _ in 0..count {
+                match sub.in_flight.pop_front() {
+                    Some(ts) => sub.resolved_ts = ts,
+                    None => break,
+                }
+            }
+        }
+    }
+
+    /// The current checkpoint of a subscription.
+    pub fn checkpoint(&self, id: usize) -> Option<ResumeToken> {
+        let subs = self.subs.lock().unwrap();
+        subs.get(&id).map(|sub| ResumeToken {
+            region_id: sub.region_id,
+            resolved_ts: sub.resolved_ts,
+        })
+    }
+}
+
+impl CdcSink for CdcEndpoint {
+    fn emit(&self, region_id: u64, events: Vec<CdcEvent>) {
+        let mut subs = self.subs.lock().unwrap();
+        for sub in subs.values_mut() {
+            if sub.region_id != region_id || sub.cancelled {
+                continue;
+            }
+            for event in &events {
+                // Skip what the consumer saw before its resume point.
+                if event.commit_ts <= sub.resolved_ts {
+                    continue;
+                }
+                if sub.buffer.len() + sub.in_flight.len() >= sub.window {
+                    // The consumer fell behind, cut it loose instead of
+                    // buffering without bound. It resumes from its
+                    // checkpoint and re-fetches what the buffer held.
+                    warn!(
+                        "cdc: subscription of region {} overflowed its window {}",
+                        region_id, sub.window
+                    );
+                    sub.cancelled = true;
+                    sub.buffer.clear();
+                    sub.in_flight.clear();
+                    break;
+                }
+                sub.buffer.push_back(event.clone());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use kvproto::metapb::Region;
+    use kvproto::raft_cmdpb::{CmdType, Request};
+    use rocksdb::Writable;
+    use tempdir::TempDir;
+
+    use raftstore::store::keys;
+    use storage::mvcc::{Lock, LockType, Write, WriteType};
+    use storage::types::Key;
+    use storage::{make_key, ALL_CFS, CF_LOCK, CF_WRITE};
+    use util::rocksdb::{self, new_engine};
+
+    use super::*;
+
+    #[derive(Default)]
+    struct CollectSink {
+        events: Mutex<Vec<(u64, Vec<CdcEvent>)>>,
+    }
+
+    impl CdcSink for CollectSink {
+        fn emit(&self, region_id: u64, events: Vec<CdcEvent>) {
+            self.events.lock().unwrap().push((region_id, events));
+        }
+    }
+
+    fn put_req(cf: &str, key: Vec<u8>, value: Vec<u8>) -> Request {
+        let mut req = Request::new();
+        req.set_cmd_type(CmdType::Put);
+        req.mut_put().set_cf(cf.to_owned());
+        req.mut_put().set_key(key);
+        req.mut_put().set_value(value);
+        req
+    }
+
+    fn delete_req(cf: &str, key: Vec<u8>) -> Request {
+        let mut req = Request::new();
+        req.set_cmd_type(CmdType::Delete);
+        req.mut_delete().set_cf(cf.to_owned());
+        req.mut_delete().set_key(key);
+        req
+    }
+
+    #[test]
+    fn test_cdc_old_value_capture() {
+        let dir = TempDir::new("_test_cdc_observer").unwrap();
+        let db = Arc::new(new_engine(dir.path().to_str().unwrap(), ALL_CFS, None).unwrap());
+        let sink = Arc::new(CollectSink::default());
+        let observer = CdcObserver::new(Arc::clone(&db), Arc::clone(&sink), true);
+        let mut region = Region::new();
+        region.set_id(1);
+
+        // A version of `k` committed at ts 5 sits in the db already.
+        let key = make_key(b"k");
+        let write = Write::new(WriteType::Put, 4, Some(b"v1".to_vec()));
+        let handle = rocksdb::get_cf_handle(&db, CF_WRITE).unwrap();
+        db.put_cf(
+            handle,
+            &keys::data_key(key.append_ts(5).encoded()),
+            &write.to_bytes(),
+        ).unwrap();
+
+        // Prewrite k=v2 at ts 10, the old value is read and cached.
+        let lock = Lock::new(LockType::Put, b"k".to_vec(), 10, 0, Some(b"v2".to_vec()), 0);
+        let prewrite = vec![put_req(CF_LOCK, key.encoded().clone(), lock.to_bytes())];
+        let mut ctx = ObserverContext::new(&region);
+        observer.pre_apply_query(&mut ctx, &prewrite);
+        assert!(sink.events.lock().unwrap().is_empty());
+        assert_eq!(observer.old_values.lock().unwrap().len(), 1);
+
+        // Commit at ts 11 emits the event with both values.
+        let write = Write::new(WriteType::Put, 10, Some(b"v2".to_vec()));
+        let commit = vec![
+            put_req(CF_WRITE, key.append_ts(11).encoded().clone(), write.to_bytes()),
+            delete_req(CF_LOCK, key.encoded().clone()),
+        ];
+        let mut ctx = ObserverContext::new(&region);
+        observer.pre_apply_query(&mut ctx, &commit);
+
+        let events = sink.events.lock().unwrap();
+        assert_eq!(events.len(), 1);
+        let &(region_id, ref batch) = &events[0];
+        assert_eq!(region_id, 1);
+        assert_eq!(
+            batch[0],
+            CdcEvent {
+                key: key.encoded().clone(),
+                start_ts: 10,
+                commit_ts: 11,
+                write_type: WriteType::Put,
+                value: Some(b"v2".to_vec()),
+                old_value: Some(b"v1".to_vec()),
+            }
+        );
+        assert!(observer.old_values.lock().unwrap().is_empty());
+    }
+
+    fn event(ts: u64) -> CdcEvent {
+        CdcEvent {
+            key: b"k".to_vec(),
+            start_ts: ts - 1,
+            commit_ts: ts,
+            write_type: WriteType::Put,
+            value: Some(b"v".to_vec()),
+            old_value: None,
+        }
+    }
+
+    #[test]
+    fn test_cdc_subscription_flow_control() {
+        let endpoint = CdcEndpoint::new();
+        let id = endpoint.subscribe(1, 5, 2);
+
+        // Events of other regions and events at or below the resume
+        // point are filtered.
+        endpoint.emit(1, vec![event(5), event(10)]);
+        endpoint.emit(2, vec![event(11)]);
+        match endpoint.poll(id, 10).unwrap() {
+            Poll::Events(events) => {
+                assert_eq!(events.len(), 1);
+                assert_eq!(events[0].commit_ts, 10);
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        // Nothing acknowledged yet, the checkpoint stays at the resume
+        // point.
+        assert_eq!(endpoint.checkpoint(id).unwrap().resolved_ts, 5);
+        endpoint.ack(id, 1);
+        assert_eq!(endpoint.checkpoint(id).unwrap().resolved_ts, 10);
+
+        // The third event does not fit the window of 2, the subscription
+        // is cancelled and hands out its checkpoint.
+        endpoint.emit(1, vec![event(20), event(30), event(40)]);
+        let token = match endpoint.poll(id, 10).unwrap() {
+            Poll::Cancelled(token) => token,
+            other => panic!("unexpected {:?}", other),
+        };
+        assert_eq!(
+            token,
+            ResumeToken {
+                region_id: 1,
+                resolved_ts: 10,
+            }
+        );
+        assert!(endpoint.poll(id, 10).is_none());
+
+        // Resuming picks up right after the checkpoint.
+        let id = endpoint.resume(token, 10);
+        endpoint.emit(1, vec![event(20)]);
+        match endpoint.poll(id, 10).unwrap() {
+            Poll::Events(events) => {
+                assert_eq!(events.len(), 1);
+                assert_eq!(events[0].commit_ts, 20);
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        endpoint.unsubscribe(id);
+        assert!(endpoint.poll(id, 10).is_none());
+    }
+}
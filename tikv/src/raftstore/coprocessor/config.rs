@@ -22,6 +22,11 @@ pub struct Config {
     /// that region crosses tables.
     pub split_region_on_table: bool,
 
+    /// When it is true, it will split a region at keyspace boundaries, so
+    /// a region never spans two keyspaces. Only meaningful when keyspaces
+    /// are enabled in the storage configuration.
+    pub split_region_on_keyspace: bool,
+
     /// When region [a, b) size meets region_max_size, it will be split
     /// into two region into [a, c), [c, b). And the size of [a, c) will
     /// be region_split_size (or a little bit smaller).
@@ -37,6 +42,7 @@ impl Default for Config {
         let split_size = ReadableSize::mb(SPLIT_SIZE_MB);
         Config {
             split_region_on_table: true,
+            split_region_on_keyspace: false,
             region_split_size: split_size,
             region_max_size: split_size / 2 * 3,
         }
@@ -138,6 +138,12 @@ impl CoprocessorHost {
                 Box::new(TableCheckObserver::default()),
             );
         }
+        if cfg.split_region_on_keyspace {
+            registry.register_split_check_observer(
+                KEYSPACE_CHECK_OBSERVER_PRIORITY,
+                Box::new(KeySpaceCheckObserver::default()),
+            );
+        }
         CoprocessorHost { registry: registry }
     }
 
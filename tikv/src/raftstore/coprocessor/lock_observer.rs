@@ -0,0 +1,194 @@
+// Copyright 2018 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Lock observation for green GC.
+//!
+//! A GC round normally scans the whole lock CF to find locks below the
+//! safe point. With the observer registered, every lock written while a
+//! round is active is recorded as it goes through apply, so the round
+//! only has to resolve the observed locks and the physical scan can be
+//! skipped. Observation only covers locks written while it is active:
+//! the first round after enabling it still has to scan, later rounds can
+//! go green as long as the observer stayed clean in between.
+
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use kvproto::raft_cmdpb::{CmdType, Request};
+
+use storage::mvcc::Lock;
+use storage::CF_LOCK;
+
+use super::{Coprocessor, ObserverContext, QueryObserver};
+
+pub const LOCK_OBSERVER_PRIORITY: u32 = 400;
+
+/// An observation that collected more than this is marked dirty, the GC
+/// round falls back to scanning instead of eating unbounded memory.
+const MAX_COLLECTED_LOCKS: usize = 128 * 1024;
+
+/// The observation state, shared between the registered observer and the
+/// GC driver.
+pub struct LockObserverState {
+    // The safe point of the active round, 0 while observation is off.
+    safe_point: AtomicUsize,
+    collected: Mutex<Vec<(Vec<u8>, Lock)>>,
+    clean: AtomicBool,
+}
+
+impl Default for LockObserverState {
+    fn default() -> LockObserverState {
+        LockObserverState {
+            safe_point: AtomicUsize::new(0),
+            collected: Mutex::new(Vec::new()),
+            clean: AtomicBool::new(true),
+        }
+    }
+}
+
+impl LockObserverState {
+    /// Starts observing locks at or below `safe_point`, dropping
+    /// whatever a previous round left behind.
+    pub fn start(&self, safe_point: u64) {
+        let mut collected = self.collected.lock().unwrap();
+        collected.clear();
+        self.clean.store(true, Ordering::Release);
+        self.safe_point.store(safe_point as usize, Ordering::Release);
+    }
+
+    /// Stops observing. Returns the observed locks, keyed by their
+    /// encoded user key, and whether the observation is complete. An
+    /// incomplete (dirty) observation means locks may have been missed
+    /// and the caller has to scan the lock CF instead.
+    pub fn stop(&self) -> (Vec<(Vec<u8>, Lock)>, bool) {
+        self.safe_point.store(0, Ordering::Release);
+        let mut collected = self.collected.lock().unwrap();
+        let locks = ::std::mem::replace(&mut *collected, Vec::new());
+        (locks, self.clean.load(Ordering::Acquire))
+    }
+
+    pub fn is_clean(&self) -> bool {
+        self.clean.load(Ordering::Acquire)
+    }
+
+    fn observe(&self, key: &[u8], value: &[u8]) {
+        let safe_point = self.safe_point.load(Ordering::Acquire) as u64;
+        if safe_point == 0 {
+            return;
+        }
+        let lock = match Lock::parse(value) {
+            Ok(lock) => lock,
+            Err(e) => {
+                warn!("lock observer: parse lock at {:?}: {:?}", key, e);
+                self.clean.store(false, Ordering::Release);
+                return;
+            }
+        };
+        if lock.ts > safe_point {
+            return;
+        }
+        let mut collected = self.collected.lock().unwrap();
+        if collected.len() >= MAX_COLLECTED_LOCKS {
+            warn!(
+                "lock observer: collected {} locks, marking dirty",
+                collected.len()
+            );
+            self.clean.store(false, Ordering::Release);
+            return;
+        }
+        collected.push((key.to_vec(), lock));
+    }
+}
+
+/// Records lock CF writes below the safe point of the active GC round.
+pub struct LockObserver {
+    state: Arc<LockObserverState>,
+}
+
+impl LockObserver {
+    pub fn new(state: Arc<LockObserverState>) -> LockObserver {
+        LockObserver { state: state }
+    }
+}
+
+impl Coprocessor for LockObserver {}
+
+impl QueryObserver for LockObserver {
+    fn pre_apply_query(&self, _: &mut ObserverContext, reqs: &[Request]) {
+        for req in reqs {
+            if req.get_cmd_type() != CmdType::Put {
+                continue;
+            }
+            let put = req.get_put();
+            if put.get_cf() == CF_LOCK {
+                self.state.observe(put.get_key(), put.get_value());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use kvproto::metapb::Region;
+    use kvproto::raft_cmdpb::{CmdType, Request};
+
+    use storage::make_key;
+    use storage::mvcc::LockType;
+
+    use super::*;
+
+    fn lock_req(key: &[u8], ts: u64) -> Request {
+        let lock = Lock::new(LockType::Put, key.to_vec(), ts, 0, None, 0);
+        let mut req = Request::new();
+        req.set_cmd_type(CmdType::Put);
+        req.mut_put().set_cf(CF_LOCK.to_owned());
+        req.mut_put().set_key(make_key(key).encoded().clone());
+        req.mut_put().set_value(lock.to_bytes());
+        req
+    }
+
+    #[test]
+    fn test_lock_observer() {
+        let state = Arc::new(LockObserverState::default());
+        let observer = LockObserver::new(Arc::clone(&state));
+        let region = Region::new();
+
+        // Nothing is recorded while observation is off.
+        let mut ctx = ObserverContext::new(&region);
+        observer.pre_apply_query(&mut ctx, &[lock_req(b"a", 5)]);
+        assert_eq!(state.stop().0.len(), 0);
+
+        // Only locks at or below the safe point are recorded.
+        state.start(10);
+        let mut ctx = ObserverContext::new(&region);
+        observer.pre_apply_query(&mut ctx, &[lock_req(b"a", 5), lock_req(b"b", 15)]);
+        assert!(state.is_clean());
+        let (locks, clean) = state.stop();
+        assert!(clean);
+        assert_eq!(locks.len(), 1);
+        assert_eq!(locks[0].0, make_key(b"a").encoded().clone());
+        assert_eq!(locks[0].1.ts, 5);
+
+        // A corrupted lock marks the observation dirty.
+        state.start(10);
+        let mut req = lock_req(b"a", 5);
+        req.mut_put().set_value(b"garbage".to_vec());
+        let mut ctx = ObserverContext::new(&region);
+        observer.pre_apply_query(&mut ctx, &[req]);
+        let (locks, clean) = state.stop();
+        assert!(!clean);
+        assert!(locks.is_empty());
+    }
+}
@@ -17,18 +17,25 @@ use kvproto::raft_cmdpb::{AdminRequest, AdminResponse, Request, Response};
 use kvproto::metapb::Region;
 use protobuf::RepeatedField;
 
+pub mod cdc;
 pub mod dispatcher;
+pub mod lock_observer;
 pub mod split_observer;
 pub mod config;
 mod error;
 mod metrics;
 mod split_check;
 
+pub use self::cdc::{CdcEndpoint, CdcEvent, CdcObserver, CdcSink, Poll as CdcPoll, ResumeToken,
+                    CDC_OBSERVER_PRIORITY};
 pub use self::config::Config;
 pub use self::dispatcher::{CoprocessorHost, Registry};
+pub use self::lock_observer::{LockObserver, LockObserverState, LOCK_OBSERVER_PRIORITY};
 pub use self::error::{Error, Result};
-pub use self::split_check::{SizeCheckObserver, Status as SplitCheckStatus, TableCheckObserver,
-                            SIZE_CHECK_OBSERVER_PRIORITY, TABLE_CHECK_OBSERVER_PRIORITY};
+pub use self::split_check::{KeySpaceCheckObserver, SizeCheckObserver,
+                            Status as SplitCheckStatus, TableCheckObserver,
+                            KEYSPACE_CHECK_OBSERVER_PRIORITY, SIZE_CHECK_OBSERVER_PRIORITY,
+                            TABLE_CHECK_OBSERVER_PRIORITY};
 
 /// Coprocessor is used to provide a convient way to inject code to
 /// KV processing.
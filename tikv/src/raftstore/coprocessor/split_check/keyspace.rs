@@ -0,0 +1,79 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use rocksdb::DB;
+
+use storage::types::KEYSPACE_PREFIX_LEN;
+use raftstore::store::keys;
+
+use super::super::{Coprocessor, ObserverContext, SplitCheckObserver};
+use super::Status;
+
+#[derive(Default)]
+pub struct KeySpaceStatus {
+    // The keyspace prefix of the last checked key.
+    current_prefix: Option<Vec<u8>>,
+}
+
+/// `KeySpaceCheckObserver` splits a region as soon as its data enters a new
+/// keyspace, so a region never spans two keyspaces.
+///
+/// The keyspace id is the first `KEYSPACE_PREFIX_LEN` bytes of a raw key,
+/// and `encode_bytes` keeps the first group of bytes in place, so comparing
+/// the leading bytes works for both raw and transactional keys.
+#[derive(Default)]
+pub struct KeySpaceCheckObserver;
+
+impl Coprocessor for KeySpaceCheckObserver {}
+
+impl SplitCheckObserver for KeySpaceCheckObserver {
+    fn new_split_check_status(&self, _: &mut ObserverContext, status: &mut Status, _: &DB) {
+        status.keyspace = Some(KeySpaceStatus::default());
+    }
+
+    fn on_split_check(
+        &self,
+        _: &mut ObserverContext,
+        status: &mut Status,
+        key: &[u8],
+        _: u64,
+    ) -> Option<Vec<u8>> {
+        if let Some(ref mut keyspace_status) = status.keyspace {
+            check_key(keyspace_status, key)
+        } else {
+            None
+        }
+    }
+}
+
+/// Feed keys in order to find the split key.
+/// Returns the data key of the keyspace prefix of `current_data_key` when
+/// it enters a keyspace different from the last checked key.
+fn check_key(status: &mut KeySpaceStatus, current_data_key: &[u8]) -> Option<Vec<u8>> {
+    let current_key = keys::origin_key(current_data_key);
+    if current_key.len() < KEYSPACE_PREFIX_LEN {
+        return None;
+    }
+    let prefix = current_key[..KEYSPACE_PREFIX_LEN].to_vec();
+    match status.current_prefix.take() {
+        Some(last_prefix) => {
+            let crossed = last_prefix != prefix;
+            status.current_prefix = Some(prefix.clone());
+            if crossed {
+                return Some(keys::data_key(&prefix));
+            }
+        }
+        None => status.current_prefix = Some(prefix),
+    }
+    None
+}
@@ -13,7 +13,9 @@
 
 mod table;
 mod size;
+mod keyspace;
 
+use self::keyspace::KeySpaceStatus;
 use self::size::SizeStatus;
 use self::table::TableStatus;
 
@@ -23,6 +25,9 @@ pub use self::table::TableCheckObserver;
 // TableCheckObserver has higher priority than TableCheckObserver.
 // Note that higher means less.
 pub const TABLE_CHECK_OBSERVER_PRIORITY: u32 = SIZE_CHECK_OBSERVER_PRIORITY - 1;
+pub use self::keyspace::KeySpaceCheckObserver;
+// A keyspace boundary beats any other split key.
+pub const KEYSPACE_CHECK_OBSERVER_PRIORITY: u32 = SIZE_CHECK_OBSERVER_PRIORITY - 2;
 
 #[derive(Default)]
 pub struct Status {
@@ -30,10 +35,12 @@ pub struct Status {
     table: Option<TableStatus>,
     // For SizeCheckObserver
     size: Option<SizeStatus>,
+    // For KeySpaceCheckObserver
+    keyspace: Option<KeySpaceStatus>,
 }
 
 impl Status {
     pub fn skip(&self) -> bool {
-        self.table.is_none() && self.size.is_none()
+        self.table.is_none() && self.size.is_none() && self.keyspace.is_none()
     }
 }
@@ -19,6 +19,7 @@ use std::net;
 use protobuf::{ProtobufError, RepeatedField};
 
 use util::codec;
+use util::error_code::{self, ErrorCode, ErrorCodeExt};
 use pd;
 use raft;
 use kvproto::{errorpb, metapb};
@@ -145,10 +146,41 @@ quick_error!{
 
 pub type Result<T> = result::Result<T, Error>;
 
+impl ErrorCodeExt for Error {
+    fn error_code(&self) -> ErrorCode {
+        match *self {
+            Error::RaftEntryTooLarge(..) => error_code::raftstore::RAFT_ENTRY_TOO_LARGE,
+            Error::StoreNotMatch(..) => error_code::raftstore::STORE_NOT_MATCH,
+            Error::RegionNotFound(_) => error_code::raftstore::REGION_NOT_FOUND,
+            Error::RegionNotInitialized(_) => error_code::raftstore::REGION_NOT_INITIALIZED,
+            Error::NotLeader(..) => error_code::raftstore::NOT_LEADER,
+            Error::KeyNotInRegion(..) => error_code::raftstore::KEY_NOT_IN_REGION,
+            Error::Other(_) => error_code::raftstore::UNKNOWN,
+            Error::Io(_) => error_code::raftstore::IO,
+            Error::RocksDb(_) => error_code::raftstore::ROCKSDB,
+            Error::Protobuf(_) => error_code::raftstore::PROTOBUF,
+            Error::Codec(_) => error_code::raftstore::CODEC,
+            Error::AddrParse(_) => error_code::raftstore::ADDR_PARSE,
+            Error::Pd(_) => error_code::raftstore::PD,
+            Error::Raft(_) => error_code::raftstore::RAFT,
+            Error::Timeout(_) => error_code::raftstore::TIMEOUT,
+            Error::StaleEpoch(..) => error_code::raftstore::STALE_EPOCH,
+            Error::StaleCommand => error_code::raftstore::STALE_COMMAND,
+            Error::Coprocessor(_) => error_code::raftstore::COPROCESSOR,
+            Error::Transport(_) => error_code::raftstore::TRANSPORT,
+            Error::Snapshot(_) => error_code::raftstore::SNAPSHOT,
+        }
+    }
+}
+
 impl Into<errorpb::Error> for Error {
     fn into(self) -> errorpb::Error {
         let mut errorpb = errorpb::Error::new();
-        errorpb.set_message(error::Error::description(&self).to_owned());
+        errorpb.set_message(format!(
+            "[{}] {}",
+            self.error_code(),
+            error::Error::description(&self)
+        ));
 
         match self {
             Error::RegionNotFound(region_id) => {
@@ -192,6 +224,15 @@ impl Into<errorpb::Error> for Error {
                 server_is_busy_err.set_reason(RAFTSTORE_IS_BUSY.to_owned());
                 errorpb.set_server_is_busy(server_is_busy_err);
             }
+            Error::Transport(transport::Error::Full(cap)) => {
+                let mut server_is_busy_err = errorpb::ServerIsBusy::new();
+                server_is_busy_err.set_reason(format!(
+                    "{}, mailbox is full (capacity {})",
+                    RAFTSTORE_IS_BUSY,
+                    cap
+                ));
+                errorpb.set_server_is_busy(server_is_busy_err);
+            }
             _ => {}
         };
 
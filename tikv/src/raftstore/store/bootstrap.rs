@@ -16,11 +16,11 @@ use kvproto::raft_serverpb::{RegionLocalState, StoreIdent};
 use kvproto::metapb;
 use raftstore::Result;
 use super::keys;
-use super::engine::{Iterable, Mutable};
+use super::engine::{Iterable, Mutable, Peekable};
 use super::peer_storage::{write_initial_apply_state, write_initial_raft_state};
 use super::store::Engines;
 use util::rocksdb;
-use storage::{CF_DEFAULT, CF_RAFT};
+use storage::{CF_APPLY, CF_DEFAULT, CF_RAFT};
 
 const INIT_EPOCH_VER: u64 = 1;
 const INIT_EPOCH_CONF_VER: u64 = 1;
@@ -83,6 +83,33 @@ pub fn write_prepare_bootstrap(engines: &Engines, region: &metapb::Region) -> Re
     Ok(())
 }
 
+// Recover a prepared but unconfirmed bootstrap after a restart. A crash
+// between the kv and raft engine writes in `write_prepare_bootstrap` leaves
+// the first region half written, rewriting the meta rolls it forward: the
+// initial states are deterministic, so the write is idempotent.
+pub fn recover_prepare_bootstrap(
+    engines: &Engines,
+    store_id: u64,
+) -> Result<Option<metapb::Region>> {
+    let region = match engines
+        .kv_engine
+        .get_msg::<metapb::Region>(keys::PREPARE_BOOTSTRAP_KEY)?
+    {
+        Some(region) => region,
+        None => return Ok(None),
+    };
+    if region.get_peers().len() != 1 || region.get_peers()[0].get_store_id() != store_id {
+        return Err(box_err!(
+            "prepared bootstrap region {:?} doesn't belong to store {}",
+            region,
+            store_id
+        ));
+    }
+    info!("recover prepared bootstrap of region {:?}", region);
+    write_prepare_bootstrap(engines, &region)?;
+    Ok(Some(region))
+}
+
 // Clear first region meta and prepare state.
 pub fn clear_prepare_bootstrap(engines: &Engines, region_id: u64) -> Result<()> {
     engines
@@ -95,7 +122,8 @@ pub fn clear_prepare_bootstrap(engines: &Engines, region_id: u64) -> Result<()>
     // should clear raft initial state too.
     let handle = rocksdb::get_cf_handle(&engines.kv_engine, CF_RAFT)?;
     wb.delete_cf(handle, &keys::region_state_key(region_id))?;
-    wb.delete_cf(handle, &keys::apply_state_key(region_id))?;
+    let apply_handle = rocksdb::get_cf_handle(&engines.kv_engine, CF_APPLY)?;
+    wb.delete_cf(apply_handle, &keys::apply_state_key(region_id))?;
     engines.kv_engine.write(wb)?;
     engines.kv_engine.sync_wal()?;
     Ok(())
@@ -148,7 +176,11 @@ mod tests {
         let path = TempDir::new("var").unwrap();
         let raft_path = path.path().join("raft");
         let kv_engine = Arc::new(
-            rocksdb::new_engine(path.path().to_str().unwrap(), &[CF_DEFAULT, CF_RAFT], None)
+            rocksdb::new_engine(
+                path.path().to_str().unwrap(),
+                &[CF_DEFAULT, CF_RAFT, CF_APPLY],
+                None,
+            )
                 .unwrap(),
         );
         let raft_engine = Arc::new(
@@ -174,7 +206,7 @@ mod tests {
         );
         assert!(
             kv_engine
-                .get_value_cf(CF_RAFT, &keys::apply_state_key(1))
+                .get_value_cf(CF_APPLY, &keys::apply_state_key(1))
                 .unwrap()
                 .is_some()
         );
@@ -195,6 +227,14 @@ mod tests {
                 &keys::region_meta_prefix(2)
             ).unwrap()
         );
+        assert!(
+            is_range_empty(
+                &kv_engine,
+                CF_APPLY,
+                &keys::region_raft_prefix(1),
+                &keys::region_raft_prefix(2)
+            ).unwrap()
+        );
         assert!(
             is_range_empty(
                 &raft_engine,
@@ -204,4 +244,44 @@ mod tests {
             ).unwrap()
         );
     }
+
+    #[test]
+    fn test_recover_prepare_bootstrap() {
+        let path = TempDir::new("var").unwrap();
+        let raft_path = path.path().join("raft");
+        let kv_engine = Arc::new(
+            rocksdb::new_engine(
+                path.path().to_str().unwrap(),
+                &[CF_DEFAULT, CF_RAFT, CF_APPLY],
+                None,
+            )
+                .unwrap(),
+        );
+        let raft_engine = Arc::new(
+            rocksdb::new_engine(raft_path.to_str().unwrap(), &[CF_DEFAULT], None).unwrap(),
+        );
+        let engines = Engines::new(Arc::clone(&kv_engine), Arc::clone(&raft_engine));
+
+        bootstrap_store(&engines, 1, 1).unwrap();
+        assert!(recover_prepare_bootstrap(&engines, 1).unwrap().is_none());
+
+        let region = prepare_bootstrap(&engines, 1, 1, 1).unwrap();
+        // Simulate a crash between the kv and raft engine writes.
+        raft_engine.delete(&keys::raft_state_key(1)).unwrap();
+
+        let recovered = recover_prepare_bootstrap(&engines, 1).unwrap().unwrap();
+        assert_eq!(recovered, region);
+        assert!(
+            raft_engine
+                .get_value(&keys::raft_state_key(1))
+                .unwrap()
+                .is_some()
+        );
+
+        // The prepared region must belong to this store.
+        assert!(recover_prepare_bootstrap(&engines, 2).is_err());
+
+        clear_prepare_bootstrap(&engines, 1).unwrap();
+        assert!(recover_prepare_bootstrap(&engines, 1).unwrap().is_none());
+    }
 }
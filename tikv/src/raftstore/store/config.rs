@@ -24,6 +24,11 @@ use util::config::{ReadableDuration, ReadableSize};
 pub struct Config {
     // true for high reliability, prevent data loss when power failure.
     pub sync_log: bool,
+    // Experimental. Skip the kv WAL when applying committed entries and
+    // recover the kv engine from the raft log on restart. Requires
+    // rocksdb.enable-atomic-flush so all kv cfs recover to a consistent
+    // apply index.
+    pub disable_kv_wal: bool,
     pub raftdb_path: String,
 
     // store capacity. 0 means no limit.
@@ -37,6 +42,12 @@ pub struct Config {
     pub raft_max_inflight_msgs: usize,
     // When the entry exceed the max size, reject to propose it.
     pub raft_entry_max_size: ReadableSize,
+    // When the messages or bytes queued in the connection to a follower's
+    // store exceed these windows, replication to that follower is paused
+    // until the queue drains, so one congested follower doesn't balloon
+    // the leader's memory. 0 means no limit.
+    pub raft_inflight_msgs_window: u64,
+    pub raft_inflight_bytes_window: ReadableSize,
 
     // Interval to gc unnecessary raft log (ms).
     pub raft_log_gc_tick_interval: ReadableDuration,
@@ -58,6 +69,17 @@ pub struct Config {
     /// When delete keys of a region exceeds the size, a compaction will
     /// be started.
     pub region_compact_delete_keys_count: u64,
+    /// When it is true, a region whose read/write load keeps exceeding
+    /// region-max-load-qps or region-max-load-bytes is split at an
+    /// approximate median key of the recent accesses, so a small but
+    /// hot region can be broken up and rescheduled.
+    pub split_region_on_load: bool,
+    /// Requests per second a region may serve before it is split by
+    /// load. 0 disables the QPS check.
+    pub region_max_load_qps: u64,
+    /// Bytes per second a region may serve before it is split by load.
+    /// 0 disables the byte rate check.
+    pub region_max_load_bytes: ReadableSize,
     pub pd_heartbeat_tick_interval: ReadableDuration,
     pub pd_store_heartbeat_tick_interval: ReadableDuration,
     pub snap_mgr_gc_tick_interval: ReadableDuration,
@@ -85,6 +107,23 @@ pub struct Config {
     // Interval (ms) to check region whether the data is consistent.
     pub consistency_check_interval: ReadableDuration,
 
+    // A region checked within this interval is skipped when picking
+    // candidates, so on demand checks don't starve the rotation and a
+    // small store isn't hashed over and over.
+    pub consistency_check_min_interval: ReadableDuration,
+
+    // How many regions may be scheduled for a consistency check per
+    // tick on this store.
+    pub consistency_check_batch: usize,
+
+    // Rate limit for the consistency check hash scan, shared by all
+    // checks on this store. 0 means unlimited.
+    pub consistency_check_io_limit: ReadableSize,
+
+    // Interval (ms) to check that region_ranges and region_peers agree with
+    // each other. 0 disables the check.
+    pub region_meta_check_interval: ReadableDuration,
+
     pub report_region_flow_interval: ReadableDuration,
 
     // The lease provided by a successfully proposed and applied entry.
@@ -97,6 +136,14 @@ pub struct Config {
 
     pub use_delete_range: bool,
 
+    // CPUs the raftstore thread is pinned to (linux only). Empty means
+    // no pinning. Keeping raftstore on the socket of its NIC and disks
+    // avoids cross-NUMA scheduling.
+    pub cpu_set: Vec<usize>,
+    // CPUs the apply worker is pinned to (linux only). Empty means it
+    // inherits cpu-set.
+    pub apply_cpu_set: Vec<usize>,
+
     // Deprecated! These two configuration has been moved to Coprocessor.
     // They are preserved for compatibility check.
     #[doc(hidden)]
@@ -112,6 +159,7 @@ impl Default for Config {
         let split_size = ReadableSize::mb(coprocessor::config::SPLIT_SIZE_MB);
         Config {
             sync_log: true,
+            disable_kv_wal: false,
             raftdb_path: String::new(),
             capacity: ReadableSize(0),
             raft_base_tick_interval: ReadableDuration::secs(1),
@@ -120,6 +168,8 @@ impl Default for Config {
             raft_max_size_per_msg: ReadableSize::mb(1),
             raft_max_inflight_msgs: 256,
             raft_entry_max_size: ReadableSize::mb(8),
+            raft_inflight_msgs_window: 10240,
+            raft_inflight_bytes_window: ReadableSize::mb(256),
             raft_log_gc_tick_interval: ReadableDuration::secs(10),
             raft_log_gc_threshold: 50,
             // Assume the average size of entries is 1k.
@@ -130,6 +180,9 @@ impl Default for Config {
             // Disable manual compaction by default.
             region_compact_check_interval: ReadableDuration::secs(0),
             region_compact_delete_keys_count: 1_000_000,
+            split_region_on_load: false,
+            region_max_load_qps: 3000,
+            region_max_load_bytes: ReadableSize::mb(30),
             pd_heartbeat_tick_interval: ReadableDuration::minutes(1),
             pd_store_heartbeat_tick_interval: ReadableDuration::secs(10),
             notify_capacity: 40960,
@@ -145,11 +198,17 @@ impl Default for Config {
             // Disable consistency check by default as it will hurt performance.
             // We should turn on this only in our tests.
             consistency_check_interval: ReadableDuration::secs(0),
+            consistency_check_min_interval: ReadableDuration::minutes(10),
+            consistency_check_batch: 1,
+            consistency_check_io_limit: ReadableSize(0),
+            region_meta_check_interval: ReadableDuration::minutes(10),
             report_region_flow_interval: ReadableDuration::minutes(1),
             raft_store_max_leader_lease: ReadableDuration::secs(9),
             right_derive_when_split: true,
             allow_remove_leader: false,
             use_delete_range: false,
+            cpu_set: Vec::new(),
+            apply_cpu_set: Vec::new(),
 
             // They are preserved for compatibility check.
             region_max_size: ReadableSize(0),
@@ -196,6 +255,18 @@ impl Config {
             return Err(box_err!("raft log gc size limit should large than 0."));
         }
 
+        if self.consistency_check_batch == 0 {
+            return Err(box_err!("consistency check batch must greater than 0"));
+        }
+
+        if self.split_region_on_load && self.region_max_load_qps == 0
+            && self.region_max_load_bytes.0 == 0
+        {
+            return Err(box_err!(
+                "split region on load needs a qps or byte rate threshold"
+            ));
+        }
+
         let election_timeout =
             self.raft_base_tick_interval.as_millis() * self.raft_election_timeout_ticks as u64;
         let lease = self.raft_store_max_leader_lease.as_millis() as u64;
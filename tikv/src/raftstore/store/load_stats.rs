@@ -0,0 +1,210 @@
+// Copyright 2018 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Tracks per-region read/write load for load based splitting.
+//!
+//! Size based splitting never breaks up a small but hot region. Every
+//! leader peer keeps a `RegionLoadStats` that counts the requests and
+//! bytes it proposes and keeps a reservoir sample of the accessed keys.
+//! On the split check tick the store compares the rates since the last
+//! reset against the configured thresholds, and a hot region is split
+//! at an approximate median of the sampled keys.
+
+use kvproto::raft_cmdpb::{CmdType, RaftCmdRequest};
+use rand::{self, Rng};
+
+use util::time::{duration_to_ms, Instant};
+
+/// How many keys are sampled per region.
+const KEY_SAMPLE_SIZE: usize = 20;
+/// How many samples are needed before a median is trusted.
+const MIN_SAMPLE_COUNT: usize = 8;
+
+/// A hint about one hot region, reported along with the store
+/// heartbeat so leaders of the hottest regions can be transferred
+/// away from an overloaded store.
+#[derive(Clone, Debug)]
+pub struct HotRegion {
+    pub region_id: u64,
+    pub qps: u64,
+    pub byte_rate: u64,
+}
+
+/// Read/write load of one region since the last reset.
+pub struct RegionLoadStats {
+    reads: u64,
+    read_bytes: u64,
+    writes: u64,
+    written_bytes: u64,
+    key_sample: Vec<Vec<u8>>,
+    sampled_keys: u64,
+    last_reset: Instant,
+}
+
+impl RegionLoadStats {
+    pub fn new() -> RegionLoadStats {
+        RegionLoadStats {
+            reads: 0,
+            read_bytes: 0,
+            writes: 0,
+            written_bytes: 0,
+            key_sample: Vec::with_capacity(KEY_SAMPLE_SIZE),
+            sampled_keys: 0,
+            last_reset: Instant::now_coarse(),
+        }
+    }
+
+    /// Records the requests of a proposed command.
+    ///
+    /// Snapshot reads and range deletes carry no key, they only count
+    /// towards the request rate.
+    pub fn record_cmd(&mut self, msg: &RaftCmdRequest) {
+        if msg.has_admin_request() {
+            return;
+        }
+        for req in msg.get_requests() {
+            match req.get_cmd_type() {
+                CmdType::Get => {
+                    let key = req.get_get().get_key();
+                    self.reads += 1;
+                    self.read_bytes += key.len() as u64;
+                    self.sample_key(key);
+                }
+                CmdType::Snap => self.reads += 1,
+                CmdType::Put => {
+                    let put = req.get_put();
+                    self.writes += 1;
+                    self.written_bytes += (put.get_key().len() + put.get_value().len()) as u64;
+                    self.sample_key(put.get_key());
+                }
+                CmdType::Delete => {
+                    let key = req.get_delete().get_key();
+                    self.writes += 1;
+                    self.written_bytes += key.len() as u64;
+                    self.sample_key(key);
+                }
+                CmdType::DeleteRange => self.writes += 1,
+                _ => {}
+            }
+        }
+    }
+
+    /// Whether the load since the last reset exceeds the thresholds.
+    /// A zero threshold is ignored.
+    pub fn exceeds(&self, max_qps: u64, max_bytes_per_sec: u64) -> bool {
+        let (qps, byte_rate) = self.rates();
+        (max_qps > 0 && qps >= max_qps) || (max_bytes_per_sec > 0 && byte_rate >= max_bytes_per_sec)
+    }
+
+    /// Request and byte rates per second since the last reset.
+    pub fn rates(&self) -> (u64, u64) {
+        let elapsed_ms = duration_to_ms(self.last_reset.elapsed());
+        if elapsed_ms == 0 {
+            return (0, 0);
+        }
+        (
+            (self.reads + self.writes) * 1000 / elapsed_ms,
+            (self.read_bytes + self.written_bytes) * 1000 / elapsed_ms,
+        )
+    }
+
+    /// An approximate median of the sampled keys, or `None` when too
+    /// few keys have been sampled to pick a sensible split point.
+    pub fn split_key(&self) -> Option<Vec<u8>> {
+        if self.key_sample.len() < MIN_SAMPLE_COUNT {
+            return None;
+        }
+        let mut sample = self.key_sample.clone();
+        sample.sort();
+        let mid = sample.len() / 2;
+        Some(sample.swap_remove(mid))
+    }
+
+    /// Starts a new measuring window.
+    pub fn reset(&mut self) {
+        self.reads = 0;
+        self.read_bytes = 0;
+        self.writes = 0;
+        self.written_bytes = 0;
+        self.key_sample.clear();
+        self.sampled_keys = 0;
+        self.last_reset = Instant::now_coarse();
+    }
+
+    fn sample_key(&mut self, key: &[u8]) {
+        self.sampled_keys += 1;
+        if self.key_sample.len() < KEY_SAMPLE_SIZE {
+            self.key_sample.push(key.to_vec());
+            return;
+        }
+        // Reservoir sampling keeps every seen key equally likely.
+        let i = rand::thread_rng().gen_range(0, self.sampled_keys);
+        if (i as usize) < KEY_SAMPLE_SIZE {
+            self.key_sample[i as usize] = key.to_vec();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::Duration;
+
+    use kvproto::raft_cmdpb::{CmdType, RaftCmdRequest, Request};
+
+    use super::*;
+
+    fn put_cmd(key: &[u8], value: &[u8]) -> RaftCmdRequest {
+        let mut req = Request::new();
+        req.set_cmd_type(CmdType::Put);
+        req.mut_put().set_key(key.to_vec());
+        req.mut_put().set_value(value.to_vec());
+        let mut cmd = RaftCmdRequest::new();
+        cmd.mut_requests().push(req);
+        cmd
+    }
+
+    #[test]
+    fn test_load_stats() {
+        let mut stats = RegionLoadStats::new();
+        // Not enough samples yet for a split key.
+        for i in 0..MIN_SAMPLE_COUNT - 1 {
+            stats.record_cmd(&put_cmd(format!("k{:04}", i).as_bytes(), b"v"));
+        }
+        assert!(stats.split_key().is_none());
+
+        for i in MIN_SAMPLE_COUNT - 1..KEY_SAMPLE_SIZE {
+            stats.record_cmd(&put_cmd(format!("k{:04}", i).as_bytes(), b"v"));
+        }
+        let key = stats.split_key().unwrap();
+        assert_eq!(key, format!("k{:04}", KEY_SAMPLE_SIZE / 2).into_bytes());
+
+        thread::sleep(Duration::from_millis(20));
+        // 20 writes of 6 bytes each in 20ms far exceed 10 qps.
+        assert!(stats.exceeds(10, 0));
+        assert!(stats.exceeds(0, 100));
+        // Zero thresholds never trigger.
+        assert!(!stats.exceeds(0, 0));
+
+        stats.reset();
+        assert!(!stats.exceeds(10, 100));
+        assert!(stats.split_key().is_none());
+
+        // Admin commands are not counted.
+        let mut cmd = put_cmd(b"k", b"v");
+        cmd.mut_admin_request();
+        stats.record_cmd(&cmd);
+        thread::sleep(Duration::from_millis(20));
+        assert!(!stats.exceeds(1, 1));
+    }
+}
@@ -79,6 +79,7 @@ impl RaftReadyMetrics {
 #[derive(Debug, Default, Clone)]
 pub struct RaftMessageMetrics {
     pub append: u64,
+    pub append_paused: u64,
     pub append_resp: u64,
     pub vote: u64,
     pub vote_resp: u64,
@@ -100,6 +101,13 @@ impl RaftMessageMetrics {
                 .unwrap();
             self.append = 0;
         }
+        if self.append_paused > 0 {
+            STORE_RAFT_SENT_MESSAGE_COUNTER_VEC
+                .with_label_values(&["append_paused"])
+                .inc_by(self.append_paused as f64)
+                .unwrap();
+            self.append_paused = 0;
+        }
         if self.append_resp > 0 {
             STORE_RAFT_SENT_MESSAGE_COUNTER_VEC
                 .with_label_values(&["append_resp"])
@@ -159,6 +167,10 @@ impl RaftMessageMetrics {
     }
 }
 
+// Log one out of this many dropped messages on paths that would otherwise
+// drop silently.
+const DROP_LOG_SAMPLE_RATIO: u64 = 64;
+
 #[derive(Debug, Default, Clone)]
 pub struct RaftMessageDropMetrics {
     pub mismatch_store_id: u64,
@@ -169,9 +181,18 @@ pub struct RaftMessageDropMetrics {
     pub region_tombstone_peer: u64,
     pub region_nonexistent: u64,
     pub applying_snap: u64,
+
+    sample_counter: u64,
 }
 
 impl RaftMessageDropMetrics {
+    /// Returns true for roughly one in `DROP_LOG_SAMPLE_RATIO` dropped
+    /// messages, so drop sites on hot paths can log a sample without
+    /// flooding the log.
+    pub fn should_sample_log(&mut self) -> bool {
+        self.sample_counter += 1;
+        self.sample_counter % DROP_LOG_SAMPLE_RATIO == 1
+    }
     fn flush(&mut self) {
         if self.mismatch_store_id > 0 {
             STORE_RAFT_DROPPED_MESSAGE_COUNTER_VEC
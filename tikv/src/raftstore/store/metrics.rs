@@ -13,6 +13,8 @@
 
 use prometheus::*;
 
+use util::metrics::latency_buckets;
+
 lazy_static! {
     pub static ref PEER_PROPOSAL_COUNTER_VEC: CounterVec =
         register_counter_vec!(
@@ -32,21 +34,21 @@ lazy_static! {
         register_histogram!(
             "tikv_raftstore_append_log_duration_seconds",
             "Bucketed histogram of peer appending log duration",
-            exponential_buckets(0.0005, 2.0, 20).unwrap()
+            latency_buckets()
         ).unwrap();
 
     pub static ref STORE_APPLY_LOG_HISTOGRAM: Histogram =
         register_histogram!(
             "tikv_raftstore_apply_log_duration_seconds",
             "Bucketed histogram of peer applying log duration",
-            exponential_buckets(0.0005, 2.0, 20).unwrap()
+            latency_buckets()
         ).unwrap();
 
     pub static ref APPLY_TASK_WAIT_TIME_HISTOGRAM: Histogram =
         register_histogram!(
             "tikv_raftstore_apply_wait_time_duration_secs",
             "Bucketed histogram of apply task wait time duration",
-            exponential_buckets(0.0005, 2.0, 20).unwrap()
+            latency_buckets()
         ).unwrap();
 
     pub static ref STORE_RAFT_READY_COUNTER_VEC: CounterVec =
@@ -96,7 +98,7 @@ lazy_static! {
             "tikv_raftstore_raft_process_duration_secs",
             "Bucketed histogram of peer processing raft duration",
             &["type"],
-            exponential_buckets(0.0005, 2.0, 20).unwrap()
+            latency_buckets()
         ).unwrap();
 
     pub static ref PEER_PROPOSE_LOG_SIZE_HISTOGRAM: Histogram =
@@ -126,7 +128,7 @@ lazy_static! {
         register_histogram!(
             "tikv_raftstore_request_wait_time_duration_secs",
             "Bucketed histogram of request wait time duration",
-            exponential_buckets(0.0005, 2.0, 20).unwrap()
+            latency_buckets()
         ).unwrap();
 
     pub static ref PEER_GC_RAFT_LOG_COUNTER: Counter =
@@ -141,6 +143,12 @@ lazy_static! {
             "Total number of update region size caused by compaction."
         ).unwrap();
 
+    pub static ref LOAD_BASE_SPLIT_COUNTER: Counter =
+        register_counter!(
+            "tikv_raftstore_load_base_split_total",
+            "Total number of load based split requests sent to PD."
+        ).unwrap();
+
     pub static ref COMPACTION_RELATED_REGION_COUNT: HistogramVec =
         register_histogram_vec!(
             "compaction_related_region_count",
@@ -177,7 +185,7 @@ lazy_static! {
         register_histogram!(
             "tikv_snapshot_build_time_duration_secs",
             "Bucketed histogram of snapshot build time duration.",
-            exponential_buckets(0.0005, 2.0, 20).unwrap()
+            latency_buckets()
         ).unwrap();
 
     pub static ref SNAPSHOT_KV_COUNT_HISTOGRAM: Histogram =
@@ -214,4 +222,10 @@ lazy_static! {
             "tikv_raftstore_leader_missing",
             "Total number of leader missed region"
         ).unwrap();
+
+    pub static ref INIT_REGION_GAUGE: Gauge =
+        register_gauge!(
+            "tikv_raftstore_init_region_count",
+            "Total number of regions loaded during store startup"
+        ).unwrap();
 }
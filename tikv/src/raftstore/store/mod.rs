@@ -21,6 +21,7 @@ pub mod cmd_resp;
 pub mod util;
 pub mod store;
 
+mod load_stats;
 mod peer;
 mod peer_storage;
 mod snap;
@@ -34,14 +35,15 @@ pub use self::msg::{BatchReadCallback, Callback, Msg, ReadCallback, ReadResponse
 pub use self::store::{create_event_loop, new_compaction_listener, Engines, Store, StoreChannel,
                       StoreStat};
 pub use self::config::Config;
+pub use self::load_stats::HotRegion;
 pub use self::transport::Transport;
 pub use self::peer::{Peer, PeerStat};
 pub use self::bootstrap::{bootstrap_store, clear_prepare_bootstrap, clear_prepare_bootstrap_state,
-                          prepare_bootstrap, write_prepare_bootstrap};
+                          prepare_bootstrap, recover_prepare_bootstrap, write_prepare_bootstrap};
 pub use self::engine::{Iterable, Mutable, Peekable};
-pub use self::peer_storage::{do_snapshot, init_apply_state, init_raft_state, write_peer_state,
-                             CacheQueryStats, PeerStorage, SnapState, RAFT_INIT_LOG_INDEX,
-                             RAFT_INIT_LOG_TERM};
+pub use self::peer_storage::{do_snapshot, init_apply_state, init_raft_state, load_apply_state,
+                             write_peer_state, CacheQueryStats, PeerStorage, SnapState,
+                             RAFT_INIT_LOG_INDEX, RAFT_INIT_LOG_TERM};
 pub use self::snap::{check_abort, copy_snapshot, ApplyOptions, Error as SnapError, SnapEntry,
                      SnapKey, SnapManager, SnapManagerBuilder, Snapshot, SnapshotDeleter,
                      SnapshotStatistics};
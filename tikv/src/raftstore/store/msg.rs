@@ -29,6 +29,9 @@ use super::RegionSnapshot;
 pub struct ReadResponse {
     pub response: RaftCmdResponse,
     pub snapshot: Option<RegionSnapshot>,
+    /// The applied index of the peer when the read was served, 0 when
+    /// the read never reached a peer.
+    pub apply_index: u64,
 }
 
 #[derive(Debug)]
@@ -65,6 +68,7 @@ impl Callback {
                 let resp = ReadResponse {
                     response: resp,
                     snapshot: None,
+                    apply_index: 0,
                 };
                 read(resp);
             }
@@ -106,6 +110,7 @@ pub enum Tick {
     SnapGc,
     CompactLockCf,
     ConsistencyCheck,
+    RegionMetaCheck,
 }
 
 #[derive(Debug, PartialEq)]
@@ -158,6 +163,13 @@ pub enum Msg {
         hash: Vec<u8>,
     },
 
+    // Triggers an on demand consistency check of one region, regardless
+    // of when it was last checked. The result is reported the same way
+    // as a scheduled check.
+    CheckConsistency {
+        region_id: u64,
+    },
+
     // For region size
     ApproximateRegionSize {
         region_id: u64,
@@ -187,6 +199,9 @@ impl fmt::Debug for Msg {
                 index,
                 escape(hash)
             ),
+            Msg::CheckConsistency { region_id } => {
+                write!(fmt, "CheckConsistency [region_id: {}]", region_id)
+            }
             Msg::SplitRegion {
                 ref region_id,
                 ref split_key,
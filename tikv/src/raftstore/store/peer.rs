@@ -38,6 +38,7 @@ use raftstore::store::worker::apply::ExecResult;
 use raftstore::store::worker::{Apply, ApplyRes, ApplyTask};
 
 use util::MustConsumeVec;
+use util::admission;
 use util::worker::{FutureWorker, Scheduler};
 use util::time::monotonic_raw_now;
 use util::collections::{FlatMap, FlatMapValues as Values, HashSet};
@@ -48,6 +49,7 @@ use super::store::{DestroyPeerJob, Store, StoreStat};
 use super::peer_storage::{write_peer_state, ApplySnapResult, InvokeContext, PeerStorage};
 use super::util::{self, Lease, LeaseState};
 use super::cmd_resp;
+use super::load_stats::RegionLoadStats;
 use super::transport::Transport;
 use super::engine::Snapshot;
 use super::metrics::*;
@@ -55,11 +57,18 @@ use super::local_metrics::{RaftMessageMetrics, RaftMetrics, RaftProposeMetrics,
 
 const TRANSFER_LEADER_ALLOW_LOG_LAG: u64 = 10;
 const DEFAULT_APPEND_WB_SIZE: usize = 4 * 1024;
+const SNAP_GEN_BACKOFF_BASE_MS: u64 = 1000;
+// Cap the backoff at SNAP_GEN_BACKOFF_BASE_MS << SNAP_GEN_BACKOFF_MAX_SHIFT.
+const SNAP_GEN_BACKOFF_MAX_SHIFT: u64 = 5;
 
 struct ReadIndexRequest {
     id: u64,
     cmds: MustConsumeVec<(RaftCmdRequest, Callback)>,
     renew_lease_time: Timespec,
+    // The commit index the read was checked against, filled in when
+    // raft hands the read state back. The read must not be served
+    // before apply reaches it.
+    read_index: Option<u64>,
 }
 
 impl ReadIndexRequest {
@@ -208,6 +217,8 @@ pub struct Peer {
     /// approximate region size.
     pub approximate_size: Option<u64>,
     pub compaction_declined_bytes: u64,
+    /// read/write load since last reset, for load based splitting.
+    pub load_stats: RegionLoadStats,
 
     pub consistency_state: ConsistencyState,
 
@@ -234,6 +245,10 @@ pub struct Peer {
     // If a snapshot is being applied asynchronously, messages should not be sent.
     pending_messages: Vec<eraftpb::Message>,
 
+    // Consecutive snapshot receive failures of each follower, used to back
+    // off snapshot regeneration instead of looping on a broken follower.
+    snap_failed_peers: FlatMap<u64, u64>,
+
     pub peer_stat: PeerStat,
 }
 
@@ -329,6 +344,7 @@ impl Peer {
             delete_keys_hint: 0,
             approximate_size: None,
             compaction_declined_bytes: 0,
+            load_stats: RegionLoadStats::new(),
             apply_scheduler: store.apply_scheduler(),
             pending_remove: false,
             marked_to_be_checked: false,
@@ -346,6 +362,7 @@ impl Peer {
             leader_lease: Lease::new(cfg.raft_store_max_leader_lease()),
             cfg: cfg,
             pending_messages: vec![],
+            snap_failed_peers: FlatMap::default(),
             peer_stat: PeerStat::default(),
         };
 
@@ -482,6 +499,33 @@ impl Peer {
         self.get_store().is_applying_snapshot()
     }
 
+    /// Report the result of sending a snapshot to `to_peer_id` to the raft
+    /// group, backing off snapshot generation exponentially while a follower
+    /// keeps failing to receive them.
+    pub fn report_snapshot_status(&mut self, to_peer_id: u64, status: SnapshotStatus) {
+        match status {
+            SnapshotStatus::Finish => {
+                self.snap_failed_peers.remove(&to_peer_id);
+            }
+            SnapshotStatus::Failure => {
+                let failures = self.snap_failed_peers
+                    .get(&to_peer_id)
+                    .cloned()
+                    .unwrap_or(0) + 1;
+                self.snap_failed_peers.insert(to_peer_id, failures);
+                let shift = cmp::min(failures - 1, SNAP_GEN_BACKOFF_MAX_SHIFT);
+                let backoff = Duration::from_millis(SNAP_GEN_BACKOFF_BASE_MS << shift);
+                warn!(
+                    "{} peer {} failed to receive snapshot {} times, back off generation \
+                     for {:?}",
+                    self.tag, to_peer_id, failures, backoff
+                );
+                self.get_store().pause_snap_gen_until(Instant::now() + backoff);
+            }
+        }
+        self.raft_group.report_snapshot(to_peer_id, status);
+    }
+
     #[inline]
     pub fn has_pending_snapshot(&self) -> bool {
         self.raft_group.get_snap().is_some()
@@ -509,6 +553,18 @@ impl Peer {
         for msg in msgs {
             let msg_type = msg.get_msg_type();
 
+            // Raft only bounds the entries in flight per follower, not what
+            // piles up in the connection to its store. When that queue
+            // grows beyond the configured windows, drop the append and put
+            // the follower into probe state, so replication pauses until
+            // the queue drains instead of ballooning the leader's memory.
+            // The dropped append is resent once the follower responds.
+            if msg_type == MessageType::MsgAppend && self.pause_follower_if_congested(&msg, trans)
+            {
+                metrics.append_paused += 1;
+                continue;
+            }
+
             self.send_raft_message(msg, trans)?;
 
             match msg_type {
@@ -537,6 +593,39 @@ impl Peer {
         Ok(())
     }
 
+    fn pause_follower_if_congested<T: Transport>(
+        &mut self,
+        msg: &eraftpb::Message,
+        trans: &T,
+    ) -> bool {
+        let msgs_window = self.cfg.raft_inflight_msgs_window;
+        let bytes_window = self.cfg.raft_inflight_bytes_window.0;
+        if msgs_window == 0 && bytes_window == 0 {
+            return false;
+        }
+        let to_peer_id = msg.get_to();
+        let to_store_id = match self.get_peer_from_cache(to_peer_id) {
+            Some(p) => p.get_store_id(),
+            None => return false,
+        };
+        let (queued_msgs, queued_bytes) = trans.queue_stats(to_store_id);
+        if (msgs_window == 0 || queued_msgs < msgs_window)
+            && (bytes_window == 0 || queued_bytes < bytes_window)
+        {
+            return false;
+        }
+        debug!(
+            "{} pause replication to {}: {} msgs and {} bytes queued for store {}",
+            self.tag,
+            to_peer_id,
+            queued_msgs,
+            queued_bytes,
+            to_store_id
+        );
+        self.raft_group.report_unreachable(to_peer_id);
+        true
+    }
+
     pub fn step(&mut self, m: eraftpb::Message) -> Result<()> {
         if self.is_leader() && m.get_from() != INVALID_ID {
             self.peer_heartbeats.insert(m.get_from(), Instant::now());
@@ -847,26 +936,17 @@ impl Peer {
 
     fn apply_reads(&mut self, ready: &Ready) {
         let mut propose_time = None;
-        if self.ready_to_handle_read() {
-            for state in &ready.read_states {
-                let mut read = self.pending_reads.reads.pop_front().unwrap();
-                assert_eq!(state.request_ctx.as_slice(), read.binary_id());
-                for (req, cb) in read.cmds.drain(..) {
-                    // TODO: we should add test case that a split happens before pending
-                    // read-index is handled. To do this we need to control async-apply
-                    // procedure precisely.
-                    cb.invoke_read(self.handle_read(req));
-                }
-                propose_time = Some(read.renew_lease_time);
-            }
-        } else {
-            for state in &ready.read_states {
-                let read = &self.pending_reads.reads[self.pending_reads.ready_cnt];
-                assert_eq!(state.request_ctx.as_slice(), read.binary_id());
-                self.pending_reads.ready_cnt += 1;
-                propose_time = Some(read.renew_lease_time);
-            }
-        }
+        for state in &ready.read_states {
+            let read = &mut self.pending_reads.reads[self.pending_reads.ready_cnt];
+            assert_eq!(state.request_ctx.as_slice(), read.binary_id());
+            // On a follower the index comes from the leader's commit
+            // index and the local apply state may still be behind it,
+            // the read is deferred until apply catches up.
+            read.read_index = Some(state.index);
+            self.pending_reads.ready_cnt += 1;
+            propose_time = Some(read.renew_lease_time);
+        }
+        self.serve_ready_reads();
 
         // Note that only after handle read_states can we identify what requests are
         // actually stale.
@@ -905,6 +985,12 @@ impl Peer {
             .advance_apply(res.apply_state.get_applied_index());
         self.mut_store().apply_state = res.apply_state.clone();
         self.mut_store().applied_index_term = res.applied_index_term;
+        // Feed admission control with how far apply is behind the
+        // committed log, a sustained gap means writes are accepted
+        // faster than they can be applied.
+        let committed = self.raft_group.raft.raft_log.committed;
+        let applied = res.apply_state.get_applied_index();
+        admission::report_apply_lag(committed.saturating_sub(applied) as usize);
         self.peer_stat.written_keys += res.metrics.written_keys;
         self.peer_stat.written_bytes += res.metrics.written_bytes;
         store_stat.engine_total_bytes_written += res.metrics.written_bytes;
@@ -923,14 +1009,35 @@ impl Peer {
             self.mark_to_be_checked(groups);
         }
 
-        if self.pending_reads.ready_cnt > 0 && self.ready_to_handle_read() {
-            for _ in 0..self.pending_reads.ready_cnt {
-                let mut read = self.pending_reads.reads.pop_front().unwrap();
-                for (req, cb) in read.cmds.drain(..) {
-                    cb.invoke_read(self.handle_read(req));
-                }
+        self.serve_ready_reads();
+    }
+
+    /// Serves the pending reads raft has already checked against a
+    /// quorum, in order, as far as the apply state allows. A read is
+    /// only served once the applied index reaches the commit index the
+    /// read was checked against, so a follower serving marked backup
+    /// or checksum scans never exposes a stale view.
+    fn serve_ready_reads(&mut self) {
+        if !self.ready_to_handle_read() {
+            return;
+        }
+        let applied = self.get_store().applied_index();
+        while self.pending_reads.ready_cnt > 0 {
+            let servable = {
+                let read = &self.pending_reads.reads[0];
+                read.read_index.map_or(false, |idx| idx <= applied)
+            };
+            if !servable {
+                return;
+            }
+            let mut read = self.pending_reads.reads.pop_front().unwrap();
+            self.pending_reads.ready_cnt -= 1;
+            for (req, cb) in read.cmds.drain(..) {
+                // TODO: we should add test case that a split happens before pending
+                // read-index is handled. To do this we need to control async-apply
+                // procedure precisely.
+                cb.invoke_read(self.handle_read(req));
             }
-            self.pending_reads.ready_cnt = 0;
         }
     }
 
@@ -1052,7 +1159,11 @@ impl Peer {
         if self.pending_remove {
             let mut response = RaftCmdResponse::new();
             cmd_resp::bind_error(&mut response, box_err!("peer is pending remove"));
-            return Some(ReadResponse { response, snapshot });
+            return Some(ReadResponse {
+                response,
+                snapshot,
+                apply_index: 0,
+            });
         }
         metrics.all += 1;
 
@@ -1069,7 +1180,11 @@ impl Peer {
             Err(e) => {
                 let mut response = cmd_resp::new_error(e);
                 cmd_resp::bind_term(&mut response, self.term());
-                Some(ReadResponse { response, snapshot })
+                Some(ReadResponse {
+                    response,
+                    snapshot,
+                    apply_index: 0,
+                })
             }
         }
     }
@@ -1242,6 +1357,26 @@ impl Peer {
         self.raft_group.transfer_leader(peer.get_id());
     }
 
+    /// Tries to hand leadership over to a follower that is allowed to be
+    /// promoted, so regions on a gracefully restarting store don't have to
+    /// wait for an election timeout. Returns true if a transfer is issued.
+    pub fn transfer_leader_on_shutdown(&mut self) -> bool {
+        if !self.is_leader() {
+            return false;
+        }
+        let candidates = self.region().get_peers().to_vec();
+        for p in &candidates {
+            if p.get_id() == self.peer_id() {
+                continue;
+            }
+            if self.is_transfer_leader_allowed(p) {
+                self.transfer_leader(p);
+                return true;
+            }
+        }
+        false
+    }
+
     fn is_transfer_leader_allowed(&self, peer: &metapb::Peer) -> bool {
         let peer_id = peer.get_id();
         let status = self.raft_group.status();
@@ -1306,6 +1441,7 @@ impl Peer {
             id: id,
             cmds: v,
             renew_lease_time: renew_lease_time,
+            read_index: None,
         });
 
         // TimeoutNow has been sent out, so we need to propose explicitly to
@@ -1350,7 +1486,7 @@ impl Peer {
         if self.next_proposal_index() == propose_index {
             // The message is dropped silently, this usually due to leader absence
             // or transferring leader. Both cases can be considered as NotLeader error.
-            return Err(Error::NotLeader(self.region_id, None));
+            return Err(Error::NotLeader(self.region_id, self.get_leader_hint()));
         }
 
         Ok(propose_index)
@@ -1427,7 +1563,7 @@ impl Peer {
         if self.next_proposal_index() == propose_index {
             // The message is dropped silently, this usually due to leader absence
             // or transferring leader. Both cases can be considered as NotLeader error.
-            return Err(Error::NotLeader(self.region_id, None));
+            return Err(Error::NotLeader(self.region_id, self.get_leader_hint()));
         }
 
         Ok(propose_index)
@@ -1442,6 +1578,7 @@ impl Peer {
             ReadResponse {
                 response: cmd_resp::new_error(e),
                 snapshot: None,
+                apply_index: 0,
             }
         });
 
@@ -1526,6 +1663,19 @@ impl Peer {
         self.peer_cache.borrow_mut().remove(&peer_id);
     }
 
+    /// Returns the latest known leader of the region, used to fill the
+    /// leader hint of `NotLeader` errors so clients can retry against the
+    /// right store in one round instead of probing.
+    pub fn get_leader_hint(&self) -> Option<metapb::Peer> {
+        let leader_id = self.leader_id();
+        if leader_id == self.peer_id() {
+            // Proposals may be dropped while this peer is still nominally
+            // the leader (e.g. during leader transfer), no better hint then.
+            return None;
+        }
+        self.get_peer_from_cache(leader_id)
+    }
+
     pub fn get_peer_from_cache(&self, peer_id: u64) -> Option<metapb::Peer> {
         if let Some(peer) = self.peer_cache.borrow().get(&peer_id) {
             return Some(peer.clone());
@@ -1620,8 +1770,7 @@ impl Peer {
             // unreachable store
             self.raft_group.report_unreachable(to_peer_id);
             if msg_type == eraftpb::MessageType::MsgSnapshot {
-                self.raft_group
-                    .report_snapshot(to_peer_id, SnapshotStatus::Failure);
+                self.report_snapshot_status(to_peer_id, SnapshotStatus::Failure);
             }
         }
 
@@ -1663,7 +1812,11 @@ impl Peer {
         } else {
             None
         };
-        Ok(ReadResponse { response, snapshot })
+        Ok(ReadResponse {
+            response,
+            snapshot,
+            apply_index: self.get_store().applied_index(),
+        })
     }
 }
 
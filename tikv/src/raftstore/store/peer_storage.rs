@@ -37,7 +37,7 @@ use super::engine::{Iterable, Mutable, Peekable, Snapshot as DbSnapshot};
 use super::peer::ReadyContext;
 use super::metrics::*;
 use super::{SnapEntry, SnapKey, SnapManager, SnapshotStatistics};
-use storage::CF_RAFT;
+use storage::{CF_APPLY, CF_RAFT};
 
 // When we create a region peer, we should initialize its log term/index > 0,
 // so that we can force the follower peer to sync the snapshot first.
@@ -265,6 +265,9 @@ pub struct PeerStorage {
     snap_state: RefCell<SnapState>,
     region_sched: Scheduler<RegionTask>,
     snap_tried_cnt: RefCell<usize>,
+    // Don't generate a new snapshot before this instant, set when a follower
+    // repeatedly fails to receive one.
+    snap_gen_paused_until: RefCell<Option<Instant>>,
 
     cache: EntryCache,
     stats: Rc<RefCell<CacheQueryStats>>,
@@ -351,7 +354,7 @@ impl InvokeContext {
 
     #[inline]
     pub fn save_apply_state_to(&self, kv_engine: &DB, kv_wb: &mut WriteBatch) -> Result<()> {
-        let handle = rocksdb::get_cf_handle(kv_engine, CF_RAFT)?;
+        let handle = rocksdb::get_cf_handle(kv_engine, CF_APPLY)?;
         kv_wb.put_msg_cf(
             handle,
             &keys::apply_state_key(self.region_id),
@@ -416,9 +419,21 @@ pub fn init_raft_state(raft_engine: &DB, region: &Region) -> Result<RaftLocalSta
     })
 }
 
+/// Load the apply state of a region. Apply states live in CF_APPLY, but stores
+/// created before the cf was split out keep them in CF_RAFT, so fall back to
+/// the legacy location on a miss. The state is rewritten to CF_APPLY on the
+/// next apply, and `clear_meta` deletes from both cfs.
+pub fn load_apply_state<T: Peekable>(engine: &T, region_id: u64) -> Result<Option<RaftApplyState>> {
+    let state_key = keys::apply_state_key(region_id);
+    match engine.get_msg_cf(CF_APPLY, &state_key)? {
+        Some(state) => Ok(Some(state)),
+        None => engine.get_msg_cf(CF_RAFT, &state_key),
+    }
+}
+
 pub fn init_apply_state(kv_engine: &DB, region: &Region) -> Result<RaftApplyState> {
     Ok(
-        match kv_engine.get_msg_cf(CF_RAFT, &keys::apply_state_key(region.get_id()))? {
+        match load_apply_state(kv_engine, region.get_id())? {
             Some(s) => s,
             None => {
                 let mut apply_state = RaftApplyState::new();
@@ -494,6 +509,7 @@ impl PeerStorage {
             snap_state: RefCell::new(SnapState::Relax),
             region_sched: region_sched,
             snap_tried_cnt: RefCell::new(0),
+            snap_gen_paused_until: RefCell::new(None),
             tag: tag,
             applied_index_term: RAFT_INIT_LOG_TERM,
             last_term: last_term,
@@ -795,6 +811,17 @@ impl PeerStorage {
             )));
         }
 
+        let mut paused_until = self.snap_gen_paused_until.borrow_mut();
+        if let Some(until) = *paused_until {
+            if Instant::now() < until {
+                debug!("{} snapshot generation is backing off", self.tag);
+                return Err(raft::Error::Store(
+                    raft::StorageError::SnapshotTemporarilyUnavailable,
+                ));
+            }
+            *paused_until = None;
+        }
+
         info!("{} requesting snapshot...", self.tag);
         *tried_cnt += 1;
         let (tx, rx) = mpsc::sync_channel(1);
@@ -816,6 +843,12 @@ impl PeerStorage {
         ))
     }
 
+    /// Pause snapshot generation until `until`. Used by the leader to back
+    /// off regeneration when a follower repeatedly fails to receive one.
+    pub fn pause_snap_gen_until(&self, until: Instant) {
+        *self.snap_gen_paused_until.borrow_mut() = Some(until);
+    }
+
     // Append the given entries to the raft log using previous last index or self.last_index.
     // Return the new last index for later update. After we commit in engine, we can set last_index
     // to the return one.
@@ -1067,10 +1100,7 @@ impl PeerStorage {
     pub fn schedule_applying_snapshot(&mut self) {
         let status = Arc::new(AtomicUsize::new(JOB_STATUS_PENDING));
         self.set_snap_state(SnapState::Applying(Arc::clone(&status)));
-        let task = RegionTask::Apply {
-            region_id: self.get_region_id(),
-            status: status,
-        };
+        let task = RegionTask::apply(self.get_region_id(), status);
         // TODO: gracefully remove region instead.
         self.region_sched
             .schedule(task)
@@ -1191,7 +1221,10 @@ pub fn clear_meta(
     let t = Instant::now();
     let handle = rocksdb::get_cf_handle(kv_engine, CF_RAFT)?;
     kv_wb.delete_cf(handle, &keys::region_state_key(region_id))?;
+    // The apply state may still live in CF_RAFT on old stores, clear both.
     kv_wb.delete_cf(handle, &keys::apply_state_key(region_id))?;
+    let apply_handle = rocksdb::get_cf_handle(kv_engine, CF_APPLY)?;
+    kv_wb.delete_cf(apply_handle, &keys::apply_state_key(region_id))?;
 
     let last_index = last_index(raft_state);
     let mut first_index = last_index + 1;
@@ -1223,16 +1256,15 @@ pub fn do_snapshot(
 ) -> raft::Result<Snapshot> {
     debug!("[region {}] begin to generate a snapshot", region_id);
 
-    let apply_state: RaftApplyState =
-        match snap.get_msg_cf(CF_RAFT, &keys::apply_state_key(region_id))? {
-            None => {
-                return Err(box_err!(
-                    "could not load raft state of region {}",
-                    region_id
-                ))
-            }
-            Some(state) => state,
-        };
+    let apply_state: RaftApplyState = match load_apply_state(snap, region_id)? {
+        None => {
+            return Err(box_err!(
+                "could not load raft state of region {}",
+                region_id
+            ))
+        }
+        Some(state) => state,
+    };
 
     let idx = apply_state.get_applied_index();
     let term = if idx == apply_state.get_truncated_state().get_index() {
@@ -1246,7 +1278,12 @@ pub fn do_snapshot(
 
     let key = SnapKey::new(region_id, term, idx);
 
-    mgr.register(key.clone(), SnapEntry::Generating);
+    if !mgr.try_register(key.clone(), SnapEntry::Generating) {
+        return Err(box_err!(
+            "too many snapshots are being generated, skip {}",
+            key
+        ));
+    }
     defer!(mgr.deregister(&key, &SnapEntry::Generating));
 
     let state: RegionLocalState = snap.get_msg_cf(CF_RAFT, &keys::region_state_key(key.region_id))
@@ -1321,7 +1358,7 @@ pub fn write_initial_apply_state<T: Mutable>(
         .mut_truncated_state()
         .set_term(RAFT_INIT_LOG_TERM);
 
-    let handle = rocksdb::get_cf_handle(kv_engine, CF_RAFT)?;
+    let handle = rocksdb::get_cf_handle(kv_engine, CF_APPLY)?;
     kv_wb.put_msg_cf(handle, &keys::apply_state_key(region_id), &apply_state)?;
     Ok(())
 }
@@ -1518,6 +1555,14 @@ mod test {
             })
             .unwrap();
 
+        store
+            .kv_engine
+            .scan_cf(CF_APPLY, &raft_start, &raft_end, false, &mut |_, _| {
+                count += 1;
+                Ok(true)
+            })
+            .unwrap();
+
         store
             .raft_engine
             .scan(&raft_start, &raft_end, false, &mut |_, _| {
@@ -151,6 +151,10 @@ impl Peekable for RegionSnapshot {
 /// `RegionIterator` wrap a rocksdb iterator and only allow it to
 /// iterate in the region. It behaves as if underlying
 /// db only contains one region.
+///
+/// The region range is handed to rocksdb as iterate_lower_bound and
+/// iterate_upper_bound, so the iterator never leaves the region and
+/// stepping doesn't need a comparison per key on the Rust side.
 pub struct RegionIterator {
     iter: DBIterator<Arc<DB>>,
     valid: bool,
@@ -221,44 +225,37 @@ impl RegionIterator {
     }
 
     pub fn seek_to_first(&mut self) -> bool {
-        self.valid = self.iter.seek(self.start_key.as_slice().into());
+        self.valid = self.iter.seek(SeekKey::Start);
 
         self.update_valid(true)
     }
 
+    // Rocksdb enforces the region range through the iterate bounds, so
+    // this only needs to forward the iterator's validity; the bound
+    // comparisons stay around as a guard in debug builds.
     #[inline]
     fn update_valid(&mut self, forward: bool) -> bool {
         if self.valid {
             let key = self.iter.key();
-            self.valid = if forward {
-                key < self.end_key.as_slice()
+            if forward {
+                debug_assert!(key < self.end_key.as_slice());
             } else {
-                key >= self.start_key.as_slice()
-            };
+                debug_assert!(key >= self.start_key.as_slice());
+            }
         }
         self.valid
     }
 
     pub fn seek_to_last(&mut self) -> bool {
-        if !self.iter.seek(self.end_key.as_slice().into()) && !self.iter.seek(SeekKey::End) {
-            self.valid = false;
-            return self.valid;
-        }
-
-        while self.iter.key() >= self.end_key.as_slice() && self.iter.prev() {}
+        self.valid = self.iter.seek(SeekKey::End);
 
-        self.valid = self.iter.valid();
         self.update_valid(false)
     }
 
     pub fn seek(&mut self, key: &[u8]) -> Result<bool> {
         self.should_seekable(key)?;
         let key = keys::data_key(key);
-        if key == self.end_key {
-            self.valid = false;
-        } else {
-            self.valid = self.iter.seek(key.as_slice().into());
-        }
+        self.valid = self.iter.seek(key.as_slice().into());
 
         Ok(self.update_valid(true))
     }
@@ -267,9 +264,7 @@ impl RegionIterator {
         self.should_seekable(key)?;
         let key = keys::data_key(key);
         self.valid = self.iter.seek_for_prev(key.as_slice().into());
-        if self.valid && self.iter.key() == self.end_key.as_slice() {
-            self.valid = self.iter.prev();
-        }
+
         Ok(self.update_valid(false))
     }
 
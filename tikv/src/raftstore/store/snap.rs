@@ -1072,6 +1072,9 @@ pub enum SnapEntry {
 pub struct SnapStats {
     pub sending_count: usize,
     pub receiving_count: usize,
+    /// Number of registrations rejected by the concurrency limits since
+    /// the last time stats were collected.
+    pub rejected_count: usize,
 }
 
 struct SnapManagerCore {
@@ -1081,6 +1084,21 @@ struct SnapManagerCore {
     snap_size: Arc<RwLock<u64>>,
 }
 
+fn register_locked(core: &mut SnapManagerCore, key: SnapKey, entry: SnapEntry) {
+    match core.registry.entry(key) {
+        Entry::Occupied(mut e) => {
+            if e.get().contains(&entry) {
+                warn!("{} is registered more than 1 time!!!", e.key());
+                return;
+            }
+            e.get_mut().push(entry);
+        }
+        Entry::Vacant(e) => {
+            e.insert(vec![entry]);
+        }
+    }
+}
+
 fn notify_stats(ch: Option<&SendCh<Msg>>) {
     if let Some(ch) = ch {
         if let Err(e) = ch.try_send(Msg::SnapshotStats) {
@@ -1097,6 +1115,11 @@ pub struct SnapManager {
     ch: Option<SendCh<Msg>>,
     limiter: Option<Arc<IOLimiter>>,
     max_total_size: u64,
+    // Max number of snapshots in each stage, 0 means no limit.
+    max_concurrent_gen: usize,
+    max_concurrent_send: usize,
+    max_concurrent_apply: usize,
+    rejected_count: Arc<AtomicUsize>,
 }
 
 impl SnapManager {
@@ -1300,20 +1323,40 @@ impl SnapManager {
     pub fn register(&self, key: SnapKey, entry: SnapEntry) {
         debug!("register [key: {}, entry: {:?}]", key, entry);
         let mut core = self.core.wl();
-        match core.registry.entry(key) {
-            Entry::Occupied(mut e) => {
-                if e.get().contains(&entry) {
-                    warn!("{} is registered more than 1 time!!!", e.key());
-                    return;
-                }
-                e.get_mut().push(entry);
-            }
-            Entry::Vacant(e) => {
-                e.insert(vec![entry]);
+        register_locked(&mut core, key, entry);
+        notify_stats(self.ch.as_ref());
+    }
+
+    /// Same as `register`, but rejects the registration when the number of
+    /// snapshots in the same stage has reached the configured limit. Returns
+    /// false on rejection.
+    pub fn try_register(&self, key: SnapKey, entry: SnapEntry) -> bool {
+        let limit = match entry {
+            SnapEntry::Generating => self.max_concurrent_gen,
+            SnapEntry::Sending => self.max_concurrent_send,
+            SnapEntry::Applying => self.max_concurrent_apply,
+            SnapEntry::Receiving => 0,
+        };
+        let mut core = self.core.wl();
+        if limit > 0 {
+            let count = core.registry
+                .values()
+                .filter(|entries| entries.contains(&entry))
+                .count();
+            if count >= limit {
+                info!(
+                    "reject to register [key: {}, entry: {:?}], {} snapshots are already \
+                     in this stage",
+                    key, entry, count
+                );
+                self.rejected_count.fetch_add(1, Ordering::SeqCst);
+                return false;
             }
         }
-
+        debug!("register [key: {}, entry: {:?}]", key, entry);
+        register_locked(&mut core, key, entry);
         notify_stats(self.ch.as_ref());
+        true
     }
 
     pub fn deregister(&self, key: &SnapKey, entry: &SnapEntry) {
@@ -1360,6 +1403,7 @@ impl SnapManager {
         SnapStats {
             sending_count: sending_cnt,
             receiving_count: receiving_cnt,
+            rejected_count: self.rejected_count.swap(0, Ordering::SeqCst),
         }
     }
 }
@@ -1392,6 +1436,9 @@ impl SnapshotDeleter for SnapManager {
 pub struct SnapManagerBuilder {
     max_write_bytes_per_sec: u64,
     max_total_size: u64,
+    max_concurrent_gen: usize,
+    max_concurrent_send: usize,
+    max_concurrent_apply: usize,
 }
 
 impl SnapManagerBuilder {
@@ -1403,6 +1450,18 @@ impl SnapManagerBuilder {
         self.max_total_size = bytes;
         self
     }
+    pub fn max_concurrent_gen(&mut self, limit: usize) -> &mut SnapManagerBuilder {
+        self.max_concurrent_gen = limit;
+        self
+    }
+    pub fn max_concurrent_send(&mut self, limit: usize) -> &mut SnapManagerBuilder {
+        self.max_concurrent_send = limit;
+        self
+    }
+    pub fn max_concurrent_apply(&mut self, limit: usize) -> &mut SnapManagerBuilder {
+        self.max_concurrent_apply = limit;
+        self
+    }
     pub fn build<T: Into<String>>(&self, path: T, ch: Option<SendCh<Msg>>) -> SnapManager {
         let limiter = if self.max_write_bytes_per_sec > 0 {
             Some(Arc::new(IOLimiter::new(self.max_write_bytes_per_sec)))
@@ -1423,6 +1482,10 @@ impl SnapManagerBuilder {
             ch: ch,
             limiter: limiter,
             max_total_size: max_total_size,
+            max_concurrent_gen: self.max_concurrent_gen,
+            max_concurrent_send: self.max_concurrent_send,
+            max_concurrent_apply: self.max_concurrent_apply,
+            rejected_count: Arc::new(AtomicUsize::new(0)),
         }
     }
 }
@@ -1446,7 +1509,7 @@ mod test {
     use kvproto::raft_serverpb::{RaftApplyState, RaftSnapshotData, RegionLocalState, SnapshotMeta};
     use rocksdb::DB;
 
-    use storage::{ALL_CFS, CF_DEFAULT, CF_LOCK, CF_RAFT, CF_WRITE};
+    use storage::{ALL_CFS, CF_APPLY, CF_DEFAULT, CF_LOCK, CF_RAFT, CF_WRITE};
     use util::{rocksdb, HandyRwLock};
     use raftstore::Result;
     use raftstore::store::keys;
@@ -1497,7 +1560,7 @@ mod test {
             let mut apply_state = RaftApplyState::new();
             apply_state.set_applied_index(10);
             apply_state.mut_truncated_state().set_index(9);
-            let handle = rocksdb::get_cf_handle(&kv, CF_RAFT)?;
+            let handle = rocksdb::get_cf_handle(&kv, CF_APPLY)?;
             kv.put_msg_cf(handle, &keys::apply_state_key(region_id), &apply_state)?;
 
             // Put region info into kv engine.
@@ -15,16 +15,17 @@ use std::sync::Arc;
 use std::sync::mpsc::{self, Receiver as StdReceiver, TryRecvError};
 use std::rc::Rc;
 use std::cell::RefCell;
-use std::collections::BTreeMap;
-use std::collections::Bound::{Excluded, Included, Unbounded};
+use std::collections::VecDeque;
+use std::ops::Deref;
 use std::time::{Duration, Instant};
 use std::thread;
 use std::u64;
 
-use rocksdb::{CompactionJobInfo, WriteBatch, DB};
+use rocksdb::{CompactionJobInfo, Writable, WriteBatch, DB};
 use rocksdb::rocksdb_options::WriteOptions;
 use mio::{self, EventLoop, EventLoopConfig, Sender};
 use protobuf;
+use rand::{self, Rng};
 use time::{self, Timespec};
 
 use kvproto::raft_serverpb::{PeerState, RaftMessage, RaftSnapshotData, RaftTruncatedState,
@@ -32,20 +33,22 @@ use kvproto::raft_serverpb::{PeerState, RaftMessage, RaftSnapshotData, RaftTrunc
 use kvproto::eraftpb::{ConfChangeType, MessageType};
 use kvproto::pdpb::StoreStats;
 use util::{escape, rocksdb};
+use util::slow_log::Subsystem;
 use util::time::{duration_to_sec, SlowTimer};
 use pd::{PdClient, PdRunner, PdTask};
-use kvproto::raft_cmdpb::{AdminCmdType, AdminRequest, RaftCmdRequest, RaftCmdResponse,
-                          StatusCmdType, StatusResponse};
-use protobuf::Message;
+use kvproto::raft_cmdpb::{AdminCmdType, AdminRequest, CmdType, RaftCmdRequest,
+                          RaftCmdResponse, StatusCmdType, StatusResponse};
+use protobuf::{CodedInputStream, CodedOutputStream, Message};
 use raft::{self, SnapshotStatus, INVALID_INDEX};
 use raftstore::{Error, Result};
 use kvproto::metapb;
 use util::worker::{FutureWorker, Scheduler, Stopped, Worker};
 use util::transport::SendCh;
 use util::RingQueue;
-use util::collections::{HashMap, HashSet};
-use util::rocksdb::{CompactedEvent, CompactionListener};
+use util::collections::{HashMap, HashSet, RangeMap};
+use util::rocksdb::{CompactedEvent, CompactionListener, RegionBoundaries};
 use util::sys as util_sys;
+use util::threadpool::{DefaultContext, ThreadPoolBuilder};
 use storage::{CF_DEFAULT, CF_LOCK, CF_RAFT, CF_WRITE};
 use raftstore::coprocessor::CoprocessorHost;
 use raftstore::coprocessor::split_observer::SplitObserver;
@@ -57,6 +60,7 @@ use super::{util, Msg, SignificantMsg, SnapKey, SnapManager, SnapshotDeleter, Ti
 use super::keys::{self, data_end_key, data_key, enc_end_key, enc_start_key};
 use super::engine::{Iterable, Peekable, Snapshot as EngineSnapshot};
 use super::config::Config;
+use super::load_stats::HotRegion;
 use super::peer::{self, ConsistencyState, Peer, ReadyContext, StaleState};
 use super::peer_storage::{self, ApplySnapResult, CacheQueryStats};
 use super::msg::{Callback, ReadResponse};
@@ -69,6 +73,67 @@ type Key = Vec<u8>;
 
 const MIO_TICK_RATIO: u64 = 10;
 const PENDING_VOTES_CAP: usize = 20;
+const TOMBSTONE_CACHE_CAP: usize = 4096;
+// How many hot region hints are attached to a store heartbeat.
+const HOT_REGION_REPORT_COUNT: usize = 8;
+// Jitter of the raft base tick delay, in percent of the tick interval.
+const RAFT_TICK_JITTER_PCT: u64 = 20;
+// Rate limit of leader transfers during graceful shutdown.
+const SHUTDOWN_TRANSFER_LEADER_BATCH: usize = 128;
+const SHUTDOWN_TRANSFER_LEADER_PAUSE_MS: u64 = 100;
+const SHUTDOWN_TRANSFER_LEADER_TIMEOUT_SECS: u64 = 5;
+const INIT_PARSE_POOL_SIZE: usize = 4;
+const INIT_PARSE_CHUNK_SIZE: usize = 1024;
+const INIT_PROGRESS_REGION_COUNT: u64 = 16384;
+
+/// Parse raw region states on a thread pool. The returned states keep the
+/// scan order of the input values.
+fn parse_region_states(values: Vec<Vec<u8>>) -> Result<Vec<RegionLocalState>> {
+    if values.len() <= INIT_PARSE_CHUNK_SIZE {
+        return values
+            .iter()
+            .map(|v| {
+                protobuf::parse_from_bytes::<RegionLocalState>(v).map_err(Error::from)
+            })
+            .collect();
+    }
+
+    let mut pool = ThreadPoolBuilder::with_default_factory(thd_name!("region loader"))
+        .thread_count(INIT_PARSE_POOL_SIZE)
+        .build();
+    let (tx, rx) = mpsc::channel();
+    let mut chunk_count = 0;
+    let mut iter = values.into_iter().peekable();
+    while iter.peek().is_some() {
+        let chunk: Vec<_> = iter.by_ref().take(INIT_PARSE_CHUNK_SIZE).collect();
+        let tx = tx.clone();
+        let idx = chunk_count;
+        chunk_count += 1;
+        pool.execute(move |_: &mut DefaultContext| {
+            let states: ::std::result::Result<Vec<_>, _> = chunk
+                .iter()
+                .map(|v| protobuf::parse_from_bytes::<RegionLocalState>(v))
+                .collect();
+            // The receiver is kept until all chunks are collected, so send
+            // can't fail here.
+            tx.send((idx, states)).unwrap();
+        });
+    }
+
+    let mut chunks = Vec::with_capacity(chunk_count);
+    for _ in 0..chunk_count {
+        chunks.push(rx.recv().unwrap());
+    }
+    if let Err(e) = pool.stop() {
+        warn!("failed to stop region loader pool: {:?}", e);
+    }
+    chunks.sort_by_key(|&(idx, _)| idx);
+    let mut states = Vec::with_capacity(chunk_count * INIT_PARSE_CHUNK_SIZE);
+    for (_, chunk) in chunks {
+        states.extend(chunk?);
+    }
+    Ok(states)
+}
 
 #[derive(Clone)]
 pub struct Engines {
@@ -126,6 +191,42 @@ pub struct StoreInfo {
     pub capacity: u64,
 }
 
+/// Map from data-encoded region end key to region id, which also mirrors its
+/// keys into the shared set read by the compaction guard, see
+/// `util::rocksdb::compaction_guard`. All reads go through `Deref`, writes
+/// must go through `insert` and `remove` to keep the mirror in sync.
+struct RegionRanges {
+    map: RangeMap,
+    boundaries: RegionBoundaries,
+}
+
+impl RegionRanges {
+    fn new(boundaries: RegionBoundaries) -> RegionRanges {
+        RegionRanges {
+            map: RangeMap::new(),
+            boundaries: boundaries,
+        }
+    }
+
+    fn insert(&mut self, end_key: Key, region_id: u64) -> Option<u64> {
+        self.boundaries.insert(end_key.clone());
+        self.map.insert(end_key, region_id)
+    }
+
+    fn remove(&mut self, end_key: &[u8]) -> Option<u64> {
+        self.boundaries.remove(end_key);
+        self.map.remove(end_key)
+    }
+}
+
+impl Deref for RegionRanges {
+    type Target = RangeMap;
+
+    fn deref(&self) -> &RangeMap {
+        &self.map
+    }
+}
+
 pub struct Store<T, C: 'static> {
     cfg: Rc<Config>,
     kv_engine: Arc<DB>,
@@ -139,7 +240,7 @@ pub struct Store<T, C: 'static> {
     region_peers: HashMap<u64, Peer>,
     pending_raft_groups: HashSet<u64>,
     // region end key -> region id
-    region_ranges: BTreeMap<Key, u64>,
+    region_ranges: RegionRanges,
     // the regions with pending snapshots between two mio ticks.
     pending_snapshot_regions: Vec<metapb::Region>,
     split_check_worker: Worker<SplitCheckTask>,
@@ -165,9 +266,18 @@ pub struct Store<T, C: 'static> {
 
     start_time: Timespec,
     is_busy: bool,
+    // stretch the next raft base tick to shed load when the event loop
+    // is overloaded.
+    coalesce_raft_tick: bool,
 
     pending_votes: RingQueue<RaftMessage>,
 
+    // the region state of recently destroyed peers, used to reject stale
+    // messages without reading the tombstone record from the kv engine.
+    // region id -> region at destroy time, and its insertion order.
+    tombstone_cache: HashMap<u64, metapb::Region>,
+    tombstone_cache_queue: VecDeque<u64>,
+
     store_stat: StoreStat,
 }
 
@@ -197,11 +307,13 @@ impl<T, C> Store<T, C> {
         mgr: SnapManager,
         pd_worker: FutureWorker<PdTask>,
         mut coprocessor_host: CoprocessorHost,
+        region_boundaries: RegionBoundaries,
     ) -> Result<Store<T, C>> {
         // TODO: we can get cluster meta regularly too later.
         cfg.validate()?;
 
-        let sendch = SendCh::new(ch.sender, "raftstore");
+        let mut sendch = SendCh::new(ch.sender, "raftstore");
+        sendch.set_capacity(cfg.notify_capacity);
         let tag = format!("[store {}]", meta.get_id());
 
         // TODO load coprocessors from configuration
@@ -226,7 +338,7 @@ impl<T, C> Store<T, C> {
             consistency_check_worker: Worker::new("consistency check worker"),
             apply_worker: Worker::new("apply worker"),
             apply_res_receiver: None,
-            region_ranges: BTreeMap::new(),
+            region_ranges: RegionRanges::new(region_boundaries),
             pending_snapshot_regions: vec![],
             trans: trans,
             pd_client: pd_client,
@@ -235,9 +347,12 @@ impl<T, C> Store<T, C> {
             raft_metrics: RaftMetrics::default(),
             entry_cache_metries: Rc::new(RefCell::new(CacheQueryStats::default())),
             pending_votes: RingQueue::with_capacity(PENDING_VOTES_CAP),
+            tombstone_cache: HashMap::default(),
+            tombstone_cache_queue: VecDeque::with_capacity(TOMBSTONE_CACHE_CAP),
             tag: tag,
             start_time: time::get_time(),
             is_busy: false,
+            coalesce_raft_tick: false,
             store_stat: StoreStat::default(),
         };
         s.init()?;
@@ -257,19 +372,48 @@ impl<T, C> Store<T, C> {
         let mut applying_count = 0;
 
         let t = Instant::now();
+
+        let local_states = match self.load_region_checkpoint()? {
+            Some(states) => {
+                info!(
+                    "{} loads {} regions from checkpoint, takes {:?}",
+                    self.tag,
+                    states.len(),
+                    t.elapsed()
+                );
+                states
+            }
+            None => {
+                // Collect raw region states first so they can be parsed on the init
+                // pool, then create peers from the parsed states. `Peer` is not
+                // `Send`, so peer creation itself has to stay on the store thread.
+                let mut region_states = vec![];
+                kv_engine.scan_cf(CF_RAFT, start_key, end_key, false, &mut |key, value| {
+                    let (_, suffix) = keys::decode_region_meta_key(key)?;
+                    if suffix != keys::REGION_STATE_SUFFIX {
+                        return Ok(true);
+                    }
+                    region_states.push(value.to_vec());
+                    Ok(true)
+                })?;
+                info!(
+                    "{} scans {} region states, takes {:?}",
+                    self.tag,
+                    region_states.len(),
+                    t.elapsed()
+                );
+                parse_region_states(region_states)?
+            }
+        };
+
         let mut kv_wb = WriteBatch::new();
         let mut raft_wb = WriteBatch::new();
         let mut applying_regions = vec![];
-        kv_engine.scan_cf(CF_RAFT, start_key, end_key, false, &mut |key, value| {
-            let (region_id, suffix) = keys::decode_region_meta_key(key)?;
-            if suffix != keys::REGION_STATE_SUFFIX {
-                return Ok(true);
-            }
-
+        for local_state in &local_states {
             total_count += 1;
 
-            let local_state = protobuf::parse_from_bytes::<RegionLocalState>(value)?;
             let region = local_state.get_region();
+            let region_id = region.get_id();
             if local_state.get_state() == PeerState::Tombstone {
                 tomebstone_count += 1;
                 debug!(
@@ -278,7 +422,7 @@ impl<T, C> Store<T, C> {
                     self.store_id()
                 );
                 self.clear_stale_meta(&mut kv_wb, &mut raft_wb, region);
-                return Ok(true);
+                continue;
             }
             if local_state.get_state() == PeerState::Applying {
                 // in case of restart happen when we just write region state to Applying,
@@ -291,7 +435,7 @@ impl<T, C> Store<T, C> {
                 )?;
                 applying_count += 1;
                 applying_regions.push(region.clone());
-                return Ok(true);
+                continue;
             }
 
             let peer = Peer::create(self, region)?;
@@ -299,8 +443,17 @@ impl<T, C> Store<T, C> {
             // No need to check duplicated here, because we use region id as the key
             // in DB.
             self.region_peers.insert(region_id, peer);
-            Ok(true)
-        })?;
+            INIT_REGION_GAUGE.inc();
+            if total_count % INIT_PROGRESS_REGION_COUNT == 0 {
+                info!(
+                    "{} has loaded {}/{} regions, takes {:?}",
+                    self.tag,
+                    total_count,
+                    local_states.len(),
+                    t.elapsed()
+                );
+            }
+        }
 
         if !kv_wb.is_empty() {
             self.kv_engine.write(kv_wb).unwrap();
@@ -340,6 +493,81 @@ impl<T, C> Store<T, C> {
         Ok(())
     }
 
+    /// Load the region checkpoint written on last graceful shutdown. The
+    /// checkpoint is deleted before it is used, so a later restart can never
+    /// replay a stale one after the engine has been written again.
+    fn load_region_checkpoint(&self) -> Result<Option<Vec<RegionLocalState>>> {
+        let value = match self.kv_engine
+            .get_value_cf(CF_RAFT, keys::REGION_CHECKPOINT_KEY)?
+        {
+            Some(value) => value,
+            None => return Ok(None),
+        };
+        let handle = box_try!(rocksdb::get_cf_handle(&self.kv_engine, CF_RAFT));
+        box_try!(self.kv_engine.delete_cf(handle, keys::REGION_CHECKPOINT_KEY));
+        box_try!(self.kv_engine.sync_wal());
+
+        let mut states = vec![];
+        let mut is = CodedInputStream::from_bytes(&value[..]);
+        while !box_try!(is.eof()) {
+            states.push(protobuf::parse_length_delimited_from::<RegionLocalState>(
+                &mut is,
+            )?);
+        }
+        Ok(Some(states))
+    }
+
+    /// Persist a compact checkpoint of all region states so the next start
+    /// can skip the full region meta scan. Failure is not fatal, the next
+    /// start just falls back to the scan.
+    fn save_region_checkpoint(&self) {
+        if self.region_peers
+            .values()
+            .any(|p| p.is_applying_snapshot())
+        {
+            info!(
+                "{} some region is still applying snapshot, skip region checkpoint",
+                self.tag
+            );
+            return;
+        }
+
+        let mut buf = Vec::with_capacity(self.region_peers.len() * 256);
+        {
+            let mut os = CodedOutputStream::vec(&mut buf);
+            for peer in self.region_peers.values() {
+                let mut state = RegionLocalState::new();
+                state.set_region(peer.region().clone());
+                if let Err(e) = state.write_length_delimited_to(&mut os) {
+                    warn!("{} failed to encode region checkpoint: {:?}", self.tag, e);
+                    return;
+                }
+            }
+            if let Err(e) = os.flush() {
+                warn!("{} failed to encode region checkpoint: {:?}", self.tag, e);
+                return;
+            }
+        }
+
+        let t = Instant::now();
+        if let Err(e) = rocksdb::get_cf_handle(&self.kv_engine, CF_RAFT)
+            .and_then(|handle| {
+                self.kv_engine
+                    .put_cf(handle, keys::REGION_CHECKPOINT_KEY, &buf)
+            })
+            .and_then(|_| self.kv_engine.sync_wal())
+        {
+            warn!("{} failed to save region checkpoint: {:?}", self.tag, e);
+            return;
+        }
+        info!(
+            "{} saves region checkpoint for {} regions, takes {:?}",
+            self.tag,
+            self.region_peers.len(),
+            t.elapsed()
+        );
+    }
+
     fn clear_stale_meta(
         &mut self,
         kv_wb: &mut WriteBatch,
@@ -478,7 +706,7 @@ impl<T, C> Store<T, C> {
                 "[region {}] report snapshot status {:?} {:?}",
                 region_id, to_peer, status
             );
-            peer.raft_group.report_snapshot(to_peer_id, status)
+            peer.report_snapshot_status(to_peer_id, status)
         }
     }
 }
@@ -487,6 +715,14 @@ impl<T: Transport, C: PdClient> Store<T, C> {
     pub fn run(&mut self, event_loop: &mut EventLoop<Self>) -> Result<()> {
         self.snap_mgr.init()?;
 
+        if !self.cfg.cpu_set.is_empty() {
+            // Pinned before the workers start so they all inherit the
+            // mask; the apply worker gets its own mask below.
+            if let Err(e) = util_sys::cpuset::set_affinity(&self.cfg.cpu_set) {
+                warn!("set cpu affinity for raftstore failed, error: {:?}", e);
+            }
+        }
+
         self.register_raft_base_tick(event_loop);
         self.register_raft_gc_log_tick(event_loop);
         self.register_split_region_check_tick(event_loop);
@@ -496,6 +732,7 @@ impl<T: Transport, C: PdClient> Store<T, C> {
         self.register_snap_mgr_gc_tick(event_loop);
         self.register_compact_lock_cf_tick(event_loop);
         self.register_consistency_check_tick(event_loop);
+        self.register_region_meta_check_tick(event_loop);
 
         let split_check_runner = SplitCheckRunner::new(
             Arc::clone(&self.kv_engine),
@@ -514,7 +751,14 @@ impl<T: Transport, C: PdClient> Store<T, C> {
         );
         box_try!(self.region_worker.start(runner));
 
-        let raftlog_gc_runner = RaftlogGcRunner::new(None);
+        let raftlog_gc_runner = RaftlogGcRunner::new(
+            if self.cfg.disable_kv_wal {
+                Some(Arc::clone(&self.kv_engine))
+            } else {
+                None
+            },
+            None,
+        );
         box_try!(self.raftlog_gc_worker.start(raftlog_gc_runner));
 
         let compact_runner = CompactRunner::new(Arc::clone(&self.kv_engine));
@@ -525,19 +769,47 @@ impl<T: Transport, C: PdClient> Store<T, C> {
             Arc::clone(&self.pd_client),
             self.sendch.clone(),
             Arc::clone(&self.kv_engine),
+            self.pd_worker.scheduler(),
         );
         box_try!(self.pd_worker.start(pd_runner));
 
-        let consistency_check_runner = ConsistencyCheckRunner::new(self.sendch.clone());
+        let consistency_check_runner = ConsistencyCheckRunner::new(
+            self.sendch.clone(),
+            self.cfg.consistency_check_io_limit.0,
+        );
         box_try!(
             self.consistency_check_worker
                 .start(consistency_check_runner)
         );
 
         let (tx, rx) = mpsc::channel();
-        let apply_runner = ApplyRunner::new(self, tx, self.cfg.sync_log, self.cfg.use_delete_range);
+        let apply_runner = ApplyRunner::new(
+            self,
+            tx,
+            self.cfg.sync_log,
+            self.cfg.disable_kv_wal,
+            self.cfg.use_delete_range,
+        );
         self.apply_res_receiver = Some(rx);
+
+        // The apply worker inherits the affinity of this thread at spawn,
+        // so its mask is narrowed around the `start` call and widened
+        // back afterwards.
+        let saved_mask = if self.cfg.apply_cpu_set.is_empty() {
+            None
+        } else {
+            let saved = util_sys::cpuset::get_affinity().ok();
+            if let Err(e) = util_sys::cpuset::set_affinity(&self.cfg.apply_cpu_set) {
+                warn!("set cpu affinity for apply worker failed, error: {:?}", e);
+            }
+            saved
+        };
         box_try!(self.apply_worker.start(apply_runner));
+        if let Some(mask) = saved_mask {
+            if let Err(e) = util_sys::cpuset::set_affinity(&mask) {
+                warn!("restore cpu affinity failed, error: {:?}", e);
+            }
+        }
 
         if let Err(e) = util_sys::pri::set_priority(util_sys::HIGH_PRI) {
             warn!("set priority for raftstore failed, error: {:?}", e);
@@ -547,6 +819,56 @@ impl<T: Transport, C: PdClient> Store<T, C> {
         Ok(())
     }
 
+    /// Proactively transfers leadership of regions on this store to healthy
+    /// followers before shutting down, so a restart doesn't leave every
+    /// region leaderless until an election timeout. Transfers are issued in
+    /// rate limited batches and the whole drain is bounded by a deadline.
+    fn drain_leaders_on_shutdown(&mut self) {
+        let t = Instant::now();
+        let deadline = Duration::from_secs(SHUTDOWN_TRANSFER_LEADER_TIMEOUT_SECS);
+        let leaders: Vec<u64> = self.region_peers
+            .iter()
+            .filter(|&(_, p)| p.is_leader())
+            .map(|(&region_id, _)| region_id)
+            .collect();
+        if leaders.is_empty() {
+            return;
+        }
+        info!(
+            "{} try to transfer leadership of {} regions before shutdown",
+            self.tag,
+            leaders.len()
+        );
+
+        let mut transferred = 0;
+        for chunk in leaders.chunks(SHUTDOWN_TRANSFER_LEADER_BATCH) {
+            if t.elapsed() >= deadline {
+                warn!(
+                    "{} leader drain times out after {:?}, stop transferring",
+                    self.tag,
+                    t.elapsed()
+                );
+                break;
+            }
+            for region_id in chunk {
+                let peer = self.region_peers.get_mut(region_id).unwrap();
+                if peer.transfer_leader_on_shutdown() {
+                    peer.mark_to_be_checked(&mut self.pending_raft_groups);
+                    transferred += 1;
+                }
+            }
+            // Flush the transfer messages out before issuing the next batch.
+            self.on_raft_ready();
+            thread::sleep(Duration::from_millis(SHUTDOWN_TRANSFER_LEADER_PAUSE_MS));
+        }
+        info!(
+            "{} transferred leadership of {} regions in {:?}",
+            self.tag,
+            transferred,
+            t.elapsed()
+        );
+    }
+
     fn stop(&mut self) {
         info!("start to stop raftstore.");
 
@@ -573,17 +895,27 @@ impl<T: Transport, C: PdClient> Store<T, C> {
 
         self.coprocessor_host.shutdown();
 
+        self.save_region_checkpoint();
+
         info!("stop raftstore finished.");
     }
 
-    fn register_raft_base_tick(&self, event_loop: &mut EventLoop<Self>) {
+    fn register_raft_base_tick(&mut self, event_loop: &mut EventLoop<Self>) {
+        let base = self.cfg.raft_base_tick_interval.as_millis();
+        // Jitter the tick delay a bit, so that after a long stall stores
+        // don't process all their regions' (randomized) election timeouts
+        // in lockstep and flood the cluster with synchronized campaigns.
+        let jitter = base * RAFT_TICK_JITTER_PCT / 100;
+        let mut delay = base - jitter / 2 + rand::thread_rng().gen_range(0, jitter + 1);
+        if self.coalesce_raft_tick {
+            // Last ready processing took longer than an election timeout,
+            // stretch one tick to let the event loop catch up.
+            delay *= 2;
+            self.coalesce_raft_tick = false;
+        }
         // If we register raft base tick failed, the whole raft can't run correctly,
         // TODO: shutdown the store?
-        if let Err(e) = register_timer(
-            event_loop,
-            Tick::Raft,
-            self.cfg.raft_base_tick_interval.as_millis(),
-        ) {
+        if let Err(e) = register_timer(event_loop, Tick::Raft, delay) {
             error!("{} register raft base tick err: {:?}", self.tag, e);
         };
     }
@@ -699,6 +1031,16 @@ impl<T: Transport, C: PdClient> Store<T, C> {
                 job = p.maybe_destroy();
                 if job.is_none() {
                     self.raft_metrics.message_dropped.applying_snap += 1;
+                    if self.raft_metrics.message_dropped.should_sample_log() {
+                        info!(
+                            "[region {}] drop {:?} for target peer {}: stale peer {} \
+                             is applying snapshot",
+                            region_id,
+                            msg.get_message().get_msg_type(),
+                            target_peer_id,
+                            p.peer_id()
+                        );
+                    }
                     return Ok(false);
                 }
             } else if p.peer_id() > target_peer_id {
@@ -733,26 +1075,40 @@ impl<T: Transport, C: PdClient> Store<T, C> {
         if msg_type != MessageType::MsgRequestVote
             && (msg_type != MessageType::MsgHeartbeat || message.get_commit() != INVALID_INDEX)
         {
-            debug!(
-                "target peer {:?} doesn't exist, stale message {:?}.",
-                target, msg_type
-            );
             self.raft_metrics.message_dropped.stale_msg += 1;
+            if self.raft_metrics.message_dropped.should_sample_log() {
+                info!(
+                    "[region {}] drop {:?} from peer {} [epoch: {:?}]: target peer \
+                     {:?} doesn't exist",
+                    region_id,
+                    msg_type,
+                    msg.get_from_peer().get_id(),
+                    msg.get_region_epoch(),
+                    target
+                );
+            }
             return Ok(false);
         }
 
         let start_key = data_key(msg.get_start_key());
-        if let Some((_, &exist_region_id)) = self.region_ranges
-            .range((Excluded(start_key), Unbounded::<Key>))
-            .next()
-        {
+        if let Some((_, exist_region_id)) = self.region_ranges.next_after(&start_key) {
             let exist_region = self.region_peers[&exist_region_id].region();
             if enc_start_key(exist_region) < data_end_key(msg.get_end_key()) {
-                debug!("msg {:?} is overlapped with region {:?}", msg, exist_region);
                 if util::is_first_vote_msg(msg) {
                     self.pending_votes.push(msg.to_owned());
                 }
                 self.raft_metrics.message_dropped.region_overlap += 1;
+                if self.raft_metrics.message_dropped.should_sample_log() {
+                    info!(
+                        "[region {}] drop {:?} from peer {} [epoch: {:?}]: range is \
+                         overlapped with region {:?}",
+                        region_id,
+                        msg.get_message().get_msg_type(),
+                        msg.get_from_peer().get_id(),
+                        msg.get_region_epoch(),
+                        exist_region
+                    );
+                }
                 return Ok(false);
             }
         }
@@ -883,6 +1239,13 @@ impl<T: Transport, C: PdClient> Store<T, C> {
             return Ok(false);
         }
 
+        // The peer doesn't exist, check with the tombstone state of recently
+        // destroyed peers first, so most stale messages can be rejected
+        // without reading the tombstone record from the kv engine.
+        if let Some(region) = self.tombstone_cache.get(&region_id) {
+            return Self::check_tombstone_msg(trans, msg, region, raft_metrics);
+        }
+
         // no exist, check with tombstone key.
         let state_key = keys::region_state_key(region_id);
         if let Some(local_state) = self.kv_engine
@@ -905,37 +1268,55 @@ impl<T: Transport, C: PdClient> Store<T, C> {
                     local_state
                 ));
             }
-            let region = local_state.get_region();
-            let region_epoch = region.get_region_epoch();
-            // The region in this peer is already destroyed
-            if util::is_epoch_stale(from_epoch, region_epoch) {
-                info!(
-                    "[region {}] tombstone peer [epoch: {:?}] \
-                     receive a stale message {:?}",
-                    region_id, region_epoch, msg_type,
-                );
+            return Self::check_tombstone_msg(trans, msg, local_state.get_region(), raft_metrics);
+        }
 
-                let not_exist = util::find_peer(region, from_store_id).is_none();
-                Self::handle_stale_msg(
-                    trans,
-                    msg,
-                    region_epoch,
-                    is_vote_msg && not_exist,
-                    raft_metrics,
-                );
+        Ok(false)
+    }
 
-                return Ok(true);
-            }
+    /// Checks a message against the tombstone `region` state of a destroyed
+    /// peer. Returns true if the message is stale and has been handled.
+    fn check_tombstone_msg(
+        trans: &T,
+        msg: &RaftMessage,
+        region: &metapb::Region,
+        raft_metrics: &mut RaftMetrics,
+    ) -> Result<bool> {
+        let region_id = msg.get_region_id();
+        let from_epoch = msg.get_region_epoch();
+        let msg_type = msg.get_message().get_msg_type();
+        let is_vote_msg = msg_type == MessageType::MsgRequestVote;
+        let from_store_id = msg.get_from_peer().get_store_id();
 
-            if from_epoch.get_conf_ver() == region_epoch.get_conf_ver() {
-                raft_metrics.message_dropped.region_tombstone_peer += 1;
-                return Err(box_err!(
-                    "tombstone peer [epoch: {:?}] receive an invalid \
-                     message {:?}, ignore it",
-                    region_epoch,
-                    msg_type
-                ));
-            }
+        let region_epoch = region.get_region_epoch();
+        // The region in this peer is already destroyed
+        if util::is_epoch_stale(from_epoch, region_epoch) {
+            info!(
+                "[region {}] tombstone peer [epoch: {:?}] \
+                 receive a stale message {:?}",
+                region_id, region_epoch, msg_type,
+            );
+
+            let not_exist = util::find_peer(region, from_store_id).is_none();
+            Self::handle_stale_msg(
+                trans,
+                msg,
+                region_epoch,
+                is_vote_msg && not_exist,
+                raft_metrics,
+            );
+
+            return Ok(true);
+        }
+
+        if from_epoch.get_conf_ver() == region_epoch.get_conf_ver() {
+            raft_metrics.message_dropped.region_tombstone_peer += 1;
+            return Err(box_err!(
+                "tombstone peer [epoch: {:?}] receive an invalid \
+                 message {:?}, ignore it",
+                region_epoch,
+                msg_type
+            ));
         }
 
         Ok(false)
@@ -1039,9 +1420,8 @@ impl<T: Transport, C: PdClient> Store<T, C> {
             self.raft_metrics.message_dropped.region_no_peer += 1;
             return Ok(Some(key));
         }
-        if let Some((_, &exist_region_id)) = self.region_ranges
-            .range((Excluded(enc_start_key(&snap_region)), Unbounded::<Key>))
-            .next()
+        if let Some((_, exist_region_id)) = self.region_ranges
+            .next_after(&enc_start_key(&snap_region))
         {
             let exist_region = self.region_peers[&exist_region_id].region();
             if enc_start_key(exist_region) < enc_end_key(&snap_region) {
@@ -1070,7 +1450,7 @@ impl<T: Transport, C: PdClient> Store<T, C> {
     }
 
     fn on_raft_ready(&mut self) {
-        let t = SlowTimer::new();
+        let t = SlowTimer::for_subsystem(Subsystem::Raftstore);
         let pending_count = self.pending_raft_groups.len();
         let previous_ready_metrics = self.raft_metrics.ready.clone();
 
@@ -1110,12 +1490,24 @@ impl<T: Transport, C: PdClient> Store<T, C> {
         if !kv_wb.is_empty() {
             // RegionLocalState, ApplyState
             let mut write_opts = WriteOptions::new();
-            write_opts.set_sync(true);
+            if self.cfg.disable_kv_wal {
+                write_opts.disable_wal(true);
+            } else {
+                write_opts.set_sync(true);
+            }
             self.kv_engine
                 .write_opt(kv_wb, &write_opts)
                 .unwrap_or_else(|e| {
                     panic!("{} failed to save append state result: {:?}", self.tag, e);
                 });
+            if self.cfg.disable_kv_wal {
+                // Without a WAL the states can only be made durable by
+                // flushing, and they must hit disk before the raft engine
+                // is written, see the comment above.
+                self.kv_engine.flush(true).unwrap_or_else(|e| {
+                    panic!("{} failed to flush append state result: {:?}", self.tag, e);
+                });
+            }
         }
         fail_point!("raft_between_save");
 
@@ -1186,6 +1578,7 @@ impl<T: Transport, C: PdClient> Store<T, C> {
             );
             if dur >= election_timeout {
                 self.is_busy = true;
+                self.coalesce_raft_tick = true;
             }
         }
 
@@ -1274,6 +1667,26 @@ impl<T: Transport, C: PdClient> Store<T, C> {
                 self.store_id()
             );
         }
+
+        self.cache_tombstone(p.region().clone());
+    }
+
+    /// Remembers the region state of a destroyed peer, so following stale
+    /// messages can be rejected in `check_msg` without reading the kv engine.
+    /// The cache is bounded, old entries are evicted in insertion order and
+    /// `check_msg` falls back to the tombstone record in the kv engine.
+    fn cache_tombstone(&mut self, region: metapb::Region) {
+        let region_id = region.get_id();
+        if self.tombstone_cache.insert(region_id, region).is_some() {
+            // The region is destroyed again with a newer epoch, keep its
+            // position in the eviction queue.
+            return;
+        }
+        self.tombstone_cache_queue.push_back(region_id);
+        if self.tombstone_cache_queue.len() > TOMBSTONE_CACHE_CAP {
+            let evicted = self.tombstone_cache_queue.pop_front().unwrap();
+            self.tombstone_cache.remove(&evicted);
+        }
     }
 
     fn on_ready_change_peer(&mut self, region_id: u64, cp: ChangePeer) {
@@ -1573,6 +1986,9 @@ impl<T: Transport, C: PdClient> Store<T, C> {
         let mut resp = RaftCmdResponse::new();
         let region_id = msg.get_header().get_region_id();
         let peer = self.region_peers.get_mut(&region_id).unwrap();
+        if self.cfg.split_region_on_load {
+            peer.load_stats.record_cmd(&msg);
+        }
         let term = peer.term();
         bind_term(&mut resp, term);
         if peer.propose(cb, msg, resp, &mut self.raft_metrics.propose) {
@@ -1597,6 +2013,7 @@ impl<T: Transport, C: PdClient> Store<T, C> {
                     ret.push(Some(ReadResponse {
                         response: resp,
                         snapshot: None,
+                        apply_index: 0,
                     }));
                     continue;
                 }
@@ -1604,6 +2021,7 @@ impl<T: Transport, C: PdClient> Store<T, C> {
                     ret.push(Some(ReadResponse {
                         response: new_error(e),
                         snapshot: None,
+                        apply_index: 0,
                     }));
                     continue;
                 }
@@ -1612,6 +2030,9 @@ impl<T: Transport, C: PdClient> Store<T, C> {
 
             let region_id = msg.get_header().get_region_id();
             let peer = self.region_peers.get_mut(&region_id).unwrap();
+            if self.cfg.split_region_on_load {
+                peer.load_stats.record_cmd(&msg);
+            }
             ret.push(peer.propose_snapshot(msg, &mut self.raft_metrics.propose));
         }
         match on_finished {
@@ -1636,11 +2057,12 @@ impl<T: Transport, C: PdClient> Store<T, C> {
             Some(peer) => peer,
             None => return Err(Error::RegionNotFound(region_id)),
         };
-        if !peer.is_leader() {
-            return Err(Error::NotLeader(
-                region_id,
-                peer.get_peer_from_cache(peer.leader_id()),
-            ));
+        if !peer.is_leader() && !is_quorum_read_request(msg) {
+            // Explicitly marked quorum reads are allowed on followers,
+            // they go through raft read-index and are held back until
+            // the local apply state catches up, so heavy backup and
+            // checksum scans can be kept off the leader.
+            return Err(Error::NotLeader(region_id, peer.get_leader_hint()));
         }
         if peer.peer_id() != peer_id {
             return Err(box_err!(
@@ -1673,15 +2095,12 @@ impl<T: Transport, C: PdClient> Store<T, C> {
     }
 
     pub fn find_sibling_region(&self, region: &metapb::Region) -> Option<u64> {
-        let start = if self.cfg.right_derive_when_split {
-            Included(enc_start_key(region))
+        let sibling = if self.cfg.right_derive_when_split {
+            self.region_ranges.next_from(&enc_start_key(region))
         } else {
-            Excluded(enc_end_key(region))
+            self.region_ranges.next_after(&enc_end_key(region))
         };
-        self.region_ranges
-            .range((start, Unbounded::<Key>))
-            .next()
-            .map(|(_, &region_id)| region_id)
+        sibling.map(|(_, region_id)| region_id)
     }
 
     fn register_raft_gc_log_tick(&self, event_loop: &mut EventLoop<Self>) {
@@ -1831,6 +2250,9 @@ impl<T: Transport, C: PdClient> Store<T, C> {
     }
 
     fn on_split_region_check_tick(&mut self, event_loop: &mut EventLoop<Self>) {
+        if self.cfg.split_region_on_load {
+            self.check_load_split();
+        }
         // To avoid frequent scan, we only add new scan tasks if all previous tasks
         // have finished.
         // TODO: check whether a gc progress has been started.
@@ -1865,6 +2287,51 @@ impl<T: Transport, C: PdClient> Store<T, C> {
         self.register_split_region_check_tick(event_loop);
     }
 
+    /// Asks PD to split regions whose load since the last check exceeds
+    /// the configured thresholds. The split key is an approximate median
+    /// of the keys accessed recently, so it may be a poor cut for skewed
+    /// traffic; PD is still free to reject the split.
+    fn check_load_split(&mut self) {
+        let max_qps = self.cfg.region_max_load_qps;
+        let max_bytes = self.cfg.region_max_load_bytes.0;
+        let mut to_split = vec![];
+        for peer in self.region_peers.values_mut() {
+            if !peer.is_leader() {
+                peer.load_stats.reset();
+                continue;
+            }
+            if peer.load_stats.exceeds(max_qps, max_bytes) {
+                if let Some(split_key) = peer.load_stats.split_key() {
+                    let region = peer.region();
+                    // The median of the samples may fall on a region boundary,
+                    // splitting there would create an empty region.
+                    if split_key.as_slice() > region.get_start_key()
+                        && (region.get_end_key().is_empty()
+                            || split_key.as_slice() < region.get_end_key())
+                    {
+                        info!(
+                            "{} load exceeds qps {} or bytes {}, split at {}",
+                            peer.tag,
+                            max_qps,
+                            max_bytes,
+                            escape(&split_key)
+                        );
+                        LOAD_BASE_SPLIT_COUNTER.inc();
+                        to_split.push((
+                            region.get_id(),
+                            region.get_region_epoch().clone(),
+                            split_key,
+                        ));
+                    }
+                }
+            }
+            peer.load_stats.reset();
+        }
+        for (region_id, epoch, split_key) in to_split {
+            self.on_prepare_split_region(region_id, epoch, split_key, Callback::None);
+        }
+    }
+
     fn register_compact_check_tick(&self, event_loop: &mut EventLoop<Self>) {
         if let Err(e) = register_timer(
             event_loop,
@@ -1958,10 +2425,7 @@ impl<T: Transport, C: PdClient> Store<T, C> {
                         region_id,
                         self.store_id()
                     );
-                    return Err(Error::NotLeader(
-                        region_id,
-                        peer.get_peer_from_cache(peer.leader_id()),
-                    ));
+                    return Err(Error::NotLeader(region_id, peer.get_leader_hint()));
                 }
                 peer
             }
@@ -2044,6 +2508,11 @@ impl<T: Transport, C: PdClient> Store<T, C> {
         let snap_stats = self.snap_mgr.stats();
         stats.set_sending_snap_count(snap_stats.sending_count as u32);
         stats.set_receiving_snap_count(snap_stats.receiving_count as u32);
+        if snap_stats.rejected_count > 0 {
+            // Some snapshots were rejected by the concurrency limits, report
+            // busy so PD slows down new replica additions to this store.
+            self.is_busy = true;
+        }
         STORE_SNAPSHOT_TRAFFIC_GAUGE_VEC
             .with_label_values(&["sending"])
             .set(snap_stats.sending_count as f64);
@@ -2089,12 +2558,39 @@ impl<T: Transport, C: PdClient> Store<T, C> {
         let task = PdTask::StoreHeartbeat {
             stats: stats,
             store_info: store_info,
+            hot_regions: self.collect_hot_regions(),
         };
         if let Err(e) = self.pd_worker.schedule(task) {
             error!("{} failed to notify pd: {}", self.tag, e);
         }
     }
 
+    /// The leader regions with the highest load since their last reset,
+    /// hottest first. Empty when load tracking is disabled.
+    fn collect_hot_regions(&self) -> Vec<HotRegion> {
+        if !self.cfg.split_region_on_load {
+            return vec![];
+        }
+        let mut hot_regions = vec![];
+        for (&region_id, peer) in &self.region_peers {
+            if !peer.is_leader() {
+                continue;
+            }
+            let (qps, byte_rate) = peer.load_stats.rates();
+            if qps == 0 && byte_rate == 0 {
+                continue;
+            }
+            hot_regions.push(HotRegion {
+                region_id: region_id,
+                qps: qps,
+                byte_rate: byte_rate,
+            });
+        }
+        hot_regions.sort_by(|a, b| (b.byte_rate, b.qps).cmp(&(a.byte_rate, a.qps)));
+        hot_regions.truncate(HOT_REGION_REPORT_COUNT);
+        hot_regions
+    }
+
     fn on_pd_store_heartbeat_tick(&mut self, event_loop: &mut EventLoop<Self>) {
         self.store_heartbeat_pd();
         self.register_pd_store_heartbeat_tick(event_loop);
@@ -2305,32 +2801,190 @@ impl<T: Transport, C: PdClient> Store<T, C> {
             self.register_consistency_check_tick(event_loop);
             return;
         }
-        let (mut candidate_id, mut candidate_check_time) = (0, Instant::now());
-        for (&region_id, peer) in &mut self.region_peers {
+        let min_interval = self.cfg.consistency_check_min_interval.0;
+        let mut candidates = Vec::new();
+        for (&region_id, peer) in &self.region_peers {
             if !peer.is_leader() {
                 continue;
             }
-            if peer.consistency_state.last_check_time < candidate_check_time {
-                candidate_id = region_id;
-                candidate_check_time = peer.consistency_state.last_check_time;
+            // A region checked recently is skipped, so the rotation
+            // spreads over the whole store instead of circling over a
+            // few regions.
+            if peer.consistency_state.last_check_time.elapsed() < min_interval {
+                continue;
             }
+            candidates.push((peer.consistency_state.last_check_time, region_id));
+        }
+        candidates.sort();
+
+        for &(_, region_id) in candidates
+            .iter()
+            .take(self.cfg.consistency_check_batch)
+        {
+            self.schedule_consistency_check(region_id);
         }
 
-        if candidate_id != 0 {
-            let peer = &self.region_peers[&candidate_id];
+        self.register_consistency_check_tick(event_loop);
+    }
 
-            info!("{} scheduling consistent check", peer.tag);
-            let msg = Msg::new_raft_cmd(
-                new_compute_hash_request(candidate_id, peer.peer.clone()),
-                Callback::None,
-            );
+    fn schedule_consistency_check(&self, region_id: u64) {
+        let peer = &self.region_peers[&region_id];
+        info!("{} scheduling consistent check", peer.tag);
+        let msg = Msg::new_raft_cmd(
+            new_compute_hash_request(region_id, peer.peer.clone()),
+            Callback::None,
+        );
 
-            if let Err(e) = self.sendch.send(msg) {
-                error!("{} failed to schedule consistent check: {:?}", peer.tag, e);
+        if let Err(e) = self.sendch.send(msg) {
+            error!("{} failed to schedule consistent check: {:?}", peer.tag, e);
+        }
+    }
+
+    /// Handles an on demand consistency check of one region. The
+    /// debug protocol has no RPC for this yet, the trigger is only
+    /// reachable for embedding callers through `Msg::CheckConsistency`.
+    fn on_check_consistency(&mut self, region_id: u64) {
+        match self.region_peers.get(&region_id) {
+            Some(peer) if peer.is_leader() => (),
+            Some(peer) => {
+                warn!("{} not leader, skip on demand consistency check", peer.tag);
+                return;
+            }
+            None => {
+                warn!(
+                    "[region {}] not found, skip on demand consistency check",
+                    region_id
+                );
+                return;
             }
         }
+        self.schedule_consistency_check(region_id);
+    }
 
-        self.register_consistency_check_tick(event_loop);
+    fn register_region_meta_check_tick(&self, event_loop: &mut EventLoop<Self>) {
+        if let Err(e) = register_timer(
+            event_loop,
+            Tick::RegionMetaCheck,
+            self.cfg.region_meta_check_interval.as_millis(),
+        ) {
+            error!("{} register region meta check tick err: {:?}", self.tag, e);
+        };
+    }
+
+    fn on_region_meta_check_tick(&mut self, event_loop: &mut EventLoop<Self>) {
+        self.check_region_meta();
+        self.register_region_meta_check_tick(event_loop);
+    }
+
+    /// Verify that `region_ranges` and `region_peers` agree with each other.
+    ///
+    /// Recoverable divergence, a dangling range entry or a missing range
+    /// entry of an initialized peer, is logged and repaired in place.
+    /// Anything else, like overlapped ranges or a mismatched end key, means
+    /// some former operation has corrupted the maps, and we panic with
+    /// enough context to track it down.
+    fn check_region_meta(&mut self) {
+        let t = Instant::now();
+
+        // Range entries that point to a non-existent peer can be dropped.
+        let mut dangling = vec![];
+        for (end_key, &region_id) in self.region_ranges.iter() {
+            match self.region_peers.get(&region_id) {
+                None => {
+                    error!(
+                        "{} region_ranges entry (end key {}) points to missing region {}, \
+                         dropping it",
+                        self.tag,
+                        escape(end_key),
+                        region_id
+                    );
+                    dangling.push(end_key.clone());
+                }
+                Some(peer) => {
+                    let expect = enc_end_key(peer.region());
+                    if expect != *end_key {
+                        panic!(
+                            "{} region {} meta corrupted: region_ranges has end key {}, \
+                             but region is {:?}",
+                            self.tag,
+                            region_id,
+                            escape(end_key),
+                            peer.region()
+                        );
+                    }
+                }
+            }
+        }
+        let dangling_count = dangling.len();
+        for end_key in dangling {
+            self.region_ranges.remove(&end_key);
+        }
+
+        // Every initialized peer must have exactly one range entry. Peers
+        // that are applying a snapshot are inserted into region_ranges only
+        // after the snapshot is applied, so they are skipped here.
+        let mut missing = vec![];
+        for (&region_id, peer) in &self.region_peers {
+            if !peer.get_store().is_initialized() || peer.is_applying_snapshot() {
+                continue;
+            }
+            let end_key = enc_end_key(peer.region());
+            match self.region_ranges.get(&end_key) {
+                Some(id) if id == region_id => {}
+                Some(id) => panic!(
+                    "{} region {} and region {} overlap: both end at {}, regions {:?} and {:?}",
+                    self.tag,
+                    region_id,
+                    id,
+                    escape(&end_key),
+                    peer.region(),
+                    self.region_peers[&id].region()
+                ),
+                None => {
+                    warn!(
+                        "{} region {} has no region_ranges entry, re-inserting end key {}",
+                        self.tag,
+                        region_id,
+                        escape(&end_key)
+                    );
+                    missing.push((end_key, region_id));
+                }
+            }
+        }
+        let missing_count = missing.len();
+        for (end_key, region_id) in missing {
+            self.region_ranges.insert(end_key, region_id);
+        }
+
+        // Ranges must not overlap: every region must start at or after the
+        // end key of the region before it.
+        let mut prev: Option<(u64, Key)> = None;
+        for (end_key, &region_id) in self.region_ranges.iter() {
+            let start_key = enc_start_key(self.region_peers[&region_id].region());
+            if let Some((prev_id, prev_end)) = prev.take() {
+                if start_key < prev_end {
+                    panic!(
+                        "{} region {} overlaps its predecessor region {}: regions {:?} and {:?}",
+                        self.tag,
+                        region_id,
+                        prev_id,
+                        self.region_peers[&region_id].region(),
+                        self.region_peers[&prev_id].region()
+                    );
+                }
+            }
+            prev = Some((region_id, end_key.clone()));
+        }
+
+        if dangling_count > 0 || missing_count > 0 {
+            info!(
+                "{} region meta check repaired {} dangling and {} missing entries, takes {:?}",
+                self.tag,
+                dangling_count,
+                missing_count,
+                t.elapsed()
+            );
+        }
     }
 
     fn on_ready_compute_hash(&mut self, region: metapb::Region, index: u64, snap: EngineSnapshot) {
@@ -2396,6 +3050,18 @@ impl<T: Transport, C: PdClient> Store<T, C> {
     }
 }
 
+/// Returns true for a read only request that explicitly asks for a
+/// quorum read. Such a request may be served by a follower, the raft
+/// read-index handshake makes it as consistent as a leader read.
+fn is_quorum_read_request(msg: &RaftCmdRequest) -> bool {
+    if !msg.get_header().get_read_quorum() || msg.get_requests().is_empty() {
+        return false;
+    }
+    msg.get_requests()
+        .iter()
+        .all(|r| r.get_cmd_type() == CmdType::Get || r.get_cmd_type() == CmdType::Snap)
+}
+
 fn new_admin_request(region_id: u64, peer: metapb::Peer) -> RaftCmdRequest {
     let mut request = RaftCmdRequest::new();
     request.mut_header().set_region_id(region_id);
@@ -2499,6 +3165,7 @@ impl<T: Transport, C: PdClient> mio::Handler for Store<T, C> {
             }
             Msg::Quit => {
                 info!("{} receive quit message", self.tag);
+                self.drain_leaders_on_shutdown();
                 event_loop.shutdown();
             }
             Msg::SnapshotStats => self.store_heartbeat_pd(),
@@ -2509,6 +3176,9 @@ impl<T: Transport, C: PdClient> mio::Handler for Store<T, C> {
             } => {
                 self.on_hash_computed(region_id, index, hash);
             }
+            Msg::CheckConsistency { region_id } => {
+                self.on_check_consistency(region_id);
+            }
             Msg::SplitRegion {
                 region_id,
                 region_epoch,
@@ -2530,7 +3200,7 @@ impl<T: Transport, C: PdClient> mio::Handler for Store<T, C> {
     }
 
     fn timeout(&mut self, event_loop: &mut EventLoop<Self>, timeout: Tick) {
-        let t = SlowTimer::new();
+        let t = SlowTimer::for_subsystem(Subsystem::Raftstore);
         match timeout {
             Tick::Raft => self.on_raft_base_tick(event_loop),
             Tick::RaftLogGc => self.on_raft_gc_log_tick(event_loop),
@@ -2541,6 +3211,7 @@ impl<T: Transport, C: PdClient> mio::Handler for Store<T, C> {
             Tick::SnapGc => self.on_snap_mgr_gc(event_loop),
             Tick::CompactLockCf => self.on_compact_lock_cf(event_loop),
             Tick::ConsistencyCheck => self.on_consistency_check_tick(event_loop),
+            Tick::RegionMetaCheck => self.on_region_meta_check_tick(event_loop),
         }
         slow_log!(t, "{} handle timeout {:?}", self.tag, timeout);
     }
@@ -2653,20 +3324,15 @@ pub fn new_compaction_listener(ch: SendCh<Msg>) -> CompactionListener {
 
 fn calc_region_declined_bytes(
     event: CompactedEvent,
-    region_ranges: &BTreeMap<Key, u64>,
+    region_ranges: &RangeMap,
     bytes_threshold: u64,
 ) -> Vec<(u64, u64)> {
     // Calculate influenced regions.
     let mut influenced_regions = vec![];
-    for (end_key, region_id) in
-        region_ranges.range((Excluded(event.start_key), Included(event.end_key.clone())))
-    {
-        influenced_regions.push((region_id, end_key.clone()));
+    for (end_key, region_id) in region_ranges.ending_in(&event.start_key, &event.end_key) {
+        influenced_regions.push((*region_id, end_key.clone()));
     }
-    if let Some((end_key, region_id)) = region_ranges
-        .range((Included(event.end_key), Unbounded))
-        .next()
-    {
+    if let Some((end_key, region_id)) = region_ranges.next_from(&event.end_key) {
         influenced_regions.push((region_id, end_key.clone()));
     }
 
@@ -2687,7 +3353,7 @@ fn calc_region_declined_bytes(
 
         // Filter some trivial declines for better performance.
         if old_size > new_size && old_size - new_size > bytes_threshold {
-            region_declined_bytes.push((*region_id, old_size - new_size));
+            region_declined_bytes.push((region_id, old_size - new_size));
         }
     }
 
@@ -2696,8 +3362,6 @@ fn calc_region_declined_bytes(
 
 #[cfg(test)]
 mod tests {
-    use std::collections::BTreeMap;
-
     use util::rocksdb::CompactedEvent;
     use util::rocksdb::properties::{IndexHandle, IndexHandles, SizeProperties};
 
@@ -2736,7 +3400,7 @@ mod tests {
             output_props: vec![],
         };
 
-        let mut region_ranges = BTreeMap::new();
+        let mut region_ranges = RangeMap::new();
         region_ranges.insert(b"a".to_vec(), 1);
         region_ranges.insert(b"b".to_vec(), 2);
         region_ranges.insert(b"c".to_vec(), 3);
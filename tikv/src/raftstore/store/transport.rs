@@ -20,4 +20,12 @@ pub trait Transport: Send + Clone {
     fn send(&self, msg: RaftMessage) -> Result<()>;
 
     fn flush(&mut self);
+
+    /// Returns the number of messages and bytes queued in the underlying
+    /// connection to `store_id` but not handed to the network yet.
+    /// Leaders use this to pause replication to congested followers.
+    /// Transports that don't track their queues report empty ones.
+    fn queue_stats(&self, _store_id: u64) -> (u64, u64) {
+        (0, 0)
+    }
 }
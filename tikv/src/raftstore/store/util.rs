@@ -12,17 +12,19 @@
 // limitations under the License.
 
 use std::option::Option;
-use std::{fmt, u64};
+use std::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+use std::{fmt, fs, u64};
 
 use kvproto::metapb;
 use kvproto::eraftpb::{self, ConfChangeType, MessageType};
 use kvproto::raft_serverpb::RaftMessage;
 use raftstore::{Error, Result};
 use raftstore::store::keys;
-use rocksdb::{Range, TablePropertiesCollection, Writable, WriteBatch, DB};
+use rocksdb::{EnvOptions, IngestExternalFileOptions, Range, SstFileWriter,
+              TablePropertiesCollection, Writable, WriteBatch, DB};
 use time::{Duration, Timespec};
 
-use storage::{Key, CF_LOCK, CF_RAFT, CF_WRITE, LARGE_CFS};
+use storage::{Key, CF_APPLY, CF_LOCK, CF_RAFT, CF_WRITE, LARGE_CFS};
 use util::properties::SizeProperties;
 use util::{rocksdb as rocksdb_util, Either};
 use util::time::monotonic_raw_now;
@@ -97,6 +99,15 @@ pub fn conf_change_type_str(conf_type: &eraftpb::ConfChangeType) -> &'static str
 
 const MAX_WRITE_BATCH_SIZE: usize = 4 * 1024 * 1024;
 
+// Ranges holding at most this many keys are still cleaned up through the
+// write batch. Ingesting an SST has a fixed cost that is not worth paying
+// for a handful of keys.
+const MAX_DELETE_COUNT_BY_KEY: usize = 2048;
+
+// Used to distinguish the SST files generated for delete-range conversion
+// from each other and from everything else in the db directory.
+static DELETE_SST_SEQ: AtomicUsize = ATOMIC_USIZE_INIT;
+
 pub fn delete_all_in_range(
     db: &DB,
     start_key: &[u8],
@@ -121,41 +132,128 @@ pub fn delete_all_in_range_cf(
     end_key: &[u8],
     use_delete_range: bool,
 ) -> Result<()> {
-    let handle = rocksdb_util::get_cf_handle(db, cf)?;
-    let mut wb = WriteBatch::new();
-    // Since CF_RAFT and CF_LOCK is usually small, so using
+    // Since CF_RAFT, CF_APPLY and CF_LOCK is usually small, so using
     // traditional way to cleanup.
-    if use_delete_range && cf != CF_RAFT && cf != CF_LOCK {
+    if cf == CF_RAFT || cf == CF_APPLY || cf == CF_LOCK {
+        return delete_all_in_range_cf_by_key(db, cf, start_key, end_key);
+    }
+
+    if use_delete_range {
+        let handle = rocksdb_util::get_cf_handle(db, cf)?;
+        let wb = WriteBatch::new();
         if cf == CF_WRITE {
             let start = Key::from_encoded(start_key.to_vec()).append_ts(u64::MAX);
             wb.delete_range_cf(handle, start.encoded(), end_key)?;
         } else {
             wb.delete_range_cf(handle, start_key, end_key)?;
         }
-    } else {
-        let iter_opt = IterOption::new(Some(start_key.to_vec()), Some(end_key.to_vec()), false);
-        let mut it = db.new_iterator_cf(cf, iter_opt)?;
-        it.seek(start_key.into());
-        while it.valid() {
-            wb.delete_cf(handle, it.key())?;
+        db.write(wb)?;
+        return Ok(());
+    }
+
+    delete_all_in_range_cf_by_ingest(db, cf, start_key, end_key)
+}
+
+fn delete_all_in_range_cf_by_key(
+    db: &DB,
+    cf: &str,
+    start_key: &[u8],
+    end_key: &[u8],
+) -> Result<()> {
+    let handle = rocksdb_util::get_cf_handle(db, cf)?;
+    let iter_opt = IterOption::new(Some(start_key.to_vec()), Some(end_key.to_vec()), false);
+    let mut it = db.new_iterator_cf(cf, iter_opt)?;
+    it.seek(start_key.into());
+    let mut wb = WriteBatch::new();
+    while it.valid() {
+        wb.delete_cf(handle, it.key())?;
+        if wb.data_size() >= MAX_WRITE_BATCH_SIZE {
+            // Can't use write_without_wal here.
+            // Otherwise it may cause dirty data when applying snapshot.
+            db.write(wb)?;
+            wb = WriteBatch::new();
+        }
+
+        if !it.next() {
+            break;
+        }
+    }
+
+    if wb.count() > 0 {
+        db.write(wb)?;
+    }
+
+    Ok(())
+}
+
+// Converts the range into an SST of delete marks and ingests it, so that
+// applying a big DeleteRange doesn't have to push every key through the
+// write batch (and the WAL).
+fn delete_all_in_range_cf_by_ingest(
+    db: &DB,
+    cf: &str,
+    start_key: &[u8],
+    end_key: &[u8],
+) -> Result<()> {
+    let handle = rocksdb_util::get_cf_handle(db, cf)?;
+    let iter_opt = IterOption::new(Some(start_key.to_vec()), Some(end_key.to_vec()), false);
+    let mut it = db.new_iterator_cf(cf, iter_opt)?;
+    it.seek(start_key.into());
+
+    // Collect the head of the range first. If the range turns out to be
+    // small, deleting by key is cheaper than ingesting a file.
+    let mut keys = Vec::new();
+    while it.valid() {
+        keys.push(it.key().to_vec());
+        if !it.next() || keys.len() > MAX_DELETE_COUNT_BY_KEY {
+            break;
+        }
+    }
+    if keys.is_empty() {
+        return Ok(());
+    }
+    if keys.len() <= MAX_DELETE_COUNT_BY_KEY {
+        let mut wb = WriteBatch::new();
+        for key in &keys {
+            wb.delete_cf(handle, key)?;
             if wb.data_size() >= MAX_WRITE_BATCH_SIZE {
-                // Can't use write_without_wal here.
-                // Otherwise it may cause dirty data when applying snapshot.
                 db.write(wb)?;
                 wb = WriteBatch::new();
             }
+        }
+        if wb.count() > 0 {
+            db.write(wb)?;
+        }
+        return Ok(());
+    }
 
+    let seq = DELETE_SST_SEQ.fetch_add(1, Ordering::SeqCst);
+    let sst_path = format!("{}/.delete_range.{}.{}.sst", db.path(), cf, seq);
+    let mut writer = SstFileWriter::new(EnvOptions::new(), db.get_options_cf(handle).clone());
+    let res = (|| {
+        writer.open(&sst_path)?;
+        for key in &keys {
+            writer.delete(key)?;
+        }
+        while it.valid() {
+            writer.delete(it.key())?;
             if !it.next() {
                 break;
             }
         }
-    }
+        writer.finish()?;
 
-    if wb.count() > 0 {
-        db.write(wb)?;
+        let mut ingest_opt = IngestExternalFileOptions::new();
+        ingest_opt.move_files(true);
+        db.ingest_external_file_cf(handle, &ingest_opt, &[&sst_path])?;
+        Ok(())
+    })();
+    // `move_files` consumes the file on success, so this only cleans up
+    // after a failure half way through.
+    if res.is_err() {
+        let _ = fs::remove_file(&sst_path);
     }
-
-    Ok(())
+    res
 }
 
 // check whether epoch is staler than check_epoch.
@@ -339,7 +437,7 @@ mod tests {
     use util::properties::SizePropertiesCollectorFactory;
     use util::rocksdb::{get_cf_handle, new_engine_opt, CFOptions};
     use util::time::monotonic_raw_now;
-    use storage::{Key, ALL_CFS};
+    use storage::{Key, ALL_CFS, CF_DEFAULT};
     use super::*;
 
     #[test]
@@ -614,6 +712,44 @@ mod tests {
         test_delete_all_in_range(false);
     }
 
+    #[test]
+    fn test_delete_all_in_range_by_ingest() {
+        let path = TempDir::new("_raftstore_util_delete_all_in_range_by_ingest").expect("");
+        let path_str = path.path().to_str().unwrap();
+
+        let cfs_opts = ALL_CFS
+            .into_iter()
+            .map(|cf| CFOptions::new(cf, ColumnFamilyOptions::new()))
+            .collect();
+        let db = new_engine_opt(path_str, DBOptions::new(), cfs_opts).unwrap();
+        let handle = get_cf_handle(&db, CF_DEFAULT).unwrap();
+
+        // Make the range hold more than `MAX_DELETE_COUNT_BY_KEY` keys so
+        // that the SST ingestion path is taken.
+        let n = MAX_DELETE_COUNT_BY_KEY + 3;
+        let mut keys = Vec::with_capacity(n);
+        for i in 0..n {
+            let key = Key::from_raw(format!("k{:08}", i).as_bytes());
+            db.put_cf(handle, key.encoded(), b"value").unwrap();
+            keys.push(key);
+        }
+
+        // Delete all in [k1, k{n-1}).
+        delete_all_in_range_cf(
+            &db,
+            CF_DEFAULT,
+            keys[1].encoded().as_slice(),
+            keys[n - 1].encoded().as_slice(),
+            false,
+        ).unwrap();
+
+        assert!(db.get_cf(handle, keys[0].encoded()).unwrap().is_some());
+        assert!(db.get_cf(handle, keys[n - 1].encoded()).unwrap().is_some());
+        for key in &keys[1..n - 1] {
+            assert!(db.get_cf(handle, key.encoded()).unwrap().is_none());
+        }
+    }
+
     fn exit_with_err(msg: String) -> ! {
         error!("{}", msg);
         process::exit(1)
@@ -30,9 +30,10 @@ use kvproto::raft_cmdpb::{AdminCmdType, AdminRequest, AdminResponse, ChangePeerR
 
 use util::worker::Runnable;
 use util::{escape, rocksdb, MustConsumeVec};
+use util::slow_log::Subsystem;
 use util::time::{duration_to_sec, Instant, SlowTimer};
 use util::collections::{HashMap, HashMapEntry as MapEntry};
-use storage::{ALL_CFS, CF_DEFAULT, CF_LOCK, CF_RAFT};
+use storage::{ALL_CFS, CF_APPLY, CF_DEFAULT, CF_LOCK};
 use raftstore::{Error, Result};
 use raftstore::coprocessor::CoprocessorHost;
 use raftstore::store::{cmd_resp, keys, util, Store};
@@ -47,6 +48,21 @@ use super::metrics::*;
 
 const WRITE_BATCH_MAX_KEYS: usize = 128;
 const DEFAULT_APPLY_WB_SIZE: usize = 4 * 1024;
+// Write batches larger than this are not kept as capacity hint, otherwise
+// a single huge poll would pin multi-MB allocations forever.
+const APPLY_WB_MAX_KEEP_SIZE: usize = 4 * 1024 * 1024;
+
+/// Round `last_size` up to a power of two so that the next apply run can
+/// allocate its write batch at full size instead of growing it put by put.
+fn apply_wb_capacity(last_size: usize) -> usize {
+    if last_size <= DEFAULT_APPLY_WB_SIZE {
+        return DEFAULT_APPLY_WB_SIZE;
+    }
+    if last_size >= APPLY_WB_MAX_KEEP_SIZE {
+        return APPLY_WB_MAX_KEEP_SIZE;
+    }
+    last_size.next_power_of_two()
+}
 
 pub struct PendingCmd {
     pub index: u64,
@@ -199,6 +215,7 @@ struct ApplyContext<'a> {
     cbs: MustConsumeVec<ApplyCallback>,
     wb_last_bytes: u64,
     wb_last_keys: u64,
+    wb_capacity: usize,
     sync_log: bool,
     exec_ctx: Option<ExecContext>,
     use_delete_range: bool,
@@ -206,12 +223,29 @@ struct ApplyContext<'a> {
 
 impl<'a> ApplyContext<'a> {
     fn new(host: &CoprocessorHost, use_delete_range: bool) -> ApplyContext {
+        ApplyContext::with_write_batch(
+            host,
+            use_delete_range,
+            WriteBatch::with_capacity(DEFAULT_APPLY_WB_SIZE),
+            DEFAULT_APPLY_WB_SIZE,
+        )
+    }
+
+    /// Build a context around a write batch kept from the last run, so the
+    /// buffer allocation is reused instead of rebuilt on every poll.
+    fn with_write_batch(
+        host: &CoprocessorHost,
+        use_delete_range: bool,
+        wb: WriteBatch,
+        wb_capacity: usize,
+    ) -> ApplyContext {
         ApplyContext {
             host: host,
-            wb: WriteBatch::with_capacity(DEFAULT_APPLY_WB_SIZE),
+            wb: wb,
             cbs: MustConsumeVec::new("callback of apply context"),
             wb_last_bytes: 0,
             wb_last_keys: 0,
+            wb_capacity: wb_capacity,
             sync_log: false,
             exec_ctx: None,
             use_delete_range: use_delete_range,
@@ -399,7 +433,7 @@ impl ApplyDelegate {
     }
 
     fn write_apply_state(&self, wb: &WriteBatch) {
-        rocksdb::get_cf_handle(&self.engine, CF_RAFT)
+        rocksdb::get_cf_handle(&self.engine, CF_APPLY)
             .map_err(From::from)
             .and_then(|handle| {
                 wb.put_msg_cf(
@@ -432,7 +466,8 @@ impl ApplyDelegate {
                 self.write_apply_state(&apply_ctx.wb);
 
                 self.update_metrics(apply_ctx);
-                let wb = WriteBatch::with_capacity(DEFAULT_APPLY_WB_SIZE);
+                apply_ctx.wb_capacity = apply_wb_capacity(apply_ctx.wb.data_size());
+                let wb = WriteBatch::with_capacity(apply_ctx.wb_capacity);
                 // flush to engine
                 self.engine
                     .write(mem::replace(&mut apply_ctx.wb, wb))
@@ -1441,7 +1476,12 @@ pub struct Runner {
     delegates: HashMap<u64, ApplyDelegate>,
     notifier: Sender<TaskRes>,
     sync_log: bool,
+    disable_wal: bool,
     use_delete_range: bool,
+    // An unused write batch kept from the last run, and the recommended
+    // capacity for the next one if the cached batch has been consumed.
+    cached_wb: Option<WriteBatch>,
+    wb_capacity: usize,
     tag: String,
 }
 
@@ -1450,6 +1490,7 @@ impl Runner {
         store: &Store<T, C>,
         notifier: Sender<TaskRes>,
         sync_log: bool,
+        disable_wal: bool,
         use_delete_range: bool,
     ) -> Runner {
         let mut delegates =
@@ -1463,16 +1504,27 @@ impl Runner {
             delegates: delegates,
             notifier: notifier,
             sync_log: sync_log,
+            disable_wal: disable_wal,
             use_delete_range: use_delete_range,
+            cached_wb: None,
+            wb_capacity: DEFAULT_APPLY_WB_SIZE,
             tag: format!("[store {}]", store.store_id()),
         }
     }
 
     fn handle_applies(&mut self, applys: Vec<Apply>) {
-        let t = SlowTimer::new();
+        let t = SlowTimer::for_subsystem(Subsystem::Apply);
 
         let mut applys_res = Vec::with_capacity(applys.len());
-        let mut apply_ctx = ApplyContext::new(self.host.as_ref(), self.use_delete_range);
+        let wb = self.cached_wb
+            .take()
+            .unwrap_or_else(|| WriteBatch::with_capacity(self.wb_capacity));
+        let mut apply_ctx = ApplyContext::with_write_batch(
+            self.host.as_ref(),
+            self.use_delete_range,
+            wb,
+            self.wb_capacity,
+        );
         let mut committed_count = 0;
         for apply in applys {
             if apply.entries.is_empty() {
@@ -1515,11 +1567,22 @@ impl Runner {
         // if power failure happen, raft WAL may synced to disk, but kv WAL may not.
         // so we use sync-log flag here.
         let mut write_opts = WriteOptions::new();
-        write_opts.set_sync(self.sync_log && apply_ctx.sync_log);
+        if self.disable_wal {
+            // Applied data is recovered by replaying the raft log on restart,
+            // see raftstore.disable-kv-wal.
+            write_opts.disable_wal(true);
+        } else {
+            write_opts.set_sync(self.sync_log && apply_ctx.sync_log);
+        }
         if !apply_ctx.wb.is_empty() {
+            self.wb_capacity = apply_wb_capacity(apply_ctx.wb.data_size());
             self.db
                 .write_opt(apply_ctx.wb, &write_opts)
                 .unwrap_or_else(|e| panic!("failed to write to engine, error: {:?}", e));
+        } else {
+            // Nothing was written, keep the batch for the next run.
+            self.wb_capacity = apply_ctx.wb_capacity;
+            self.cached_wb = Some(apply_ctx.wb);
         }
 
         // Call callbacks
@@ -1659,6 +1722,9 @@ mod tests {
             delegates: HashMap::default(),
             notifier: tx,
             sync_log: false,
+            disable_wal: false,
+            cached_wb: None,
+            wb_capacity: DEFAULT_APPLY_WB_SIZE,
             tag: "".to_owned(),
             use_delete_range: true,
         }
@@ -1672,6 +1738,17 @@ mod tests {
         e
     }
 
+    #[test]
+    fn test_apply_wb_capacity() {
+        assert_eq!(apply_wb_capacity(0), DEFAULT_APPLY_WB_SIZE);
+        assert_eq!(apply_wb_capacity(DEFAULT_APPLY_WB_SIZE), DEFAULT_APPLY_WB_SIZE);
+        assert_eq!(
+            apply_wb_capacity(DEFAULT_APPLY_WB_SIZE + 1),
+            DEFAULT_APPLY_WB_SIZE * 2
+        );
+        assert_eq!(apply_wb_capacity(usize::max_value()), APPLY_WB_MAX_KEEP_SIZE);
+    }
+
     #[test]
     fn test_should_flush_to_engine() {
         // ComputeHash command
@@ -1813,7 +1890,7 @@ mod tests {
             assert_eq!(delegate.applied_index_term, 5);
             assert_eq!(delegate.apply_state.get_applied_index(), 4);
             let apply_state: RaftApplyState =
-                db.get_msg_cf(CF_RAFT, &apply_state_key).unwrap().unwrap();
+                db.get_msg_cf(CF_APPLY, &apply_state_key).unwrap().unwrap();
             assert_eq!(apply_state, delegate.apply_state);
         }
 
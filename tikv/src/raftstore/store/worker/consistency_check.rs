@@ -20,6 +20,7 @@ use kvproto::metapb::Region;
 use raftstore::store::{keys, Msg};
 use raftstore::store::engine::{Iterable, Peekable, Snapshot};
 use storage::CF_RAFT;
+use util::io_limiter::IOLimiter;
 use util::worker::Runnable;
 
 use super::metrics::*;
@@ -55,13 +56,28 @@ impl Display for Task {
     }
 }
 
+// How many scanned bytes are accumulated before the limiter is asked,
+// so the hash scan does not pay a limiter call per key.
+const LIMIT_REQUEST_BYTES: i64 = 64 * 1024;
+
 pub struct Runner<C: MsgSender> {
     ch: C,
+    // Throttles the hash scan, shared by all checks on this store.
+    // `None` means unlimited.
+    limiter: Option<IOLimiter>,
 }
 
 impl<C: MsgSender> Runner<C> {
-    pub fn new(ch: C) -> Runner<C> {
-        Runner { ch: ch }
+    pub fn new(ch: C, io_limit: u64) -> Runner<C> {
+        let limiter = if io_limit > 0 {
+            Some(IOLimiter::new(io_limit))
+        } else {
+            None
+        };
+        Runner {
+            ch: ch,
+            limiter: limiter,
+        }
     }
 
     fn compute_hash(&mut self, region: Region, index: u64, snap: Snapshot) {
@@ -77,10 +93,19 @@ impl<C: MsgSender> Runner<C> {
         cf_names.sort();
         let start_key = keys::enc_start_key(&region);
         let end_key = keys::enc_end_key(&region);
+        let limiter = &self.limiter;
+        let mut pending_bytes = 0;
         for cf in cf_names {
             let res = snap.scan_cf(cf, &start_key, &end_key, false, &mut |k, v| {
                 digest.write(k);
                 digest.write(v);
+                if let Some(ref limiter) = *limiter {
+                    pending_bytes += (k.len() + v.len()) as i64;
+                    if pending_bytes >= LIMIT_REQUEST_BYTES {
+                        limiter.request(pending_bytes);
+                        pending_bytes = 0;
+                    }
+                }
                 Ok(true)
             });
             if let Err(e) = res {
@@ -161,7 +186,7 @@ mod test {
         region.mut_peers().push(Peer::new());
 
         let (tx, rx) = mpsc::channel();
-        let mut runner = Runner::new(tx);
+        let mut runner = Runner::new(tx, 0);
         let mut digest = Digest::new(crc32::IEEE);
         let kvs = vec![(b"k1", b"v1"), (b"k2", b"v2")];
         for (k, v) in kvs {
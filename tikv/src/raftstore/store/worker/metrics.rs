@@ -11,7 +11,9 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use prometheus::{exponential_buckets, CounterVec, Histogram, HistogramVec};
+use prometheus::{exponential_buckets, CounterVec, Gauge, Histogram, HistogramVec};
+
+use util::metrics::latency_buckets;
 
 lazy_static! {
     pub static ref SNAP_COUNTER_VEC: CounterVec =
@@ -21,6 +23,19 @@ lazy_static! {
             &["type", "status"]
         ).unwrap();
 
+    pub static ref SNAP_APPLY_PENDING_GAUGE: Gauge =
+        register_gauge!(
+            "tikv_raftstore_snapshot_pending_applies",
+            "Total number of snapshot apply tasks waiting in the region worker queue."
+        ).unwrap();
+
+    pub static ref SNAP_APPLY_WAIT_HISTOGRAM: Histogram =
+        register_histogram!(
+            "tikv_raftstore_snapshot_apply_wait_duration_seconds",
+            "Bucketed histogram of time a snapshot apply task waits before it is handled",
+            latency_buckets()
+        ).unwrap();
+
     pub static ref CHECK_SPILT_COUNTER_VEC: CounterVec =
         register_counter_vec!(
             "tikv_raftstore_check_split_total",
@@ -33,14 +48,14 @@ lazy_static! {
             "tikv_raftstore_snapshot_duration_seconds",
             "Bucketed histogram of raftstore snapshot process duration",
             &["type"],
-            exponential_buckets(0.0005, 2.0, 20).unwrap()
+            latency_buckets()
         ).unwrap();
 
     pub static ref CHECK_SPILT_HISTOGRAM: Histogram =
         register_histogram!(
             "tikv_raftstore_check_split_duration_seconds",
             "Bucketed histogram of raftstore split check duration",
-            exponential_buckets(0.0005, 2.0, 20).unwrap()
+            latency_buckets()
         ).unwrap();
 
     pub static ref COMPACT_RANGE_CF: HistogramVec =
@@ -55,12 +55,19 @@ quick_error! {
 }
 
 pub struct Runner {
+    // Set when the kv WAL is disabled. The kv engine must be flushed before
+    // raft logs are deleted, otherwise a restart may recover to an apply
+    // index whose following logs are already gone.
+    kv_engine: Option<Arc<DB>>,
     ch: Option<Sender<TaskRes>>,
 }
 
 impl Runner {
-    pub fn new(ch: Option<Sender<TaskRes>>) -> Runner {
-        Runner { ch: ch }
+    pub fn new(kv_engine: Option<Arc<DB>>, ch: Option<Sender<TaskRes>>) -> Runner {
+        Runner {
+            kv_engine: kv_engine,
+            ch: ch,
+        }
     }
 
     /// Do the gc job and return the count of log collected.
@@ -83,6 +90,9 @@ impl Runner {
             info!("[region {}] no need to gc", region_id);
             return Ok(0);
         }
+        if let Some(ref kv_engine) = self.kv_engine {
+            box_try!(kv_engine.flush(true));
+        }
         let raft_wb = WriteBatch::new();
         for idx in first_idx..end_idx {
             let key = keys::raft_log_key(region_id, idx);
@@ -147,7 +157,7 @@ mod test {
         let raft_db = Arc::new(raft_db);
 
         let (tx, rx) = mpsc::channel();
-        let mut runner = Runner::new(Some(tx));
+        let mut runner = Runner::new(None, Some(tx));
 
         // generate raft logs
         let region_id = 1;
@@ -15,21 +15,23 @@ use std::fmt::{self, Display, Formatter};
 use std::sync::Arc;
 use std::sync::mpsc::SyncSender;
 use std::sync::atomic::{AtomicUsize, Ordering};
-use std::time::Instant;
+use std::thread;
+use std::time::{Duration, Instant};
 
 use rocksdb::{Writable, WriteBatch, DB};
 use kvproto::raft_serverpb::{PeerState, RaftApplyState, RegionLocalState};
 use kvproto::eraftpb::Snapshot as RaftSnapshot;
 
 use util::threadpool::{DefaultContext, ThreadPool, ThreadPoolBuilder};
+use util::time::duration_to_sec;
 use util::worker::Runnable;
 use util::{escape, rocksdb};
 use raftstore::store::engine::{Mutable, Snapshot};
 use raftstore::store::peer_storage::{JOB_STATUS_CANCELLED, JOB_STATUS_CANCELLING,
                                      JOB_STATUS_FAILED, JOB_STATUS_FINISHED, JOB_STATUS_PENDING,
                                      JOB_STATUS_RUNNING};
-use raftstore::store::{self, check_abort, keys, ApplyOptions, Peekable, SnapEntry, SnapKey,
-                       SnapManager};
+use raftstore::store::{self, check_abort, keys, load_apply_state, ApplyOptions, Peekable,
+                       SnapEntry, SnapKey, SnapManager};
 use raftstore::store::snap::{Error, Result};
 use storage::CF_RAFT;
 
@@ -47,6 +49,7 @@ pub enum Task {
     Apply {
         region_id: u64,
         status: Arc<AtomicUsize>,
+        start: Instant,
     },
     /// Destroy data between [start_key, end_key).
     ///
@@ -59,6 +62,15 @@ pub enum Task {
 }
 
 impl Task {
+    pub fn apply(region_id: u64, status: Arc<AtomicUsize>) -> Task {
+        SNAP_APPLY_PENDING_GAUGE.inc();
+        Task::Apply {
+            region_id: region_id,
+            status: status,
+            start: Instant::now(),
+        }
+    }
+
     pub fn destroy(region_id: u64, start_key: Vec<u8>, end_key: Vec<u8>) -> Task {
         Task::Destroy {
             region_id: region_id,
@@ -169,21 +181,25 @@ impl SnapContext {
         ));
         check_abort(&abort)?;
 
-        let state_key = keys::apply_state_key(region_id);
-        let apply_state: RaftApplyState = match box_try!(self.kv_db.get_msg_cf(CF_RAFT, &state_key))
-        {
-            Some(state) => state,
-            None => {
-                return Err(box_err!(
-                    "failed to get raftstate from {}",
-                    escape(&state_key)
-                ))
-            }
-        };
+        let apply_state: RaftApplyState =
+            match box_try!(load_apply_state(self.kv_db.as_ref(), region_id)) {
+                Some(state) => state,
+                None => {
+                    return Err(box_err!(
+                        "failed to get apply state of region {}",
+                        region_id
+                    ))
+                }
+            };
         let term = apply_state.get_truncated_state().get_term();
         let idx = apply_state.get_truncated_state().get_index();
         let snap_key = SnapKey::new(region_id, term, idx);
-        self.mgr.register(snap_key.clone(), SnapEntry::Applying);
+        // Applies on this worker are serial, but other stages share the
+        // registry, so wait for a free slot instead of failing the apply.
+        while !self.mgr.try_register(snap_key.clone(), SnapEntry::Applying) {
+            check_abort(&abort)?;
+            thread::sleep(Duration::from_millis(100));
+        }
         defer!({
             self.mgr.deregister(&snap_key, &SnapEntry::Applying);
         });
@@ -217,8 +233,28 @@ impl SnapContext {
         Ok(())
     }
 
-    fn handle_apply(&self, region_id: u64, status: Arc<AtomicUsize>) {
-        status.compare_and_swap(JOB_STATUS_PENDING, JOB_STATUS_RUNNING, Ordering::SeqCst);
+    fn handle_apply(&self, region_id: u64, status: Arc<AtomicUsize>, start: Instant) {
+        SNAP_APPLY_PENDING_GAUGE.dec();
+        SNAP_APPLY_WAIT_HISTOGRAM.observe(duration_to_sec(start.elapsed()));
+        if status.compare_and_swap(JOB_STATUS_PENDING, JOB_STATUS_RUNNING, Ordering::SeqCst)
+            != JOB_STATUS_PENDING
+        {
+            // The task is cancelled while it is still waiting in the queue,
+            // or a newer snapshot has superseded it. Reject it without
+            // touching the engine at all.
+            assert_eq!(
+                status.swap(JOB_STATUS_CANCELLED, Ordering::SeqCst),
+                JOB_STATUS_CANCELLING
+            );
+            info!(
+                "[region {}] snap apply is cancelled before being handled",
+                region_id
+            );
+            SNAP_COUNTER_VEC
+                .with_label_values(&["apply", "abort"])
+                .inc();
+            return;
+        }
         SNAP_COUNTER_VEC.with_label_values(&["apply", "all"]).inc();
         let apply_histogram = SNAP_HISTOGRAM.with_label_values(&["apply"]);
         let timer = apply_histogram.start_coarse_timer();
@@ -311,7 +347,11 @@ impl Runnable<Task> for Runner {
                 self.pool
                     .execute(move |_| ctx.handle_gen(region_id, notifier))
             }
-            Task::Apply { region_id, status } => self.ctx.handle_apply(region_id, status),
+            Task::Apply {
+                region_id,
+                status,
+                start,
+            } => self.ctx.handle_apply(region_id, status, start),
             Task::Destroy {
                 region_id,
                 start_key,
@@ -15,6 +15,7 @@ use sys_info;
 
 use util::collections::HashMap;
 use util::config::{self, ReadableDuration, ReadableSize};
+use util::sys;
 use coprocessor::DEFAULT_REQUEST_MAX_HANDLE_SECS;
 use util::io_limiter::DEFAULT_SNAP_MAX_BYTES_PER_SEC;
 use super::Result;
@@ -30,6 +31,10 @@ const DEFAULT_GRPC_CONCURRENCY: usize = 4;
 const DEFAULT_GRPC_CONCURRENT_STREAM: usize = 1024;
 const DEFAULT_GRPC_RAFT_CONN_NUM: usize = 10;
 const DEFAULT_GRPC_STREAM_INITIAL_WINDOW_SIZE: u64 = 2 * 1024 * 1024;
+// Max number of messages and bytes one raft connection may queue before
+// bulky messages are shed.
+const DEFAULT_RAFT_CLIENT_MAX_PENDING_MSGS: usize = 8192;
+const DEFAULT_RAFT_CLIENT_MAX_PENDING_BYTES: u64 = 256 * 1024 * 1024;
 const DEFAULT_MESSAGES_PER_TICK: usize = 4096;
 // Enpoints may occur very deep recursion,
 // so enlarge their stack size to 10 MB.
@@ -44,12 +49,35 @@ pub const DEFAULT_MAX_RUNNING_TASK_COUNT: usize = 2 as usize * 1000;
 // Number of rows in each chunk.
 pub const DEFAULT_ENDPOINT_BATCH_ROW_LIMIT: usize = 64;
 
+// How many versioned entries coprocessor scans step over before they seek
+// to the next user key instead.
+pub const DEFAULT_ENDPOINT_SCAN_SEEK_BOUND: usize = 30;
+
+// Server-side caps on the per-request ANALYZE sampling parameters. The
+// sampling sizes are picked by the client, a mistuned one must not be able
+// to make the endpoint collect unbounded samples or sketches.
+const DEFAULT_ENDPOINT_ANALYZE_MAX_BUCKET_SIZE: usize = 1024;
+const DEFAULT_ENDPOINT_ANALYZE_MAX_SAMPLE_SIZE: usize = 100_000;
+const DEFAULT_ENDPOINT_ANALYZE_MAX_SKETCH_SIZE: usize = 100_000;
+const DEFAULT_ENDPOINT_ANALYZE_MAX_CMSKETCH_DEPTH: usize = 8;
+const DEFAULT_ENDPOINT_ANALYZE_MAX_CMSKETCH_WIDTH: usize = 8192;
+
+// Max number of snapshots being generated, sent and applied concurrently.
+const DEFAULT_SNAP_MAX_CONCURRENT_GENERATIONS: usize = 8;
+const DEFAULT_SNAP_MAX_CONCURRENT_SENDS: usize = 32;
+const DEFAULT_SNAP_MAX_CONCURRENT_APPLIES: usize = 8;
+
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
 #[serde(default)]
 #[serde(rename_all = "kebab-case")]
 pub struct Config {
     #[serde(skip)] pub cluster_id: u64,
 
+    // Skip the startup check of the persisted store id against PD, to let an
+    // existing store re-register into a freshly deployed cluster. Only
+    // settable from the command line on purpose, it must not be left on.
+    #[serde(skip)] pub force_new_cluster: bool,
+
     // Server listening address.
     pub addr: String,
 
@@ -62,14 +90,49 @@ pub struct Config {
     pub grpc_concurrent_stream: usize,
     pub grpc_raft_conn_num: usize,
     pub grpc_stream_initial_window_size: ReadableSize,
+    // CPUs the grpc poller threads are pinned to (linux only). Empty
+    // means no pinning. Keeping the pollers on one socket avoids the
+    // cross-NUMA traffic of a completion queue polled from the other one.
+    pub grpc_cpu_set: Vec<usize>,
+    // When non-zero, grpc-concurrency is ignored and the number of grpc
+    // completion queues is sized as this value times the number of NUMA
+    // nodes of the machine.
+    pub grpc_concurrency_per_numa_node: usize,
+    // When the messages or bytes queued in one raft connection exceed
+    // these windows, append and snapshot messages are dropped instead of
+    // queued; votes and heartbeats survive until twice the window, so
+    // congestion sheds the bulky replication traffic first instead of
+    // costing an election. 0 means no limit.
+    pub raft_client_max_pending_msgs: usize,
+    pub raft_client_max_pending_bytes: ReadableSize,
     pub end_point_concurrency: usize,
     pub end_point_max_tasks: usize,
     pub end_point_stack_size: ReadableSize,
     pub end_point_recursion_limit: u32,
     pub end_point_batch_row_limit: usize,
+    pub end_point_scan_seek_bound: usize,
     pub end_point_request_max_handle_duration: ReadableDuration,
+    pub end_point_analyze_max_bucket_size: usize,
+    pub end_point_analyze_max_sample_size: usize,
+    pub end_point_analyze_max_sketch_size: usize,
+    pub end_point_analyze_max_cmsketch_depth: usize,
+    pub end_point_analyze_max_cmsketch_width: usize,
+    // Collects RocksDB perf context counters around coprocessor reads
+    // and exposes them as metrics and in the slow query log, at the
+    // price of perf counter bookkeeping on every key a request touches.
+    pub end_point_perf_context: bool,
     pub snap_max_write_bytes_per_sec: ReadableSize,
     pub snap_max_total_size: ReadableSize,
+    // Max number of snapshots in each stage, 0 means no limit.
+    pub snap_max_concurrent_generations: usize,
+    pub snap_max_concurrent_sends: usize,
+    pub snap_max_concurrent_applies: usize,
+
+    // Audit log for mutating and raw operations. An empty path disables
+    // it; with sampling only one of every `audit-log-sample-every`
+    // entries is written.
+    pub audit_log_path: String,
+    pub audit_log_sample_every: usize,
 
     // Server labels to specify some attributes about this server.
     #[serde(with = "config::order_map_serde")] pub labels: HashMap<String, String>,
@@ -85,6 +148,7 @@ impl Default for Config {
         };
         Config {
             cluster_id: DEFAULT_CLUSTER_ID,
+            force_new_cluster: false,
             addr: DEFAULT_LISTENING_ADDR.to_owned(),
             labels: HashMap::default(),
             advertise_addr: DEFAULT_ADVERTISE_LISTENING_ADDR.to_owned(),
@@ -94,16 +158,32 @@ impl Default for Config {
             grpc_concurrent_stream: DEFAULT_GRPC_CONCURRENT_STREAM,
             grpc_raft_conn_num: DEFAULT_GRPC_RAFT_CONN_NUM,
             grpc_stream_initial_window_size: ReadableSize(DEFAULT_GRPC_STREAM_INITIAL_WINDOW_SIZE),
+            grpc_cpu_set: Vec::new(),
+            grpc_concurrency_per_numa_node: 0,
+            raft_client_max_pending_msgs: DEFAULT_RAFT_CLIENT_MAX_PENDING_MSGS,
+            raft_client_max_pending_bytes: ReadableSize(DEFAULT_RAFT_CLIENT_MAX_PENDING_BYTES),
             end_point_concurrency: concurrency,
             end_point_max_tasks: DEFAULT_MAX_RUNNING_TASK_COUNT,
             end_point_stack_size: ReadableSize::mb(DEFAULT_ENDPOINT_STACK_SIZE_MB),
             end_point_recursion_limit: 1000,
             end_point_batch_row_limit: DEFAULT_ENDPOINT_BATCH_ROW_LIMIT,
+            end_point_scan_seek_bound: DEFAULT_ENDPOINT_SCAN_SEEK_BOUND,
             end_point_request_max_handle_duration: ReadableDuration::secs(
                 DEFAULT_REQUEST_MAX_HANDLE_SECS,
             ),
+            end_point_analyze_max_bucket_size: DEFAULT_ENDPOINT_ANALYZE_MAX_BUCKET_SIZE,
+            end_point_analyze_max_sample_size: DEFAULT_ENDPOINT_ANALYZE_MAX_SAMPLE_SIZE,
+            end_point_analyze_max_sketch_size: DEFAULT_ENDPOINT_ANALYZE_MAX_SKETCH_SIZE,
+            end_point_analyze_max_cmsketch_depth: DEFAULT_ENDPOINT_ANALYZE_MAX_CMSKETCH_DEPTH,
+            end_point_analyze_max_cmsketch_width: DEFAULT_ENDPOINT_ANALYZE_MAX_CMSKETCH_WIDTH,
+            end_point_perf_context: false,
             snap_max_write_bytes_per_sec: ReadableSize(DEFAULT_SNAP_MAX_BYTES_PER_SEC),
             snap_max_total_size: ReadableSize(0),
+            snap_max_concurrent_generations: DEFAULT_SNAP_MAX_CONCURRENT_GENERATIONS,
+            snap_max_concurrent_sends: DEFAULT_SNAP_MAX_CONCURRENT_SENDS,
+            snap_max_concurrent_applies: DEFAULT_SNAP_MAX_CONCURRENT_APPLIES,
+            audit_log_path: String::new(),
+            audit_log_sample_every: 1,
         }
     }
 }
@@ -124,6 +204,15 @@ impl Config {
             ));
         }
 
+        if self.grpc_concurrency_per_numa_node > 0 {
+            let nodes = sys::numa_node_count();
+            self.grpc_concurrency = self.grpc_concurrency_per_numa_node * nodes;
+            info!(
+                "sizing grpc concurrency by numa topology: {} queues per node on {} nodes",
+                self.grpc_concurrency_per_numa_node, nodes
+            );
+        }
+
         if self.end_point_concurrency == 0 {
             return Err(box_err!("server.end-point-concurrency should not be 0."));
         }
@@ -144,12 +233,29 @@ impl Config {
             return Err(box_err!("server.end-point-recursion-limit is too small"));
         }
 
+        if self.end_point_scan_seek_bound == 0 {
+            return Err(box_err!("server.end-point-scan-seek-bound should not be 0."));
+        }
+
         if self.end_point_request_max_handle_duration.as_secs() < DEFAULT_REQUEST_MAX_HANDLE_SECS {
             return Err(box_err!(
                 "server.end-point-request-max-handle-secs is too small."
             ));
         }
 
+        if self.end_point_analyze_max_bucket_size == 0
+            || self.end_point_analyze_max_sample_size == 0
+            || self.end_point_analyze_max_sketch_size == 0
+        {
+            return Err(box_err!(
+                "server.end-point-analyze-max-* sizes should not be 0."
+            ));
+        }
+
+        if !self.audit_log_path.is_empty() && self.audit_log_sample_every == 0 {
+            return Err(box_err!("server.audit-log-sample-every should not be 0."));
+        }
+
         for (k, v) in &self.labels {
             validate_label(k, "key")?;
             validate_label(v, "value")?;
@@ -222,6 +328,10 @@ mod tests {
         invalid_cfg.end_point_request_max_handle_duration = ReadableDuration::secs(0);
         assert!(invalid_cfg.validate().is_err());
 
+        let mut invalid_cfg = cfg.clone();
+        invalid_cfg.end_point_analyze_max_sample_size = 0;
+        assert!(invalid_cfg.validate().is_err());
+
         invalid_cfg = Config::default();
         invalid_cfg.addr = "0.0.0.0:1000".to_owned();
         assert!(invalid_cfg.validate().is_err());
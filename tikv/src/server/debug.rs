@@ -11,15 +11,17 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::{error, result};
+use std::{cmp, error, result};
 use std::cmp::Ordering;
+use std::fs::File;
+use std::io::{Read, Write as IoWrite};
 use std::sync::Arc;
 use std::rc::Rc;
 use std::cell::RefCell;
 
-use protobuf::{self, RepeatedField};
+use protobuf::{self, CodedInputStream, CodedOutputStream, Message, RepeatedField};
 
-use rocksdb::{Kv, SeekKey, WriteBatch, WriteOptions, DB};
+use rocksdb::{Kv, Range, SeekKey, WriteBatch, WriteOptions, DB};
 use kvproto::metapb::Region;
 use kvproto::kvrpcpb::{LockInfo, MvccInfo, Op, ValueInfo, WriteInfo};
 use kvproto::debugpb::DB as DBType;
@@ -28,15 +30,16 @@ use kvproto::raft_serverpb::*;
 
 use raft::{self, RawNode};
 use raftstore::store::{keys, CacheQueryStats, Engines, Iterable, Peekable, PeerStorage};
-use raftstore::store::{init_apply_state, init_raft_state, write_peer_state};
+use raftstore::store::{init_apply_state, init_raft_state, load_apply_state, write_peer_state};
 use raftstore::store::util as raftstore_util;
 use raftstore::store::engine::IterOption;
-use storage::{is_short_value, CF_DEFAULT, CF_LOCK, CF_RAFT, CF_WRITE};
+use storage::{is_short_value, CF_APPLY, CF_DEFAULT, CF_LOCK, CF_RAFT, CF_WRITE, LARGE_CFS};
 use storage::types::{truncate_ts, Key};
 use storage::mvcc::{Lock, Write, WriteType};
 use util::escape;
 use util::config::ReadableSize;
 use util::rocksdb::{compact_range, get_cf_handle};
+use util::rocksdb::properties::SizeProperties;
 use util::worker::Worker;
 
 pub type Result<T> = result::Result<T, Error>;
@@ -152,12 +155,10 @@ impl Debugger {
                 .get_msg::<RaftLocalState>(&raft_state_key)
         );
 
-        let apply_state_key = keys::apply_state_key(region_id);
-        let apply_state = box_try!(
-            self.engines
-                .kv_engine
-                .get_msg_cf::<RaftApplyState>(CF_RAFT, &apply_state_key)
-        );
+        let apply_state = box_try!(load_apply_state(
+            self.engines.kv_engine.as_ref(),
+            region_id
+        ));
 
         let region_state_key = keys::region_state_key(region_id);
         let region_state = box_try!(
@@ -210,6 +211,64 @@ impl Debugger {
         }
     }
 
+    /// Returns the approximate size in bytes and number of keys of the
+    /// large CFs within the raw key range `[start, end)`, reported per
+    /// region as `(region_id, size, keys)`. The numbers come from
+    /// RocksDB table properties and memtable stats, no data is scanned,
+    /// so capacity tools can ask how big an arbitrary range is on this
+    /// store cheaply. An empty `end` means the end of the data range.
+    /// The debug protocol has no RPC for this yet, it is reachable for
+    /// embedding callers only.
+    pub fn range_size(&self, start: &[u8], end: &[u8]) -> Result<Vec<(u64, u64, u64)>> {
+        if !end.is_empty() && start > end {
+            return Err(Error::InvalidArgument(
+                "start key is larger than end key".to_owned(),
+            ));
+        }
+        let range_start = keys::data_key(start);
+        let range_end = keys::data_end_key(end);
+
+        let db = &self.engines.kv_engine;
+        let mut regions = Vec::new();
+        box_try!(db.scan_cf(
+            CF_RAFT,
+            keys::REGION_META_MIN_KEY,
+            keys::REGION_META_MAX_KEY,
+            false,
+            &mut |key, value| {
+                let (_, suffix) = keys::decode_region_meta_key(key)?;
+                if suffix != keys::REGION_STATE_SUFFIX {
+                    return Ok(true);
+                }
+                let state = protobuf::parse_from_bytes::<RegionLocalState>(value)?;
+                if state.get_state() == PeerState::Normal {
+                    regions.push(state.get_region().clone());
+                }
+                Ok(true)
+            }
+        ));
+
+        let mut res = Vec::new();
+        for region in regions {
+            // Clamp the region to the requested range, regions outside
+            // of it are skipped entirely.
+            let start_key = cmp::max(keys::enc_start_key(&region), range_start.clone());
+            let end_key = cmp::min(keys::enc_end_key(&region), range_end.clone());
+            if start_key >= end_key {
+                continue;
+            }
+            let mut size = 0;
+            let mut keys = 0;
+            for cf in LARGE_CFS {
+                let (s, k) = range_size_cf(db, cf, &start_key, &end_key)?;
+                size += s;
+                keys += k;
+            }
+            res.push((region.get_id(), size, keys));
+        }
+        Ok(res)
+    }
+
     pub fn scan_mvcc(&self, start: &[u8], end: &[u8], limit: u64) -> Result<MvccInfoIterator> {
         if end.is_empty() && limit == 0 {
             return Err(Error::InvalidArgument("no limit and to_key".to_owned()));
@@ -326,6 +385,212 @@ impl Debugger {
         Ok(res)
     }
 
+    /// Scan the whole mvcc range for default CF values that no lock and
+    /// no write CF record refers to. Such values are left behind when a
+    /// GC write batch was only partially applied before a crash, they are
+    /// invisible to every read and ordinary GC never visits them again.
+    /// Returns how many values were examined and how many of them are
+    /// orphaned. With `clean` set the orphaned values are deleted.
+    pub fn orphaned_values(&self, clean: bool) -> Result<(u64, u64)> {
+        let db = &self.engines.kv_engine;
+        let readopts = IterOption::new(
+            Some(keys::DATA_MIN_KEY.to_vec()),
+            Some(keys::DATA_MAX_KEY.to_vec()),
+            false,
+        ).build_read_opts();
+        let handle = box_try!(get_cf_handle(db, CF_DEFAULT));
+        let mut iter = DBIterator::new_cf(Arc::clone(db), handle, readopts);
+        iter.seek(SeekKey::from(keys::DATA_MIN_KEY));
+
+        let wb = WriteBatch::new();
+        let mut scanned = 0;
+        let mut orphans = 0;
+        while let Some((prefix, versions)) = MvccInfoIterator::next_grouped(&mut iter) {
+            let referenced = self.referenced_versions(&prefix)?;
+            for (key, _) in versions {
+                scanned += 1;
+                let encoded_key = Key::from_encoded(keys::origin_key(&key).to_owned());
+                let start_ts = box_try!(encoded_key.decode_ts());
+                if referenced.contains(&start_ts) {
+                    continue;
+                }
+                orphans += 1;
+                if clean {
+                    box_try!(wb.delete_cf(handle, &key));
+                }
+            }
+        }
+        if clean && orphans > 0 {
+            let mut write_opts = WriteOptions::new();
+            write_opts.set_sync(true);
+            box_try!(db.write_opt(wb, &write_opts));
+        }
+        Ok((scanned, orphans))
+    }
+
+    /// All start_ts of `data_key`'s versions that a lock or a write CF
+    /// record still refers to. Rollback records are included, their
+    /// values are reclaimed by ordinary GC.
+    fn referenced_versions(&self, data_key: &[u8]) -> Result<Vec<u64>> {
+        let db = &self.engines.kv_engine;
+        let mut refs = Vec::new();
+        if let Some(value) = box_try!(db.get_value_cf(CF_LOCK, data_key)) {
+            let lock = box_try!(Lock::parse(&value));
+            refs.push(lock.ts);
+        }
+        let handle = box_try!(get_cf_handle(db, CF_WRITE));
+        let readopts =
+            IterOption::new(None, Some(keys::DATA_MAX_KEY.to_vec()), false).build_read_opts();
+        let mut iter = DBIterator::new_cf(Arc::clone(db), handle, readopts);
+        iter.seek(SeekKey::from(data_key));
+        while iter.valid() && iter.key().starts_with(data_key) {
+            let write = box_try!(Write::parse(iter.value()));
+            refs.push(write.start_ts);
+            iter.next();
+        }
+        Ok(refs)
+    }
+
+    /// Export RegionLocalState and RaftApplyState of all regions on this
+    /// store into a portable file, so region meta can be carried to a new
+    /// machine together with a copy of the data.
+    pub fn export_region_meta(&self, path: &str) -> Result<usize> {
+        let db = &self.engines.kv_engine;
+        let mut count = 0;
+        let mut buf = Vec::with_capacity(4096);
+        {
+            let mut os = CodedOutputStream::vec(&mut buf);
+            box_try!(db.scan_cf(
+                CF_RAFT,
+                keys::REGION_META_MIN_KEY,
+                keys::REGION_META_MAX_KEY,
+                false,
+                &mut |key, value| {
+                    let (region_id, suffix) = keys::decode_region_meta_key(key)?;
+                    if suffix != keys::REGION_STATE_SUFFIX {
+                        return Ok(true);
+                    }
+                    let region_state = protobuf::parse_from_bytes::<RegionLocalState>(value)?;
+                    let apply_state = load_apply_state(db.as_ref(), region_id)?
+                        .unwrap_or_else(RaftApplyState::new);
+                    region_state.write_length_delimited_to(&mut os)?;
+                    apply_state.write_length_delimited_to(&mut os)?;
+                    count += 1;
+                    Ok(true)
+                }
+            ));
+            box_try!(os.flush());
+        }
+
+        let mut f = box_try!(File::create(path));
+        box_try!(f.write_all(&buf));
+        box_try!(f.sync_all());
+        Ok(count)
+    }
+
+    /// Import region meta exported by `export_region_meta`. Existing states
+    /// of the same regions are overwritten.
+    pub fn import_region_meta(&self, path: &str) -> Result<usize> {
+        let records = self.read_region_meta(path)?;
+
+        let db = &self.engines.kv_engine;
+        let raft_handle = box_try!(get_cf_handle(db, CF_RAFT));
+        let apply_handle = box_try!(get_cf_handle(db, CF_APPLY));
+        let wb = WriteBatch::new();
+        let count = records.len();
+        for (region_state, apply_state) in records {
+            let region_id = region_state.get_region().get_id();
+            box_try!(wb.put_msg_cf(
+                raft_handle,
+                &keys::region_state_key(region_id),
+                &region_state
+            ));
+            // An uninitialized apply state was exported for the region.
+            if apply_state.get_applied_index() > 0 {
+                box_try!(wb.put_msg_cf(
+                    apply_handle,
+                    &keys::apply_state_key(region_id),
+                    &apply_state
+                ));
+            }
+        }
+        let mut write_opts = WriteOptions::new();
+        write_opts.set_sync(true);
+        box_try!(db.write_opt(wb, &write_opts));
+        Ok(count)
+    }
+
+    /// Check region meta exported by `export_region_meta` against the
+    /// current store. Returns the regions whose states differ and why.
+    pub fn verify_region_meta(&self, path: &str) -> Result<Vec<(u64, String)>> {
+        let records = self.read_region_meta(path)?;
+
+        let db = &self.engines.kv_engine;
+        let mut res = Vec::new();
+        for (region_state, apply_state) in records {
+            let region_id = region_state.get_region().get_id();
+            match box_try!(
+                db.get_msg_cf::<RegionLocalState>(CF_RAFT, &keys::region_state_key(region_id))
+            ) {
+                Some(ref current) if *current == region_state => {}
+                Some(_) => res.push((region_id, "region state not match".to_owned())),
+                None => {
+                    res.push((region_id, "region state not found".to_owned()));
+                    continue;
+                }
+            }
+            if apply_state.get_applied_index() == 0 {
+                continue;
+            }
+            match box_try!(load_apply_state(db.as_ref(), region_id)) {
+                Some(ref current) if *current == apply_state => {}
+                Some(_) => res.push((region_id, "apply state not match".to_owned())),
+                None => res.push((region_id, "apply state not found".to_owned())),
+            }
+        }
+        Ok(res)
+    }
+
+    fn read_region_meta(&self, path: &str) -> Result<Vec<(RegionLocalState, RaftApplyState)>> {
+        let mut buf = Vec::new();
+        let mut f = box_try!(File::open(path));
+        box_try!(f.read_to_end(&mut buf));
+
+        let mut records = Vec::new();
+        let mut is = CodedInputStream::from_bytes(&buf);
+        while !box_try!(is.eof()) {
+            let region_state = box_try!(protobuf::parse_length_delimited_from::<RegionLocalState>(
+                &mut is
+            ));
+            let apply_state = box_try!(protobuf::parse_length_delimited_from::<RaftApplyState>(
+                &mut is
+            ));
+            records.push((region_state, apply_state));
+        }
+        Ok(records)
+    }
+
+    /// Change a config value of a running engine. Only the RocksDB rate
+    /// limiter is dynamic today, everything else needs a restart.
+    pub fn modify_tikv_config(&self, module: &str, name: &str, value: &str) -> Result<()> {
+        let db = match module {
+            "kvdb" => &self.engines.kv_engine,
+            "raftdb" => &self.engines.raft_engine,
+            _ => return Err(Error::InvalidArgument(format!("invalid module: {}", module))),
+        };
+        match name {
+            "rate-bytes-per-sec" => {
+                let rate: ReadableSize = value.parse().map_err(Error::InvalidArgument)?;
+                box_try!(db.get_db_options().set_rate_bytes_per_sec(rate.0 as i64));
+                Ok(())
+            }
+            _ => Err(Error::InvalidArgument(format!(
+                "{} is not dynamically changeable",
+                name
+            ))),
+        }
+    }
+
     fn get_store_id(&self) -> Result<u64> {
         let db = &self.engines.kv_engine;
         db.get_msg::<StoreIdent>(keys::STORE_IDENT_KEY)
@@ -508,6 +773,7 @@ pub fn validate_db_and_cf(db: DBType, cf: &str) -> Result<()> {
         | (DBType::KV, CF_WRITE)
         | (DBType::KV, CF_LOCK)
         | (DBType::KV, CF_RAFT)
+        | (DBType::KV, CF_APPLY)
         | (DBType::RAFT, CF_DEFAULT) => Ok(()),
         _ => Err(Error::InvalidArgument(format!(
             "invalid cf {:?} for db {:?}",
@@ -516,6 +782,27 @@ pub fn validate_db_and_cf(db: DBType, cf: &str) -> Result<()> {
     }
 }
 
+/// Approximate `(size, keys)` of one cf in `[start, end)` of data keys,
+/// from the memtable stats and the size properties of the tables that
+/// overlap the range.
+fn range_size_cf(db: &DB, cfname: &str, start: &[u8], end: &[u8]) -> Result<(u64, u64)> {
+    let handle = box_try!(get_cf_handle(db, cfname));
+    let range = Range::new(start, end);
+    let (mut keys, mut size) = db.get_approximate_memtable_stats_cf(handle, &range);
+    let collection = box_try!(db.get_properties_of_tables_in_range(handle, &[range]));
+    for (_, v) in &*collection {
+        let props = box_try!(SizeProperties::decode(v.user_collected_properties()));
+        let in_range = props.get_approximate_size_in_range(start, end);
+        size += in_range;
+        // The entry count of a table is not indexed by key, it is
+        // prorated by the covered share of the table's size.
+        if props.total_size > 0 {
+            keys += v.num_entries() * in_range / props.total_size;
+        }
+    }
+    Ok((size, keys))
+}
+
 fn set_region_tombstone(db: &DB, store_id: u64, region: Region, wb: &WriteBatch) -> Result<()> {
     let id = region.get_id();
     let key = keys::region_state_key(id);
@@ -567,7 +854,7 @@ mod tests {
     use tempdir::TempDir;
 
     use raftstore::store::engine::Mutable;
-    use storage::{CF_DEFAULT, CF_LOCK, CF_RAFT, CF_WRITE};
+    use storage::{CF_APPLY, CF_DEFAULT, CF_LOCK, CF_RAFT, CF_WRITE};
     use storage::mvcc::{Lock, LockType};
     use util::rocksdb::{self as rocksdb_util, CFOptions};
     use super::*;
@@ -605,6 +892,7 @@ mod tests {
             (DBType::KV, CF_WRITE),
             (DBType::KV, CF_LOCK),
             (DBType::KV, CF_RAFT),
+            (DBType::KV, CF_APPLY),
             (DBType::RAFT, CF_DEFAULT),
         ];
         for (db, cf) in valid_cases {
@@ -635,6 +923,7 @@ mod tests {
                     CFOptions::new(CF_WRITE, ColumnFamilyOptions::new()),
                     CFOptions::new(CF_LOCK, ColumnFamilyOptions::new()),
                     CFOptions::new(CF_RAFT, ColumnFamilyOptions::new()),
+                    CFOptions::new(CF_APPLY, ColumnFamilyOptions::new()),
                 ],
             ).unwrap(),
         );
@@ -696,6 +985,7 @@ mod tests {
         let raft_engine = &debugger.engines.raft_engine;
         let kv_engine = &debugger.engines.kv_engine;
         let raft_cf = kv_engine.cf_handle(CF_RAFT).unwrap();
+        let apply_cf = kv_engine.cf_handle(CF_APPLY).unwrap();
         let region_id = 1;
 
         let raft_state_key = keys::raft_state_key(region_id);
@@ -714,11 +1004,11 @@ mod tests {
         let mut apply_state = RaftApplyState::new();
         apply_state.set_applied_index(42);
         kv_engine
-            .put_msg_cf(raft_cf, &apply_state_key, &apply_state)
+            .put_msg_cf(apply_cf, &apply_state_key, &apply_state)
             .unwrap();
         assert_eq!(
             kv_engine
-                .get_msg_cf::<RaftApplyState>(CF_RAFT, &apply_state_key)
+                .get_msg_cf::<RaftApplyState>(CF_APPLY, &apply_state_key)
                 .unwrap()
                 .unwrap(),
             apply_state
@@ -781,6 +1071,68 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_range_size() {
+        let tmp = TempDir::new("test_debug_range_size").unwrap();
+        let path = tmp.path().to_str().unwrap();
+        let mut large_cf_opts = ColumnFamilyOptions::new();
+        let f = Box::new(SizePropertiesCollectorFactory::default());
+        large_cf_opts.add_table_properties_collector_factory("tikv.size-collector", f);
+        let engine = Arc::new(
+            rocksdb_util::new_engine_opt(
+                path,
+                DBOptions::new(),
+                vec![
+                    CFOptions::new(CF_DEFAULT, large_cf_opts.clone()),
+                    CFOptions::new(CF_WRITE, large_cf_opts),
+                    CFOptions::new(CF_LOCK, ColumnFamilyOptions::new()),
+                    CFOptions::new(CF_RAFT, ColumnFamilyOptions::new()),
+                    CFOptions::new(CF_APPLY, ColumnFamilyOptions::new()),
+                ],
+            ).unwrap(),
+        );
+        let engines = Engines::new(Arc::clone(&engine), Arc::clone(&engine));
+        let debugger = Debugger::new(engines);
+
+        // Two regions covering [a, m) and [m, z).
+        for &(id, start, end) in &[(1, b"a", b"m"), (2, b"m", b"z")] {
+            let mut region = metapb::Region::new();
+            region.set_id(id);
+            region.set_start_key(start.to_vec());
+            region.set_end_key(end.to_vec());
+            let mut state = RegionLocalState::new();
+            state.set_region(region);
+            let cf_raft = engine.cf_handle(CF_RAFT).unwrap();
+            engine
+                .put_msg_cf(cf_raft, &keys::region_state_key(id), &state)
+                .unwrap();
+        }
+
+        for cf in LARGE_CFS {
+            let handle = engine.cf_handle(cf).unwrap();
+            for k in &[keys::data_key(b"b"), keys::data_key(b"n")] {
+                engine.put_cf(handle, k, &[0; 1024]).unwrap();
+            }
+            engine.flush_cf(handle, true).unwrap();
+        }
+
+        // The full range covers one key per large cf in each region.
+        let entries = debugger.range_size(b"", b"").unwrap();
+        assert_eq!(entries.len(), 2);
+        for &(id, size, keys) in &entries {
+            assert!(id == 1 || id == 2);
+            assert!(size > 1024 * LARGE_CFS.len() as u64);
+            assert_eq!(keys, LARGE_CFS.len() as u64);
+        }
+
+        // A sub range only reports the regions it overlaps.
+        let entries = debugger.range_size(b"a", b"c").unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].0, 1);
+
+        assert!(debugger.range_size(b"z", b"a").is_err());
+    }
+
     #[test]
     fn test_scan_mvcc() {
         let debugger = new_debugger();
@@ -802,7 +1154,7 @@ mod tests {
         for &(prefix, tp, value, version) in &cf_lock_data {
             let encoded_key = Key::from_raw(prefix);
             let key = keys::data_key(encoded_key.encoded().as_slice());
-            let lock = Lock::new(tp, value.to_vec(), version, 0, None);
+            let lock = Lock::new(tp, value.to_vec(), version, 0, None, 0);
             let value = lock.to_bytes();
             engine
                 .put_cf(lock_cf, key.as_slice(), value.as_slice())
@@ -834,6 +1186,44 @@ mod tests {
         assert_eq!(count, 7);
     }
 
+    #[test]
+    fn test_orphaned_values() {
+        let debugger = new_debugger();
+        let engine = &debugger.engines.kv_engine;
+
+        // k1@5 is referenced by a write record, k2@10 by a lock and
+        // k3@15 by nothing at all.
+        let cf_default_data = vec![(b"k1", b"v", 5), (b"k2", b"x", 10), (b"k3", b"y", 15)];
+        for &(prefix, value, ts) in &cf_default_data {
+            let encoded_key = Key::from_raw(prefix).append_ts(ts);
+            let key = keys::data_key(encoded_key.encoded().as_slice());
+            engine.put(key.as_slice(), value).unwrap();
+        }
+
+        let write_cf = engine.cf_handle(CF_WRITE).unwrap();
+        let encoded_key = Key::from_raw(b"k1").append_ts(10);
+        let key = keys::data_key(encoded_key.encoded().as_slice());
+        let write = Write::new(WriteType::Put, 5, None);
+        engine
+            .put_cf(write_cf, key.as_slice(), write.to_bytes().as_slice())
+            .unwrap();
+
+        let lock_cf = engine.cf_handle(CF_LOCK).unwrap();
+        let encoded_key = Key::from_raw(b"k2");
+        let key = keys::data_key(encoded_key.encoded().as_slice());
+        let lock = Lock::new(LockType::Put, b"k2".to_vec(), 10, 0, None, 0);
+        engine
+            .put_cf(lock_cf, key.as_slice(), lock.to_bytes().as_slice())
+            .unwrap();
+
+        assert_eq!(debugger.orphaned_values(false).unwrap(), (3, 1));
+        // A dry run must not delete anything.
+        assert_eq!(debugger.orphaned_values(false).unwrap(), (3, 1));
+
+        assert_eq!(debugger.orphaned_values(true).unwrap(), (3, 1));
+        assert_eq!(debugger.orphaned_values(false).unwrap(), (2, 0));
+    }
+
     #[test]
     fn test_tombstone_regions() {
         let debugger = new_debugger();
@@ -894,6 +1284,7 @@ mod tests {
 
         let wb2 = WriteBatch::new();
         let handle2 = get_cf_handle(kv_engine, CF_RAFT).unwrap();
+        let apply_handle = get_cf_handle(kv_engine, CF_APPLY).unwrap();
 
         {
             let mock_region_state = |region_id: u64, peers: &[u64]| {
@@ -926,7 +1317,7 @@ mod tests {
                 let raft_apply_key = keys::apply_state_key(region_id);
                 let mut apply_state = RaftApplyState::new();
                 apply_state.set_applied_index(apply_index);
-                wb2.put_msg_cf(handle2, &raft_apply_key, &apply_state)
+                wb2.put_msg_cf(apply_handle, &raft_apply_key, &apply_state)
                     .unwrap();
             };
 
@@ -956,4 +1347,55 @@ mod tests {
             assert_eq!(region_id, (10 + i) as u64);
         }
     }
+
+    #[test]
+    fn test_region_meta_export_import() {
+        let debugger = new_debugger();
+        let kv_engine = debugger.engines.kv_engine.as_ref();
+        let raft_cf = kv_engine.cf_handle(CF_RAFT).unwrap();
+        let apply_cf = kv_engine.cf_handle(CF_APPLY).unwrap();
+
+        for &region_id in &[1, 2, 3] {
+            let mut region_state = RegionLocalState::new();
+            region_state.mut_region().set_id(region_id);
+            kv_engine
+                .put_msg_cf(raft_cf, &keys::region_state_key(region_id), &region_state)
+                .unwrap();
+            let mut apply_state = RaftApplyState::new();
+            apply_state.set_applied_index(region_id * 10);
+            kv_engine
+                .put_msg_cf(apply_cf, &keys::apply_state_key(region_id), &apply_state)
+                .unwrap();
+        }
+
+        let tmp = TempDir::new("test_region_meta").unwrap();
+        let file = tmp.path().join("meta");
+        let path = file.to_str().unwrap();
+        assert_eq!(debugger.export_region_meta(path).unwrap(), 3);
+        assert!(debugger.verify_region_meta(path).unwrap().is_empty());
+
+        // Change one region and drop another, then verify should complain
+        // and import should restore both.
+        let mut apply_state = RaftApplyState::new();
+        apply_state.set_applied_index(42);
+        kv_engine
+            .put_msg_cf(apply_cf, &keys::apply_state_key(2), &apply_state)
+            .unwrap();
+        kv_engine
+            .delete_cf(raft_cf, &keys::region_state_key(3))
+            .unwrap();
+
+        let mismatches = debugger.verify_region_meta(path).unwrap();
+        assert_eq!(mismatches.len(), 2);
+        assert_eq!(mismatches[0].0, 2);
+        assert_eq!(mismatches[1].0, 3);
+
+        assert_eq!(debugger.import_region_meta(path).unwrap(), 3);
+        assert!(debugger.verify_region_meta(path).unwrap().is_empty());
+        let apply_state = kv_engine
+            .get_msg_cf::<RaftApplyState>(CF_APPLY, &keys::apply_state_key(2))
+            .unwrap()
+            .unwrap();
+        assert_eq!(apply_state.get_applied_index(), 20);
+    }
 }
@@ -21,6 +21,7 @@ use protobuf::ProtobufError;
 use grpc::Error as GrpcError;
 
 use util::codec::Error as CodecError;
+use util::error_code::{self, ErrorCode, ErrorCodeExt};
 use util::worker::ScheduleError;
 use raftstore::Error as RaftServerError;
 use storage::engine::Error as EngineError;
@@ -113,3 +114,15 @@ quick_error!{
 }
 
 pub type Result<T> = result::Result<T, Error>;
+
+impl Error {
+    /// Returns the stable code of the wrapped error, if it has one.
+    pub fn error_code(&self) -> Option<ErrorCode> {
+        match *self {
+            Error::RaftServer(ref e) => Some(e.error_code()),
+            Error::Storage(ref e) => Some(e.error_code()),
+            Error::Engine(_) => Some(error_code::storage::ENGINE),
+            _ => None,
+        }
+    }
+}
@@ -13,6 +13,8 @@
 
 use prometheus::*;
 
+use util::metrics::latency_buckets;
+
 lazy_static! {
     pub static ref SEND_SNAP_HISTOGRAM: Histogram =
         register_histogram!(
@@ -27,12 +29,35 @@ lazy_static! {
             &["type"]
         ).unwrap();
 
+    pub static ref SNAP_TRANSFER_BYTES_VEC: CounterVec =
+        register_counter_vec!(
+            "tikv_server_snapshot_transfer_bytes_total",
+            "Total bytes of snapshot data sent and received",
+            &["type"]
+        ).unwrap();
+
     pub static ref GRPC_MSG_HISTOGRAM_VEC: HistogramVec =
         register_histogram_vec!(
             "tikv_grpc_msg_duration_seconds",
             "Bucketed histogram of grpc server messages",
             &["type"],
-            exponential_buckets(0.0005, 2.0, 20).unwrap()
+            latency_buckets()
+        ).unwrap();
+
+    pub static ref GRPC_REQ_SIZE_HISTOGRAM_VEC: HistogramVec =
+        register_histogram_vec!(
+            "tikv_grpc_req_size_bytes",
+            "Bucketed histogram of grpc request sizes",
+            &["type"],
+            exponential_buckets(16.0, 2.0, 22).unwrap()
+        ).unwrap();
+
+    pub static ref GRPC_RESP_SIZE_HISTOGRAM_VEC: HistogramVec =
+        register_histogram_vec!(
+            "tikv_grpc_resp_size_bytes",
+            "Bucketed histogram of grpc response sizes",
+            &["type"],
+            exponential_buckets(16.0, 2.0, 22).unwrap()
         ).unwrap();
 
     pub static ref GRPC_MSG_FAIL_COUNTER: CounterVec =
@@ -48,6 +73,13 @@ lazy_static! {
             "Total number of raft messages received"
         ).unwrap();
 
+    pub static ref RAFT_MESSAGE_DROP_COUNTER: CounterVec =
+        register_counter_vec!(
+            "tikv_server_raft_message_drop_total",
+            "Total number of raft messages dropped by the raft client",
+            &["priority"]
+        ).unwrap();
+
     pub static ref RESOLVE_STORE_COUNTER: CounterVec =
         register_counter_vec!(
             "tikv_server_resolve_store_total",
@@ -17,6 +17,7 @@ mod metrics;
 mod service;
 mod raft_client;
 
+pub mod audit;
 pub mod readpool;
 pub mod config;
 pub mod errors;
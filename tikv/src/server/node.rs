@@ -15,7 +15,6 @@ use std::thread;
 use std::sync::{mpsc, Arc};
 use std::sync::mpsc::Receiver;
 use std::time::Duration;
-use std::process;
 
 use mio::EventLoop;
 
@@ -23,6 +22,8 @@ use pd::{Error as PdError, PdClient, PdTask, INVALID_ID};
 use kvproto::raft_serverpb::StoreIdent;
 use kvproto::metapb;
 use protobuf::RepeatedField;
+use util::collections::HashMap;
+use util::rocksdb::RegionBoundaries;
 use util::transport::SendCh;
 use util::worker::FutureWorker;
 use raftstore::coprocessor::dispatcher::CoprocessorHost;
@@ -69,6 +70,7 @@ fn check_region_epoch(region: &metapb::Region, other: &metapb::Region) -> Result
 // TODO: we will rename another better name like RaftStore later.
 pub struct Node<C: PdClient + 'static> {
     cluster_id: u64,
+    force_new_cluster: bool,
     store: metapb::Store,
     store_cfg: StoreConfig,
     store_handle: Option<thread::JoinHandle<()>>,
@@ -107,9 +109,11 @@ where
         }
         store.set_labels(RepeatedField::from_vec(labels));
 
-        let ch = SendCh::new(event_loop.channel(), "raftstore");
+        let mut ch = SendCh::new(event_loop.channel(), "raftstore");
+        ch.set_capacity(store_cfg.notify_capacity);
         Node {
             cluster_id: cfg.cluster_id,
+            force_new_cluster: cfg.force_new_cluster,
             store: store,
             store_cfg: store_cfg.clone(),
             store_handle: None,
@@ -128,6 +132,7 @@ where
         significant_msg_receiver: Receiver<SignificantMsg>,
         pd_worker: FutureWorker<PdTask>,
         coprocessor_host: CoprocessorHost,
+        region_boundaries: RegionBoundaries,
     ) -> Result<()>
     where
         T: Transport + 'static,
@@ -137,22 +142,39 @@ where
         if store_id == INVALID_ID {
             store_id = self.bootstrap_store(&engines)?;
         } else if !bootstrapped {
-            // We have saved data before, and the cluster must be bootstrapped.
-            return Err(box_err!(
-                "store {} is not empty, but cluster {} is not bootstrapped, \
-                 maybe you connected a wrong PD or need to remove the TiKV data \
-                 and start again",
-                store_id,
-                self.cluster_id
-            ));
+            if !self.force_new_cluster {
+                // We have saved data before, and the cluster must be bootstrapped.
+                return Err(box_err!(
+                    "store {} is not empty, but cluster {} is not bootstrapped, \
+                     maybe you connected a wrong PD or need to remove the TiKV data \
+                     and start again, or pass --force-new-cluster if this PD \
+                     deployment really is a new cluster for this store",
+                    store_id,
+                    self.cluster_id
+                ));
+            }
+            warn!(
+                "store {} has data but cluster {} is not bootstrapped, \
+                 continuing because force-new-cluster is set",
+                store_id, self.cluster_id
+            );
+        } else {
+            self.validate_store(store_id)?;
         }
 
         self.store.set_id(store_id);
-        self.check_prepare_bootstrap_cluster(&engines)?;
-        if !bootstrapped {
-            // cluster is not bootstrapped, and we choose first store to bootstrap
-            // prepare bootstrap.
-            let region = self.prepare_bootstrap_cluster(&engines, store_id)?;
+        if bootstrapped {
+            self.check_prepare_bootstrap_cluster(&engines)?;
+        } else {
+            // The cluster is not bootstrapped yet, so a bootstrap prepared by
+            // a crashed run can be rolled forward directly instead of asking
+            // PD about a region it has never heard of.
+            let region = match store::recover_prepare_bootstrap(&engines, store_id)? {
+                Some(region) => region,
+                // cluster is not bootstrapped, and we choose first store to
+                // bootstrap prepare bootstrap.
+                None => self.prepare_bootstrap_cluster(&engines, store_id)?,
+            };
             self.bootstrap_cluster(&engines, region)?;
         }
 
@@ -167,6 +189,7 @@ where
             significant_msg_receiver,
             pd_worker,
             coprocessor_host,
+            region_boundaries,
         )?;
         Ok(())
     }
@@ -179,6 +202,31 @@ where
         self.ch.clone()
     }
 
+    // Update the store labels and report them to PD, so topology relabeling
+    // (zone/rack/host) doesn't require a restart. Labels only travel in
+    // `put_store`, the periodic store heartbeat carries statistics, so
+    // they are pushed here instead of waiting for the next heartbeat.
+    pub fn refresh_store_labels(&mut self, new_labels: &HashMap<String, String>) -> Result<()> {
+        let mut labels = Vec::new();
+        for (k, v) in new_labels {
+            let mut label = metapb::StoreLabel::new();
+            label.set_key(k.to_owned());
+            label.set_value(v.to_owned());
+            labels.push(label);
+        }
+        if self.store.get_labels() == labels.as_slice() {
+            return Ok(());
+        }
+        info!(
+            "store {} labels change to {:?}",
+            self.store.get_id(),
+            new_labels
+        );
+        self.store.set_labels(RepeatedField::from_vec(labels));
+        self.pd_client.put_store(self.store.clone())?;
+        Ok(())
+    }
+
     // check store, return store id for the engine.
     // If the store is not bootstrapped, use INVALID_ID.
     fn check_store(&self, engines: &Engines) -> Result<u64> {
@@ -191,13 +239,14 @@ where
 
         let ident = res.unwrap();
         if ident.get_cluster_id() != self.cluster_id {
-            error!(
+            // No overriding this one, not even with force-new-cluster: mixing
+            // data of two clusters corrupts both.
+            return Err(box_err!(
                 "cluster ID mismatch: local_id {} remote_id {}. \
                  you are trying to connect to another cluster, please reconnect to the correct PD",
                 ident.get_cluster_id(),
                 self.cluster_id
-            );
-            process::exit(1);
+            ));
         }
 
         let store_id = ident.get_store_id();
@@ -208,6 +257,56 @@ where
         Ok(store_id)
     }
 
+    // Verify the persisted store id against PD, so that a store accidentally
+    // pointed at the wrong PD fails fast instead of corrupting metadata. The
+    // store is only checked, no data is touched here.
+    fn validate_store(&self, store_id: u64) -> Result<()> {
+        for _ in 0..MAX_CHECK_CLUSTER_BOOTSTRAPPED_RETRY_COUNT {
+            match self.pd_client.get_store(store_id) {
+                Ok(store) => {
+                    if store.get_state() == metapb::StoreState::Tombstone {
+                        return Err(box_err!(
+                            "store {} is tombstoned in cluster {}, remove the data \
+                             directory to start a fresh store",
+                            store_id,
+                            self.cluster_id
+                        ));
+                    }
+                    if store.get_address() != self.store.get_address() {
+                        warn!(
+                            "store {} address changed from {} to {}",
+                            store_id,
+                            store.get_address(),
+                            self.store.get_address()
+                        );
+                    }
+                    return Ok(());
+                }
+                Err(e) => {
+                    if self.force_new_cluster {
+                        warn!(
+                            "store {} is unknown to PD ({:?}), continuing because \
+                             force-new-cluster is set",
+                            store_id, e
+                        );
+                        return Ok(());
+                    }
+                    warn!("validate store {} against pd failed: {:?}", store_id, e);
+                }
+            }
+            thread::sleep(Duration::from_secs(
+                CHECK_CLUSTER_BOOTSTRAPPED_RETRY_SECONDS,
+            ));
+        }
+        Err(box_err!(
+            "store {} is unknown to cluster {}, this store has local data and \
+             joining a wrong PD would corrupt its metadata, check the PD endpoints \
+             or pass --force-new-cluster to re-register the store",
+            store_id,
+            self.cluster_id
+        ))
+    }
+
     fn alloc_id(&self) -> Result<u64> {
         let id = self.pd_client.alloc_id()?;
         Ok(id)
@@ -318,6 +417,7 @@ where
         significant_msg_receiver: Receiver<SignificantMsg>,
         pd_worker: FutureWorker<PdTask>,
         coprocessor_host: CoprocessorHost,
+        region_boundaries: RegionBoundaries,
     ) -> Result<()>
     where
         T: Transport + 'static,
@@ -350,6 +450,7 @@ where
                 snap_mgr,
                 pd_worker,
                 coprocessor_host,
+                region_boundaries,
             ) {
                 Err(e) => panic!("construct store {} err {:?}", store_id, e),
                 Ok(s) => s,
@@ -19,8 +19,10 @@ use futures::sync::mpsc::{self, UnboundedSender};
 use futures::sync::oneshot::{self, Sender};
 use futures::{stream, Future, Sink, Stream};
 use grpc::{ChannelBuilder, Environment, WriteFlags};
+use kvproto::eraftpb::MessageType;
 use kvproto::raft_serverpb::RaftMessage;
 use kvproto::tikvpb_grpc::TikvClient;
+use protobuf::Message;
 
 use util::collections::HashMap;
 use util::security::SecurityManager;
@@ -38,6 +40,10 @@ struct Conn {
     buffer: Option<Vec<(RaftMessage, WriteFlags)>>,
     store_id: u64,
     alive: Arc<AtomicBool>,
+    // Messages and bytes buffered or queued for this connection but not
+    // handed to the gRPC sink yet.
+    queued_msgs: Arc<AtomicUsize>,
+    queued_bytes: Arc<AtomicUsize>,
 
     _client: TikvClient,
     _close: Sender<()>,
@@ -55,6 +61,10 @@ impl Conn {
 
         let alive = Arc::new(AtomicBool::new(true));
         let alive1 = Arc::clone(&alive);
+        let queued_msgs = Arc::new(AtomicUsize::new(0));
+        let queued_bytes = Arc::new(AtomicUsize::new(0));
+        let sent_msgs = Arc::clone(&queued_msgs);
+        let sent_bytes = Arc::clone(&queued_bytes);
         let cb = ChannelBuilder::new(env)
             .stream_initial_window_size(cfg.grpc_stream_initial_window_size.0 as usize)
             .max_receive_message_len(MAX_GRPC_RECV_MSG_LEN)
@@ -75,7 +85,17 @@ impl Conn {
                 .map_err(|_| ())
                 .select(
                     sink.sink_map_err(Error::from)
-                        .send_all(rx.map(stream::iter_ok).flatten().map_err(|()| Error::Sink))
+                        .send_all(
+                            rx.map(move |msgs| {
+                                let bytes: usize = msgs.iter()
+                                    .map(|&(ref m, _)| m.compute_size() as usize)
+                                    .sum();
+                                sent_msgs.fetch_sub(msgs.len(), Ordering::Relaxed);
+                                sent_bytes.fetch_sub(bytes, Ordering::Relaxed);
+                                stream::iter_ok(msgs)
+                            }).flatten()
+                                .map_err(|()| Error::Sink),
+                        )
                         .then(move |r| {
                             alive.store(false, Ordering::SeqCst);
                             r
@@ -97,6 +117,8 @@ impl Conn {
             buffer: Some(Vec::with_capacity(INITIAL_BUFFER_CAP)),
             store_id: store_id,
             alive: alive1,
+            queued_msgs: queued_msgs,
+            queued_bytes: queued_bytes,
 
             _client: client,
             _close: tx_close,
@@ -140,14 +162,51 @@ impl RaftClient {
     }
 
     pub fn send(&mut self, store_id: u64, addr: &str, msg: RaftMessage) -> Result<()> {
+        let size = msg.compute_size() as usize;
+        let max_msgs = self.cfg.raft_client_max_pending_msgs;
+        let max_bytes = self.cfg.raft_client_max_pending_bytes.0 as usize;
         let conn = self.get_conn(addr, msg.region_id, store_id);
+        // When the connection is congested the bulky replication traffic
+        // is shed first: a dropped append is retransmitted by the raft
+        // leader anyway, while a dropped vote or heartbeat can cost a
+        // needless election. Votes and heartbeats only start to drop once
+        // the queue grows to twice the window.
+        let (factor, priority) = match msg.get_message().get_msg_type() {
+            MessageType::MsgAppend | MessageType::MsgSnapshot => (1, "bulk"),
+            _ => (2, "critical"),
+        };
+        let over = |limit: usize, queued: &AtomicUsize| {
+            limit > 0 && queued.load(Ordering::Relaxed) >= limit * factor
+        };
+        if over(max_msgs, &conn.queued_msgs) || over(max_bytes, &conn.queued_bytes) {
+            RAFT_MESSAGE_DROP_COUNTER
+                .with_label_values(&[priority])
+                .inc();
+            return Ok(());
+        }
         conn.buffer
             .as_mut()
             .unwrap()
             .push((msg, WriteFlags::default().buffer_hint(true)));
+        conn.queued_msgs.fetch_add(1, Ordering::Relaxed);
+        conn.queued_bytes.fetch_add(size, Ordering::Relaxed);
         Ok(())
     }
 
+    /// Returns the number of messages and bytes queued for `store_id`
+    /// but not handed to the gRPC sinks yet.
+    pub fn queue_stats(&self, store_id: u64) -> (u64, u64) {
+        let mut msgs = 0;
+        let mut bytes = 0;
+        for conn in self.conns.values() {
+            if conn.store_id == store_id {
+                msgs += conn.queued_msgs.load(Ordering::Relaxed) as u64;
+                bytes += conn.queued_bytes.load(Ordering::Relaxed) as u64;
+            }
+        }
+        (msgs, bytes)
+    }
+
     pub fn flush(&mut self) {
         let addrs = &mut self.addrs;
         self.conns.retain(|&(ref addr, _), conn| {
@@ -11,11 +11,13 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use util::config::ReadableSize;
+use util::config::{ReadableDuration, ReadableSize};
 
 const DEFAULT_HIGH_CONCURRENCY: usize = 8;
 const DEFAULT_NORMAL_CONCURRENCY: usize = 8;
 const DEFAULT_LOW_CONCURRENCY: usize = 8;
+const DEFAULT_MIN_CONCURRENCY: usize = 1;
+const DEFAULT_TARGET_QUEUE_WAIT_MS: u64 = 10;
 
 // Assume a request can be finished in 1ms, a request at position x will wait about
 // 0.001 * x secs to be actual started. A server-is-busy error will trigger 2 seconds
@@ -36,6 +38,13 @@ pub struct Config {
     pub max_tasks_normal: usize,
     pub max_tasks_low: usize,
     pub stack_size: ReadableSize,
+    /// Automatically scales the active threads of each pool between
+    /// `min-concurrency` and the configured concurrency, based on observed
+    /// queue wait latency.
+    pub auto_scale: bool,
+    pub min_concurrency: usize,
+    /// Queue wait latency the auto scaler tries to stay below.
+    pub target_queue_wait: ReadableDuration,
 }
 
 impl Default for Config {
@@ -48,6 +57,9 @@ impl Default for Config {
             max_tasks_normal: DEFAULT_MAX_TASKS_PER_CORE * DEFAULT_NORMAL_CONCURRENCY,
             max_tasks_low: DEFAULT_MAX_TASKS_PER_CORE * DEFAULT_LOW_CONCURRENCY,
             stack_size: ReadableSize::mb(DEFAULT_STACK_SIZE_MB),
+            auto_scale: false,
+            min_concurrency: DEFAULT_MIN_CONCURRENCY,
+            target_queue_wait: ReadableDuration::millis(DEFAULT_TARGET_QUEUE_WAIT_MS),
         }
     }
 }
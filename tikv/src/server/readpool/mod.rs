@@ -14,15 +14,20 @@
 mod config;
 mod context;
 mod priority;
+mod scale;
 
 use std::fmt;
 use std::error::Error;
+use std::sync::Arc;
 use std::time::Duration;
 use futures::Future;
 use futures_cpupool::CpuFuture;
 
 use util;
 use util::futurepool::{self, FuturePool};
+use util::time::Instant;
+
+use self::scale::{Gate, ScaleTarget};
 
 pub use self::config::Config;
 pub use self::context::Context;
@@ -38,6 +43,11 @@ pub struct ReadPool {
     max_tasks_high: usize,
     max_tasks_normal: usize,
     max_tasks_low: usize,
+    // `None` unless `auto-scale` is enabled, so that the default
+    // configuration pays no gating overhead.
+    gate_high: Option<Arc<Gate>>,
+    gate_normal: Option<Arc<Gate>>,
+    gate_low: Option<Arc<Gate>>,
 }
 
 impl util::AssertSend for ReadPool {}
@@ -47,6 +57,32 @@ impl ReadPool {
     pub fn new(config: &Config) -> Self {
         let tick_interval = Duration::from_secs(TICK_INTERVAL_SEC);
         let build_context_factory = || || Context {};
+        let build_gate = |concurrency: usize| {
+            if config.auto_scale {
+                Some(Arc::new(Gate::new(concurrency)))
+            } else {
+                None
+            }
+        };
+        let gate_high = build_gate(config.high_concurrency);
+        let gate_normal = build_gate(config.normal_concurrency);
+        let gate_low = build_gate(config.low_concurrency);
+        if config.auto_scale {
+            let build_target = |label, gate: &Option<Arc<Gate>>, max_concurrency| ScaleTarget {
+                label: label,
+                gate: Arc::downgrade(gate.as_ref().unwrap()),
+                min_concurrency: config.min_concurrency,
+                max_concurrency: max_concurrency,
+            };
+            scale::spawn_scaler(
+                vec![
+                    build_target("high", &gate_high, config.high_concurrency),
+                    build_target("normal", &gate_normal, config.normal_concurrency),
+                    build_target("low", &gate_low, config.low_concurrency),
+                ],
+                config.target_queue_wait.0,
+            );
+        }
         ReadPool {
             pool_high: FuturePool::new(
                 config.high_concurrency,
@@ -72,6 +108,9 @@ impl ReadPool {
             max_tasks_high: config.max_tasks_high,
             max_tasks_normal: config.max_tasks_normal,
             max_tasks_low: config.max_tasks_low,
+            gate_high: gate_high,
+            gate_normal: gate_normal,
+            gate_low: gate_low,
         }
     }
 
@@ -93,6 +132,15 @@ impl ReadPool {
         }
     }
 
+    #[inline]
+    fn get_gate_by_priority(&self, priority: Priority) -> &Option<Arc<Gate>> {
+        match priority {
+            Priority::High => &self.gate_high,
+            Priority::Normal => &self.gate_normal,
+            Priority::Low => &self.gate_low,
+        }
+    }
+
     /// Executes a future (generated by the `future_factory`) on specified future pool,
     /// returning a success future representing the produced value, or a fail future if
     /// the future pool is full.
@@ -110,9 +158,22 @@ impl ReadPool {
         let pool = self.get_pool_by_priority(priority);
         let max_tasks = self.get_max_tasks_by_priority(priority);
         if pool.get_running_task_count() >= max_tasks {
-            Err(Full {})
-        } else {
-            Ok(pool.spawn(future_factory))
+            return Err(Full {});
+        }
+        match *self.get_gate_by_priority(priority) {
+            None => Ok(pool.spawn(future_factory)),
+            Some(ref gate) => {
+                let gate = Arc::clone(gate);
+                let submitted = Instant::now_coarse();
+                Ok(pool.spawn(move |ctxd| {
+                    gate.record_wait(submitted.elapsed());
+                    let guard = Gate::enter(&gate);
+                    future_factory(ctxd).then(move |r| {
+                        drop(guard);
+                        r
+                    })
+                }))
+            }
         }
     }
 }
@@ -0,0 +1,266 @@
+// Copyright 2018 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::cmp;
+use std::sync::{Arc, Condvar, Mutex, Weak};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::Duration;
+
+const SCALE_TICK_INTERVAL_SEC: u64 = 1;
+
+/// An admission gate limiting the number of tasks running concurrently in a
+/// pool. The underlying thread pool keeps its configured thread count; tasks
+/// beyond the gate limit block their worker thread until a running task
+/// finishes, which effectively shrinks the active concurrency.
+pub struct Gate {
+    state: Mutex<GateState>,
+    cond: Condvar,
+    wait_sum_us: AtomicUsize,
+    wait_count: AtomicUsize,
+}
+
+struct GateState {
+    limit: usize,
+    running: usize,
+}
+
+impl Gate {
+    pub fn new(limit: usize) -> Gate {
+        Gate {
+            state: Mutex::new(GateState {
+                limit: limit,
+                running: 0,
+            }),
+            cond: Condvar::new(),
+            wait_sum_us: AtomicUsize::new(0),
+            wait_count: AtomicUsize::new(0),
+        }
+    }
+
+    /// Blocks until a slot is available, returning a guard that releases the
+    /// slot when dropped.
+    pub fn enter(gate: &Arc<Gate>) -> GateGuard {
+        let mut state = gate.state.lock().unwrap();
+        while state.running >= state.limit {
+            state = gate.cond.wait(state).unwrap();
+        }
+        state.running += 1;
+        GateGuard {
+            gate: Arc::clone(gate),
+        }
+    }
+
+    fn leave(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.running -= 1;
+        drop(state);
+        self.cond.notify_one();
+    }
+
+    /// Records how long a task waited in the pool queue before starting.
+    pub fn record_wait(&self, wait: Duration) {
+        let us = wait.as_secs() as usize * 1_000_000 + wait.subsec_nanos() as usize / 1_000;
+        self.wait_sum_us.fetch_add(us, Ordering::Relaxed);
+        self.wait_count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Takes the average queue wait observed since the last call, or `None`
+    /// if no task was recorded in the interval.
+    fn take_avg_wait(&self) -> Option<Duration> {
+        let count = self.wait_count.swap(0, Ordering::Relaxed);
+        let sum_us = self.wait_sum_us.swap(0, Ordering::Relaxed);
+        if count == 0 {
+            None
+        } else {
+            let avg_us = (sum_us / count) as u64;
+            Some(Duration::new(
+                avg_us / 1_000_000,
+                (avg_us % 1_000_000) as u32 * 1_000,
+            ))
+        }
+    }
+
+    fn limit(&self) -> usize {
+        self.state.lock().unwrap().limit
+    }
+
+    fn set_limit(&self, limit: usize) {
+        let mut state = self.state.lock().unwrap();
+        let raised = limit > state.limit;
+        state.limit = limit;
+        drop(state);
+        if raised {
+            self.cond.notify_all();
+        }
+    }
+}
+
+pub struct GateGuard {
+    gate: Arc<Gate>,
+}
+
+impl Drop for GateGuard {
+    fn drop(&mut self) {
+        self.gate.leave();
+    }
+}
+
+/// A scaled pool watched by the controller thread.
+pub struct ScaleTarget {
+    pub label: &'static str,
+    pub gate: Weak<Gate>,
+    pub min_concurrency: usize,
+    pub max_concurrency: usize,
+}
+
+/// Decides the next gate limit from the average queue wait of the last tick.
+/// Grows by one when waits exceed the target, shrinks by one when the pool is
+/// idle or waits are well below the target.
+fn next_limit(
+    limit: usize,
+    min_concurrency: usize,
+    max_concurrency: usize,
+    avg_wait: Option<Duration>,
+    target: Duration,
+) -> usize {
+    match avg_wait {
+        Some(wait) if wait > target => cmp::min(limit + 1, max_concurrency),
+        Some(wait) if wait * 4 < target => cmp::max(limit.saturating_sub(1), min_concurrency),
+        Some(_) => limit,
+        None => cmp::max(limit.saturating_sub(1), min_concurrency),
+    }
+}
+
+/// Spawns the controller thread. It exits once every watched gate has been
+/// dropped, i.e. when the owning `ReadPool` goes away.
+pub fn spawn_scaler(targets: Vec<ScaleTarget>, target_wait: Duration) {
+    thread::Builder::new()
+        .name(thd_name!("readpool-scale"))
+        .spawn(move || loop {
+            thread::sleep(Duration::from_secs(SCALE_TICK_INTERVAL_SEC));
+            let mut alive = false;
+            for t in &targets {
+                let gate = match t.gate.upgrade() {
+                    Some(gate) => gate,
+                    None => continue,
+                };
+                alive = true;
+                let limit = gate.limit();
+                let avg_wait = gate.take_avg_wait();
+                let new_limit = next_limit(
+                    limit,
+                    t.min_concurrency,
+                    t.max_concurrency,
+                    avg_wait,
+                    target_wait,
+                );
+                if new_limit != limit {
+                    info!(
+                        "readpool {}: scale concurrency {} -> {}, avg queue wait {:?}",
+                        t.label,
+                        limit,
+                        new_limit,
+                        avg_wait
+                    );
+                    gate.set_limit(new_limit);
+                }
+            }
+            if !alive {
+                return;
+            }
+        })
+        .unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::channel;
+    use super::*;
+
+    #[test]
+    fn test_gate_enter_leave() {
+        let gate = Arc::new(Gate::new(2));
+        let g1 = Gate::enter(&gate);
+        let _g2 = Gate::enter(&gate);
+
+        let (tx, rx) = channel();
+        let gate2 = Arc::clone(&gate);
+        thread::spawn(move || {
+            let _g3 = Gate::enter(&gate2);
+            tx.send(()).unwrap();
+        });
+        // gate is full, the third enter blocks.
+        assert!(rx.recv_timeout(Duration::from_millis(50)).is_err());
+
+        drop(g1);
+        rx.recv_timeout(Duration::from_secs(1)).unwrap();
+    }
+
+    #[test]
+    fn test_gate_raise_limit() {
+        let gate = Arc::new(Gate::new(1));
+        let _g1 = Gate::enter(&gate);
+
+        let (tx, rx) = channel();
+        let gate2 = Arc::clone(&gate);
+        thread::spawn(move || {
+            let _g2 = Gate::enter(&gate2);
+            tx.send(()).unwrap();
+        });
+        assert!(rx.recv_timeout(Duration::from_millis(50)).is_err());
+
+        gate.set_limit(2);
+        rx.recv_timeout(Duration::from_secs(1)).unwrap();
+    }
+
+    #[test]
+    fn test_avg_wait() {
+        let gate = Gate::new(1);
+        assert_eq!(gate.take_avg_wait(), None);
+        gate.record_wait(Duration::from_millis(10));
+        gate.record_wait(Duration::from_millis(30));
+        assert_eq!(gate.take_avg_wait(), Some(Duration::from_millis(20)));
+        assert_eq!(gate.take_avg_wait(), None);
+    }
+
+    #[test]
+    fn test_next_limit() {
+        let target = Duration::from_millis(10);
+        // waits above the target grow the limit, bounded by max.
+        assert_eq!(
+            next_limit(2, 1, 4, Some(Duration::from_millis(20)), target),
+            3
+        );
+        assert_eq!(
+            next_limit(4, 1, 4, Some(Duration::from_millis(20)), target),
+            4
+        );
+        // waits well below the target shrink the limit, bounded by min.
+        assert_eq!(
+            next_limit(2, 1, 4, Some(Duration::from_millis(1)), target),
+            1
+        );
+        assert_eq!(
+            next_limit(1, 1, 4, Some(Duration::from_millis(1)), target),
+            1
+        );
+        // waits near the target keep the limit.
+        assert_eq!(
+            next_limit(2, 1, 4, Some(Duration::from_millis(5)), target),
+            2
+        );
+        // an idle tick shrinks the limit.
+        assert_eq!(next_limit(2, 1, 4, None, target), 1);
+    }
+}
@@ -14,10 +14,13 @@
 use std::sync::Arc;
 use std::boxed::FnBox;
 use std::fmt::{self, Display, Formatter};
+use std::net::SocketAddr;
+use std::str::FromStr;
 use std::time::Instant;
 
 use kvproto::metapb;
 
+use util;
 use util::collections::HashMap;
 use util::worker::{Runnable, Scheduler, Worker};
 use pd::PdClient;
@@ -67,7 +70,23 @@ impl<T: PdClient> Runner<T> {
             }
         }
 
-        let addr = self.get_address(store_id)?;
+        let addr = match self.get_address(store_id) {
+            Ok(addr) => addr,
+            Err(e) => {
+                // A refresh failing on a DNS blip or a PD hiccup must not
+                // break an established peer: keep serving the stale
+                // address and retry on the next request.
+                if let Some(s) = self.store_addrs.get(&store_id) {
+                    RESOLVE_STORE_COUNTER.with_label_values(&["stale"]).inc();
+                    warn!(
+                        "refresh store {} address failed, keep stale {}: {:?}",
+                        store_id, s.addr, e
+                    );
+                    return Ok(s.addr.clone());
+                }
+                return Err(e);
+            }
+        };
 
         let cache = StoreAddr {
             addr: addr.clone(),
@@ -85,6 +104,9 @@ impl<T: PdClient> Runner<T> {
             RESOLVE_STORE_COUNTER
                 .with_label_values(&["tombstone"])
                 .inc();
+            // Drop the cache entry too, a removed store must not be kept
+            // alive through the stale address fallback.
+            self.store_addrs.remove(&store_id);
             return Err(box_err!("store {} has been removed", store_id));
         }
         let addr = s.get_address().to_owned();
@@ -94,7 +116,16 @@ impl<T: PdClient> Runner<T> {
         if addr.is_empty() {
             return Err(box_err!("invalid empty address for store {}", store_id));
         }
-        Ok(addr)
+        if SocketAddr::from_str(&addr).is_ok() {
+            return Ok(addr);
+        }
+        // The address is a hostname. Resolve it here, on the resolver
+        // worker, so a slow or flaky DNS server can never stall a sending
+        // thread, and so the resolution is refreshed together with the
+        // cache entry. TLS deployments verifying the peer by name keep
+        // working through security.override-ssl-target.
+        let sock = box_try!(util::to_socket_addr(addr.as_str()));
+        Ok(format!("{}", sock))
     }
 }
 
@@ -150,6 +181,7 @@ mod tests {
     use super::*;
     use std::net::SocketAddr;
     use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering, ATOMIC_BOOL_INIT};
     use std::time::{Duration, Instant};
     use std::ops::Sub;
     use std::str::FromStr;
@@ -166,6 +198,7 @@ mod tests {
     struct MockPdClient {
         start: Instant,
         store: metapb::Store,
+        fail: AtomicBool,
     }
 
     impl PdClient for MockPdClient {
@@ -185,11 +218,16 @@ mod tests {
             unimplemented!();
         }
         fn get_store(&self, _: u64) -> Result<metapb::Store> {
-            // The store address will be changed every millisecond.
+            if self.fail.load(Ordering::SeqCst) {
+                return Err(box_err!("injected resolve failure"));
+            }
+            // The store address will be changed every millisecond,
+            // but only when it is already an ip, a hostname is kept as is.
             let mut store = self.store.clone();
-            let mut sock = SocketAddr::from_str(store.get_address()).unwrap();
-            sock.set_port(util::time::duration_to_ms(self.start.elapsed()) as u16);
-            store.set_address(format!("{}:{}", sock.ip(), sock.port()));
+            if let Ok(mut sock) = SocketAddr::from_str(store.get_address()) {
+                sock.set_port(util::time::duration_to_ms(self.start.elapsed()) as u16);
+                store.set_address(format!("{}:{}", sock.ip(), sock.port()));
+            }
             Ok(store)
         }
         fn get_cluster_config(&self) -> Result<metapb::Cluster> {
@@ -240,6 +278,7 @@ mod tests {
         let client = MockPdClient {
             start: Instant::now(),
             store: store,
+            fail: ATOMIC_BOOL_INIT,
         };
         Runner {
             pd_client: Arc::new(client),
@@ -270,6 +309,59 @@ mod tests {
         assert!(runner.get_address(0).is_err());
     }
 
+    #[test]
+    fn test_resolve_hostname() {
+        let store = new_store("localhost:12345", metapb::StoreState::Up);
+        let mut runner = new_runner(store);
+        let addr = runner.get_address(0).unwrap();
+        let sock = SocketAddr::from_str(&addr).unwrap();
+        assert_eq!(sock.port(), 12345);
+    }
+
+    #[test]
+    fn test_resolve_retains_stale_address() {
+        let store = new_store(STORE_ADDR, metapb::StoreState::Up);
+        let store_id = store.get_id();
+        let client = Arc::new(MockPdClient {
+            start: Instant::now(),
+            store: store,
+            fail: ATOMIC_BOOL_INIT,
+        });
+        let mut runner = Runner {
+            pd_client: Arc::clone(&client),
+            store_addrs: HashMap::default(),
+        };
+
+        let addr = runner.resolve(store_id).unwrap();
+
+        // Expire the cache and make the refresh fail: the stale address
+        // must still be served.
+        {
+            let s = runner.store_addrs.get_mut(&store_id).unwrap();
+            s.last_update =
+                Instant::now().sub(Duration::from_secs(STORE_ADDRESS_REFRESH_SECONDS + 1));
+        }
+        client.fail.store(true, Ordering::SeqCst);
+        assert_eq!(runner.resolve(store_id).unwrap(), addr);
+
+        // A store that was never resolved still reports the failure.
+        assert!(runner.resolve(store_id + 1).is_err());
+    }
+
+    #[test]
+    fn test_resolve_tombstone_purges_cache() {
+        let store = new_store(STORE_ADDR, metapb::StoreState::Tombstone);
+        let store_id = store.get_id();
+        let mut runner = new_runner(store);
+        let stale = StoreAddr {
+            addr: STORE_ADDR.to_owned(),
+            last_update: Instant::now().sub(Duration::from_secs(STORE_ADDRESS_REFRESH_SECONDS + 1)),
+        };
+        runner.store_addrs.insert(store_id, stale);
+        assert!(runner.resolve(store_id).is_err());
+        assert!(runner.store_addrs.is_empty());
+    }
+
     #[test]
     fn test_store_address_refresh() {
         let store = new_store(STORE_ADDR, metapb::StoreState::Up);
@@ -21,19 +21,21 @@ use kvproto::debugpb_grpc::create_debug;
 use kvproto::importpb_grpc::create_import_sst;
 
 use import::ImportSSTService;
+use util::sys as util_sys;
 use util::worker::{Builder as WorkerBuilder, FutureScheduler, Worker};
 use util::security::SecurityManager;
 use storage::Storage;
 use raftstore::store::{Engines, SnapManager};
 
 use super::{Config, Result};
+use super::audit::AuditLogger;
 use coprocessor::{EndPointHost, EndPointTask};
 use super::service::*;
 use super::transport::{RaftStoreRouter, ServerTransport};
 use super::resolve::StoreAddrResolver;
 use super::snap::{Runner as SnapHandler, Task as SnapTask};
 use super::raft_client::RaftClient;
-use pd::PdTask;
+use pd::{PdClient, PdTask};
 
 const DEFAULT_COPROCESSOR_BATCH: usize = 256;
 const MAX_GRPC_RECV_MSG_LEN: usize = 10 * 1024 * 1024;
@@ -58,7 +60,7 @@ pub struct Server<T: RaftStoreRouter + 'static, S: StoreAddrResolver + 'static>
 
 impl<T: RaftStoreRouter, S: StoreAddrResolver + 'static> Server<T, S> {
     #[allow(too_many_arguments)]
-    pub fn new(
+    pub fn new<C: PdClient + 'static>(
         cfg: &Arc<Config>,
         security_mgr: &Arc<SecurityManager>,
         region_split_size: usize,
@@ -68,14 +70,31 @@ impl<T: RaftStoreRouter, S: StoreAddrResolver + 'static> Server<T, S> {
         snap_mgr: SnapManager,
         pd_scheduler: FutureScheduler<PdTask>,
         debug_engines: Option<Engines>,
-        import_service: Option<ImportSSTService>,
+        import_service: Option<ImportSSTService<C, T>>,
     ) -> Result<Server<T, S>> {
+        // grpc spawns its completion queue poller threads inside `build`
+        // and they inherit this thread's affinity, so pinning them means
+        // narrowing our own mask around the call and widening it back.
+        let saved_mask = if cfg.grpc_cpu_set.is_empty() {
+            None
+        } else {
+            let saved = util_sys::cpuset::get_affinity().ok();
+            if let Err(e) = util_sys::cpuset::set_affinity(&cfg.grpc_cpu_set) {
+                warn!("set cpu affinity for grpc pollers failed, error: {:?}", e);
+            }
+            saved
+        };
         let env = Arc::new(
             EnvBuilder::new()
                 .cq_count(cfg.grpc_concurrency)
                 .name_prefix(thd_name!("grpc-server"))
                 .build(),
         );
+        if let Some(mask) = saved_mask {
+            if let Err(e) = util_sys::cpuset::set_affinity(&mask) {
+                warn!("restore cpu affinity failed, error: {:?}", e);
+            }
+        }
         let raft_client = Arc::new(RwLock::new(RaftClient::new(
             Arc::clone(&env),
             Arc::clone(cfg),
@@ -86,11 +105,18 @@ impl<T: RaftStoreRouter, S: StoreAddrResolver + 'static> Server<T, S> {
             .create();
         let snap_worker = Worker::new("snap-handler");
 
+        let audit = if cfg.audit_log_path.is_empty() {
+            AuditLogger::disabled()
+        } else {
+            AuditLogger::new(&cfg.audit_log_path, cfg.audit_log_sample_every)?
+        };
         let kv_service = KvService::new(
             storage.clone(),
             end_point_worker.scheduler(),
             raft_router.clone(),
             snap_worker.scheduler(),
+            audit,
+            Arc::clone(security_mgr),
             cfg.end_point_recursion_limit,
             cfg.end_point_request_max_handle_duration.as_secs(),
         );
@@ -150,12 +176,15 @@ impl<T: RaftStoreRouter, S: StoreAddrResolver + 'static> Server<T, S> {
     }
 
     pub fn start(&mut self, cfg: Arc<Config>, security_mgr: Arc<SecurityManager>) -> Result<()> {
-        let end_point = EndPointHost::new(
+        let mut end_point = EndPointHost::new(
             self.storage.get_engine(),
             self.end_point_worker.scheduler(),
             &cfg,
             self.pd_scheduler.clone(),
         );
+        if let Some(checker) = self.storage.access_checker() {
+            end_point.set_access_checker(checker);
+        }
         box_try!(self.end_point_worker.start(end_point));
         let snap_runner = SnapHandler::new(
             Arc::clone(&self.env),
@@ -198,6 +227,7 @@ mod tests {
     use super::super::{Config, Result};
     use super::super::transport::RaftStoreRouter;
     use super::super::resolve::{Callback as ResolveCallback, StoreAddrResolver};
+    use pd::RpcClient;
     use storage::{Config as StorageConfig, Storage};
     use kvproto::raft_serverpb::RaftMessage;
     use raftstore::Result as RaftStoreResult;
@@ -291,7 +321,7 @@ mod tests {
             SnapManager::new("", None),
             pd_worker.scheduler(),
             None,
-            None,
+            Option::<ImportSSTService<RpcClient, TestRaftStoreRouter>>::None,
         ).unwrap();
 
         server.start(cfg, security_mgr).unwrap();
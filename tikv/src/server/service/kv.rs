@@ -12,6 +12,7 @@
 // limitations under the License.
 
 use std::boxed::FnBox;
+use std::cmp;
 use std::fmt::Debug;
 use std::io::Write;
 use std::iter::{self, FromIterator};
@@ -22,20 +23,22 @@ use grpc::{ClientStreamingSink, RequestStream, RpcContext, RpcStatus, RpcStatusC
            ServerStreamingSink, UnarySink};
 use futures::{future, Future, Stream};
 use futures::sync::oneshot;
-use protobuf::RepeatedField;
+use protobuf::{Message, RepeatedField};
 use kvproto::tikvpb_grpc;
 use kvproto::raft_serverpb::*;
 use kvproto::kvrpcpb::*;
 use kvproto::coprocessor::*;
 use kvproto::errorpb::{Error as RegionError, ServerIsBusy};
 
+use util::security::SecurityManager;
 use util::worker::Scheduler;
 use util::collections::HashMap;
 use util::buf::PipeBuffer;
-use storage::{self, Key, Mutation, Options, Storage, Value};
+use storage::{self, paired_future_callback, Key, Mutation, Options, Storage, Value};
 use storage::txn::Error as TxnError;
 use storage::mvcc::{Error as MvccError, Write as MvccWrite, WriteType};
 use storage::engine::Error as EngineError;
+use server::audit::{AuditEntry, AuditLogger};
 use server::transport::RaftStoreRouter;
 use server::snap::Task as SnapTask;
 use server::metrics::*;
@@ -55,6 +58,10 @@ pub struct Service<T: RaftStoreRouter + 'static> {
     ch: T,
     // For handling snapshot.
     snap_scheduler: Scheduler<SnapTask>,
+    // For auditing data access operations.
+    audit: AuditLogger,
+    // For authenticating clients.
+    security_mgr: Arc<SecurityManager>,
     token: Arc<AtomicUsize>, // TODO: remove it.
     recursion_limit: u32,
     request_max_handle_secs: u64,
@@ -66,6 +73,8 @@ impl<T: RaftStoreRouter + 'static> Service<T> {
         end_point_scheduler: Scheduler<EndPointTask>,
         ch: T,
         snap_scheduler: Scheduler<SnapTask>,
+        audit: AuditLogger,
+        security_mgr: Arc<SecurityManager>,
         recursion_limit: u32,
         request_max_handle_secs: u64,
     ) -> Service<T> {
@@ -74,6 +83,8 @@ impl<T: RaftStoreRouter + 'static> Service<T> {
             end_point_scheduler: end_point_scheduler,
             ch: ch,
             snap_scheduler: snap_scheduler,
+            audit: audit,
+            security_mgr: security_mgr,
             token: Arc::new(AtomicUsize::new(1)),
             recursion_limit: recursion_limit,
             request_max_handle_secs: request_max_handle_secs,
@@ -87,11 +98,61 @@ impl<T: RaftStoreRouter + 'static> Service<T> {
         err: Error,
         code: RpcStatusCode,
     ) {
-        let status = RpcStatus::new(code, Some(format!("{}", err)));
+        let msg = match err.error_code() {
+            Some(error_code) => format!("[{}] {}", error_code, err),
+            None => format!("{}", err),
+        };
+        let status = RpcStatus::new(code, Some(msg));
+        ctx.spawn(sink.fail(status).map_err(|_| ()));
+    }
+
+    fn audit(
+        &self,
+        ctx: &RpcContext,
+        label: &'static str,
+        req_ctx: &Context,
+        start_key: &[u8],
+        end_key: &[u8],
+    ) -> Option<AuditEntry> {
+        if !self.audit.enabled() {
+            return None;
+        }
+        self.audit
+            .entry(ctx.peer(), label, req_ctx, start_key, end_key)
+    }
+
+    // Whether the request carries a valid auth token. Always true when
+    // no token is configured. Only client facing methods are checked;
+    // the raft and snapshot streams carry peer traffic and stay open.
+    fn auth_ok(&self, ctx: &RpcContext) -> bool {
+        self.security_mgr
+            .check_auth(ctx.request_headers().iter())
+    }
+
+    fn reject_unauthenticated<M>(&self, ctx: RpcContext, sink: UnarySink<M>) {
+        let status = RpcStatus::new(
+            RpcStatusCode::Unauthenticated,
+            Some("invalid auth token".to_owned()),
+        );
         ctx.spawn(sink.fail(status).map_err(|_| ()));
     }
 }
 
+// The smallest and largest key a request touches, for the audit log.
+fn key_span<'a, I>(keys: I) -> (&'a [u8], &'a [u8])
+where
+    I: IntoIterator<Item = &'a [u8]>,
+{
+    let mut span: Option<(&[u8], &[u8])> = None;
+    for key in keys {
+        span = Some(match span {
+            None => (key, key),
+            Some((start, end)) => (cmp::min(start, key), cmp::max(end, key)),
+        });
+    }
+    span.unwrap_or((b"", b""))
+}
+
 fn make_callback<T: Debug + Send + 'static>() -> (Box<FnBox(T) + Send>, oneshot::Receiver<T>) {
     let (tx, rx) = oneshot::channel();
     let callback = move |resp| {
@@ -100,14 +161,38 @@ fn make_callback<T: Debug + Send + 'static>() -> (Box<FnBox(T) + Send>, oneshot:
     (box callback, rx)
 }
 
+// Logs a failed request together with its stable error code, if any.
+fn log_fail(label: &str, e: &Error) {
+    match e.error_code() {
+        Some(error_code) => debug!("{} failed: [{}] {:?}", label, error_code, e),
+        None => debug!("{} failed: {:?}", label, e),
+    }
+}
+
+fn observe_req_size<M: Message>(label: &str, msg: &M) {
+    GRPC_REQ_SIZE_HISTOGRAM_VEC
+        .with_label_values(&[label])
+        .observe(f64::from(msg.compute_size()));
+}
+
+fn observe_resp_size<M: Message>(label: &str, msg: &M) {
+    GRPC_RESP_SIZE_HISTOGRAM_VEC
+        .with_label_values(&[label])
+        .observe(f64::from(msg.compute_size()));
+}
+
 impl<T: RaftStoreRouter + 'static> tikvpb_grpc::Tikv for Service<T> {
     fn kv_get(&self, ctx: RpcContext, mut req: GetRequest, sink: UnarySink<GetResponse>) {
         let label = "kv_get";
+        if !self.auth_ok(&ctx) {
+            return self.reject_unauthenticated(ctx, sink);
+        }
         let timer = GRPC_MSG_HISTOGRAM_VEC
             .with_label_values(&[label])
             .start_coarse_timer();
+        observe_req_size(label, &req);
 
-        let (cb, future) = make_callback();
+        let (cb, future) = paired_future_callback();
         let res = self.storage.async_get(
             req.take_context(),
             Key::from_raw(req.get_key()),
@@ -134,10 +219,13 @@ impl<T: RaftStoreRouter + 'static> tikvpb_grpc::Tikv for Service<T> {
                 }
                 res
             })
-            .and_then(|res| sink.success(res).map_err(Error::from))
+            .and_then(move |res| {
+                observe_resp_size(label, &res);
+                sink.success(res).map_err(Error::from)
+            })
             .map(|_| timer.observe_duration())
             .map_err(move |e| {
-                debug!("{} failed: {:?}", label, e);
+                log_fail(label, &e);
                 GRPC_MSG_FAIL_COUNTER.with_label_values(&[label]).inc();
             });
 
@@ -146,18 +234,23 @@ impl<T: RaftStoreRouter + 'static> tikvpb_grpc::Tikv for Service<T> {
 
     fn kv_scan(&self, ctx: RpcContext, mut req: ScanRequest, sink: UnarySink<ScanResponse>) {
         let label = "kv_scan";
+        if !self.auth_ok(&ctx) {
+            return self.reject_unauthenticated(ctx, sink);
+        }
         let timer = GRPC_MSG_HISTOGRAM_VEC
             .with_label_values(&[label])
             .start_coarse_timer();
+        observe_req_size(label, &req);
 
         let storage = self.storage.clone();
         let mut options = Options::default();
         options.key_only = req.get_key_only();
 
-        let (cb, future) = make_callback();
+        let (cb, future) = paired_future_callback();
         let res = storage.async_scan(
             req.take_context(),
             Key::from_raw(req.get_start_key()),
+            None,
             req.get_limit() as usize,
             req.get_version(),
             options,
@@ -179,10 +272,13 @@ impl<T: RaftStoreRouter + 'static> tikvpb_grpc::Tikv for Service<T> {
                 }
                 resp
             })
-            .and_then(|res| sink.success(res).map_err(Error::from))
+            .and_then(move |res| {
+                observe_resp_size(label, &res);
+                sink.success(res).map_err(Error::from)
+            })
             .map(|_| timer.observe_duration())
             .map_err(move |e| {
-                debug!("{} failed: {:?}", label, e);
+                log_fail(label, &e);
                 GRPC_MSG_FAIL_COUNTER.with_label_values(&[label]).inc();
             });
 
@@ -196,10 +292,18 @@ impl<T: RaftStoreRouter + 'static> tikvpb_grpc::Tikv for Service<T> {
         sink: UnarySink<PrewriteResponse>,
     ) {
         let label = "kv_prewrite";
+        if !self.auth_ok(&ctx) {
+            return self.reject_unauthenticated(ctx, sink);
+        }
         let timer = GRPC_MSG_HISTOGRAM_VEC
             .with_label_values(&[label])
             .start_coarse_timer();
+        observe_req_size(label, &req);
 
+        let audit = {
+            let (start_key, end_key) = key_span(req.get_mutations().iter().map(|m| m.get_key()));
+            self.audit(&ctx, label, req.get_context(), start_key, end_key)
+        };
         let mutations = req.take_mutations()
             .into_iter()
             .map(|mut x| match x.get_op() {
@@ -213,7 +317,7 @@ impl<T: RaftStoreRouter + 'static> tikvpb_grpc::Tikv for Service<T> {
         options.lock_ttl = req.get_lock_ttl();
         options.skip_constraint_check = req.get_skip_constraint_check();
 
-        let (cb, future) = make_callback();
+        let (cb, future) = paired_future_callback();
         let res = self.storage.async_prewrite(
             req.take_context(),
             mutations,
@@ -229,7 +333,10 @@ impl<T: RaftStoreRouter + 'static> tikvpb_grpc::Tikv for Service<T> {
 
         let future = future
             .map_err(Error::from)
-            .map(|v| {
+            .map(move |v| {
+                if let Some(entry) = audit {
+                    entry.finish(v.is_ok());
+                }
                 let mut resp = PrewriteResponse::new();
                 if let Some(err) = extract_region_error(&v) {
                     resp.set_region_error(err);
@@ -238,10 +345,13 @@ impl<T: RaftStoreRouter + 'static> tikvpb_grpc::Tikv for Service<T> {
                 }
                 resp
             })
-            .and_then(|res| sink.success(res).map_err(Error::from))
+            .and_then(move |res| {
+                observe_resp_size(label, &res);
+                sink.success(res).map_err(Error::from)
+            })
             .map(|_| timer.observe_duration())
             .map_err(move |e| {
-                debug!("{} failed: {:?}", label, e);
+                log_fail(label, &e);
                 GRPC_MSG_FAIL_COUNTER.with_label_values(&[label]).inc();
             });
 
@@ -250,13 +360,21 @@ impl<T: RaftStoreRouter + 'static> tikvpb_grpc::Tikv for Service<T> {
 
     fn kv_commit(&self, ctx: RpcContext, mut req: CommitRequest, sink: UnarySink<CommitResponse>) {
         let label = "kv_commit";
+        if !self.auth_ok(&ctx) {
+            return self.reject_unauthenticated(ctx, sink);
+        }
         let timer = GRPC_MSG_HISTOGRAM_VEC
             .with_label_values(&[label])
             .start_coarse_timer();
+        observe_req_size(label, &req);
 
+        let audit = {
+            let (start_key, end_key) = key_span(req.get_keys().iter().map(|k| k.as_slice()));
+            self.audit(&ctx, label, req.get_context(), start_key, end_key)
+        };
         let keys = req.get_keys().iter().map(|x| Key::from_raw(x)).collect();
 
-        let (cb, future) = make_callback();
+        let (cb, future) = paired_future_callback();
         let res = self.storage.async_commit(
             req.take_context(),
             keys,
@@ -271,7 +389,10 @@ impl<T: RaftStoreRouter + 'static> tikvpb_grpc::Tikv for Service<T> {
 
         let future = future
             .map_err(Error::from)
-            .map(|v| {
+            .map(move |v| {
+                if let Some(entry) = audit {
+                    entry.finish(v.is_ok());
+                }
                 let mut resp = CommitResponse::new();
                 if let Some(err) = extract_region_error(&v) {
                     resp.set_region_error(err);
@@ -280,10 +401,13 @@ impl<T: RaftStoreRouter + 'static> tikvpb_grpc::Tikv for Service<T> {
                 }
                 resp
             })
-            .and_then(|res| sink.success(res).map_err(Error::from))
+            .and_then(move |res| {
+                observe_resp_size(label, &res);
+                sink.success(res).map_err(Error::from)
+            })
             .map(|_| timer.observe_duration())
             .map_err(move |e| {
-                debug!("{} failed: {:?}", label, e);
+                log_fail(label, &e);
                 GRPC_MSG_FAIL_COUNTER.with_label_values(&[label]).inc();
             });
 
@@ -301,11 +425,16 @@ impl<T: RaftStoreRouter + 'static> tikvpb_grpc::Tikv for Service<T> {
         sink: UnarySink<CleanupResponse>,
     ) {
         let label = "kv_cleanup";
+        if !self.auth_ok(&ctx) {
+            return self.reject_unauthenticated(ctx, sink);
+        }
         let timer = GRPC_MSG_HISTOGRAM_VEC
             .with_label_values(&[label])
             .start_coarse_timer();
+        observe_req_size(label, &req);
 
-        let (cb, future) = make_callback();
+        let audit = self.audit(&ctx, label, req.get_context(), req.get_key(), b"");
+        let (cb, future) = paired_future_callback();
         let res = self.storage.async_cleanup(
             req.take_context(),
             Key::from_raw(req.get_key()),
@@ -319,7 +448,10 @@ impl<T: RaftStoreRouter + 'static> tikvpb_grpc::Tikv for Service<T> {
 
         let future = future
             .map_err(Error::from)
-            .map(|v| {
+            .map(move |v| {
+                if let Some(entry) = audit {
+                    entry.finish(v.is_ok());
+                }
                 let mut resp = CleanupResponse::new();
                 if let Some(err) = extract_region_error(&v) {
                     resp.set_region_error(err);
@@ -332,10 +464,13 @@ impl<T: RaftStoreRouter + 'static> tikvpb_grpc::Tikv for Service<T> {
                 }
                 resp
             })
-            .and_then(|res| sink.success(res).map_err(Error::from))
+            .and_then(move |res| {
+                observe_resp_size(label, &res);
+                sink.success(res).map_err(Error::from)
+            })
             .map(|_| timer.observe_duration())
             .map_err(move |e| {
-                debug!("{} failed: {:?}", label, e);
+                log_fail(label, &e);
                 GRPC_MSG_FAIL_COUNTER.with_label_values(&[label]).inc();
             });
 
@@ -349,16 +484,20 @@ impl<T: RaftStoreRouter + 'static> tikvpb_grpc::Tikv for Service<T> {
         sink: UnarySink<BatchGetResponse>,
     ) {
         let label = "kv_batchget";
+        if !self.auth_ok(&ctx) {
+            return self.reject_unauthenticated(ctx, sink);
+        }
         let timer = GRPC_MSG_HISTOGRAM_VEC
             .with_label_values(&[label])
             .start_coarse_timer();
+        observe_req_size(label, &req);
 
         let keys = req.get_keys()
             .into_iter()
             .map(|x| Key::from_raw(x))
             .collect();
 
-        let (cb, future) = make_callback();
+        let (cb, future) = paired_future_callback();
         let res = self.storage
             .async_batch_get(req.take_context(), keys, req.get_version(), cb);
         if let Err(e) = res {
@@ -377,10 +516,13 @@ impl<T: RaftStoreRouter + 'static> tikvpb_grpc::Tikv for Service<T> {
                 }
                 resp
             })
-            .and_then(|res| sink.success(res).map_err(Error::from))
+            .and_then(move |res| {
+                observe_resp_size(label, &res);
+                sink.success(res).map_err(Error::from)
+            })
             .map(|_| timer.observe_duration())
             .map_err(move |e| {
-                debug!("{} failed: {:?}", label, e);
+                log_fail(label, &e);
                 GRPC_MSG_FAIL_COUNTER.with_label_values(&[label]).inc();
             });
 
@@ -394,16 +536,24 @@ impl<T: RaftStoreRouter + 'static> tikvpb_grpc::Tikv for Service<T> {
         sink: UnarySink<BatchRollbackResponse>,
     ) {
         let label = "kv_batch_rollback";
+        if !self.auth_ok(&ctx) {
+            return self.reject_unauthenticated(ctx, sink);
+        }
         let timer = GRPC_MSG_HISTOGRAM_VEC
             .with_label_values(&[label])
             .start_coarse_timer();
+        observe_req_size(label, &req);
 
+        let audit = {
+            let (start_key, end_key) = key_span(req.get_keys().iter().map(|k| k.as_slice()));
+            self.audit(&ctx, label, req.get_context(), start_key, end_key)
+        };
         let keys = req.get_keys()
             .into_iter()
             .map(|x| Key::from_raw(x))
             .collect();
 
-        let (cb, future) = make_callback();
+        let (cb, future) = paired_future_callback();
         let res =
             self.storage
                 .async_rollback(req.take_context(), keys, req.get_start_version(), cb);
@@ -414,7 +564,10 @@ impl<T: RaftStoreRouter + 'static> tikvpb_grpc::Tikv for Service<T> {
 
         let future = future
             .map_err(Error::from)
-            .map(|v| {
+            .map(move |v| {
+                if let Some(entry) = audit {
+                    entry.finish(v.is_ok());
+                }
                 let mut resp = BatchRollbackResponse::new();
                 if let Some(err) = extract_region_error(&v) {
                     resp.set_region_error(err);
@@ -423,10 +576,13 @@ impl<T: RaftStoreRouter + 'static> tikvpb_grpc::Tikv for Service<T> {
                 }
                 resp
             })
-            .and_then(|res| sink.success(res).map_err(Error::from))
+            .and_then(move |res| {
+                observe_resp_size(label, &res);
+                sink.success(res).map_err(Error::from)
+            })
             .map(|_| timer.observe_duration())
             .map_err(move |e| {
-                debug!("{} failed: {:?}", label, e);
+                log_fail(label, &e);
                 GRPC_MSG_FAIL_COUNTER.with_label_values(&[label]).inc();
             });
 
@@ -440,11 +596,15 @@ impl<T: RaftStoreRouter + 'static> tikvpb_grpc::Tikv for Service<T> {
         sink: UnarySink<ScanLockResponse>,
     ) {
         let label = "kv_scan_lock";
+        if !self.auth_ok(&ctx) {
+            return self.reject_unauthenticated(ctx, sink);
+        }
         let timer = GRPC_MSG_HISTOGRAM_VEC
             .with_label_values(&[label])
             .start_coarse_timer();
+        observe_req_size(label, &req);
 
-        let (cb, future) = make_callback();
+        let (cb, future) = paired_future_callback();
         let res = self.storage.async_scan_lock(
             req.take_context(),
             req.get_max_version(),
@@ -471,10 +631,13 @@ impl<T: RaftStoreRouter + 'static> tikvpb_grpc::Tikv for Service<T> {
                 }
                 resp
             })
-            .and_then(|res| sink.success(res).map_err(Error::from))
+            .and_then(move |res| {
+                observe_resp_size(label, &res);
+                sink.success(res).map_err(Error::from)
+            })
             .map(|_| timer.observe_duration())
             .map_err(move |e| {
-                debug!("{} failed: {:?}", label, e);
+                log_fail(label, &e);
                 GRPC_MSG_FAIL_COUNTER.with_label_values(&[label]).inc();
             });
 
@@ -488,9 +651,13 @@ impl<T: RaftStoreRouter + 'static> tikvpb_grpc::Tikv for Service<T> {
         sink: UnarySink<ResolveLockResponse>,
     ) {
         let label = "kv_resolve_lock";
+        if !self.auth_ok(&ctx) {
+            return self.reject_unauthenticated(ctx, sink);
+        }
         let timer = GRPC_MSG_HISTOGRAM_VEC
             .with_label_values(&[label])
             .start_coarse_timer();
+        observe_req_size(label, &req);
 
         let txn_status = if req.get_start_version() > 0 {
             HashMap::from_iter(iter::once((
@@ -505,7 +672,8 @@ impl<T: RaftStoreRouter + 'static> tikvpb_grpc::Tikv for Service<T> {
             )
         };
 
-        let (cb, future) = make_callback();
+        let audit = self.audit(&ctx, label, req.get_context(), b"", b"");
+        let (cb, future) = paired_future_callback();
         let res = self.storage
             .async_resolve_lock(req.take_context(), txn_status, cb);
         if let Err(e) = res {
@@ -515,7 +683,10 @@ impl<T: RaftStoreRouter + 'static> tikvpb_grpc::Tikv for Service<T> {
 
         let future = future
             .map_err(Error::from)
-            .map(|v| {
+            .map(move |v| {
+                if let Some(entry) = audit {
+                    entry.finish(v.is_ok());
+                }
                 let mut resp = ResolveLockResponse::new();
                 if let Some(err) = extract_region_error(&v) {
                     resp.set_region_error(err);
@@ -524,10 +695,13 @@ impl<T: RaftStoreRouter + 'static> tikvpb_grpc::Tikv for Service<T> {
                 }
                 resp
             })
-            .and_then(|res| sink.success(res).map_err(Error::from))
+            .and_then(move |res| {
+                observe_resp_size(label, &res);
+                sink.success(res).map_err(Error::from)
+            })
             .map(|_| timer.observe_duration())
             .map_err(move |e| {
-                debug!("{} failed: {:?}", label, e);
+                log_fail(label, &e);
                 GRPC_MSG_FAIL_COUNTER.with_label_values(&[label]).inc();
             });
 
@@ -536,11 +710,16 @@ impl<T: RaftStoreRouter + 'static> tikvpb_grpc::Tikv for Service<T> {
 
     fn kv_gc(&self, ctx: RpcContext, mut req: GCRequest, sink: UnarySink<GCResponse>) {
         let label = "kv_gc";
+        if !self.auth_ok(&ctx) {
+            return self.reject_unauthenticated(ctx, sink);
+        }
         let timer = GRPC_MSG_HISTOGRAM_VEC
             .with_label_values(&[label])
             .start_coarse_timer();
+        observe_req_size(label, &req);
 
-        let (cb, future) = make_callback();
+        let audit = self.audit(&ctx, label, req.get_context(), b"", b"");
+        let (cb, future) = paired_future_callback();
         let res = self.storage
             .async_gc(req.take_context(), req.get_safe_point(), cb);
         if let Err(e) = res {
@@ -550,7 +729,10 @@ impl<T: RaftStoreRouter + 'static> tikvpb_grpc::Tikv for Service<T> {
 
         let future = future
             .map_err(Error::from)
-            .map(|v| {
+            .map(move |v| {
+                if let Some(entry) = audit {
+                    entry.finish(v.is_ok());
+                }
                 let mut resp = GCResponse::new();
                 if let Some(err) = extract_region_error(&v) {
                     resp.set_region_error(err);
@@ -559,10 +741,13 @@ impl<T: RaftStoreRouter + 'static> tikvpb_grpc::Tikv for Service<T> {
                 }
                 resp
             })
-            .and_then(|res| sink.success(res).map_err(Error::from))
+            .and_then(move |res| {
+                observe_resp_size(label, &res);
+                sink.success(res).map_err(Error::from)
+            })
             .map(|_| timer.observe_duration())
             .map_err(move |e| {
-                debug!("{} failed: {:?}", label, e);
+                log_fail(label, &e);
                 GRPC_MSG_FAIL_COUNTER.with_label_values(&[label]).inc();
             });
 
@@ -576,11 +761,22 @@ impl<T: RaftStoreRouter + 'static> tikvpb_grpc::Tikv for Service<T> {
         sink: UnarySink<DeleteRangeResponse>,
     ) {
         let label = "kv_delete_range";
+        if !self.auth_ok(&ctx) {
+            return self.reject_unauthenticated(ctx, sink);
+        }
         let timer = GRPC_MSG_HISTOGRAM_VEC
             .with_label_values(&[label])
             .start_coarse_timer();
-
-        let (cb, future) = make_callback();
+        observe_req_size(label, &req);
+
+        let audit = self.audit(
+            &ctx,
+            label,
+            req.get_context(),
+            req.get_start_key(),
+            req.get_end_key(),
+        );
+        let (cb, future) = paired_future_callback();
         let res = self.storage.async_delete_range(
             req.take_context(),
             Key::from_raw(req.get_start_key()),
@@ -594,7 +790,10 @@ impl<T: RaftStoreRouter + 'static> tikvpb_grpc::Tikv for Service<T> {
 
         let future = future
             .map_err(Error::from)
-            .map(|v| {
+            .map(move |v| {
+                if let Some(entry) = audit {
+                    entry.finish(v.is_ok());
+                }
                 let mut resp = DeleteRangeResponse::new();
                 if let Some(err) = extract_region_error(&v) {
                     resp.set_region_error(err);
@@ -603,10 +802,13 @@ impl<T: RaftStoreRouter + 'static> tikvpb_grpc::Tikv for Service<T> {
                 }
                 resp
             })
-            .and_then(|res| sink.success(res).map_err(Error::from))
+            .and_then(move |res| {
+                observe_resp_size(label, &res);
+                sink.success(res).map_err(Error::from)
+            })
             .map(|_| timer.observe_duration())
             .map_err(move |e| {
-                debug!("{} failed: {:?}", label, e);
+                log_fail(label, &e);
                 GRPC_MSG_FAIL_COUNTER.with_label_values(&[label]).inc();
             });
 
@@ -615,13 +817,18 @@ impl<T: RaftStoreRouter + 'static> tikvpb_grpc::Tikv for Service<T> {
 
     fn raw_get(&self, ctx: RpcContext, mut req: RawGetRequest, sink: UnarySink<RawGetResponse>) {
         let label = "raw_get";
+        if !self.auth_ok(&ctx) {
+            return self.reject_unauthenticated(ctx, sink);
+        }
         let timer = GRPC_MSG_HISTOGRAM_VEC
             .with_label_values(&[label])
             .start_coarse_timer();
+        observe_req_size(label, &req);
 
-        let (cb, future) = make_callback();
+        let audit = self.audit(&ctx, label, req.get_context(), req.get_key(), b"");
+        let (cb, future) = paired_future_callback();
         let res = self.storage
-            .async_raw_get(req.take_context(), req.take_key(), cb);
+            .async_raw_get(req.take_context(), Default::default(), req.take_key(), cb);
         if let Err(e) = res {
             self.send_fail_status(ctx, sink, Error::from(e), RpcStatusCode::ResourceExhausted);
             return;
@@ -629,7 +836,10 @@ impl<T: RaftStoreRouter + 'static> tikvpb_grpc::Tikv for Service<T> {
 
         let future = future
             .map_err(Error::from)
-            .map(|v| {
+            .map(move |v| {
+                if let Some(entry) = audit {
+                    entry.finish(v.is_ok());
+                }
                 let mut resp = RawGetResponse::new();
                 if let Some(err) = extract_region_error(&v) {
                     resp.set_region_error(err);
@@ -642,10 +852,13 @@ impl<T: RaftStoreRouter + 'static> tikvpb_grpc::Tikv for Service<T> {
                 }
                 resp
             })
-            .and_then(|res| sink.success(res).map_err(Error::from))
+            .and_then(move |res| {
+                observe_resp_size(label, &res);
+                sink.success(res).map_err(Error::from)
+            })
             .map(|_| timer.observe_duration())
             .map_err(move |e| {
-                debug!("{} failed: {:?}", label, e);
+                log_fail(label, &e);
                 GRPC_MSG_FAIL_COUNTER.with_label_values(&[label]).inc();
             });
 
@@ -654,15 +867,22 @@ impl<T: RaftStoreRouter + 'static> tikvpb_grpc::Tikv for Service<T> {
 
     fn raw_scan(&self, ctx: RpcContext, mut req: RawScanRequest, sink: UnarySink<RawScanResponse>) {
         let label = "raw_scan";
+        if !self.auth_ok(&ctx) {
+            return self.reject_unauthenticated(ctx, sink);
+        }
         let timer = GRPC_MSG_HISTOGRAM_VEC
             .with_label_values(&[label])
             .start_coarse_timer();
+        observe_req_size(label, &req);
 
-        let (cb, future) = make_callback();
+        let audit = self.audit(&ctx, label, req.get_context(), req.get_start_key(), b"");
+        let (cb, future) = paired_future_callback();
         let res = self.storage.async_raw_scan(
             req.take_context(),
+            Default::default(),
             req.take_start_key(),
             req.get_limit() as usize,
+            Options::default(),
             cb,
         );
         if let Err(e) = res {
@@ -672,7 +892,10 @@ impl<T: RaftStoreRouter + 'static> tikvpb_grpc::Tikv for Service<T> {
 
         let future = future
             .map_err(Error::from)
-            .map(|v| {
+            .map(move |v| {
+                if let Some(entry) = audit {
+                    entry.finish(v.is_ok());
+                }
                 let mut resp = RawScanResponse::new();
                 if let Some(err) = extract_region_error(&v) {
                     resp.set_region_error(err);
@@ -681,10 +904,13 @@ impl<T: RaftStoreRouter + 'static> tikvpb_grpc::Tikv for Service<T> {
                 }
                 resp
             })
-            .and_then(|res| sink.success(res).map_err(Error::from))
+            .and_then(move |res| {
+                observe_resp_size(label, &res);
+                sink.success(res).map_err(Error::from)
+            })
             .map(|_| timer.observe_duration())
             .map_err(move |e| {
-                debug!("{} failed: {:?}", label, e);
+                log_fail(label, &e);
                 GRPC_MSG_FAIL_COUNTER.with_label_values(&[label]).inc();
             });
 
@@ -693,14 +919,24 @@ impl<T: RaftStoreRouter + 'static> tikvpb_grpc::Tikv for Service<T> {
 
     fn raw_put(&self, ctx: RpcContext, mut req: RawPutRequest, sink: UnarySink<RawPutResponse>) {
         let label = "raw_put";
+        if !self.auth_ok(&ctx) {
+            return self.reject_unauthenticated(ctx, sink);
+        }
         let timer = GRPC_MSG_HISTOGRAM_VEC
             .with_label_values(&[label])
             .start_coarse_timer();
+        observe_req_size(label, &req);
 
-        let (cb, future) = make_callback();
-        let res =
-            self.storage
-                .async_raw_put(req.take_context(), req.take_key(), req.take_value(), cb);
+        let audit = self.audit(&ctx, label, req.get_context(), req.get_key(), b"");
+        let (cb, future) = paired_future_callback();
+        let res = self.storage.async_raw_put(
+            req.take_context(),
+            Default::default(),
+            req.take_key(),
+            req.take_value(),
+            0,
+            cb,
+        );
         if let Err(e) = res {
             self.send_fail_status(ctx, sink, Error::from(e), RpcStatusCode::ResourceExhausted);
             return;
@@ -708,7 +944,10 @@ impl<T: RaftStoreRouter + 'static> tikvpb_grpc::Tikv for Service<T> {
 
         let future = future
             .map_err(Error::from)
-            .map(|v| {
+            .map(move |v| {
+                if let Some(entry) = audit {
+                    entry.finish(v.is_ok());
+                }
                 let mut resp = RawPutResponse::new();
                 if let Some(err) = extract_region_error(&v) {
                     resp.set_region_error(err);
@@ -717,10 +956,13 @@ impl<T: RaftStoreRouter + 'static> tikvpb_grpc::Tikv for Service<T> {
                 }
                 resp
             })
-            .and_then(|res| sink.success(res).map_err(Error::from))
+            .and_then(move |res| {
+                observe_resp_size(label, &res);
+                sink.success(res).map_err(Error::from)
+            })
             .map(|_| timer.observe_duration())
             .map_err(move |e| {
-                debug!("{} failed: {:?}", label, e);
+                log_fail(label, &e);
                 GRPC_MSG_FAIL_COUNTER.with_label_values(&[label]).inc();
             });
 
@@ -734,13 +976,18 @@ impl<T: RaftStoreRouter + 'static> tikvpb_grpc::Tikv for Service<T> {
         sink: UnarySink<RawDeleteResponse>,
     ) {
         let label = "raw_delete";
+        if !self.auth_ok(&ctx) {
+            return self.reject_unauthenticated(ctx, sink);
+        }
         let timer = GRPC_MSG_HISTOGRAM_VEC
             .with_label_values(&[label])
             .start_coarse_timer();
+        observe_req_size(label, &req);
 
-        let (cb, future) = make_callback();
+        let audit = self.audit(&ctx, label, req.get_context(), req.get_key(), b"");
+        let (cb, future) = paired_future_callback();
         let res = self.storage
-            .async_raw_delete(req.take_context(), req.take_key(), cb);
+            .async_raw_delete(req.take_context(), Default::default(), req.take_key(), cb);
         if let Err(e) = res {
             self.send_fail_status(ctx, sink, Error::from(e), RpcStatusCode::ResourceExhausted);
             return;
@@ -748,7 +995,10 @@ impl<T: RaftStoreRouter + 'static> tikvpb_grpc::Tikv for Service<T> {
 
         let future = future
             .map_err(Error::from)
-            .map(|v| {
+            .map(move |v| {
+                if let Some(entry) = audit {
+                    entry.finish(v.is_ok());
+                }
                 let mut resp = RawDeleteResponse::new();
                 if let Some(err) = extract_region_error(&v) {
                     resp.set_region_error(err);
@@ -757,10 +1007,13 @@ impl<T: RaftStoreRouter + 'static> tikvpb_grpc::Tikv for Service<T> {
                 }
                 resp
             })
-            .and_then(|res| sink.success(res).map_err(Error::from))
+            .and_then(move |res| {
+                observe_resp_size(label, &res);
+                sink.success(res).map_err(Error::from)
+            })
             .map(|_| timer.observe_duration())
             .map_err(move |e| {
-                debug!("{} failed: {:?}", label, e);
+                log_fail(label, &e);
                 GRPC_MSG_FAIL_COUNTER.with_label_values(&[label]).inc();
             });
 
@@ -769,9 +1022,13 @@ impl<T: RaftStoreRouter + 'static> tikvpb_grpc::Tikv for Service<T> {
 
     fn coprocessor(&self, ctx: RpcContext, req: Request, sink: UnarySink<Response>) {
         let label = "coprocessor";
+        if !self.auth_ok(&ctx) {
+            return self.reject_unauthenticated(ctx, sink);
+        }
         let timer = GRPC_MSG_HISTOGRAM_VEC
             .with_label_values(&[label])
             .start_coarse_timer();
+        observe_req_size(label, &req);
 
         let (cb, future) = make_callback();
         let res = self.end_point_scheduler
@@ -788,10 +1045,13 @@ impl<T: RaftStoreRouter + 'static> tikvpb_grpc::Tikv for Service<T> {
 
         let future = future
             .map_err(Error::from)
-            .and_then(|res| sink.success(res).map_err(Error::from))
+            .and_then(move |res| {
+                observe_resp_size(label, &res);
+                sink.success(res).map_err(Error::from)
+            })
             .map(|_| timer.observe_duration())
             .map_err(move |e| {
-                debug!("{} failed: {:?}", label, e);
+                log_fail(label, &e);
                 GRPC_MSG_FAIL_COUNTER.with_label_values(&[label]).inc();
             });
 
@@ -874,14 +1134,18 @@ impl<T: RaftStoreRouter + 'static> tikvpb_grpc::Tikv for Service<T> {
         sink: UnarySink<MvccGetByKeyResponse>,
     ) {
         let label = "mvcc_get_by_key";
+        if !self.auth_ok(&ctx) {
+            return self.reject_unauthenticated(ctx, sink);
+        }
         let timer = GRPC_MSG_HISTOGRAM_VEC
             .with_label_values(&[label])
             .start_coarse_timer();
+        observe_req_size(label, &req);
 
         let storage = self.storage.clone();
 
         let key = Key::from_raw(req.get_key());
-        let (cb, future) = make_callback();
+        let (cb, future) = paired_future_callback();
         let res = storage.async_mvcc_by_key(req.take_context(), key.clone(), cb);
         if let Err(e) = res {
             self.send_fail_status(ctx, sink, Error::from(e), RpcStatusCode::ResourceExhausted);
@@ -904,10 +1168,13 @@ impl<T: RaftStoreRouter + 'static> tikvpb_grpc::Tikv for Service<T> {
                 }
                 resp
             })
-            .and_then(|res| sink.success(res).map_err(Error::from))
+            .and_then(move |res| {
+                observe_resp_size(label, &res);
+                sink.success(res).map_err(Error::from)
+            })
             .map(|_| timer.observe_duration())
             .map_err(move |e| {
-                debug!("{} failed: {:?}", label, e);
+                log_fail(label, &e);
                 GRPC_MSG_FAIL_COUNTER.with_label_values(&[label]).inc();
             });
 
@@ -921,13 +1188,17 @@ impl<T: RaftStoreRouter + 'static> tikvpb_grpc::Tikv for Service<T> {
         sink: UnarySink<MvccGetByStartTsResponse>,
     ) {
         let label = "mvcc_get_by_start_ts";
+        if !self.auth_ok(&ctx) {
+            return self.reject_unauthenticated(ctx, sink);
+        }
         let timer = GRPC_MSG_HISTOGRAM_VEC
             .with_label_values(&[label])
             .start_coarse_timer();
+        observe_req_size(label, &req);
 
         let storage = self.storage.clone();
 
-        let (cb, future) = make_callback();
+        let (cb, future) = paired_future_callback();
 
         let res = storage.async_mvcc_by_start_ts(req.take_context(), req.get_start_ts(), cb);
         if let Err(e) = res {
@@ -955,10 +1226,13 @@ impl<T: RaftStoreRouter + 'static> tikvpb_grpc::Tikv for Service<T> {
                 }
                 resp
             })
-            .and_then(|res| sink.success(res).map_err(Error::from))
+            .and_then(move |res| {
+                observe_resp_size(label, &res);
+                sink.success(res).map_err(Error::from)
+            })
             .map(|_| timer.observe_duration())
             .map_err(move |e| {
-                debug!("{} failed: {:?}", label, e);
+                log_fail(label, &e);
                 GRPC_MSG_FAIL_COUNTER.with_label_values(&[label]).inc();
             });
         ctx.spawn(future);
@@ -971,9 +1245,13 @@ impl<T: RaftStoreRouter + 'static> tikvpb_grpc::Tikv for Service<T> {
         sink: UnarySink<SplitRegionResponse>,
     ) {
         let label = "split_region";
+        if !self.auth_ok(&ctx) {
+            return self.reject_unauthenticated(ctx, sink);
+        }
         let timer = GRPC_MSG_HISTOGRAM_VEC
             .with_label_values(&[label])
             .start_coarse_timer();
+        observe_req_size(label, &req);
 
         let (cb, future) = make_callback();
         let req = StoreMessage::SplitRegion {
@@ -1002,10 +1280,13 @@ impl<T: RaftStoreRouter + 'static> tikvpb_grpc::Tikv for Service<T> {
                 }
                 resp
             })
-            .and_then(|res| sink.success(res).map_err(Error::from))
+            .and_then(move |res| {
+                observe_resp_size(label, &res);
+                sink.success(res).map_err(Error::from)
+            })
             .map(|_| timer.observe_duration())
             .map_err(move |e| {
-                debug!("{} failed: {:?}", label, e);
+                log_fail(label, &e);
                 GRPC_MSG_FAIL_COUNTER.with_label_values(&[label]).inc();
             });
 
@@ -125,7 +125,9 @@ fn send_snap(
         let snap = msg.get_message().get_snapshot();
         SnapKey::from_snap(snap)?
     };
-    mgr.register(key.clone(), SnapEntry::Sending);
+    if !mgr.try_register(key.clone(), SnapEntry::Sending) {
+        return Err(box_err!("too many snapshots are being sent, skip {}", key));
+    }
     defer!({
         mgr.deregister(&key, &SnapEntry::Sending);
     });
@@ -158,6 +160,9 @@ fn send_snap(
     let send = chunks.forward(sink);
     let res = send.and_then(|_| receiver.map_err(Error::from))
         .and_then(|_| {
+            SNAP_TRANSFER_BYTES_VEC
+                .with_label_values(&["send"])
+                .inc_by(total_size as f64);
             info!(
                 "[region {}] sent snapshot {} [size: {}, dur: {:?}]",
                 key.region_id,
@@ -247,6 +252,7 @@ impl<R: RaftStoreRouter + 'static> Runnable<Task> for Runner<R> {
             }
             Task::Write(token, mut data) => {
                 SNAP_TASK_COUNTER.with_label_values(&["write"]).inc();
+                let data_len = data.len();
                 match self.files.entry(token) {
                     Entry::Occupied(mut e) => {
                         if let Err(err) = data.write_all_to(&mut e.get_mut().0) {
@@ -259,6 +265,10 @@ impl<R: RaftStoreRouter + 'static> Runnable<Task> for Runner<R> {
                             let (_, msg) = e.remove();
                             let key = SnapKey::from_snap(msg.get_message().get_snapshot()).unwrap();
                             self.snap_mgr.deregister(&key, &SnapEntry::Receiving);
+                        } else {
+                            SNAP_TRANSFER_BYTES_VEC
+                                .with_label_values(&["recv"])
+                                .inc_by(data_len as f64);
                         }
                     }
                     Entry::Vacant(_) => error!("invalid snap token {:?}", token),
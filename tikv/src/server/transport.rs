@@ -318,6 +318,10 @@ where
     fn flush(&mut self) {
         self.flush_raft_client();
     }
+
+    fn queue_stats(&self, store_id: u64) -> (u64, u64) {
+        self.raft_client.rl().queue_stats(store_id)
+    }
 }
 
 struct SnapshotReporter<T: RaftStoreRouter + 'static> {
@@ -0,0 +1,193 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A convenience layer over the low level `async_*` calls for embedders
+//! that use the crate as a library.
+//!
+//! `Client` hands out transactions that buffer mutations locally, take care
+//! of prewrite/commit sequencing on commit, and transparently retry
+//! retryable failures, so callers don't have to re-implement two phase
+//! commit themselves. Timestamps are supplied by the caller, who is
+//! expected to fetch them from a timestamp oracle such as PD.
+
+use std::collections::BTreeMap;
+use std::thread;
+use std::time::Duration;
+
+use futures::Future;
+use kvproto::kvrpcpb::Context;
+
+use util::error_code::ErrorCodeExt;
+use super::{make_key, Key, Mutation, Options, Result, Storage, Value};
+
+const DEFAULT_RETRY_LIMIT: usize = 3;
+const RETRY_PAUSE_MS: u64 = 50;
+
+/// A handle for starting transactions against a `Storage`.
+#[derive(Clone)]
+pub struct Client {
+    storage: Storage,
+    retry_limit: usize,
+}
+
+impl Client {
+    pub fn new(storage: Storage) -> Client {
+        Client {
+            storage: storage,
+            retry_limit: DEFAULT_RETRY_LIMIT,
+        }
+    }
+
+    pub fn set_retry_limit(&mut self, limit: usize) {
+        self.retry_limit = limit;
+    }
+
+    /// Begins a transaction reading at `start_ts`.
+    pub fn begin(&self, ctx: Context, start_ts: u64) -> Txn {
+        Txn {
+            storage: self.storage.clone(),
+            ctx: ctx,
+            start_ts: start_ts,
+            retry_limit: self.retry_limit,
+            buffer: BTreeMap::new(),
+        }
+    }
+}
+
+/// An uncommitted transaction.
+///
+/// Mutations are buffered locally and only sent to the storage layer on
+/// `commit`, which runs the two phase commit protocol with the first
+/// mutated key as the primary.
+pub struct Txn {
+    storage: Storage,
+    ctx: Context,
+    start_ts: u64,
+    retry_limit: usize,
+    // buffered mutations; `None` marks a delete.
+    buffer: BTreeMap<Vec<u8>, Option<Value>>,
+}
+
+impl Txn {
+    /// Gets the value of `key`, reading through the local buffer first.
+    pub fn get(&self, key: &[u8]) -> Result<Option<Value>> {
+        if let Some(value) = self.buffer.get(key) {
+            return Ok(value.clone());
+        }
+        retry(self.retry_limit, || {
+            self.storage
+                .future_get(self.ctx.clone(), make_key(key), self.start_ts)
+                .wait()
+        })
+    }
+
+    /// Buffers a put of `key`.
+    pub fn put(&mut self, key: Vec<u8>, value: Value) {
+        self.buffer.insert(key, Some(value));
+    }
+
+    /// Buffers a delete of `key`.
+    pub fn delete(&mut self, key: Vec<u8>) {
+        self.buffer.insert(key, None);
+    }
+
+    /// Commits all buffered mutations at `commit_ts`.
+    pub fn commit(self, commit_ts: u64) -> Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        let primary = self.buffer.keys().next().unwrap().to_vec();
+        let keys: Vec<Key> = self.buffer.keys().map(|key| make_key(key)).collect();
+        let mutations: Vec<Mutation> = self.buffer
+            .iter()
+            .map(|(key, value)| match *value {
+                Some(ref value) => Mutation::Put((make_key(key), value.clone())),
+                None => Mutation::Delete(make_key(key)),
+            })
+            .collect();
+
+        retry(self.retry_limit, || {
+            let results = self.storage
+                .future_prewrite(
+                    self.ctx.clone(),
+                    mutations.clone(),
+                    primary.clone(),
+                    self.start_ts,
+                    Options::default(),
+                )
+                .wait()?;
+            for res in results {
+                res?;
+            }
+            Ok(())
+        })?;
+        retry(self.retry_limit, || {
+            self.storage
+                .future_commit(self.ctx.clone(), keys.clone(), self.start_ts, commit_ts)
+                .wait()
+        })
+    }
+}
+
+/// Runs `f` up to `limit` times, stopping early on success or on an error
+/// that is not retryable.
+fn retry<T, F>(limit: usize, mut f: F) -> Result<T>
+where
+    F: FnMut() -> Result<T>,
+{
+    let mut last_err = None;
+    for i in 0..limit {
+        match f() {
+            Ok(res) => return Ok(res),
+            Err(e) => {
+                if !e.error_code().is_retryable() {
+                    return Err(e);
+                }
+                debug!("txn client retries {} on {:?}", i, e);
+                last_err = Some(e);
+            }
+        }
+        thread::sleep(Duration::from_millis(RETRY_PAUSE_MS));
+    }
+    Err(last_err.unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use storage::Config;
+
+    #[test]
+    fn test_txn_client() {
+        let config = Config::default();
+        let mut storage = Storage::new(&config).unwrap();
+        storage.start(&config).unwrap();
+        let client = Client::new(storage.clone());
+
+        let mut txn = client.begin(Context::new(), 10);
+        assert_eq!(txn.get(b"x").unwrap(), None);
+        txn.put(b"x".to_vec(), b"100".to_vec());
+        // Uncommitted mutations are visible through the buffer.
+        assert_eq!(txn.get(b"x").unwrap(), Some(b"100".to_vec()));
+        txn.commit(11).unwrap();
+
+        let mut txn = client.begin(Context::new(), 12);
+        assert_eq!(txn.get(b"x").unwrap(), Some(b"100".to_vec()));
+        txn.delete(b"x".to_vec());
+        txn.commit(13).unwrap();
+
+        let txn = client.begin(Context::new(), 14);
+        assert_eq!(txn.get(b"x").unwrap(), None);
+        storage.stop().unwrap();
+    }
+}
@@ -15,6 +15,7 @@ use std::error::Error;
 
 use sys_info;
 
+use storage::txn::RESOLVE_LOCK_BATCH_SIZE;
 use util::config::{self, ReadableSize};
 
 pub const DEFAULT_DATA_DIR: &str = "";
@@ -36,6 +37,23 @@ const DEFAULT_SCHED_PENDING_WRITE_MB: u64 = 100;
 #[serde(rename_all = "kebab-case")]
 pub struct Config {
     pub data_dir: String,
+    /// When set, all raw keys are silently prefixed with the keyspace id,
+    /// so multiple logical tenants can share one cluster with disjoint
+    /// keyspaces.
+    pub enable_keyspace: bool,
+    /// The keyspace this server serves. Only meaningful when
+    /// `enable_keyspace` is set.
+    pub keyspace_id: u32,
+    /// When set, every raw value is stored with an expiry timestamp
+    /// appended and an expired entry reads as missing until a compaction
+    /// purges it. Pre-existing raw data survives the switch, but values
+    /// written with it on are not readable with it off again.
+    pub enable_raw_ttl: bool,
+
+    /// Collects RocksDB perf context counters around scheduler reads
+    /// and exposes them as metrics, at the price of perf counter
+    /// bookkeeping on every key the reads touch.
+    pub enable_perf_context: bool,
     pub gc_ratio_threshold: f64,
     pub max_key_size: usize,
     pub scheduler_notify_capacity: usize,
@@ -43,6 +61,27 @@ pub struct Config {
     pub scheduler_concurrency: usize,
     pub scheduler_worker_pool_size: usize,
     pub scheduler_pending_write_threshold: ReadableSize,
+    /// How many locks one resolve-lock round scans and resolves before
+    /// it reports progress and reschedules itself, bounding the size of
+    /// a single raft proposal when millions of locks are cleaned up.
+    pub resolve_lock_batch_size: usize,
+    /// The shared rate limit for backup scans, 0 means no limit. A
+    /// backup job may override it with a speed limit of its own.
+    pub backup_rate_limit: ReadableSize,
+    /// When set, every MVCC commit and rollback re-checks the write
+    /// column family for invariant violations and aborts the server on
+    /// one, catching corruption when it is written. Meant for CI and
+    /// canary stores, the checks cost extra reads per write.
+    pub verify_mvcc_invariants: bool,
+    /// When set, a point read that meets a lock whose TTL has expired
+    /// rolls the lock back through its primary and retries once, instead
+    /// of returning the lock to the client for resolution.
+    pub auto_rollback_expired_locks: bool,
+    /// How long, in milliseconds, a write that ran into a lock may be
+    /// parked in the scheduler waiting for the lock to be released,
+    /// before the lock is returned to the client after all. Zero turns
+    /// parking off and every conflict is returned right away.
+    pub wait_for_lock_timeout: u64,
 }
 
 impl Default for Config {
@@ -50,6 +89,10 @@ impl Default for Config {
         let total_cpu = sys_info::cpu_num().unwrap();
         Config {
             data_dir: DEFAULT_DATA_DIR.to_owned(),
+            enable_keyspace: false,
+            keyspace_id: 0,
+            enable_raw_ttl: false,
+            enable_perf_context: false,
             gc_ratio_threshold: DEFAULT_GC_RATIO_THRESHOLD,
             max_key_size: DEFAULT_MAX_KEY_SIZE,
             scheduler_notify_capacity: DEFAULT_SCHED_CAPACITY,
@@ -57,6 +100,11 @@ impl Default for Config {
             scheduler_concurrency: DEFAULT_SCHED_CONCURRENCY,
             scheduler_worker_pool_size: if total_cpu >= 16 { 8 } else { 4 },
             scheduler_pending_write_threshold: ReadableSize::mb(DEFAULT_SCHED_PENDING_WRITE_MB),
+            resolve_lock_batch_size: RESOLVE_LOCK_BATCH_SIZE,
+            backup_rate_limit: ReadableSize(0),
+            verify_mvcc_invariants: false,
+            auto_rollback_expired_locks: false,
+            wait_for_lock_timeout: 0,
         }
     }
 }
@@ -66,6 +114,9 @@ impl Config {
         if self.data_dir != DEFAULT_DATA_DIR {
             self.data_dir = config::canonicalize_path(&self.data_dir)?
         }
+        if self.resolve_lock_batch_size == 0 {
+            return Err("storage.resolve-lock-batch-size must greater than 0".into());
+        }
         Ok(())
     }
 }
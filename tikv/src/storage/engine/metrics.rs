@@ -11,7 +11,9 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use prometheus::{exponential_buckets, CounterVec, HistogramVec};
+use prometheus::{CounterVec, HistogramVec};
+
+use util::metrics::latency_buckets;
 
 lazy_static! {
     pub static ref ASYNC_REQUESTS_COUNTER_VEC: CounterVec =
@@ -26,6 +28,6 @@ lazy_static! {
             "tikv_storage_engine_async_request_duration_seconds",
             "Bucketed histogram of processing successful asynchronous requests.",
             &["type"],
-            exponential_buckets(0.0005, 2.0, 20).unwrap()
+            latency_buckets()
         ).unwrap();
 }
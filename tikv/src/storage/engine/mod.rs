@@ -17,6 +17,7 @@ use std::cmp::Ordering;
 use std::boxed::FnBox;
 use std::time::Duration;
 
+pub use self::perf_context::PerfStatistics;
 pub use self::rocksdb::EngineRocksdb;
 use rocksdb::{ColumnFamilyOptions, TablePropertiesCollection};
 use storage::{CfName, Key, Value, CF_DEFAULT, CF_LOCK, CF_RAFT, CF_WRITE};
@@ -27,6 +28,7 @@ use config;
 
 use util::rocksdb::CFOptions;
 
+mod perf_context;
 mod rocksdb;
 pub mod raftkv;
 mod metrics;
@@ -35,7 +37,7 @@ use super::super::raftstore::store::engine::IterOption;
 // only used for rocksdb without persistent.
 pub const TEMP_DIR: &str = "";
 
-const SEEK_BOUND: usize = 30;
+pub const SEEK_BOUND: usize = 30;
 const DEFAULT_TIMEOUT_SECS: u64 = 5;
 
 const STAT_TOTAL: &str = "total";
@@ -45,6 +47,7 @@ const STAT_NEXT: &str = "next";
 const STAT_PREV: &str = "prev";
 const STAT_SEEK: &str = "seek";
 const STAT_SEEK_FOR_PREV: &str = "seek_for_prev";
+const STAT_NEAR_SEEK: &str = "near_seek";
 const STAT_OVER_SEEK_BOUND: &str = "over_seek_bound";
 
 pub type Callback<T> = Box<FnBox((CbContext, Result<T>)) + Send>;
@@ -54,11 +57,17 @@ pub type BatchCallback<T> = Box<FnBox(BatchResults<T>) + Send>;
 #[derive(Clone, Debug)]
 pub struct CbContext {
     pub term: Option<u64>,
+    /// The applied index of the peer that served the request, if the
+    /// engine knows it.
+    pub apply_index: Option<u64>,
 }
 
 impl CbContext {
     fn new() -> CbContext {
-        CbContext { term: None }
+        CbContext {
+            term: None,
+            apply_index: None,
+        }
     }
 }
 
@@ -69,9 +78,53 @@ pub enum Modify {
     DeleteRange(CfName, Key, Key),
 }
 
+/// Per-write tuning of `Engine::async_write_opt`. Engines honor the
+/// options on a best effort basis, an option the engine cannot express
+/// is ignored.
+#[derive(Debug, Clone, Default)]
+pub struct WriteOptions {
+    /// Skips the write ahead log. Only safe for data that can be
+    /// rebuilt after a crash, like a rewritable cache column family.
+    pub no_wal: bool,
+    /// Syncs the write to disk before the callback runs, for critical
+    /// metadata that must survive a power failure.
+    pub sync: bool,
+    /// Deprioritizes the write behind foreground traffic, for
+    /// background work like GC.
+    pub low_priority: bool,
+}
+
 pub trait Engine: Send + Debug {
-    fn async_write(&self, ctx: &Context, batch: Vec<Modify>, callback: Callback<()>) -> Result<()>;
+    fn async_write(
+        &self,
+        ctx: &Context,
+        batch: Vec<Modify>,
+        callback: Callback<()>,
+    ) -> Result<()> {
+        self.async_write_opt(ctx, batch, WriteOptions::default(), callback)
+    }
+    fn async_write_opt(
+        &self,
+        ctx: &Context,
+        batch: Vec<Modify>,
+        opts: WriteOptions,
+        callback: Callback<()>,
+    ) -> Result<()>;
     fn async_snapshot(&self, ctx: &Context, callback: Callback<Box<Snapshot>>) -> Result<()>;
+    /// Gets a snapshot that is guaranteed to observe every write applied
+    /// up to raft log index `min_index`, so a caller that has seen an
+    /// event at that index (an incremental scan or a backup resuming
+    /// from a checkpoint) reads a causally consistent view. A local
+    /// engine applies writes synchronously and every snapshot already
+    /// observes all of them, so the bound holds trivially.
+    fn async_snapshot_min_index(
+        &self,
+        ctx: &Context,
+        _min_index: u64,
+        callback: Callback<Box<Snapshot>>,
+    ) -> Result<()> {
+        self.async_snapshot(ctx, callback)
+    }
     /// Snapshots are token by `Context`s, the results are send to the `on_finished` callback,
     /// with the same order. If a read-index is occurred, a `None` is placed in the corresponding
     /// slot, and the caller is responsible for reissuing it again, in `async_snapshot`.
@@ -155,11 +208,12 @@ pub trait Iterator {
 }
 
 macro_rules! near_loop {
-    ($cond:expr, $fallback:expr, $st:expr) => ({
+    ($cond:expr, $fallback:expr, $st:expr, $bound:expr) => ({
+        let bound = $bound;
         let mut cnt = 0;
         while $cond {
             cnt += 1;
-            if cnt >= SEEK_BOUND {
+            if cnt >= bound {
                 $st.over_seek_bound += 1;
                 return $fallback;
             }
@@ -185,6 +239,9 @@ pub struct CFStatistics {
     pub prev: usize,
     pub seek: usize,
     pub seek_for_prev: usize,
+    // How many near seeks move the cursor by stepping instead of seeking.
+    // Not a RocksDB op by itself, so it's excluded from `total_op_count`.
+    pub near_seek: usize,
     pub over_seek_bound: usize,
     pub flow_stats: FlowStatistics,
 }
@@ -197,7 +254,7 @@ pub struct FlowStatistics {
 
 impl FlowStatistics {
     pub fn add(&mut self, other: &Self) {
-        self.read_bytes = self.read_keys.saturating_add(other.read_bytes);
+        self.read_bytes = self.read_bytes.saturating_add(other.read_bytes);
         self.read_keys = self.read_keys.saturating_add(other.read_keys);
     }
 }
@@ -217,6 +274,7 @@ impl CFStatistics {
             (STAT_PREV, self.prev),
             (STAT_SEEK, self.seek),
             (STAT_SEEK_FOR_PREV, self.seek_for_prev),
+            (STAT_NEAR_SEEK, self.near_seek),
             (STAT_OVER_SEEK_BOUND, self.over_seek_bound),
         ]
     }
@@ -228,6 +286,7 @@ impl CFStatistics {
         self.prev = self.prev.saturating_add(other.prev);
         self.seek = self.seek.saturating_add(other.seek);
         self.seek_for_prev = self.seek_for_prev.saturating_add(other.seek_for_prev);
+        self.near_seek = self.near_seek.saturating_add(other.near_seek);
         self.over_seek_bound = self.over_seek_bound.saturating_add(other.over_seek_bound);
         self.flow_stats.add(&other.flow_stats);
     }
@@ -270,6 +329,14 @@ impl Statistics {
         self.data.add(&other.data);
     }
 
+    pub fn mut_cf_statistics(&mut self, cf: CfName) -> &mut CFStatistics {
+        match cf {
+            CF_LOCK => &mut self.lock,
+            CF_WRITE => &mut self.write,
+            _ => &mut self.data,
+        }
+    }
+
     pub fn scan_detail(&self) -> ScanDetail {
         let mut detail = ScanDetail::new();
         detail.set_data(self.data.scan_info());
@@ -295,6 +362,8 @@ impl StatisticsSummary {
 pub struct Cursor {
     iter: Box<Iterator>,
     scan_mode: ScanMode,
+    // How many steps near seeks take before falling back to a real seek.
+    seek_bound: usize,
     // the data cursor can be seen will be
     min_key: Option<Vec<u8>>,
     max_key: Option<Vec<u8>>,
@@ -305,11 +374,17 @@ impl Cursor {
         Cursor {
             iter: iter,
             scan_mode: mode,
+            seek_bound: SEEK_BOUND,
             min_key: None,
             max_key: None,
         }
     }
 
+    pub fn set_seek_bound(&mut self, bound: usize) {
+        assert!(bound > 0);
+        self.seek_bound = bound;
+    }
+
     pub fn seek(&mut self, key: &Key, statistics: &mut CFStatistics) -> Result<bool> {
         assert_ne!(self.scan_mode, ScanMode::Backward);
         if self.max_key.as_ref().map_or(false, |k| k <= key.encoded()) {
@@ -351,11 +426,13 @@ impl Cursor {
             self.iter.validate_key(key)?;
             return Ok(false);
         }
+        statistics.near_seek += 1;
         if ord == Ordering::Greater {
             near_loop!(
                 self.prev(statistics) && self.iter.key() > key.encoded().as_slice(),
                 self.seek(key, statistics),
-                statistics
+                statistics,
+                self.seek_bound
             );
             if self.iter.valid() {
                 if self.iter.key() < key.encoded().as_slice() {
@@ -370,7 +447,8 @@ impl Cursor {
             near_loop!(
                 self.next(statistics) && self.iter.key() < key.encoded().as_slice(),
                 self.seek(key, statistics),
-                statistics
+                statistics,
+                self.seek_bound
             );
         }
         if !self.iter.valid() {
@@ -435,11 +513,13 @@ impl Cursor {
             return Ok(false);
         }
 
+        statistics.near_seek += 1;
         if ord == Ordering::Less {
             near_loop!(
                 self.next(statistics) && self.iter.key() < key.encoded().as_slice(),
                 self.seek_for_prev(key, statistics),
-                statistics
+                statistics,
+                self.seek_bound
             );
             if self.iter.valid() {
                 if self.iter.key() > key.encoded().as_slice() {
@@ -453,7 +533,8 @@ impl Cursor {
             near_loop!(
                 self.prev(statistics) && self.iter.key() > key.encoded().as_slice(),
                 self.seek_for_prev(key, statistics),
-                statistics
+                statistics,
+                self.seek_bound
             );
         }
 
@@ -540,9 +621,15 @@ pub fn new_local_engine(path: &str, cfs: &[CfName]) -> Result<Box<Engine>> {
     let cfg_rocksdb = config::DbConfig::default();
     for cf in cfs {
         let cf_opt = match *cf {
-            CF_DEFAULT => CFOptions::new(CF_DEFAULT, cfg_rocksdb.defaultcf.build_opt()),
+            CF_DEFAULT => CFOptions::new(
+                CF_DEFAULT,
+                cfg_rocksdb.defaultcf.build_opt(&cfg_rocksdb.secondary_cache),
+            ),
             CF_LOCK => CFOptions::new(CF_LOCK, cfg_rocksdb.lockcf.build_opt()),
-            CF_WRITE => CFOptions::new(CF_WRITE, cfg_rocksdb.writecf.build_opt()),
+            CF_WRITE => CFOptions::new(
+                CF_WRITE,
+                cfg_rocksdb.writecf.build_opt(&cfg_rocksdb.secondary_cache),
+            ),
             CF_RAFT => CFOptions::new(CF_RAFT, cfg_rocksdb.raftcf.build_opt()),
             _ => CFOptions::new(*cf, ColumnFamilyOptions::new()),
         };
@@ -618,6 +705,7 @@ mod tests {
         test_empty_seek(e.as_ref());
         test_seek(e.as_ref());
         test_near_seek(e.as_ref());
+        test_custom_seek_bound(e.as_ref());
         test_cf(e.as_ref());
         test_empty_write(e.as_ref());
         test_empty_batch_snapshot(e.as_ref());
@@ -822,6 +910,28 @@ mod tests {
         }
     }
 
+    fn test_custom_seek_bound(engine: &Engine) {
+        must_put(engine, b"x", b"1");
+        must_put(engine, b"y", b"2");
+        must_put(engine, b"z", b"3");
+        let snapshot = engine.snapshot(&Context::new()).unwrap();
+        let mut cursor = snapshot
+            .iter(IterOption::default(), ScanMode::Mixed)
+            .unwrap();
+        cursor.set_seek_bound(1);
+        let mut statistics = CFStatistics::default();
+        assert_near_seek(&mut cursor, b"x", (b"x", b"1"));
+        // Stepping from 'x' to 'z' crosses 'y', which exceeds the bound of 1,
+        // so the near seek falls back to a real seek.
+        assert!(cursor.near_seek(&make_key(b"z"), &mut statistics).unwrap());
+        assert_eq!(statistics.near_seek, 1);
+        assert_eq!(statistics.over_seek_bound, 1);
+
+        must_delete(engine, b"x");
+        must_delete(engine, b"y");
+        must_delete(engine, b"z");
+    }
+
     fn test_empty_seek(engine: &Engine) {
         let snapshot = engine.snapshot(&Context::new()).unwrap();
         let mut cursor = snapshot
@@ -0,0 +1,90 @@
+// Copyright 2018 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Snapshots of the RocksDB perf context.
+//!
+//! `CFStatistics` counts what a scan did at the storage layer, but not
+//! what it cost inside RocksDB. The perf context knows how many blocks a
+//! read touched, how many of them came out of the block cache and how
+//! many deletion tombstones it stepped over, which is usually the answer
+//! to why one scan is slow while an identical looking one is fast.
+//! Collection is gated behind config flags because the counters are
+//! updated on every key an iterator touches. The kvproto `ScanDetail`
+//! carries no perf fields yet, so the numbers surface through metrics
+//! and the slow query log instead of the response.
+
+use prometheus::CounterVec;
+use rocksdb::{set_perf_level, PerfContext, PerfLevel};
+
+/// The RocksDB perf counters that explain read cost.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PerfStatistics {
+    pub internal_key_skipped_count: u64,
+    pub internal_delete_skipped_count: u64,
+    pub block_cache_hit_count: u64,
+    pub block_read_count: u64,
+    pub block_read_byte: u64,
+}
+
+impl PerfStatistics {
+    /// Enables counter collection on the calling thread and takes a
+    /// snapshot to diff against later. The perf context is thread local,
+    /// `delta` must be called on the thread that called `start`.
+    pub fn start() -> PerfStatistics {
+        set_perf_level(PerfLevel::EnableCount);
+        Self::snapshot()
+    }
+
+    /// Returns the counters accumulated since `start`.
+    pub fn delta(&self) -> PerfStatistics {
+        let now = Self::snapshot();
+        PerfStatistics {
+            internal_key_skipped_count: now.internal_key_skipped_count
+                - self.internal_key_skipped_count,
+            internal_delete_skipped_count: now.internal_delete_skipped_count
+                - self.internal_delete_skipped_count,
+            block_cache_hit_count: now.block_cache_hit_count - self.block_cache_hit_count,
+            block_read_count: now.block_read_count - self.block_read_count,
+            block_read_byte: now.block_read_byte - self.block_read_byte,
+        }
+    }
+
+    /// Adds the counters to `counter`, labelled with the request type.
+    pub fn report_to(&self, counter: &CounterVec, req: &str) {
+        for &(metric, value) in &[
+            ("internal_key_skipped", self.internal_key_skipped_count),
+            ("internal_delete_skipped", self.internal_delete_skipped_count),
+            ("block_cache_hit", self.block_cache_hit_count),
+            ("block_read", self.block_read_count),
+            ("block_read_byte", self.block_read_byte),
+        ] {
+            if value > 0 {
+                counter
+                    .with_label_values(&[req, metric])
+                    .inc_by(value as f64)
+                    .unwrap();
+            }
+        }
+    }
+
+    fn snapshot() -> PerfStatistics {
+        let ctx = PerfContext::get();
+        PerfStatistics {
+            internal_key_skipped_count: ctx.internal_key_skipped_count(),
+            internal_delete_skipped_count: ctx.internal_delete_skipped_count(),
+            block_cache_hit_count: ctx.block_cache_hit_count(),
+            block_read_count: ctx.block_read_count(),
+            block_read_byte: ctx.block_read_byte(),
+        }
+    }
+}
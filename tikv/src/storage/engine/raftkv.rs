@@ -13,6 +13,7 @@
 
 use std::fmt::{self, Debug, Formatter};
 use std::io::Error as IoError;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 use std::result;
 
@@ -31,7 +32,7 @@ use raftstore::store::engine::IterOption;
 use rocksdb::TablePropertiesCollection;
 use storage::{self, engine, CfName, Key, Value, CF_DEFAULT};
 use super::{BatchCallback, Callback, CbContext, Cursor, Engine, Iterator as EngineIterator,
-            Modify, ScanMode, Snapshot};
+            Modify, ScanMode, Snapshot, WriteOptions};
 use super::metrics::*;
 
 quick_error! {
@@ -107,10 +108,57 @@ impl From<RaftServerError> for engine::Error {
     }
 }
 
+/// How many read completion slots are pre-allocated per engine.
+const INIT_READ_SLOTS: usize = 1024;
+
+/// A slab of reusable completion slots for in flight reads.
+///
+/// Read responses used to be routed back through a fresh boxed closure
+/// capturing the whole callback chain per request; at high read QPS that
+/// allocation churn shows up in profiles. The slots park the callback
+/// and the request count in place instead, so the closure handed to the
+/// raft layer only has to carry the slot token.
+struct ReadSlots {
+    slots: Vec<Option<(usize, Callback<CmdRes>)>>,
+    free: Vec<usize>,
+}
+
+impl ReadSlots {
+    fn with_capacity(cap: usize) -> ReadSlots {
+        ReadSlots {
+            slots: (0..cap).map(|_| None).collect(),
+            free: (0..cap).rev().collect(),
+        }
+    }
+
+    /// Parks a read in a free slot and returns its token. The slab grows
+    /// when more reads are in flight than there are slots.
+    fn acquire(&mut self, req_cnt: usize, cb: Callback<CmdRes>) -> usize {
+        match self.free.pop() {
+            Some(token) => {
+                self.slots[token] = Some((req_cnt, cb));
+                token
+            }
+            None => {
+                self.slots.push(Some((req_cnt, cb)));
+                self.slots.len() - 1
+            }
+        }
+    }
+
+    /// Takes a parked read out of its slot and frees the slot.
+    fn release(&mut self, token: usize) -> (usize, Callback<CmdRes>) {
+        let slot = self.slots[token].take().unwrap();
+        self.free.push(token);
+        slot
+    }
+}
+
 /// `RaftKv` is a storage engine base on `RaftStore`.
 #[derive(Clone)]
 pub struct RaftKv<S: RaftStoreRouter + 'static> {
     router: S,
+    read_slots: Arc<Mutex<ReadSlots>>,
 }
 
 enum CmdRes {
@@ -149,7 +197,10 @@ fn on_write_result(mut write_resp: WriteResponse, req_cnt: usize) -> (CbContext,
 }
 
 fn on_read_result(mut read_resp: ReadResponse, req_cnt: usize) -> (CbContext, Result<CmdRes>) {
-    let cb_ctx = new_ctx(&read_resp.response);
+    let mut cb_ctx = new_ctx(&read_resp.response);
+    if read_resp.apply_index > 0 {
+        cb_ctx.apply_index = Some(read_resp.apply_index);
+    }
     if let Err(e) = check_raft_cmd_response(&mut read_resp.response, req_cnt) {
         return (cb_ctx, Err(e));
     }
@@ -164,7 +215,10 @@ fn on_read_result(mut read_resp: ReadResponse, req_cnt: usize) -> (CbContext, Re
 impl<S: RaftStoreRouter> RaftKv<S> {
     /// Create a RaftKv using specified configuration.
     pub fn new(router: S) -> RaftKv<S> {
-        RaftKv { router }
+        RaftKv {
+            router,
+            read_slots: Arc::new(Mutex::new(ReadSlots::with_capacity(INIT_READ_SLOTS))),
+        }
     }
 
     fn batch_call_snap_commands(
@@ -214,33 +268,52 @@ impl<S: RaftStoreRouter> RaftKv<S> {
         &self,
         ctx: &Context,
         reqs: Vec<Request>,
+        read_quorum: bool,
         cb: Callback<CmdRes>,
     ) -> Result<()> {
         let len = reqs.len();
-        let header = self.new_request_header(ctx);
+        let mut header = self.new_request_header(ctx);
+        if read_quorum {
+            header.set_read_quorum(true);
+        }
         let mut cmd = RaftCmdRequest::new();
         cmd.set_header(header);
         cmd.set_requests(RepeatedField::from_vec(reqs));
 
-        self.router
-            .send_command(
-                cmd,
-                StoreCallback::Read(box move |resp| {
-                    let (cb_ctx, res) = on_read_result(resp, len);
-                    cb((cb_ctx, res.map_err(Error::into)));
-                }),
-            )
-            .map_err(From::from)
+        let token = self.read_slots.lock().unwrap().acquire(len, cb);
+        let slots = Arc::clone(&self.read_slots);
+        let res = self.router.send_command(
+            cmd,
+            StoreCallback::Read(box move |resp| {
+                let (len, cb) = slots.lock().unwrap().release(token);
+                let (cb_ctx, res) = on_read_result(resp, len);
+                cb((cb_ctx, res.map_err(Error::into)));
+            }),
+        );
+        if let Err(e) = res {
+            // The store callback will never run, reclaim the slot.
+            self.read_slots.lock().unwrap().release(token);
+            return Err(e.into());
+        }
+        Ok(())
     }
 
     fn exec_write_requests(
         &self,
         ctx: &Context,
         reqs: Vec<Request>,
+        opts: WriteOptions,
         cb: Callback<CmdRes>,
     ) -> Result<()> {
         let len = reqs.len();
-        let header = self.new_request_header(ctx);
+        let mut header = self.new_request_header(ctx);
+        // Syncing maps to the proposal's `sync_log` flag, the raft log
+        // entry is then fsynced on every peer before it is acknowledged.
+        // `no_wal` and `low_priority` have no corresponding header flag
+        // in kvproto yet and cannot cross the raft protocol.
+        if opts.sync {
+            header.set_sync_log(true);
+        }
         let mut cmd = RaftCmdRequest::new();
         cmd.set_header(header);
         cmd.set_requests(RepeatedField::from_vec(reqs));
@@ -288,10 +361,11 @@ impl<S: RaftStoreRouter> Debug for RaftKv<S> {
 }
 
 impl<S: RaftStoreRouter> Engine for RaftKv<S> {
-    fn async_write(
+    fn async_write_opt(
         &self,
         ctx: &Context,
         modifies: Vec<Modify>,
+        opts: WriteOptions,
         cb: Callback<()>,
     ) -> engine::Result<()> {
         fail_point!("raftkv_async_write");
@@ -341,7 +415,7 @@ impl<S: RaftStoreRouter> Engine for RaftKv<S> {
             .with_label_values(&["write"])
             .start_coarse_timer();
 
-        self.exec_write_requests(ctx, reqs, box move |(cb_ctx, res)| match res {
+        self.exec_write_requests(ctx, reqs, opts, box move |(cb_ctx, res)| match res {
             Ok(CmdRes::Resp(_)) => {
                 req_timer.observe_duration();
                 ASYNC_REQUESTS_COUNTER_VEC
@@ -382,7 +456,7 @@ impl<S: RaftStoreRouter> Engine for RaftKv<S> {
             .with_label_values(&["snapshot"])
             .start_coarse_timer();
 
-        self.exec_read_requests(ctx, vec![req], box move |(cb_ctx, res)| match res {
+        self.exec_read_requests(ctx, vec![req], false, box move |(cb_ctx, res)| match res {
             Ok(CmdRes::Resp(r)) => cb((
                 cb_ctx,
                 Err(invalid_resp_type(CmdType::Snap, r[0].get_cmd_type()).into()),
@@ -411,6 +485,69 @@ impl<S: RaftStoreRouter> Engine for RaftKv<S> {
         })
     }
 
+    fn async_snapshot_min_index(
+        &self,
+        ctx: &Context,
+        min_index: u64,
+        cb: Callback<Box<Snapshot>>,
+    ) -> engine::Result<()> {
+        let mut req = Request::new();
+        req.set_cmd_type(CmdType::Snap);
+
+        ASYNC_REQUESTS_COUNTER_VEC
+            .with_label_values(&["snapshot", "all"])
+            .inc();
+        let req_timer = ASYNC_REQUESTS_DURATIONS_VEC
+            .with_label_values(&["snapshot"])
+            .start_coarse_timer();
+
+        // A quorum read goes through raft read-index, so the snapshot is
+        // only taken after apply has caught up with the commit index the
+        // leader saw when the read arrived. Any event the caller observed
+        // before issuing the request is committed by then, the served
+        // index is still checked against the bound in case the event was
+        // observed on a newer leader this peer has not heard from.
+        self.exec_read_requests(ctx, vec![req], true, box move |(cb_ctx, res)| match res {
+            Ok(CmdRes::Resp(r)) => cb((
+                cb_ctx,
+                Err(invalid_resp_type(CmdType::Snap, r[0].get_cmd_type()).into()),
+            )),
+            Ok(CmdRes::Snap(s)) => {
+                req_timer.observe_duration();
+                let served = cb_ctx.apply_index.unwrap_or(0);
+                if served < min_index {
+                    ASYNC_REQUESTS_COUNTER_VEC
+                        .with_label_values(&["snapshot", "stale_apply"])
+                        .inc();
+                    let e = box_err!(
+                        "applied index {} is less than required {}",
+                        served,
+                        min_index
+                    );
+                    cb((cb_ctx, Err(e)));
+                    return;
+                }
+                ASYNC_REQUESTS_COUNTER_VEC
+                    .with_label_values(&["snapshot", "success"])
+                    .inc();
+                cb((cb_ctx, Ok(box s)))
+            }
+            Err(e) => {
+                let tag = get_tag_from_engine_error(&e);
+                ASYNC_REQUESTS_COUNTER_VEC
+                    .with_label_values(&["snapshot", tag])
+                    .inc();
+                cb((cb_ctx, Err(e)))
+            }
+        }).map_err(|e| {
+            let tag = get_tag_from_error(&e);
+            ASYNC_REQUESTS_COUNTER_VEC
+                .with_label_values(&["snapshot", tag])
+                .inc();
+            e.into()
+        })
+    }
+
     fn async_batch_snapshot(
         &self,
         batch: Vec<Context>,
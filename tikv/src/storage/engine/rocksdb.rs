@@ -15,6 +15,7 @@ use std::ops::Deref;
 use std::fmt::{self, Debug, Display, Formatter};
 use std::sync::{Arc, Mutex};
 use rocksdb::{DBIterator, SeekKey, Writable, WriteBatch, DB};
+use rocksdb::rocksdb_options::WriteOptions as RocksWriteOptions;
 use kvproto::kvrpcpb::Context;
 use storage::{CfName, Key, Value, CF_DEFAULT};
 use raftstore::store::engine::{IterOption, Peekable, SyncSnapshot as RocksSnapshot};
@@ -23,11 +24,11 @@ use util::rocksdb;
 use util::worker::{Runnable, Scheduler, Worker};
 use util::rocksdb::CFOptions;
 use super::{BatchCallback, Callback, CbContext, Cursor, Engine, Error, Iterator as EngineIterator,
-            Modify, Result, ScanMode, Snapshot, TEMP_DIR};
+            Modify, Result, ScanMode, Snapshot, WriteOptions, TEMP_DIR};
 use tempdir::TempDir;
 
 enum Task {
-    Write(Vec<Modify>, Callback<()>),
+    Write(Vec<Modify>, WriteOptions, Callback<()>),
     Snapshot(Callback<Box<Snapshot>>),
     SnapshotBatch(usize, BatchCallback<Box<Snapshot>>),
 }
@@ -47,7 +48,9 @@ struct Runner(Arc<DB>);
 impl Runnable<Task> for Runner {
     fn run(&mut self, t: Task) {
         match t {
-            Task::Write(modifies, cb) => cb((CbContext::new(), write_modifies(&self.0, modifies))),
+            Task::Write(modifies, opts, cb) => {
+                cb((CbContext::new(), write_modifies(&self.0, modifies, &opts)))
+            }
             Task::Snapshot(cb) => cb((
                 CbContext::new(),
                 Ok(box RocksSnapshot::new(Arc::clone(&self.0))),
@@ -130,7 +133,7 @@ impl Debug for EngineRocksdb {
     }
 }
 
-fn write_modifies(db: &DB, modifies: Vec<Modify>) -> Result<()> {
+fn write_modifies(db: &DB, modifies: Vec<Modify>, opts: &WriteOptions) -> Result<()> {
     let wb = WriteBatch::new();
     for rev in modifies {
         let res = match rev {
@@ -165,18 +168,31 @@ fn write_modifies(db: &DB, modifies: Vec<Modify>) -> Result<()> {
             return Err(Error::RocksDb(msg));
         }
     }
-    if let Err(msg) = db.write(wb) {
+    // `low_priority` has no equivalent in the rocksdb binding yet, it
+    // is accepted but ignored here.
+    let mut write_opts = RocksWriteOptions::new();
+    if opts.no_wal {
+        write_opts.disable_wal(true);
+    }
+    write_opts.set_sync(opts.sync);
+    if let Err(msg) = db.write_opt(wb, &write_opts) {
         return Err(Error::RocksDb(msg));
     }
     Ok(())
 }
 
 impl Engine for EngineRocksdb {
-    fn async_write(&self, _: &Context, modifies: Vec<Modify>, cb: Callback<()>) -> Result<()> {
+    fn async_write_opt(
+        &self,
+        _: &Context,
+        modifies: Vec<Modify>,
+        opts: WriteOptions,
+        cb: Callback<()>,
+    ) -> Result<()> {
         if modifies.is_empty() {
             return Err(Error::EmptyRequest);
         }
-        box_try!(self.sched.schedule(Task::Write(modifies, cb)));
+        box_try!(self.sched.schedule(Task::Write(modifies, opts, cb)));
         Ok(())
     }
 
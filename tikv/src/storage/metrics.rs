@@ -13,6 +13,8 @@
 
 use prometheus::*;
 
+use util::metrics::latency_buckets;
+
 lazy_static! {
     pub static ref KV_COMMAND_COUNTER_VEC: CounterVec =
         register_counter_vec!(
@@ -45,7 +47,27 @@ lazy_static! {
             "tikv_scheduler_command_duration_seconds",
             "Bucketed histogram of command execution",
             &["type"],
-            exponential_buckets(0.0005, 2.0, 20).unwrap()
+            latency_buckets()
+        ).unwrap();
+
+    pub static ref SCHED_LOCK_CONFLICT_COUNTER: Counter =
+        register_counter!(
+            "tikv_scheduler_lock_conflict_total",
+            "Total number of KeyIsLocked conflicts observed by the scheduler."
+        ).unwrap();
+
+    pub static ref SCHED_LOCK_WAIT_COUNTER_VEC: CounterVec =
+        register_counter_vec!(
+            "tikv_scheduler_lock_wait_total",
+            "Total number of commands parked on a lock, by outcome.",
+            &["type"]
+        ).unwrap();
+
+    pub static ref SCHED_HELD_SNAPSHOT_COUNTER_VEC: CounterVec =
+        register_counter_vec!(
+            "tikv_scheduler_held_snapshot_total",
+            "Total number of snapshots held for and reused by follow-up lookups.",
+            &["type"]
         ).unwrap();
 
     pub static ref SCHED_LATCH_HISTOGRAM_VEC: HistogramVec =
@@ -53,7 +75,7 @@ lazy_static! {
             "tikv_scheduler_latch_wait_duration_seconds",
             "Bucketed histogram of latch wait",
             &["type"],
-            exponential_buckets(0.0005, 2.0, 20).unwrap()
+            latency_buckets()
         ).unwrap();
 
     pub static ref SCHED_PROCESSING_READ_HISTOGRAM_VEC: HistogramVec =
@@ -61,7 +83,7 @@ lazy_static! {
             "tikv_scheduler_processing_read_duration_seconds",
             "Bucketed histogram of processing read duration",
             &["type"],
-            exponential_buckets(0.0005, 2.0, 20).unwrap()
+            latency_buckets()
         ).unwrap();
 
     pub static ref SCHED_PROCESSING_WRITE_HISTOGRAM_VEC: HistogramVec =
@@ -69,7 +91,7 @@ lazy_static! {
             "tikv_scheduler_processing_write_duration_seconds",
             "Bucketed histogram of processing write duration",
             &["type"],
-            exponential_buckets(0.0005, 2.0, 20).unwrap()
+            latency_buckets()
         ).unwrap();
 
     pub static ref SCHED_TOO_BUSY_COUNTER_VEC: CounterVec =
@@ -101,6 +123,13 @@ lazy_static! {
             &["req","cf","tag"]
         ).unwrap();
 
+    pub static ref KV_COMMAND_ROCKSDB_PERF_COUNTER: CounterVec =
+        register_counter_vec!(
+            "tikv_scheduler_rocksdb_perf",
+            "Total RocksDB perf context counters of kv commands",
+            &["req", "metric"]
+        ).unwrap();
+
     pub static ref RAWKV_COMMAND_COUNTER_VEC: CounterVec =
         register_counter_vec!(
             "tikv_storage_rawkv_command_total",
@@ -12,43 +12,130 @@
 // limitations under the License.
 
 use std::boxed::FnBox;
+use std::collections::BTreeMap;
 use std::sync::{Arc, Mutex};
 use std::fmt::{self, Debug, Display, Formatter};
 use std::error;
 use std::io::Error as IoError;
+use std::time::Duration;
 use std::u64;
-use kvproto::kvrpcpb::{CommandPri, Context, LockInfo};
+use kvproto::kvrpcpb::{CommandPri, Context, IsolationLevel, LockInfo};
 use kvproto::errorpb;
+use crc::crc32::{self, Digest, Hasher32};
+use futures::{future, Future};
+use futures::sync::oneshot;
+use time;
 use self::metrics::*;
-use self::mvcc::Lock;
-use self::txn::CMD_BATCH_SIZE;
+use self::mvcc::{Lock, WriteType};
+use self::txn::{CMD_BATCH_SIZE, LOCK_WAIT_CHECK_INTERVAL_MS};
+use pd::PdTask;
+use util::codec::number;
 use util::collections::HashMap;
-use util::worker::{self, Builder, Worker};
+use util::error_code::{self, ErrorCode, ErrorCodeExt};
+use util::io_limiter::{IOLimiter, IOPriority};
+use util::rocksdb::raw_ttl;
+use util::timer::Timer;
+use util::worker::{self, Builder, FutureScheduler, Worker};
 
 pub mod engine;
 pub mod mvcc;
 pub mod txn;
 pub mod config;
 pub mod types;
+pub mod client;
 mod metrics;
 
 pub use self::config::{Config, DEFAULT_DATA_DIR, DEFAULT_ROCKSDB_SUB_DIR};
 pub use self::engine::{new_local_engine, CFStatistics, Cursor, Engine, Error as EngineError,
                        FlowStatistics, Iterator, Modify, ScanMode, Snapshot, Statistics,
-                       StatisticsSummary, TEMP_DIR};
+                       StatisticsSummary, WriteOptions, TEMP_DIR};
 pub use self::engine::raftkv::RaftKv;
 pub use self::txn::{Msg, Scheduler, SnapshotStore, StoreScanner};
-pub use self::types::{make_key, Key, KvPair, MvccInfo, Value};
-pub type Callback<T> = Box<FnBox(Result<T>) + Send>;
+pub use self::types::{keyspace_prefix, make_key, strip_keyspace, Key, KvPair, MvccInfo, Value,
+                      KEYSPACE_PREFIX_LEN};
+
+/// `Callback` delivers the result of an async storage request.
+///
+/// The gRPC layer waits for every unary response on a oneshot channel, so
+/// it completes the sender directly via `Oneshot` instead of boxing a
+/// closure that captures it. This saves a heap allocation and a dynamic
+/// dispatch per request. `Boxed` remains for callers that need to run
+/// arbitrary code on completion.
+pub enum Callback<T> {
+    Boxed(Box<FnBox(Result<T>) + Send>),
+    Oneshot(oneshot::Sender<Result<T>>),
+}
+
+impl<T> Callback<T> {
+    /// Consumes the callback with the result of the request.
+    pub fn call(self, res: Result<T>) {
+        match self {
+            Callback::Boxed(cb) => cb(res),
+            Callback::Oneshot(tx) => {
+                // The receiver is dropped when the request is cancelled,
+                // in which case nobody cares about the result.
+                let _ = tx.send(res);
+            }
+        }
+    }
+}
+
+/// A future yielding the result of an async storage request.
+pub type StorageFuture<T> = Box<Future<Item = T, Error = Error> + Send>;
+
+/// Creates a oneshot callback paired with the receiver that waits on it.
+pub fn paired_future_callback<T>() -> (Callback<T>, oneshot::Receiver<Result<T>>) {
+    let (tx, rx) = oneshot::channel();
+    (Callback::Oneshot(tx), rx)
+}
+
+/// Drops raw scan results that run past the end of the keyspace and strips
+/// the keyspace prefix from the remaining keys.
+fn strip_keyspace_pairs(keyspace_id: u32, pairs: Vec<Result<KvPair>>) -> Vec<Result<KvPair>> {
+    let prefix = keyspace_prefix(keyspace_id);
+    pairs
+        .into_iter()
+        .take_while(|pair| match *pair {
+            Ok((ref key, _)) => key.starts_with(&prefix),
+            Err(_) => true,
+        })
+        .map(|pair| pair.map(|(key, value)| (key[KEYSPACE_PREFIX_LEN..].to_vec(), value)))
+        .collect()
+}
+
+/// Drops expired pairs and strips the expiry suffix from the rest. See
+/// `util::rocksdb::raw_ttl`.
+fn strip_ttl_pairs(pairs: Vec<Result<KvPair>>) -> Vec<Result<KvPair>> {
+    pairs
+        .into_iter()
+        .filter_map(|r| match r {
+            Ok((k, v)) => raw_ttl::strip_expire_ts(v).map(|v| Ok((k, v))),
+            Err(e) => Some(Err(e)),
+        })
+        .collect()
+}
+
+// A TSO timestamp carries the physical time in milliseconds in its high
+// bits, the low bits are a logical counter.
+const TS_PHYSICAL_SHIFT_BITS: u64 = 18;
+
+/// Checks whether a lock has outlived its TTL against the wall clock.
+fn is_lock_expired(lock_ts: u64, ttl: u64) -> bool {
+    let now = time::get_time();
+    let now_ms = now.sec as u64 * 1000 + now.nsec as u64 / 1_000_000;
+    (lock_ts >> TS_PHYSICAL_SHIFT_BITS) + ttl < now_ms
+}
 
 pub type CfName = &'static str;
 pub const CF_DEFAULT: CfName = "default";
 pub const CF_LOCK: CfName = "lock";
 pub const CF_WRITE: CfName = "write";
 pub const CF_RAFT: CfName = "raft";
+// Cf for frequently-updated apply states, kept small and compacted aggressively.
+pub const CF_APPLY: CfName = "apply";
 // Cfs that should be very large generally.
 pub const LARGE_CFS: &[CfName] = &[CF_DEFAULT, CF_WRITE];
-pub const ALL_CFS: &[CfName] = &[CF_DEFAULT, CF_LOCK, CF_WRITE, CF_RAFT];
+pub const ALL_CFS: &[CfName] = &[CF_DEFAULT, CF_LOCK, CF_WRITE, CF_RAFT, CF_APPLY];
 pub const DATA_CFS: &[CfName] = &[CF_DEFAULT, CF_LOCK, CF_WRITE];
 
 // Short value max len must <= 255.
@@ -101,6 +188,9 @@ pub enum Command {
     Scan {
         ctx: Context,
         start_key: Key,
+        // An optional boundary the scan does not cross: a forward scan
+        // stops before `end_key`, a reverse scan does not go below it.
+        end_key: Option<Key>,
         limit: usize,
         start_ts: u64,
         options: Options,
@@ -112,6 +202,14 @@ pub enum Command {
         start_ts: u64,
         options: Options,
     },
+    AcquirePessimisticLock {
+        ctx: Context,
+        keys: Vec<Key>,
+        primary: Vec<u8>,
+        start_ts: u64,
+        for_update_ts: u64,
+        options: Options,
+    },
     Commit {
         ctx: Context,
         keys: Vec<Key>,
@@ -128,6 +226,12 @@ pub enum Command {
         keys: Vec<Key>,
         start_ts: u64,
     },
+    PessimisticRollback {
+        ctx: Context,
+        keys: Vec<Key>,
+        start_ts: u64,
+        for_update_ts: u64,
+    },
     ScanLock {
         ctx: Context,
         max_ts: u64,
@@ -149,12 +253,31 @@ pub enum Command {
     },
     RawGet {
         ctx: Context,
+        cf: CfName,
         key: Key,
     },
+    RawBatchGet {
+        ctx: Context,
+        cf: CfName,
+        keys: Vec<Key>,
+    },
     RawScan {
         ctx: Context,
+        cf: CfName,
         start_key: Key,
         limit: usize,
+        options: Options,
+    },
+    RawIncr {
+        ctx: Context,
+        key: Key,
+        delta: i64,
+    },
+    RawCompareAndSwap {
+        ctx: Context,
+        key: Key,
+        expected: Option<Value>,
+        new_value: Value,
     },
     DeleteRange {
         ctx: Context,
@@ -199,13 +322,14 @@ impl Display for Command {
             Command::Scan {
                 ref ctx,
                 ref start_key,
+                ref end_key,
                 limit,
                 start_ts,
                 ..
             } => write!(
                 f,
-                "kv::command::scan {}({}) @ {} | {:?}",
-                start_key, limit, start_ts, ctx
+                "kv::command::scan {} -> {:?}({}) @ {} | {:?}",
+                start_key, end_key, limit, start_ts, ctx
             ),
             Command::Prewrite {
                 ref ctx,
@@ -233,6 +357,20 @@ impl Display for Command {
                 commit_ts,
                 ctx
             ),
+            Command::AcquirePessimisticLock {
+                ref ctx,
+                ref keys,
+                start_ts,
+                for_update_ts,
+                ..
+            } => write!(
+                f,
+                "kv::command::acquire_pessimistic_lock keys({}) @ {} {} | {:?}",
+                keys.len(),
+                start_ts,
+                for_update_ts,
+                ctx
+            ),
             Command::Cleanup {
                 ref ctx,
                 ref key,
@@ -251,6 +389,19 @@ impl Display for Command {
                 start_ts,
                 ctx
             ),
+            Command::PessimisticRollback {
+                ref ctx,
+                ref keys,
+                start_ts,
+                for_update_ts,
+            } => write!(
+                f,
+                "kv::command::pessimistic_rollback keys({}) @ {} {} | {:?}",
+                keys.len(),
+                start_ts,
+                for_update_ts,
+                ctx
+            ),
             Command::ScanLock {
                 ref ctx,
                 max_ts,
@@ -273,18 +424,41 @@ impl Display for Command {
                 "kv::command::gc scan {:?} @ {} | {:?}",
                 scan_key, safe_point, ctx
             ),
-            Command::RawGet { ref ctx, ref key } => {
-                write!(f, "kv::command::rawget {:?} | {:?}", key, ctx)
-            }
+            Command::RawGet {
+                ref ctx,
+                cf,
+                ref key,
+            } => write!(f, "kv::command::rawget {:?} {} | {:?}", key, cf, ctx),
+            Command::RawBatchGet {
+                ref ctx,
+                cf,
+                ref keys,
+            } => write!(
+                f,
+                "kv::command::rawbatchget {} {} | {:?}",
+                keys.len(),
+                cf,
+                ctx
+            ),
             Command::RawScan {
                 ref ctx,
+                cf,
                 ref start_key,
                 limit,
+                ..
             } => write!(
                 f,
-                "kv::command::rawscan {:?} {} | {:?}",
-                start_key, limit, ctx
+                "kv::command::rawscan {:?} {} {} | {:?}",
+                start_key, cf, limit, ctx
             ),
+            Command::RawIncr {
+                ref ctx,
+                ref key,
+                delta,
+            } => write!(f, "kv::command::rawincr {:?} {} | {:?}", key, delta, ctx),
+            Command::RawCompareAndSwap {
+                ref ctx, ref key, ..
+            } => write!(f, "kv::command::rawcas {:?} | {:?}", key, ctx),
             Command::DeleteRange {
                 ref ctx,
                 ref start_key,
@@ -324,6 +498,7 @@ impl Command {
             Command::Scan { .. } |
             Command::ScanLock { .. } |
             Command::RawGet { .. } |
+            Command::RawBatchGet { .. } |
             Command::RawScan { .. } |
             // DeleteRange only called by DDL bg thread after table is dropped and
             // must guarantee that there is no other read or write on these keys, so
@@ -360,14 +535,19 @@ impl Command {
             Command::BatchGet { .. } => "batch_get",
             Command::Scan { .. } => "scan",
             Command::Prewrite { .. } => "prewrite",
+            Command::AcquirePessimisticLock { .. } => "acquire_pessimistic_lock",
             Command::Commit { .. } => "commit",
             Command::Cleanup { .. } => "cleanup",
             Command::Rollback { .. } => "rollback",
+            Command::PessimisticRollback { .. } => "pessimistic_rollback",
             Command::ScanLock { .. } => "scan_lock",
             Command::ResolveLock { .. } => "resolve_lock",
             Command::Gc { .. } => CMD_TAG_GC,
             Command::RawGet { .. } => "raw_get",
+            Command::RawBatchGet { .. } => "raw_batch_get",
             Command::RawScan { .. } => "raw_scan",
+            Command::RawIncr { .. } => "raw_incr",
+            Command::RawCompareAndSwap { .. } => "raw_cas",
             Command::DeleteRange { .. } => "delete_range",
             Command::Pause { .. } => "pause",
             Command::MvccByKey { .. } => "key_mvcc",
@@ -381,15 +561,20 @@ impl Command {
             | Command::BatchGet { start_ts, .. }
             | Command::Scan { start_ts, .. }
             | Command::Prewrite { start_ts, .. }
+            | Command::AcquirePessimisticLock { start_ts, .. }
             | Command::Cleanup { start_ts, .. }
             | Command::Rollback { start_ts, .. }
+            | Command::PessimisticRollback { start_ts, .. }
             | Command::MvccByStartTs { start_ts, .. } => start_ts,
             Command::Commit { lock_ts, .. } => lock_ts,
             Command::ScanLock { max_ts, .. } => max_ts,
             Command::Gc { safe_point, .. } => safe_point,
             Command::ResolveLock { .. }
             | Command::RawGet { .. }
+            | Command::RawBatchGet { .. }
             | Command::RawScan { .. }
+            | Command::RawIncr { .. }
+            | Command::RawCompareAndSwap { .. }
             | Command::DeleteRange { .. }
             | Command::Pause { .. }
             | Command::MvccByKey { .. } => 0,
@@ -402,14 +587,19 @@ impl Command {
             | Command::BatchGet { ref ctx, .. }
             | Command::Scan { ref ctx, .. }
             | Command::Prewrite { ref ctx, .. }
+            | Command::AcquirePessimisticLock { ref ctx, .. }
             | Command::Commit { ref ctx, .. }
             | Command::Cleanup { ref ctx, .. }
             | Command::Rollback { ref ctx, .. }
+            | Command::PessimisticRollback { ref ctx, .. }
             | Command::ScanLock { ref ctx, .. }
             | Command::ResolveLock { ref ctx, .. }
             | Command::Gc { ref ctx, .. }
             | Command::RawGet { ref ctx, .. }
+            | Command::RawBatchGet { ref ctx, .. }
             | Command::RawScan { ref ctx, .. }
+            | Command::RawIncr { ref ctx, .. }
+            | Command::RawCompareAndSwap { ref ctx, .. }
             | Command::DeleteRange { ref ctx, .. }
             | Command::Pause { ref ctx, .. }
             | Command::MvccByKey { ref ctx, .. }
@@ -423,14 +613,19 @@ impl Command {
             | Command::BatchGet { ref mut ctx, .. }
             | Command::Scan { ref mut ctx, .. }
             | Command::Prewrite { ref mut ctx, .. }
+            | Command::AcquirePessimisticLock { ref mut ctx, .. }
             | Command::Commit { ref mut ctx, .. }
             | Command::Cleanup { ref mut ctx, .. }
             | Command::Rollback { ref mut ctx, .. }
+            | Command::PessimisticRollback { ref mut ctx, .. }
             | Command::ScanLock { ref mut ctx, .. }
             | Command::ResolveLock { ref mut ctx, .. }
             | Command::Gc { ref mut ctx, .. }
             | Command::RawGet { ref mut ctx, .. }
+            | Command::RawBatchGet { ref mut ctx, .. }
             | Command::RawScan { ref mut ctx, .. }
+            | Command::RawIncr { ref mut ctx, .. }
+            | Command::RawCompareAndSwap { ref mut ctx, .. }
             | Command::DeleteRange { ref mut ctx, .. }
             | Command::Pause { ref mut ctx, .. }
             | Command::MvccByKey { ref mut ctx, .. }
@@ -452,7 +647,10 @@ impl Command {
                     }
                 }
             },
-            Command::Commit { ref keys, .. } | Command::Rollback { ref keys, .. } => {
+            Command::AcquirePessimisticLock { ref keys, .. }
+            | Command::Commit { ref keys, .. }
+            | Command::Rollback { ref keys, .. }
+            | Command::PessimisticRollback { ref keys, .. } => {
                 for key in keys {
                     bytes += key.encoded().len();
                 }
@@ -463,10 +661,79 @@ impl Command {
             Command::Cleanup { ref key, .. } => {
                 bytes += key.encoded().len();
             }
+            Command::RawIncr { ref key, .. } => {
+                bytes += key.encoded().len() + number::I64_SIZE;
+            }
+            Command::RawCompareAndSwap {
+                ref key,
+                ref new_value,
+                ..
+            } => {
+                bytes += key.encoded().len() + new_value.len();
+            }
             _ => {}
         }
         bytes
     }
+
+    /// Returns a crc32 digest of the keys the command touches, so slow log
+    /// entries can identify a command without logging raw keys.
+    pub fn key_digest(&self) -> u32 {
+        let mut digest = Digest::new(crc32::IEEE);
+        match *self {
+            Command::Get { ref key, .. } |
+            Command::Cleanup { ref key, .. } |
+            Command::RawGet { ref key, .. } |
+            Command::RawIncr { ref key, .. } |
+            Command::RawCompareAndSwap { ref key, .. } |
+            Command::MvccByKey { ref key, .. } => {
+                digest.write(key.encoded());
+            }
+            Command::BatchGet { ref keys, .. } |
+            Command::RawBatchGet { ref keys, .. } |
+            Command::AcquirePessimisticLock { ref keys, .. } |
+            Command::Commit { ref keys, .. } |
+            Command::Rollback { ref keys, .. } |
+            Command::PessimisticRollback { ref keys, .. } => for key in keys {
+                digest.write(key.encoded());
+            },
+            Command::Prewrite { ref mutations, .. } => for m in mutations {
+                match *m {
+                    Mutation::Put((ref key, _)) |
+                    Mutation::Delete(ref key) |
+                    Mutation::Lock(ref key) => {
+                        digest.write(key.encoded());
+                    }
+                }
+            },
+            Command::ResolveLock { ref key_locks, .. } => for lock in key_locks {
+                digest.write(lock.0.encoded());
+            },
+            Command::Scan {
+                ref start_key,
+                ref end_key,
+                ..
+            } => {
+                digest.write(start_key.encoded());
+                if let Some(ref k) = *end_key {
+                    digest.write(k.encoded());
+                }
+            }
+            Command::RawScan { ref start_key, .. } => {
+                digest.write(start_key.encoded());
+            }
+            Command::DeleteRange {
+                ref start_key,
+                ref end_key,
+                ..
+            } => {
+                digest.write(start_key.encoded());
+                digest.write(end_key.encoded());
+            }
+            _ => {}
+        }
+        digest.sum32()
+    }
 }
 
 #[derive(Clone, Default)]
@@ -474,6 +741,18 @@ pub struct Options {
     pub lock_ttl: u64,
     pub skip_constraint_check: bool,
     pub key_only: bool,
+    /// Hints that a `BatchGet` on the same range with the same
+    /// `start_ts` follows right away, so the scheduler holds the scan's
+    /// snapshot for it and spares the second snapshot acquisition. The
+    /// kvproto scan request carries no such hint yet, only embedding
+    /// callers can set it.
+    pub hold_snapshot: bool,
+    /// Makes a `Scan` iterate backward, from `start_key` (exclusive)
+    /// toward smaller keys, so a descending index read does not have to
+    /// fetch the whole range forward and reverse it client side. The
+    /// kvproto scan request carries no reverse flag yet, only embedding
+    /// callers can set it.
+    pub reverse: bool,
 }
 
 impl Options {
@@ -482,10 +761,59 @@ impl Options {
             lock_ttl: lock_ttl,
             skip_constraint_check: skip_constraint_check,
             key_only: key_only,
+            ..Default::default()
         }
     }
 }
 
+/// A pluggable authorization hook consulted before a request runs, at
+/// the storage entry points and the coprocessor endpoint.
+///
+/// Embedders install one with `Storage::set_access_checker` to enforce
+/// tenant isolation or read only key ranges; without one every client
+/// with network access can read or overwrite any key, which matches the
+/// trust model of a closed cluster.
+pub trait AccessChecker: Send + Sync {
+    /// Checks a request against the key range it touches. The bounds
+    /// are raw user keys and inclusive; an empty `end_key` means no
+    /// upper bound and an empty `start_key` no lower bound. `write` is
+    /// set for mutating commands. Returning an error rejects the
+    /// request before it is scheduled and the message is reported to
+    /// the client.
+    fn check_access(
+        &self,
+        ctx: &Context,
+        start_key: &[u8],
+        end_key: &[u8],
+        write: bool,
+    ) -> ::std::result::Result<(), String>;
+}
+
+/// Validates that the keys of a command fall within the region its
+/// `Context` claims (region id plus epoch), before the command is
+/// scheduled. Without it, malformed client routing only fails deep in
+/// raftstore with a confusing error after the command went through the
+/// scheduler.
+///
+/// Installed with `Storage::set_region_validator`; the region cache in
+/// `pd` implements it.
+pub trait RegionValidator: Send + Sync {
+    /// Checks the key bounds of a request against the claimed region.
+    /// `start_key` is inclusive and `end_key` is exclusive — one past
+    /// the last key addressed, so a range clipped to the region end is
+    /// valid. Both live in the encoded key space, the same form
+    /// raftstore checks proposals against; an empty `end_key` means
+    /// there is no upper bound to check. A validator that can not
+    /// judge a request (e.g. its view of the region is older than the
+    /// claimed epoch) must let it pass; raftstore stays the authority.
+    fn validate(
+        &self,
+        ctx: &Context,
+        start_key: &[u8],
+        end_key: &[u8],
+    ) -> ::std::result::Result<(), errorpb::Error>;
+}
+
 pub struct Storage {
     engine: Box<Engine>,
 
@@ -493,9 +821,253 @@ pub struct Storage {
     worker: Arc<Mutex<Worker<Msg>>>,
     worker_scheduler: worker::Scheduler<Msg>,
 
+    // to report read flow statistics to PD, if set.
+    read_flow_scheduler: Option<FutureScheduler<PdTask>>,
+
     // Storage configurations.
     gc_ratio_threshold: f64,
     max_key_size: usize,
+    // the keyspace all raw keys are prefixed with, if enabled.
+    keyspace: Option<u32>,
+
+    // raw values carry an expiry timestamp suffix, if enabled.
+    raw_ttl: bool,
+
+    // shared rate limit for backup scans, if configured.
+    backup_limiter: Option<Arc<IOLimiter>>,
+
+    // roll expired locks back on point reads instead of reporting them.
+    auto_rollback_expired_locks: bool,
+
+    // authorization hook consulted before every data access, if set.
+    access_checker: Option<Arc<AccessChecker>>,
+
+    // region routing check applied before commands are scheduled, if set.
+    region_validator: Option<Arc<RegionValidator>>,
+
+    // active historical read timestamps; GC is held back to the oldest one.
+    read_ts_registry: Arc<Mutex<BTreeMap<u64, usize>>>,
+}
+
+/// Holds back GC for one registered read. See `Storage::register_read_ts`.
+pub struct ReadTsGuard {
+    ts: u64,
+    registry: Arc<Mutex<BTreeMap<u64, usize>>>,
+}
+
+impl Drop for ReadTsGuard {
+    fn drop(&mut self) {
+        let mut registry = self.registry.lock().unwrap();
+        {
+            let count = registry.get_mut(&self.ts).unwrap();
+            *count -= 1;
+            if *count > 0 {
+                return;
+            }
+        }
+        registry.remove(&self.ts);
+    }
+}
+
+/// A pull based iterator over the committed key value pairs of a key
+/// range. See `Storage::snapshot_scan_stream`.
+pub struct SnapshotScanStream {
+    engine: Box<Engine>,
+    ctx: Context,
+    // Lazily taken on the first pull after the stream is created or the
+    // context is switched.
+    store: Option<SnapshotStore>,
+    // Takes the place of `store` in incremental mode (`start_ts > 0`),
+    // where the stream walks the write CF records itself.
+    reader: Option<mvcc::MvccReader>,
+    ts: u64,
+    // Exclusive lower bound on the commit ts, 0 for a full scan.
+    start_ts: u64,
+    cursor: Key,
+    end_key: Option<Key>,
+    batch_size: usize,
+    finished: bool,
+    // Limits the scan read traffic, shared with the other backup jobs
+    // unless the request carried its own speed limit.
+    limiter: Option<Arc<IOLimiter>>,
+    // Holds back GC so the versions at `ts` outlive the scan.
+    _read_ts_guard: ReadTsGuard,
+}
+
+impl SnapshotScanStream {
+    /// Moves the stream over to another region. The snapshot taken for
+    /// the previous context is dropped and the next pull validates the
+    /// new region epoch. The scan resumes from where it stopped.
+    pub fn set_context(&mut self, ctx: Context) {
+        self.ctx = ctx;
+        self.store = None;
+        self.reader = None;
+    }
+
+    /// Pulls the next batch of pairs. Returns `None` once the range is
+    /// exhausted. A pull that fails (e.g. on a stale region epoch) does
+    /// not end the stream, so the caller may adjust the context and pull
+    /// again.
+    pub fn next_batch(&mut self) -> Option<Result<Vec<Result<KvPair>>>> {
+        if self.finished {
+            return None;
+        }
+        match self.pull() {
+            Ok(ref pairs) if pairs.is_empty() => None,
+            Ok(pairs) => Some(Ok(pairs)),
+            Err(e) => Some(Err(e)),
+        }
+    }
+
+    fn pull(&mut self) -> Result<Vec<Result<KvPair>>> {
+        if self.start_ts > 0 {
+            return self.pull_incremental();
+        }
+        if self.store.is_none() {
+            let snap = self.engine.snapshot(&self.ctx)?;
+            self.store = Some(SnapshotStore::new(
+                snap,
+                self.ts,
+                self.ctx.get_isolation_level(),
+                !self.ctx.get_not_fill_cache(),
+            ));
+        }
+        let upper_bound = self.end_key.as_ref().map(|k| k.encoded().to_vec());
+        let mut pairs = {
+            let store = self.store.as_ref().unwrap();
+            let mut scanner = store.scanner(ScanMode::Forward, false, None, upper_bound)?;
+            scanner.scan(self.cursor.clone(), self.batch_size)?
+        };
+        if pairs.len() < self.batch_size {
+            self.finished = true;
+        }
+        let mut last_key = None;
+        let pairs = pairs
+            .drain(..)
+            .map(|r| match r {
+                Ok((key, value)) => {
+                    last_key = Some(key.clone());
+                    Ok((key, value))
+                }
+                Err(e) => {
+                    if let txn::Error::Mvcc(mvcc::Error::KeyIsLocked { ref key, .. }) = e {
+                        last_key = Some(key.clone());
+                    }
+                    Err(Error::from(e))
+                }
+            })
+            .collect();
+        if let Some(key) = last_key {
+            // `scan` seeks inclusively, so resume past all versions of
+            // the last returned key, the same way `StoreScanner::scan`
+            // steps between keys.
+            self.cursor = Key::from_raw(&key).append_ts(0);
+        }
+        self.throttle(&pairs);
+        Ok(pairs)
+    }
+
+    // Backups run against live stores, so their read traffic goes
+    // through the limiter at low priority and yields to foreground
+    // requests.
+    fn throttle(&self, pairs: &[Result<KvPair>]) {
+        if let Some(ref limiter) = self.limiter {
+            let mut bytes = 0;
+            for pair in pairs {
+                if let Ok((ref k, ref v)) = *pair {
+                    bytes += k.len() + v.len();
+                }
+            }
+            limiter.throttle(IOPriority::Low, bytes as i64);
+        }
+    }
+
+    /// Pulls a batch in incremental mode. The full scan reads through a
+    /// `SnapshotStore`, but that only exposes the latest version at `ts`,
+    /// so this walks the write CF directly: for every key in the range it
+    /// looks for the newest `Put` at or below `ts` and keeps it only if
+    /// its commit ts is above `start_ts`.
+    fn pull_incremental(&mut self) -> Result<Vec<Result<KvPair>>> {
+        if self.reader.is_none() {
+            let snap = self.engine.snapshot(&self.ctx)?;
+            self.reader = Some(mvcc::MvccReader::new(
+                snap,
+                Some(ScanMode::Forward),
+                !self.ctx.get_not_fill_cache(),
+                None,
+                None,
+                self.ctx.get_isolation_level(),
+            ));
+        }
+        let mut pairs = Vec::new();
+        while pairs.len() < self.batch_size {
+            let (keys, next) = {
+                let reader = self.reader.as_mut().unwrap();
+                reader.scan_keys(Some(self.cursor.clone()), self.batch_size - pairs.len())?
+            };
+            let exhausted = next.is_none();
+            for key in keys {
+                // `scan_keys` does not know about the end key, it is
+                // enforced here instead.
+                if let Some(ref end) = self.end_key {
+                    if key.encoded().as_slice() >= end.encoded().as_slice() {
+                        self.finished = true;
+                        self.throttle(&pairs);
+                        return Ok(pairs);
+                    }
+                }
+                self.cursor = key.append_ts(0);
+                let reader = self.reader.as_mut().unwrap();
+                // Surface pending transactions the same way the full scan
+                // does, so the caller can resolve them and pull again.
+                if self.ctx.get_isolation_level() == IsolationLevel::SI {
+                    if let Some(lock) = reader.load_lock(&key)? {
+                        if lock.ts <= self.ts {
+                            pairs.push(Err(Error::from(mvcc::Error::KeyIsLocked {
+                                key: key.raw().map_err(mvcc::Error::from)?,
+                                primary: lock.primary,
+                                ts: lock.ts,
+                                ttl: lock.ttl,
+                            })));
+                            continue;
+                        }
+                    }
+                }
+                let mut found = None;
+                let mut ts = self.ts;
+                while let Some((commit_ts, write)) = reader.seek_write(&key, ts)? {
+                    if commit_ts <= self.start_ts {
+                        break;
+                    }
+                    match write.write_type {
+                        WriteType::Put => {
+                            found = Some(write);
+                            break;
+                        }
+                        // The key was deleted within the range, it is
+                        // simply absent from the output.
+                        WriteType::Delete => break,
+                        // Lock and Rollback records carry no data, step
+                        // down to the next older version.
+                        WriteType::Lock | WriteType::Rollback => ts = commit_ts - 1,
+                    }
+                }
+                if let Some(write) = found {
+                    let value = match write.short_value {
+                        Some(v) => v,
+                        None => reader.load_data(&key, write.start_ts)?,
+                    };
+                    pairs.push(Ok((key.raw().map_err(mvcc::Error::from)?, value)));
+                }
+            }
+            if exhausted {
+                self.finished = true;
+                break;
+            }
+        }
+        self.throttle(&pairs);
+        Ok(pairs)
+    }
 }
 
 impl Storage {
@@ -513,11 +1085,171 @@ impl Storage {
             engine: engine,
             worker: worker,
             worker_scheduler: worker_scheduler,
+            read_flow_scheduler: None,
             gc_ratio_threshold: config.gc_ratio_threshold,
             max_key_size: config.max_key_size,
+            keyspace: if config.enable_keyspace {
+                Some(config.keyspace_id)
+            } else {
+                None
+            },
+            raw_ttl: config.enable_raw_ttl,
+            backup_limiter: if config.backup_rate_limit.0 > 0 {
+                Some(Arc::new(IOLimiter::new(config.backup_rate_limit.0)))
+            } else {
+                None
+            },
+            auto_rollback_expired_locks: config.auto_rollback_expired_locks,
+            access_checker: None,
+            region_validator: None,
+            read_ts_registry: Arc::new(Mutex::new(BTreeMap::new())),
         })
     }
 
+    /// Sets the scheduler used to report per region read flow statistics
+    /// to PD. Must be called before `start`.
+    pub fn set_read_flow_scheduler(&mut self, scheduler: FutureScheduler<PdTask>) {
+        self.read_flow_scheduler = Some(scheduler);
+    }
+
+    /// Installs the authorization hook consulted before every data
+    /// access. Must be called before requests are served.
+    pub fn set_access_checker(&mut self, checker: Arc<AccessChecker>) {
+        self.access_checker = Some(checker);
+    }
+
+    /// The installed authorization hook, if any, so other request paths
+    /// (e.g. the coprocessor endpoint) can consult the same hook.
+    pub fn access_checker(&self) -> Option<Arc<AccessChecker>> {
+        self.access_checker.clone()
+    }
+
+    fn check_access(
+        &self,
+        ctx: &Context,
+        start_key: &[u8],
+        end_key: &[u8],
+        write: bool,
+    ) -> Result<()> {
+        if let Some(ref checker) = self.access_checker {
+            if let Err(msg) = checker.check_access(ctx, start_key, end_key, write) {
+                return Err(Error::AccessDenied(msg));
+            }
+        }
+        Ok(())
+    }
+
+    /// Consults the access hook with the raw bounds of a set of keys.
+    /// The keys are only decoded when a checker is installed.
+    fn check_keys_access<'a, I>(&self, ctx: &Context, keys: I, write: bool) -> Result<()>
+    where
+        I: IntoIterator<Item = &'a Key>,
+    {
+        if self.access_checker.is_none() {
+            return Ok(());
+        }
+        let mut start = vec![];
+        let mut end = vec![];
+        for key in keys {
+            let raw = key.raw().map_err(mvcc::Error::from)?;
+            if start.is_empty() || raw < start {
+                start = raw.clone();
+            }
+            if raw > end {
+                end = raw;
+            }
+        }
+        self.check_access(ctx, &start, &end, write)
+    }
+
+    /// Consults the access hook with the raw bounds of a key range. An
+    /// absent bound stands for the end of the key space on that side.
+    fn check_range_access(
+        &self,
+        ctx: &Context,
+        start_key: Option<&Key>,
+        end_key: Option<&Key>,
+        write: bool,
+    ) -> Result<()> {
+        if self.access_checker.is_none() {
+            return Ok(());
+        }
+        let start = match start_key {
+            Some(k) => k.raw().map_err(mvcc::Error::from)?,
+            None => vec![],
+        };
+        let end = match end_key {
+            Some(k) => k.raw().map_err(mvcc::Error::from)?,
+            None => vec![],
+        };
+        self.check_access(ctx, &start, &end, write)
+    }
+
+    /// Installs the region routing check applied before commands are
+    /// scheduled. Must be called before requests are served.
+    pub fn set_region_validator(&mut self, validator: Arc<RegionValidator>) {
+        self.region_validator = Some(validator);
+    }
+
+    fn check_in_region(&self, ctx: &Context, start_key: &[u8], end_key: &[u8]) -> Result<()> {
+        if let Some(ref validator) = self.region_validator {
+            if let Err(e) = validator.validate(ctx, start_key, end_key) {
+                return Err(Error::Engine(EngineError::Request(e)));
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that a set of keys lies in the claimed region. Region
+    /// boundaries live in the encoded key space, so the keys need not
+    /// be decoded.
+    fn check_keys_in_region<'a, I>(&self, ctx: &Context, keys: I) -> Result<()>
+    where
+        I: IntoIterator<Item = &'a Key>,
+    {
+        if self.region_validator.is_none() {
+            return Ok(());
+        }
+        let mut start: &[u8] = b"";
+        let mut end: &[u8] = b"";
+        for key in keys {
+            let k = key.encoded().as_slice();
+            if start.is_empty() || k < start {
+                start = k;
+            }
+            if k > end {
+                end = k;
+            }
+        }
+        if start.is_empty() && end.is_empty() {
+            // No keys, nothing to judge.
+            return Ok(());
+        }
+        // The validator takes an exclusive end bound, the successor of
+        // the largest key is the tightest one.
+        let mut end = end.to_vec();
+        end.push(0);
+        self.check_in_region(ctx, start, &end)
+    }
+
+    /// Checks that a key range lies in the claimed region. The end key
+    /// is exclusive, one equal to the region boundary is valid; an
+    /// absent end key leaves the upper bound unchecked, e.g. for scans
+    /// that stop at the region boundary by themselves.
+    fn check_range_in_region(
+        &self,
+        ctx: &Context,
+        start_key: Option<&Key>,
+        end_key: Option<&Key>,
+    ) -> Result<()> {
+        if self.region_validator.is_none() {
+            return Ok(());
+        }
+        let start = start_key.map_or(&[][..], |k| k.encoded().as_slice());
+        let end = end_key.map_or(&[][..], |k| k.encoded().as_slice());
+        self.check_in_region(ctx, start, end)
+    }
+
     pub fn new(config: &Config) -> Result<Storage> {
         let engine = engine::new_local_engine(&config.data_dir, ALL_CFS)?;
         Storage::from_engine(engine, config)
@@ -527,6 +1259,7 @@ impl Storage {
         let sched_concurrency = config.scheduler_concurrency;
         let sched_worker_pool_size = config.scheduler_worker_pool_size;
         let sched_pending_write_threshold = config.scheduler_pending_write_threshold.0 as usize;
+        mvcc::verify_invariants(config.verify_mvcc_invariants);
         let mut worker = self.worker.lock().unwrap();
         let scheduler = Scheduler::new(
             self.engine.clone(),
@@ -534,8 +1267,18 @@ impl Storage {
             sched_concurrency,
             sched_worker_pool_size,
             sched_pending_write_threshold,
+            config.resolve_lock_batch_size,
+            self.read_flow_scheduler.clone(),
+            config.enable_perf_context,
+            config.wait_for_lock_timeout,
         );
-        worker.start(scheduler)?;
+        // The tick sweeps parked commands for expired lock waits; with
+        // parking off the timer stays empty and never fires.
+        let mut timer = Timer::new(1);
+        if config.wait_for_lock_timeout > 0 {
+            timer.add_task(Duration::from_millis(LOCK_WAIT_CHECK_INTERVAL_MS), ());
+        }
+        worker.start_with_timer(scheduler, timer)?;
         Ok(())
     }
 
@@ -574,6 +1317,23 @@ impl Storage {
         key: Key,
         start_ts: u64,
         callback: Callback<Option<Value>>,
+    ) -> Result<()> {
+        self.check_keys_access(&ctx, Some(&key), false)?;
+        self.check_keys_in_region(&ctx, Some(&key))?;
+        let callback = if self.auto_rollback_expired_locks {
+            self.auto_rollback_cb(ctx.clone(), key.clone(), start_ts, callback)
+        } else {
+            callback
+        };
+        self.sched_get(ctx, key, start_ts, callback)
+    }
+
+    fn sched_get(
+        &self,
+        ctx: Context,
+        key: Key,
+        start_ts: u64,
+        callback: Callback<Option<Value>>,
     ) -> Result<()> {
         let cmd = Command::Get {
             ctx: ctx,
@@ -586,6 +1346,53 @@ impl Storage {
         Ok(())
     }
 
+    /// Wraps a get callback so an expired lock is rolled back through its
+    /// primary and the read retried once, instead of handing the lock
+    /// back to the client for resolution. See
+    /// `Config::auto_rollback_expired_locks`.
+    fn auto_rollback_cb(
+        &self,
+        ctx: Context,
+        key: Key,
+        start_ts: u64,
+        callback: Callback<Option<Value>>,
+    ) -> Callback<Option<Value>> {
+        let storage = self.clone();
+        Callback::Boxed(Box::new(move |res: Result<Option<Value>>| {
+            let (primary, lock_ts) = match res {
+                Err(Error::Txn(txn::Error::Mvcc(mvcc::Error::KeyIsLocked {
+                    ref primary,
+                    ts,
+                    ttl,
+                    ..
+                }))) if is_lock_expired(ts, ttl) =>
+                {
+                    (primary.clone(), ts)
+                }
+                other => return callback.call(other),
+            };
+            // Roll the expired lock back through its primary, then retry
+            // the read once. A failed rollback is fine: the transaction
+            // may just have committed, in which case the retried read
+            // sees the commit; if the lock is still there the retry
+            // reports it to the client as before.
+            let cleanup = Command::Cleanup {
+                ctx: ctx.clone(),
+                key: Key::from_raw(&primary),
+                start_ts: lock_ts,
+            };
+            let sched = storage.clone();
+            let retry = Callback::Boxed(Box::new(move |_: Result<()>| {
+                if let Err(e) = storage.sched_get(ctx, key, start_ts, callback) {
+                    error!("retry get after expired lock rollback: {:?}", e);
+                }
+            }));
+            if let Err(e) = sched.schedule(cleanup, StorageCb::Boolean(retry)) {
+                error!("schedule rollback of expired lock: {:?}", e);
+            }
+        }))
+    }
+
     pub fn async_batch_get(
         &self,
         ctx: Context,
@@ -593,6 +1400,8 @@ impl Storage {
         start_ts: u64,
         callback: Callback<Vec<Result<KvPair>>>,
     ) -> Result<()> {
+        self.check_keys_access(&ctx, &keys, false)?;
+        self.check_keys_in_region(&ctx, &keys)?;
         let cmd = Command::BatchGet {
             ctx: ctx,
             keys: keys,
@@ -608,14 +1417,25 @@ impl Storage {
         &self,
         ctx: Context,
         start_key: Key,
+        end_key: Option<Key>,
         limit: usize,
         start_ts: u64,
         options: Options,
         callback: Callback<Vec<Result<KvPair>>>,
     ) -> Result<()> {
+        // A reverse scan walks toward smaller keys, its range lies on
+        // the other side of `start_key`.
+        if options.reverse {
+            self.check_range_access(&ctx, end_key.as_ref(), Some(&start_key), false)?;
+            self.check_range_in_region(&ctx, end_key.as_ref(), Some(&start_key))?;
+        } else {
+            self.check_range_access(&ctx, Some(&start_key), end_key.as_ref(), false)?;
+            self.check_range_in_region(&ctx, Some(&start_key), end_key.as_ref())?;
+        }
         let cmd = Command::Scan {
             ctx: ctx,
             start_key: start_key,
+            end_key: end_key,
             limit: limit,
             start_ts: start_ts,
             options: options,
@@ -626,6 +1446,71 @@ impl Storage {
         Ok(())
     }
 
+    /// Creates a pull based stream over the committed key value pairs in
+    /// `[start_key, end_key)` at `ts`, for the backup subsystem and
+    /// data-export tools.
+    ///
+    /// Every pull reads one batch under a snapshot taken for the current
+    /// context, and taking the snapshot validates the region epoch, so a
+    /// stale context surfaces as an error instead of leaking keys the
+    /// region no longer owns. The caller walks the stream across the
+    /// regions of the local store with `SnapshotScanStream::set_context`.
+    /// Consistency across pulls comes from reading at the fixed `ts`: GC
+    /// is held back to it for as long as the stream is alive.
+    pub fn snapshot_scan_stream(
+        &self,
+        ctx: Context,
+        start_key: Key,
+        end_key: Option<Key>,
+        ts: u64,
+        batch_size: usize,
+        speed_limit: u64,
+    ) -> SnapshotScanStream {
+        let guard = self.register_read_ts(ts);
+        // A job may carry its own speed limit, otherwise the scan shares
+        // the `backup-rate-limit` budget of the store.
+        let limiter = if speed_limit > 0 {
+            Some(Arc::new(IOLimiter::new(speed_limit)))
+        } else {
+            self.backup_limiter.clone()
+        };
+        SnapshotScanStream {
+            engine: self.engine.clone(),
+            ctx: ctx,
+            store: None,
+            reader: None,
+            ts: ts,
+            start_ts: 0,
+            cursor: start_key,
+            end_key: end_key,
+            batch_size: batch_size,
+            finished: false,
+            limiter: limiter,
+            _read_ts_guard: guard,
+        }
+    }
+
+    /// Like `snapshot_scan_stream`, but only yields the pairs whose commit
+    /// ts falls in `(start_ts, end_ts]`. Chained onto a full backup taken
+    /// at `start_ts`, the result is an incremental backup at `end_ts`.
+    /// Deletions are not represented: a key deleted within the range is
+    /// simply absent from the output.
+    pub fn incremental_scan_stream(
+        &self,
+        ctx: Context,
+        start_key: Key,
+        end_key: Option<Key>,
+        start_ts: u64,
+        end_ts: u64,
+        batch_size: usize,
+        speed_limit: u64,
+    ) -> SnapshotScanStream {
+        let mut stream =
+            self.snapshot_scan_stream(ctx, start_key, end_key, end_ts, batch_size, speed_limit);
+        stream.start_ts = start_ts;
+        stream
+    }
+
     pub fn async_pause(&self, ctx: Context, duration: u64, callback: Callback<()>) -> Result<()> {
         let cmd = Command::Pause {
             ctx: ctx,
@@ -644,10 +1529,12 @@ impl Storage {
         options: Options,
         callback: Callback<Vec<Result<()>>>,
     ) -> Result<()> {
+        self.check_keys_access(&ctx, mutations.iter().map(|m| m.key()), true)?;
+        self.check_keys_in_region(&ctx, mutations.iter().map(|m| m.key()))?;
         for m in &mutations {
             let size = m.key().encoded().len();
             if size > self.max_key_size {
-                callback(Err(Error::KeyTooLarge(size, self.max_key_size)));
+                callback.call(Err(Error::KeyTooLarge(size, self.max_key_size)));
                 return Ok(());
             }
         }
@@ -664,6 +1551,44 @@ impl Storage {
         Ok(())
     }
 
+    /// Locks `keys` for a pessimistic transaction ahead of its
+    /// prewrite, failing per key with `KeyIsLocked` on contention and
+    /// as a whole with `WriteConflict` on a commit newer than
+    /// `for_update_ts`. The kvproto API carries no pessimistic lock
+    /// requests yet, only embedding callers can reach this.
+    pub fn async_acquire_pessimistic_lock(
+        &self,
+        ctx: Context,
+        keys: Vec<Key>,
+        primary: Vec<u8>,
+        start_ts: u64,
+        for_update_ts: u64,
+        options: Options,
+        callback: Callback<Vec<Result<()>>>,
+    ) -> Result<()> {
+        self.check_keys_access(&ctx, &keys, true)?;
+        self.check_keys_in_region(&ctx, &keys)?;
+        for key in &keys {
+            let size = key.encoded().len();
+            if size > self.max_key_size {
+                callback.call(Err(Error::KeyTooLarge(size, self.max_key_size)));
+                return Ok(());
+            }
+        }
+        let cmd = Command::AcquirePessimisticLock {
+            ctx: ctx,
+            keys: keys,
+            primary: primary,
+            start_ts: start_ts,
+            for_update_ts: for_update_ts,
+            options: options,
+        };
+        let tag = cmd.tag();
+        self.schedule(cmd, StorageCb::Booleans(callback))?;
+        KV_COMMAND_COUNTER_VEC.with_label_values(&[tag]).inc();
+        Ok(())
+    }
+
     pub fn async_commit(
         &self,
         ctx: Context,
@@ -672,6 +1597,8 @@ impl Storage {
         commit_ts: u64,
         callback: Callback<()>,
     ) -> Result<()> {
+        self.check_keys_access(&ctx, &keys, true)?;
+        self.check_keys_in_region(&ctx, &keys)?;
         let cmd = Command::Commit {
             ctx: ctx,
             keys: keys,
@@ -691,6 +1618,8 @@ impl Storage {
         end_key: Key,
         callback: Callback<()>,
     ) -> Result<()> {
+        self.check_range_access(&ctx, Some(&start_key), Some(&end_key), true)?;
+        self.check_range_in_region(&ctx, Some(&start_key), Some(&end_key))?;
         let mut modifies = Vec::with_capacity(DATA_CFS.len());
         for cf in DATA_CFS {
             // We enable memtable prefix bloom for CF_WRITE column family, for delete_range
@@ -708,7 +1637,7 @@ impl Storage {
 
         self.engine
             .async_write(&ctx, modifies, box |(_, res): (_, engine::Result<_>)| {
-                callback(res.map_err(Error::from))
+                callback.call(res.map_err(Error::from))
             })?;
         KV_COMMAND_COUNTER_VEC
             .with_label_values(&["delete_range"])
@@ -723,6 +1652,8 @@ impl Storage {
         start_ts: u64,
         callback: Callback<()>,
     ) -> Result<()> {
+        self.check_keys_access(&ctx, Some(&key), true)?;
+        self.check_keys_in_region(&ctx, Some(&key))?;
         let cmd = Command::Cleanup {
             ctx: ctx,
             key: key,
@@ -741,6 +1672,8 @@ impl Storage {
         start_ts: u64,
         callback: Callback<()>,
     ) -> Result<()> {
+        self.check_keys_access(&ctx, &keys, true)?;
+        self.check_keys_in_region(&ctx, &keys)?;
         let cmd = Command::Rollback {
             ctx: ctx,
             keys: keys,
@@ -752,6 +1685,31 @@ impl Storage {
         Ok(())
     }
 
+    /// Releases the pessimistic locks a transaction holds on `keys`
+    /// without leaving rollback records, locks of a newer locking round
+    /// than `for_update_ts` are kept.
+    pub fn async_pessimistic_rollback(
+        &self,
+        ctx: Context,
+        keys: Vec<Key>,
+        start_ts: u64,
+        for_update_ts: u64,
+        callback: Callback<()>,
+    ) -> Result<()> {
+        self.check_keys_access(&ctx, &keys, true)?;
+        self.check_keys_in_region(&ctx, &keys)?;
+        let cmd = Command::PessimisticRollback {
+            ctx: ctx,
+            keys: keys,
+            start_ts: start_ts,
+            for_update_ts: for_update_ts,
+        };
+        let tag = cmd.tag();
+        self.schedule(cmd, StorageCb::Boolean(callback))?;
+        KV_COMMAND_COUNTER_VEC.with_label_values(&[tag]).inc();
+        Ok(())
+    }
+
     pub fn async_scan_lock(
         &self,
         ctx: Context,
@@ -782,6 +1740,9 @@ impl Storage {
         txn_status: HashMap<u64, u64>,
         callback: Callback<()>,
     ) -> Result<()> {
+        // Lock resolution rewrites whatever the transaction touched, so
+        // it is checked against the whole key space.
+        self.check_access(&ctx, b"", b"", true)?;
         let cmd = Command::ResolveLock {
             ctx: ctx,
             txn_status: txn_status,
@@ -794,10 +1755,42 @@ impl Storage {
         Ok(())
     }
 
+    /// Registers an active read at `start_ts` and holds back GC to it for
+    /// as long as the returned guard is alive.
+    ///
+    /// Long running historical reads (e.g. analytical queries at an old
+    /// timestamp) use this to keep the versions they read from being
+    /// collected when the GC safe point advances past their timestamp.
+    pub fn register_read_ts(&self, start_ts: u64) -> ReadTsGuard {
+        let mut registry = self.read_ts_registry.lock().unwrap();
+        *registry.entry(start_ts).or_insert(0) += 1;
+        ReadTsGuard {
+            ts: start_ts,
+            registry: Arc::clone(&self.read_ts_registry),
+        }
+    }
+
+    /// Returns `safe_point` clamped to the oldest registered read timestamp.
+    fn clamp_safe_point(&self, safe_point: u64) -> u64 {
+        let registry = self.read_ts_registry.lock().unwrap();
+        match registry.keys().next() {
+            Some(&ts) if ts < safe_point => {
+                info!(
+                    "gc safe point {} held back to {} by an active read",
+                    safe_point,
+                    ts
+                );
+                ts
+            }
+            _ => safe_point,
+        }
+    }
+
     pub fn async_gc(&self, ctx: Context, safe_point: u64, callback: Callback<()>) -> Result<()> {
+        self.check_access(&ctx, b"", b"", true)?;
         let cmd = Command::Gc {
             ctx: ctx,
-            safe_point: safe_point,
+            safe_point: self.clamp_safe_point(safe_point),
             ratio_threshold: self.gc_ratio_threshold,
             scan_key: None,
             keys: vec![],
@@ -808,55 +1801,206 @@ impl Storage {
         Ok(())
     }
 
+    /// Prepends the configured keyspace prefix, if any, to a raw key.
+    fn rawkv_key(&self, key: Vec<u8>) -> Key {
+        match self.keyspace {
+            Some(keyspace_id) => {
+                let mut prefixed = keyspace_prefix(keyspace_id);
+                prefixed.extend_from_slice(&key);
+                Key::from_encoded(prefixed)
+            }
+            None => Key::from_encoded(key),
+        }
+    }
+
+    /// Maps the cf name a client sent to one of `DATA_CFS`. Raw data
+    /// used to live in `CF_DEFAULT` only, an empty name keeps meaning
+    /// that. The kvproto raw requests carry no cf field yet, so over
+    /// gRPC everything still goes to `CF_DEFAULT`.
+    fn rawkv_cf(cf: &str) -> Result<CfName> {
+        if cf.is_empty() {
+            return Ok(CF_DEFAULT);
+        }
+        for c in DATA_CFS {
+            if cf == *c {
+                return Ok(c);
+            }
+        }
+        Err(Error::InvalidCf(cf.to_owned()))
+    }
+
     pub fn async_raw_get(
         &self,
         ctx: Context,
+        cf: String,
         key: Vec<u8>,
         callback: Callback<Option<Vec<u8>>>,
     ) -> Result<()> {
+        self.check_access(&ctx, &key, &key, false)?;
+        let key = self.rawkv_key(key);
+        self.check_in_region(&ctx, &key, &key)?;
         let cmd = Command::RawGet {
             ctx: ctx,
-            key: Key::from_encoded(key),
+            cf: Self::rawkv_cf(&cf)?,
+            key: key,
+        };
+        let callback = if self.raw_ttl {
+            Callback::Boxed(box move |res: Result<Option<Value>>| {
+                callback.call(res.map(|v| v.and_then(raw_ttl::strip_expire_ts)))
+            })
+        } else {
+            callback
         };
         self.schedule(cmd, StorageCb::SingleValue(callback))?;
         RAWKV_COMMAND_COUNTER_VEC.with_label_values(&["get"]).inc();
         Ok(())
     }
 
+    /// Looks up all raw `keys` under one snapshot, missing keys are
+    /// left out of the result. kvproto carries no raw batch RPCs yet,
+    /// only embedding callers can issue them.
+    pub fn async_raw_batch_get(
+        &self,
+        ctx: Context,
+        cf: String,
+        keys: Vec<Vec<u8>>,
+        callback: Callback<Vec<Result<KvPair>>>,
+    ) -> Result<()> {
+        for key in &keys {
+            self.check_access(&ctx, key, key, false)?;
+        }
+        let mut raw_keys = Vec::with_capacity(keys.len());
+        for key in keys {
+            let key = self.rawkv_key(key);
+            self.check_in_region(&ctx, &key, &key)?;
+            raw_keys.push(key);
+        }
+        let cmd = Command::RawBatchGet {
+            ctx: ctx,
+            cf: Self::rawkv_cf(&cf)?,
+            keys: raw_keys,
+        };
+        let callback = if self.raw_ttl {
+            Callback::Boxed(box move |res: Result<Vec<Result<KvPair>>>| {
+                callback.call(res.map(strip_ttl_pairs))
+            })
+        } else {
+            callback
+        };
+        let callback = match self.keyspace {
+            Some(keyspace_id) => Callback::Boxed(box move |res: Result<Vec<Result<KvPair>>>| {
+                callback.call(res.map(|pairs| strip_keyspace_pairs(keyspace_id, pairs)))
+            }),
+            None => callback,
+        };
+        self.schedule(cmd, StorageCb::KvPairs(callback))?;
+        RAWKV_COMMAND_COUNTER_VEC
+            .with_label_values(&["batch_get"])
+            .inc();
+        Ok(())
+    }
+
+    /// Writes one raw key. With `storage.enable-raw-ttl` set a non-zero
+    /// `ttl` makes the value expire that many seconds from now; without
+    /// it a non-zero `ttl` is rejected. The kvproto raw requests carry
+    /// no ttl field yet, over gRPC everything is written without one.
     pub fn async_raw_put(
         &self,
         ctx: Context,
+        cf: String,
         key: Vec<u8>,
         value: Vec<u8>,
+        ttl: u64,
         callback: Callback<()>,
     ) -> Result<()> {
+        let cf = Self::rawkv_cf(&cf)?;
+        if ttl > 0 && !self.raw_ttl {
+            return Err(box_err!("raw ttl is not enabled"));
+        }
+        self.check_access(&ctx, &key, &key, true)?;
         if key.len() > self.max_key_size {
-            callback(Err(Error::KeyTooLarge(key.len(), self.max_key_size)));
+            callback.call(Err(Error::KeyTooLarge(key.len(), self.max_key_size)));
             return Ok(());
         }
+        let key = self.rawkv_key(key);
+        self.check_in_region(&ctx, &key, &key)?;
+        let value = if self.raw_ttl {
+            let expire_ts = if ttl == 0 { 0 } else { raw_ttl::now_ts() + ttl };
+            raw_ttl::encode_expire_ts(value, expire_ts)
+        } else {
+            value
+        };
         self.engine.async_write(
             &ctx,
-            vec![Modify::Put(CF_DEFAULT, Key::from_encoded(key), value)],
-            box |(_, res): (_, engine::Result<_>)| callback(res.map_err(Error::from)),
+            vec![Modify::Put(cf, key, value)],
+            box |(_, res): (_, engine::Result<_>)| callback.call(res.map_err(Error::from)),
         )?;
         RAWKV_COMMAND_COUNTER_VEC.with_label_values(&["put"]).inc();
         Ok(())
     }
 
+    /// Writes all raw `pairs` through one engine write, so a bulk load
+    /// pays a single raft proposal instead of one per key. kvproto
+    /// carries no raw batch RPCs yet, only embedding callers can issue
+    /// them.
+    pub fn async_raw_batch_put(
+        &self,
+        ctx: Context,
+        cf: String,
+        pairs: Vec<KvPair>,
+        callback: Callback<()>,
+    ) -> Result<()> {
+        let cf = Self::rawkv_cf(&cf)?;
+        for &(ref key, _) in &pairs {
+            self.check_access(&ctx, key, key, true)?;
+            if key.len() > self.max_key_size {
+                callback.call(Err(Error::KeyTooLarge(key.len(), self.max_key_size)));
+                return Ok(());
+            }
+        }
+        let mut modifies = Vec::with_capacity(pairs.len());
+        for (key, value) in pairs {
+            let key = self.rawkv_key(key);
+            self.check_in_region(&ctx, &key, &key)?;
+            // Batch puts take no ttl, in ttl mode their values still need
+            // the suffix marking them permanent.
+            let value = if self.raw_ttl {
+                raw_ttl::encode_expire_ts(value, 0)
+            } else {
+                value
+            };
+            modifies.push(Modify::Put(cf, key, value));
+        }
+        self.engine.async_write(
+            &ctx,
+            modifies,
+            box |(_, res): (_, engine::Result<_>)| callback.call(res.map_err(Error::from)),
+        )?;
+        RAWKV_COMMAND_COUNTER_VEC
+            .with_label_values(&["batch_put"])
+            .inc();
+        Ok(())
+    }
+
     pub fn async_raw_delete(
         &self,
         ctx: Context,
+        cf: String,
         key: Vec<u8>,
         callback: Callback<()>,
     ) -> Result<()> {
+        let cf = Self::rawkv_cf(&cf)?;
+        self.check_access(&ctx, &key, &key, true)?;
         if key.len() > self.max_key_size {
-            callback(Err(Error::KeyTooLarge(key.len(), self.max_key_size)));
+            callback.call(Err(Error::KeyTooLarge(key.len(), self.max_key_size)));
             return Ok(());
         }
+        let key = self.rawkv_key(key);
+        self.check_in_region(&ctx, &key, &key)?;
         self.engine.async_write(
             &ctx,
-            vec![Modify::Delete(CF_DEFAULT, Key::from_encoded(key))],
-            box |(_, res): (_, engine::Result<_>)| callback(res.map_err(Error::from)),
+            vec![Modify::Delete(cf, key)],
+            box |(_, res): (_, engine::Result<_>)| callback.call(res.map_err(Error::from)),
         )?;
         RAWKV_COMMAND_COUNTER_VEC
             .with_label_values(&["delete"])
@@ -864,17 +2008,183 @@ impl Storage {
         Ok(())
     }
 
+    /// Deletes all raw `keys` through one engine write. kvproto carries
+    /// no raw batch RPCs yet, only embedding callers can issue them.
+    pub fn async_raw_batch_delete(
+        &self,
+        ctx: Context,
+        cf: String,
+        keys: Vec<Vec<u8>>,
+        callback: Callback<()>,
+    ) -> Result<()> {
+        let cf = Self::rawkv_cf(&cf)?;
+        for key in &keys {
+            self.check_access(&ctx, key, key, true)?;
+            if key.len() > self.max_key_size {
+                callback.call(Err(Error::KeyTooLarge(key.len(), self.max_key_size)));
+                return Ok(());
+            }
+        }
+        let mut modifies = Vec::with_capacity(keys.len());
+        for key in keys {
+            let key = self.rawkv_key(key);
+            self.check_in_region(&ctx, &key, &key)?;
+            modifies.push(Modify::Delete(cf, key));
+        }
+        self.engine.async_write(
+            &ctx,
+            modifies,
+            box |(_, res): (_, engine::Result<_>)| callback.call(res.map_err(Error::from)),
+        )?;
+        RAWKV_COMMAND_COUNTER_VEC
+            .with_label_values(&["batch_delete"])
+            .inc();
+        Ok(())
+    }
+
+    /// Deletes all raw keys in `[start_key, end_key)` through a single
+    /// `DeleteRange` on the selected column family. Raw data never
+    /// touches the MVCC CFs, so unlike `async_delete_range` the other
+    /// CFs are left alone. kvproto carries no RPC for this yet, only
+    /// embedding callers can issue it.
+    pub fn async_raw_delete_range(
+        &self,
+        ctx: Context,
+        cf: String,
+        start_key: Vec<u8>,
+        end_key: Vec<u8>,
+        callback: Callback<()>,
+    ) -> Result<()> {
+        let cf = Self::rawkv_cf(&cf)?;
+        self.check_access(&ctx, &start_key, &end_key, true)?;
+        let start_key = self.rawkv_key(start_key);
+        let end_key = self.rawkv_key(end_key);
+        self.check_in_region(&ctx, &start_key, &end_key)?;
+        self.engine.async_write(
+            &ctx,
+            vec![Modify::DeleteRange(cf, start_key, end_key)],
+            box |(_, res): (_, engine::Result<_>)| callback.call(res.map_err(Error::from)),
+        )?;
+        RAWKV_COMMAND_COUNTER_VEC
+            .with_label_values(&["delete_range"])
+            .inc();
+        Ok(())
+    }
+
+    /// Atomically adds `delta` to the raw value of `key`, interpreting
+    /// the value as a little endian i64 and a missing key as zero. The
+    /// new value is returned through the callback. The scheduler latch
+    /// on the key serializes concurrent increments, so counters need no
+    /// CAS loop. kvproto carries no RPC for this yet, only embedding
+    /// callers can issue it.
+    pub fn async_raw_incr(
+        &self,
+        ctx: Context,
+        key: Vec<u8>,
+        delta: i64,
+        callback: Callback<Option<Value>>,
+    ) -> Result<()> {
+        if self.raw_ttl {
+            // The counter encoding and the expiry suffix would trample
+            // each other.
+            return Err(box_err!("raw incr does not support ttl values"));
+        }
+        self.check_access(&ctx, &key, &key, true)?;
+        if key.len() > self.max_key_size {
+            callback.call(Err(Error::KeyTooLarge(key.len(), self.max_key_size)));
+            return Ok(());
+        }
+        let key = self.rawkv_key(key);
+        self.check_in_region(&ctx, &key, &key)?;
+        let cmd = Command::RawIncr {
+            ctx: ctx,
+            key: key,
+            delta: delta,
+        };
+        self.schedule(cmd, StorageCb::SingleValue(callback))?;
+        RAWKV_COMMAND_COUNTER_VEC
+            .with_label_values(&["incr"])
+            .inc();
+        Ok(())
+    }
+
+    /// Atomically writes `new_value` if the current value of `key`
+    /// equals `expected`, `None` expecting the key to be absent. The
+    /// previous value is returned either way, the swap happened iff it
+    /// equals `expected`. The check and the write run under the
+    /// scheduler latch for the key, so concurrent raw writes cannot
+    /// slip in between them.
+    pub fn async_raw_compare_and_swap(
+        &self,
+        ctx: Context,
+        key: Vec<u8>,
+        expected: Option<Value>,
+        new_value: Vec<u8>,
+        callback: Callback<Option<Value>>,
+    ) -> Result<()> {
+        if self.raw_ttl {
+            // Like raw incr: the comparison would see expiry suffixes.
+            return Err(box_err!("raw compare-and-swap does not support ttl values"));
+        }
+        self.check_access(&ctx, &key, &key, true)?;
+        if key.len() > self.max_key_size {
+            callback.call(Err(Error::KeyTooLarge(key.len(), self.max_key_size)));
+            return Ok(());
+        }
+        let key = self.rawkv_key(key);
+        self.check_in_region(&ctx, &key, &key)?;
+        let cmd = Command::RawCompareAndSwap {
+            ctx: ctx,
+            key: key,
+            expected: expected,
+            new_value: new_value,
+        };
+        self.schedule(cmd, StorageCb::SingleValue(callback))?;
+        RAWKV_COMMAND_COUNTER_VEC.with_label_values(&["cas"]).inc();
+        Ok(())
+    }
+
     pub fn async_raw_scan(
         &self,
         ctx: Context,
+        cf: String,
         key: Vec<u8>,
         limit: usize,
+        options: Options,
         callback: Callback<Vec<Result<KvPair>>>,
     ) -> Result<()> {
+        // A reverse scan walks toward smaller keys, its range lies on
+        // the other side of `key`.
+        if options.reverse {
+            self.check_access(&ctx, b"", &key, false)?;
+        } else {
+            self.check_access(&ctx, &key, b"", false)?;
+        }
+        let key = self.rawkv_key(key);
+        if options.reverse {
+            self.check_in_region(&ctx, b"", &key)?;
+        } else {
+            self.check_in_region(&ctx, &key, b"")?;
+        }
         let cmd = Command::RawScan {
             ctx: ctx,
-            start_key: Key::from_encoded(key),
+            cf: Self::rawkv_cf(&cf)?,
+            start_key: key,
             limit: limit,
+            options: options,
+        };
+        let callback = if self.raw_ttl {
+            Callback::Boxed(box move |res: Result<Vec<Result<KvPair>>>| {
+                callback.call(res.map(strip_ttl_pairs))
+            })
+        } else {
+            callback
+        };
+        let callback = match self.keyspace {
+            Some(keyspace_id) => Callback::Boxed(box move |res: Result<Vec<Result<KvPair>>>| {
+                callback.call(res.map(|pairs| strip_keyspace_pairs(keyspace_id, pairs)))
+            }),
+            None => callback,
         };
         self.schedule(cmd, StorageCb::KvPairs(callback))?;
         RAWKV_COMMAND_COUNTER_VEC.with_label_values(&["scan"]).inc();
@@ -909,6 +2219,109 @@ impl Storage {
         KV_COMMAND_COUNTER_VEC.with_label_values(&[tag]).inc();
         Ok(())
     }
+
+    // The `future_*` methods below mirror their `async_*` counterparts but
+    // resolve a future instead of invoking a callback, so callers can compose
+    // operations without nesting closures. A dropped callback resolves the
+    // future to `Error::Closed`.
+
+    pub fn future_get(
+        &self,
+        ctx: Context,
+        key: Key,
+        start_ts: u64,
+    ) -> StorageFuture<Option<Value>> {
+        let (cb, future) = paired_future_callback();
+        match self.async_get(ctx, key, start_ts, cb) {
+            Ok(()) => box future.then(|res| res.unwrap_or(Err(Error::Closed))),
+            Err(e) => box future::err(e),
+        }
+    }
+
+    pub fn future_batch_get(
+        &self,
+        ctx: Context,
+        keys: Vec<Key>,
+        start_ts: u64,
+    ) -> StorageFuture<Vec<Result<KvPair>>> {
+        let (cb, future) = paired_future_callback();
+        match self.async_batch_get(ctx, keys, start_ts, cb) {
+            Ok(()) => box future.then(|res| res.unwrap_or(Err(Error::Closed))),
+            Err(e) => box future::err(e),
+        }
+    }
+
+    pub fn future_scan(
+        &self,
+        ctx: Context,
+        start_key: Key,
+        end_key: Option<Key>,
+        limit: usize,
+        start_ts: u64,
+        options: Options,
+    ) -> StorageFuture<Vec<Result<KvPair>>> {
+        let (cb, future) = paired_future_callback();
+        match self.async_scan(ctx, start_key, end_key, limit, start_ts, options, cb) {
+            Ok(()) => box future.then(|res| res.unwrap_or(Err(Error::Closed))),
+            Err(e) => box future::err(e),
+        }
+    }
+
+    pub fn future_prewrite(
+        &self,
+        ctx: Context,
+        mutations: Vec<Mutation>,
+        primary: Vec<u8>,
+        start_ts: u64,
+        options: Options,
+    ) -> StorageFuture<Vec<Result<()>>> {
+        let (cb, future) = paired_future_callback();
+        match self.async_prewrite(ctx, mutations, primary, start_ts, options, cb) {
+            Ok(()) => box future.then(|res| res.unwrap_or(Err(Error::Closed))),
+            Err(e) => box future::err(e),
+        }
+    }
+
+    pub fn future_commit(
+        &self,
+        ctx: Context,
+        keys: Vec<Key>,
+        lock_ts: u64,
+        commit_ts: u64,
+    ) -> StorageFuture<()> {
+        let (cb, future) = paired_future_callback();
+        match self.async_commit(ctx, keys, lock_ts, commit_ts, cb) {
+            Ok(()) => box future.then(|res| res.unwrap_or(Err(Error::Closed))),
+            Err(e) => box future::err(e),
+        }
+    }
+
+    pub fn future_raw_get(
+        &self,
+        ctx: Context,
+        cf: String,
+        key: Vec<u8>,
+    ) -> StorageFuture<Option<Vec<u8>>> {
+        let (cb, future) = paired_future_callback();
+        match self.async_raw_get(ctx, cf, key, cb) {
+            Ok(()) => box future.then(|res| res.unwrap_or(Err(Error::Closed))),
+            Err(e) => box future::err(e),
+        }
+    }
+
+    pub fn future_raw_put(
+        &self,
+        ctx: Context,
+        cf: String,
+        key: Vec<u8>,
+        value: Vec<u8>,
+    ) -> StorageFuture<()> {
+        let (cb, future) = paired_future_callback();
+        match self.async_raw_put(ctx, cf, key, value, 0, cb) {
+            Ok(()) => box future.then(|res| res.unwrap_or(Err(Error::Closed))),
+            Err(e) => box future::err(e),
+        }
+    }
 }
 
 impl Clone for Storage {
@@ -917,8 +2330,16 @@ impl Clone for Storage {
             engine: self.engine.clone(),
             worker: Arc::clone(&self.worker),
             worker_scheduler: self.worker_scheduler.clone(),
+            read_flow_scheduler: self.read_flow_scheduler.clone(),
             gc_ratio_threshold: self.gc_ratio_threshold,
             max_key_size: self.max_key_size,
+            keyspace: self.keyspace,
+            raw_ttl: self.raw_ttl,
+            backup_limiter: self.backup_limiter.clone(),
+            auto_rollback_expired_locks: self.auto_rollback_expired_locks,
+            access_checker: self.access_checker.clone(),
+            region_validator: self.region_validator.clone(),
+            read_ts_registry: Arc::clone(&self.read_ts_registry),
         }
     }
 }
@@ -961,11 +2382,36 @@ quick_error! {
             description("max key size exceeded")
             display("max key size exceeded, size: {}, limit: {}", size, limit)
         }
+        AccessDenied(msg: String) {
+            description("access denied")
+            display("access denied: {}", msg)
+        }
+        InvalidCf(cf_name: String) {
+            description("invalid cf name")
+            display("invalid cf name: {}", cf_name)
+        }
     }
 }
 
 pub type Result<T> = ::std::result::Result<T, Error>;
 
+impl ErrorCodeExt for Error {
+    fn error_code(&self) -> ErrorCode {
+        match *self {
+            Error::Engine(_) => error_code::storage::ENGINE,
+            Error::Txn(_) => error_code::storage::TXN,
+            Error::Mvcc(_) => error_code::storage::MVCC,
+            Error::Closed => error_code::storage::CLOSED,
+            Error::Other(_) => error_code::storage::UNKNOWN,
+            Error::Io(_) => error_code::storage::IO,
+            Error::SchedTooBusy => error_code::storage::SCHED_TOO_BUSY,
+            Error::KeyTooLarge(..) => error_code::storage::KEY_TOO_LARGE,
+            Error::AccessDenied(_) => error_code::storage::ACCESS_DENIED,
+            Error::InvalidCf(_) => error_code::storage::INVALID_CF,
+        }
+    }
+}
+
 pub fn get_tag_from_header(header: &errorpb::Error) -> &'static str {
     if header.has_not_leader() {
         "not_leader"
@@ -991,47 +2437,50 @@ pub fn get_tag_from_header(header: &errorpb::Error) -> &'static str {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::sync::Arc;
     use std::sync::mpsc::{channel, Sender};
+    use std::thread;
+    use kvproto::errorpb;
     use kvproto::kvrpcpb::Context;
     use util::config::ReadableSize;
 
     fn expect_get_none(done: Sender<i32>, id: i32) -> Callback<Option<Value>> {
-        Box::new(move |x: Result<Option<Value>>| {
+        Callback::Boxed(Box::new(move |x: Result<Option<Value>>| {
             assert_eq!(x.unwrap(), None);
             done.send(id).unwrap();
-        })
+        }))
     }
 
     fn expect_get_val(done: Sender<i32>, v: Vec<u8>, id: i32) -> Callback<Option<Value>> {
-        Box::new(move |x: Result<Option<Value>>| {
+        Callback::Boxed(Box::new(move |x: Result<Option<Value>>| {
             assert_eq!(x.unwrap().unwrap(), v);
             done.send(id).unwrap();
-        })
+        }))
     }
 
     fn expect_ok<T>(done: Sender<i32>, id: i32) -> Callback<T> {
-        Box::new(move |x: Result<T>| {
+        Callback::Boxed(Box::new(move |x: Result<T>| {
             assert!(x.is_ok());
             done.send(id).unwrap();
-        })
+        }))
     }
 
     fn expect_fail<T>(done: Sender<i32>, id: i32) -> Callback<T> {
-        Box::new(move |x: Result<T>| {
+        Callback::Boxed(Box::new(move |x: Result<T>| {
             assert!(x.is_err());
             done.send(id).unwrap();
-        })
+        }))
     }
 
     fn expect_too_busy<T>(done: Sender<i32>, id: i32) -> Callback<T> {
-        Box::new(move |x: Result<T>| {
+        Callback::Boxed(Box::new(move |x: Result<T>| {
             assert!(x.is_err());
             match x {
                 Err(Error::SchedTooBusy) => {}
                 _ => panic!("expect too busy"),
             }
             done.send(id).unwrap();
-        })
+        }))
     }
 
     fn expect_scan(
@@ -1039,11 +2488,11 @@ mod tests {
         pairs: Vec<Option<KvPair>>,
         id: i32,
     ) -> Callback<Vec<Result<KvPair>>> {
-        Box::new(move |rlt: Result<Vec<Result<KvPair>>>| {
+        Callback::Boxed(Box::new(move |rlt: Result<Vec<Result<KvPair>>>| {
             let rlt: Vec<Option<KvPair>> = rlt.unwrap().into_iter().map(Result::ok).collect();
             assert_eq!(rlt, pairs);
             done.send(id).unwrap()
-        })
+        }))
     }
 
     fn expect_batch_get_vals(
@@ -1051,64 +2500,1112 @@ mod tests {
         pairs: Vec<Option<KvPair>>,
         id: i32,
     ) -> Callback<Vec<Result<KvPair>>> {
-        Box::new(move |rlt: Result<Vec<Result<KvPair>>>| {
+        Callback::Boxed(Box::new(move |rlt: Result<Vec<Result<KvPair>>>| {
             let rlt: Vec<Option<KvPair>> = rlt.unwrap().into_iter().map(Result::ok).collect();
             assert_eq!(rlt, pairs);
             done.send(id).unwrap()
-        })
+        }))
+    }
+
+    fn never_cb() -> Callback<()> {
+        Callback::Boxed(Box::new(|_: Result<()>| {
+            panic!("callback of a rejected request must not run");
+        }))
+    }
+
+    // Rejects writes whose keys fall into the `ro` prefix.
+    struct ReadOnlyRangeChecker;
+
+    impl AccessChecker for ReadOnlyRangeChecker {
+        fn check_access(
+            &self,
+            _: &Context,
+            start_key: &[u8],
+            _: &[u8],
+            write: bool,
+        ) -> ::std::result::Result<(), String> {
+            if write && start_key.starts_with(b"ro") {
+                return Err("range is read only".to_owned());
+            }
+            Ok(())
+        }
     }
 
     #[test]
-    fn test_get_put() {
+    fn test_access_checker() {
         let config = Config::default();
         let mut storage = Storage::new(&config).unwrap();
+        storage.set_access_checker(Arc::new(ReadOnlyRangeChecker));
         storage.start(&config).unwrap();
         let (tx, rx) = channel();
+
+        // denied requests are rejected before they are scheduled.
+        match storage.async_raw_put(
+            Context::new(),
+            String::new(),
+            b"ro_key".to_vec(),
+            b"v".to_vec(),
+            0,
+            never_cb(),
+        ) {
+            Err(Error::AccessDenied(_)) => {}
+            other => panic!("expect AccessDenied, got {:?}", other),
+        }
+        match storage.async_prewrite(
+            Context::new(),
+            vec![Mutation::Put((make_key(b"ro_key"), b"v".to_vec()))],
+            b"ro_key".to_vec(),
+            100,
+            Options::default(),
+            Callback::Boxed(Box::new(|_: Result<Vec<Result<()>>>| {
+                panic!("callback of a denied request must not run");
+            })),
+        ) {
+            Err(Error::AccessDenied(_)) => {}
+            other => panic!("expect AccessDenied, got {:?}", other),
+        }
+
+        // reads of the read only range and writes elsewhere still pass.
         storage
-            .async_get(
+            .async_raw_get(
                 Context::new(),
-                make_key(b"x"),
-                100,
+                String::new(),
+                b"ro_key".to_vec(),
                 expect_get_none(tx.clone(), 0),
             )
             .unwrap();
         rx.recv().unwrap();
         storage
-            .async_prewrite(
+            .async_raw_put(
                 Context::new(),
-                vec![Mutation::Put((make_key(b"x"), b"100".to_vec()))],
-                b"x".to_vec(),
-                100,
-                Options::default(),
+                String::new(),
+                b"rw_key".to_vec(),
+                b"v".to_vec(),
+                0,
                 expect_ok(tx.clone(), 1),
             )
             .unwrap();
         rx.recv().unwrap();
-        storage
-            .async_commit(
-                Context::new(),
-                vec![make_key(b"x")],
+        storage.stop().unwrap();
+    }
+
+    // Claims region 1 owning the encoded keys below `zz`, with epoch
+    // version 5.
+    struct OneRegionValidator;
+
+    impl RegionValidator for OneRegionValidator {
+        fn validate(
+            &self,
+            ctx: &Context,
+            start_key: &[u8],
+            end_key: &[u8],
+        ) -> ::std::result::Result<(), errorpb::Error> {
+            if ctx.get_region_id() != 1 || ctx.get_region_epoch().get_version() > 5 {
+                return Ok(());
+            }
+            let key_not_in_region = |key: &[u8]| {
+                let mut err = errorpb::Error::new();
+                err.mut_key_not_in_region().set_key(key.to_vec());
+                err.mut_key_not_in_region().set_region_id(1);
+                err
+            };
+            if !start_key.is_empty() && start_key >= b"zz".as_ref() {
+                return Err(key_not_in_region(start_key));
+            }
+            // The end bound is exclusive, equal to the region end is fine.
+            if !end_key.is_empty() && end_key > b"zz".as_ref() {
+                return Err(key_not_in_region(end_key));
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_region_validator() {
+        let config = Config::default();
+        let mut storage = Storage::new(&config).unwrap();
+        storage.set_region_validator(Arc::new(OneRegionValidator));
+        storage.start(&config).unwrap();
+        let (tx, rx) = channel();
+
+        let mut ctx = Context::new();
+        ctx.set_region_id(1);
+
+        // a key outside the claimed region is rejected before it is
+        // scheduled.
+        match storage.async_raw_put(
+            ctx.clone(),
+            String::new(),
+            b"zz_key".to_vec(),
+            b"v".to_vec(),
+            0,
+            never_cb(),
+        ) {
+            Err(Error::Engine(EngineError::Request(ref e))) => {
+                assert!(e.has_key_not_in_region())
+            }
+            other => panic!("expect KeyNotInRegion, got {:?}", other),
+        }
+
+        // keys inside pass, as do claims the validator can not judge.
+        storage
+            .async_get(ctx.clone(), make_key(b"a"), 100, expect_get_none(tx.clone(), 0))
+            .unwrap();
+        rx.recv().unwrap();
+        ctx.mut_region_epoch().set_version(6);
+        storage
+            .async_raw_put(
+                ctx,
+                String::new(),
+                b"zz_key".to_vec(),
+                b"v".to_vec(),
+                0,
+                expect_ok(tx.clone(), 1),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        storage.stop().unwrap();
+    }
+
+    #[test]
+    fn test_get_put() {
+        let config = Config::default();
+        let mut storage = Storage::new(&config).unwrap();
+        storage.start(&config).unwrap();
+        let (tx, rx) = channel();
+        storage
+            .async_get(
+                Context::new(),
+                make_key(b"x"),
+                100,
+                expect_get_none(tx.clone(), 0),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        storage
+            .async_prewrite(
+                Context::new(),
+                vec![Mutation::Put((make_key(b"x"), b"100".to_vec()))],
+                b"x".to_vec(),
+                100,
+                Options::default(),
+                expect_ok(tx.clone(), 1),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        storage
+            .async_commit(
+                Context::new(),
+                vec![make_key(b"x")],
+                100,
+                101,
+                expect_ok(tx.clone(), 2),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        storage
+            .async_get(
+                Context::new(),
+                make_key(b"x"),
+                100,
+                expect_get_none(tx.clone(), 3),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        storage
+            .async_get(
+                Context::new(),
+                make_key(b"x"),
+                101,
+                expect_get_val(tx.clone(), b"100".to_vec(), 4),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        storage.stop().unwrap();
+    }
+
+    #[test]
+    fn test_auto_rollback_expired_lock() {
+        let mut config = Config::default();
+        config.auto_rollback_expired_locks = true;
+        let mut storage = Storage::new(&config).unwrap();
+        storage.start(&config).unwrap();
+        let (tx, rx) = channel();
+        storage
+            .async_prewrite(
+                Context::new(),
+                vec![Mutation::Put((make_key(b"x"), b"100".to_vec()))],
+                b"x".to_vec(),
+                100,
+                Options::default(),
+                expect_ok(tx.clone(), 0),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        storage
+            .async_commit(
+                Context::new(),
+                vec![make_key(b"x")],
                 100,
                 101,
+                expect_ok(tx.clone(), 1),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        // Leave a lock behind whose TTL (zero) has long expired.
+        storage
+            .async_prewrite(
+                Context::new(),
+                vec![Mutation::Put((make_key(b"x"), b"101".to_vec()))],
+                b"x".to_vec(),
+                200,
+                Options::default(),
+                expect_ok(tx.clone(), 2),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        // The read rolls the lock back and retries, so it sees the
+        // committed value instead of reporting the lock.
+        storage
+            .async_get(
+                Context::new(),
+                make_key(b"x"),
+                300,
+                expect_get_val(tx.clone(), b"100".to_vec(), 3),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        // The rolled back transaction can not commit any more.
+        storage
+            .async_commit(
+                Context::new(),
+                vec![make_key(b"x")],
+                200,
+                201,
+                expect_fail(tx.clone(), 4),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        storage.stop().unwrap();
+    }
+
+    #[test]
+    fn test_future_get_put() {
+        let config = Config::default();
+        let mut storage = Storage::new(&config).unwrap();
+        storage.start(&config).unwrap();
+        assert_eq!(
+            storage
+                .future_get(Context::new(), make_key(b"x"), 100)
+                .wait()
+                .unwrap(),
+            None
+        );
+        for res in storage
+            .future_prewrite(
+                Context::new(),
+                vec![Mutation::Put((make_key(b"x"), b"100".to_vec()))],
+                b"x".to_vec(),
+                100,
+                Options::default(),
+            )
+            .wait()
+            .unwrap()
+        {
+            res.unwrap();
+        }
+        storage
+            .future_commit(Context::new(), vec![make_key(b"x")], 100, 101)
+            .wait()
+            .unwrap();
+        assert_eq!(
+            storage
+                .future_get(Context::new(), make_key(b"x"), 101)
+                .wait()
+                .unwrap()
+                .unwrap(),
+            b"100".to_vec()
+        );
+        storage.stop().unwrap();
+    }
+
+    #[test]
+    fn test_register_read_ts() {
+        let config = Config::default();
+        let mut storage = Storage::new(&config).unwrap();
+        storage.start(&config).unwrap();
+        // Two versions of `x`.
+        for &(value, start_ts, commit_ts) in &[(b"100", 100, 101), (b"110", 110, 111)] {
+            for res in storage
+                .future_prewrite(
+                    Context::new(),
+                    vec![Mutation::Put((make_key(b"x"), value.to_vec()))],
+                    b"x".to_vec(),
+                    start_ts,
+                    Options::default(),
+                )
+                .wait()
+                .unwrap()
+            {
+                res.unwrap();
+            }
+            storage
+                .future_commit(Context::new(), vec![make_key(b"x")], start_ts, commit_ts)
+                .wait()
+                .unwrap();
+        }
+        // An active read at 105 holds GC back, so the old version survives.
+        let (tx, rx) = channel();
+        let guard = storage.register_read_ts(105);
+        storage
+            .async_gc(Context::new(), 120, expect_ok(tx.clone(), 0))
+            .unwrap();
+        rx.recv().unwrap();
+        assert_eq!(
+            storage
+                .future_get(Context::new(), make_key(b"x"), 105)
+                .wait()
+                .unwrap(),
+            Some(b"100".to_vec())
+        );
+        // Once the read finishes, GC is free to collect it.
+        drop(guard);
+        storage
+            .async_gc(Context::new(), 120, expect_ok(tx.clone(), 1))
+            .unwrap();
+        rx.recv().unwrap();
+        assert_eq!(
+            storage
+                .future_get(Context::new(), make_key(b"x"), 105)
+                .wait()
+                .unwrap(),
+            None
+        );
+        storage.stop().unwrap();
+    }
+
+    #[test]
+    fn test_raw_kv_keyspace() {
+        let mut config = Config::default();
+        config.enable_keyspace = true;
+        config.keyspace_id = 1;
+        let mut storage = Storage::new(&config).unwrap();
+        storage.start(&config).unwrap();
+        storage
+            .future_raw_put(Context::new(), String::new(), b"k1".to_vec(), b"v1".to_vec())
+            .wait()
+            .unwrap();
+        assert_eq!(
+            storage
+                .future_raw_get(Context::new(), String::new(), b"k1".to_vec())
+                .wait()
+                .unwrap(),
+            Some(b"v1".to_vec())
+        );
+        // Scan results come back with the keyspace prefix stripped.
+        let (tx, rx) = channel();
+        storage
+            .async_raw_scan(
+                Context::new(),
+                String::new(),
+                b"".to_vec(),
+                10,
+                Options::default(),
+                expect_scan(tx.clone(), vec![Some((b"k1".to_vec(), b"v1".to_vec()))], 0),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        storage.stop().unwrap();
+    }
+
+    #[test]
+    fn test_raw_batch() {
+        let config = Config::default();
+        let mut storage = Storage::new(&config).unwrap();
+        storage.start(&config).unwrap();
+        let (tx, rx) = channel();
+        storage
+            .async_raw_batch_put(
+                Context::new(),
+                String::new(),
+                vec![
+                    (b"a".to_vec(), b"aa".to_vec()),
+                    (b"b".to_vec(), b"bb".to_vec()),
+                    (b"c".to_vec(), b"cc".to_vec()),
+                ],
+                expect_ok(tx.clone(), 0),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        // Missing keys are left out of the result.
+        storage
+            .async_raw_batch_get(
+                Context::new(),
+                String::new(),
+                vec![b"a".to_vec(), b"b".to_vec(), b"d".to_vec()],
+                expect_batch_get_vals(
+                    tx.clone(),
+                    vec![
+                        Some((b"a".to_vec(), b"aa".to_vec())),
+                        Some((b"b".to_vec(), b"bb".to_vec())),
+                    ],
+                    1,
+                ),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        storage
+            .async_raw_batch_delete(
+                Context::new(),
+                String::new(),
+                vec![b"a".to_vec(), b"c".to_vec()],
                 expect_ok(tx.clone(), 2),
             )
             .unwrap();
         rx.recv().unwrap();
         storage
-            .async_get(
+            .async_raw_batch_get(
+                Context::new(),
+                String::new(),
+                vec![b"a".to_vec(), b"b".to_vec(), b"c".to_vec()],
+                expect_batch_get_vals(
+                    tx.clone(),
+                    vec![Some((b"b".to_vec(), b"bb".to_vec()))],
+                    3,
+                ),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        storage.stop().unwrap();
+    }
+
+    #[test]
+    fn test_raw_scan() {
+        let config = Config::default();
+        let mut storage = Storage::new(&config).unwrap();
+        storage.start(&config).unwrap();
+        let (tx, rx) = channel();
+        for (i, key) in [b"a", b"b", b"c"].iter().enumerate() {
+            storage
+                .async_raw_put(
+                    Context::new(),
+                    String::new(),
+                    key.to_vec(),
+                    b"v".to_vec(),
+                    0,
+                    expect_ok(tx.clone(), i as i32),
+                )
+                .unwrap();
+            rx.recv().unwrap();
+        }
+        // A reverse scan walks from `start_key` toward smaller keys.
+        let mut options = Options::default();
+        options.reverse = true;
+        storage
+            .async_raw_scan(
+                Context::new(),
+                String::new(),
+                b"c".to_vec(),
+                10,
+                options,
+                expect_scan(
+                    tx.clone(),
+                    vec![
+                        Some((b"b".to_vec(), b"v".to_vec())),
+                        Some((b"a".to_vec(), b"v".to_vec())),
+                    ],
+                    3,
+                ),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        // With key_only the values stay behind.
+        let mut options = Options::default();
+        options.key_only = true;
+        storage
+            .async_raw_scan(
+                Context::new(),
+                String::new(),
+                b"".to_vec(),
+                10,
+                options,
+                expect_scan(
+                    tx.clone(),
+                    vec![
+                        Some((b"a".to_vec(), vec![])),
+                        Some((b"b".to_vec(), vec![])),
+                        Some((b"c".to_vec(), vec![])),
+                    ],
+                    4,
+                ),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        storage.stop().unwrap();
+    }
+
+    #[test]
+    fn test_raw_delete_range() {
+        let config = Config::default();
+        let mut storage = Storage::new(&config).unwrap();
+        storage.start(&config).unwrap();
+        let (tx, rx) = channel();
+        for (i, key) in [b"a", b"b", b"c"].iter().enumerate() {
+            storage
+                .async_raw_put(
+                    Context::new(),
+                    String::new(),
+                    key.to_vec(),
+                    b"v".to_vec(),
+                    0,
+                    expect_ok(tx.clone(), i as i32),
+                )
+                .unwrap();
+            rx.recv().unwrap();
+        }
+        // The end key is exclusive.
+        storage
+            .async_raw_delete_range(
+                Context::new(),
+                String::new(),
+                b"a".to_vec(),
+                b"c".to_vec(),
+                expect_ok(tx.clone(), 3),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        storage
+            .async_raw_get(
+                Context::new(),
+                String::new(),
+                b"a".to_vec(),
+                expect_get_none(tx.clone(), 4),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        storage
+            .async_raw_get(
+                Context::new(),
+                String::new(),
+                b"c".to_vec(),
+                expect_get_val(tx.clone(), b"v".to_vec(), 5),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        storage.stop().unwrap();
+    }
+
+    #[test]
+    fn test_raw_cf() {
+        let config = Config::default();
+        let mut storage = Storage::new(&config).unwrap();
+        storage.start(&config).unwrap();
+        let (tx, rx) = channel();
+        // The same key lives independently in different cfs.
+        storage
+            .async_raw_put(
+                Context::new(),
+                String::new(),
+                b"k".to_vec(),
+                b"dv".to_vec(),
+                0,
+                expect_ok(tx.clone(), 0),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        storage
+            .async_raw_put(
+                Context::new(),
+                CF_LOCK.to_owned(),
+                b"k".to_vec(),
+                b"lv".to_vec(),
+                0,
+                expect_ok(tx.clone(), 1),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        storage
+            .async_raw_get(
+                Context::new(),
+                CF_LOCK.to_owned(),
+                b"k".to_vec(),
+                expect_get_val(tx.clone(), b"lv".to_vec(), 2),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        storage
+            .async_raw_delete(
+                Context::new(),
+                CF_LOCK.to_owned(),
+                b"k".to_vec(),
+                expect_ok(tx.clone(), 3),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        storage
+            .async_raw_get(
+                Context::new(),
+                CF_LOCK.to_owned(),
+                b"k".to_vec(),
+                expect_get_none(tx.clone(), 4),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        // The default cf copy is untouched.
+        storage
+            .async_raw_get(
+                Context::new(),
+                String::new(),
+                b"k".to_vec(),
+                expect_get_val(tx.clone(), b"dv".to_vec(), 5),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        // A cf outside DATA_CFS is rejected before it is scheduled.
+        match storage.async_raw_put(
+            Context::new(),
+            "foo".to_owned(),
+            b"k".to_vec(),
+            b"v".to_vec(),
+            0,
+            never_cb(),
+        ) {
+            Err(Error::InvalidCf(ref cf)) => assert_eq!(cf, "foo"),
+            other => panic!("expect InvalidCf, got {:?}", other),
+        }
+        storage.stop().unwrap();
+    }
+
+    #[test]
+    fn test_raw_ttl() {
+        let mut config = Config::default();
+        config.enable_raw_ttl = true;
+        let mut storage = Storage::new(&config).unwrap();
+        storage.start(&config).unwrap();
+        let (tx, rx) = channel();
+        storage
+            .async_raw_put(
+                Context::new(),
+                String::new(),
+                b"k1".to_vec(),
+                b"v1".to_vec(),
+                0,
+                expect_ok(tx.clone(), 0),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        storage
+            .async_raw_put(
+                Context::new(),
+                String::new(),
+                b"k2".to_vec(),
+                b"v2".to_vec(),
+                3600,
+                expect_ok(tx.clone(), 1),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        // Reads strip the expiry suffix again.
+        storage
+            .async_raw_get(
+                Context::new(),
+                String::new(),
+                b"k1".to_vec(),
+                expect_get_val(tx.clone(), b"v1".to_vec(), 2),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        storage
+            .async_raw_batch_get(
+                Context::new(),
+                String::new(),
+                vec![b"k1".to_vec(), b"k2".to_vec()],
+                expect_scan(
+                    tx.clone(),
+                    vec![
+                        Some((b"k1".to_vec(), b"v1".to_vec())),
+                        Some((b"k2".to_vec(), b"v2".to_vec())),
+                    ],
+                    3,
+                ),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        storage
+            .async_raw_scan(
+                Context::new(),
+                String::new(),
+                b"".to_vec(),
+                10,
+                Options::default(),
+                expect_scan(
+                    tx.clone(),
+                    vec![
+                        Some((b"k1".to_vec(), b"v1".to_vec())),
+                        Some((b"k2".to_vec(), b"v2".to_vec())),
+                    ],
+                    4,
+                ),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        // A ttl makes no sense as a counter suffix.
+        assert!(
+            storage
+                .async_raw_incr(Context::new(), b"c".to_vec(), 1, expect_ok(tx.clone(), 5))
+                .is_err()
+        );
+        storage.stop().unwrap();
+
+        // Without the switch a ttl is rejected up front.
+        let config = Config::default();
+        let mut storage = Storage::new(&config).unwrap();
+        storage.start(&config).unwrap();
+        assert!(
+            storage
+                .async_raw_put(
+                    Context::new(),
+                    String::new(),
+                    b"k".to_vec(),
+                    b"v".to_vec(),
+                    1,
+                    never_cb(),
+                )
+                .is_err()
+        );
+        storage.stop().unwrap();
+    }
+
+    #[test]
+    fn test_raw_incr() {
+        use util::codec::number::NumberEncoder;
+
+        fn le_bytes(v: i64) -> Vec<u8> {
+            let mut buf = vec![];
+            buf.encode_i64_le(v).unwrap();
+            buf
+        }
+
+        let config = Config::default();
+        let mut storage = Storage::new(&config).unwrap();
+        storage.start(&config).unwrap();
+        let (tx, rx) = channel();
+        // A missing counter starts from zero.
+        storage
+            .async_raw_incr(
+                Context::new(),
+                b"c".to_vec(),
+                5,
+                expect_get_val(tx.clone(), le_bytes(5), 0),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        storage
+            .async_raw_incr(
+                Context::new(),
+                b"c".to_vec(),
+                -2,
+                expect_get_val(tx.clone(), le_bytes(3), 1),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        storage
+            .async_raw_get(
+                Context::new(),
+                String::new(),
+                b"c".to_vec(),
+                expect_get_val(tx.clone(), le_bytes(3), 2),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        // Overflow must leave the counter untouched.
+        storage
+            .async_raw_incr(
+                Context::new(),
+                b"c".to_vec(),
+                i64::max_value(),
+                expect_fail(tx.clone(), 3),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        storage
+            .async_raw_get(
+                Context::new(),
+                String::new(),
+                b"c".to_vec(),
+                expect_get_val(tx.clone(), le_bytes(3), 4),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        // Values of the wrong width are not counters.
+        storage
+            .async_raw_put(
+                Context::new(),
+                String::new(),
+                b"s".to_vec(),
+                b"x".to_vec(),
+                0,
+                expect_ok(tx.clone(), 5),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        storage
+            .async_raw_incr(Context::new(), b"s".to_vec(), 1, expect_fail(tx.clone(), 6))
+            .unwrap();
+        rx.recv().unwrap();
+        storage.stop().unwrap();
+    }
+
+    #[test]
+    fn test_raw_cas() {
+        let config = Config::default();
+        let mut storage = Storage::new(&config).unwrap();
+        storage.start(&config).unwrap();
+        let (tx, rx) = channel();
+        // Expecting a value on a missing key fails the swap.
+        storage
+            .async_raw_compare_and_swap(
+                Context::new(),
+                b"k".to_vec(),
+                Some(b"v0".to_vec()),
+                b"v1".to_vec(),
+                expect_get_none(tx.clone(), 0),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        storage
+            .async_raw_get(
+                Context::new(),
+                String::new(),
+                b"k".to_vec(),
+                expect_get_none(tx.clone(), 1),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        // Expecting absence installs the first value.
+        storage
+            .async_raw_compare_and_swap(
+                Context::new(),
+                b"k".to_vec(),
+                None,
+                b"v1".to_vec(),
+                expect_get_none(tx.clone(), 2),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        // A stale expectation returns the current value untouched.
+        storage
+            .async_raw_compare_and_swap(
+                Context::new(),
+                b"k".to_vec(),
+                Some(b"v0".to_vec()),
+                b"v2".to_vec(),
+                expect_get_val(tx.clone(), b"v1".to_vec(), 3),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        storage
+            .async_raw_get(
+                Context::new(),
+                String::new(),
+                b"k".to_vec(),
+                expect_get_val(tx.clone(), b"v1".to_vec(), 4),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        // A matching expectation swaps.
+        storage
+            .async_raw_compare_and_swap(
+                Context::new(),
+                b"k".to_vec(),
+                Some(b"v1".to_vec()),
+                b"v2".to_vec(),
+                expect_get_val(tx.clone(), b"v1".to_vec(), 5),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        storage
+            .async_raw_get(
+                Context::new(),
+                String::new(),
+                b"k".to_vec(),
+                expect_get_val(tx.clone(), b"v2".to_vec(), 6),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        storage.stop().unwrap();
+    }
+
+    #[test]
+    fn test_put_with_err() {
+        let config = Config::default();
+        // New engine lack of some column families.
+        let engine = engine::new_local_engine(&config.data_dir, &["default"]).unwrap();
+        let mut storage = Storage::from_engine(engine, &config).unwrap();
+        storage.start(&config).unwrap();
+        let (tx, rx) = channel();
+        storage
+            .async_prewrite(
+                Context::new(),
+                vec![
+                    Mutation::Put((make_key(b"a"), b"aa".to_vec())),
+                    Mutation::Put((make_key(b"b"), b"bb".to_vec())),
+                    Mutation::Put((make_key(b"c"), b"cc".to_vec())),
+                ],
+                b"a".to_vec(),
+                1,
+                Options::default(),
+                expect_fail(tx.clone(), 0),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        storage.stop().unwrap();
+    }
+
+    #[test]
+    fn test_scan() {
+        let config = Config::default();
+        let mut storage = Storage::new(&config).unwrap();
+        storage.start(&config).unwrap();
+        let (tx, rx) = channel();
+        storage
+            .async_prewrite(
+                Context::new(),
+                vec![
+                    Mutation::Put((make_key(b"a"), b"aa".to_vec())),
+                    Mutation::Put((make_key(b"b"), b"bb".to_vec())),
+                    Mutation::Put((make_key(b"c"), b"cc".to_vec())),
+                ],
+                b"a".to_vec(),
+                1,
+                Options::default(),
+                expect_ok(tx.clone(), 0),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        storage
+            .async_commit(
+                Context::new(),
+                vec![make_key(b"a"), make_key(b"b"), make_key(b"c")],
+                1,
+                2,
+                expect_ok(tx.clone(), 1),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        storage
+            .async_scan(
+                Context::new(),
+                make_key(b"\x00"),
+                None,
+                1000,
+                5,
+                Options::default(),
+                expect_scan(
+                    tx.clone(),
+                    vec![
+                        Some((b"a".to_vec(), b"aa".to_vec())),
+                        Some((b"b".to_vec(), b"bb".to_vec())),
+                        Some((b"c".to_vec(), b"cc".to_vec())),
+                    ],
+                    2,
+                ),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        // Bounded by an end key the scan stops at the range boundary.
+        storage
+            .async_scan(
+                Context::new(),
+                make_key(b"\x00"),
+                Some(make_key(b"c")),
+                1000,
+                5,
+                Options::default(),
+                expect_scan(
+                    tx.clone(),
+                    vec![
+                        Some((b"a".to_vec(), b"aa".to_vec())),
+                        Some((b"b".to_vec(), b"bb".to_vec())),
+                    ],
+                    3,
+                ),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        storage.stop().unwrap();
+    }
+
+    #[test]
+    fn test_reverse_scan() {
+        let config = Config::default();
+        let mut storage = Storage::new(&config).unwrap();
+        storage.start(&config).unwrap();
+        let (tx, rx) = channel();
+        storage
+            .async_prewrite(
+                Context::new(),
+                vec![
+                    Mutation::Put((make_key(b"a"), b"aa".to_vec())),
+                    Mutation::Put((make_key(b"b"), b"bb".to_vec())),
+                    Mutation::Put((make_key(b"c"), b"cc".to_vec())),
+                ],
+                b"a".to_vec(),
+                1,
+                Options::default(),
+                expect_ok(tx.clone(), 0),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        storage
+            .async_commit(
                 Context::new(),
-                make_key(b"x"),
-                100,
-                expect_get_none(tx.clone(), 3),
+                vec![make_key(b"a"), make_key(b"b"), make_key(b"c")],
+                1,
+                2,
+                expect_ok(tx.clone(), 1),
             )
             .unwrap();
         rx.recv().unwrap();
+        let mut options = Options::default();
+        options.reverse = true;
         storage
-            .async_get(
+            .async_scan(
                 Context::new(),
-                make_key(b"x"),
-                101,
-                expect_get_val(tx.clone(), b"100".to_vec(), 4),
+                make_key(b"\xff"),
+                None,
+                1000,
+                5,
+                options.clone(),
+                expect_scan(
+                    tx.clone(),
+                    vec![
+                        Some((b"c".to_vec(), b"cc".to_vec())),
+                        Some((b"b".to_vec(), b"bb".to_vec())),
+                        Some((b"a".to_vec(), b"aa".to_vec())),
+                    ],
+                    2,
+                ),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        // A reverse scan does not go below its end key.
+        storage
+            .async_scan(
+                Context::new(),
+                make_key(b"\xff"),
+                Some(make_key(b"b")),
+                1000,
+                5,
+                options,
+                expect_scan(
+                    tx.clone(),
+                    vec![
+                        Some((b"c".to_vec(), b"cc".to_vec())),
+                        Some((b"b".to_vec(), b"bb".to_vec())),
+                    ],
+                    3,
+                ),
             )
             .unwrap();
         rx.recv().unwrap();
@@ -1116,11 +3613,9 @@ mod tests {
     }
 
     #[test]
-    fn test_put_with_err() {
+    fn test_snapshot_scan_stream() {
         let config = Config::default();
-        // New engine lack of some column families.
-        let engine = engine::new_local_engine(&config.data_dir, &["default"]).unwrap();
-        let mut storage = Storage::from_engine(engine, &config).unwrap();
+        let mut storage = Storage::new(&config).unwrap();
         storage.start(&config).unwrap();
         let (tx, rx) = channel();
         storage
@@ -1134,15 +3629,62 @@ mod tests {
                 b"a".to_vec(),
                 1,
                 Options::default(),
-                expect_fail(tx.clone(), 0),
+                expect_ok(tx.clone(), 0),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        storage
+            .async_commit(
+                Context::new(),
+                vec![make_key(b"a"), make_key(b"b"), make_key(b"c")],
+                1,
+                2,
+                expect_ok(tx.clone(), 1),
             )
             .unwrap();
         rx.recv().unwrap();
+
+        let mut stream =
+            storage.snapshot_scan_stream(Context::new(), make_key(b"\x00"), None, 5, 2, 0);
+        let batch = stream.next_batch().unwrap().unwrap();
+        let pairs: Vec<KvPair> = batch.into_iter().map(|x| x.unwrap()).collect();
+        assert_eq!(
+            pairs,
+            vec![
+                (b"a".to_vec(), b"aa".to_vec()),
+                (b"b".to_vec(), b"bb".to_vec()),
+            ]
+        );
+        let batch = stream.next_batch().unwrap().unwrap();
+        let pairs: Vec<KvPair> = batch.into_iter().map(|x| x.unwrap()).collect();
+        assert_eq!(pairs, vec![(b"c".to_vec(), b"cc".to_vec())]);
+        assert!(stream.next_batch().is_none());
+
+        // An end key bounds the stream.
+        let mut stream = storage.snapshot_scan_stream(
+            Context::new(),
+            make_key(b"\x00"),
+            Some(make_key(b"c")),
+            5,
+            10,
+            0,
+        );
+        let batch = stream.next_batch().unwrap().unwrap();
+        let pairs: Vec<KvPair> = batch.into_iter().map(|x| x.unwrap()).collect();
+        assert_eq!(
+            pairs,
+            vec![
+                (b"a".to_vec(), b"aa".to_vec()),
+                (b"b".to_vec(), b"bb".to_vec()),
+            ]
+        );
+        assert!(stream.next_batch().is_none());
+
         storage.stop().unwrap();
     }
 
     #[test]
-    fn test_scan() {
+    fn test_incremental_scan_stream() {
         let config = Config::default();
         let mut storage = Storage::new(&config).unwrap();
         storage.start(&config).unwrap();
@@ -1172,14 +3714,104 @@ mod tests {
             )
             .unwrap();
         rx.recv().unwrap();
+        // Overwrite `b`, delete `a` and add `d` on top of the first batch.
         storage
-            .async_scan(
+            .async_prewrite(
                 Context::new(),
-                make_key(b"\x00"),
-                1000,
-                5,
+                vec![
+                    Mutation::Delete(make_key(b"a")),
+                    Mutation::Put((make_key(b"b"), b"b2".to_vec())),
+                    Mutation::Put((make_key(b"d"), b"dd".to_vec())),
+                ],
+                b"b".to_vec(),
+                10,
                 Options::default(),
-                expect_scan(
+                expect_ok(tx.clone(), 2),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        storage
+            .async_commit(
+                Context::new(),
+                vec![make_key(b"a"), make_key(b"b"), make_key(b"d")],
+                10,
+                11,
+                expect_ok(tx.clone(), 3),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+
+        // Only `b` and `d` committed in `(2, 20]`: `c` is untouched and
+        // the deletion of `a` leaves no pair behind.
+        let mut stream = storage.incremental_scan_stream(
+            Context::new(),
+            make_key(b"\x00"),
+            None,
+            2,
+            20,
+            1,
+            0,
+        );
+        let batch = stream.next_batch().unwrap().unwrap();
+        let pairs: Vec<KvPair> = batch.into_iter().map(|x| x.unwrap()).collect();
+        assert_eq!(pairs, vec![(b"b".to_vec(), b"b2".to_vec())]);
+        let batch = stream.next_batch().unwrap().unwrap();
+        let pairs: Vec<KvPair> = batch.into_iter().map(|x| x.unwrap()).collect();
+        assert_eq!(pairs, vec![(b"d".to_vec(), b"dd".to_vec())]);
+        assert!(stream.next_batch().is_none());
+
+        // Nothing committed in `(11, 20]`.
+        let mut stream = storage.incremental_scan_stream(
+            Context::new(),
+            make_key(b"\x00"),
+            None,
+            11,
+            20,
+            10,
+            0,
+        );
+        assert!(stream.next_batch().is_none());
+
+        storage.stop().unwrap();
+    }
+
+    #[test]
+    fn test_batch_get() {
+        let config = Config::default();
+        let mut storage = Storage::new(&config).unwrap();
+        storage.start(&config).unwrap();
+        let (tx, rx) = channel();
+        storage
+            .async_prewrite(
+                Context::new(),
+                vec![
+                    Mutation::Put((make_key(b"a"), b"aa".to_vec())),
+                    Mutation::Put((make_key(b"b"), b"bb".to_vec())),
+                    Mutation::Put((make_key(b"c"), b"cc".to_vec())),
+                ],
+                b"a".to_vec(),
+                1,
+                Options::default(),
+                expect_ok(tx.clone(), 0),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        storage
+            .async_commit(
+                Context::new(),
+                vec![make_key(b"a"), make_key(b"b"), make_key(b"c")],
+                1,
+                2,
+                expect_ok(tx.clone(), 1),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        storage
+            .async_batch_get(
+                Context::new(),
+                vec![make_key(b"a"), make_key(b"b"), make_key(b"c")],
+                5,
+                expect_batch_get_vals(
                     tx.clone(),
                     vec![
                         Some((b"a".to_vec(), b"aa".to_vec())),
@@ -1195,7 +3827,7 @@ mod tests {
     }
 
     #[test]
-    fn test_batch_get() {
+    fn test_scan_hold_snapshot_for_batch_get() {
         let config = Config::default();
         let mut storage = Storage::new(&config).unwrap();
         storage.start(&config).unwrap();
@@ -1225,6 +3857,31 @@ mod tests {
             )
             .unwrap();
         rx.recv().unwrap();
+        // The scan announces a follow-up lookup, its snapshot is held.
+        let mut options = Options::default();
+        options.hold_snapshot = true;
+        storage
+            .async_scan(
+                Context::new(),
+                make_key(b"\x00"),
+                None,
+                1000,
+                5,
+                options,
+                expect_scan(
+                    tx.clone(),
+                    vec![
+                        Some((b"a".to_vec(), b"aa".to_vec())),
+                        Some((b"b".to_vec(), b"bb".to_vec())),
+                        Some((b"c".to_vec(), b"cc".to_vec())),
+                    ],
+                    2,
+                ),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        // The lookup with the same start_ts is served from the held
+        // snapshot and sees exactly what the scan saw.
         storage
             .async_batch_get(
                 Context::new(),
@@ -1237,7 +3894,7 @@ mod tests {
                         Some((b"b".to_vec(), b"bb".to_vec())),
                         Some((b"c".to_vec(), b"cc".to_vec())),
                     ],
-                    2,
+                    3,
                 ),
             )
             .unwrap();
@@ -1325,6 +3982,158 @@ mod tests {
         storage.stop().unwrap();
     }
 
+    #[test]
+    fn test_wait_for_lock() {
+        let mut config = Config::default();
+        config.wait_for_lock_timeout = 1000;
+        let mut storage = Storage::new(&config).unwrap();
+        storage.start(&config).unwrap();
+        let (tx, rx) = channel();
+        storage
+            .async_prewrite(
+                Context::new(),
+                vec![Mutation::Put((make_key(b"x"), b"100".to_vec()))],
+                b"x".to_vec(),
+                10,
+                Options::default(),
+                expect_ok(tx.clone(), 0),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        // Runs into txn 10's lock and parks instead of failing.
+        storage
+            .async_prewrite(
+                Context::new(),
+                vec![Mutation::Put((make_key(b"x"), b"101".to_vec()))],
+                b"x".to_vec(),
+                20,
+                Options::default(),
+                expect_ok(tx.clone(), 1),
+            )
+            .unwrap();
+        // Committing txn 10 wakes the parked prewrite, whose retry
+        // succeeds because the commit is below its start_ts.
+        storage
+            .async_commit(
+                Context::new(),
+                vec![make_key(b"x")],
+                10,
+                15,
+                expect_ok(tx.clone(), 2),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        rx.recv().unwrap();
+        storage.stop().unwrap();
+    }
+
+    #[test]
+    fn test_wait_for_lock_timeout() {
+        let mut config = Config::default();
+        config.wait_for_lock_timeout = 100;
+        let mut storage = Storage::new(&config).unwrap();
+        storage.start(&config).unwrap();
+        let (tx, rx) = channel();
+        storage
+            .async_prewrite(
+                Context::new(),
+                vec![Mutation::Put((make_key(b"x"), b"100".to_vec()))],
+                b"x".to_vec(),
+                10,
+                Options::default(),
+                expect_ok(tx.clone(), 0),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        // Nobody releases txn 10's lock, the parked prewrite gets the
+        // lock back once the wait expires, as if it was never parked.
+        let done = tx.clone();
+        storage
+            .async_prewrite(
+                Context::new(),
+                vec![Mutation::Put((make_key(b"x"), b"101".to_vec()))],
+                b"x".to_vec(),
+                20,
+                Options::default(),
+                Callback::Boxed(Box::new(move |x: Result<Vec<Result<()>>>| {
+                    assert!(x.unwrap()[0].is_err());
+                    done.send(1).unwrap();
+                })),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        storage.stop().unwrap();
+    }
+
+    #[test]
+    fn test_deadlock() {
+        let mut config = Config::default();
+        config.wait_for_lock_timeout = 1000;
+        let mut storage = Storage::new(&config).unwrap();
+        storage.start(&config).unwrap();
+        let (tx, rx) = channel();
+        storage
+            .async_acquire_pessimistic_lock(
+                Context::new(),
+                vec![make_key(b"x")],
+                b"x".to_vec(),
+                10,
+                10,
+                Options::default(),
+                expect_ok(tx.clone(), 0),
+            )
+            .unwrap();
+        storage
+            .async_acquire_pessimistic_lock(
+                Context::new(),
+                vec![make_key(b"y")],
+                b"y".to_vec(),
+                20,
+                20,
+                Options::default(),
+                expect_ok(tx.clone(), 1),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        rx.recv().unwrap();
+        // Txn 10 parks behind txn 20's lock on y.
+        let done = tx.clone();
+        storage
+            .async_acquire_pessimistic_lock(
+                Context::new(),
+                vec![make_key(b"y")],
+                b"x".to_vec(),
+                10,
+                10,
+                Options::default(),
+                Callback::Boxed(Box::new(move |x: Result<Vec<Result<()>>>| {
+                    assert!(x.unwrap()[0].is_err());
+                    done.send(2).unwrap();
+                })),
+            )
+            .unwrap();
+        // Give the request above time to park, so the cycle is closed
+        // by the request below and the rejection lands on txn 20.
+        thread::sleep(Duration::from_millis(100));
+        // Txn 20 asking for txn 10's lock on x would close the cycle
+        // and is rejected instead of parked.
+        storage
+            .async_acquire_pessimistic_lock(
+                Context::new(),
+                vec![make_key(b"x")],
+                b"y".to_vec(),
+                20,
+                20,
+                Options::default(),
+                expect_fail(tx.clone(), 3),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        // Stopping the scheduler flushes the waiter still parked on y.
+        storage.stop().unwrap();
+        rx.recv().unwrap();
+    }
+
     #[test]
     fn test_sched_too_busy() {
         let mut config = Config::default();
@@ -23,11 +23,13 @@ pub enum LockType {
     Put,
     Delete,
     Lock,
+    Pessimistic,
 }
 
 const FLAG_PUT: u8 = b'P';
 const FLAG_DELETE: u8 = b'D';
 const FLAG_LOCK: u8 = b'L';
+const FLAG_PESSIMISTIC: u8 = b'S';
 
 impl LockType {
     pub fn from_mutation(mutation: &Mutation) -> LockType {
@@ -43,6 +45,7 @@ impl LockType {
             FLAG_PUT => Some(LockType::Put),
             FLAG_DELETE => Some(LockType::Delete),
             FLAG_LOCK => Some(LockType::Lock),
+            FLAG_PESSIMISTIC => Some(LockType::Pessimistic),
             _ => None,
         }
     }
@@ -52,6 +55,7 @@ impl LockType {
             LockType::Put => FLAG_PUT,
             LockType::Delete => FLAG_DELETE,
             LockType::Lock => FLAG_LOCK,
+            LockType::Pessimistic => FLAG_PESSIMISTIC,
         }
     }
 }
@@ -63,6 +67,9 @@ pub struct Lock {
     pub ts: u64,
     pub ttl: u64,
     pub short_value: Option<Value>,
+    // The snapshot a pessimistic lock certified as conflict free, zero
+    // on every other lock type.
+    pub for_update_ts: u64,
 }
 
 impl Lock {
@@ -72,6 +79,7 @@ impl Lock {
         ts: u64,
         ttl: u64,
         short_value: Option<Value>,
+        for_update_ts: u64,
     ) -> Lock {
         Lock {
             lock_type: lock_type,
@@ -79,6 +87,7 @@ impl Lock {
             ts: ts,
             ttl: ttl,
             short_value: short_value,
+            for_update_ts: for_update_ts,
         }
     }
 
@@ -90,6 +99,11 @@ impl Lock {
         b.encode_compact_bytes(&self.primary).unwrap();
         b.encode_var_u64(self.ts).unwrap();
         b.encode_var_u64(self.ttl).unwrap();
+        // Only pessimistic locks carry a for_update_ts, so locks written
+        // by old versions still parse.
+        if self.lock_type == LockType::Pessimistic {
+            b.encode_var_u64(self.for_update_ts).unwrap();
+        }
         if let Some(ref v) = self.short_value {
             b.push(SHORT_VALUE_PREFIX);
             b.push(v.len() as u8);
@@ -107,9 +121,14 @@ impl Lock {
         let ts = b.decode_var_u64()?;
         let ttl =
             if b.is_empty() { 0 } else { b.decode_var_u64()? };
+        let for_update_ts = if lock_type == LockType::Pessimistic {
+            b.decode_var_u64()?
+        } else {
+            0
+        };
 
         if b.is_empty() {
-            return Ok(Lock::new(lock_type, primary, ts, ttl, None));
+            return Ok(Lock::new(lock_type, primary, ts, ttl, None, for_update_ts));
         }
 
         let flag = b.read_u8()?;
@@ -129,7 +148,14 @@ impl Lock {
             );
         }
 
-        Ok(Lock::new(lock_type, primary, ts, ttl, Some(b.to_vec())))
+        Ok(Lock::new(
+            lock_type,
+            primary,
+            ts,
+            ttl,
+            Some(b.to_vec()),
+            for_update_ts,
+        ))
     }
 }
 
@@ -186,20 +212,28 @@ mod tests {
                 lt
             );
         }
+        // A pessimistic lock is never built from a mutation.
+        assert_eq!(LockType::Pessimistic.to_u8(), FLAG_PESSIMISTIC);
+        assert_eq!(
+            LockType::from_u8(FLAG_PESSIMISTIC).unwrap(),
+            LockType::Pessimistic
+        );
     }
 
     #[test]
     fn test_lock() {
         // Test `Lock::to_bytes()` and `Lock::parse()` works as a pair.
         let mut locks = vec![
-            Lock::new(LockType::Put, b"pk".to_vec(), 1, 10, None),
+            Lock::new(LockType::Put, b"pk".to_vec(), 1, 10, None, 0),
             Lock::new(
                 LockType::Delete,
                 b"pk".to_vec(),
                 1,
                 10,
                 Some(b"short_value".to_vec()),
+                0,
             ),
+            Lock::new(LockType::Pessimistic, b"pk".to_vec(), 20, 10, None, 23),
         ];
         for (i, lock) in locks.drain(..).enumerate() {
             let v = lock.to_bytes();
@@ -216,6 +250,7 @@ mod tests {
             1,
             10,
             Some(b"short_value".to_vec()),
+            0,
         );
         let v = lock.to_bytes();
         assert!(Lock::parse(&v[..4]).is_err());
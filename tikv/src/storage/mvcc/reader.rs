@@ -23,6 +23,17 @@ use util::properties::MvccProperties;
 
 const GC_MAX_ROW_VERSIONS_THRESHOLD: u64 = 100;
 
+// A point get steps over at most this many `Lock`/`Rollback` records
+// before it falls back to timestamped seeks.
+const NEAR_POINT_GET_BOUND: usize = 4;
+
+/// The outcome of a bounded point get: either a definitive value, or the
+/// timestamp to resume from with regular timestamped seeks.
+enum NearPointGet {
+    Value(Option<Value>),
+    Fallback(u64),
+}
+
 pub struct MvccReader {
     snapshot: Box<Snapshot>,
     statistics: Statistics,
@@ -33,6 +44,9 @@ pub struct MvccReader {
 
     scan_mode: Option<ScanMode>,
     key_only: bool,
+    // Overrides how many versions a scan steps over before it seeks to the
+    // next user key. `None` keeps the cursor default.
+    seek_bound: Option<usize>,
 
     fill_cache: bool,
     lower_bound: Option<Vec<u8>>,
@@ -58,6 +72,7 @@ impl MvccReader {
             scan_mode: scan_mode,
             isolation_level: isolation_level,
             key_only: false,
+            seek_bound: None,
             fill_cache: fill_cache,
             lower_bound: lower_bound,
             upper_bound: upper_bound,
@@ -72,6 +87,13 @@ impl MvccReader {
         self.key_only = key_only;
     }
 
+    /// Sets how many versioned entries a scan steps over before it gives up
+    /// stepping and seeks to the next user key instead. Only affects the
+    /// write cursor of scans; point gets use prefix seeks.
+    pub fn set_seek_bound(&mut self, bound: usize) {
+        self.seek_bound = Some(bound);
+    }
+
     pub fn load_data(&mut self, key: &Key, ts: u64) -> Result<Value> {
         if self.key_only {
             return Ok(vec![]);
@@ -154,8 +176,11 @@ impl MvccReader {
         if self.scan_mode.is_some() {
             if self.write_cursor.is_none() {
                 let iter_opt = IterOption::new(None, None, self.fill_cache);
-                let iter = self.snapshot
+                let mut iter = self.snapshot
                     .iter_cf(CF_WRITE, iter_opt, self.get_scan_mode(false))?;
+                if let Some(bound) = self.seek_bound {
+                    iter.set_seek_bound(bound);
+                }
                 self.write_cursor = Some(iter);
             }
         } else {
@@ -219,6 +244,15 @@ impl MvccReader {
             },
             IsolationLevel::RC => {}
         }
+        if self.scan_mode.is_none() {
+            // Point gets step over adjacent versions with the write cursor
+            // instead of paying a seek for each one; only keys with long
+            // version chains fall back to timestamped seeks.
+            match self.near_point_get(key, ts)? {
+                NearPointGet::Value(v) => return Ok(v),
+                NearPointGet::Fallback(fallback_ts) => ts = fallback_ts,
+            }
+        }
         loop {
             match self.seek_write(key, ts)? {
                 Some((commit_ts, mut write)) => match write.write_type {
@@ -241,6 +275,62 @@ impl MvccReader {
         }
     }
 
+    /// Point gets the value of `key` at `ts` by walking versions with the
+    /// write cursor.
+    ///
+    /// `seek_write` positions the cursor at the newest version visible at
+    /// `ts`; older versions are adjacent in the write CF, so `Lock` and
+    /// `Rollback` records are stepped over with `next` instead of another
+    /// seek each. Keys can accumulate a lot of such records between GC
+    /// runs, so walking more than `NEAR_POINT_GET_BOUND` of them falls
+    /// back to timestamped seeks.
+    fn near_point_get(&mut self, key: &Key, ts: u64) -> Result<NearPointGet> {
+        let (mut commit_ts, mut write) = match self.seek_write(key, ts)? {
+            Some(x) => x,
+            None => return Ok(NearPointGet::Value(None)),
+        };
+        let mut steps = 0;
+        loop {
+            match write.write_type {
+                WriteType::Put => {
+                    if write.short_value.is_some() {
+                        if self.key_only {
+                            return Ok(NearPointGet::Value(Some(vec![])));
+                        }
+                        return Ok(NearPointGet::Value(write.short_value.take()));
+                    }
+                    let value = self.load_data(key, write.start_ts)?;
+                    return Ok(NearPointGet::Value(Some(value)));
+                }
+                WriteType::Delete => return Ok(NearPointGet::Value(None)),
+                WriteType::Lock | WriteType::Rollback => {
+                    steps += 1;
+                    if steps > NEAR_POINT_GET_BOUND {
+                        self.statistics.write.over_seek_bound += 1;
+                        return Ok(NearPointGet::Fallback(commit_ts - 1));
+                    }
+                    let next = {
+                        let cursor = self.write_cursor.as_mut().unwrap();
+                        if !cursor.next(&mut self.statistics.write) {
+                            return Ok(NearPointGet::Value(None));
+                        }
+                        let write_key = Key::from_encoded(cursor.key().to_vec());
+                        if &write_key.truncate_ts()? != key {
+                            return Ok(NearPointGet::Value(None));
+                        }
+                        self.statistics.write.flow_stats.read_bytes +=
+                            cursor.key().len() + cursor.value().len();
+                        self.statistics.write.flow_stats.read_keys += 1;
+                        (write_key.decode_ts()?, Write::parse(cursor.value())?)
+                    };
+                    commit_ts = next.0;
+                    write = next.1;
+                    self.statistics.write.processed += 1;
+                }
+            }
+        }
+    }
+
     pub fn get_txn_commit_info(
         &mut self,
         key: &Key,
@@ -273,8 +363,11 @@ impl MvccReader {
                 self.upper_bound.as_ref().cloned(),
                 self.fill_cache,
             );
-            let iter = self.snapshot
+            let mut iter = self.snapshot
                 .iter_cf(CF_WRITE, iter_opt, self.get_scan_mode(false))?;
+            if let Some(bound) = self.seek_bound {
+                iter.set_seek_bound(bound);
+            }
             self.write_cursor = Some(iter);
         }
         Ok(())
@@ -562,6 +655,7 @@ mod tests {
     use raftstore::store::keys;
     use util::rocksdb::{self as rocksdb_util, CFOptions};
     use util::properties::{MvccProperties, MvccPropertiesCollectorFactory};
+    use super::NEAR_POINT_GET_BOUND;
 
     struct RegionEngine {
         db: Arc<DB>,
@@ -699,6 +793,39 @@ mod tests {
         reader.get_mvcc_properties(safe_point)
     }
 
+    #[test]
+    fn test_near_point_get() {
+        let path = TempDir::new("_test_storage_mvcc_reader_near_point_get").expect("");
+        let path = path.path().to_str().unwrap();
+        let region = make_region(1, vec![0], vec![10]);
+        let db = open_db(path, true);
+        let mut engine = RegionEngine::new(Arc::clone(&db), region.clone());
+
+        let k = &[1];
+        engine.put(k, 1, 2);
+        // A couple of `Lock` records on top of the value stay on the
+        // stepping path.
+        engine.lock(k, 3, 4);
+        engine.lock(k, 5, 6);
+
+        let snap = RegionSnapshot::from_raw(Arc::clone(&db), region.clone());
+        let mut reader =
+            MvccReader::new(Box::new(snap), None, true, None, None, IsolationLevel::SI);
+        assert_eq!(reader.get(&make_key(k), 10).unwrap(), Some(vec![]));
+        assert_eq!(reader.get_statistics().write.over_seek_bound, 0);
+
+        // Enough records to exceed the bound force a fallback to seeks,
+        // which still finds the same version.
+        for i in 0..NEAR_POINT_GET_BOUND as u64 {
+            engine.lock(k, 7 + 2 * i, 8 + 2 * i);
+        }
+        let snap = RegionSnapshot::from_raw(Arc::clone(&db), region);
+        let mut reader =
+            MvccReader::new(Box::new(snap), None, true, None, None, IsolationLevel::SI);
+        assert_eq!(reader.get(&make_key(k), 100).unwrap(), Some(vec![]));
+        assert_eq!(reader.get_statistics().write.over_seek_bound, 1);
+    }
+
     #[test]
     fn test_need_gc() {
         let path = TempDir::new("_test_storage_mvcc_reader").expect("");
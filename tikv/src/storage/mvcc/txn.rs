@@ -12,6 +12,7 @@
 // limitations under the License.
 
 use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering, ATOMIC_BOOL_INIT};
 use storage::{is_short_value, Key, Mutation, Options, Statistics, Value, CF_DEFAULT, CF_LOCK,
               CF_WRITE};
 use storage::engine::{Modify, ScanMode, Snapshot};
@@ -24,6 +25,23 @@ use kvproto::kvrpcpb::IsolationLevel;
 
 pub const MAX_TXN_WRITE_SIZE: usize = 32 * 1024;
 
+static VERIFY_INVARIANTS: AtomicBool = ATOMIC_BOOL_INIT;
+
+/// Turns verification of MVCC write invariants on or off. When on,
+/// every commit and rollback re-reads the write column family and the
+/// server aborts on a violated invariant, so corruption is caught the
+/// moment it would be written instead of at the next consistency check
+/// panic. The extra reads are paid on every transactional write, this
+/// is meant for CI and canary stores, it is off by default and set
+/// through `storage.verify-mvcc-invariants`.
+pub fn verify_invariants(enable: bool) {
+    VERIFY_INVARIANTS.store(enable, Ordering::Relaxed);
+}
+
+fn invariants_on() -> bool {
+    VERIFY_INVARIANTS.load(Ordering::Relaxed)
+}
+
 pub struct MvccTxn {
     reader: MvccReader,
     start_ts: u64,
@@ -73,8 +91,16 @@ impl MvccTxn {
         primary: Vec<u8>,
         ttl: u64,
         short_value: Option<Value>,
+        for_update_ts: u64,
     ) {
-        let lock = Lock::new(lock_type, primary, self.start_ts, ttl, short_value).to_bytes();
+        let lock = Lock::new(
+            lock_type,
+            primary,
+            self.start_ts,
+            ttl,
+            short_value,
+            for_update_ts,
+        ).to_bytes();
         self.write_size += CF_LOCK.len() + key.encoded().len() + lock.len();
         self.writes.push(Modify::Put(CF_LOCK, key, lock));
     }
@@ -119,9 +145,35 @@ impl MvccTxn {
         options: &Options,
     ) -> Result<()> {
         let key = mutation.key();
-        if !options.skip_constraint_check {
+        // Abort on locks at any timestamp, except our own: a retry is
+        // answered idempotently and a pessimistic lock of this
+        // transaction is replaced by the ordinary lock below.
+        let mut pessimistic = false;
+        if let Some(lock) = self.reader.load_lock(key)? {
+            if lock.ts != self.start_ts {
+                return Err(Error::KeyIsLocked {
+                    key: key.raw()?,
+                    primary: lock.primary,
+                    ts: lock.ts,
+                    ttl: lock.ttl,
+                });
+            }
+            if lock.lock_type == LockType::Pessimistic {
+                pessimistic = true;
+            } else {
+                // No need to overwrite the lock and data.
+                // If we use single delete, we can't put a key multiple times.
+                MVCC_DUPLICATE_CMD_COUNTER_VEC
+                    .with_label_values(&["prewrite"])
+                    .inc();
+                return Ok(());
+            }
+        }
+        // A pessimistic lock has fenced off other writers since it was
+        // acquired, the conflict check already happened there.
+        if !pessimistic && !options.skip_constraint_check {
             if let Some((commit, _)) = self.reader.seek_write(key, u64::max_value())? {
-                // Abort on writes after our start timestamp ...
+                // Abort on writes after our start timestamp.
                 if commit >= self.start_ts {
                     MVCC_CONFLICT_COUNTER
                         .with_label_values(&["prewrite_write_conflict"])
@@ -135,23 +187,6 @@ impl MvccTxn {
                 }
             }
         }
-        // ... or locks at any timestamp.
-        if let Some(lock) = self.reader.load_lock(key)? {
-            if lock.ts != self.start_ts {
-                return Err(Error::KeyIsLocked {
-                    key: key.raw()?,
-                    primary: lock.primary,
-                    ts: lock.ts,
-                    ttl: lock.ttl,
-                });
-            }
-            // No need to overwrite the lock and data.
-            // If we use single delete, we can't put a key multiple times.
-            MVCC_DUPLICATE_CMD_COUNTER_VEC
-                .with_label_values(&["prewrite"])
-                .inc();
-            return Ok(());
-        }
 
         let short_value = if let Mutation::Put((_, ref value)) = mutation {
             if is_short_value(value) {
@@ -169,6 +204,7 @@ impl MvccTxn {
             primary.to_vec(),
             options.lock_ttl,
             short_value,
+            0,
         );
 
         if let Mutation::Put((_, ref value)) = mutation {
@@ -180,9 +216,102 @@ impl MvccTxn {
         Ok(())
     }
 
+    /// Locks `key` for a pessimistic transaction before its prewrite.
+    /// `for_update_ts` is the snapshot the caller read the key at, a
+    /// commit above it is a conflict and the caller has to retry with a
+    /// fresher timestamp. The lock keeps other writers out until the
+    /// transaction prewrites, rolls back or its ttl expires.
+    pub fn acquire_pessimistic_lock(
+        &mut self,
+        key: Key,
+        primary: &[u8],
+        for_update_ts: u64,
+        options: &Options,
+    ) -> Result<()> {
+        if let Some(lock) = self.reader.load_lock(&key)? {
+            if lock.ts != self.start_ts {
+                return Err(Error::KeyIsLocked {
+                    key: key.raw()?,
+                    primary: lock.primary,
+                    ts: lock.ts,
+                    ttl: lock.ttl,
+                });
+            }
+            // Already locked by this transaction, a retry with a newer
+            // for_update_ts moves the lock forward below.
+            if lock.for_update_ts >= for_update_ts {
+                MVCC_DUPLICATE_CMD_COUNTER_VEC
+                    .with_label_values(&["acquire_pessimistic_lock"])
+                    .inc();
+                return Ok(());
+            }
+        }
+        if let Some((commit, _)) = self.reader.seek_write(&key, u64::max_value())? {
+            if commit > for_update_ts {
+                MVCC_CONFLICT_COUNTER
+                    .with_label_values(&["acquire_pessimistic_lock_conflict"])
+                    .inc();
+                return Err(Error::WriteConflict {
+                    start_ts: self.start_ts,
+                    conflict_ts: commit,
+                    key: key.encoded().to_owned(),
+                    primary: primary.to_vec(),
+                });
+            }
+        }
+        // The transaction was rolled back, e.g. by a cleanup of an
+        // expired lock, it must not lock the key again.
+        if let Some((ts, WriteType::Rollback)) =
+            self.reader.get_txn_commit_info(&key, self.start_ts)?
+        {
+            MVCC_CONFLICT_COUNTER
+                .with_label_values(&["acquire_pessimistic_lock_rollback"])
+                .inc();
+            return Err(Error::WriteConflict {
+                start_ts: self.start_ts,
+                conflict_ts: ts,
+                key: key.encoded().to_owned(),
+                primary: primary.to_vec(),
+            });
+        }
+        self.lock_key(
+            key,
+            LockType::Pessimistic,
+            primary.to_vec(),
+            options.lock_ttl,
+            None,
+            for_update_ts,
+        );
+        Ok(())
+    }
+
+    /// Releases the pessimistic lock this transaction holds on `key`,
+    /// if any. Unlike `rollback` it leaves no rollback record: the
+    /// transaction has not prewritten yet and may still lock the key
+    /// again. Locks with a for_update_ts above the given one belong to
+    /// a newer locking round and are kept.
+    pub fn pessimistic_rollback(&mut self, key: &Key, for_update_ts: u64) -> Result<()> {
+        if let Some(lock) = self.reader.load_lock(key)? {
+            if lock.lock_type == LockType::Pessimistic && lock.ts == self.start_ts
+                && lock.for_update_ts <= for_update_ts
+            {
+                self.unlock_key(key.clone());
+                return Ok(());
+            }
+        }
+        MVCC_DUPLICATE_CMD_COUNTER_VEC
+            .with_label_values(&["pessimistic_rollback"])
+            .inc();
+        Ok(())
+    }
+
     pub fn commit(&mut self, key: &Key, commit_ts: u64) -> Result<()> {
         let (lock_type, short_value) = match self.reader.load_lock(key)? {
-            Some(ref mut lock) if lock.ts == self.start_ts => {
+            // A pessimistic lock means the prewrite never happened, it
+            // falls through to the lock not found handling below.
+            Some(ref mut lock)
+                if lock.ts == self.start_ts && lock.lock_type != LockType::Pessimistic =>
+            {
                 (lock.lock_type, lock.short_value.take())
             }
             _ => {
@@ -215,6 +344,9 @@ impl MvccTxn {
                 };
             }
         };
+        if invariants_on() {
+            self.verify_commit(key, commit_ts)?;
+        }
         let write = Write::new(
             WriteType::from_lock_type(lock_type),
             self.start_ts,
@@ -225,6 +357,55 @@ impl MvccTxn {
         Ok(())
     }
 
+    /// Checks the invariants a commit must uphold, the matching lock
+    /// has already been loaded at this point. A violation means the
+    /// write about to happen would corrupt the key, the process aborts
+    /// instead of writing it.
+    fn verify_commit(&mut self, key: &Key, commit_ts: u64) -> Result<()> {
+        // Error injection for tests and drills.
+        fail_point!("mvcc_verify_commit", |_| {
+            self.invariant_violation(key, "injected violation")
+        });
+        if commit_ts <= self.start_ts {
+            let what = format!(
+                "commit ts {} is not above start ts {}",
+                commit_ts, self.start_ts
+            );
+            self.invariant_violation(key, &what);
+        }
+        // Our lock kept every other transaction from writing this key,
+        // a commit record at or above our commit ts can only be
+        // corruption.
+        if let Some((latest, _)) = self.reader.seek_write(key, u64::max_value())? {
+            if latest >= commit_ts {
+                let what = format!(
+                    "commit ts {} is not above the latest write at {}",
+                    commit_ts, latest
+                );
+                self.invariant_violation(key, &what);
+            }
+        }
+        // Rollback protection: a rollback record of this transaction
+        // and its lock must never coexist.
+        if let Some((_, WriteType::Rollback)) =
+            self.reader.get_txn_commit_info(key, self.start_ts)?
+        {
+            self.invariant_violation(key, "commit of a rolled back transaction");
+        }
+        Ok(())
+    }
+
+    fn invariant_violation(&self, key: &Key, what: &str) -> ! {
+        error!(
+            "mvcc invariant violated on key {} by txn @{}: {}",
+            key, self.start_ts, what
+        );
+        panic!(
+            "mvcc invariant violated on key {} by txn @{}: {}",
+            key, self.start_ts, what
+        );
+    }
+
     pub fn rollback(&mut self, key: &Key) -> Result<()> {
         match self.reader.load_lock(key)? {
             Some(ref lock) if lock.ts == self.start_ts => {
@@ -263,6 +444,17 @@ impl MvccTxn {
                 };
             }
         }
+        // Rollback protection: while our lock exists no commit record
+        // of this transaction may, finding one means the key is already
+        // corrupted.
+        if invariants_on() {
+            if let Some((ts, write_type)) = self.reader.get_txn_commit_info(key, self.start_ts)? {
+                if write_type != WriteType::Rollback {
+                    let what = format!("rollback of a transaction committed at {}", ts);
+                    self.invariant_violation(key, &what);
+                }
+            }
+        }
         let write = Write::new(WriteType::Rollback, self.start_ts, None);
         let ts = self.start_ts;
         self.put_write(key, ts, write.to_bytes());
@@ -336,8 +528,9 @@ impl MvccTxn {
 mod tests {
     use tempdir::TempDir;
     use kvproto::kvrpcpb::{Context, IsolationLevel};
-    use super::MvccTxn;
+    use super::{verify_invariants, MvccTxn};
     use super::super::MvccReader;
+    use super::super::lock::LockType;
     use super::super::write::{Write, WriteType};
     use storage::{make_key, Mutation, Options, ScanMode, ALL_CFS, CF_WRITE, SHORT_VALUE_MAX_LEN};
     use storage::engine::{self, Engine, Modify, TEMP_DIR};
@@ -379,6 +572,41 @@ mod tests {
         must_get_none(engine.as_ref(), k, 23);
     }
 
+    #[test]
+    fn test_verify_invariants() {
+        let engine = engine::new_local_engine(TEMP_DIR, ALL_CFS).unwrap();
+        verify_invariants(true);
+        defer!(verify_invariants(false));
+        // Well formed transactions pass the checks untouched.
+        must_prewrite_put(engine.as_ref(), b"k", b"v", b"k", 5);
+        must_commit(engine.as_ref(), b"k", 5, 10);
+        must_prewrite_put(engine.as_ref(), b"k", b"v2", b"k", 15);
+        must_rollback(engine.as_ref(), b"k", 15);
+        must_get(engine.as_ref(), b"k", 20, b"v");
+    }
+
+    #[test]
+    #[should_panic(expected = "mvcc invariant violated")]
+    fn test_verify_commit_below_existing_write() {
+        let engine = engine::new_local_engine(TEMP_DIR, ALL_CFS).unwrap();
+        verify_invariants(true);
+        defer!(verify_invariants(false));
+        must_prewrite_put(engine.as_ref(), b"k", b"v", b"k", 5);
+        must_commit(engine.as_ref(), b"k", 5, 10);
+        // A bulk load style prewrite skips the constraint check, so the
+        // lock lands although a later write exists. Committing below
+        // that write must abort instead of burying it.
+        let ctx = Context::new();
+        let snapshot = engine.snapshot(&ctx).unwrap();
+        let mut txn = MvccTxn::new(snapshot, 2, None, IsolationLevel::SI, true);
+        let mut options = Options::default();
+        options.skip_constraint_check = true;
+        txn.prewrite(Mutation::Put((make_key(b"k"), b"v2".to_vec())), b"k", &options)
+            .unwrap();
+        write(engine.as_ref(), &ctx, txn.into_modifies());
+        must_commit(engine.as_ref(), b"k", 2, 3);
+    }
+
     #[test]
     fn test_mvcc_txn_read() {
         test_mvcc_txn_read_imp(b"k1", b"v1");
@@ -570,6 +798,62 @@ mod tests {
         test_mvcc_txn_rollback_err_imp(b"k2", &long_value);
     }
 
+    #[test]
+    fn test_pessimistic_lock() {
+        let engine = engine::new_local_engine(TEMP_DIR, ALL_CFS).unwrap();
+        let (k, v) = (b"k", b"v");
+
+        // The full path: lock, prewrite, commit.
+        must_acquire_pessimistic_lock(engine.as_ref(), k, k, 5, 5);
+        must_pessimistic_locked(engine.as_ref(), k, 5, 5);
+        // Lock acquisition is idempotent.
+        must_acquire_pessimistic_lock(engine.as_ref(), k, k, 5, 5);
+        // Other transactions are kept out.
+        must_acquire_pessimistic_lock_err(engine.as_ref(), k, k, 6, 6);
+        must_prewrite_lock_err(engine.as_ref(), k, k, 6);
+        // Commit needs the prewrite first.
+        must_commit_err(engine.as_ref(), k, 5, 8);
+        // Prewrite replaces the pessimistic lock with an ordinary one.
+        must_prewrite_put(engine.as_ref(), k, v, k, 5);
+        must_locked(engine.as_ref(), k, 5);
+        must_commit(engine.as_ref(), k, 5, 10);
+        must_unlocked(engine.as_ref(), k);
+        must_get(engine.as_ref(), k, 12, v);
+
+        // A commit above for_update_ts is a conflict, one below is not.
+        must_acquire_pessimistic_lock_err(engine.as_ref(), k, k, 8, 8);
+        must_acquire_pessimistic_lock(engine.as_ref(), k, k, 15, 15);
+
+        // A pessimistic rollback leaves no trace, the transaction may
+        // lock the key again with a fresher for_update_ts.
+        must_pessimistic_rollback(engine.as_ref(), k, 15, 15);
+        must_unlocked(engine.as_ref(), k);
+        must_acquire_pessimistic_lock(engine.as_ref(), k, k, 15, 16);
+        // A replayed rollback of an older locking round does not
+        // release the newer lock.
+        must_pessimistic_rollback(engine.as_ref(), k, 15, 15);
+        must_pessimistic_locked(engine.as_ref(), k, 15, 16);
+        must_pessimistic_rollback(engine.as_ref(), k, 15, 16);
+        must_unlocked(engine.as_ref(), k);
+        // Rolling back what is not locked is a no-op.
+        must_pessimistic_rollback(engine.as_ref(), k, 15, 16);
+    }
+
+    #[test]
+    fn test_pessimistic_lock_after_rollback() {
+        let engine = engine::new_local_engine(TEMP_DIR, ALL_CFS).unwrap();
+        let k = b"k";
+
+        must_acquire_pessimistic_lock(engine.as_ref(), k, k, 5, 5);
+        // A cleanup of the expired lock rolls the transaction back.
+        must_rollback(engine.as_ref(), k, 5);
+        must_unlocked(engine.as_ref(), k);
+        // The rolled back transaction must not lock the key again ...
+        must_acquire_pessimistic_lock_err(engine.as_ref(), k, k, 5, 10);
+        // ... and cannot commit either.
+        must_commit_err(engine.as_ref(), k, 5, 10);
+    }
+
     #[test]
     fn test_mvcc_txn_rollback_before_prewrite() {
         let engine = engine::new_local_engine(TEMP_DIR, ALL_CFS).unwrap();
@@ -903,6 +1187,55 @@ mod tests {
         assert!(txn.commit(&make_key(key), commit_ts).is_err());
     }
 
+    fn must_acquire_pessimistic_lock(
+        engine: &Engine,
+        key: &[u8],
+        pk: &[u8],
+        start_ts: u64,
+        for_update_ts: u64,
+    ) {
+        let ctx = Context::new();
+        let snapshot = engine.snapshot(&ctx).unwrap();
+        let mut txn = MvccTxn::new(snapshot, start_ts, None, IsolationLevel::SI, true);
+        txn.acquire_pessimistic_lock(make_key(key), pk, for_update_ts, &Options::default())
+            .unwrap();
+        write(engine, &ctx, txn.into_modifies());
+    }
+
+    fn must_acquire_pessimistic_lock_err(
+        engine: &Engine,
+        key: &[u8],
+        pk: &[u8],
+        start_ts: u64,
+        for_update_ts: u64,
+    ) {
+        let ctx = Context::new();
+        let snapshot = engine.snapshot(&ctx).unwrap();
+        let mut txn = MvccTxn::new(snapshot, start_ts, None, IsolationLevel::SI, true);
+        assert!(
+            txn.acquire_pessimistic_lock(make_key(key), pk, for_update_ts, &Options::default())
+                .is_err()
+        );
+    }
+
+    fn must_pessimistic_rollback(engine: &Engine, key: &[u8], start_ts: u64, for_update_ts: u64) {
+        let ctx = Context::new();
+        let snapshot = engine.snapshot(&ctx).unwrap();
+        let mut txn = MvccTxn::new(snapshot, start_ts, None, IsolationLevel::SI, true);
+        txn.pessimistic_rollback(&make_key(key), for_update_ts)
+            .unwrap();
+        write(engine, &ctx, txn.into_modifies());
+    }
+
+    fn must_pessimistic_locked(engine: &Engine, key: &[u8], start_ts: u64, for_update_ts: u64) {
+        let snapshot = engine.snapshot(&Context::new()).unwrap();
+        let mut reader = MvccReader::new(snapshot, None, true, None, None, IsolationLevel::SI);
+        let lock = reader.load_lock(&make_key(key)).unwrap().unwrap();
+        assert_eq!(lock.lock_type, LockType::Pessimistic);
+        assert_eq!(lock.ts, start_ts);
+        assert_eq!(lock.for_update_ts, for_update_ts);
+    }
+
     fn must_rollback(engine: &Engine, key: &[u8], start_ts: u64) {
         let ctx = Context::new();
         let snapshot = engine.snapshot(&ctx).unwrap();
@@ -37,6 +37,9 @@ impl WriteType {
             LockType::Put => WriteType::Put,
             LockType::Delete => WriteType::Delete,
             LockType::Lock => WriteType::Lock,
+            // Commit never sees a pessimistic lock, prewrite has
+            // replaced it with an ordinary one before.
+            LockType::Pessimistic => panic!("pessimistic lock cannot be committed"),
         }
     }
 
@@ -0,0 +1,136 @@
+// Copyright 2018 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Tracks lock contention per key digest.
+//!
+//! The scheduler reports `KeyIsLocked` conflicts and latch waits here,
+//! keyed by a crc32 digest of the contended key. A bounded table keeps
+//! counters and a sampled key prefix per digest, so hot-row contention
+//! can be identified without packet captures. The hottest entries are
+//! queried with `top_contended`; serving them over the debug service
+//! needs a new RPC in the debugpb protocol, which lives in kvproto.
+
+use std::cmp;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use util::collections::HashMap;
+
+// How many digests are tracked. Conflicts on further keys still show up
+// in the aggregate metrics, just without a per-digest breakdown.
+const TRACKED_DIGESTS: usize = 1024;
+// How many bytes of the sampled key are kept.
+const PREFIX_LEN: usize = 32;
+
+/// Contention counters of one key digest.
+#[derive(Clone, Debug)]
+pub struct ContentionStat {
+    pub digest: u32,
+    /// A truncated sample of one contended key.
+    pub key_prefix: Vec<u8>,
+    /// `KeyIsLocked` conflicts observed.
+    pub conflicts: u64,
+    /// Commands that had to wait for a latch.
+    pub latch_waits: u64,
+    /// Total time those commands spent waiting.
+    pub total_wait: Duration,
+}
+
+impl ContentionStat {
+    fn new(digest: u32) -> ContentionStat {
+        ContentionStat {
+            digest: digest,
+            key_prefix: vec![],
+            conflicts: 0,
+            latch_waits: 0,
+            total_wait: Duration::new(0, 0),
+        }
+    }
+}
+
+lazy_static! {
+    static ref STATS: Mutex<HashMap<u32, ContentionStat>> = Mutex::new(HashMap::default());
+}
+
+fn with_stat<F: FnOnce(&mut ContentionStat)>(digest: u32, key: &[u8], f: F) {
+    let mut stats = STATS.lock().unwrap();
+    if !stats.contains_key(&digest) && stats.len() >= TRACKED_DIGESTS {
+        // The table is full, only known digests keep counting.
+        return;
+    }
+    let stat = stats
+        .entry(digest)
+        .or_insert_with(|| ContentionStat::new(digest));
+    if stat.key_prefix.is_empty() && !key.is_empty() {
+        stat.key_prefix = key[..cmp::min(key.len(), PREFIX_LEN)].to_vec();
+    }
+    f(stat);
+}
+
+/// Records a `KeyIsLocked` conflict on `key`.
+pub fn record_conflict(digest: u32, key: &[u8]) {
+    with_stat(digest, key, |stat| stat.conflicts += 1);
+}
+
+/// Records a command that waited `wait` for a latch.
+pub fn record_latch_wait(digest: u32, key: &[u8], wait: Duration) {
+    with_stat(digest, key, |stat| {
+        stat.latch_waits += 1;
+        stat.total_wait += wait;
+    });
+}
+
+/// The `n` most contended digests, most conflicts first; latch waits
+/// break ties.
+pub fn top_contended(n: usize) -> Vec<ContentionStat> {
+    let stats = STATS.lock().unwrap();
+    let mut all: Vec<_> = stats.values().cloned().collect();
+    all.sort_by(|a, b| (b.conflicts, b.latch_waits).cmp(&(a.conflicts, a.latch_waits)));
+    all.truncate(n);
+    all
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::Duration;
+
+    #[test]
+    fn test_contention_tracker() {
+        // The table is process global, so filter down to the digests
+        // this test uses in case other tests recorded events too.
+        record_conflict(1, b"hot_row_1");
+        record_conflict(1, b"hot_row_1");
+        record_conflict(2, b"hot_row_2");
+        record_latch_wait(1, b"", Duration::from_millis(30));
+        record_latch_wait(3, b"waited_row", Duration::from_millis(5));
+
+        let top: Vec<_> = top_contended(TRACKED_DIGESTS)
+            .into_iter()
+            .filter(|s| s.digest <= 3)
+            .collect();
+        assert_eq!(top.len(), 3);
+        assert_eq!(top[0].digest, 1);
+        assert_eq!(top[0].conflicts, 2);
+        assert_eq!(top[0].latch_waits, 1);
+        assert_eq!(top[0].total_wait, Duration::from_millis(30));
+        // The prefix comes from the first event that carried a key.
+        assert_eq!(top[0].key_prefix, b"hot_row_1");
+        assert_eq!(top[1].digest, 2);
+        // An entry created by a latch wait has no conflicts yet.
+        assert_eq!(top[2].digest, 3);
+        assert_eq!(top[2].conflicts, 0);
+        assert_eq!(top[2].key_prefix, b"waited_row");
+    }
+}
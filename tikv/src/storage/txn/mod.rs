@@ -14,11 +14,14 @@
 mod store;
 mod scheduler;
 mod latch;
+mod waiter;
+pub mod contention;
 
 use std::error;
 use std::io::Error as IoError;
 
-pub use self::scheduler::{Msg, Scheduler, CMD_BATCH_SIZE, GC_BATCH_SIZE, RESOLVE_LOCK_BATCH_SIZE};
+pub use self::scheduler::{Msg, Scheduler, CMD_BATCH_SIZE, GC_BATCH_SIZE,
+                          LOCK_WAIT_CHECK_INTERVAL_MS, RESOLVE_LOCK_BATCH_SIZE};
 pub use self::store::{SnapshotStore, StoreScanner};
 
 quick_error! {
@@ -61,6 +64,16 @@ quick_error! {
                         start_ts,
                         commit_ts)
         }
+        // The kvproto error carries no deadlock field yet, the client
+        // sees this through the generic error string and aborts the
+        // transaction like any other fatal error.
+        Deadlock {start_ts: u64, lock_ts: u64, key: Vec<u8>} {
+            description("deadlock among lock waiters")
+            display("deadlock: txn:{} waiting for lock of txn:{} on key:{:?}",
+                        start_ts,
+                        lock_ts,
+                        key)
+        }
     }
 }
 
@@ -77,6 +90,15 @@ impl Error {
                 start_ts: start_ts,
                 commit_ts: commit_ts,
             }),
+            Error::Deadlock {
+                start_ts,
+                lock_ts,
+                ref key,
+            } => Some(Error::Deadlock {
+                start_ts: start_ts,
+                lock_ts: lock_ts,
+                key: key.clone(),
+            }),
             Error::Other(_) | Error::ProtoBuf(_) | Error::Io(_) => None,
         }
     }
@@ -38,37 +38,56 @@ use std::hash::{Hash, Hasher};
 use std::u64;
 use std::mem;
 
+use crc::crc32;
 use prometheus::HistogramTimer;
 use prometheus::local::{LocalCounter, LocalHistogramVec};
 use kvproto::kvrpcpb::{CommandPri, Context, LockInfo};
+use kvproto::metapb::RegionEpoch;
 
-use storage::{Command, Engine, Error as StorageError, Result as StorageResult, ScanMode, Snapshot,
-              Statistics, StatisticsSummary, StorageCb};
+use pd::PdTask;
+use storage::{Command, Engine, Error as StorageError, FlowStatistics, Result as StorageResult,
+              ScanMode, Snapshot, Statistics, StatisticsSummary, StorageCb};
 use storage::mvcc::{Error as MvccError, Lock as MvccLock, MvccReader, MvccTxn, Write, WriteType,
                     MAX_TXN_WRITE_SIZE};
-use storage::{Key, KvPair, MvccInfo, Value, CMD_TAG_GC};
-use storage::engine::{self, Callback as EngineCallback, CbContext, Error as EngineError, Modify,
-                      Result as EngineResult};
+use storage::{CfName, Key, KvPair, MvccInfo, Value, CF_DEFAULT, CMD_TAG_GC};
+use storage::engine::{self, Callback as EngineCallback, CbContext, Cursor, Error as EngineError,
+                      Modify, PerfStatistics, Result as EngineResult};
 use raftstore::store::engine::IterOption;
-use util::threadpool::{Context as ThreadContext, ThreadPool, ThreadPoolBuilder};
-use util::time::SlowTimer;
+use util::threadpool::{Context as ThreadContext, ContextFactory, ThreadPool, ThreadPoolBuilder};
+use util::slow_log::Subsystem;
+use util::admission;
+use util::codec::number::{self, NumberDecoder, NumberEncoder};
+use util::time::{Instant, SlowTimer};
+use util::timer::Timer;
 use util::collections::HashMap;
-use util::worker::{self, Runnable, ScheduleError};
+use util::worker::{self, FutureScheduler, Runnable, RunnableWithTimer, ScheduleError};
 
 use super::Result;
 use super::Error;
+use super::contention;
 use super::store::SnapshotStore;
 use super::latch::{Latches, Lock};
+use super::waiter::{Waiter, WaiterManager};
 use super::super::metrics::*;
 
 pub const CMD_BATCH_SIZE: usize = 256;
 // TODO: make it configurable.
 pub const GC_BATCH_SIZE: usize = 512;
 
+// How long a snapshot held for an announced follow-up lookup stays
+// reusable. The follow-up is expected right after the scan returns,
+// anything older likely belongs to an abandoned request.
+const HELD_SNAPSHOT_TTL_MS: u64 = 1_000;
+
 // To resolve a key, the write size is about 100~150 bytes, depending on key and value length.
-// The write batch will be around 32KB if we scan 256 keys each time.
+// The write batch will be around 32KB if we scan 256 keys each time. This is
+// only the default, `storage.resolve-lock-batch-size` overrides it.
 pub const RESOLVE_LOCK_BATCH_SIZE: usize = 256;
 
+// How often the scheduler sweeps the parked commands for waits that
+// outlived `storage.wait-for-lock-timeout`.
+pub const LOCK_WAIT_CHECK_INTERVAL_MS: u64 = 100;
+
 /// Process result of a command.
 pub enum ProcessResult {
     Res,
@@ -84,6 +103,10 @@ pub enum ProcessResult {
 
 type SnapshotResult = (Vec<u64>, CbContext, EngineResult<Box<Snapshot>>);
 
+/// A write command that finished its prepare phase and is waiting for the
+/// engine write: (cid, cmd, process result, modifies, rows).
+type WritePrepared = (u64, Command, ProcessResult, Vec<Modify>, usize);
+
 /// Message types for the scheduler event loop.
 pub enum Msg {
     Quit,
@@ -179,43 +202,131 @@ impl Display for Msg {
 fn execute_callback(callback: StorageCb, pr: ProcessResult) {
     match callback {
         StorageCb::Boolean(cb) => match pr {
-            ProcessResult::Res => cb(Ok(())),
-            ProcessResult::Failed { err } => cb(Err(err)),
+            ProcessResult::Res => cb.call(Ok(())),
+            ProcessResult::Failed { err } => cb.call(Err(err)),
             _ => panic!("process result mismatch"),
         },
         StorageCb::Booleans(cb) => match pr {
-            ProcessResult::MultiRes { results } => cb(Ok(results)),
-            ProcessResult::Failed { err } => cb(Err(err)),
+            ProcessResult::MultiRes { results } => cb.call(Ok(results)),
+            ProcessResult::Failed { err } => cb.call(Err(err)),
             _ => panic!("process result mismatch"),
         },
         StorageCb::SingleValue(cb) => match pr {
-            ProcessResult::Value { value } => cb(Ok(value)),
-            ProcessResult::Failed { err } => cb(Err(err)),
+            ProcessResult::Value { value } => cb.call(Ok(value)),
+            ProcessResult::Failed { err } => cb.call(Err(err)),
             _ => panic!("process result mismatch"),
         },
         StorageCb::KvPairs(cb) => match pr {
-            ProcessResult::MultiKvpairs { pairs } => cb(Ok(pairs)),
-            ProcessResult::Failed { err } => cb(Err(err)),
+            ProcessResult::MultiKvpairs { pairs } => cb.call(Ok(pairs)),
+            ProcessResult::Failed { err } => cb.call(Err(err)),
             _ => panic!("process result mismatch"),
         },
         StorageCb::MvccInfoByKey(cb) => match pr {
-            ProcessResult::MvccKey { mvcc } => cb(Ok(mvcc)),
-            ProcessResult::Failed { err } => cb(Err(err)),
+            ProcessResult::MvccKey { mvcc } => cb.call(Ok(mvcc)),
+            ProcessResult::Failed { err } => cb.call(Err(err)),
             _ => panic!("process result mismatch"),
         },
         StorageCb::MvccInfoByStartTs(cb) => match pr {
-            ProcessResult::MvccStartTs { mvcc } => cb(Ok(mvcc)),
-            ProcessResult::Failed { err } => cb(Err(err)),
+            ProcessResult::MvccStartTs { mvcc } => cb.call(Ok(mvcc)),
+            ProcessResult::Failed { err } => cb.call(Err(err)),
             _ => panic!("process result mismatch"),
         },
         StorageCb::Locks(cb) => match pr {
-            ProcessResult::Locks { locks } => cb(Ok(locks)),
-            ProcessResult::Failed { err } => cb(Err(err)),
+            ProcessResult::Locks { locks } => cb.call(Ok(locks)),
+            ProcessResult::Failed { err } => cb.call(Err(err)),
             _ => panic!("process result mismatch"),
         },
     }
 }
 
+/// Records a `KeyIsLocked` conflict for contention tracking.
+fn track_lock_conflict(err: &StorageError) {
+    if let StorageError::Txn(Error::Mvcc(MvccError::KeyIsLocked { ref key, .. })) = *err {
+        SCHED_LOCK_CONFLICT_COUNTER.inc();
+        contention::record_conflict(crc32::checksum_ieee(key), key);
+    }
+}
+
+/// Scans a process result for `KeyIsLocked` conflicts and records each of them.
+fn track_lock_conflicts(pr: &ProcessResult) {
+    match *pr {
+        ProcessResult::Failed { ref err } => track_lock_conflict(err),
+        ProcessResult::MultiRes { ref results } => for res in results {
+            if let Err(ref e) = *res {
+                track_lock_conflict(e);
+            }
+        },
+        ProcessResult::MultiKvpairs { ref pairs } => for pair in pairs {
+            if let Err(ref e) = *pair {
+                track_lock_conflict(e);
+            }
+        },
+        _ => {}
+    }
+}
+
+/// When a write command got stopped by locks and nothing else, extracts
+/// the transaction to wait for: (lock_ts, key). A command that ran into
+/// locks of several transactions waits for the first one; the retry
+/// after waking meets the next lock and parks again.
+fn extract_lock_wait(cmd: &Command, pr: &ProcessResult) -> Option<(u64, Vec<u8>)> {
+    match *cmd {
+        Command::Prewrite { .. } | Command::AcquirePessimisticLock { .. } => {}
+        _ => return None,
+    }
+    if let ProcessResult::MultiRes { ref results } = *pr {
+        for res in results {
+            if let Err(StorageError::Txn(Error::Mvcc(MvccError::KeyIsLocked {
+                ts, ref key, ..
+            }))) = *res
+            {
+                return Some((ts, key.clone()));
+            }
+        }
+    }
+    None
+}
+
+/// Lifecycle timestamps of a command, used to break down where a slow
+/// command spent its time.
+struct CmdTrace {
+    enqueued: Instant,
+    latched: Option<Instant>,
+    snapshot: Option<Instant>,
+    write_finished: Option<Instant>,
+}
+
+impl CmdTrace {
+    fn new() -> CmdTrace {
+        CmdTrace {
+            enqueued: Instant::now_coarse(),
+            latched: None,
+            snapshot: None,
+            write_finished: None,
+        }
+    }
+}
+
+impl Display for CmdTrace {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let mut last = self.enqueued;
+        for &(stage, t) in &[
+            ("latch", self.latched),
+            ("snapshot", self.snapshot),
+            ("write", self.write_finished),
+        ] {
+            match t {
+                Some(t) => {
+                    write!(f, "{}: {:?}, ", stage, t.duration_since(last))?;
+                    last = t;
+                }
+                None => write!(f, "{}: -, ", stage)?,
+            }
+        }
+        write!(f, "finish: {:?}", last.elapsed())
+    }
+}
+
 /// Context for a running command.
 pub struct RunningCtx {
     cid: u64,
@@ -226,9 +337,12 @@ pub struct RunningCtx {
     tag: &'static str,
     ts: u64,
     region_id: u64,
+    key_digest: u32,
     latch_timer: Option<HistogramTimer>,
     _timer: HistogramTimer,
-    slow_timer: Option<SlowTimer>,
+    slow_timer: SlowTimer,
+    trace: CmdTrace,
+    latch_wait_from: Option<Instant>,
 }
 
 impl RunningCtx {
@@ -238,6 +352,18 @@ impl RunningCtx {
         let ts = cmd.ts();
         let region_id = cmd.get_context().get_region_id();
         let write_bytes = cmd.write_bytes();
+        let key_digest = cmd.key_digest();
+        // Read-only commands never wait for latches, a latch timer
+        // would only drag the histogram towards zero.
+        let latch_timer = if cmd.readonly() {
+            None
+        } else {
+            Some(
+                SCHED_LATCH_HISTOGRAM_VEC
+                    .with_label_values(&[tag])
+                    .start_coarse_timer(),
+            )
+        };
         RunningCtx {
             cid: cid,
             cmd: Some(cmd),
@@ -247,30 +373,29 @@ impl RunningCtx {
             tag: tag,
             ts: ts,
             region_id: region_id,
-            latch_timer: Some(
-                SCHED_LATCH_HISTOGRAM_VEC
-                    .with_label_values(&[tag])
-                    .start_coarse_timer(),
-            ),
+            key_digest: key_digest,
+            latch_timer: latch_timer,
             _timer: SCHED_HISTOGRAM_VEC
                 .with_label_values(&[tag])
                 .start_coarse_timer(),
-            slow_timer: None,
+            slow_timer: SlowTimer::for_subsystem(Subsystem::Scheduler),
+            trace: CmdTrace::new(),
+            latch_wait_from: None,
         }
     }
 }
 
 impl Drop for RunningCtx {
     fn drop(&mut self) {
-        if let Some(ref mut timer) = self.slow_timer {
-            slow_log!(
-                timer,
-                "[region {}] scheduler handle command: {}, ts: {}",
-                self.region_id,
-                self.tag,
-                self.ts
-            );
-        }
+        slow_log!(
+            self.slow_timer,
+            "[region {}] scheduler handle command: {}, ts: {}, key digest: {:x}, {}",
+            self.region_id,
+            self.tag,
+            self.ts,
+            self.key_digest,
+            self.trace
+        );
     }
 }
 
@@ -305,6 +430,56 @@ fn make_engine_cb(
     })
 }
 
+/// Makes an engine callback that fans the result of one merged engine
+/// write out to every command it carries: (tag, cid, pr, rows) each.
+fn make_batch_engine_cb(
+    batch: Vec<(&'static str, u64, ProcessResult, usize)>,
+    scheduler: worker::Scheduler<Msg>,
+) -> EngineCallback<()> {
+    Box::new(move |(cb_ctx, result): (CbContext, EngineResult<()>)| {
+        for (cmd, cid, pr, rows) in batch {
+            let result = match result {
+                Ok(()) => Ok(()),
+                Err(ref e) => Err(e.maybe_clone()
+                    .unwrap_or_else(|| EngineError::Other(box_err!("{:?}", e)))),
+            };
+            match scheduler.schedule(Msg::WriteFinished {
+                cid: cid,
+                pr: pr,
+                cb_ctx: cb_ctx.clone(),
+                result: result,
+            }) {
+                Ok(_) => {
+                    KV_COMMAND_KEYWRITE_HISTOGRAM_VEC
+                        .with_label_values(&[cmd])
+                        .observe(rows as f64);
+                }
+                e @ Err(ScheduleError::Stopped(_)) => info!("scheduler worker stopped, {:?}", e),
+                Err(e) => {
+                    panic!(
+                        "schedule WriteFinished msg failed, cid={}, err:{:?}",
+                        cid, e
+                    );
+                }
+            }
+        }
+    })
+}
+
+/// Whether a command's engine write may be merged with others of the same
+/// region. Only the small single purpose commands take part: a storm of
+/// commits or rollbacks of distinct transactions is where merging pays
+/// off, and keeping prewrites out keeps the merged proposals small.
+fn mergeable_write(cmd: &Command) -> bool {
+    match *cmd {
+        Command::Commit { .. }
+        | Command::Rollback { .. }
+        | Command::PessimisticRollback { .. }
+        | Command::Cleanup { .. } => true,
+        _ => false,
+    }
+}
+
 #[derive(Clone)]
 struct HashableContext(Context);
 
@@ -333,12 +508,28 @@ impl Hash for HashableContext {
 
 impl Eq for HashableContext {}
 
+/// A snapshot a `Scan` asked to hold for a follow-up `BatchGet`.
+///
+/// Reusing it spares the follow-up a raft read index round. The reuse
+/// is only offered when the region epoch and the `start_ts` match the
+/// scan exactly, so the lookup observes precisely the data the scan
+/// observed.
+struct HeldSnapshot {
+    snapshot: Box<Snapshot>,
+    cb_ctx: CbContext,
+    epoch: RegionEpoch,
+    start_ts: u64,
+    held_at: Instant,
+}
+
 /// Scheduler which schedules the execution of `storage::Command`s.
 pub struct Scheduler {
     engine: Box<Engine>,
 
     // cid -> RunningCtx
     cmd_ctxs: HashMap<u64, RunningCtx>,
+    // region id -> snapshot held for a follow-up lookup
+    held_snapshots: HashMap<u64, HeldSnapshot>,
     // Context -> cids
     grouped_cmds: Option<HashMap<HashableContext, Vec<u64>>>,
 
@@ -351,6 +542,9 @@ pub struct Scheduler {
     // write concurrency control
     latches: Latches,
 
+    // commands parked until a conflicting lock is released
+    waiters: WaiterManager,
+
     // TODO: Dynamically calculate this value according to processing
     // speed of recent write requests.
     sched_pending_write_threshold: usize,
@@ -412,10 +606,20 @@ impl Scheduler {
         concurrency: usize,
         worker_pool_size: usize,
         sched_pending_write_threshold: usize,
+        resolve_lock_batch_size: usize,
+        read_flow_sender: Option<FutureScheduler<PdTask>>,
+        enable_perf_context: bool,
+        wait_for_lock_timeout: u64,
     ) -> Scheduler {
+        let factory = SchedContextFactory {
+            resolve_lock_batch_size: resolve_lock_batch_size,
+            read_flow_sender: read_flow_sender,
+            perf_context: enable_perf_context,
+        };
         Scheduler {
             engine: engine,
             cmd_ctxs: Default::default(),
+            held_snapshots: Default::default(),
             grouped_cmds: Some(HashMap::with_capacity_and_hasher(
                 CMD_BATCH_SIZE,
                 Default::default(),
@@ -423,13 +627,13 @@ impl Scheduler {
             scheduler: scheduler,
             id_alloc: 0,
             latches: Latches::new(concurrency),
+            waiters: WaiterManager::new(wait_for_lock_timeout),
             sched_pending_write_threshold: sched_pending_write_threshold,
-            worker_pool: ThreadPoolBuilder::with_default_factory(thd_name!("sched-worker-pool"))
+            worker_pool: ThreadPoolBuilder::new(thd_name!("sched-worker-pool"), factory.clone())
                 .thread_count(worker_pool_size)
                 .build(),
-            high_priority_pool: ThreadPoolBuilder::with_default_factory(thd_name!(
-                "sched-high-pri-pool"
-            )).build(),
+            high_priority_pool: ThreadPoolBuilder::new(thd_name!("sched-high-pri-pool"), factory)
+                .build(),
             has_gc_command: false,
             running_write_bytes: 0,
         }
@@ -512,10 +716,12 @@ fn process_read(
                 },
             }
         }
-        // Scans a range starting with `start_key` up to `limit` rows from the snapshot.
+        // Scans up to `limit` rows from the snapshot, starting with `start_key`
+        // and moving backward instead when the reverse option is set.
         Command::Scan {
             ref ctx,
             ref start_key,
+            ref end_key,
             limit,
             start_ts,
             ref options,
@@ -527,10 +733,27 @@ fn process_read(
                 ctx.get_isolation_level(),
                 !ctx.get_not_fill_cache(),
             );
+            let scan_mode = if options.reverse {
+                ScanMode::Backward
+            } else {
+                ScanMode::Forward
+            };
+            // The end key bounds the iterator itself, a reverse scan
+            // walks down to it, a forward scan stops before it.
+            let bound = end_key.as_ref().map(|k| k.encoded().to_vec());
+            let (lower_bound, upper_bound) = if options.reverse {
+                (bound, None)
+            } else {
+                (None, bound)
+            };
             let res = snap_store
-                .scanner(ScanMode::Forward, options.key_only, None, None)
+                .scanner(scan_mode, options.key_only, lower_bound, upper_bound)
                 .and_then(|mut scanner| {
-                    let res = scanner.scan(start_key.clone(), limit);
+                    let res = if options.reverse {
+                        scanner.reverse_scan(start_key.clone(), limit)
+                    } else {
+                        scanner.scan(start_key.clone(), limit)
+                    };
                     statistics.add(scanner.get_statistics());
                     res
                 })
@@ -661,7 +884,7 @@ fn process_read(
                 .scan_lock(
                     scan_key.take(),
                     |lock| txn_status.contains_key(&lock.ts),
-                    RESOLVE_LOCK_BATCH_SIZE,
+                    sched_ctx.resolve_lock_batch_size,
                 )
                 .map_err(Error::from)
                 .and_then(|(v, next_scan_key)| {
@@ -748,23 +971,50 @@ fn process_read(
                 Err(e) => ProcessResult::Failed { err: e.into() },
             }
         }
-        Command::RawGet { ref key, .. } => {
+        Command::RawGet { cf, ref key, .. } => {
             sched_ctx
                 .command_keyread_duration
                 .with_label_values(&[tag])
                 .observe(1f64);
-            match snapshot.get(key) {
+            match snapshot.get_cf(cf, key) {
                 Ok(val) => ProcessResult::Value { value: val },
                 Err(e) => ProcessResult::Failed {
                     err: StorageError::from(e),
                 },
             }
         }
+        Command::RawBatchGet { cf, ref keys, .. } => {
+            sched_ctx
+                .command_keyread_duration
+                .with_label_values(&[tag])
+                .observe(keys.len() as f64);
+            statistics.mut_cf_statistics(cf).get += keys.len();
+            let mut pairs = Vec::with_capacity(keys.len());
+            let mut err = None;
+            for key in keys {
+                match snapshot.get_cf(cf, key) {
+                    Ok(Some(value)) => pairs.push(Ok((key.encoded().to_vec(), value))),
+                    Ok(None) => {}
+                    Err(e) => {
+                        err = Some(e);
+                        break;
+                    }
+                }
+            }
+            match err {
+                None => ProcessResult::MultiKvpairs { pairs: pairs },
+                Some(e) => ProcessResult::Failed {
+                    err: StorageError::from(e),
+                },
+            }
+        }
         Command::RawScan {
+            cf,
             ref start_key,
             limit,
+            ref options,
             ..
-        } => match process_rawscan(snapshot, start_key, limit, &mut statistics) {
+        } => match process_rawscan(snapshot, cf, start_key, limit, options, &mut statistics) {
             Ok(val) => ProcessResult::MultiKvpairs { pairs: val },
             Err(e) => ProcessResult::Failed {
                 err: StorageError::from(e),
@@ -786,18 +1036,44 @@ fn process_read(
 
 fn process_rawscan(
     snapshot: Box<Snapshot>,
+    cf: CfName,
     start_key: &Key,
     limit: usize,
+    options: &Options,
     stats: &mut Statistics,
 ) -> Result<Vec<StorageResult<KvPair>>> {
-    let mut cursor = snapshot.iter(IterOption::default(), ScanMode::Forward)?;
-    if !cursor.seek(start_key, &mut stats.data)? {
-        return Ok(vec![]);
-    }
+    // With key_only set the values stay inside RocksDB, the pairs carry
+    // empty values.
+    let read_pair = |cursor: &Cursor, key_only: bool| {
+        let value = if key_only {
+            vec![]
+        } else {
+            cursor.value().to_owned()
+        };
+        Ok((cursor.key().to_owned(), value))
+    };
+    let stats = stats.mut_cf_statistics(cf);
     let mut pairs = vec![];
-    while cursor.valid() && pairs.len() < limit {
-        pairs.push(Ok((cursor.key().to_owned(), cursor.value().to_owned())));
-        cursor.next(&mut stats.data);
+    if options.reverse {
+        let mut cursor = snapshot.iter_cf(cf, IterOption::default(), ScanMode::Backward)?;
+        // A reverse scan starts just below `start_key` and walks toward
+        // smaller keys.
+        if !cursor.reverse_seek(start_key, stats)? {
+            return Ok(vec![]);
+        }
+        while cursor.valid() && pairs.len() < limit {
+            pairs.push(read_pair(&cursor, options.key_only));
+            cursor.prev(stats);
+        }
+    } else {
+        let mut cursor = snapshot.iter_cf(cf, IterOption::default(), ScanMode::Forward)?;
+        if !cursor.seek(start_key, stats)? {
+            return Ok(vec![]);
+        }
+        while cursor.valid() && pairs.len() < limit {
+            pairs.push(read_pair(&cursor, options.key_only));
+            cursor.next(stats);
+        }
     }
     Ok(pairs)
 }
@@ -870,6 +1146,45 @@ fn process_write_impl(
                 (pr, vec![], 0)
             }
         }
+        Command::AcquirePessimisticLock {
+            ref ctx,
+            ref keys,
+            ref primary,
+            start_ts,
+            for_update_ts,
+            ref options,
+            ..
+        } => {
+            let mut txn = MvccTxn::new(
+                snapshot,
+                start_ts,
+                None,
+                ctx.get_isolation_level(),
+                !ctx.get_not_fill_cache(),
+            );
+            let mut locks = vec![];
+            let rows = keys.len();
+            for k in keys {
+                match txn.acquire_pessimistic_lock(k.clone(), primary, for_update_ts, options) {
+                    Ok(_) => {}
+                    e @ Err(MvccError::KeyIsLocked { .. }) => {
+                        locks.push(e.map_err(Error::from).map_err(StorageError::from));
+                    }
+                    Err(e) => return Err(Error::from(e)),
+                }
+            }
+
+            statistics.add(txn.get_statistics());
+            if locks.is_empty() {
+                let pr = ProcessResult::MultiRes { results: vec![] };
+                let modifies = txn.into_modifies();
+                (pr, modifies, rows)
+            } else {
+                // Skip write stage if some keys are locked.
+                let pr = ProcessResult::MultiRes { results: locks };
+                (pr, vec![], 0)
+            }
+        }
         Command::Commit {
             ref ctx,
             ref keys,
@@ -937,6 +1252,27 @@ fn process_write_impl(
             statistics.add(txn.get_statistics());
             (ProcessResult::Res, txn.into_modifies(), rows)
         }
+        Command::PessimisticRollback {
+            ref ctx,
+            ref keys,
+            start_ts,
+            for_update_ts,
+        } => {
+            let mut txn = MvccTxn::new(
+                snapshot,
+                start_ts,
+                None,
+                ctx.get_isolation_level(),
+                !ctx.get_not_fill_cache(),
+            );
+            let rows = keys.len();
+            for k in keys {
+                txn.pessimistic_rollback(k, for_update_ts)?;
+            }
+
+            statistics.add(txn.get_statistics());
+            (ProcessResult::Res, txn.into_modifies(), rows)
+        }
         Command::ResolveLock {
             ref ctx,
             ref mut txn_status,
@@ -1035,6 +1371,50 @@ fn process_write_impl(
             };
             (pr, txn.into_modifies(), rows)
         }
+        Command::RawIncr { ref key, delta, .. } => {
+            statistics.data.get += 1;
+            let cur = match snapshot.get(key)? {
+                Some(ref value) if value.len() == number::I64_SIZE => {
+                    value.as_slice().decode_i64_le()?
+                }
+                Some(ref value) => {
+                    return Err(box_err!(
+                        "raw value of {} bytes is not an i64 counter",
+                        value.len()
+                    ));
+                }
+                // A missing counter starts from zero.
+                None => 0,
+            };
+            let new = match cur.checked_add(delta) {
+                Some(v) => v,
+                None => return Err(box_err!("incr {} by {} overflows i64", cur, delta)),
+            };
+            let mut value = Vec::with_capacity(number::I64_SIZE);
+            value.encode_i64_le(new)?;
+            let pr = ProcessResult::Value {
+                value: Some(value.clone()),
+            };
+            (pr, vec![Modify::Put(CF_DEFAULT, key.clone(), value)], 1)
+        }
+        Command::RawCompareAndSwap {
+            ref key,
+            ref expected,
+            ref new_value,
+            ..
+        } => {
+            statistics.data.get += 1;
+            let prev = snapshot.get(key)?;
+            let pr = ProcessResult::Value { value: prev.clone() };
+            if prev == *expected {
+                let modify = Modify::Put(CF_DEFAULT, key.clone(), new_value.clone());
+                (pr, vec![modify], 1)
+            } else {
+                // The caller learns from the returned value that the
+                // swap did not happen.
+                (pr, vec![], 0)
+            }
+        }
         _ => panic!("unsupported write command"),
     };
 
@@ -1049,19 +1429,21 @@ fn process_write_impl(
     Ok(())
 }
 
-struct SchedContext {
-    stats: HashMap<&'static str, StatisticsSummary>,
-    processing_read_duration: LocalHistogramVec,
-    processing_write_duration: LocalHistogramVec,
-    command_keyread_duration: LocalHistogramVec,
-    command_gc_skipped_counter: LocalCounter,
-    command_gc_empty_range_counter: LocalCounter,
+#[derive(Clone)]
+struct SchedContextFactory {
+    resolve_lock_batch_size: usize,
+    read_flow_sender: Option<FutureScheduler<PdTask>>,
+    perf_context: bool,
 }
 
-impl Default for SchedContext {
-    fn default() -> SchedContext {
+impl ContextFactory<SchedContext> for SchedContextFactory {
+    fn create(&self) -> SchedContext {
         SchedContext {
+            resolve_lock_batch_size: self.resolve_lock_batch_size,
+            perf_context: self.perf_context,
             stats: HashMap::default(),
+            read_flow_stats: HashMap::default(),
+            read_flow_sender: self.read_flow_sender.clone(),
             processing_read_duration: SCHED_PROCESSING_READ_HISTOGRAM_VEC.local(),
             processing_write_duration: SCHED_PROCESSING_WRITE_HISTOGRAM_VEC.local(),
             command_keyread_duration: KV_COMMAND_KEYREAD_HISTOGRAM_VEC.local(),
@@ -1071,11 +1453,33 @@ impl Default for SchedContext {
     }
 }
 
+struct SchedContext {
+    resolve_lock_batch_size: usize,
+    perf_context: bool,
+    stats: HashMap<&'static str, StatisticsSummary>,
+    read_flow_stats: HashMap<u64, FlowStatistics>,
+    read_flow_sender: Option<FutureScheduler<PdTask>>,
+    processing_read_duration: LocalHistogramVec,
+    processing_write_duration: LocalHistogramVec,
+    command_keyread_duration: LocalHistogramVec,
+    command_gc_skipped_counter: LocalCounter,
+    command_gc_empty_range_counter: LocalCounter,
+}
+
 impl SchedContext {
     fn add_statistics(&mut self, cmd_tag: &'static str, stat: &Statistics) {
         let entry = self.stats.entry(cmd_tag).or_insert_with(Default::default);
         entry.add_statistics(stat);
     }
+
+    fn add_flow_statistics(&mut self, region_id: u64, stat: &Statistics) {
+        if self.read_flow_sender.is_none() {
+            return;
+        }
+        let flow_stats = self.read_flow_stats.entry(region_id).or_default();
+        flow_stats.add(&stat.write.flow_stats);
+        flow_stats.add(&stat.data.flow_stats);
+    }
 }
 
 impl ThreadContext for SchedContext {
@@ -1090,6 +1494,17 @@ impl ThreadContext for SchedContext {
                 }
             }
         }
+        if let Some(ref sender) = self.read_flow_sender {
+            if !self.read_flow_stats.is_empty() {
+                let mut to_send_stats = HashMap::default();
+                mem::swap(&mut to_send_stats, &mut self.read_flow_stats);
+                if let Err(e) = sender.schedule(PdTask::ReadStats {
+                    read_stats: to_send_stats,
+                }) {
+                    error!("send storage read flow statistics: {:?}", e);
+                }
+            }
+        }
         self.processing_read_duration.flush();
         self.processing_write_duration.flush();
         self.command_keyread_duration.flush();
@@ -1118,6 +1533,7 @@ impl Scheduler {
         }
         SCHED_WRITING_BYTES_GAUGE.set(self.running_write_bytes as f64);
         SCHED_CONTEX_GAUGE.set(self.cmd_ctxs.len() as f64);
+        admission::report_sched_queue(self.cmd_ctxs.len());
     }
 
     fn remove_ctx(&mut self, cid: u64) -> RunningCtx {
@@ -1131,6 +1547,7 @@ impl Scheduler {
         }
         SCHED_WRITING_BYTES_GAUGE.set(self.running_write_bytes as f64);
         SCHED_CONTEX_GAUGE.set(self.cmd_ctxs.len() as f64);
+        admission::report_sched_queue(self.cmd_ctxs.len());
         ctx
     }
 
@@ -1158,6 +1575,7 @@ impl Scheduler {
         let mut cmd = {
             let ctx = &mut self.cmd_ctxs.get_mut(&cid).unwrap();
             assert_eq!(ctx.cid, cid);
+            ctx.trace.snapshot = Some(Instant::now_coarse());
             ctx.cmd.take().unwrap()
         };
         if let Some(term) = cb_ctx.term {
@@ -1166,6 +1584,7 @@ impl Scheduler {
         let readcmd = cmd.readonly();
         let worker_pool = self.fetch_worker_pool(cmd.priority());
         let tag = cmd.tag();
+        let region_id = cmd.get_context().get_region_id();
         let scheduler = self.scheduler.clone();
         if readcmd {
             worker_pool.execute(move |ctx: &mut SchedContext| {
@@ -1173,8 +1592,18 @@ impl Scheduler {
                     .with_label_values(&[tag])
                     .start_coarse_timer();
 
+                let perf = if ctx.perf_context {
+                    Some(PerfStatistics::start())
+                } else {
+                    None
+                };
                 let s = process_read(ctx, cid, cmd, scheduler, snapshot);
+                if let Some(perf) = perf {
+                    perf.delta()
+                        .report_to(&KV_COMMAND_ROCKSDB_PERF_COUNTER, tag);
+                }
                 ctx.add_statistics(tag, &s);
+                ctx.add_flow_statistics(region_id, &s);
             });
         } else {
             worker_pool.execute(move |ctx: &mut SchedContext| {
@@ -1231,6 +1660,14 @@ impl Scheduler {
             .inc();
         let cid = self.gen_id();
         debug!("received new command, cid={}, cmd={}", cid, cmd);
+        if cmd.readonly() {
+            // Read-only commands take no latches, skip the latch
+            // bookkeeping entirely and go straight for a snapshot.
+            let ctx = RunningCtx::new(cid, cmd, Lock::new(vec![]), callback);
+            self.insert_ctx(ctx);
+            self.register_get_snapshot(cid);
+            return;
+        }
         let lock = gen_command_lock(&self.latches, &cmd);
         let ctx = RunningCtx::new(cid, cmd, lock, callback);
         self.insert_ctx(ctx);
@@ -1239,6 +1676,10 @@ impl Scheduler {
 
     fn too_busy(&self) -> bool {
         fail_point!("txn_scheduler_busy", |_| true);
+        if admission::is_overloaded() {
+            admission::on_shed("sched");
+            return true;
+        }
         self.running_write_bytes >= self.sched_pending_write_threshold
     }
 
@@ -1281,7 +1722,13 @@ impl Scheduler {
         let ok = self.latches.acquire(&mut ctx.lock, cid);
         if ok {
             ctx.latch_timer.take();
-            ctx.slow_timer = Some(SlowTimer::new());
+            let now = Instant::now_coarse();
+            ctx.trace.latched = Some(now);
+            if let Some(from) = ctx.latch_wait_from.take() {
+                contention::record_latch_wait(ctx.key_digest, b"", now.duration_since(from));
+            }
+        } else if ctx.latch_wait_from.is_none() {
+            ctx.latch_wait_from = Some(Instant::now_coarse());
         }
         ok
     }
@@ -1421,6 +1868,7 @@ impl Scheduler {
                 SCHED_STAGE_COUNTER_VEC
                     .with_label_values(&[self.get_ctx_tag(cid), "snapshot_ok"])
                     .inc();
+                self.try_hold_snapshot(cid, cb_ctx.clone(), snapshot.clone());
                 self.process_by_worker(cid, cb_ctx.clone(), snapshot.clone());
             },
             Err(ref e) => {
@@ -1448,6 +1896,7 @@ impl Scheduler {
             .with_label_values(&[ctx.tag, "read_finish"])
             .inc();
         let cb = ctx.callback.take().unwrap();
+        track_lock_conflicts(&pr);
         if let ProcessResult::NextCommand { cmd } = pr {
             SCHED_STAGE_COUNTER_VEC
                 .with_label_values(&[ctx.tag, "next_cmd"])
@@ -1488,6 +1937,11 @@ impl Scheduler {
             .with_label_values(&[self.get_ctx_tag(cid), "write"])
             .inc();
         if to_be_write.is_empty() {
+            if self.waiters.enabled() {
+                if let Some((lock_ts, key)) = extract_lock_wait(&cmd, &pr) {
+                    return self.wait_for_lock(cid, cmd, pr, lock_ts, key);
+                }
+            }
             return self.on_write_finished(cid, pr, Ok(()));
         }
         let engine_cb = make_engine_cb(cmd.tag(), cid, pr, self.scheduler.clone(), rows);
@@ -1501,13 +1955,111 @@ impl Scheduler {
         }
     }
 
+    /// Event handler for the write prepares that finished in one batch.
+    ///
+    /// Commands that share a region are merged into a single engine
+    /// write, so that a storm of small commits costs one raft proposal
+    /// instead of one per command. Every command keeps its own process
+    /// result and callback; a group of one takes the ordinary path.
+    fn on_write_prepare_batch(&mut self, prepared: Vec<WritePrepared>) {
+        let mut groups: HashMap<HashableContext, Vec<WritePrepared>> = HashMap::default();
+        for p in prepared {
+            let ctx = HashableContext(p.1.get_context().clone());
+            groups.entry(ctx).or_insert_with(Vec::new).push(p);
+        }
+        for (ctx, mut group) in groups {
+            if group.len() == 1 {
+                let (cid, cmd, pr, to_be_write, rows) = group.pop().unwrap();
+                self.on_write_prepare_finished(cid, cmd, pr, to_be_write, rows);
+                continue;
+            }
+            let mut to_be_write = Vec::new();
+            let mut batch = Vec::with_capacity(group.len());
+            for (cid, cmd, pr, mut modifies, rows) in group {
+                SCHED_STAGE_COUNTER_VEC
+                    .with_label_values(&[self.get_ctx_tag(cid), "batch_write"])
+                    .inc();
+                to_be_write.append(&mut modifies);
+                batch.push((cmd.tag(), cid, pr, rows));
+            }
+            let cids: Vec<u64> = batch.iter().map(|w| w.1).collect();
+            let engine_cb = make_batch_engine_cb(batch, self.scheduler.clone());
+            if let Err(e) = self.engine.async_write(&ctx.0, to_be_write, engine_cb) {
+                for cid in cids {
+                    SCHED_STAGE_COUNTER_VEC
+                        .with_label_values(&[self.get_ctx_tag(cid), "async_write_err"])
+                        .inc();
+                    let err = e.maybe_clone()
+                        .unwrap_or_else(|| EngineError::Other(box_err!("{:?}", e)));
+                    self.finish_with_err(cid, Error::from(err));
+                }
+            }
+        }
+    }
+
+    /// Parks a command that ran into a lock until the owning transaction
+    /// finishes, unless parking would close a wait cycle among the
+    /// transactions parked here. The latches are released: the command
+    /// that releases the lock needs them, and the parked command starts
+    /// over through `schedule_command` when it is woken.
+    fn wait_for_lock(
+        &mut self,
+        cid: u64,
+        cmd: Command,
+        pr: ProcessResult,
+        lock_ts: u64,
+        key: Vec<u8>,
+    ) {
+        let start_ts = cmd.ts();
+        if self.waiters.would_deadlock(start_ts, lock_ts) {
+            SCHED_LOCK_WAIT_COUNTER_VEC
+                .with_label_values(&["deadlock"])
+                .inc();
+            let err = Error::Deadlock {
+                start_ts: start_ts,
+                lock_ts: lock_ts,
+                key: key,
+            };
+            return self.finish_with_err(cid, err);
+        }
+        SCHED_STAGE_COUNTER_VEC
+            .with_label_values(&[self.get_ctx_tag(cid), "lock_wait"])
+            .inc();
+        SCHED_LOCK_WAIT_COUNTER_VEC.with_label_values(&["wait"]).inc();
+        let mut ctx = self.remove_ctx(cid);
+        let cb = ctx.callback.take().unwrap();
+        self.waiters
+            .wait_for(Waiter::new(cmd, cb, pr, start_ts, lock_ts));
+        self.release_lock(&ctx.lock, cid);
+    }
+
+    /// Reschedules the commands parked on the locks of a transaction
+    /// that just finished.
+    fn wake_up_waiters(&mut self, lock_ts: u64) {
+        for waiter in self.waiters.take_ready(lock_ts) {
+            SCHED_LOCK_WAIT_COUNTER_VEC.with_label_values(&["wake"]).inc();
+            self.schedule_command(waiter.cmd, waiter.cb);
+        }
+    }
+
+    /// Reschedules every parked command, after a write that may have
+    /// released locks of many transactions at once.
+    fn wake_up_all_waiters(&mut self) {
+        for waiter in self.waiters.take_all() {
+            SCHED_LOCK_WAIT_COUNTER_VEC.with_label_values(&["wake"]).inc();
+            self.schedule_command(waiter.cmd, waiter.cb);
+        }
+    }
+
     /// Event handler for the success of write.
     fn on_write_finished(&mut self, cid: u64, pr: ProcessResult, result: EngineResult<()>) {
         SCHED_STAGE_COUNTER_VEC
             .with_label_values(&[self.get_ctx_tag(cid), "write_finish"])
             .inc();
         debug!("write finished for command, cid={}", cid);
+        let write_succeeded = result.is_ok();
         let mut ctx = self.remove_ctx(cid);
+        ctx.trace.write_finished = Some(Instant::now_coarse());
         let cb = ctx.callback.take().unwrap();
         let pr = match result {
             Ok(()) => pr,
@@ -1515,6 +2067,7 @@ impl Scheduler {
                 err: ::storage::Error::from(e),
             },
         };
+        track_lock_conflicts(&pr);
         if let ProcessResult::NextCommand { cmd } = pr {
             SCHED_STAGE_COUNTER_VEC
                 .with_label_values(&[ctx.tag, "next_cmd"])
@@ -1525,6 +2078,21 @@ impl Scheduler {
         }
 
         self.release_lock(&ctx.lock, cid);
+        if write_succeeded && !self.waiters.is_empty() {
+            match ctx.tag {
+                // `ts()` of a commit is its lock_ts, of the others the
+                // start_ts; either way the transaction whose locks the
+                // write removed.
+                "commit" | "rollback" | "cleanup" | "pessimistic_rollback" => {
+                    self.wake_up_waiters(ctx.ts)
+                }
+                // Resolve-lock cleans up many transactions and its
+                // `ts()` is zero, wake everyone and let the retries
+                // sort out what is still locked.
+                "resolve_lock" => self.wake_up_all_waiters(),
+                _ => {}
+            }
+        }
     }
 
     /// Releases all the latches held by a command.
@@ -1539,14 +2107,87 @@ impl Scheduler {
     /// the method initiates a get snapshot operation for furthur processing.
     fn lock_and_register_get_snapshot(&mut self, cid: u64) {
         if self.acquire_lock(cid) {
-            let ctx = self.extract_context(cid).clone();
-            let group = self.grouped_cmds
-                .as_mut()
-                .unwrap()
-                .entry(HashableContext(ctx))
-                .or_insert_with(Vec::new);
-            group.push(cid);
+            self.register_get_snapshot(cid);
+        }
+    }
+
+    /// Registers the command for the next batch snapshot on its region.
+    fn register_get_snapshot(&mut self, cid: u64) {
+        if self.try_reuse_held_snapshot(cid) {
+            return;
         }
+        let ctx = self.extract_context(cid).clone();
+        let group = self.grouped_cmds
+            .as_mut()
+            .unwrap()
+            .entry(HashableContext(ctx))
+            .or_insert_with(Vec::new);
+        group.push(cid);
+    }
+
+    /// Holds the snapshot of a scan that announced a follow-up lookup
+    /// with `Options::hold_snapshot`, keyed by its region.
+    fn try_hold_snapshot(&mut self, cid: u64, cb_ctx: CbContext, snapshot: Box<Snapshot>) {
+        let (region_id, epoch, start_ts) = {
+            let rctx = self.cmd_ctxs.get(&cid).unwrap();
+            match rctx.cmd {
+                Some(Command::Scan {
+                    ref ctx,
+                    start_ts,
+                    ref options,
+                    ..
+                }) if options.hold_snapshot =>
+                {
+                    (ctx.get_region_id(), ctx.get_region_epoch().clone(), start_ts)
+                }
+                _ => return,
+            }
+        };
+        let ttl = Duration::from_millis(HELD_SNAPSHOT_TTL_MS);
+        self.held_snapshots.retain(|_, s| s.held_at.elapsed() < ttl);
+        SCHED_HELD_SNAPSHOT_COUNTER_VEC
+            .with_label_values(&["hold"])
+            .inc();
+        self.held_snapshots.insert(
+            region_id,
+            HeldSnapshot {
+                snapshot: snapshot,
+                cb_ctx: cb_ctx,
+                epoch: epoch,
+                start_ts: start_ts,
+                held_at: Instant::now_coarse(),
+            },
+        );
+    }
+
+    /// Serves a `BatchGet` from the snapshot a preceding scan held for
+    /// it, skipping the engine snapshot acquisition. Returns false when
+    /// no held snapshot matches the region epoch and `start_ts`.
+    fn try_reuse_held_snapshot(&mut self, cid: u64) -> bool {
+        let region_id = {
+            let rctx = self.cmd_ctxs.get(&cid).unwrap();
+            let (ctx, start_ts) = match rctx.cmd {
+                Some(Command::BatchGet {
+                    ref ctx, start_ts, ..
+                }) => (ctx, start_ts),
+                _ => return false,
+            };
+            let region_id = ctx.get_region_id();
+            match self.held_snapshots.get(&region_id) {
+                Some(held)
+                    if held.start_ts == start_ts && held.epoch == *ctx.get_region_epoch()
+                        && held.held_at.elapsed()
+                            < Duration::from_millis(HELD_SNAPSHOT_TTL_MS) => {}
+                _ => return false,
+            }
+            region_id
+        };
+        let held = self.held_snapshots.remove(&region_id).unwrap();
+        SCHED_HELD_SNAPSHOT_COUNTER_VEC
+            .with_label_values(&["reuse"])
+            .inc();
+        self.on_snapshot_finished(vec![cid], held.cb_ctx, Ok(held.snapshot));
+        true
     }
 }
 
@@ -1583,8 +2224,31 @@ impl Runnable<Msg> for Scheduler {
     }
 
     fn run_batch(&mut self, msgs: &mut Vec<Msg>) {
+        // Write prepares arriving in the same batch are held back and
+        // merged per region at the end, see `on_write_prepare_batch`.
+        // Holding them back is safe: a prepared command already owns the
+        // latches of its keys, so nothing in this batch can touch them.
+        let mut prepared = Vec::new();
         for msg in msgs.drain(..) {
-            self.run(msg);
+            match msg {
+                Msg::WritePrepareFinished {
+                    cid,
+                    cmd,
+                    pr,
+                    to_be_write,
+                    rows,
+                } => {
+                    if mergeable_write(&cmd) && !to_be_write.is_empty() {
+                        prepared.push((cid, cmd, pr, to_be_write, rows));
+                    } else {
+                        self.on_write_prepare_finished(cid, cmd, pr, to_be_write, rows);
+                    }
+                }
+                other => self.run(other),
+            }
+        }
+        if !prepared.is_empty() {
+            self.on_write_prepare_batch(prepared);
         }
     }
 
@@ -1609,6 +2273,11 @@ impl Runnable<Msg> for Scheduler {
     }
 
     fn shutdown(&mut self) {
+        // Parked commands get no wake-up once the loop stops, deliver
+        // the lock conflict they were parked on instead.
+        for waiter in self.waiters.take_all() {
+            execute_callback(waiter.cb, waiter.pr);
+        }
         if let Err(e) = self.worker_pool.stop() {
             error!("scheduler run err:{:?}", e);
         }
@@ -1619,6 +2288,20 @@ impl Runnable<Msg> for Scheduler {
     }
 }
 
+impl RunnableWithTimer<Msg, ()> for Scheduler {
+    fn on_timeout(&mut self, timer: &mut Timer<()>, _: ()) {
+        for waiter in self.waiters.take_timed_out() {
+            SCHED_LOCK_WAIT_COUNTER_VEC
+                .with_label_values(&["timeout"])
+                .inc();
+            // The process result still carries the lock the command ran
+            // into, the client resolves it as if it was never parked.
+            execute_callback(waiter.cb, waiter.pr);
+        }
+        timer.add_task(Duration::from_millis(LOCK_WAIT_CHECK_INTERVAL_MS), ());
+    }
+}
+
 /// Generates the lock for a command.
 ///
 /// Basically, read-only commands require no latches, write commands require latches hashed
@@ -1633,10 +2316,13 @@ pub fn gen_command_lock(latches: &Latches, cmd: &Command) -> Lock {
             let keys: Vec<&Key> = key_locks.iter().map(|x| &x.0).collect();
             latches.gen_lock(&keys)
         }
-        Command::Commit { ref keys, .. } | Command::Rollback { ref keys, .. } => {
-            latches.gen_lock(keys)
-        }
+        Command::AcquirePessimisticLock { ref keys, .. }
+        | Command::Commit { ref keys, .. }
+        | Command::Rollback { ref keys, .. }
+        | Command::PessimisticRollback { ref keys, .. } => latches.gen_lock(keys),
         Command::Cleanup { ref key, .. } => latches.gen_lock(&[key]),
+        Command::RawIncr { ref key, .. } |
+        Command::RawCompareAndSwap { ref key, .. } => latches.gen_lock(&[key]),
         _ => Lock::new(vec![]),
     }
 }
@@ -1668,6 +2354,7 @@ mod tests {
             Command::Scan {
                 ctx: Context::new(),
                 start_key: make_key(b"k"),
+                end_key: None,
                 limit: 100,
                 start_ts: 25,
                 options: Options::default(),
@@ -1708,6 +2395,14 @@ mod tests {
                 start_ts: 10,
                 options: Options::default(),
             },
+            Command::AcquirePessimisticLock {
+                ctx: Context::new(),
+                keys: vec![make_key(b"k")],
+                primary: b"k".to_vec(),
+                start_ts: 10,
+                for_update_ts: 11,
+                options: Options::default(),
+            },
             Command::Commit {
                 ctx: Context::new(),
                 keys: vec![make_key(b"k")],
@@ -1724,6 +2419,12 @@ mod tests {
                 keys: vec![make_key(b"k")],
                 start_ts: 10,
             },
+            Command::PessimisticRollback {
+                ctx: Context::new(),
+                keys: vec![make_key(b"k")],
+                start_ts: 10,
+                for_update_ts: 11,
+            },
             Command::ResolveLock {
                 ctx: Context::new(),
                 txn_status: temp_map.clone(),
@@ -1731,10 +2432,21 @@ mod tests {
                 key_locks: vec![
                     (
                         make_key(b"k"),
-                        mvcc::Lock::new(mvcc::LockType::Put, b"k".to_vec(), 10, 20, None),
+                        mvcc::Lock::new(mvcc::LockType::Put, b"k".to_vec(), 10, 20, None, 0),
                     ),
                 ],
             },
+            Command::RawIncr {
+                ctx: Context::new(),
+                key: make_key(b"k"),
+                delta: 1,
+            },
+            Command::RawCompareAndSwap {
+                ctx: Context::new(),
+                key: make_key(b"k"),
+                expected: None,
+                new_value: b"v".to_vec(),
+            },
         ];
 
         let mut latches = Latches::new(1024);
@@ -11,6 +11,8 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::cmp;
+
 use storage::{Key, KvPair, ScanMode, Snapshot, Statistics, Value};
 use storage::mvcc::{Error as MvccError, MvccReader};
 use super::{Error, Result};
@@ -21,6 +23,7 @@ pub struct SnapshotStore {
     start_ts: u64,
     isolation_level: IsolationLevel,
     fill_cache: bool,
+    scan_seek_bound: Option<usize>,
 }
 
 impl SnapshotStore {
@@ -35,9 +38,16 @@ impl SnapshotStore {
             start_ts: start_ts,
             isolation_level: isolation_level,
             fill_cache: fill_cache,
+            scan_seek_bound: None,
         }
     }
 
+    /// Sets how many versioned entries scanners step over before they seek
+    /// to the next user key instead.
+    pub fn set_scan_seek_bound(&mut self, bound: usize) {
+        self.scan_seek_bound = Some(bound);
+    }
+
     pub fn get(&self, key: &Key, statistics: &mut Statistics) -> Result<Option<Value>> {
         let mut reader = MvccReader::new(
             self.snapshot.clone(),
@@ -57,10 +67,17 @@ impl SnapshotStore {
         keys: &[Key],
         statistics: &mut Statistics,
     ) -> Result<Vec<Result<Option<Value>>>> {
-        // TODO: sort the keys and use ScanMode::Forward
+        // A sorted and dense batch steps one forward cursor pair over
+        // all the keys, anything else pays a bloom filter backed
+        // iterator per key.
+        let scan_mode = if batch_sorted_and_dense(keys) {
+            Some(ScanMode::Forward)
+        } else {
+            None
+        };
         let mut reader = MvccReader::new(
             self.snapshot.clone(),
-            None,
+            scan_mode,
             self.fill_cache,
             None,
             None,
@@ -92,6 +109,9 @@ impl SnapshotStore {
             self.isolation_level,
         );
         reader.set_key_only(key_only);
+        if let Some(bound) = self.scan_seek_bound {
+            reader.set_seek_bound(bound);
+        }
         Ok(StoreScanner {
             reader: reader,
             start_ts: self.start_ts,
@@ -99,6 +119,29 @@ impl SnapshotStore {
     }
 }
 
+/// Whether the keys are sorted in ascending order and close enough
+/// together that stepping one cursor pair forward across all of them
+/// beats creating a fresh prefix seek iterator per key. Adjacent rows
+/// of one table share a long key prefix, so the shared prefix of the
+/// first and the last key serves as the distance heuristic: a cursor
+/// between nearby keys steps instead of seeking anyway.
+fn batch_sorted_and_dense(keys: &[Key]) -> bool {
+    if keys.len() < 2 {
+        return false;
+    }
+    if keys.windows(2).any(|w| w[0].encoded() >= w[1].encoded()) {
+        return false;
+    }
+    let first = keys.first().unwrap().encoded();
+    let last = keys.last().unwrap().encoded();
+    let common = first
+        .iter()
+        .zip(last.iter())
+        .take_while(|&(a, b)| a == b)
+        .count();
+    common * 2 >= cmp::min(first.len(), last.len())
+}
+
 pub struct StoreScanner {
     reader: MvccReader,
     start_ts: u64,
@@ -293,6 +336,42 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_batch_sorted_and_dense() {
+        let dense: Vec<_> = (1000..1010)
+            .map(|i| make_key(format!("{}{}", KEY_PREFIX, i).as_bytes()))
+            .collect();
+        assert!(super::batch_sorted_and_dense(&dense));
+        // A single key always pays a point lookup.
+        assert!(!super::batch_sorted_and_dense(&dense[..1]));
+        // Unsorted batches cannot step a cursor forward.
+        let mut unsorted = dense.clone();
+        unsorted.reverse();
+        assert!(!super::batch_sorted_and_dense(&unsorted));
+        // Keys sharing almost no prefix are too far apart.
+        let sparse = vec![make_key(b"a0"), make_key(b"z9")];
+        assert!(!super::batch_sorted_and_dense(&sparse));
+    }
+
+    #[test]
+    fn test_snapshot_store_batch_get_unsorted() {
+        let key_num = 100;
+        let store = TestStore::new(key_num);
+        let snapshot_store = store.store();
+        let mut statistics = Statistics::default();
+        let keys_list: Vec<_> = store
+            .keys
+            .iter()
+            .rev()
+            .map(|k| make_key(k.as_bytes()))
+            .collect();
+        let data = snapshot_store.batch_get(&keys_list, &mut statistics).unwrap();
+        for item in data {
+            let item = item.unwrap();
+            assert!(item.is_some(), "item expect some while get none");
+        }
+    }
+
     #[test]
     fn test_snapshot_store_scan() {
         let key_num = 100;
@@ -315,6 +394,30 @@ mod test {
         assert_eq!(result, expect, "expect {:?}, but got {:?}", expect, result);
     }
 
+    #[test]
+    fn test_snapshot_store_scan_seek_bound() {
+        let key_num = 100;
+        let store = TestStore::new(key_num);
+        let mut snapshot_store = store.store();
+        // With a bound of 1 every step over the next user key that has to
+        // move the cursor falls back to a seek; the results must not change.
+        snapshot_store.set_scan_seek_bound(1);
+        let mut scanner = snapshot_store
+            .scanner(ScanMode::Forward, false, None, None)
+            .unwrap();
+
+        let key = format!("{}{}", KEY_PREFIX, START_ID);
+        let start_key = make_key(key.as_bytes());
+        let result = scanner.scan(start_key, key_num as usize).unwrap();
+        let result: Vec<Option<KvPair>> = result.into_iter().map(Result::ok).collect();
+        let expect: Vec<Option<KvPair>> = store
+            .keys
+            .iter()
+            .map(|k| Some((k.clone().into_bytes(), k.clone().into_bytes())))
+            .collect();
+        assert_eq!(result, expect, "expect {:?}, but got {:?}", expect, result);
+    }
+
     #[test]
     fn test_snapshot_store_reverse_scan() {
         let key_num = 100;
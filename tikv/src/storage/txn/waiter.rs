@@ -0,0 +1,213 @@
+// Copyright 2018 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Parking for commands that ran into a lock.
+//!
+//! When a write meets a `KeyIsLocked` conflict the scheduler may park it
+//! here instead of returning the lock to the client, and reschedule it
+//! once the owning transaction commits or rolls back. A parked command
+//! holds no latches, otherwise it would block the very command that
+//! releases the lock it waits for. Waiters of transactions that never
+//! finish are flushed by a timeout.
+//!
+//! Before a command is parked the manager checks whether doing so would
+//! close a wait cycle among the transactions parked on this store and
+//! rejects the command with a deadlock error if it would. Spotting
+//! cycles that span stores needs a detector service the kvproto
+//! interface does not carry yet, those cycles are broken by the wait
+//! timeout instead.
+
+use std::time::Duration;
+
+use storage::{Command, StorageCb};
+use util::collections::{HashMap, HashSet};
+use util::time::Instant;
+
+use super::scheduler::ProcessResult;
+
+/// A command parked until the lock it ran into is released.
+pub struct Waiter {
+    pub cmd: Command,
+    pub cb: StorageCb,
+    /// The process result carrying the `KeyIsLocked` conflict, delivered
+    /// as-is when the wait times out, as if the command had never been
+    /// parked.
+    pub pr: ProcessResult,
+    pub start_ts: u64,
+    pub lock_ts: u64,
+    parked_at: Instant,
+}
+
+impl Waiter {
+    pub fn new(
+        cmd: Command,
+        cb: StorageCb,
+        pr: ProcessResult,
+        start_ts: u64,
+        lock_ts: u64,
+    ) -> Waiter {
+        Waiter {
+            cmd: cmd,
+            cb: cb,
+            pr: pr,
+            start_ts: start_ts,
+            lock_ts: lock_ts,
+            parked_at: Instant::now_coarse(),
+        }
+    }
+}
+
+/// Keeps the parked commands, keyed by the transaction they wait for.
+pub struct WaiterManager {
+    // start_ts of the lock owner -> commands waiting for it
+    waiters: HashMap<u64, Vec<Waiter>>,
+    timeout: Duration,
+}
+
+impl WaiterManager {
+    /// Creates a manager whose waits expire after `wait_timeout_ms`.
+    /// A zero timeout disables parking altogether.
+    pub fn new(wait_timeout_ms: u64) -> WaiterManager {
+        WaiterManager {
+            waiters: Default::default(),
+            timeout: Duration::from_millis(wait_timeout_ms),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.timeout > Duration::from_millis(0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.waiters.is_empty()
+    }
+
+    /// Parks a command until `take_ready(waiter.lock_ts)` is called.
+    pub fn wait_for(&mut self, waiter: Waiter) {
+        self.waiters
+            .entry(waiter.lock_ts)
+            .or_insert_with(Vec::new)
+            .push(waiter);
+    }
+
+    /// Whether parking `start_ts` behind `lock_ts` would close a wait
+    /// cycle among the transactions parked on this store.
+    pub fn would_deadlock(&self, start_ts: u64, lock_ts: u64) -> bool {
+        let mut stack = vec![lock_ts];
+        let mut visited = HashSet::default();
+        while let Some(ts) = stack.pop() {
+            if ts == start_ts {
+                return true;
+            }
+            if !visited.insert(ts) {
+                continue;
+            }
+            // The owner may itself be parked behind other transactions,
+            // follow its edges.
+            for (owner, waiters) in &self.waiters {
+                if waiters.iter().any(|w| w.start_ts == ts) {
+                    stack.push(*owner);
+                }
+            }
+        }
+        false
+    }
+
+    /// Takes the commands waiting for `lock_ts`, typically because the
+    /// transaction just committed or rolled back.
+    pub fn take_ready(&mut self, lock_ts: u64) -> Vec<Waiter> {
+        self.waiters.remove(&lock_ts).unwrap_or_else(Vec::new)
+    }
+
+    /// Takes every parked command, e.g. when locks are resolved in bulk
+    /// or the scheduler shuts down.
+    pub fn take_all(&mut self) -> Vec<Waiter> {
+        let waiters = ::std::mem::replace(&mut self.waiters, Default::default());
+        waiters.into_iter().flat_map(|(_, ws)| ws).collect()
+    }
+
+    /// Takes the commands that have waited longer than the timeout.
+    pub fn take_timed_out(&mut self) -> Vec<Waiter> {
+        let timeout = self.timeout;
+        let mut timed_out = Vec::new();
+        for waiters in self.waiters.values_mut() {
+            let mut i = 0;
+            while i < waiters.len() {
+                if waiters[i].parked_at.elapsed() >= timeout {
+                    timed_out.push(waiters.swap_remove(i));
+                } else {
+                    i += 1;
+                }
+            }
+        }
+        self.waiters.retain(|_, ws| !ws.is_empty());
+        timed_out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kvproto::kvrpcpb::Context;
+    use storage::Callback;
+
+    fn dummy_waiter(start_ts: u64, lock_ts: u64) -> Waiter {
+        let cmd = Command::Rollback {
+            ctx: Context::new(),
+            keys: vec![],
+            start_ts: start_ts,
+        };
+        let cb = StorageCb::Boolean(Callback::Boxed(box |_| {}));
+        Waiter::new(cmd, cb, ProcessResult::Res, start_ts, lock_ts)
+    }
+
+    #[test]
+    fn test_deadlock_detection() {
+        let mut mgr = WaiterManager::new(1000);
+        assert!(!mgr.would_deadlock(1, 2));
+
+        // 1 waits for 2, so parking 2 behind 1 closes a cycle.
+        mgr.wait_for(dummy_waiter(1, 2));
+        assert!(mgr.would_deadlock(2, 1));
+        assert!(!mgr.would_deadlock(3, 1));
+
+        // 1 -> 2 -> 3, the cycle check follows the chain.
+        mgr.wait_for(dummy_waiter(2, 3));
+        assert!(mgr.would_deadlock(3, 1));
+
+        // Waking 3's waiters breaks the chain.
+        mgr.take_ready(3);
+        assert!(!mgr.would_deadlock(3, 1));
+    }
+
+    #[test]
+    fn test_wake_and_timeout() {
+        assert!(!WaiterManager::new(0).enabled());
+
+        let mut mgr = WaiterManager::new(1000);
+        assert!(mgr.enabled());
+        assert!(mgr.is_empty());
+        mgr.wait_for(dummy_waiter(1, 10));
+        mgr.wait_for(dummy_waiter(2, 10));
+        mgr.wait_for(dummy_waiter(3, 20));
+        assert!(!mgr.is_empty());
+
+        // Nothing has waited a full second yet.
+        assert!(mgr.take_timed_out().is_empty());
+
+        assert_eq!(mgr.take_ready(10).len(), 2);
+        assert_eq!(mgr.take_ready(10).len(), 0);
+        assert_eq!(mgr.take_all().len(), 1);
+        assert!(mgr.is_empty());
+    }
+}
@@ -48,6 +48,32 @@ pub fn truncate_ts(key: &[u8]) -> &[u8] {
     &key[..key.len() - number::U64_SIZE]
 }
 
+/// Length in bytes of the keyspace prefix prepended to raw keys when
+/// keyspaces are enabled. The prefix is a big endian `u32` keyspace id.
+pub const KEYSPACE_PREFIX_LEN: usize = 4;
+
+/// Returns the raw key prefix of the given keyspace.
+pub fn keyspace_prefix(keyspace_id: u32) -> Vec<u8> {
+    vec![
+        (keyspace_id >> 24) as u8,
+        (keyspace_id >> 16) as u8,
+        (keyspace_id >> 8) as u8,
+        keyspace_id as u8,
+    ]
+}
+
+/// Strips the keyspace prefix from a raw key.
+///
+/// It is an error if the key does not belong to the given keyspace.
+pub fn strip_keyspace(keyspace_id: u32, key: &[u8]) -> Result<&[u8], codec::Error> {
+    if key.len() < KEYSPACE_PREFIX_LEN
+        || key[..KEYSPACE_PREFIX_LEN] != *keyspace_prefix(keyspace_id)
+    {
+        return Err(codec::Error::KeySpace);
+    }
+    Ok(&key[KEYSPACE_PREFIX_LEN..])
+}
+
 /// Key type.
 ///
 /// Keys have 2 types of binary representation - raw and encoded. The raw
@@ -73,6 +99,23 @@ impl Key {
         self.0.as_slice().decode_bytes(false)
     }
 
+    /// Creates a key in the given keyspace from raw bytes.
+    pub fn from_raw_in_keyspace(keyspace_id: u32, key: &[u8]) -> Key {
+        let mut prefixed = keyspace_prefix(keyspace_id);
+        prefixed.extend_from_slice(key);
+        Key::from_raw(&prefixed)
+    }
+
+    /// Gets the raw representation of this key with the keyspace prefix
+    /// stripped.
+    ///
+    /// It is an error if the key is not in the given keyspace.
+    pub fn raw_in_keyspace(&self, keyspace_id: u32) -> Result<Vec<u8>, codec::Error> {
+        let raw = self.raw()?;
+        let key = strip_keyspace(keyspace_id, &raw)?;
+        Ok(key.to_vec())
+    }
+
     /// Creates a key from encoded bytes.
     pub fn from_encoded(encoded_key: Vec<u8>) -> Key {
         Key(encoded_key)
@@ -169,6 +212,15 @@ pub fn split_encoded_key_on_ts(key: &[u8]) -> Result<(&[u8], u64), codec::Error>
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_keyspace() {
+        let key = Key::from_raw_in_keyspace(258, b"k");
+        assert_eq!(key.raw().unwrap(), vec![0u8, 0, 1, 2, b'k']);
+        assert_eq!(key.raw_in_keyspace(258).unwrap(), b"k".to_vec());
+        assert!(key.raw_in_keyspace(259).is_err());
+        assert!(strip_keyspace(0, b"k").is_err());
+    }
+
     #[test]
     fn test_split_ts() {
         let k = b"k";
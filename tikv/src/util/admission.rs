@@ -0,0 +1,159 @@
+// Copyright 2018 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Admission control for sustained overload.
+//!
+//! Components report load signals here and the entry points consult
+//! `is_overloaded` to reject low priority or flow controlled work with
+//! `ServerIsBusy` before it piles up. Shedding early keeps the queues
+//! short, so requests that are admitted still finish within their
+//! deadline instead of collapsing latency for everyone.
+//!
+//! The signals are live gauges, not rates: the scheduler reports its
+//! command queue depth, raftstore the raft log apply lag and the
+//! coprocessor the queue wait of finished requests. Entering overload
+//! and leaving it use different thresholds, the gap gives hysteresis so
+//! the controller does not flap around a single watermark.
+
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering, ATOMIC_BOOL_INIT,
+                        ATOMIC_USIZE_INIT};
+
+use prometheus::*;
+
+/// Scheduler command queue depths that start and stop shedding.
+const SCHED_QUEUE_SHED: usize = 4096;
+const SCHED_QUEUE_RESUME: usize = 2048;
+
+/// Raft apply lag, in log entries, that starts and stops shedding.
+const APPLY_LAG_SHED: usize = 8192;
+const APPLY_LAG_RESUME: usize = 4096;
+
+/// Coprocessor queue wait, in milliseconds, that starts and stops
+/// shedding.
+const READ_WAIT_SHED_MS: usize = 1000;
+const READ_WAIT_RESUME_MS: usize = 300;
+
+static SCHED_QUEUE: AtomicUsize = ATOMIC_USIZE_INIT;
+static APPLY_LAG: AtomicUsize = ATOMIC_USIZE_INIT;
+static READ_WAIT_MS: AtomicUsize = ATOMIC_USIZE_INIT;
+static OVERLOADED: AtomicBool = ATOMIC_BOOL_INIT;
+
+lazy_static! {
+    pub static ref ADMISSION_SHED_COUNTER_VEC: CounterVec =
+        register_counter_vec!(
+            "tikv_admission_shed_total",
+            "Total number of requests rejected by admission control.",
+            &["type"]
+        ).unwrap();
+
+    pub static ref ADMISSION_OVERLOAD_GAUGE: Gauge =
+        register_gauge!(
+            "tikv_admission_overloaded",
+            "Whether admission control is currently shedding load."
+        ).unwrap();
+}
+
+/// Reports the current depth of the storage scheduler command queue.
+pub fn report_sched_queue(depth: usize) {
+    SCHED_QUEUE.store(depth, Ordering::Relaxed);
+    refresh();
+}
+
+/// Reports how far raft apply is behind the committed log, in entries.
+/// Callers report per region, the controller keeps the latest value as
+/// an approximation of the worst lag.
+pub fn report_apply_lag(lag: usize) {
+    APPLY_LAG.store(lag, Ordering::Relaxed);
+    refresh();
+}
+
+/// Reports how long a finished read waited in queue before it ran.
+pub fn report_read_wait_ms(wait_ms: usize) {
+    READ_WAIT_MS.store(wait_ms, Ordering::Relaxed);
+    refresh();
+}
+
+/// Returns true while the server is shedding load. Entry points should
+/// reject low priority and flow controlled requests with `ServerIsBusy`
+/// and count the rejection through `on_shed`.
+pub fn is_overloaded() -> bool {
+    OVERLOADED.load(Ordering::Relaxed)
+}
+
+/// Records a request rejected by admission control.
+pub fn on_shed(tag: &str) {
+    ADMISSION_SHED_COUNTER_VEC.with_label_values(&[tag]).inc();
+}
+
+fn next_state(over: bool, sched: usize, lag: usize, wait: usize) -> bool {
+    if over {
+        // Leave overload only once every signal is back under its
+        // resume watermark.
+        sched > SCHED_QUEUE_RESUME || lag > APPLY_LAG_RESUME || wait > READ_WAIT_RESUME_MS
+    } else {
+        sched >= SCHED_QUEUE_SHED || lag >= APPLY_LAG_SHED || wait >= READ_WAIT_SHED_MS
+    }
+}
+
+fn refresh() {
+    let over = OVERLOADED.load(Ordering::Relaxed);
+    let sched = SCHED_QUEUE.load(Ordering::Relaxed);
+    let lag = APPLY_LAG.load(Ordering::Relaxed);
+    let wait = READ_WAIT_MS.load(Ordering::Relaxed);
+    let next = next_state(over, sched, lag, wait);
+    if next != over && OVERLOADED.compare_and_swap(over, next, Ordering::Relaxed) == over {
+        ADMISSION_OVERLOAD_GAUGE.set(if next { 1.0 } else { 0.0 });
+        if next {
+            warn!(
+                "admission control starts shedding load, scheduler queue {}, \
+                 apply lag {}, read wait {}ms",
+                sched, lag, wait
+            );
+        } else {
+            info!("admission control stops shedding load");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hysteresis() {
+        // The globals are shared with concurrently running tests, the
+        // transition logic is checked on its own instead.
+        assert!(!next_state(false, 0, 0, 0));
+
+        // Crossing a shed watermark flips the controller on.
+        assert!(next_state(false, SCHED_QUEUE_SHED, 0, 0));
+        assert!(next_state(false, 0, APPLY_LAG_SHED, 0));
+        assert!(next_state(false, 0, 0, READ_WAIT_SHED_MS));
+
+        // Dropping below the shed watermark is not enough to leave.
+        assert!(next_state(true, SCHED_QUEUE_RESUME + 1, 0, 0));
+
+        // Another signal above its resume watermark keeps it on even
+        // when the first one recovers.
+        assert!(next_state(true, 0, APPLY_LAG_RESUME + 1, 0));
+
+        // Only when every signal is under its resume watermark the
+        // controller flips off.
+        assert!(!next_state(
+            true,
+            SCHED_QUEUE_RESUME,
+            APPLY_LAG_RESUME,
+            READ_WAIT_RESUME_MS
+        ));
+    }
+}
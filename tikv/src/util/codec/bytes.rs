@@ -11,7 +11,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::io::{Read, Write};
+use std::io::{self, ErrorKind, Read, Write};
 
 use super::{Error, Result};
 use util::codec::number::{NumberDecoder, NumberEncoder};
@@ -142,6 +142,50 @@ pub fn encoded_bytes_len(encoded: &[u8], desc: bool) -> usize {
     }
 }
 
+/// `read_bytes` decodes a memcomparable key from the start of `data`,
+/// advancing it past the encoded bytes.
+///
+/// Unlike `decode_bytes` it works group wise on the slice: full groups
+/// are appended directly instead of being staged through an intermediate
+/// chunk buffer, which matters on scan workloads where every key passes
+/// through here.
+pub fn read_bytes(data: &mut &[u8], desc: bool) -> Result<Vec<u8>> {
+    let mut key = Vec::with_capacity(data.len());
+    let mut offset = 0;
+    loop {
+        if data.len() < offset + ENC_GROUP_SIZE + 1 {
+            return Err(Error::Io(io::Error::new(ErrorKind::UnexpectedEof, "eof")));
+        }
+        let marker = data[offset + ENC_GROUP_SIZE];
+        let pad_size = if desc {
+            marker as usize
+        } else {
+            (ENC_MARKER - marker) as usize
+        };
+        if pad_size == 0 {
+            key.extend_from_slice(&data[offset..offset + ENC_GROUP_SIZE]);
+            offset += ENC_GROUP_SIZE + 1;
+            continue;
+        }
+        if pad_size > ENC_GROUP_SIZE {
+            return Err(Error::KeyPadding);
+        }
+        key.extend_from_slice(&data[offset..offset + ENC_GROUP_SIZE - pad_size]);
+        let padding = &data[offset + ENC_GROUP_SIZE - pad_size..offset + ENC_GROUP_SIZE];
+        let pad_byte = if desc { !0 } else { 0 };
+        if padding.iter().any(|x| *x != pad_byte) {
+            return Err(Error::KeyPadding);
+        }
+        *data = &data[offset + ENC_GROUP_SIZE + 1..];
+        if desc {
+            for k in &mut key {
+                *k = !*k;
+            }
+        }
+        return Ok(key);
+    }
+}
+
 pub trait BytesDecoder: NumberDecoder + CompactBytesDecoder {
     /// Get the remaining length in bytes of current reader.
     fn remaining(&self) -> usize;
@@ -270,6 +314,14 @@ mod tests {
             let mut desc_input = desc.as_slice();
             assert_eq!(source, desc_input.decode_bytes(true).unwrap());
             assert_eq!(desc_input.as_ptr() as usize - desc_offset, desc.len());
+
+            // The slice reader decodes and advances the same way.
+            let mut asc_input = asc.as_slice();
+            assert_eq!(source, read_bytes(&mut asc_input, false).unwrap());
+            assert!(asc_input.is_empty());
+            let mut desc_input = desc.as_slice();
+            assert_eq!(source, read_bytes(&mut desc_input, true).unwrap());
+            assert!(desc_input.is_empty());
         }
     }
 
@@ -289,6 +341,8 @@ mod tests {
 
         for x in invalid_bytes {
             assert!(x.as_slice().decode_bytes(false).is_err());
+            let mut input = x.as_slice();
+            assert!(read_bytes(&mut input, false).is_err());
         }
     }
 
@@ -376,4 +430,24 @@ mod tests {
         let encoded = encode_bytes(&key);
         b.iter(|| encoded.as_slice().decode_bytes(false));
     }
+
+    #[bench]
+    fn bench_read_bytes(b: &mut Bencher) {
+        let key = [b'x'; 2000000];
+        let encoded = encode_bytes(&key);
+        b.iter(|| {
+            let mut data = encoded.as_slice();
+            read_bytes(&mut data, false)
+        });
+    }
+
+    #[bench]
+    fn bench_read_bytes_short(b: &mut Bencher) {
+        let key = [b'x'; 20];
+        let encoded = encode_bytes(&key);
+        b.iter(|| {
+            let mut data = encoded.as_slice();
+            read_bytes(&mut data, false)
+        });
+    }
 }
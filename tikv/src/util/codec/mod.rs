@@ -36,6 +36,7 @@ quick_error! {
         }
         KeyLength {description("bad format key(length)")}
         KeyPadding {description("bad format key(padding)")}
+        KeySpace {description("bad format key(keyspace)")}
         KeyNotFound {description("key not found")}
         InvalidDataType(reason: String) {
             description("invalid data type")
@@ -60,6 +61,7 @@ impl Error {
         match *self {
             Error::KeyLength => Some(Error::KeyLength),
             Error::KeyPadding => Some(Error::KeyPadding),
+            Error::KeySpace => Some(Error::KeySpace),
             Error::KeyNotFound => Some(Error::KeyNotFound),
             Error::InvalidDataType(ref r) => Some(Error::InvalidDataType(r.clone())),
             Error::Encoding(e) => Some(Error::Encoding(e)),
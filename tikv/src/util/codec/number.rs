@@ -219,6 +219,127 @@ pub trait NumberDecoder: Read {
 
 impl<T: Read> NumberDecoder for T {}
 
+const VAR_TERM_MASK: u64 = 0x8080808080808080;
+
+fn eof() -> Error {
+    Error::Io(io::Error::new(ErrorKind::UnexpectedEof, "eof"))
+}
+
+/// `read_u64` decodes a value encoded by `encode_u64` from the start of
+/// `data`, advancing it.
+///
+/// The slice based readers below exist for the hot decode paths: going
+/// through `io::Read` costs a length check per byte for varints, which
+/// shows up on scan workloads where every datum passes through here.
+pub fn read_u64(data: &mut &[u8]) -> Result<u64> {
+    if data.len() < U64_SIZE {
+        return Err(eof());
+    }
+    // One bounds check for the whole word, then an unrolled big endian
+    // assemble the compiler turns into a single load plus byte swap.
+    let v = u64::from(data[0]) << 56 | u64::from(data[1]) << 48 | u64::from(data[2]) << 40
+        | u64::from(data[3]) << 32 | u64::from(data[4]) << 24 | u64::from(data[5]) << 16
+        | u64::from(data[6]) << 8 | u64::from(data[7]);
+    *data = &data[U64_SIZE..];
+    Ok(v)
+}
+
+/// `read_u64_desc` decodes a value encoded by `encode_u64_desc` from the
+/// start of `data`, advancing it.
+pub fn read_u64_desc(data: &mut &[u8]) -> Result<u64> {
+    read_u64(data).map(|v| !v)
+}
+
+/// `read_i64` decodes a value encoded by `encode_i64` from the start of
+/// `data`, advancing it.
+pub fn read_i64(data: &mut &[u8]) -> Result<i64> {
+    read_u64(data).map(order_decode_i64)
+}
+
+/// `read_var_u64` decodes a value encoded by `encode_var_u64` from the
+/// start of `data`, advancing it.
+pub fn read_var_u64(data: &mut &[u8]) -> Result<u64> {
+    if !data.is_empty() && data[0] < 0x80 {
+        // Single byte values dominate row decodes.
+        let v = u64::from(data[0]);
+        *data = &data[1..];
+        return Ok(v);
+    }
+    if data.len() >= U64_SIZE {
+        // SWAR fast path: load one word, locate the terminating byte
+        // (high bit clear) in it, then fold the payload bits together.
+        let mut word = 0;
+        for (i, &b) in data[..U64_SIZE].iter().enumerate() {
+            word |= u64::from(b) << (i * 8);
+        }
+        let term = !word & VAR_TERM_MASK;
+        if term != 0 {
+            let len = (term.trailing_zeros() as usize >> 3) + 1;
+            let (mut x, mut s) = (0, 0);
+            for i in 0..len {
+                x |= (word >> (i * 8) & 0x7f) << s;
+                s += 7;
+            }
+            *data = &data[len..];
+            return Ok(x);
+        }
+    }
+    read_var_u64_slow(data)
+}
+
+// Varints longer than eight bytes (or truncated ones) take this path.
+fn read_var_u64_slow(data: &mut &[u8]) -> Result<u64> {
+    let (mut x, mut s) = (0, 0);
+    for (i, &b) in data.iter().enumerate() {
+        if b < 0x80 {
+            if i > 9 || i == 9 && b > 1 {
+                return Err(Error::Io(io::Error::new(
+                    ErrorKind::InvalidData,
+                    "overflow",
+                )));
+            }
+            x |= u64::from(b) << s;
+            *data = &data[i + 1..];
+            return Ok(x);
+        }
+        x |= u64::from(b & 0x7f) << s;
+        s += 7;
+    }
+    Err(eof())
+}
+
+/// `read_var_i64` decodes a value encoded by `encode_var_i64` from the
+/// start of `data`, advancing it.
+pub fn read_var_i64(data: &mut &[u8]) -> Result<i64> {
+    let v = read_var_u64(data)?;
+    let mut vx = v >> 1;
+    if v & 1 != 0 {
+        vx = !vx;
+    }
+    Ok(vx as i64)
+}
+
+/// `read_var_u64_batch` decodes `n` consecutive varints, appending them
+/// to `out`. A whole row is decoded with one reserve and the cursor kept
+/// in a register across values.
+pub fn read_var_u64_batch(data: &mut &[u8], n: usize, out: &mut Vec<u64>) -> Result<()> {
+    let orig = out.len();
+    out.reserve(n);
+    let mut buf = *data;
+    for _ in 0..n {
+        match read_var_u64(&mut buf) {
+            Ok(v) => out.push(v),
+            Err(e) => {
+                // Neither the cursor nor the output move on failure.
+                out.truncate(orig);
+                return Err(e);
+            }
+        }
+    }
+    *data = buf;
+    Ok(())
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -488,4 +609,140 @@ mod test {
         buf.push(0);
         assert_eq!(0, buf.as_slice().decode_var_u64().unwrap());
     }
+
+    // use macro to check the slice readers against the trait decoders.
+    macro_rules! test_read {
+        ($tag:ident, $enc:ident, $read:ident, $cases:expr) => {
+            #[test]
+            fn $tag() {
+                let mut buf = vec![];
+                for &v in $cases {
+                    buf.$enc(v).unwrap();
+                }
+                let mut data = buf.as_slice();
+                for &v in $cases {
+                    assert_eq!(v, $read(&mut data).unwrap());
+                }
+                assert!(data.is_empty());
+            }
+        }
+    }
+
+    test_read!(test_read_u64, encode_u64, read_u64, U64_TESTS);
+    test_read!(test_read_u64_desc, encode_u64_desc, read_u64_desc, U64_TESTS);
+    test_read!(test_read_i64, encode_i64, read_i64, I64_TESTS);
+    test_read!(test_read_var_u64, encode_var_u64, read_var_u64, U64_TESTS);
+    test_read!(test_read_var_i64, encode_var_i64, read_var_i64, I64_TESTS);
+
+    #[test]
+    fn test_read_eof() {
+        let mut data = &[0u8; 7][..];
+        check_error!(read_u64(&mut data), ErrorKind::UnexpectedEof);
+
+        let buf = vec![0x80; 3];
+        let mut data = buf.as_slice();
+        check_error!(read_var_u64(&mut data), ErrorKind::UnexpectedEof);
+        assert_eq!(data.len(), 3);
+
+        let mut buf = vec![0x80; 9];
+        buf.push(0x2);
+        let mut data = buf.as_slice();
+        check_error!(read_var_u64(&mut data), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_read_var_u64_batch() {
+        let mut buf = vec![];
+        for &v in U64_TESTS {
+            buf.encode_var_u64(v).unwrap();
+        }
+        let mut data = buf.as_slice();
+        let mut out = vec![];
+        read_var_u64_batch(&mut data, U64_TESTS.len(), &mut out).unwrap();
+        assert!(data.is_empty());
+        assert_eq!(out, U64_TESTS);
+
+        // A truncated batch moves neither the cursor nor the output.
+        let mut data = &buf[..buf.len() - 1];
+        let before = data;
+        let mut out = vec![42];
+        check_error!(
+            read_var_u64_batch(&mut data, U64_TESTS.len(), &mut out),
+            ErrorKind::UnexpectedEof
+        );
+        assert_eq!(data, before);
+        assert_eq!(out, vec![42]);
+    }
+
+    use test::Bencher;
+
+    fn var_u64_bench_data() -> Vec<u8> {
+        let mut buf = vec![];
+        for i in 0..1024u64 {
+            // mix of one, two and many byte varints.
+            buf.encode_var_u64(i * i * i).unwrap();
+        }
+        buf
+    }
+
+    #[bench]
+    fn bench_decode_var_u64(b: &mut Bencher) {
+        let buf = var_u64_bench_data();
+        b.iter(|| {
+            let mut data = buf.as_slice();
+            for _ in 0..1024 {
+                data.decode_var_u64().unwrap();
+            }
+        });
+    }
+
+    #[bench]
+    fn bench_read_var_u64(b: &mut Bencher) {
+        let buf = var_u64_bench_data();
+        b.iter(|| {
+            let mut data = buf.as_slice();
+            for _ in 0..1024 {
+                read_var_u64(&mut data).unwrap();
+            }
+        });
+    }
+
+    #[bench]
+    fn bench_read_var_u64_batch(b: &mut Bencher) {
+        let buf = var_u64_bench_data();
+        let mut out = Vec::with_capacity(1024);
+        b.iter(|| {
+            out.clear();
+            let mut data = buf.as_slice();
+            read_var_u64_batch(&mut data, 1024, &mut out).unwrap();
+        });
+    }
+
+    #[bench]
+    fn bench_decode_u64(b: &mut Bencher) {
+        let mut buf = vec![];
+        for i in 0..1024u64 {
+            buf.encode_u64(i).unwrap();
+        }
+        b.iter(|| {
+            let mut data = buf.as_slice();
+            for _ in 0..1024 {
+                data.decode_u64().unwrap();
+            }
+        });
+    }
+
+    #[bench]
+    fn bench_read_u64(b: &mut Bencher) {
+        let mut buf = vec![];
+        for i in 0..1024u64 {
+            buf.encode_u64(i).unwrap();
+        }
+        b.iter(|| {
+            let mut data = buf.as_slice();
+            for _ in 0..1024 {
+                read_u64(&mut data).unwrap();
+            }
+        });
+    }
 }
@@ -19,3 +19,123 @@ pub use std::collections::hash_map::Entry as HashMapEntry;
 pub use flat_map::FlatMap;
 pub use flat_map::flat_map::{Entry as FlatMapEntry, Values as FlatMapValues};
 pub use ordermap::{Entry as OrderMapEntry, OrderMap};
+
+use std::collections::btree_map;
+use std::collections::BTreeMap;
+use std::collections::Bound::{Excluded, Included, Unbounded};
+
+/// An ordered map of ranges, each keyed by its exclusive end key.
+///
+/// `raftstore` tracks which region owns which slice of the key space
+/// this way. The neighbor and overlap queries used to be spelled out as
+/// raw `range(..)` calls at every call site, and more than one past bug
+/// came from getting the bounds wrong; they live here once instead.
+///
+/// The map does not know the start keys of its ranges, so an overlap
+/// query returns the first candidate and the caller has to check the
+/// candidate's start key.
+#[derive(Debug, Default)]
+pub struct RangeMap {
+    map: BTreeMap<Vec<u8>, u64>,
+}
+
+impl RangeMap {
+    pub fn new() -> RangeMap {
+        RangeMap::default()
+    }
+
+    pub fn insert(&mut self, end_key: Vec<u8>, id: u64) -> Option<u64> {
+        // An empty end key would sort before every other key and hide
+        // the entry from all queries. Real end keys are data keys and
+        // are never empty.
+        debug_assert!(!end_key.is_empty());
+        self.map.insert(end_key, id)
+    }
+
+    pub fn remove(&mut self, end_key: &[u8]) -> Option<u64> {
+        self.map.remove(end_key)
+    }
+
+    pub fn get(&self, end_key: &[u8]) -> Option<u64> {
+        self.map.get(end_key).cloned()
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    pub fn iter(&self) -> btree_map::Iter<Vec<u8>, u64> {
+        self.map.iter()
+    }
+
+    pub fn values(&self) -> btree_map::Values<Vec<u8>, u64> {
+        self.map.values()
+    }
+
+    /// The first range whose end key is strictly greater than `key`:
+    /// the only range that can contain `key`.
+    pub fn next_after(&self, key: &[u8]) -> Option<(&Vec<u8>, u64)> {
+        self.map
+            .range::<[u8], _>((Excluded(key), Unbounded))
+            .next()
+            .map(|(k, &id)| (k, id))
+    }
+
+    /// The first range whose end key is at or after `key`.
+    pub fn next_from(&self, key: &[u8]) -> Option<(&Vec<u8>, u64)> {
+        self.map
+            .range::<[u8], _>((Included(key), Unbounded))
+            .next()
+            .map(|(k, &id)| (k, id))
+    }
+
+    /// The ranges whose end key lies in `(start, end]`. Every such range
+    /// overlaps `(start, end]`; the one range that overlaps it but ends
+    /// beyond `end` is found with `next_from(end)`.
+    pub fn ending_in(&self, start: &[u8], end: &[u8]) -> btree_map::Range<Vec<u8>, u64> {
+        self.map.range::<[u8], _>((Excluded(start), Included(end)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_range_map() {
+        let mut map = RangeMap::new();
+        // Three ranges: (, b] -> 1, (b, d] -> 2, (d, f] -> 3.
+        assert_eq!(map.insert(b"b".to_vec(), 1), None);
+        assert_eq!(map.insert(b"d".to_vec(), 2), None);
+        assert_eq!(map.insert(b"f".to_vec(), 3), None);
+        assert_eq!(map.insert(b"f".to_vec(), 4), Some(3));
+        assert_eq!(map.insert(b"f".to_vec(), 3), Some(4));
+        assert_eq!(map.len(), 3);
+
+        assert_eq!(map.get(b"d"), Some(2));
+        assert_eq!(map.get(b"c"), None);
+
+        // The range containing a key is the first one ending after it.
+        assert_eq!(map.next_after(b"a").unwrap().1, 1);
+        assert_eq!(map.next_after(b"b").unwrap().1, 2);
+        assert_eq!(map.next_after(b"e").unwrap().1, 3);
+        assert_eq!(map.next_after(b"f"), None);
+
+        assert_eq!(map.next_from(b"f").unwrap().1, 3);
+        assert_eq!(map.next_from(b"g"), None);
+
+        let ids: Vec<u64> = map.ending_in(b"a", b"d").map(|(_, &id)| id).collect();
+        assert_eq!(ids, vec![1, 2]);
+        let ids: Vec<u64> = map.ending_in(b"b", b"z").map(|(_, &id)| id).collect();
+        assert_eq!(ids, vec![2, 3]);
+
+        assert_eq!(map.remove(b"d"), Some(2));
+        assert_eq!(map.remove(b"d"), None);
+        assert_eq!(map.next_after(b"b").unwrap().1, 3);
+        assert_eq!(map.values().cloned().collect::<Vec<_>>(), vec![1, 3]);
+    }
+}
@@ -0,0 +1,135 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Stable error codes for the error types that cross component boundaries.
+//!
+//! Every variant of `storage::Error`, `coprocessor::Error` and
+//! `raftstore::Error` maps to an `ErrorCode`. The codes show up in gRPC
+//! error messages and logs, and clients key retry policies off them, so a
+//! released code must never be renamed or reused for a different failure.
+
+use std::fmt::{self, Display, Formatter};
+
+/// Whether a failed request is worth retrying.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorClass {
+    /// The failure is transient; the same request may succeed later,
+    /// possibly against another peer.
+    Retryable,
+    /// Retrying the same request can not succeed.
+    Fatal,
+}
+
+/// A stable, machine readable identifier for an error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ErrorCode {
+    pub code: &'static str,
+    pub class: ErrorClass,
+}
+
+impl ErrorCode {
+    pub fn is_retryable(self) -> bool {
+        self.class == ErrorClass::Retryable
+    }
+}
+
+impl Display for ErrorCode {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}", self.code)
+    }
+}
+
+/// An error that maps to a stable code.
+pub trait ErrorCodeExt {
+    fn error_code(&self) -> ErrorCode;
+}
+
+macro_rules! error_codes {
+    ($($name:ident => ($code:expr, $class:ident),)+) => {
+        $(pub const $name: ErrorCode = ErrorCode {
+            code: $code,
+            class: ErrorClass::$class,
+        };)+
+    };
+}
+
+/// Codes for `storage::Error`.
+pub mod storage {
+    use super::{ErrorClass, ErrorCode};
+
+    error_codes! {
+        ENGINE => ("KV:Storage:Engine", Retryable),
+        TXN => ("KV:Storage:Txn", Retryable),
+        MVCC => ("KV:Storage:Mvcc", Retryable),
+        CLOSED => ("KV:Storage:Closed", Fatal),
+        IO => ("KV:Storage:Io", Fatal),
+        SCHED_TOO_BUSY => ("KV:Storage:SchedTooBusy", Retryable),
+        KEY_TOO_LARGE => ("KV:Storage:KeyTooLarge", Fatal),
+        ACCESS_DENIED => ("KV:Storage:AccessDenied", Fatal),
+        INVALID_CF => ("KV:Storage:InvalidCf", Fatal),
+        UNKNOWN => ("KV:Storage:Unknown", Fatal),
+    }
+}
+
+/// Codes for `coprocessor::Error`.
+pub mod coprocessor {
+    use super::{ErrorClass, ErrorCode};
+
+    error_codes! {
+        REGION => ("KV:Coprocessor:Region", Retryable),
+        LOCKED => ("KV:Coprocessor:Locked", Retryable),
+        OUTDATED => ("KV:Coprocessor:Outdated", Retryable),
+        FULL => ("KV:Coprocessor:Full", Retryable),
+        UNKNOWN => ("KV:Coprocessor:Unknown", Fatal),
+    }
+}
+
+/// Codes for `raftstore::Error`.
+pub mod raftstore {
+    use super::{ErrorClass, ErrorCode};
+
+    error_codes! {
+        RAFT_ENTRY_TOO_LARGE => ("KV:Raftstore:RaftEntryTooLarge", Fatal),
+        STORE_NOT_MATCH => ("KV:Raftstore:StoreNotMatch", Retryable),
+        REGION_NOT_FOUND => ("KV:Raftstore:RegionNotFound", Retryable),
+        REGION_NOT_INITIALIZED => ("KV:Raftstore:RegionNotInitialized", Retryable),
+        NOT_LEADER => ("KV:Raftstore:NotLeader", Retryable),
+        KEY_NOT_IN_REGION => ("KV:Raftstore:KeyNotInRegion", Retryable),
+        IO => ("KV:Raftstore:Io", Fatal),
+        ROCKSDB => ("KV:Raftstore:RocksDb", Fatal),
+        PROTOBUF => ("KV:Raftstore:Protobuf", Fatal),
+        CODEC => ("KV:Raftstore:Codec", Fatal),
+        ADDR_PARSE => ("KV:Raftstore:AddrParse", Fatal),
+        PD => ("KV:Raftstore:Pd", Retryable),
+        RAFT => ("KV:Raftstore:Raft", Retryable),
+        TIMEOUT => ("KV:Raftstore:Timeout", Retryable),
+        STALE_EPOCH => ("KV:Raftstore:StaleEpoch", Retryable),
+        STALE_COMMAND => ("KV:Raftstore:StaleCommand", Retryable),
+        COPROCESSOR => ("KV:Raftstore:Coprocessor", Fatal),
+        TRANSPORT => ("KV:Raftstore:Transport", Retryable),
+        SNAPSHOT => ("KV:Raftstore:Snapshot", Retryable),
+        UNKNOWN => ("KV:Raftstore:Unknown", Fatal),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_code() {
+        assert!(storage::SCHED_TOO_BUSY.is_retryable());
+        assert!(!raftstore::ROCKSDB.is_retryable());
+        assert_eq!(format!("{}", raftstore::NOT_LEADER), "KV:Raftstore:NotLeader");
+    }
+}
@@ -0,0 +1,330 @@
+// Copyright 2018 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fs::{self, File};
+use std::io::{self, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use futures::{Future, Stream};
+use rusoto_core::reactor::RequestDispatcher;
+use rusoto_core::{DefaultCredentialsProvider, Region};
+use rusoto_s3::{AbortMultipartUploadRequest, CompleteMultipartUploadRequest,
+                CompletedMultipartUpload, CompletedPart, CreateMultipartUploadRequest,
+                GetObjectRequest, PutObjectRequest, S3Client, UploadPartRequest, S3};
+use url::Url;
+
+use util::io_limiter::{IOLimiter, IOPriority, LimitWriter};
+
+/// The part size of S3 multipart uploads. Objects at or below it go up
+/// in a single `PutObject`, S3 requires every part but the last to be at
+/// least 5 MB.
+const S3_PART_SIZE: usize = 8 * 1024 * 1024;
+
+const MAX_RETRY_TIMES: usize = 3;
+const RETRY_INTERVAL_MILLIS: u64 = 500;
+
+/// A storage target outside the local store, shared by the backup writer
+/// and the import downloader. Implementations store whole objects under
+/// flat names, there is no directory structure beyond what the name
+/// encodes.
+///
+/// All implementations limit their traffic through the optional
+/// `IOLimiter` handed to them, so backup and import can share one budget
+/// with the other IO heavy jobs of the store.
+pub trait ExternalStorage: Send + Sync {
+    /// Writes `data` as a new object under `name`, replacing an existing
+    /// object atomically.
+    fn write(&self, name: &str, data: &[u8]) -> io::Result<()>;
+
+    /// Reads the whole object stored under `name`.
+    fn read(&self, name: &str) -> io::Result<Vec<u8>>;
+}
+
+/// Creates an `ExternalStorage` from a URL, e.g. `local:///mnt/backup`
+/// or `s3://bucket/prefix?region=us-west-2`.
+pub fn create_storage(
+    url: &str,
+    limiter: Option<Arc<IOLimiter>>,
+) -> io::Result<Arc<ExternalStorage>> {
+    let url = Url::parse(url).map_err(|e| {
+        io::Error::new(ErrorKind::InvalidInput, format!("invalid storage url: {}", e))
+    })?;
+    match url.scheme() {
+        "local" | "file" => {
+            let storage = LocalStorage::new(Path::new(url.path()), limiter)?;
+            Ok(Arc::new(storage))
+        }
+        "s3" => {
+            let bucket = match url.host_str() {
+                Some(host) => host.to_owned(),
+                None => {
+                    return Err(io::Error::new(
+                        ErrorKind::InvalidInput,
+                        "missing S3 bucket name",
+                    ))
+                }
+            };
+            let prefix = url.path().trim_matches('/').to_owned();
+            let mut region = String::new();
+            for (k, v) in url.query_pairs() {
+                if k == "region" {
+                    region = v.into_owned();
+                }
+            }
+            let storage = S3Storage::new(&region, bucket, prefix, limiter)?;
+            Ok(Arc::new(storage))
+        }
+        other => Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            format!("unknown storage scheme: {}", other),
+        )),
+    }
+}
+
+/// Retries transient failures of `f` a few times before giving up. The
+/// external targets sit behind networks and shared disks, a single
+/// failed call does not have to fail a whole backup.
+fn retry<T, E: ::std::fmt::Debug, F: FnMut() -> Result<T, E>>(mut f: F) -> Result<T, E> {
+    let mut last = None;
+    for _ in 0..MAX_RETRY_TIMES {
+        match f() {
+            Ok(v) => return Ok(v),
+            Err(e) => {
+                warn!("external storage request: {:?}, retrying", e);
+                last = Some(e);
+                thread::sleep(Duration::from_millis(RETRY_INTERVAL_MILLIS));
+            }
+        }
+    }
+    Err(last.unwrap())
+}
+
+/// A directory on the local file system, usually a mounted distributed
+/// file system like NFS or HDFS.
+pub struct LocalStorage {
+    base: PathBuf,
+    limiter: Option<Arc<IOLimiter>>,
+}
+
+impl LocalStorage {
+    pub fn new(base: &Path, limiter: Option<Arc<IOLimiter>>) -> io::Result<LocalStorage> {
+        if !base.is_dir() {
+            fs::create_dir_all(base)?;
+        }
+        Ok(LocalStorage {
+            base: base.to_owned(),
+            limiter: limiter,
+        })
+    }
+}
+
+impl ExternalStorage for LocalStorage {
+    fn write(&self, name: &str, data: &[u8]) -> io::Result<()> {
+        // Write to a temporary sibling and rename, so a reader never
+        // observes a partially written object.
+        let tmp = self.base.join(format!("{}.tmp", name));
+        let path = self.base.join(name);
+        {
+            let mut file = File::create(&tmp)?;
+            let mut writer =
+                LimitWriter::with_priority(self.limiter.clone(), &mut file, IOPriority::Low);
+            writer.write_all(data)?;
+            writer.flush()?;
+        }
+        fs::rename(&tmp, &path)
+    }
+
+    fn read(&self, name: &str) -> io::Result<Vec<u8>> {
+        let mut data = Vec::new();
+        File::open(self.base.join(name))?.read_to_end(&mut data)?;
+        Ok(data)
+    }
+}
+
+/// An S3 bucket, objects are stored under `prefix/name`.
+pub struct S3Storage {
+    client: S3Client<DefaultCredentialsProvider, RequestDispatcher>,
+    bucket: String,
+    prefix: String,
+    limiter: Option<Arc<IOLimiter>>,
+}
+
+impl S3Storage {
+    pub fn new(
+        region: &str,
+        bucket: String,
+        prefix: String,
+        limiter: Option<Arc<IOLimiter>>,
+    ) -> io::Result<S3Storage> {
+        let region = region
+            .parse::<Region>()
+            .map_err(|e| io::Error::new(ErrorKind::InvalidInput, format!("{:?}", e)))?;
+        Ok(S3Storage {
+            // Credentials come from the environment or the instance
+            // profile, the same way the other AWS tooling finds them.
+            client: S3Client::simple(region),
+            bucket: bucket,
+            prefix: prefix,
+            limiter: limiter,
+        })
+    }
+
+    fn key(&self, name: &str) -> String {
+        if self.prefix.is_empty() {
+            name.to_owned()
+        } else {
+            format!("{}/{}", self.prefix, name)
+        }
+    }
+
+    fn request_through(&self, bytes: usize) {
+        if let Some(ref limiter) = self.limiter {
+            limiter.throttle(IOPriority::Low, bytes as i64);
+        }
+    }
+
+    fn put_object(&self, key: &str, data: &[u8]) -> io::Result<()> {
+        self.request_through(data.len());
+        retry(|| {
+            let mut req = PutObjectRequest::default();
+            req.bucket = self.bucket.clone();
+            req.key = key.to_owned();
+            req.body = Some(data.to_vec());
+            self.client.put_object(&req).wait()
+        }).map_err(other_err)?;
+        Ok(())
+    }
+
+    fn multipart_upload(&self, key: &str, data: &[u8]) -> io::Result<()> {
+        let upload_id = retry(|| {
+            let mut req = CreateMultipartUploadRequest::default();
+            req.bucket = self.bucket.clone();
+            req.key = key.to_owned();
+            self.client.create_multipart_upload(&req).wait()
+        }).map_err(other_err)?
+            .upload_id
+            .ok_or_else(|| other_err("missing upload id"))?;
+
+        match self.upload_parts(key, &upload_id, data) {
+            Ok(()) => Ok(()),
+            Err(e) => {
+                // Abort on a best effort basis, a leaked upload only
+                // costs storage until the bucket lifecycle expires it.
+                let mut req = AbortMultipartUploadRequest::default();
+                req.bucket = self.bucket.clone();
+                req.key = key.to_owned();
+                req.upload_id = upload_id;
+                if let Err(abort) = self.client.abort_multipart_upload(&req).wait() {
+                    warn!("abort multipart upload {}: {:?}", key, abort);
+                }
+                Err(e)
+            }
+        }
+    }
+
+    fn upload_parts(&self, key: &str, upload_id: &str, data: &[u8]) -> io::Result<()> {
+        let mut parts = Vec::new();
+        for (i, chunk) in data.chunks(S3_PART_SIZE).enumerate() {
+            let part_number = i as i64 + 1;
+            self.request_through(chunk.len());
+            let output = retry(|| {
+                let mut req = UploadPartRequest::default();
+                req.bucket = self.bucket.clone();
+                req.key = key.to_owned();
+                req.upload_id = upload_id.to_owned();
+                req.part_number = part_number;
+                req.body = Some(chunk.to_vec());
+                self.client.upload_part(&req).wait()
+            }).map_err(other_err)?;
+            let mut part = CompletedPart::default();
+            part.e_tag = output.e_tag;
+            part.part_number = Some(part_number);
+            parts.push(part);
+        }
+        retry(|| {
+            let mut req = CompleteMultipartUploadRequest::default();
+            req.bucket = self.bucket.clone();
+            req.key = key.to_owned();
+            req.upload_id = upload_id.to_owned();
+            let mut completed = CompletedMultipartUpload::default();
+            completed.parts = Some(parts.clone());
+            req.multipart_upload = Some(completed);
+            self.client.complete_multipart_upload(&req).wait()
+        }).map_err(other_err)?;
+        Ok(())
+    }
+}
+
+impl ExternalStorage for S3Storage {
+    fn write(&self, name: &str, data: &[u8]) -> io::Result<()> {
+        let key = self.key(name);
+        if data.len() <= S3_PART_SIZE {
+            self.put_object(&key, data)
+        } else {
+            self.multipart_upload(&key, data)
+        }
+    }
+
+    fn read(&self, name: &str) -> io::Result<Vec<u8>> {
+        let output = retry(|| {
+            let mut req = GetObjectRequest::default();
+            req.bucket = self.bucket.clone();
+            req.key = self.key(name);
+            self.client.get_object(&req).wait()
+        }).map_err(other_err)?;
+        let body = output.body.ok_or_else(|| other_err("missing object body"))?;
+        let data = body.concat2().wait().map_err(other_err)?;
+        self.request_through(data.len());
+        Ok(data)
+    }
+}
+
+fn other_err<E: ::std::fmt::Debug>(e: E) -> io::Error {
+    io::Error::new(ErrorKind::Other, format!("{:?}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use tempdir::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn test_local_storage() {
+        let dir = TempDir::new("_test_local_storage").unwrap();
+        let base = dir.path().join("backup");
+        let storage = LocalStorage::new(&base, None).unwrap();
+        storage.write("a.sst", b"data").unwrap();
+        assert_eq!(storage.read("a.sst").unwrap(), b"data".to_vec());
+        // Overwrites are atomic replacements.
+        storage.write("a.sst", b"data2").unwrap();
+        assert_eq!(storage.read("a.sst").unwrap(), b"data2".to_vec());
+        assert!(storage.read("missing.sst").is_err());
+    }
+
+    #[test]
+    fn test_create_storage() {
+        let dir = TempDir::new("_test_create_storage").unwrap();
+        let url = format!("local://{}", dir.path().display());
+        let storage = create_storage(&url, None).unwrap();
+        storage.write("a.sst", b"data").unwrap();
+        assert_eq!(storage.read("a.sst").unwrap(), b"data".to_vec());
+
+        assert!(create_storage("s3://", None).is_err());
+        assert!(create_storage("ftp://host/path", None).is_err());
+        assert!(create_storage("not a url", None).is_err());
+    }
+}
@@ -17,12 +17,22 @@ use std::option::Option;
 
 use rocksdb::RateLimiter;
 
+const PRIORITY_LOW: u8 = 0;
 const PRIORITY_HIGH: u8 = 1;
 const REFILL_PERIOD: i64 = 100 * 1000;
 const FARENESS: i32 = 10;
 const SNAP_MAX_BYTES_PER_TIME: i64 = 4 * 1024 * 1024;
 pub const DEFAULT_SNAP_MAX_BYTES_PER_SEC: u64 = 30 * 1024 * 1024;
 
+/// The priority class of a request against a shared `IOLimiter`.
+/// Foreground traffic is served before the background maintenance jobs
+/// (backup, restore, import) when both compete for the same budget.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum IOPriority {
+    Low,
+    High,
+}
+
 pub struct IOLimiter {
     inner: RateLimiter,
 }
@@ -42,6 +52,24 @@ impl IOLimiter {
         self.inner.request(bytes, PRIORITY_HIGH)
     }
 
+    pub fn request_with_priority(&self, pri: IOPriority, bytes: i64) {
+        let pri = match pri {
+            IOPriority::Low => PRIORITY_LOW,
+            IOPriority::High => PRIORITY_HIGH,
+        };
+        self.inner.request(bytes, pri)
+    }
+
+    /// Requests `bytes` in bursts the limiter accepts, blocking until the
+    /// whole amount went through.
+    pub fn throttle(&self, pri: IOPriority, mut bytes: i64) {
+        while bytes > 0 {
+            let single = ::std::cmp::min(bytes, self.get_max_bytes_per_time());
+            self.request_with_priority(pri, single);
+            bytes -= single;
+        }
+    }
+
     pub fn get_max_bytes_per_time(&self) -> i64 {
         if self.inner.get_singleburst_bytes() > SNAP_MAX_BYTES_PER_TIME {
             SNAP_MAX_BYTES_PER_TIME
@@ -66,13 +94,23 @@ impl IOLimiter {
 pub struct LimitWriter<'a, T: Write + 'a> {
     limiter: Option<Arc<IOLimiter>>,
     writer: &'a mut T,
+    priority: IOPriority,
 }
 
 impl<'a, T: Write + 'a> LimitWriter<'a, T> {
     pub fn new(limiter: Option<Arc<IOLimiter>>, writer: &'a mut T) -> LimitWriter<'a, T> {
+        LimitWriter::with_priority(limiter, writer, IOPriority::High)
+    }
+
+    pub fn with_priority(
+        limiter: Option<Arc<IOLimiter>>,
+        writer: &'a mut T,
+        priority: IOPriority,
+    ) -> LimitWriter<'a, T> {
         LimitWriter {
             limiter: limiter,
             writer: writer,
+            priority: priority,
         }
     }
 }
@@ -90,7 +128,7 @@ impl<'a, T: Write + 'a> Write for LimitWriter<'a, T> {
                 } else {
                     end = curr + single;
                 }
-                limiter.request((end - curr) as i64);
+                limiter.request_with_priority(self.priority, (end - curr) as i64);
                 self.writer.write_all(&buf[curr..end])?;
                 curr = end;
             }
@@ -119,11 +119,16 @@ macro_rules! recover_safe {
     })
 }
 
-/// Log slow operations with warn!.
+/// Log slow operations with warn!. Operations timed with
+/// `SlowTimer::for_subsystem` are also recorded in the slow-event ring
+/// buffer.
 macro_rules! slow_log {
     ($t:expr, $($arg:tt)*) => {{
         if $t.is_slow() {
             warn!("{} [takes {:?}]", format_args!($($arg)*), $t.elapsed());
+            if let Some(subsystem) = $t.subsystem() {
+                $crate::util::slow_log::record(subsystem, $t.elapsed(), format!($($arg)*));
+            }
         }
     }}
 }
@@ -11,7 +11,15 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use prometheus::CounterVec;
+use std::sync::Mutex;
+
+use prometheus::{exponential_buckets, CounterVec};
+
+// Default latency histogram buckets: from 50us up to several minutes, so
+// that sub-millisecond requests remain observable.
+const DEFAULT_LATENCY_BUCKETS_START: f64 = 0.00005;
+const DEFAULT_LATENCY_BUCKETS_FACTOR: f64 = 2.0;
+const DEFAULT_LATENCY_BUCKETS_COUNT: usize = 23;
 
 lazy_static! {
     pub static ref CHANNEL_FULL_COUNTER_VEC: CounterVec =
@@ -20,4 +28,26 @@ lazy_static! {
             "Total number of channel full errors.",
             &["type"]
         ).unwrap();
+
+    static ref LATENCY_BUCKETS: Mutex<Vec<f64>> = Mutex::new(
+        exponential_buckets(
+            DEFAULT_LATENCY_BUCKETS_START,
+            DEFAULT_LATENCY_BUCKETS_FACTOR,
+            DEFAULT_LATENCY_BUCKETS_COUNT,
+        ).unwrap()
+    );
+}
+
+/// Overrides the buckets used by latency histograms.
+///
+/// Histograms capture the buckets when they are registered, so this must
+/// be called during bootstrap, before any metric is used.
+pub fn set_latency_buckets(buckets: Vec<f64>) {
+    assert!(!buckets.is_empty());
+    *LATENCY_BUCKETS.lock().unwrap() = buckets;
+}
+
+/// Returns the buckets latency histograms are registered with.
+pub fn latency_buckets() -> Vec<f64> {
+    LATENCY_BUCKETS.lock().unwrap().clone()
 }
@@ -25,6 +25,8 @@ use prometheus;
 use rand::{self, ThreadRng};
 use protobuf::Message;
 
+use self::collections::HashMap;
+
 #[macro_use]
 pub mod macros;
 pub mod logger;
@@ -42,10 +44,14 @@ pub mod threadpool;
 pub mod collections;
 pub mod time;
 pub mod io_limiter;
+pub mod external_storage;
 pub mod security;
+pub mod slow_log;
+pub mod admission;
 pub mod timer;
 pub mod sys;
 pub mod futurepool;
+pub mod error_code;
 
 pub use self::rocksdb::properties;
 
@@ -350,10 +356,16 @@ pub fn print_tikv_info() {
 }
 
 /// `run_prometheus` runs a background prometheus client.
+///
+/// Every metric name is prepended with `prefix` (if not empty), and the
+/// `labels` are merged into the grouping key, so the push gateway attaches
+/// them to all pushed series.
 pub fn run_prometheus(
     interval: Duration,
     address: &str,
     job: &str,
+    prefix: &str,
+    labels: &HashMap<String, String>,
 ) -> Option<thread::JoinHandle<()>> {
     if interval == Duration::from_secs(0) {
         return None;
@@ -361,17 +373,25 @@ pub fn run_prometheus(
 
     let job = job.to_owned();
     let address = address.to_owned();
+    let prefix = prefix.to_owned();
+    let labels = labels.clone();
     let handler = thread::Builder::new()
         .name("promepusher".to_owned())
         .spawn(move || loop {
-            let metric_familys = prometheus::gather();
-
-            let res = prometheus::push_metrics(
-                &job,
-                prometheus::hostname_grouping_key(),
-                &address,
-                metric_familys,
-            );
+            let mut metric_familys = prometheus::gather();
+            if !prefix.is_empty() {
+                for mf in &mut metric_familys {
+                    let name = format!("{}_{}", prefix, mf.get_name());
+                    mf.set_name(name);
+                }
+            }
+
+            let mut grouping = prometheus::hostname_grouping_key();
+            for (k, v) in &labels {
+                grouping.insert(k.clone(), v.clone());
+            }
+
+            let res = prometheus::push_metrics(&job, grouping, &address, metric_familys);
             if let Err(e) = res {
                 error!("fail to push metrics: {}", e);
             }
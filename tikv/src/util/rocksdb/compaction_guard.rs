@@ -0,0 +1,198 @@
+// Copyright 2018 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::BTreeSet;
+use std::ops::Bound::Excluded;
+use std::sync::{Arc, RwLock};
+
+use rocksdb::{SstPartitioner, SstPartitionerContext, SstPartitionerFactory,
+              SstPartitionerRequest, SstPartitionerResult};
+
+use storage::CfName;
+
+/// A thread-safe set of data-encoded region end keys.
+///
+/// It is maintained by the raftstore alongside its region map and read by
+/// `CompactionGuardFactory` from RocksDB background threads whenever a
+/// compaction starts, so it may lag behind the real region distribution
+/// slightly. That is fine: boundaries only guide where output files are cut,
+/// a stale cut is never incorrect, just less useful.
+#[derive(Clone, Default)]
+pub struct RegionBoundaries {
+    end_keys: Arc<RwLock<BTreeSet<Vec<u8>>>>,
+}
+
+impl RegionBoundaries {
+    pub fn insert(&self, end_key: Vec<u8>) {
+        self.end_keys.write().unwrap().insert(end_key);
+    }
+
+    pub fn remove(&self, end_key: &[u8]) {
+        self.end_keys.write().unwrap().remove(end_key);
+    }
+
+    /// Collect all boundaries strictly inside `(start, end)`. Boundaries equal
+    /// to either end of a compaction can't split anything and are skipped.
+    pub fn collect(&self, start: &[u8], end: &[u8]) -> Vec<Vec<u8>> {
+        let end_keys = self.end_keys.read().unwrap();
+        end_keys
+            .range::<[u8], _>((Excluded(start), Excluded(end)))
+            .cloned()
+            .collect()
+    }
+}
+
+/// An SST partitioner that cuts compaction output files at region boundaries,
+/// so that dropping or moving a region later can delete whole files instead
+/// of issuing overlapping delete-ranges and compactions.
+///
+/// Files smaller than `min_output_file_size` are not cut, otherwise a run of
+/// tiny regions would produce a flood of tiny SSTs. The factory is only
+/// attached to the data cfs, whose keys are what region boundaries split.
+pub struct CompactionGuardFactory {
+    cf: CfName,
+    boundaries: RegionBoundaries,
+    min_output_file_size: u64,
+}
+
+impl CompactionGuardFactory {
+    pub fn new(
+        cf: CfName,
+        boundaries: RegionBoundaries,
+        min_output_file_size: u64,
+    ) -> CompactionGuardFactory {
+        CompactionGuardFactory {
+            cf: cf,
+            boundaries: boundaries,
+            min_output_file_size: min_output_file_size,
+        }
+    }
+}
+
+impl SstPartitionerFactory for CompactionGuardFactory {
+    fn create_partitioner(&self, context: &SstPartitionerContext) -> Option<Box<SstPartitioner>> {
+        let boundaries = self.boundaries
+            .collect(context.smallest_key, context.largest_key);
+        if boundaries.is_empty() {
+            return None;
+        }
+        debug!(
+            "created compaction guard for cf {} with {} boundaries",
+            self.cf,
+            boundaries.len()
+        );
+        Some(Box::new(CompactionGuard {
+            boundaries: boundaries,
+            pos: 0,
+            min_output_file_size: self.min_output_file_size,
+        }))
+    }
+}
+
+struct CompactionGuard {
+    // Sorted, all inside the key range of the compaction.
+    boundaries: Vec<Vec<u8>>,
+    // Boundaries before `pos` are at or before the last key written and can
+    // no longer cut the current output file.
+    pos: usize,
+    min_output_file_size: u64,
+}
+
+impl SstPartitioner for CompactionGuard {
+    fn should_partition(&mut self, req: &SstPartitionerRequest) -> SstPartitionerResult {
+        while self.pos < self.boundaries.len()
+            && self.boundaries[self.pos].as_slice() <= req.prev_user_key
+        {
+            self.pos += 1;
+        }
+        if self.pos < self.boundaries.len()
+            && self.boundaries[self.pos].as_slice() <= req.current_user_key
+            && req.current_output_file_size >= self.min_output_file_size
+        {
+            SstPartitionerResult::Required
+        } else {
+            SstPartitionerResult::NotRequired
+        }
+    }
+
+    fn can_do_trivial_move(&mut self, _smallest_user_key: &[u8], _largest_user_key: &[u8]) -> bool {
+        // A trivial move would keep files that span region boundaries.
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn must_partition(guard: &mut CompactionGuard, prev: &[u8], current: &[u8], size: u64) {
+        let req = SstPartitionerRequest {
+            prev_user_key: prev,
+            current_user_key: current,
+            current_output_file_size: size,
+        };
+        assert_eq!(guard.should_partition(&req), SstPartitionerResult::Required);
+    }
+
+    fn must_not_partition(guard: &mut CompactionGuard, prev: &[u8], current: &[u8], size: u64) {
+        let req = SstPartitionerRequest {
+            prev_user_key: prev,
+            current_user_key: current,
+            current_output_file_size: size,
+        };
+        assert_eq!(
+            guard.should_partition(&req),
+            SstPartitionerResult::NotRequired
+        );
+    }
+
+    #[test]
+    fn test_region_boundaries() {
+        let boundaries = RegionBoundaries::default();
+        boundaries.insert(b"zb".to_vec());
+        boundaries.insert(b"zd".to_vec());
+        boundaries.insert(b"zf".to_vec());
+
+        assert_eq!(
+            boundaries.collect(b"za", b"ze"),
+            vec![b"zb".to_vec(), b"zd".to_vec()]
+        );
+        // Boundaries equal to either end are skipped.
+        assert_eq!(boundaries.collect(b"zb", b"zd"), Vec::<Vec<u8>>::new());
+
+        boundaries.remove(b"zd");
+        assert_eq!(
+            boundaries.collect(b"za", b"zz"),
+            vec![b"zb".to_vec(), b"zf".to_vec()]
+        );
+    }
+
+    #[test]
+    fn test_should_partition() {
+        let mut guard = CompactionGuard {
+            boundaries: vec![b"zb".to_vec(), b"zd".to_vec()],
+            pos: 0,
+            min_output_file_size: 100,
+        };
+
+        // Not crossing any boundary.
+        must_not_partition(&mut guard, b"za1", b"za2", 200);
+        // Crossing a boundary but the file is still too small.
+        must_not_partition(&mut guard, b"za2", b"zb1", 99);
+        // Crossing a boundary with a large enough file.
+        must_partition(&mut guard, b"za2", b"zb1", 100);
+        // The crossed boundary can't cut the next file again.
+        must_not_partition(&mut guard, b"zb1", b"zb2", 200);
+        must_partition(&mut guard, b"zc", b"zd", 100);
+    }
+}
@@ -15,7 +15,10 @@ pub mod properties;
 pub mod event_listener;
 pub mod engine_metrics;
 pub mod metrics_flusher;
+pub mod compaction_guard;
+pub mod raw_ttl;
 
+pub use self::compaction_guard::{CompactionGuardFactory, RegionBoundaries};
 pub use self::event_listener::{CompactedEvent, CompactionListener, EventListener};
 pub use self::metrics_flusher::MetricsFlusher;
 
@@ -0,0 +1,130 @@
+// Copyright 2018 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Raw values with a time to live.
+//!
+//! With `storage.enable-raw-ttl` set every raw value is stored with an
+//! eight byte expiry timestamp appended, zero meaning the value never
+//! expires. Reads strip the suffix and treat expired values as missing,
+//! and the compaction filter below purges them from disk for good.
+//! Values too short to carry a suffix are served untouched, so a store
+//! with pre-existing raw data keeps working.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use byteorder::{BigEndian, ByteOrder};
+use rocksdb::CompactionFilter;
+
+const EXPIRE_TS_LEN: usize = 8;
+
+/// The current unix timestamp in seconds.
+pub fn now_ts() -> u64 {
+    match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(d) => d.as_secs(),
+        Err(_) => 0,
+    }
+}
+
+/// Appends `expire_ts` to `value`, zero meaning no expiry.
+pub fn encode_expire_ts(mut value: Vec<u8>, expire_ts: u64) -> Vec<u8> {
+    let mut suffix = [0; EXPIRE_TS_LEN];
+    BigEndian::write_u64(&mut suffix, expire_ts);
+    value.extend_from_slice(&suffix);
+    value
+}
+
+fn expire_ts(value: &[u8]) -> Option<u64> {
+    if value.len() < EXPIRE_TS_LEN {
+        return None;
+    }
+    Some(BigEndian::read_u64(&value[value.len() - EXPIRE_TS_LEN..]))
+}
+
+fn expired(ts: u64, now: u64) -> bool {
+    ts != 0 && ts <= now
+}
+
+/// Strips the expiry suffix from `value`, `None` when it has expired. A
+/// value too short to carry a suffix is returned as is, which also keeps
+/// the empty values of `key_only` scans alive.
+pub fn strip_expire_ts(mut value: Vec<u8>) -> Option<Vec<u8>> {
+    match expire_ts(&value) {
+        Some(ts) => {
+            if expired(ts, now_ts()) {
+                return None;
+            }
+            let len = value.len() - EXPIRE_TS_LEN;
+            value.truncate(len);
+            Some(value)
+        }
+        None => Some(value),
+    }
+}
+
+/// Drops expired raw values during compaction. Until a value is
+/// compacted the read path already hides it, so the filter only needs to
+/// reclaim the space eventually.
+#[derive(Default)]
+pub struct TtlCompactionFilter;
+
+impl CompactionFilter for TtlCompactionFilter {
+    fn filter(
+        &mut self,
+        _level: usize,
+        _key: &[u8],
+        value: &[u8],
+        _new_value: &mut Vec<u8>,
+        _value_changed: &mut bool,
+    ) -> bool {
+        // Returning true removes the entry.
+        match expire_ts(value) {
+            Some(ts) => expired(ts, now_ts()),
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expire_ts_round_trip() {
+        let v = encode_expire_ts(b"v".to_vec(), 0);
+        assert_eq!(v.len(), 1 + EXPIRE_TS_LEN);
+        assert_eq!(strip_expire_ts(v), Some(b"v".to_vec()));
+
+        let v = encode_expire_ts(b"v".to_vec(), u64::max_value());
+        assert_eq!(strip_expire_ts(v), Some(b"v".to_vec()));
+
+        let v = encode_expire_ts(b"v".to_vec(), 1);
+        assert_eq!(strip_expire_ts(v), None);
+
+        // a value too short for a suffix is kept untouched.
+        assert_eq!(strip_expire_ts(b"v".to_vec()), Some(b"v".to_vec()));
+    }
+
+    #[test]
+    fn test_compaction_filter() {
+        let mut filter = TtlCompactionFilter::default();
+        let mut unused = vec![];
+        let mut changed = false;
+        let expired = encode_expire_ts(b"v".to_vec(), 1);
+        assert!(filter.filter(0, b"k", &expired, &mut unused, &mut changed));
+        let live = encode_expire_ts(b"v".to_vec(), u64::max_value());
+        assert!(!filter.filter(0, b"k", &live, &mut unused, &mut changed));
+        let permanent = encode_expire_ts(b"v".to_vec(), 0);
+        assert!(!filter.filter(0, b"k", &permanent, &mut unused, &mut changed));
+        assert!(!filter.filter(0, b"k", b"v", &mut unused, &mut changed));
+    }
+}
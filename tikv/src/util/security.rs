@@ -11,6 +11,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::env;
 use std::fs::File;
 use std::error::Error;
 use std::io::Read;
@@ -26,6 +27,19 @@ pub struct SecurityConfig {
     pub ca_path: String,
     pub cert_path: String,
     pub key_path: String,
+    // Colon separated cipher suite list handed to the TLS library, e.g.
+    // "ECDHE-RSA-AES128-GCM-SHA256:ECDHE-RSA-AES256-GCM-SHA384". Empty
+    // keeps the library defaults. Deployments with compliance
+    // requirements (FIPS, 国密/SM4 capable builds) restrict this.
+    pub cipher_suites: String,
+    // Minimum accepted TLS protocol version, e.g. "1.2". Empty keeps
+    // the library default.
+    pub min_tls_version: String,
+    // Static token clients must send in the "tikv-auth-token" request
+    // metadata. Empty disables the check. This is an application level
+    // guard for shared networks; it is no substitute for TLS, which
+    // keeps the token itself off the wire in clear text.
+    pub auth_token: String,
     // Test purpose only.
     #[serde(skip)] pub override_ssl_target: String,
 }
@@ -36,6 +50,9 @@ impl Default for SecurityConfig {
             ca_path: String::new(),
             cert_path: String::new(),
             key_path: String::new(),
+            cipher_suites: String::new(),
+            min_tls_version: String::new(),
+            auth_token: String::new(),
             override_ssl_target: String::new(),
         }
     }
@@ -74,15 +91,32 @@ impl SecurityConfig {
         {
             return Err("ca, cert and private key should be all configured.".into());
         }
+        if self.cipher_suites.split(':').any(|s| s.is_empty()) && !self.cipher_suites.is_empty() {
+            return Err("cipher suite list contains an empty entry.".into());
+        }
+        // The linked gRPC negotiates exactly TLS 1.2 and exposes no way
+        // to move the floor, so anything else can not be honored. Fail
+        // loudly instead of silently accepting weaker connections.
+        match self.min_tls_version.as_ref() {
+            "" | "1.2" => {}
+            v @ "1.0" | v @ "1.1" => {
+                return Err(format!("TLS {} is below what this build supports.", v).into())
+            }
+            v => return Err(format!("unsupported minimum TLS version {}.", v).into()),
+        }
 
         Ok(())
     }
 }
 
+/// The request metadata key carrying the client auth token.
+pub const AUTH_TOKEN_HEADER: &'static str = "tikv-auth-token";
+
 pub struct SecurityManager {
     ca: Vec<u8>,
     cert: Vec<u8>,
     key: Vec<u8>,
+    auth_token: Vec<u8>,
     override_ssl_target: String,
 }
 
@@ -92,20 +126,45 @@ impl Drop for SecurityManager {
             for b in &mut self.key {
                 ptr::write_volatile(b, 0);
             }
+            for b in &mut self.auth_token {
+                ptr::write_volatile(b, 0);
+            }
         }
     }
 }
 
 impl SecurityManager {
     pub fn new(cfg: &SecurityConfig) -> Result<SecurityManager, Box<Error>> {
+        if !cfg.cipher_suites.is_empty() {
+            // gRPC reads this once when TLS is first initialized, so it
+            // must be in place before any secure channel or server is
+            // built. The manager is created during bootstrap, well
+            // before either.
+            env::set_var("GRPC_SSL_CIPHER_SUITES", &cfg.cipher_suites);
+        }
         Ok(SecurityManager {
             ca: load_key("CA", &cfg.ca_path)?,
             cert: load_key("certificate", &cfg.cert_path)?,
             key: load_key("private key", &cfg.key_path)?,
+            auth_token: cfg.auth_token.clone().into_bytes(),
             override_ssl_target: cfg.override_ssl_target.clone(),
         })
     }
 
+    /// Checks the request metadata against the configured auth token.
+    /// Passes everything when no token is configured.
+    pub fn check_auth<'a, I>(&self, metadata: I) -> bool
+    where
+        I: IntoIterator<Item = (&'a str, &'a [u8])>,
+    {
+        if self.auth_token.is_empty() {
+            return true;
+        }
+        metadata
+            .into_iter()
+            .any(|(k, v)| k == AUTH_TOKEN_HEADER && v == self.auth_token.as_slice())
+    }
+
     pub fn connect(&self, mut cb: ChannelBuilder, addr: &str) -> Channel {
         if self.ca.is_empty() {
             cb.connect(addr)
@@ -138,6 +197,7 @@ impl SecurityManager {
 mod tests {
     use super::*;
 
+    use std::env;
     use std::fs::File;
     use std::io::Write;
 
@@ -169,6 +229,18 @@ mod tests {
             false,
         );
 
+        // only TLS 1.2 can be enforced by this build.
+        assert_cfg(|c| c.min_tls_version = "1.2".to_owned(), true);
+        assert_cfg(|c| c.min_tls_version = "1.1".to_owned(), false);
+        assert_cfg(|c| c.min_tls_version = "1.3".to_owned(), false);
+
+        // malformed cipher suite lists are rejected.
+        assert_cfg(
+            |c| c.cipher_suites = "ECDHE-RSA-AES128-GCM-SHA256".to_owned(),
+            true,
+        );
+        assert_cfg(|c| c.cipher_suites = "AES128-SHA::AES256-SHA".to_owned(), false);
+
         let temp = TempDir::new("test_cred").unwrap();
         let example_ca = temp.path().join("ca");
         let example_cert = temp.path().join("cert");
@@ -192,5 +264,34 @@ mod tests {
         assert_eq!(mgr.ca, vec![0]);
         assert_eq!(mgr.cert, vec![1]);
         assert_eq!(mgr.key, vec![2]);
+
+        // the cipher suite list is handed to gRPC through the
+        // environment.
+        c.cipher_suites = "ECDHE-RSA-AES128-GCM-SHA256".to_owned();
+        c.validate().unwrap();
+        SecurityManager::new(&c).unwrap();
+        assert_eq!(
+            env::var("GRPC_SSL_CIPHER_SUITES").unwrap(),
+            c.cipher_suites
+        );
+    }
+
+    #[test]
+    fn test_check_auth() {
+        let mut cfg = SecurityConfig::default();
+        // without a configured token every request passes.
+        let mgr = SecurityManager::new(&cfg).unwrap();
+        assert!(mgr.check_auth(vec![]));
+        assert!(mgr.check_auth(vec![(AUTH_TOKEN_HEADER, &b"whatever"[..])]));
+
+        cfg.auth_token = "s3cr3t".to_owned();
+        let mgr = SecurityManager::new(&cfg).unwrap();
+        assert!(!mgr.check_auth(vec![]));
+        assert!(!mgr.check_auth(vec![(AUTH_TOKEN_HEADER, &b"wrong"[..])]));
+        assert!(!mgr.check_auth(vec![("other-header", &b"s3cr3t"[..])]));
+        assert!(mgr.check_auth(vec![
+            ("other-header", &b"noise"[..]),
+            (AUTH_TOKEN_HEADER, &b"s3cr3t"[..]),
+        ]));
     }
 }
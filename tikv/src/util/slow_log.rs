@@ -0,0 +1,171 @@
+// Copyright 2018 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A process wide slow-log facility.
+//!
+//! Subsystems time their work with `SlowTimer::for_subsystem` and report
+//! through `slow_log!`. An operation that crosses the threshold of its
+//! subsystem is logged with warn! as before and additionally pushed into
+//! a bounded ring buffer, so the most recent slow events can be pulled
+//! out of a running process after the fact. Thresholds default to one
+//! second and are installed from the `[slow-log]` config section during
+//! bootstrap.
+//!
+//! The buffer is queried with `recent_events`. Serving it over the debug
+//! service needs a new RPC in the debugpb protocol, which lives in
+//! kvproto; until that lands the events only show up in the log.
+
+use std::cmp;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+use std::time::Duration;
+
+use time::{self, Timespec};
+
+use super::time::duration_to_ms;
+
+/// The part of the system a slow event originated from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Subsystem {
+    Raftstore,
+    Apply,
+    Coprocessor,
+    Scheduler,
+}
+
+const SUBSYSTEM_COUNT: usize = 4;
+
+impl Subsystem {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            Subsystem::Raftstore => "raftstore",
+            Subsystem::Apply => "apply",
+            Subsystem::Coprocessor => "coprocessor",
+            Subsystem::Scheduler => "scheduler",
+        }
+    }
+
+    fn index(&self) -> usize {
+        *self as usize
+    }
+}
+
+const DEFAULT_THRESHOLD_MS: u64 = 1000;
+
+// Thresholds in milliseconds; 0 means not configured, which falls back
+// to the default.
+static THRESHOLD_MS: [AtomicUsize; SUBSYSTEM_COUNT] = [
+    ATOMIC_USIZE_INIT,
+    ATOMIC_USIZE_INIT,
+    ATOMIC_USIZE_INIT,
+    ATOMIC_USIZE_INIT,
+];
+
+// How many slow events are kept before the oldest is dropped.
+const EVENT_CAPACITY: usize = 128;
+
+/// A recorded slow event.
+#[derive(Debug, Clone)]
+pub struct SlowEvent {
+    /// Wall clock time the event was recorded at.
+    pub ts: Timespec,
+    pub subsystem: Subsystem,
+    /// How long the operation took.
+    pub takes: Duration,
+    /// The formatted slow-log line, without the elapsed time suffix.
+    pub detail: String,
+}
+
+lazy_static! {
+    static ref EVENTS: Mutex<VecDeque<SlowEvent>> =
+        Mutex::new(VecDeque::with_capacity(EVENT_CAPACITY));
+}
+
+/// Sets the slow threshold of a subsystem. A zero duration is bumped to
+/// one millisecond, the finest granularity the facility keeps.
+pub fn set_threshold(subsystem: Subsystem, threshold: Duration) {
+    let ms = cmp::max(duration_to_ms(threshold), 1);
+    THRESHOLD_MS[subsystem.index()].store(ms as usize, Ordering::Relaxed);
+}
+
+/// The slow threshold of a subsystem.
+pub fn threshold(subsystem: Subsystem) -> Duration {
+    match THRESHOLD_MS[subsystem.index()].load(Ordering::Relaxed) {
+        0 => Duration::from_millis(DEFAULT_THRESHOLD_MS),
+        ms => Duration::from_millis(ms as u64),
+    }
+}
+
+/// Records a slow event, evicting the oldest one when the buffer is
+/// full.
+pub fn record(subsystem: Subsystem, takes: Duration, detail: String) {
+    let mut events = EVENTS.lock().unwrap();
+    if events.len() >= EVENT_CAPACITY {
+        events.pop_front();
+    }
+    events.push_back(SlowEvent {
+        ts: time::get_time(),
+        subsystem: subsystem,
+        takes: takes,
+        detail: detail,
+    });
+}
+
+/// The most recent slow events, oldest first.
+pub fn recent_events() -> Vec<SlowEvent> {
+    let events = EVENTS.lock().unwrap();
+    events.iter().cloned().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::Duration;
+
+    #[test]
+    fn test_slow_log() {
+        // unset thresholds fall back to the default.
+        assert_eq!(
+            threshold(Subsystem::Apply),
+            Duration::from_millis(DEFAULT_THRESHOLD_MS)
+        );
+        set_threshold(Subsystem::Apply, Duration::from_millis(500));
+        assert_eq!(threshold(Subsystem::Apply), Duration::from_millis(500));
+        set_threshold(Subsystem::Apply, Duration::new(0, 0));
+        assert_eq!(threshold(Subsystem::Apply), Duration::from_millis(1));
+
+        for i in 0..EVENT_CAPACITY + 10 {
+            record(
+                Subsystem::Scheduler,
+                Duration::from_secs(2),
+                format!("test_slow_log event {}", i),
+            );
+        }
+        let events: Vec<_> = recent_events()
+            .into_iter()
+            .filter(|e| e.detail.starts_with("test_slow_log"))
+            .collect();
+        // the oldest events have been evicted.
+        assert!(events.len() <= EVENT_CAPACITY);
+        let last = events.last().unwrap();
+        assert_eq!(last.subsystem, Subsystem::Scheduler);
+        assert_eq!(last.subsystem.as_str(), "scheduler");
+        assert_eq!(last.takes, Duration::from_secs(2));
+        assert_eq!(
+            last.detail,
+            format!("test_slow_log event {}", EVENT_CAPACITY + 9)
+        );
+    }
+}
@@ -71,3 +71,105 @@ pub mod pri {
         Ok(0)
     }
 }
+
+#[cfg(target_os = "linux")]
+pub mod cpuset {
+    use libc;
+    use std::io::Error;
+    use std::mem;
+
+    /// Restricts the calling thread to the given CPUs. Threads spawned
+    /// afterwards inherit the mask, which is the only handle we have on
+    /// threads a library spawns internally: narrow the mask, spawn, then
+    /// widen it back.
+    pub fn set_affinity(cpus: &[usize]) -> Result<(), Error> {
+        unsafe {
+            let mut set: libc::cpu_set_t = mem::zeroed();
+            libc::CPU_ZERO(&mut set);
+            for &cpu in cpus {
+                libc::CPU_SET(cpu, &mut set);
+            }
+            if libc::sched_setaffinity(0, mem::size_of::<libc::cpu_set_t>(), &set) != 0 {
+                return Err(Error::last_os_error());
+            }
+            Ok(())
+        }
+    }
+
+    /// Returns the CPUs the calling thread may run on.
+    pub fn get_affinity() -> Result<Vec<usize>, Error> {
+        unsafe {
+            let mut set: libc::cpu_set_t = mem::zeroed();
+            if libc::sched_getaffinity(0, mem::size_of::<libc::cpu_set_t>(), &mut set) != 0 {
+                return Err(Error::last_os_error());
+            }
+            let mut cpus = Vec::new();
+            for cpu in 0..libc::CPU_SETSIZE as usize {
+                if libc::CPU_ISSET(cpu, &set) {
+                    cpus.push(cpu);
+                }
+            }
+            Ok(cpus)
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn test_set_affinity() {
+            let saved = get_affinity().unwrap();
+            assert!(!saved.is_empty());
+
+            // any thread can shrink its own mask to a cpu it already owns.
+            set_affinity(&saved[..1]).unwrap();
+            assert_eq!(get_affinity().unwrap(), &saved[..1]);
+
+            set_affinity(&saved).unwrap();
+            assert_eq!(get_affinity().unwrap(), saved);
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub mod cpuset {
+    use std::io::{Error, ErrorKind};
+
+    pub fn set_affinity(_: &[usize]) -> Result<(), Error> {
+        Err(Error::new(
+            ErrorKind::Other,
+            "cpu affinity is only supported on linux",
+        ))
+    }
+
+    pub fn get_affinity() -> Result<Vec<usize>, Error> {
+        Err(Error::new(
+            ErrorKind::Other,
+            "cpu affinity is only supported on linux",
+        ))
+    }
+}
+
+/// Number of NUMA nodes the machine exposes, 1 when there is no NUMA or
+/// the topology cannot be read.
+#[cfg(target_os = "linux")]
+pub fn numa_node_count() -> usize {
+    use std::fs;
+    let nodes = match fs::read_dir("/sys/devices/system/node") {
+        Ok(dir) => dir.filter_map(|e| e.ok())
+            .filter(|e| {
+                let name = e.file_name();
+                let name = name.to_string_lossy();
+                name.starts_with("node") && name[4..].bytes().all(|b| b.is_ascii_digit())
+            })
+            .count(),
+        Err(_) => 0,
+    };
+    ::std::cmp::max(nodes, 1)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn numa_node_count() -> usize {
+    1
+}
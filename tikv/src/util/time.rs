@@ -19,6 +19,8 @@ use std::cmp::Ordering;
 
 use time::{Duration as TimeDuration, Timespec};
 
+use super::slow_log::{self, Subsystem};
+
 /// Convert Duration to milliseconds.
 #[inline]
 pub fn duration_to_ms(d: Duration) -> u64 {
@@ -46,6 +48,7 @@ pub fn duration_to_nanos(d: Duration) -> u64 {
 pub struct SlowTimer {
     slow_time: Duration,
     t: Instant,
+    subsystem: Option<Subsystem>,
 }
 
 impl SlowTimer {
@@ -57,9 +60,25 @@ impl SlowTimer {
         SlowTimer {
             slow_time: slow_time,
             t: Instant::now_coarse(),
+            subsystem: None,
+        }
+    }
+
+    /// Creates a timer against the configured threshold of `subsystem`.
+    /// Slow operations reported through `slow_log!` with such a timer
+    /// also land in the slow-event ring buffer.
+    pub fn for_subsystem(subsystem: Subsystem) -> SlowTimer {
+        SlowTimer {
+            slow_time: slow_log::threshold(subsystem),
+            t: Instant::now_coarse(),
+            subsystem: Some(subsystem),
         }
     }
 
+    pub fn subsystem(&self) -> Option<Subsystem> {
+        self.subsystem
+    }
+
     pub fn from_secs(secs: u64) -> SlowTimer {
         SlowTimer::from(Duration::from_secs(secs))
     }
@@ -11,7 +11,12 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::cmp::{Ord, Ordering, Reverse};
+use std::boxed::FnBox;
+use std::cmp::{self, Ord, Ordering, Reverse};
+use std::mem;
+use std::sync::atomic::{AtomicBool, Ordering as AtomicOrdering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, Builder as ThreadBuilder, JoinHandle};
 use std::time::Duration;
 use std::collections::BinaryHeap;
 
@@ -85,6 +90,174 @@ impl<T> Ord for TimeoutTask<T> {
     }
 }
 
+const WHEEL_LEVELS: usize = 4;
+const WHEEL_SLOT_BITS: usize = 6;
+const WHEEL_SLOTS: usize = 1 << WHEEL_SLOT_BITS;
+/// Timeouts at or beyond this many ticks are clamped. With the default
+/// 100ms tick the wheel still covers more than two weeks.
+const WHEEL_MAX_TICKS: u64 = 1 << (WHEEL_SLOT_BITS * WHEEL_LEVELS);
+
+struct WheelTask {
+    tick: u64,
+    cancelled: Arc<AtomicBool>,
+    cb: Box<FnBox() + Send>,
+}
+
+/// A hierarchical timer wheel for coarse one-shot timeouts.
+///
+/// Each level holds 64 slots and covers 64 times the span of the level
+/// below it, so insertion and expiry are O(1) regardless of how many
+/// timeouts are pending. The wheel itself is passive: the owner calls
+/// `tick` once per tick and runs whatever comes back. `TimerService`
+/// wraps it with a driving thread.
+pub struct TimerWheel {
+    // slots[level][slot]
+    slots: Vec<Vec<Vec<WheelTask>>>,
+    current: u64,
+}
+
+impl Default for TimerWheel {
+    fn default() -> TimerWheel {
+        TimerWheel {
+            slots: (0..WHEEL_LEVELS)
+                .map(|_| (0..WHEEL_SLOTS).map(|_| Vec::new()).collect())
+                .collect(),
+            current: 0,
+        }
+    }
+}
+
+impl TimerWheel {
+    pub fn new() -> TimerWheel {
+        TimerWheel::default()
+    }
+
+    /// Schedule `cb` to run `ticks` ticks from now. A timeout of zero
+    /// fires on the next tick. The returned handle can cancel the
+    /// callback as long as it has not fired yet.
+    pub fn schedule(&mut self, ticks: u64, cb: Box<FnBox() + Send>) -> TimerHandle {
+        let ticks = cmp::max(1, cmp::min(ticks, WHEEL_MAX_TICKS - 1));
+        let cancelled = Arc::new(AtomicBool::new(false));
+        self.insert(WheelTask {
+            tick: self.current + ticks,
+            cancelled: Arc::clone(&cancelled),
+            cb: cb,
+        });
+        TimerHandle { cancelled: cancelled }
+    }
+
+    fn insert(&mut self, task: WheelTask) {
+        let delta = task.tick - self.current;
+        let mut level = 0;
+        while level + 1 < WHEEL_LEVELS && delta >= 1 << (WHEEL_SLOT_BITS * (level + 1)) {
+            level += 1;
+        }
+        let slot = (task.tick >> (WHEEL_SLOT_BITS * level)) as usize & (WHEEL_SLOTS - 1);
+        self.slots[level][slot].push(task);
+    }
+
+    /// Advance the wheel one tick and return the callbacks that are due.
+    /// The caller runs them outside any lock guarding the wheel.
+    pub fn tick(&mut self) -> Vec<Box<FnBox() + Send>> {
+        self.current += 1;
+        // When the tick crosses a slot boundary of a higher level, the
+        // tasks parked in that slot are redistributed to lower levels.
+        for level in 1..WHEEL_LEVELS {
+            if self.current & ((1 << (WHEEL_SLOT_BITS * level)) - 1) != 0 {
+                break;
+            }
+            let slot = (self.current >> (WHEEL_SLOT_BITS * level)) as usize & (WHEEL_SLOTS - 1);
+            let tasks = mem::replace(&mut self.slots[level][slot], Vec::new());
+            for task in tasks {
+                self.insert(task);
+            }
+        }
+        let slot = self.current as usize & (WHEEL_SLOTS - 1);
+        let tasks = mem::replace(&mut self.slots[0][slot], Vec::new());
+        tasks
+            .into_iter()
+            .filter(|t| !t.cancelled.load(AtomicOrdering::Relaxed))
+            .map(|t| t.cb)
+            .collect()
+    }
+}
+
+/// Cancellation handle for a timeout registered with a `TimerWheel`.
+/// Dropping the handle does not cancel the timeout.
+pub struct TimerHandle {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl TimerHandle {
+    pub fn cancel(&self) {
+        self.cancelled.store(true, AtomicOrdering::Relaxed);
+    }
+}
+
+/// A shared coarse timer service backed by a hierarchical wheel.
+///
+/// Components that only need tick resolution — lease expiry, request
+/// deadlines, tick jitter — register their timeouts here instead of
+/// keeping a `timeout_ms` registration or a sleep loop per component,
+/// so one thread serves them all. Callbacks run on the timer thread
+/// and must be cheap, usually just sending a message.
+///
+/// The service is cheap to clone; clones drive the same wheel.
+#[derive(Clone)]
+pub struct TimerService {
+    wheel: Arc<Mutex<TimerWheel>>,
+    tick: Duration,
+    stopped: Arc<AtomicBool>,
+    handle: Arc<Mutex<Option<JoinHandle<()>>>>,
+}
+
+impl TimerService {
+    /// Start the timer thread. `tick` is the wheel resolution; timeouts
+    /// are rounded up to it.
+    pub fn new(tick: Duration) -> TimerService {
+        let wheel = Arc::new(Mutex::new(TimerWheel::new()));
+        let stopped = Arc::new(AtomicBool::new(false));
+        let w = Arc::clone(&wheel);
+        let s = Arc::clone(&stopped);
+        let h = ThreadBuilder::new()
+            .name(thd_name!("timer-wheel"))
+            .spawn(move || {
+                // The wheel drifts by however long the callbacks take to
+                // run; coarse timers do not care.
+                while !s.load(AtomicOrdering::Relaxed) {
+                    thread::sleep(tick);
+                    let cbs = w.lock().unwrap().tick();
+                    for cb in cbs {
+                        cb();
+                    }
+                }
+            })
+            .unwrap();
+        TimerService {
+            wheel: wheel,
+            tick: tick,
+            stopped: stopped,
+            handle: Arc::new(Mutex::new(Some(h))),
+        }
+    }
+
+    /// Run `f` on the timer thread once `timeout` has elapsed, rounded
+    /// up to the wheel resolution.
+    pub fn delay<F: FnOnce() + Send + 'static>(&self, timeout: Duration, f: F) -> TimerHandle {
+        let tick_ns = self.tick.as_secs() * 1_000_000_000 + u64::from(self.tick.subsec_nanos());
+        let timeout_ns = timeout.as_secs() * 1_000_000_000 + u64::from(timeout.subsec_nanos());
+        let ticks = (timeout_ns + tick_ns - 1) / tick_ns;
+        self.wheel.lock().unwrap().schedule(ticks, box f)
+    }
+
+    pub fn stop(&self) {
+        self.stopped.store(true, AtomicOrdering::Relaxed);
+        if let Some(h) = self.handle.lock().unwrap().take() {
+            h.join().unwrap();
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -193,4 +366,66 @@ mod tests {
 
         worker.stop().unwrap().join().unwrap();
     }
+
+    #[test]
+    fn test_timer_wheel() {
+        let mut wheel = TimerWheel::new();
+        let (tx, rx) = mpsc::channel();
+        // Timeouts spanning all levels of the wheel, out of order.
+        let timeouts: Vec<u64> = vec![100, 1, 63, 64, (1 << 12) + 5, 65];
+        for &ticks in &timeouts {
+            let ch = tx.clone();
+            wheel.schedule(ticks, box move || ch.send(ticks).unwrap());
+        }
+        // A timeout of zero fires on the next tick.
+        let ch = tx.clone();
+        wheel.schedule(0, box move || ch.send(0).unwrap());
+
+        let last = (1 << 12) + 5;
+        for tick in 1..last + 1 {
+            for cb in wheel.tick() {
+                cb();
+            }
+            while let Ok(fired) = rx.try_recv() {
+                assert_eq!(cmp::max(fired, 1), tick, "fired at the wrong tick");
+            }
+        }
+        assert!(wheel.tick().is_empty());
+    }
+
+    #[test]
+    fn test_timer_wheel_cancel() {
+        let mut wheel = TimerWheel::new();
+        let (tx, rx) = mpsc::channel();
+        let ch = tx.clone();
+        let handle = wheel.schedule(2, box move || ch.send("cancelled").unwrap());
+        wheel.schedule(3, box move || tx.send("kept").unwrap());
+        handle.cancel();
+        for _ in 0..4 {
+            for cb in wheel.tick() {
+                cb();
+            }
+        }
+        assert_eq!(rx.try_recv().unwrap(), "kept");
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_timer_service() {
+        let service = TimerService::new(Duration::from_millis(10));
+        let (tx, rx) = mpsc::channel();
+        let ch = tx.clone();
+        service.delay(Duration::from_millis(50), move || ch.send("a").unwrap());
+        let ch = tx.clone();
+        let handle = service.delay(Duration::from_millis(30), move || {
+            ch.send("cancelled").unwrap()
+        });
+        handle.cancel();
+        service.delay(Duration::from_millis(100), move || tx.send("b").unwrap());
+
+        assert_eq!(rx.recv_timeout(Duration::from_secs(1)).unwrap(), "a");
+        assert_eq!(rx.recv_timeout(Duration::from_secs(1)).unwrap(), "b");
+        service.stop();
+        assert!(rx.try_recv().is_err());
+    }
 }
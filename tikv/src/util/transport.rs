@@ -29,6 +29,10 @@ quick_error! {
             description("message is discarded")
             display("{}", reason)
         }
+        Full(cap: usize) {
+            description("channel is full")
+            display("channel is full (capacity {})", cap)
+        }
         Closed {
             description("channel is closed")
             display("channel is closed")
@@ -45,8 +49,7 @@ quick_error! {
 impl<T: Debug> From<NotifyError<T>> for Error {
     fn from(e: NotifyError<T>) -> Error {
         match e {
-            // ALERT!! May cause sensitive data leak.
-            NotifyError::Full(m) => Error::Discard(format!("Failed to send {:?} due to full", m)),
+            NotifyError::Full(_) => Error::Full(0),
             NotifyError::Closed(..) => Error::Closed,
             _ => box_err!("{:?}", e),
         }
@@ -89,6 +92,10 @@ impl<T> Sender<T> for mpsc::SyncSender<T> {
 pub struct RetryableSendCh<T, C> {
     ch: C,
     name: &'static str,
+    // The configured capacity of the underlying channel, purely
+    // informational: it is attached to `Error::Full` so callers can
+    // report how deep the mailbox is. 0 means unknown.
+    capacity: usize,
 
     marker: PhantomData<T>,
 }
@@ -103,10 +110,17 @@ impl<T: Debug, C: Sender<T>> RetryableSendCh<T, C> {
         RetryableSendCh {
             ch: ch,
             name: name,
+            capacity: 0,
             marker: Default::default(),
         }
     }
 
+    /// Records the configured capacity of the underlying channel. Must be
+    /// called before the channel is cloned to take effect everywhere.
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+    }
+
     /// Try send t with default try times.
     pub fn send(&self, t: T) -> Result<(), Error> {
         self.send_with_try_times(t, MAX_SEND_RETRY_CNT)
@@ -125,7 +139,7 @@ impl<T: Debug, C: Sender<T>> RetryableSendCh<T, C> {
                         CHANNEL_FULL_COUNTER_VEC
                             .with_label_values(&[self.name])
                             .inc();
-                        return Err(NotifyError::Full(m).into());
+                        return Err(Error::Full(self.capacity));
                     }
                     try_times -= 1;
                     m
@@ -145,6 +159,7 @@ impl<T, C: Sender<T>> Clone for RetryableSendCh<T, C> {
         RetryableSendCh {
             ch: self.ch.clone(),
             name: self.name,
+            capacity: self.capacity,
             marker: Default::default(),
         }
     }
@@ -224,7 +239,8 @@ mod tests {
         let mut config = EventLoopConfig::new();
         config.notify_capacity(2);
         let mut event_loop = EventLoop::configured(config).unwrap();
-        let ch = SendCh::new(event_loop.channel(), "test");
+        let mut ch = SendCh::new(event_loop.channel(), "test");
+        ch.set_capacity(2);
         let _ch = ch.clone();
         let h = thread::spawn(move || {
             let mut sender = SenderHandler { ch: _ch };
@@ -235,8 +251,8 @@ mod tests {
         ch.send(Msg::Stop).unwrap();
         ch.send(Msg::Stop).unwrap();
         match ch.send(Msg::Stop) {
-            Err(Error::Discard(_)) => {}
-            res => panic!("expect discard error, but found: {:?}", res),
+            Err(Error::Full(2)) => {}
+            res => panic!("expect full error, but found: {:?}", res),
         }
 
         h.join().unwrap();
@@ -271,8 +287,8 @@ mod tests {
         ch.send(Msg::Stop).unwrap();
         ch.send(Msg::Stop).unwrap();
         match ch.send(Msg::Stop) {
-            Err(Error::Discard(_)) => {}
-            res => panic!("expect discard error, but found: {:?}", res),
+            Err(Error::Full(0)) => {}
+            res => panic!("expect full error, but found: {:?}", res),
         }
 
         h.join().unwrap();
@@ -57,6 +57,7 @@ fn test_serde_custom_tikv_config() {
     value.log_file = "foo".to_owned();
     value.server = ServerConfig {
         cluster_id: 0, // KEEP IT ZERO, it is skipped by serde.
+        force_new_cluster: false, // KEEP IT FALSE, it is skipped by serde.
         addr: "example.com:443".to_owned(),
         labels: map!{ "a".to_owned() => "b".to_owned() },
         advertise_addr: "example.com:443".to_owned(),
@@ -66,14 +67,30 @@ fn test_serde_custom_tikv_config() {
         grpc_concurrent_stream: 1_234,
         grpc_raft_conn_num: 123,
         grpc_stream_initial_window_size: ReadableSize(12_345),
+        grpc_cpu_set: vec![0, 1],
+        grpc_concurrency_per_numa_node: 12,
+        raft_client_max_pending_msgs: 123,
+        raft_client_max_pending_bytes: ReadableSize(12_345),
         end_point_concurrency: 12,
         end_point_max_tasks: 12,
         end_point_stack_size: ReadableSize::mb(12),
         end_point_recursion_limit: 100,
         end_point_batch_row_limit: 64,
+        end_point_scan_seek_bound: 12,
         end_point_request_max_handle_duration: ReadableDuration::secs(12),
+        end_point_analyze_max_bucket_size: 123,
+        end_point_analyze_max_sample_size: 123,
+        end_point_analyze_max_sketch_size: 123,
+        end_point_analyze_max_cmsketch_depth: 12,
+        end_point_analyze_max_cmsketch_width: 123,
+        end_point_perf_context: true,
         snap_max_write_bytes_per_sec: ReadableSize::mb(10),
         snap_max_total_size: ReadableSize::gb(10),
+        snap_max_concurrent_generations: 12,
+        snap_max_concurrent_sends: 12,
+        snap_max_concurrent_applies: 12,
+        audit_log_path: "/tmp/audit.log".to_owned(),
+        audit_log_sample_every: 12,
     };
     value.readpool = ReadPoolConfig {
         high_concurrency: 1,
@@ -83,14 +100,30 @@ fn test_serde_custom_tikv_config() {
         max_tasks_normal: 20000,
         max_tasks_low: 30000,
         stack_size: ReadableSize::mb(20),
+        auto_scale: true,
+        min_concurrency: 2,
+        target_queue_wait: ReadableDuration::millis(100),
     };
     value.metric = MetricConfig {
         interval: ReadableDuration::secs(12),
         address: "example.com:443".to_owned(),
         job: "tikv_1".to_owned(),
+        prefix: "tikv".to_owned(),
+        latency_buckets: vec![0.0001, 0.001, 0.01, 0.1, 1.0],
+        labels: map!{ "cluster".to_owned() => "cluster_1".to_owned() },
+        basic_auth_user: "user".to_owned(),
+        basic_auth_password: "pass".to_owned(),
+        disable_push: true,
+    };
+    value.slow_log = SlowLogConfig {
+        raftstore: ReadableDuration::secs(2),
+        apply: ReadableDuration::millis(500),
+        coprocessor: ReadableDuration::secs(3),
+        scheduler: ReadableDuration::millis(250),
     };
     value.raft_store = RaftstoreConfig {
         sync_log: false,
+        disable_kv_wal: true,
         raftdb_path: "/var".to_owned(),
         capacity: ReadableSize(123),
         raft_base_tick_interval: ReadableDuration::secs(12),
@@ -99,6 +132,8 @@ fn test_serde_custom_tikv_config() {
         raft_max_size_per_msg: ReadableSize::mb(12),
         raft_max_inflight_msgs: 123,
         raft_entry_max_size: ReadableSize::mb(12),
+        raft_inflight_msgs_window: 123,
+        raft_inflight_bytes_window: ReadableSize::mb(12),
         raft_log_gc_tick_interval: ReadableDuration::secs(12),
         raft_log_gc_threshold: 12,
         raft_log_gc_count_limit: 12,
@@ -107,6 +142,9 @@ fn test_serde_custom_tikv_config() {
         region_split_check_diff: ReadableSize::mb(6),
         region_compact_check_interval: ReadableDuration::secs(12),
         region_compact_delete_keys_count: 1_234,
+        split_region_on_load: true,
+        region_max_load_qps: 2_000,
+        region_max_load_bytes: ReadableSize::mb(10),
         pd_heartbeat_tick_interval: ReadableDuration::minutes(12),
         pd_store_heartbeat_tick_interval: ReadableDuration::secs(12),
         notify_capacity: 12_345,
@@ -120,11 +158,17 @@ fn test_serde_custom_tikv_config() {
         lock_cf_compact_interval: ReadableDuration::minutes(12),
         lock_cf_compact_bytes_threshold: ReadableSize::mb(123),
         consistency_check_interval: ReadableDuration::secs(12),
+        consistency_check_min_interval: ReadableDuration::minutes(12),
+        consistency_check_batch: 12,
+        consistency_check_io_limit: ReadableSize::mb(12),
+        region_meta_check_interval: ReadableDuration::minutes(12),
         report_region_flow_interval: ReadableDuration::minutes(12),
         raft_store_max_leader_lease: ReadableDuration::secs(12),
         right_derive_when_split: false,
         allow_remove_leader: true,
         use_delete_range: true,
+        cpu_set: vec![2, 3],
+        apply_cpu_set: vec![4, 5],
         region_max_size: ReadableSize(0),
         region_split_size: ReadableSize(0),
     };
@@ -148,12 +192,14 @@ fn test_serde_custom_tikv_config() {
         info_log_roll_time: ReadableDuration::secs(12),
         info_log_dir: "/var".to_owned(),
         rate_bytes_per_sec: ReadableSize::kb(1),
+        rate_limiter_auto_tuned: true,
         bytes_per_sync: ReadableSize::mb(1),
         wal_bytes_per_sync: ReadableSize::kb(32),
         max_sub_compactions: 12,
         writable_file_max_buffer_size: ReadableSize::mb(12),
         use_direct_io_for_flush_and_compaction: true,
         enable_pipelined_write: false,
+        enable_atomic_flush: true,
         defaultcf: DefaultCfConfig {
             block_size: ReadableSize::kb(12),
             block_cache_size: ReadableSize::gb(12),
@@ -290,6 +336,50 @@ fn test_serde_custom_tikv_config() {
             num_levels: 4,
             max_bytes_for_level_multiplier: 8,
         },
+        applycf: ApplyCfConfig {
+            block_size: ReadableSize::kb(12),
+            block_cache_size: ReadableSize::gb(12),
+            disable_block_cache: false,
+            cache_index_and_filter_blocks: false,
+            pin_l0_filter_and_index_blocks: false,
+            use_bloom_filter: false,
+            whole_key_filtering: true,
+            bloom_filter_bits_per_key: 123,
+            block_based_bloom_filter: true,
+            read_amp_bytes_per_bit: 0,
+            compression_per_level: [
+                DBCompressionType::No,
+                DBCompressionType::No,
+                DBCompressionType::Zstd,
+                DBCompressionType::Zstd,
+                DBCompressionType::No,
+                DBCompressionType::Zstd,
+                DBCompressionType::Lz4,
+            ],
+            write_buffer_size: ReadableSize::mb(1),
+            max_write_buffer_number: 12,
+            min_write_buffer_number_to_merge: 12,
+            max_bytes_for_level_base: ReadableSize::kb(12),
+            target_file_size_base: ReadableSize::kb(123),
+            level0_file_num_compaction_trigger: 123,
+            level0_slowdown_writes_trigger: 123,
+            level0_stop_writes_trigger: 123,
+            max_compaction_bytes: ReadableSize::gb(1),
+            compaction_pri: CompactionPriority::MinOverlappingRatio,
+            dynamic_level_bytes: true,
+            num_levels: 4,
+            max_bytes_for_level_multiplier: 8,
+        },
+        secondary_cache: SecondaryCacheConfig {
+            path: "/var/secondary-cache".to_owned(),
+            capacity: ReadableSize::gb(1),
+            optimized_for_nvm: false,
+        },
+        compaction_guard: CompactionGuardConfig {
+            enable: true,
+            min_output_file_size: ReadableSize::mb(12),
+            max_output_file_size: ReadableSize::mb(123),
+        },
     };
     value.raftdb = RaftDbConfig {
         wal_recovery_mode: DBRecoveryMode::SkipAnyCorruptedRecords,
@@ -350,6 +440,10 @@ fn test_serde_custom_tikv_config() {
     };
     value.storage = StorageConfig {
         data_dir: "/var".to_owned(),
+        enable_keyspace: true,
+        keyspace_id: 123,
+        enable_raw_ttl: true,
+        enable_perf_context: true,
         gc_ratio_threshold: 1.2,
         max_key_size: 8192,
         scheduler_notify_capacity: 123,
@@ -358,9 +452,15 @@ fn test_serde_custom_tikv_config() {
         scheduler_concurrency: 123,
         scheduler_worker_pool_size: 1,
         scheduler_pending_write_threshold: ReadableSize::kb(123),
+        resolve_lock_batch_size: 123,
+        backup_rate_limit: ReadableSize::mb(123),
+        verify_mvcc_invariants: true,
+        auto_rollback_expired_locks: true,
+        wait_for_lock_timeout: 123,
     };
     value.coprocessor = CopConfig {
         split_region_on_table: true,
+        split_region_on_keyspace: true,
         region_max_size: ReadableSize::mb(12),
         region_split_size: ReadableSize::mb(12),
     };
@@ -368,11 +468,16 @@ fn test_serde_custom_tikv_config() {
         ca_path: "invalid path".to_owned(),
         cert_path: "invalid path".to_owned(),
         key_path: "invalid path".to_owned(),
+        cipher_suites: "ECDHE-RSA-AES128-GCM-SHA256".to_owned(),
+        min_tls_version: "1.2".to_owned(),
+        auth_token: "test-token".to_owned(),
         override_ssl_target: "".to_owned(),
     };
     value.import = ImportConfig {
         num_threads: 123,
         stream_channel_window: 123,
+        sst_ttl: ReadableDuration::hours(12),
+        speed_limit: ReadableSize::mb(123),
     };
 
     let custom = read_file_in_project_dir("tests/config/test-custom.toml");
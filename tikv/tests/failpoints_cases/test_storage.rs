@@ -36,23 +36,31 @@ fn test_storage_1gc() {
     fail::cfg(batch_snapshot_fp, "pause").unwrap();
     let (tx1, rx1) = channel();
     storage
-        .async_gc(ctx.clone(), 1, box move |res: storage::Result<()>| {
-            assert!(res.is_ok());
-            tx1.send(1).unwrap();
-        })
+        .async_gc(
+            ctx.clone(),
+            1,
+            Callback::Boxed(box move |res: storage::Result<()>| {
+                assert!(res.is_ok());
+                tx1.send(1).unwrap();
+            }),
+        )
         .unwrap();
     // Sleep to make sure the failpoint is triggered.
     thread::sleep(Duration::from_millis(2000));
     // Old GC command is blocked at snapshot stage, the other one will get ServerIsBusy error.
     let (tx2, rx2) = channel();
     storage
-        .async_gc(Context::new(), 1, box move |res: storage::Result<()>| {
-            match res {
-                Err(storage::Error::SchedTooBusy) => {}
-                _ => panic!("expect too busy"),
-            }
-            tx2.send(1).unwrap();
-        })
+        .async_gc(
+            Context::new(),
+            1,
+            Callback::Boxed(box move |res: storage::Result<()>| {
+                match res {
+                    Err(storage::Error::SchedTooBusy) => {}
+                    _ => panic!("expect too busy"),
+                }
+                tx2.send(1).unwrap();
+            }),
+        )
         .unwrap();
 
     rx2.recv().unwrap();
@@ -89,7 +97,7 @@ fn test_scheduler_leader_change_twice() {
             b"k".to_vec(),
             10,
             Options::default(),
-            box move |res: storage::Result<_>| match res {
+            Callback::Boxed(box move |res: storage::Result<_>| match res {
                 Err(storage::Error::Txn(txn::Error::Engine(engine::Error::Request(ref e))))
                 | Err(storage::Error::Engine(engine::Error::Request(ref e))) => {
                     assert!(e.has_stale_command(), "{:?}", e);
@@ -101,7 +109,7 @@ fn test_scheduler_leader_change_twice() {
                 _ => {
                     panic!("expect stale command, but got {:?}", res);
                 }
-            },
+            }),
         )
         .unwrap();
     // Sleep to make sure the failpoint is triggered.
@@ -132,9 +140,9 @@ fn test_scheduler_leader_change_twice() {
                 vec![make_key(b"k")],
                 10,
                 11,
-                box move |res: storage::Result<_>| {
+                Callback::Boxed(box move |res: storage::Result<_>| {
                     commit_tx.send(res).unwrap();
-                },
+                }),
             )
             .unwrap();
         // wait for the commit result.
@@ -129,7 +129,8 @@ impl<T: Simulator> Cluster<T> {
         for _ in 0..self.count {
             let path = TempDir::new("test_cluster").unwrap();
             let kv_db_opt = self.cfg.rocksdb.build_opt();
-            let kv_cfs_opt = self.cfg.rocksdb.build_cf_opts();
+            let kv_cfs_opt = self.cfg.rocksdb
+                .build_cf_opts(None, self.cfg.storage.enable_raw_ttl);
             let engine = Arc::new(
                 rocksdb::new_engine_opt(path.path().to_str().unwrap(), kv_db_opt, kv_cfs_opt)
                     .unwrap(),
@@ -28,6 +28,7 @@ use tikv::config::TiKvConfig;
 use tikv::raftstore::Result;
 use tikv::raftstore::coprocessor::CoprocessorHost;
 use tikv::util::HandyRwLock;
+use tikv::util::rocksdb::RegionBoundaries;
 use tikv::util::worker::FutureWorker;
 use tikv::util::transport::SendCh;
 use tikv::server::transport::{RaftStoreRouter, ServerRaftStoreRouter};
@@ -195,6 +196,7 @@ impl Simulator for NodeCluster {
             snap_status_receiver,
             pd_worker,
             coprocessor_host,
+            RegionBoundaries::default(),
         ).unwrap();
         assert!(
             Arc::clone(&engines.kv_engine)
@@ -26,6 +26,7 @@ use tikv::raftstore::{store, Result};
 use tikv::raftstore::store::{Callback, Engines, Msg as StoreMsg, SnapManager};
 use tikv::raftstore::coprocessor::CoprocessorHost;
 use tikv::server::transport::RaftStoreRouter;
+use tikv::util::rocksdb::RegionBoundaries;
 use tikv::util::transport::SendCh;
 use tikv::util::security::SecurityManager;
 use tikv::util::worker::{FutureWorker, Worker};
@@ -125,18 +126,25 @@ impl Simulator for ServerCluster {
         self.storages.insert(node_id, store.get_engine());
 
         // Create import service.
+        let security_mgr = Arc::new(SecurityManager::new(&cfg.security).unwrap());
         let importer = {
             let dir = TempDir::new("test-import-sst").unwrap().into_path();
             Arc::new(SSTImporter::new(dir).unwrap())
         };
-        let import_service = ImportSSTService::new(cfg.import.clone(), store.clone(), importer);
+        let import_service = ImportSSTService::new(
+            cfg.import.clone(),
+            store.clone(),
+            importer,
+            sim_router.clone(),
+            Arc::clone(&self.pd_client),
+            Arc::clone(&security_mgr),
+        );
 
         // Create pd client, snapshot manager, server.
         let (worker, resolver) = resolve::new_resolver(Arc::clone(&self.pd_client)).unwrap();
         let snap_mgr = SnapManager::new(tmp_str, Some(store_sendch));
         let pd_worker = FutureWorker::new("test-pd-worker");
         let server_cfg = Arc::new(cfg.server.clone());
-        let security_mgr = Arc::new(SecurityManager::new(&cfg.security).unwrap());
         let mut server = Server::new(
             &server_cfg,
             &security_mgr,
@@ -174,6 +182,7 @@ impl Simulator for ServerCluster {
             snap_status_receiver,
             pd_worker,
             coprocessor_host,
+            RegionBoundaries::default(),
         ).unwrap();
         assert!(node_id == 0 || node_id == node.id());
         let node_id = node.id();
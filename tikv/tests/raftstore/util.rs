@@ -347,12 +347,35 @@ pub fn read_on_peer<T: Simulator>(
     region: metapb::Region,
     key: &[u8],
     timeout: Duration,
+) -> Result<Vec<u8>> {
+    do_read_on_peer(cluster, peer, region, key, false, timeout)
+}
+
+// Issue a quorum read request on the specified peer, which may be a
+// follower.
+pub fn read_quorum_on_peer<T: Simulator>(
+    cluster: &mut Cluster<T>,
+    peer: metapb::Peer,
+    region: metapb::Region,
+    key: &[u8],
+    timeout: Duration,
+) -> Result<Vec<u8>> {
+    do_read_on_peer(cluster, peer, region, key, true, timeout)
+}
+
+fn do_read_on_peer<T: Simulator>(
+    cluster: &mut Cluster<T>,
+    peer: metapb::Peer,
+    region: metapb::Region,
+    key: &[u8],
+    read_quorum: bool,
+    timeout: Duration,
 ) -> Result<Vec<u8>> {
     let mut request = new_request(
         region.get_id(),
         region.get_region_epoch().clone(),
         vec![new_get_cmd(key)],
-        false,
+        read_quorum,
     );
     request.mut_header().set_peer(peer);
     let mut resp = cluster.call_command(request, timeout)?;
@@ -388,6 +411,27 @@ pub fn must_read_on_peer<T: Simulator>(
     }
 }
 
+pub fn must_read_quorum_on_peer<T: Simulator>(
+    cluster: &mut Cluster<T>,
+    peer: metapb::Peer,
+    region: metapb::Region,
+    key: &[u8],
+    value: &[u8],
+) {
+    let timeout = Duration::from_secs(5);
+    match read_quorum_on_peer(cluster, peer, region, key, timeout) {
+        Ok(v) => if v != value {
+            panic!(
+                "read key {}, expect value {}, got {}",
+                escape(key),
+                escape(value),
+                escape(&v)
+            )
+        },
+        Err(e) => panic!("failed to read for key {}, err {:?}", escape(key), e),
+    }
+}
+
 pub fn must_error_read_on_peer<T: Simulator>(
     cluster: &mut Cluster<T>,
     peer: metapb::Peer,
@@ -427,7 +471,8 @@ pub fn create_test_engine(
                 cmpacted_handler,
                 Some(dummpy_filter),
             ));
-            let kv_cfs_opt = cfg.rocksdb.build_cf_opts();
+            let kv_cfs_opt = cfg.rocksdb
+                .build_cf_opts(None, cfg.storage.enable_raw_ttl);
             let engine = Arc::new(
                 rocksdb::new_engine_opt(
                     path.as_ref().unwrap().path().to_str().unwrap(),
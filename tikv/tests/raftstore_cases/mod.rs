@@ -27,6 +27,7 @@ mod test_snap;
 mod test_region_heartbeat;
 mod test_stale_peer;
 mod test_lease_read;
+mod test_follower_read;
 mod test_bootstrap;
 mod test_service;
 mod test_update_region_size;
@@ -14,7 +14,7 @@
 use std::collections::HashMap;
 
 use tikv::raftstore::store::*;
-use tikv::storage::CF_RAFT;
+use tikv::storage::CF_APPLY;
 use tikv::util::config::*;
 use rocksdb::DB;
 use protobuf;
@@ -38,7 +38,7 @@ fn test_compact_log<T: Simulator>(cluster: &mut Cluster<T>) {
 
     for (&id, engines) in &cluster.engines {
         let mut state: RaftApplyState =
-            get_msg_cf_or_default(&engines.kv_engine, CF_RAFT, &keys::apply_state_key(1));
+            get_msg_cf_or_default(&engines.kv_engine, CF_APPLY, &keys::apply_state_key(1));
         before_states.insert(id, state.take_truncated_state());
     }
 
@@ -66,7 +66,7 @@ fn check_compacted(
 
     for (&id, engines) in all_engines {
         let mut state: RaftApplyState =
-            get_msg_cf_or_default(&engines.kv_engine, CF_RAFT, &keys::apply_state_key(1));
+            get_msg_cf_or_default(&engines.kv_engine, CF_APPLY, &keys::apply_state_key(1));
         let after_state = state.take_truncated_state();
 
         let before_state = &before_states[&id];
@@ -110,7 +110,7 @@ fn test_compact_count_limit<T: Simulator>(cluster: &mut Cluster<T>) {
     for (&id, engines) in &cluster.engines {
         must_get_equal(&engines.kv_engine, b"k1", b"v1");
         let mut state: RaftApplyState =
-            get_msg_cf_or_default(&engines.kv_engine, CF_RAFT, &keys::apply_state_key(1));
+            get_msg_cf_or_default(&engines.kv_engine, CF_APPLY, &keys::apply_state_key(1));
         let state = state.take_truncated_state();
         // compact should not start
         assert_eq!(RAFT_INIT_LOG_INDEX, state.get_index());
@@ -130,7 +130,7 @@ fn test_compact_count_limit<T: Simulator>(cluster: &mut Cluster<T>) {
     // limit has not reached, should not gc.
     for (&id, engines) in &cluster.engines {
         let mut state: RaftApplyState =
-            get_msg_cf_or_default(&engines.kv_engine, CF_RAFT, &keys::apply_state_key(1));
+            get_msg_cf_or_default(&engines.kv_engine, CF_APPLY, &keys::apply_state_key(1));
         let after_state = state.take_truncated_state();
 
         let before_state = &before_states[&id];
@@ -166,7 +166,7 @@ fn test_compact_many_times<T: Simulator>(cluster: &mut Cluster<T>) {
     for (&id, engines) in &cluster.engines {
         must_get_equal(&engines.kv_engine, b"k1", b"v1");
         let mut state: RaftApplyState =
-            get_msg_cf_or_default(&engines.kv_engine, CF_RAFT, &keys::apply_state_key(1));
+            get_msg_cf_or_default(&engines.kv_engine, CF_APPLY, &keys::apply_state_key(1));
         let state = state.take_truncated_state();
         // compact should not start
         assert_eq!(RAFT_INIT_LOG_INDEX, state.get_index());
@@ -226,7 +226,7 @@ fn test_compact_size_limit<T: Simulator>(cluster: &mut Cluster<T>) {
         }
         must_get_equal(&engines.kv_engine, b"k1", b"v1");
         let mut state: RaftApplyState =
-            get_msg_cf_or_default(&engines.kv_engine, CF_RAFT, &keys::apply_state_key(1));
+            get_msg_cf_or_default(&engines.kv_engine, CF_APPLY, &keys::apply_state_key(1));
         let state = state.take_truncated_state();
         // compact should not start
         assert_eq!(RAFT_INIT_LOG_INDEX, state.get_index());
@@ -251,7 +251,7 @@ fn test_compact_size_limit<T: Simulator>(cluster: &mut Cluster<T>) {
             continue;
         }
         let mut state: RaftApplyState =
-            get_msg_cf_or_default(&engines.kv_engine, CF_RAFT, &keys::apply_state_key(1));
+            get_msg_cf_or_default(&engines.kv_engine, CF_APPLY, &keys::apply_state_key(1));
         let after_state = state.take_truncated_state();
 
         let before_state = &before_states[&id];
@@ -277,7 +277,7 @@ fn test_compact_size_limit<T: Simulator>(cluster: &mut Cluster<T>) {
             continue;
         }
         let mut state: RaftApplyState =
-            get_msg_cf_or_default(&engines.kv_engine, CF_RAFT, &keys::apply_state_key(1));
+            get_msg_cf_or_default(&engines.kv_engine, CF_APPLY, &keys::apply_state_key(1));
         let after_state = state.take_truncated_state();
 
         let before_state = &before_states[&id];
@@ -0,0 +1,71 @@
+// Copyright 2018 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A module contains test cases for quorum reads served on followers.
+
+use std::time::Duration;
+
+use super::cluster::{Cluster, Simulator};
+use super::node::new_node_cluster;
+use super::server::new_server_cluster;
+use super::util::*;
+
+// A follower may serve a read request that explicitly asks for a quorum
+// read. The follower goes through a raft read-index handshake with the
+// leader and holds the request back until its apply state reaches the
+// commit index the leader reported, so the response is as consistent as
+// a leader read. Reads without the quorum mark are still rejected with
+// `NotLeader`.
+fn test_follower_read<T: Simulator>(cluster: &mut Cluster<T>) {
+    cluster.run();
+
+    let key = b"k1";
+    cluster.must_put(key, b"v1");
+
+    let region = cluster.get_region(key);
+    let leader = cluster.leader_of_region(region.get_id()).unwrap();
+    let follower = region
+        .get_peers()
+        .iter()
+        .find(|p| p.get_id() != leader.get_id())
+        .unwrap()
+        .clone();
+
+    // A plain read addressed at a follower must still be rejected.
+    must_error_read_on_peer(
+        cluster,
+        follower.clone(),
+        region.clone(),
+        key,
+        Duration::from_secs(1),
+    );
+
+    // A quorum read is served by the follower itself.
+    must_read_quorum_on_peer(cluster, follower.clone(), region.clone(), key, b"v1");
+
+    // The follower observes later writes as well.
+    cluster.must_put(key, b"v2");
+    must_read_quorum_on_peer(cluster, follower, region, key, b"v2");
+}
+
+#[test]
+fn test_node_follower_read() {
+    let mut cluster = new_node_cluster(0, 3);
+    test_follower_read(&mut cluster);
+}
+
+#[test]
+fn test_server_follower_read() {
+    let mut cluster = new_server_cluster(0, 3);
+    test_follower_read(&mut cluster);
+}
@@ -14,7 +14,7 @@
 use std::sync::Arc;
 
 use tikv::util::HandyRwLock;
-use tikv::storage::{Key, CF_DEFAULT, CF_LOCK, CF_RAFT};
+use tikv::storage::{Key, CF_APPLY, CF_DEFAULT, CF_LOCK, CF_RAFT};
 use tikv::storage::mvcc::{Lock, LockType};
 use tikv::raftstore::store::{keys, Mutable, Peekable};
 
@@ -595,6 +595,7 @@ fn test_debug_region_info() {
     let raft_engine = cluster.get_raft_engine(store_id);
     let kv_engine = cluster.get_engine(store_id);
     let raft_cf = kv_engine.cf_handle(CF_RAFT).unwrap();
+    let apply_cf = kv_engine.cf_handle(CF_APPLY).unwrap();
 
     let region_id = 100;
     let raft_state_key = keys::raft_state_key(region_id);
@@ -613,11 +614,11 @@ fn test_debug_region_info() {
     let mut apply_state = raft_serverpb::RaftApplyState::new();
     apply_state.set_applied_index(42);
     kv_engine
-        .put_msg_cf(raft_cf, &apply_state_key, &apply_state)
+        .put_msg_cf(apply_cf, &apply_state_key, &apply_state)
         .unwrap();
     assert_eq!(
         kv_engine
-            .get_msg_cf::<raft_serverpb::RaftApplyState>(CF_RAFT, &apply_state_key)
+            .get_msg_cf::<raft_serverpb::RaftApplyState>(CF_APPLY, &apply_state_key)
             .unwrap()
             .unwrap(),
         apply_state
@@ -743,7 +744,7 @@ fn test_debug_scan_mvcc() {
         keys::data_key(b"meta_lock_2"),
     ];
     for k in &keys {
-        let v = Lock::new(LockType::Put, b"pk".to_vec(), 1, 10, None).to_bytes();
+        let v = Lock::new(LockType::Put, b"pk".to_vec(), 1, 10, None, 0).to_bytes();
         let cf_handle = engine.cf_handle(CF_LOCK).unwrap();
         engine.put_cf(cf_handle, k.as_slice(), &v).unwrap();
     }
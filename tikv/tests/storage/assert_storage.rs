@@ -441,30 +441,36 @@ impl AssertionStorage {
     }
 
     pub fn raw_get_ok(&self, key: Vec<u8>, value: Option<Vec<u8>>) {
-        assert_eq!(self.store.raw_get(self.ctx.clone(), key).unwrap(), value);
+        assert_eq!(self.store.raw_get(self.ctx.clone(), String::new(), key).unwrap(), value);
     }
 
     pub fn raw_put_ok(&self, key: Vec<u8>, value: Vec<u8>) {
-        self.store.raw_put(self.ctx.clone(), key, value).unwrap();
+        self.store
+            .raw_put(self.ctx.clone(), String::new(), key, value)
+            .unwrap();
     }
 
     pub fn raw_put_err(&self, key: Vec<u8>, value: Vec<u8>) {
         self.store
-            .raw_put(self.ctx.clone(), key, value)
+            .raw_put(self.ctx.clone(), String::new(), key, value)
             .unwrap_err();
     }
 
     pub fn raw_delete_ok(&self, key: Vec<u8>) {
-        self.store.raw_delete(self.ctx.clone(), key).unwrap()
+        self.store
+            .raw_delete(self.ctx.clone(), String::new(), key)
+            .unwrap()
     }
 
     pub fn raw_delete_err(&self, key: Vec<u8>) {
-        self.store.raw_delete(self.ctx.clone(), key).unwrap_err();
+        self.store
+            .raw_delete(self.ctx.clone(), String::new(), key)
+            .unwrap_err();
     }
 
     pub fn raw_scan_ok(&self, start_key: Vec<u8>, limit: usize, expect: Vec<(&[u8], &[u8])>) {
         let result: Vec<KvPair> = self.store
-            .raw_scan(self.ctx.clone(), start_key, limit)
+            .raw_scan(self.ctx.clone(), String::new(), start_key, limit)
             .unwrap()
             .into_iter()
             .map(|x| x.unwrap())
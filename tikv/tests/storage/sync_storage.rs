@@ -15,7 +15,7 @@ use std::sync::Arc;
 use std::sync::atomic::{AtomicUsize, Ordering};
 
 use tikv::util::collections::HashMap;
-use tikv::storage::{Engine, Key, KvPair, Mutation, Options, Result, Storage, Value};
+use tikv::storage::{Callback, Engine, Key, KvPair, Mutation, Options, Result, Storage, Value};
 use tikv::storage::config::Config;
 use kvproto::kvrpcpb::{Context, LockInfo};
 
@@ -63,7 +63,7 @@ impl SyncStorage {
     }
 
     pub fn get(&self, ctx: Context, key: &Key, start_ts: u64) -> Result<Option<Value>> {
-        wait_op!(|cb| self.store.async_get(ctx, key.to_owned(), start_ts, cb)).unwrap()
+        wait_op!(|cb| self.store.async_get(ctx, key.to_owned(), start_ts, Callback::Boxed(cb))).unwrap()
     }
 
     #[allow(dead_code)]
@@ -74,7 +74,7 @@ impl SyncStorage {
         start_ts: u64,
     ) -> Result<Vec<Result<KvPair>>> {
         wait_op!(|cb| self.store
-            .async_batch_get(ctx, keys.to_owned(), start_ts, cb))
+            .async_batch_get(ctx, keys.to_owned(), start_ts, Callback::Boxed(cb)))
             .unwrap()
     }
 
@@ -89,10 +89,11 @@ impl SyncStorage {
         wait_op!(|cb| self.store.async_scan(
             ctx,
             key,
+            None,
             limit,
             start_ts,
             Options::new(0, false, key_only),
-            cb,
+            Callback::Boxed(cb),
         )).unwrap()
     }
 
@@ -109,7 +110,7 @@ impl SyncStorage {
             primary,
             start_ts,
             Options::default(),
-            cb
+            Callback::Boxed(cb)
         )).unwrap()
     }
 
@@ -120,15 +121,15 @@ impl SyncStorage {
         start_ts: u64,
         commit_ts: u64,
     ) -> Result<()> {
-        wait_op!(|cb| self.store.async_commit(ctx, keys, start_ts, commit_ts, cb)).unwrap()
+        wait_op!(|cb| self.store.async_commit(ctx, keys, start_ts, commit_ts, Callback::Boxed(cb))).unwrap()
     }
 
     pub fn cleanup(&self, ctx: Context, key: Key, start_ts: u64) -> Result<()> {
-        wait_op!(|cb| self.store.async_cleanup(ctx, key, start_ts, cb)).unwrap()
+        wait_op!(|cb| self.store.async_cleanup(ctx, key, start_ts, Callback::Boxed(cb))).unwrap()
     }
 
     pub fn rollback(&self, ctx: Context, keys: Vec<Key>, start_ts: u64) -> Result<()> {
-        wait_op!(|cb| self.store.async_rollback(ctx, keys, start_ts, cb)).unwrap()
+        wait_op!(|cb| self.store.async_rollback(ctx, keys, start_ts, Callback::Boxed(cb))).unwrap()
     }
 
     pub fn scan_lock(
@@ -139,44 +140,53 @@ impl SyncStorage {
         limit: usize,
     ) -> Result<Vec<LockInfo>> {
         wait_op!(|cb| self.store
-            .async_scan_lock(ctx, max_ts, start_key, limit, cb))
+            .async_scan_lock(ctx, max_ts, start_key, limit, Callback::Boxed(cb)))
             .unwrap()
     }
 
     pub fn resolve_lock(&self, ctx: Context, start_ts: u64, commit_ts: Option<u64>) -> Result<()> {
         let mut txn_status = HashMap::default();
         txn_status.insert(start_ts, commit_ts.unwrap_or(0));
-        wait_op!(|cb| self.store.async_resolve_lock(ctx, txn_status, cb)).unwrap()
+        wait_op!(|cb| self.store.async_resolve_lock(ctx, txn_status, Callback::Boxed(cb))).unwrap()
     }
 
     pub fn resolve_lock_batch(&self, ctx: Context, txns: Vec<(u64, u64)>) -> Result<()> {
         let txn_status: HashMap<u64, u64> = txns.into_iter().collect();
-        wait_op!(|cb| self.store.async_resolve_lock(ctx, txn_status, cb)).unwrap()
+        wait_op!(|cb| self.store.async_resolve_lock(ctx, txn_status, Callback::Boxed(cb))).unwrap()
     }
 
     pub fn gc(&self, ctx: Context, safe_point: u64) -> Result<()> {
-        wait_op!(|cb| self.store.async_gc(ctx, safe_point, cb)).unwrap()
+        wait_op!(|cb| self.store.async_gc(ctx, safe_point, Callback::Boxed(cb))).unwrap()
     }
 
-    pub fn raw_get(&self, ctx: Context, key: Vec<u8>) -> Result<Option<Vec<u8>>> {
-        wait_op!(|cb| self.store.async_raw_get(ctx, key, cb)).unwrap()
+    pub fn raw_get(&self, ctx: Context, cf: String, key: Vec<u8>) -> Result<Option<Vec<u8>>> {
+        wait_op!(|cb| self.store.async_raw_get(ctx, cf, key, Callback::Boxed(cb))).unwrap()
     }
 
-    pub fn raw_put(&self, ctx: Context, key: Vec<u8>, value: Vec<u8>) -> Result<()> {
-        wait_op!(|cb| self.store.async_raw_put(ctx, key, value, cb)).unwrap()
+    pub fn raw_put(&self, ctx: Context, cf: String, key: Vec<u8>, value: Vec<u8>) -> Result<()> {
+        wait_op!(|cb| self.store
+            .async_raw_put(ctx, cf, key, value, 0, Callback::Boxed(cb))).unwrap()
     }
 
-    pub fn raw_delete(&self, ctx: Context, key: Vec<u8>) -> Result<()> {
-        wait_op!(|cb| self.store.async_raw_delete(ctx, key, cb)).unwrap()
+    pub fn raw_delete(&self, ctx: Context, cf: String, key: Vec<u8>) -> Result<()> {
+        wait_op!(|cb| self.store.async_raw_delete(ctx, cf, key, Callback::Boxed(cb))).unwrap()
     }
 
     pub fn raw_scan(
         &self,
         ctx: Context,
+        cf: String,
         start_key: Vec<u8>,
         limit: usize,
     ) -> Result<Vec<Result<KvPair>>> {
-        wait_op!(|cb| self.store.async_raw_scan(ctx, start_key, limit, cb)).unwrap()
+        wait_op!(|cb| self.store.async_raw_scan(
+            ctx,
+            cf,
+            start_key,
+            limit,
+            Options::default(),
+            Callback::Boxed(cb),
+        )).unwrap()
     }
 }
 
@@ -1,6 +1,7 @@
 use std::thread;
 use std::sync::mpsc::channel;
 use std::time::Duration;
+use std::u64;
 
 use tikv::util::HandyRwLock;
 use tikv::storage::engine::*;
@@ -34,6 +35,8 @@ fn test_raftkv() {
 
     get_put(&ctx, storage.as_ref());
     batch(&ctx, storage.as_ref());
+    write_with_options(&ctx, storage.as_ref());
+    snapshot_min_index(&ctx, storage.as_ref());
     seek(&ctx, storage.as_ref());
     near_seek(&ctx, storage.as_ref());
     cf(&ctx, storage.as_ref());
@@ -303,6 +306,46 @@ fn cf(ctx: &Context, engine: &Engine) {
     assert_none_cf(ctx, engine, "default", b"key");
 }
 
+fn write_with_options(ctx: &Context, engine: &Engine) {
+    let mut opts = WriteOptions::default();
+    opts.sync = true;
+    let (tx, rx) = channel();
+    engine
+        .async_write_opt(
+            ctx,
+            vec![Modify::Put(CF_DEFAULT, make_key(b"o"), b"1".to_vec())],
+            opts,
+            box move |(_, res)| tx.send(res).unwrap(),
+        )
+        .unwrap();
+    rx.recv().unwrap().unwrap();
+    assert_has(ctx, engine, b"o", b"1");
+    must_delete(ctx, engine, b"o");
+}
+
+fn snapshot_min_index(ctx: &Context, engine: &Engine) {
+    must_put(ctx, engine, b"i", b"1");
+    // Index 1 is applied as soon as the region is initialized, the
+    // bound is trivially satisfied.
+    let (tx, rx) = channel();
+    engine
+        .async_snapshot_min_index(ctx, 1, box move |(_, res)| tx.send(res).unwrap())
+        .unwrap();
+    let snapshot = rx.recv().unwrap().unwrap();
+    assert_eq!(
+        snapshot.get(&make_key(b"i")).unwrap().unwrap(),
+        b"1".to_vec()
+    );
+    // An index far beyond the raft log must be rejected, the peer
+    // cannot have applied it.
+    let (tx, rx) = channel();
+    engine
+        .async_snapshot_min_index(ctx, u64::MAX, box move |(_, res)| tx.send(res).unwrap())
+        .unwrap();
+    assert!(rx.recv().unwrap().is_err());
+    must_delete(ctx, engine, b"i");
+}
+
 fn empty_write(ctx: &Context, engine: &Engine) {
     engine.write(ctx, vec![]).unwrap_err();
 }
@@ -19,7 +19,7 @@ use std::thread;
 use rand::random;
 use super::sync_storage::SyncStorage;
 use kvproto::kvrpcpb::{Context, LockInfo};
-use tikv::storage::{make_key, Key, Mutation, ALL_CFS};
+use tikv::storage::{make_key, Callback, Key, Mutation, ALL_CFS};
 use tikv::storage::engine::{Engine, EngineRocksdb, TEMP_DIR};
 use tikv::storage::txn::{GC_BATCH_SIZE, RESOLVE_LOCK_BATCH_SIZE};
 use tikv::storage::mvcc::MAX_TXN_WRITE_SIZE;
@@ -881,9 +881,9 @@ fn test_conflict_commands_on_fault_engine() {
             k.clone(),
             start_ts,
             Default::default(),
-            box move |res| {
+            Callback::Boxed(box move |res| {
                 tx.send(res).unwrap();
-            },
+            }),
         )
         .unwrap();
     async_storage
@@ -892,18 +892,23 @@ fn test_conflict_commands_on_fault_engine() {
             vec![make_key(&k)],
             start_ts,
             commit_ts,
-            box |_| {},
+            Callback::Boxed(box |_| {}),
         )
         .unwrap();
     async_storage
-        .async_cleanup(storage.ctx.clone(), make_key(&k), start_ts, box |_| {})
+        .async_cleanup(
+            storage.ctx.clone(),
+            make_key(&k),
+            start_ts,
+            Callback::Boxed(box |_| {}),
+        )
         .unwrap();
     async_storage
         .async_rollback(
             storage.ctx.clone(),
             vec![make_key(&k)],
             start_ts,
-            box |_| {},
+            Callback::Boxed(box |_| {}),
         )
         .unwrap();
 